@@ -0,0 +1,234 @@
+use std::hint::black_box;
+use std::rc::Rc;
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+use lisp_rs::builtins::global_env;
+use lisp_rs::compiler::{compile, compile_program, TopLevel};
+use lisp_rs::eval::eval_str;
+use lisp_rs::lexer::tokenizer;
+use lisp_rs::object::Object;
+use lisp_rs::optimizer::optimize_forms;
+use lisp_rs::parser::parse;
+use lisp_rs::vm;
+
+/// Deep, non-tail recursion: `(fib 20)` makes 21891 calls.
+const FIB: &str = "
+    (define (fib n)
+      (if (< n 2) n (+ (fib (- n 1)) (fib (- n 2)))))
+    (fib 20)";
+const FIB_CALLS: u64 = 21891;
+
+/// A tail-recursive arithmetic loop of 10000 iterations.
+const ARITHMETIC: &str = "
+    (define (sum-squares n acc)
+      (if (= n 0) acc (sum-squares (- n 1) (+ acc (* n n)))))
+    (sum-squares 10000 0)";
+const ARITHMETIC_ITERATIONS: u64 = 10000;
+
+/// A loop over arithmetic the optimizer can fold, with constants written as
+/// the expressions they come from.
+const CONSTANTS: &str = "
+    (define (seconds days acc)
+      (if (= days 0)
+          acc
+          (seconds (- days 1) (+ acc (* 24 60 60) (* (/ 1 2) (* 2 1000))))))
+    (seconds 10000 0)";
+
+/// Builds a 1000-element list, then maps and folds over it.
+const LISTS: &str = "
+    (define (range n acc) (if (= n 0) acc (range (- n 1) (cons n acc))))
+    (define xs (range 1000 '()))
+    (fold-left + 0 (map (lambda (x) (* x x)) (filter (lambda (x) (< x 500)) xs)))";
+
+/// Maps and filters with helpers bound by `let`, which the lambdas passed
+/// to `map` and `filter` call.
+const PIPELINES: &str = "
+    (define (range n acc) (if (= n 0) acc (range (- n 1) (cons n acc))))
+    (define xs (range 1000 '()))
+    (define (pipeline xs k)
+      (let ((scale (lambda (x) (* x k)))
+            (small? (lambda (x) (< x 500)))
+            (add (lambda (acc x) (+ acc x))))
+        (fold-left (lambda (acc x) (add acc (scale x)))
+                   0
+                   (filter (lambda (x) (small? x)) xs))))
+    (define (repeat n acc) (if (= n 0) acc (repeat (- n 1) (pipeline xs n))))
+    (repeat 20 0)";
+
+/// A loop through accessors, small procedures a compiled program has the
+/// calls of compiled in place.
+const ACCESSORS: &str = "
+    (define (make-point x y) (cons x y))
+    (define (point-x p) (car p))
+    (define (point-y p) (cdr p))
+    (define (norm p) (+ (* (point-x p) (point-x p)) (* (point-y p) (point-y p))))
+    (define (total n acc) (if (= n 0) acc (total (- n 1) (+ acc (norm (make-point n 1))))))
+    (total 10000 0)";
+const ACCESSOR_ITERATIONS: u64 = 10000;
+
+/// Round-trips a string through character lists.
+const STRINGS: &str = "
+    (define s \"the quick brown fox jumps over the lazy dog\")
+    (define (shout n acc)
+      (if (= n 0)
+          acc
+          (shout (- n 1) (list->string (map char-upcase (string->list s))))))
+    (shout 200 \"\")";
+
+/// Slices a long string repeatedly; substrings share the original's text.
+const SUBSTRINGS: &str = "
+    (define s (string-append \"the quick brown fox jumps over the lazy dog \"
+                             \"the quick brown fox jumps over the lazy dog\"))
+    (define (slice n acc)
+      (if (= n 0)
+          acc
+          (slice (- n 1) (+ acc (string-length (substring (substring s 4 87) 10))))))
+    (slice 1000 0)";
+
+/// A source made mostly of string literals, the data of configuration-like
+/// scripts.
+fn string_literals() -> String {
+    (0..20_000)
+        .map(|i| format!("(define s{} \"value number {} of the table\")\n", i, i))
+        .collect()
+}
+
+/// A large source made of many copies of the sample programs, for measuring
+/// front-end throughput.
+fn large_source() -> String {
+    [FIB, LISTS, STRINGS].concat().repeat(200)
+}
+
+fn bench_tokenizer(c: &mut Criterion) {
+    let source = large_source();
+    let mut group = c.benchmark_group("tokenizer");
+    group.throughput(Throughput::Bytes(source.len() as u64));
+    group.bench_function("tokenize", |b| {
+        b.iter(|| tokenizer(black_box(&source)).unwrap())
+    });
+    group.finish();
+}
+
+fn bench_parser(c: &mut Criterion) {
+    let source = large_source();
+    let mut group = c.benchmark_group("parser");
+    group.throughput(Throughput::Bytes(source.len() as u64));
+    group.bench_function("parse", |b| b.iter(|| parse(black_box(&source)).unwrap()));
+
+    let literals = string_literals();
+    group.throughput(Throughput::Bytes(literals.len() as u64));
+    group.bench_function("string-literals", |b| {
+        b.iter(|| parse(black_box(&literals)).unwrap())
+    });
+    group.finish();
+}
+
+fn bench_eval(c: &mut Criterion) {
+    let mut group = c.benchmark_group("eval");
+
+    group.throughput(Throughput::Elements(FIB_CALLS));
+    group.bench_function("deep-recursion", |b| {
+        b.iter(|| eval_str(black_box(FIB), &global_env()).unwrap())
+    });
+    group.throughput(Throughput::Elements(ARITHMETIC_ITERATIONS));
+    group.bench_function("arithmetic", |b| {
+        b.iter(|| eval_str(black_box(ARITHMETIC), &global_env()).unwrap())
+    });
+
+    // One element per run, so these report whole programs per second.
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("list-heavy", |b| {
+        b.iter(|| eval_str(black_box(LISTS), &global_env()).unwrap())
+    });
+    group.bench_function("closure-pipelines", |b| {
+        b.iter(|| eval_str(black_box(PIPELINES), &global_env()).unwrap())
+    });
+    group.bench_function("string-heavy", |b| {
+        b.iter(|| eval_str(black_box(STRINGS), &global_env()).unwrap())
+    });
+    group.bench_function("substrings", |b| {
+        b.iter(|| eval_str(black_box(SUBSTRINGS), &global_env()).unwrap())
+    });
+
+    group.finish();
+}
+
+/// The same programs on the bytecode VM, for comparison with `eval`.
+fn bench_vm(c: &mut Criterion) {
+    let mut group = c.benchmark_group("vm");
+
+    group.throughput(Throughput::Elements(FIB_CALLS));
+    group.bench_function("deep-recursion", |b| {
+        b.iter(|| vm::eval_str(black_box(FIB), &global_env()).unwrap())
+    });
+    group.throughput(Throughput::Elements(ARITHMETIC_ITERATIONS));
+    group.bench_function("arithmetic", |b| {
+        b.iter(|| vm::eval_str(black_box(ARITHMETIC), &global_env()).unwrap())
+    });
+
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("list-heavy", |b| {
+        b.iter(|| vm::eval_str(black_box(LISTS), &global_env()).unwrap())
+    });
+    group.bench_function("closure-pipelines", |b| {
+        b.iter(|| vm::eval_str(black_box(PIPELINES), &global_env()).unwrap())
+    });
+    group.bench_function("string-heavy", |b| {
+        b.iter(|| vm::eval_str(black_box(STRINGS), &global_env()).unwrap())
+    });
+
+    group.finish();
+}
+
+/// A program on the VM as written and after the optimizer.
+fn bench_optimizer(c: &mut Criterion) {
+    let mut group = c.benchmark_group("optimizer");
+    group.throughput(Throughput::Elements(ARITHMETIC_ITERATIONS));
+    let run = |forms: &[Object]| {
+        let env = global_env();
+        for form in forms {
+            vm::eval(form, &env).unwrap();
+        }
+    };
+
+    let forms = parse(CONSTANTS).unwrap();
+    group.bench_function("unoptimized", |b| b.iter(|| run(black_box(&forms))));
+    let optimized = optimize_forms(forms.clone());
+    group.bench_function("optimized", |b| b.iter(|| run(black_box(&optimized))));
+
+    group.finish();
+}
+
+/// A program on the VM compiled a form at a time, and as a whole, with the
+/// calls of its accessors compiled in place.
+fn bench_inlining(c: &mut Criterion) {
+    let mut group = c.benchmark_group("inlining");
+    group.throughput(Throughput::Elements(ACCESSOR_ITERATIONS));
+
+    let forms = parse(ACCESSORS).unwrap();
+    let separately: Vec<TopLevel> = forms
+        .iter()
+        .map(|form| TopLevel::Compiled(Rc::new(compile(form).unwrap())))
+        .collect();
+    group.bench_function("called", |b| {
+        b.iter(|| vm::run_program(black_box(&separately), &global_env()).unwrap())
+    });
+    let whole = compile_program(forms);
+    group.bench_function("inlined", |b| {
+        b.iter(|| vm::run_program(black_box(&whole), &global_env()).unwrap())
+    });
+
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_tokenizer,
+    bench_parser,
+    bench_eval,
+    bench_vm,
+    bench_optimizer,
+    bench_inlining
+);
+criterion_main!(benches);
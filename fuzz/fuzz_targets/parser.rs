@@ -0,0 +1,18 @@
+//! Parses any UTF-8 input, whole, with recovery and a line at a time as
+//! the REPL reads it, none of which may panic.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use lisp_rs::parser::{parse, parse_with_recovery};
+use lisp_rs::repl::FormReader;
+
+fuzz_target!(|input: &str| {
+    let _ = parse(input);
+    let _ = parse_with_recovery(input);
+
+    let mut reader = FormReader::new();
+    for line in input.lines() {
+        let _ = reader.push_line(line);
+    }
+});
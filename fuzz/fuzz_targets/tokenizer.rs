@@ -0,0 +1,13 @@
+//! Tokenizes any UTF-8 input, in each of the ways the crate does, none of
+//! which may panic.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use lisp_rs::lexer::{highlight, tokenizer, tokenizer_with_recovery};
+
+fuzz_target!(|input: &str| {
+    let _ = tokenizer(input);
+    let _ = tokenizer_with_recovery(input);
+    let _ = highlight(input);
+});
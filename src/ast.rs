@@ -0,0 +1,275 @@
+//! Programs as trees of nodes with spans, for tools such as linters,
+//! optimizers and macro passes that look through or rewrite them.
+//!
+//! `parse` reads a program into a `Node` for each of its forms. A node is
+//! an atom, a list or a dotted list, and has the span of its source text,
+//! if it came from some: every list and every element of one has its span,
+//! except in the lists a quote prefix stands for. `walk` calls a `Visitor`
+//! on every node of a tree, and `fold` rebuilds one bottom-up through a
+//! `Folder`. A node a folder makes without a span takes that of the node it
+//! replaces, so that errors in a rewritten program still point at the
+//! source:
+//!
+//! ```text
+//! struct Calls(Vec<(&'static str, Option<Span>)>);
+//!
+//! impl Visitor for Calls {
+//!     fn enter(&mut self, node: &Node) -> bool {
+//!         if let Some(name) = node.head() {
+//!             self.0.push((name, node.span));
+//!         }
+//!         node.head() != Some("quote")
+//!     }
+//! }
+//! ```
+
+use std::fmt;
+
+use crate::diagnostic::Span;
+use crate::object::Object;
+use crate::parser::{parse_with_list_spans, ListSpans, ParseError};
+
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub expr: Expr,
+    pub span: Option<Span>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Expr {
+    /// Anything that is not a pair: a number, a string, a symbol, `()`...
+    Atom(Object),
+    List(Vec<Node>),
+    /// `(a b . tail)`, its elements and its tail.
+    Dotted(Vec<Node>, Box<Node>),
+}
+
+impl Node {
+    /// A node without a span, as made by a rewrite.
+    pub fn new(expr: Expr) -> Self {
+        Node { expr, span: None }
+    }
+
+    pub fn atom(value: Object) -> Self {
+        Node::new(Expr::Atom(value))
+    }
+
+    pub fn list(items: Vec<Node>) -> Self {
+        Node::new(Expr::List(items))
+    }
+
+    /// `value` as a tree of nodes without spans.
+    pub fn from_object(value: &Object) -> Self {
+        node(value, None, &ListSpans::default())
+    }
+
+    /// The value the node stands for, as the evaluator takes it.
+    pub fn to_object(&self) -> Object {
+        match &self.expr {
+            Expr::Atom(value) => value.clone(),
+            Expr::List(items) => {
+                Object::list(items.iter().map(Node::to_object).collect::<Vec<_>>())
+            }
+            Expr::Dotted(items, tail) => items.iter().rev().fold(tail.to_object(), |tail, item| {
+                Object::cons(item.to_object(), tail)
+            }),
+        }
+    }
+
+    /// The name of the symbol a list starts with, as `define` in
+    /// `(define x 1)`.
+    pub fn head(&self) -> Option<&'static str> {
+        match self.children().first()?.expr {
+            Expr::Atom(Object::Symbol(name)) => Some(name.as_str()),
+            _ => None,
+        }
+    }
+
+    /// The nodes a node is made of, its tail last for a dotted list.
+    pub fn children(&self) -> Vec<&Node> {
+        match &self.expr {
+            Expr::Atom(_) => Vec::new(),
+            Expr::List(items) => items.iter().collect(),
+            Expr::Dotted(items, tail) => items.iter().chain([&**tail]).collect(),
+        }
+    }
+}
+
+impl fmt::Display for Node {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_object())
+    }
+}
+
+/// Parses `program` into a tree for each of its top-level forms.
+pub fn parse(program: &str) -> Result<Vec<Node>, ParseError> {
+    let (forms, spans) = parse_with_list_spans(program)?;
+
+    Ok(forms
+        .iter()
+        .map(|(form, span)| node(form, Some(*span), &spans))
+        .collect())
+}
+
+fn node(value: &Object, span: Option<Span>, spans: &ListSpans) -> Node {
+    let Object::Pair(_) = value else {
+        return Node {
+            expr: Expr::Atom(value.clone()),
+            span,
+        };
+    };
+
+    let mut items = Vec::new();
+    let mut current = value.clone();
+    while let Object::Pair(pair) = &current {
+        items.push(node(&pair.car(), spans.car(&current), spans));
+        let next = pair.cdr();
+        current = next;
+    }
+    let expr = match current {
+        Object::Nil => Expr::List(items),
+        tail => Expr::Dotted(items, Box::new(node(&tail, None, spans))),
+    };
+
+    Node {
+        expr,
+        span: spans.get(value).or(span),
+    }
+}
+
+/// Something called on the nodes of a tree by `walk`.
+pub trait Visitor {
+    /// Called on a node before its children, which are visited only if it
+    /// returns true.
+    fn enter(&mut self, _node: &Node) -> bool {
+        true
+    }
+
+    /// Called on a node after its children.
+    fn leave(&mut self, _node: &Node) {}
+}
+
+/// Visits `node` and the nodes under it, depth first and in source order.
+pub fn walk(node: &Node, visitor: &mut impl Visitor) {
+    if visitor.enter(node) {
+        for child in node.children() {
+            walk(child, visitor);
+        }
+    }
+    visitor.leave(node);
+}
+
+/// A rewrite of trees, applied by `fold`.
+pub trait Folder {
+    /// Called on a node before its children are folded. A node it returns
+    /// false for is kept as it is, with everything under it, as the
+    /// quoted data of `(quote ...)` should be.
+    fn enter(&mut self, _node: &Node) -> bool {
+        true
+    }
+
+    /// Rewrites a node whose children have been folded already. Keeps it
+    /// as it is by default.
+    fn fold(&mut self, node: Node) -> Node {
+        node
+    }
+}
+
+/// Rebuilds `node` bottom-up, replacing each node `folder` enters with
+/// what it makes of it. A replacement without a span takes that of the node replaced.
+pub fn fold(node: Node, folder: &mut impl Folder) -> Node {
+    if !folder.enter(&node) {
+        return node;
+    }
+    let Node { expr, span } = node;
+    let expr = match expr {
+        Expr::Atom(value) => Expr::Atom(value),
+        Expr::List(items) => Expr::List(fold_all(items, folder)),
+        Expr::Dotted(items, tail) => {
+            Expr::Dotted(fold_all(items, folder), Box::new(fold(*tail, folder)))
+        }
+    };
+
+    let folded = folder.fold(Node { expr, span });
+    Node {
+        span: folded.span.or(span),
+        ..folded
+    }
+}
+
+fn fold_all(nodes: Vec<Node>, folder: &mut impl Folder) -> Vec<Node> {
+    nodes.into_iter().map(|node| fold(node, folder)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_walk() {
+        struct Calls(Vec<(&'static str, Option<Span>)>);
+
+        impl Visitor for Calls {
+            fn enter(&mut self, node: &Node) -> bool {
+                if let Some(name) = node.head() {
+                    self.0.push((name, node.span));
+                }
+                node.head() != Some("quote")
+            }
+        }
+
+        let program = "(define (f x) (g '(h x) (+ x 1)))";
+        let mut calls = Calls(Vec::new());
+        for form in parse(program).unwrap() {
+            walk(&form, &mut calls);
+        }
+        let calls = calls
+            .0
+            .into_iter()
+            .map(|(name, span)| (name, span.map(|span| &program[span.start..span.end])))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            calls,
+            [
+                ("define", Some(program)),
+                ("f", Some("(f x)")),
+                ("g", Some("(g '(h x) (+ x 1))")),
+                ("quote", Some("'(h x)")),
+                ("+", Some("(+ x 1)")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fold() {
+        /// Replaces `(inc x)` with `(+ x 1)`.
+        struct Inc;
+
+        impl Folder for Inc {
+            fn fold(&mut self, node: Node) -> Node {
+                match (&node.expr, node.head()) {
+                    (Expr::List(items), Some("inc")) if items.len() == 2 => Node::list(vec![
+                        Node::atom(Object::symbol("+")),
+                        items[1].clone(),
+                        Node::atom(Object::Integer(1)),
+                    ]),
+                    _ => node,
+                }
+            }
+        }
+
+        let program = "(list (inc (inc y)) '(a . b))";
+        let form = parse(program).unwrap().remove(0);
+        let folded = fold(form, &mut Inc);
+        assert_eq!(folded.to_string(), "(list (+ (+ y 1) 1) (quote (a . b)))");
+
+        let outer = folded.children()[1];
+        assert_eq!(outer.span, Some(Span::new(6, 19)));
+        assert_eq!(outer.children()[1].span, Some(Span::new(11, 18)));
+        assert_eq!(outer.children()[2].span, None);
+        assert_eq!(
+            Node::from_object(&folded.to_object()).to_string(),
+            folded.to_string()
+        );
+    }
+}
@@ -0,0 +1,163 @@
+//! `(benchmark thunk #:iterations n)`: times a procedure and reports summary
+//! statistics.
+//!
+//! Allocation counts are only available when the host installs
+//! [`CountingAllocator`] as its global allocator, as the `lisp-rs` binary
+//! does; otherwise they are reported as `#f`.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use crate::eval::{apply, EvalError};
+use crate::object::Object;
+
+const DEFAULT_ITERATIONS: i64 = 100;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+static COUNTING: AtomicBool = AtomicBool::new(false);
+
+/// A global allocator that counts allocations on top of [`System`].
+pub struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        COUNTING.store(true, Ordering::Relaxed);
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+/// The allocations made so far, if `CountingAllocator` is counting them.
+pub(crate) fn allocations() -> Option<u64> {
+    COUNTING
+        .load(Ordering::Relaxed)
+        .then(|| ALLOCATIONS.load(Ordering::Relaxed))
+}
+
+fn parse_args(args: &[Object]) -> Result<(&Object, usize), EvalError> {
+    let (thunk, options) = match args.split_first() {
+        Some((
+            thunk @ (Object::Lambda(_)
+            | Object::Builtin(_)
+            | Object::Native(_)
+            | Object::Memo(_)
+            | Object::Closure(_)),
+            options,
+        )) => (thunk, options),
+        _ => {
+            return Err(EvalError::new(
+                "benchmark expects a procedure of no arguments",
+            ))
+        }
+    };
+
+    let mut iterations = DEFAULT_ITERATIONS;
+    for option in options.chunks(2) {
+        match option {
+            [Object::Symbol(key), Object::Integer(n)] if key == "#:iterations" && *n > 0 => {
+                iterations = *n;
+            }
+            _ => {
+                return Err(EvalError::new(
+                    "benchmark accepts only #:iterations with a positive integer",
+                ))
+            }
+        }
+    }
+
+    Ok((thunk, iterations as usize))
+}
+
+/// A clock reading in milliseconds. `Instant` is unsupported on wasm32,
+/// where the wall clock stands in for it.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn now() -> f64 {
+    use std::sync::OnceLock;
+    use std::time::Instant;
+
+    static START: OnceLock<Instant> = OnceLock::new();
+    START.get_or_init(Instant::now).elapsed().as_secs_f64() * 1000.0
+}
+
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn now() -> f64 {
+    jiff::Timestamp::now().as_millisecond() as f64
+}
+
+/// Runs the thunk and returns an association list of timings in
+/// milliseconds, plus the mean number of allocations per iteration.
+pub fn benchmark(args: &[Object]) -> Result<Object, EvalError> {
+    let (thunk, iterations) = parse_args(args)?;
+    let mut samples = Vec::with_capacity(iterations);
+
+    let allocations_before = allocations();
+    for _ in 0..iterations {
+        let start = now();
+        apply(thunk, Vec::new())?;
+        samples.push(now() - start);
+    }
+    let allocations = match (allocations_before, allocations()) {
+        (Some(before), Some(after)) => {
+            Object::Integer(((after - before) / iterations as u64) as i64)
+        }
+        _ => Object::Bool(false),
+    };
+
+    samples.sort_by(f64::total_cmp);
+    let n = samples.len() as f64;
+    let mean = samples.iter().sum::<f64>() / n;
+    let variance = samples.iter().map(|s| (s - mean).powi(2)).sum::<f64>() / n;
+    let median = match samples.len() % 2 {
+        0 => (samples[samples.len() / 2 - 1] + samples[samples.len() / 2]) / 2.0,
+        _ => samples[samples.len() / 2],
+    };
+
+    let entry = |key: &str, value: Object| Object::cons(Object::symbol(key), value);
+    Ok(Object::list(vec![
+        entry("iterations", Object::Integer(iterations as i64)),
+        entry("min", Object::Float(samples[0])),
+        entry("median", Object::Float(median)),
+        entry("mean", Object::Float(mean)),
+        entry("stddev", Object::Float(variance.sqrt())),
+        entry("allocations", allocations),
+    ]))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builtins::global_env;
+    use crate::eval::eval_str;
+
+    #[test]
+    fn test_benchmark_report() {
+        let env = global_env();
+        let report = eval_str(
+            "(define calls 0)
+             (benchmark (lambda () (set! calls (+ calls 1))) #:iterations 5)",
+            &env,
+        )
+        .unwrap();
+
+        assert_eq!(report.to_vec().unwrap().len(), 6);
+        assert!(report.to_string().starts_with("((iterations . 5) (min . "));
+        assert_eq!(eval_str("calls", &env).unwrap().to_string(), "5");
+    }
+
+    #[test]
+    fn test_benchmark_rejects_bad_options() {
+        let env = global_env();
+
+        assert!(eval_str("(benchmark 1)", &env).is_err());
+        assert!(eval_str("(benchmark (lambda () 1) #:iterations 0)", &env).is_err());
+        assert!(eval_str("(benchmark (lambda () 1) #:runs 3)", &env).is_err());
+    }
+}
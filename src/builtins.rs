@@ -0,0 +1,1320 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::rc::Rc;
+
+use crate::bench;
+use crate::bigint::BigInt;
+use crate::collections;
+use crate::comparator;
+use crate::continuation;
+use crate::datetime;
+use crate::debugger;
+use crate::diff;
+use crate::env::Env;
+use crate::eval::EvalError;
+use crate::fiber;
+use crate::functional;
+use crate::gc;
+use crate::graph;
+use crate::i18n;
+use crate::id;
+use crate::json;
+use crate::kv;
+use crate::limits;
+use crate::macros;
+use crate::math;
+use crate::memo;
+use crate::module;
+use crate::net;
+use crate::object::{self, Builtin, BuiltinFn, Object, Pair};
+use crate::parallel;
+use crate::prelude;
+use crate::printer;
+use crate::process;
+use crate::promise;
+use crate::ratelimit;
+use crate::rational::Rational;
+use crate::reader;
+use crate::reference;
+use crate::scheduler;
+use crate::signature;
+use crate::sorted_map;
+use crate::taint;
+use crate::task;
+use crate::template;
+use crate::text::Str;
+use crate::values;
+
+const BUILTINS: &[(&str, BuiltinFn)] = &[
+    ("+", add),
+    ("-", sub),
+    ("*", mul),
+    ("/", div),
+    ("expt", expt),
+    ("abs", math::abs),
+    ("min", math::min),
+    ("max", math::max),
+    ("floor", math::floor),
+    ("ceiling", math::ceiling),
+    ("round", math::round),
+    ("truncate", math::truncate),
+    ("quotient", math::quotient),
+    ("remainder", math::remainder),
+    ("modulo", math::modulo),
+    ("sqrt", math::sqrt),
+    ("exp", math::exp),
+    ("log", math::log),
+    ("sin", math::sin),
+    ("cos", math::cos),
+    ("tan", math::tan),
+    ("asin", math::asin),
+    ("acos", math::acos),
+    ("atan", math::atan),
+    ("random", math::random),
+    ("set-random-seed!", math::set_random_seed),
+    ("exact->inexact", exact_to_inexact),
+    ("inexact->exact", inexact_to_exact),
+    ("=", num_eq),
+    ("<", lt),
+    (">", gt),
+    ("<=", le),
+    (">=", ge),
+    ("cons", cons),
+    ("car", car),
+    ("cdr", cdr),
+    ("set-car!", set_car),
+    ("set-cdr!", set_cdr),
+    ("list-set!", list_set),
+    ("append!", append_in_place),
+    ("reverse!", reverse_in_place),
+    ("list", list),
+    ("eq?", eq),
+    ("eqv?", eqv),
+    ("equal?", equal),
+    ("number?", is_number),
+    ("string?", is_string),
+    ("symbol?", is_symbol),
+    ("pair?", is_pair),
+    ("null?", is_null),
+    ("list?", is_list),
+    ("procedure?", is_procedure),
+    ("diff", diff::diff_builtin),
+    ("hash", hash),
+    ("copy", copy),
+    ("apply", functional::apply),
+    ("values", values::values),
+    ("call-with-values", values::call_with_values),
+    ("force", promise::force),
+    ("make-promise", promise::make_promise),
+    ("promise?", promise::is_promise),
+    ("map", functional::map),
+    ("par-map", parallel::par_map),
+    ("for-each", functional::for_each),
+    ("filter", functional::filter),
+    ("reduce", functional::reduce),
+    ("fold-left", functional::fold_left),
+    ("fold-right", functional::fold_right),
+    ("call/cc", continuation::call_cc),
+    ("call-with-current-continuation", continuation::call_cc),
+    ("call-with-escape-continuation", continuation::call_cc),
+    ("dynamic-wind", continuation::dynamic_wind),
+    ("memoize", memo::memoize),
+    ("benchmark", bench::benchmark),
+    ("break", debugger::break_),
+    ("gc", gc::gc),
+    ("gc-stats", gc::gc_stats),
+    ("char->integer", char_to_integer),
+    ("integer->char", integer_to_char),
+    ("char-upcase", char_upcase),
+    ("char-downcase", char_downcase),
+    ("string-ref", string_ref),
+    ("string-length", string_length),
+    ("substring", substring),
+    ("string-append", string_append),
+    ("string-copy", string_copy),
+    ("string->list", string_to_list),
+    ("list->string", list_to_string),
+    ("tainted?", taint::is_tainted),
+    ("taint", taint::taint),
+    ("untaint", taint::untaint),
+    ("make-comparator", comparator::make_comparator),
+    (
+        "make-default-comparator",
+        comparator::make_default_comparator,
+    ),
+    ("comparator?", comparator::is_comparator),
+    ("comparator-ordered?", comparator::is_ordered),
+    ("comparator-hashable?", comparator::is_hashable),
+    ("comparator-hash", comparator::comparator_hash),
+    ("=?", comparator::equal),
+    ("<?", comparator::less),
+    (">?", comparator::greater),
+    ("<=?", comparator::less_or_equal),
+    (">=?", comparator::greater_or_equal),
+    ("sort", comparator::sort),
+    ("deque-sort!", comparator::deque_sort),
+    ("make-heap", collections::make_heap),
+    ("heap-push!", collections::heap_push),
+    ("heap-pop!", collections::heap_pop),
+    ("heap-peek", collections::heap_peek),
+    ("heap-size", collections::heap_size),
+    ("make-deque", collections::make_deque),
+    ("deque-push-front!", collections::deque_push_front),
+    ("deque-push-back!", collections::deque_push_back),
+    ("deque-pop-front!", collections::deque_pop_front),
+    ("deque-pop-back!", collections::deque_pop_back),
+    ("deque-size", collections::deque_size),
+    ("make-hash-table", collections::make_hash_table),
+    ("hash-table?", collections::is_hash_table),
+    ("hash-table-set!", collections::hash_table_set),
+    ("hash-table-ref", collections::hash_table_ref),
+    ("hash-table-delete!", collections::hash_table_delete),
+    ("hash-table-count", collections::hash_table_count),
+    ("hash-table-keys", collections::hash_table_keys),
+    ("hash-table->alist", collections::hash_table_to_alist),
+    ("alist->hash-table", collections::alist_to_hash_table),
+    ("assq", collections::assq),
+    ("assv", collections::assv),
+    ("assoc", collections::assoc),
+    ("gensym", macros::gensym),
+    ("kv-open", kv::kv_open),
+    ("kv-get", kv::kv_get),
+    ("kv-set!", kv::kv_set),
+    ("kv-delete!", kv::kv_delete),
+    ("kv-keys", kv::kv_keys),
+    ("kv-compact!", kv::kv_compact),
+    ("tcp-connect", net::tcp_connect),
+    ("tcp-listen", net::tcp_listen),
+    ("tcp-accept", net::tcp_accept),
+    ("socket-read-line", net::socket_read_line),
+    ("socket-write", net::socket_write),
+    ("socket-port", net::socket_port),
+    ("socket-close", net::socket_close),
+    ("render-template", template::render_template),
+    ("json-parse", json::json_parse),
+    ("json-stringify", json::json_stringify),
+    ("make-sorted-map", sorted_map::make_sorted_map),
+    ("sorted-map-set!", sorted_map::sorted_map_set),
+    ("sorted-map-ref", sorted_map::sorted_map_ref),
+    ("sorted-map-delete!", sorted_map::sorted_map_delete),
+    ("sorted-map-size", sorted_map::sorted_map_size),
+    ("sorted-map->list", sorted_map::sorted_map_to_list),
+    ("sorted-map-keys", sorted_map::sorted_map_keys),
+    ("sorted-map-floor-key", sorted_map::sorted_map_floor_key),
+    ("sorted-map-ceiling-key", sorted_map::sorted_map_ceiling_key),
+    ("sorted-map-submap", sorted_map::sorted_map_submap),
+    ("make-graph", graph::make_graph),
+    ("topological-sort", graph::topological_sort),
+    (
+        "strongly-connected-components",
+        graph::strongly_connected_components,
+    ),
+    ("shortest-path", graph::shortest_path),
+    ("datetime-now", datetime::datetime_now),
+    ("string->datetime", datetime::string_to_datetime),
+    ("datetime->string", datetime::datetime_to_string),
+    ("datetime->timezone", datetime::datetime_to_timezone),
+    ("datetime-add", datetime::datetime_add),
+    ("datetime-difference", datetime::datetime_difference),
+    ("current-time", datetime::current_time),
+    ("time-millis", datetime::time_millis),
+    ("sleep", datetime::sleep),
+    ("define-messages", i18n::define_messages),
+    ("set-locale!", i18n::set_locale),
+    ("current-locale", i18n::current_locale),
+    ("msg", i18n::msg),
+    ("uuid4", id::uuid4),
+    ("uuid7", id::uuid7),
+    ("ulid", id::ulid),
+    ("spawn", task::spawn),
+    ("join", task::join),
+    ("spawn-fiber", fiber::spawn_fiber),
+    ("yield-fiber", fiber::yield_fiber),
+    ("join-fiber", fiber::join_fiber),
+    ("run-fibers", fiber::run_fibers),
+    ("fiber-done?", fiber::fiber_done),
+    ("make-channel", fiber::make_channel),
+    ("channel-send!", fiber::channel_send),
+    ("channel-receive", fiber::channel_receive),
+    ("make-rate-limiter", ratelimit::make_rate_limiter),
+    ("acquire", ratelimit::acquire),
+    ("try-acquire", ratelimit::try_acquire),
+    ("schedule", scheduler::schedule),
+    ("run-scheduler", scheduler::run_scheduler),
+    ("stop-scheduler", scheduler::stop_scheduler),
+    ("pp", printer::pp),
+    ("set-ordered-printing!", printer::set_ordered_printing),
+    ("set-float-format!", printer::set_float_format_builtin),
+    ("float-format", printer::float_format_builtin),
+    ("reload-module", module::reload_module),
+    ("read", reader::read),
+    ("read-string", reader::read_string),
+    ("eval", reader::eval_datum),
+    ("interaction-environment", reader::interaction_environment),
+    ("command-line", command_line),
+    ("getenv", process::getenv),
+    ("setenv", process::setenv),
+    ("system", process::system),
+    ("process-run", process::process_run),
+    ("exit", exit),
+    ("procedure-signature", signature::procedure_signature),
+    ("help", reference::help),
+];
+
+/// Every builtin table: the core one plus those of enabled features.
+pub(crate) fn builtins() -> impl Iterator<Item = &'static (&'static str, BuiltinFn)> {
+    let tables: &[&[(&str, BuiltinFn)]] = &[
+        BUILTINS,
+        #[cfg(feature = "terminal")]
+        crate::terminal::BUILTINS,
+        #[cfg(all(feature = "signals", unix))]
+        crate::signal::BUILTINS,
+        #[cfg(feature = "watch")]
+        crate::watch::BUILTINS,
+        #[cfg(feature = "regex")]
+        crate::regex::BUILTINS,
+    ];
+
+    tables.iter().flat_map(|table| table.iter())
+}
+
+/// A global environment with every builtin defined, and the procedures of
+/// the prelude unless it is disabled on this thread.
+pub fn global_env() -> Rc<RefCell<Env>> {
+    let env = builtin_env();
+    if prelude::is_enabled() {
+        prelude::load(&env);
+    }
+
+    env
+}
+
+/// A global environment with only the builtins written in Rust.
+pub fn builtin_env() -> Rc<RefCell<Env>> {
+    let mut env = Env::new();
+
+    for &(name, func) in builtins() {
+        env.define(name, Object::Builtin(Builtin { name, func }));
+    }
+
+    let env = Rc::new(RefCell::new(env));
+    gc::track(&env);
+
+    env
+}
+
+/// Finds a builtin by name without going through an environment.
+pub fn lookup(name: &str) -> Option<Builtin> {
+    builtins()
+        .find(|(builtin, _)| *builtin == name)
+        .map(|&(name, func)| Builtin { name, func })
+}
+
+pub(crate) fn check_arity(name: &str, args: &[Object], expected: usize) -> Result<(), EvalError> {
+    if args.len() != expected {
+        return Err(EvalError::new(format!(
+            "{} expects {} arguments, got {}",
+            name,
+            expected,
+            args.len()
+        )));
+    }
+
+    Ok(())
+}
+
+pub(crate) enum Numbers {
+    Integers(i64, i64),
+    BigInts(BigInt, BigInt),
+    Rationals(Rational, Rational),
+    Floats(f64, f64),
+}
+
+pub(crate) fn numbers(name: &str, args: &[Object]) -> Result<Numbers, EvalError> {
+    check_arity(name, args, 2)?;
+
+    match (&args[0], &args[1]) {
+        (Object::Integer(a), Object::Integer(b)) => Ok(Numbers::Integers(*a, *b)),
+        (a, b) => match (to_bigint(a), to_bigint(b)) {
+            (Some(a), Some(b)) => Ok(Numbers::BigInts(a, b)),
+            _ => match (to_rational(a), to_rational(b)) {
+                (Some(a), Some(b)) => Ok(Numbers::Rationals(a, b)),
+                _ => match (to_f64(a), to_f64(b)) {
+                    (Some(a), Some(b)) => Ok(Numbers::Floats(a, b)),
+                    _ => Err(EvalError::new(format!(
+                        "{} expects numbers, got {} and {}",
+                        name,
+                        a.type_name(),
+                        b.type_name()
+                    ))),
+                },
+            },
+        },
+    }
+}
+
+fn to_bigint(obj: &Object) -> Option<BigInt> {
+    match obj {
+        Object::Integer(n) => Some(BigInt::from_i64(*n)),
+        Object::BigInt(n) => Some(n.as_ref().clone()),
+        _ => None,
+    }
+}
+
+fn to_rational(obj: &Object) -> Option<Rational> {
+    match obj {
+        Object::Rational(n) => Some(n.as_ref().clone()),
+        _ => to_bigint(obj).map(Rational::from_integer),
+    }
+}
+
+pub(crate) fn to_f64(obj: &Object) -> Option<f64> {
+    match obj {
+        Object::Integer(n) => Some(*n as f64),
+        Object::BigInt(n) => Some(n.to_f64()),
+        Object::Rational(n) => Some(n.to_f64()),
+        Object::Float(n) => Some(*n),
+        _ => None,
+    }
+}
+
+/// Defines an arithmetic builtin whose `i64` fast path promotes to a bignum
+/// when the checked operation overflows.
+macro_rules! arithmetic {
+    ($name:ident, $symbol:literal, $checked:ident, $op:tt) => {
+        fn $name(args: &[Object]) -> Result<Object, EvalError> {
+            Ok(match numbers($symbol, args)? {
+                Numbers::Integers(a, b) => match a.$checked(b) {
+                    Some(n) => Object::Integer(n),
+                    None => Object::from_bigint(&BigInt::from_i64(a) $op &BigInt::from_i64(b)),
+                },
+                Numbers::BigInts(a, b) => Object::from_bigint(&a $op &b),
+                Numbers::Rationals(a, b) => Object::from_rational(&a $op &b),
+                Numbers::Floats(a, b) => Object::Float(a $op b),
+            })
+        }
+    };
+}
+
+arithmetic!(add_pair, "+", checked_add, +);
+arithmetic!(sub, "-", checked_sub, -);
+arithmetic!(mul_pair, "*", checked_mul, *);
+
+/// Folds a binary operation over any number of arguments, left to right,
+/// starting from its identity.
+fn fold_numbers(args: &[Object], identity: i64, op: BuiltinFn) -> Result<Object, EvalError> {
+    args.iter().try_fold(Object::Integer(identity), |acc, arg| {
+        op(&[acc, arg.clone()])
+    })
+}
+
+fn add(args: &[Object]) -> Result<Object, EvalError> {
+    fold_numbers(args, 0, add_pair)
+}
+
+fn mul(args: &[Object]) -> Result<Object, EvalError> {
+    fold_numbers(args, 1, mul_pair)
+}
+
+/// Exact division yields an integer when the divisor divides evenly and a
+/// rational otherwise; only floats divide inexactly.
+fn div(args: &[Object]) -> Result<Object, EvalError> {
+    let quotient = match numbers("/", args)? {
+        // The remainder of `i64::MIN / -1` overflows, but the division is exact.
+        Numbers::Integers(a, b) if b != 0 && a.checked_rem(b).unwrap_or(0) == 0 => {
+            match a.checked_div(b) {
+                Some(n) => return Ok(Object::Integer(n)),
+                None => return Ok(Object::from_bigint(-&BigInt::from_i64(a))),
+            }
+        }
+        Numbers::Integers(a, b) => Rational::new(BigInt::from_i64(a), BigInt::from_i64(b)),
+        Numbers::BigInts(a, b) => Rational::new(a, b),
+        Numbers::Rationals(a, b) => a.checked_div(&b),
+        Numbers::Floats(a, b) => return Ok(Object::Float(a / b)),
+    };
+
+    quotient
+        .map(Object::from_rational)
+        .ok_or_else(|| EvalError::new("division by zero"))
+}
+
+fn expt(args: &[Object]) -> Result<Object, EvalError> {
+    check_arity("expt", args, 2)?;
+
+    if let (Some(base), Object::Integer(exp)) = (to_rational(&args[0]), &args[1]) {
+        if let Ok(magnitude) = u32::try_from(exp.unsigned_abs()) {
+            if let (Object::Integer(base), Ok(exp)) = (&args[0], u32::try_from(*exp)) {
+                if let Some(n) = base.checked_pow(exp) {
+                    return Ok(Object::Integer(n));
+                }
+            }
+
+            // Squaring a number of n limbs takes about n² products of
+            // limbs, a few hundred of which take as long as a step.
+            let bits = base.numerator().bits().max(base.denominator().bits());
+            let limbs = bits.saturating_sub(1).saturating_mul(magnitude.into()) / 32 + 1;
+            limits::charge(limbs.saturating_mul(limbs) / 256)?;
+
+            let power = base.pow(magnitude);
+            if *exp >= 0 {
+                return Ok(Object::from_rational(power));
+            }
+
+            return power
+                .recip()
+                .map(Object::from_rational)
+                .ok_or_else(|| EvalError::new("division by zero"));
+        }
+    }
+
+    match (to_f64(&args[0]), to_f64(&args[1])) {
+        (Some(base), Some(exp)) => Ok(Object::Float(base.powf(exp))),
+        _ => Err(EvalError::new(format!(
+            "expt expects numbers, got {} and {}",
+            args[0].type_name(),
+            args[1].type_name()
+        ))),
+    }
+}
+
+macro_rules! comparison {
+    ($name:ident, $symbol:literal, $op:tt) => {
+        fn $name(args: &[Object]) -> Result<Object, EvalError> {
+            Ok(Object::Bool(match numbers($symbol, args)? {
+                Numbers::Integers(a, b) => a $op b,
+                Numbers::BigInts(a, b) => a $op b,
+                Numbers::Rationals(a, b) => a $op b,
+                Numbers::Floats(a, b) => a $op b,
+            }))
+        }
+    };
+}
+
+comparison!(num_eq, "=", ==);
+comparison!(lt, "<", <);
+comparison!(gt, ">", >);
+comparison!(le, "<=", <=);
+comparison!(ge, ">=", >=);
+
+fn exact_to_inexact(args: &[Object]) -> Result<Object, EvalError> {
+    check_arity("exact->inexact", args, 1)?;
+
+    to_f64(&args[0]).map(Object::Float).ok_or_else(|| {
+        EvalError::new(format!(
+            "exact->inexact expects a number, got {}",
+            args[0].type_name()
+        ))
+    })
+}
+
+fn inexact_to_exact(args: &[Object]) -> Result<Object, EvalError> {
+    check_arity("inexact->exact", args, 1)?;
+
+    match &args[0] {
+        Object::Float(n) => Rational::from_f64(*n)
+            .map(Object::from_rational)
+            .ok_or_else(|| EvalError::new(format!("{} has no exact representation", n))),
+        n @ (Object::Integer(_) | Object::BigInt(_) | Object::Rational(_)) => Ok(n.clone()),
+        other => Err(EvalError::new(format!(
+            "inexact->exact expects a number, got {}",
+            other.type_name()
+        ))),
+    }
+}
+
+fn cons(args: &[Object]) -> Result<Object, EvalError> {
+    check_arity("cons", args, 2)?;
+
+    Ok(Object::cons(args[0].clone(), args[1].clone()))
+}
+
+fn car(args: &[Object]) -> Result<Object, EvalError> {
+    check_arity("car", args, 1)?;
+
+    match &args[0] {
+        Object::Pair(pair) => Ok(pair.car()),
+        other => Err(EvalError::new(format!("car expects a pair, got {}", other))),
+    }
+}
+
+fn cdr(args: &[Object]) -> Result<Object, EvalError> {
+    check_arity("cdr", args, 1)?;
+
+    match &args[0] {
+        Object::Pair(pair) => Ok(pair.cdr()),
+        other => Err(EvalError::new(format!("cdr expects a pair, got {}", other))),
+    }
+}
+
+fn set_car(args: &[Object]) -> Result<Object, EvalError> {
+    check_arity("set-car!", args, 2)?;
+
+    match &args[0] {
+        Object::Pair(pair) => pair.set_car(args[1].clone()),
+        other => {
+            return Err(EvalError::new(format!(
+                "set-car! expects a pair, got {}",
+                other
+            )))
+        }
+    }
+    Ok(Object::Void)
+}
+
+fn set_cdr(args: &[Object]) -> Result<Object, EvalError> {
+    check_arity("set-cdr!", args, 2)?;
+
+    match &args[0] {
+        Object::Pair(pair) => pair.set_cdr(args[1].clone()),
+        other => {
+            return Err(EvalError::new(format!(
+                "set-cdr! expects a pair, got {}",
+                other
+            )))
+        }
+    }
+    Ok(Object::Void)
+}
+
+/// The pairs of the proper list `obj`, for the builtins relinking them.
+fn pairs(name: &str, obj: &Object) -> Result<Vec<Rc<Pair>>, EvalError> {
+    let expected = || EvalError::new(format!("{} expects a list, got {}", name, obj));
+    let length = obj.to_vec().ok_or_else(expected)?.len();
+    let mut pairs = Vec::with_capacity(length);
+    let mut current = obj.clone();
+    while let Object::Pair(pair) = current {
+        current = pair.cdr();
+        pairs.push(pair);
+    }
+
+    Ok(pairs)
+}
+
+/// `(list-set! list k obj)` replaces the element of `list` at index `k`.
+fn list_set(args: &[Object]) -> Result<Object, EvalError> {
+    check_arity("list-set!", args, 3)?;
+    let k = index_arg("list-set!", &args[1])?;
+
+    match pairs("list-set!", &args[0])?.get(k) {
+        Some(pair) => pair.set_car(args[2].clone()),
+        None => {
+            return Err(EvalError::new(format!(
+                "list-set! index out of range: {}",
+                k
+            )))
+        }
+    }
+    Ok(Object::Void)
+}
+
+/// `(append! list ...)` joins the lists by linking the last pair of each to
+/// the next, and returns the first that is not empty. The last argument,
+/// like `append`'s, can be any object.
+fn append_in_place(args: &[Object]) -> Result<Object, EvalError> {
+    let Some((last, lists)) = args.split_last() else {
+        return Ok(Object::Nil);
+    };
+
+    let mut result = last.clone();
+    for list in lists.iter().rev() {
+        if let Some(end) = pairs("append!", list)?.last() {
+            end.set_cdr(result);
+            result = list.clone();
+        }
+    }
+    Ok(result)
+}
+
+/// `(reverse! list)` reverses `list` by relinking its pairs, and returns
+/// the one that was last.
+fn reverse_in_place(args: &[Object]) -> Result<Object, EvalError> {
+    check_arity("reverse!", args, 1)?;
+
+    let mut reversed = Object::Nil;
+    for pair in pairs("reverse!", &args[0])? {
+        pair.set_cdr(reversed);
+        reversed = Object::Pair(pair);
+    }
+    Ok(reversed)
+}
+
+fn list(args: &[Object]) -> Result<Object, EvalError> {
+    Ok(Object::list(args.to_vec()))
+}
+
+fn eq(args: &[Object]) -> Result<Object, EvalError> {
+    check_arity("eq?", args, 2)?;
+
+    Ok(Object::Bool(args[0].is_eq(&args[1])))
+}
+
+fn eqv(args: &[Object]) -> Result<Object, EvalError> {
+    check_arity("eqv?", args, 2)?;
+
+    Ok(Object::Bool(args[0].is_eqv(&args[1])))
+}
+
+fn equal(args: &[Object]) -> Result<Object, EvalError> {
+    check_arity("equal?", args, 2)?;
+
+    Ok(Object::Bool(args[0] == args[1]))
+}
+
+/// Defines a builtin telling whether its one argument is of some type.
+macro_rules! type_predicate {
+    ($name:ident, $symbol:literal, $obj:ident => $test:expr) => {
+        fn $name(args: &[Object]) -> Result<Object, EvalError> {
+            check_arity($symbol, args, 1)?;
+            let $obj = &args[0];
+
+            Ok(Object::Bool($test))
+        }
+    };
+}
+
+type_predicate!(is_number, "number?", obj => to_f64(obj).is_some());
+type_predicate!(is_string, "string?", obj => matches!(obj, Object::String(_)));
+type_predicate!(is_symbol, "symbol?", obj => matches!(obj, Object::Symbol(_)));
+type_predicate!(is_pair, "pair?", obj => matches!(obj, Object::Pair(_)));
+type_predicate!(is_null, "null?", obj => matches!(obj, Object::Nil));
+type_predicate!(is_procedure, "procedure?", obj => obj.is_procedure());
+
+/// `(list? x)` is true of the empty list and pairs ending in one, and false
+/// of circular lists.
+fn is_list(args: &[Object]) -> Result<Object, EvalError> {
+    check_arity("list?", args, 1)?;
+
+    Ok(Object::Bool(args[0].to_vec().is_some()))
+}
+
+/// `(hash v)` is the same for values that are `equal?`. Symbols and
+/// procedures hash by identity, so hashes only hold within one run.
+fn hash(args: &[Object]) -> Result<Object, EvalError> {
+    check_arity("hash", args, 1)?;
+
+    let mut hasher = DefaultHasher::new();
+    args[0].hash(&mut hasher);
+
+    Ok(Object::Integer(hasher.finish() as i64))
+}
+
+/// What is left to do while copying: copy an object, or fill in a pair or
+/// a deque from the copies made last.
+enum CopyStep {
+    Copy(Object),
+    Cons,
+    /// Sets the car and cdr of a pair made before its parts were copied.
+    Link(Rc<Pair>),
+    Fill(Rc<RefCell<VecDeque<Object>>>, usize),
+}
+
+/// `(copy obj)` makes a fresh copy of the lists and deques in `obj`, all the
+/// way down. Other objects are shared with the original. A list or deque
+/// reached twice is copied once, so the copy of a circular list loops back
+/// in the same place.
+fn copy(args: &[Object]) -> Result<Object, EvalError> {
+    check_arity("copy", args, 1)?;
+
+    // The copies made so far, by the address of the original. Pairs only
+    // need them once a list may contain itself; until then each pair is
+    // built from its copied parts, without being registered with the
+    // collector.
+    let mut copied = HashMap::new();
+    let cycles = object::may_cycle();
+    let mut steps = vec![CopyStep::Copy(args[0].clone())];
+    let mut copies = Vec::new();
+    while let Some(step) = steps.pop() {
+        match step {
+            CopyStep::Copy(Object::Pair(pair)) if cycles => {
+                let address = Rc::as_ptr(&pair) as usize;
+                if let Some(copy) = copied.get(&address) {
+                    copies.push(Object::clone(copy));
+                    continue;
+                }
+                let Object::Pair(copy) = Object::cons(Object::Nil, Object::Nil) else {
+                    unreachable!("cons makes pairs");
+                };
+                copied.insert(address, Object::Pair(copy.clone()));
+                copies.push(Object::Pair(copy.clone()));
+                steps.push(CopyStep::Link(copy));
+                steps.push(CopyStep::Copy(pair.cdr()));
+                steps.push(CopyStep::Copy(pair.car()));
+            }
+            CopyStep::Copy(Object::Pair(pair)) => {
+                steps.push(CopyStep::Cons);
+                steps.push(CopyStep::Copy(pair.cdr()));
+                steps.push(CopyStep::Copy(pair.car()));
+            }
+            CopyStep::Copy(Object::Deque(deque)) => {
+                let address = Rc::as_ptr(&deque) as usize;
+                if let Some(copy) = copied.get(&address) {
+                    copies.push(Object::clone(copy));
+                    continue;
+                }
+                let copy = Rc::new(RefCell::new(VecDeque::new()));
+                gc::track(&copy);
+                copied.insert(address, Object::Deque(copy.clone()));
+                copies.push(Object::Deque(copy.clone()));
+                let deque = deque.borrow();
+                steps.push(CopyStep::Fill(copy, deque.len()));
+                steps.extend(deque.iter().rev().cloned().map(CopyStep::Copy));
+            }
+            CopyStep::Copy(other) => copies.push(other),
+            CopyStep::Cons => {
+                let cdr = copies.pop().expect("the cdr was copied");
+                let car = copies.pop().expect("the car was copied");
+                copies.push(Object::cons(car, cdr));
+            }
+            CopyStep::Link(pair) => {
+                let cdr = copies.pop().expect("the cdr was copied");
+                let car = copies.pop().expect("the car was copied");
+                pair.set_car(car);
+                pair.set_cdr(cdr);
+            }
+            CopyStep::Fill(deque, len) => {
+                let items = copies.split_off(copies.len() - len);
+                deque.borrow_mut().extend(items);
+            }
+        }
+    }
+
+    Ok(copies.pop().expect("the argument was copied"))
+}
+
+fn char_arg(name: &str, args: &[Object]) -> Result<char, EvalError> {
+    check_arity(name, args, 1)?;
+
+    match &args[0] {
+        Object::Char(c) => Ok(*c),
+        other => Err(EvalError::new(format!(
+            "{} expects a char, got {}",
+            name,
+            other.type_name()
+        ))),
+    }
+}
+
+fn char_to_integer(args: &[Object]) -> Result<Object, EvalError> {
+    Ok(Object::Integer(char_arg("char->integer", args)? as i64))
+}
+
+fn integer_to_char(args: &[Object]) -> Result<Object, EvalError> {
+    check_arity("integer->char", args, 1)?;
+
+    match &args[0] {
+        Object::Integer(n) => u32::try_from(*n)
+            .ok()
+            .and_then(char::from_u32)
+            .map(Object::Char)
+            .ok_or_else(|| EvalError::new(format!("{} is not a valid character code", n))),
+        other => Err(EvalError::new(format!(
+            "integer->char expects an integer, got {}",
+            other.type_name()
+        ))),
+    }
+}
+
+/// Maps a character through a case conversion, leaving it unchanged when the
+/// conversion does not produce exactly one character (e.g. `ß` to `SS`).
+fn convert_case<I>(c: char, converted: I) -> char
+where
+    I: Iterator<Item = char>,
+{
+    let converted: Vec<char> = converted.collect();
+
+    match converted.as_slice() {
+        [single] => *single,
+        _ => c,
+    }
+}
+
+fn char_upcase(args: &[Object]) -> Result<Object, EvalError> {
+    let c = char_arg("char-upcase", args)?;
+
+    Ok(Object::Char(convert_case(c, c.to_uppercase())))
+}
+
+fn char_downcase(args: &[Object]) -> Result<Object, EvalError> {
+    let c = char_arg("char-downcase", args)?;
+
+    Ok(Object::Char(convert_case(c, c.to_lowercase())))
+}
+
+fn string_ref(args: &[Object]) -> Result<Object, EvalError> {
+    check_arity("string-ref", args, 2)?;
+
+    match (&args[0], &args[1]) {
+        (Object::String(s), Object::Integer(k)) => usize::try_from(*k)
+            .ok()
+            .and_then(|k| {
+                taint::take_apart(s);
+                s.chars().nth(k)
+            })
+            .map(Object::Char)
+            .ok_or_else(|| EvalError::new(format!("string-ref index out of range: {}", k))),
+        (a, b) => Err(EvalError::new(format!(
+            "string-ref expects a string and an integer, got {} and {}",
+            a.type_name(),
+            b.type_name()
+        ))),
+    }
+}
+
+fn string_arg<'a>(name: &str, obj: &'a Object) -> Result<&'a Str, EvalError> {
+    match obj {
+        Object::String(s) => Ok(s),
+        other => Err(EvalError::new(format!(
+            "{} expects a string, got {}",
+            name,
+            other.type_name()
+        ))),
+    }
+}
+
+fn index_arg(name: &str, obj: &Object) -> Result<usize, EvalError> {
+    match obj {
+        Object::Integer(k) => usize::try_from(*k)
+            .map_err(|_| EvalError::new(format!("{} index out of range: {}", name, k))),
+        other => Err(EvalError::new(format!(
+            "{} expects an integer index, got {}",
+            name,
+            other.type_name()
+        ))),
+    }
+}
+
+fn string_length(args: &[Object]) -> Result<Object, EvalError> {
+    check_arity("string-length", args, 1)?;
+
+    let len = string_arg("string-length", &args[0])?.char_len();
+    Ok(Object::Integer(len as i64))
+}
+
+/// `(substring s start [end])` shares the text of `s` instead of copying it.
+fn substring(args: &[Object]) -> Result<Object, EvalError> {
+    let (s, start, end) = match args {
+        [s, start] => {
+            let s = string_arg("substring", s)?;
+            (s, index_arg("substring", start)?, s.char_len())
+        }
+        [s, start, end] => (
+            string_arg("substring", s)?,
+            index_arg("substring", start)?,
+            index_arg("substring", end)?,
+        ),
+        _ => {
+            return Err(EvalError::new(
+                "substring expects a string, a start and an optional end",
+            ))
+        }
+    };
+
+    s.substring(start, end).map(Object::String).ok_or_else(|| {
+        EvalError::new(format!(
+            "substring range out of bounds: {} to {}",
+            start, end
+        ))
+    })
+}
+
+fn string_append(args: &[Object]) -> Result<Object, EvalError> {
+    let mut out = String::new();
+    let mut tainted = false;
+    for arg in args {
+        let s = string_arg("string-append", arg)?;
+        out.push_str(s);
+        tainted |= s.is_tainted();
+    }
+
+    Ok(Object::String(Str::from(out).with_taint(tainted)))
+}
+
+/// A copy that does not keep the buffer of a longer string alive.
+fn string_copy(args: &[Object]) -> Result<Object, EvalError> {
+    check_arity("string-copy", args, 1)?;
+
+    Ok(Object::String(
+        string_arg("string-copy", &args[0])?.compact(),
+    ))
+}
+
+fn string_to_list(args: &[Object]) -> Result<Object, EvalError> {
+    check_arity("string->list", args, 1)?;
+
+    match &args[0] {
+        Object::String(s) => {
+            taint::take_apart(s);
+            Ok(Object::list(
+                s.chars().map(Object::Char).collect::<Vec<_>>(),
+            ))
+        }
+        other => Err(EvalError::new(format!(
+            "string->list expects a string, got {}",
+            other.type_name()
+        ))),
+    }
+}
+
+fn list_to_string(args: &[Object]) -> Result<Object, EvalError> {
+    check_arity("list->string", args, 1)?;
+
+    let items = args[0]
+        .to_vec()
+        .ok_or_else(|| EvalError::new("list->string expects a list"))?;
+
+    items
+        .iter()
+        .map(|item| match item {
+            Object::Char(c) => Ok(*c),
+            other => Err(EvalError::new(format!(
+                "list->string expects a list of chars, got {}",
+                other.type_name()
+            ))),
+        })
+        .collect::<Result<String, _>>()
+        .map(|s| taint::string(s, taint::characters_tainted()))
+}
+
+fn command_line(args: &[Object]) -> Result<Object, EvalError> {
+    check_arity("command-line", args, 0)?;
+
+    Ok(taint::mark(Object::list(
+        std::env::args()
+            .skip(1)
+            .map(Object::string)
+            .collect::<Vec<_>>(),
+    )))
+}
+
+fn exit(args: &[Object]) -> Result<Object, EvalError> {
+    let code = match args {
+        [] | [Object::Bool(true)] => 0,
+        [Object::Bool(false)] => 1,
+        [Object::Integer(code)] => *code as i32,
+        [other] => {
+            return Err(EvalError::new(format!(
+                "exit expects an integer or boolean, got {}",
+                other
+            )))
+        }
+        _ => return Err(EvalError::new("exit expects at most one argument")),
+    };
+
+    Err(EvalError::exit(code))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    #[test]
+    fn test_copy() {
+        let deep = (0..100_000).fold(Object::Integer(1), |inner, _| {
+            Object::cons(inner, Object::Nil)
+        });
+        let copied = copy(std::slice::from_ref(&deep)).unwrap();
+        assert_eq!(copied, deep);
+        let (Object::Pair(a), Object::Pair(b)) = (&copied, &deep) else {
+            panic!("expected pairs");
+        };
+        assert!(!Rc::ptr_eq(a, b));
+
+        let long = Object::list((0..1_000_000).map(Object::Integer));
+        assert_eq!(copy(std::slice::from_ref(&long)).unwrap(), long);
+
+        let deque = Rc::new(RefCell::new(VecDeque::from([long.clone()])));
+        let Object::Deque(copied) = copy(&[Object::Deque(deque.clone())]).unwrap() else {
+            panic!("expected a deque");
+        };
+        deque.borrow_mut().clear();
+        assert_eq!(copied.borrow().front(), Some(&long));
+
+        let env = global_env();
+        assert_eq!(
+            crate::eval::eval_str(
+                "(define l (list 1 2))
+                 (set-cdr! (cdr l) l)
+                 (define c (copy l))
+                 (set-car! l 'a)
+                 (list c (eq? c (cddr c)) (eq? c l))",
+                &env
+            )
+            .unwrap()
+            .to_string(),
+            "((1 2 . ...) #t #f)"
+        );
+
+        assert_eq!(
+            equal(&[Object::Integer(1), Object::Float(1.0)]).unwrap(),
+            Object::Bool(false)
+        );
+    }
+
+    #[test]
+    fn test_hash_agrees_with_equal() {
+        let env = global_env();
+        let hash = |program| crate::eval::eval_str(program, &env).unwrap();
+
+        assert_eq!(
+            hash("(hash (list 1 \"a\" 'b))"),
+            hash("(hash '(1 \"a\" b))")
+        );
+        assert_eq!(hash("(hash 0.0)"), hash("(hash -0.0)"));
+        assert_eq!(hash("(hash car)"), hash("(hash car)"));
+        assert_ne!(hash("(hash '(1 2))"), hash("(hash '(2 1))"));
+
+        let deep = (0..100_000).fold(Object::Nil, |inner, _| Object::cons(inner, Object::Nil));
+        assert!(super::hash(&[deep]).is_ok());
+    }
+
+    #[test]
+    fn test_mixed_arithmetic() {
+        assert_eq!(
+            add(&[Object::Integer(1), Object::Integer(2)]).unwrap(),
+            Object::Integer(3)
+        );
+        assert_eq!(
+            mul(&[Object::Integer(2), Object::Float(1.5)]).unwrap(),
+            Object::Float(3.0)
+        );
+        assert!(div(&[Object::Integer(1), Object::Integer(0)]).is_err());
+        assert_eq!(
+            div(&[Object::Integer(6), Object::Integer(3)]).unwrap(),
+            Object::Integer(2)
+        );
+        assert_eq!(
+            div(&[Object::Integer(1), Object::Integer(3)])
+                .unwrap()
+                .to_string(),
+            "1/3"
+        );
+        assert!(add(&[Object::Integer(1), Object::Bool(true)]).is_err());
+    }
+
+    #[test]
+    fn test_variadic_arithmetic() {
+        assert_eq!(add(&[]).unwrap(), Object::Integer(0));
+        assert_eq!(mul(&[]).unwrap(), Object::Integer(1));
+        assert_eq!(add(&[Object::Integer(7)]).unwrap(), Object::Integer(7));
+        assert_eq!(
+            mul(&[Object::Integer(2), Object::Integer(3), Object::Integer(4)]).unwrap(),
+            Object::Integer(24)
+        );
+        assert!(add(&[Object::symbol("x")]).is_err());
+    }
+
+    #[test]
+    fn test_overflow_promotes_to_bigint() {
+        let big = mul(&[Object::Integer(i64::MAX), Object::Integer(2)]).unwrap();
+        assert_eq!(big.to_string(), "18446744073709551614");
+
+        let back = sub(&[big.clone(), Object::Integer(i64::MAX)]).unwrap();
+        assert_eq!(back, Object::Integer(i64::MAX));
+
+        assert_eq!(
+            expt(&[Object::Integer(2), Object::Integer(64)])
+                .unwrap()
+                .to_string(),
+            "18446744073709551616"
+        );
+        assert_eq!(lt(&[Object::Integer(1), big]).unwrap(), Object::Bool(true));
+
+        let env = global_env();
+        assert_eq!(
+            crate::eval::eval_str("(/ (- (- 0 9223372036854775807) 1) -1)", &env)
+                .unwrap()
+                .to_string(),
+            "9223372036854775808"
+        );
+    }
+
+    #[test]
+    fn test_big_powers_count_as_steps() {
+        let limited = limits::Limits {
+            steps: Some(1_000_000),
+            ..limits::Limits::default()
+        };
+        let power = |exp: i64| {
+            limits::enforce(limited, || {
+                expt(&[Object::Integer(10), Object::Integer(exp)])
+            })
+        };
+
+        assert_eq!(power(1000).unwrap().to_string().len(), 1001);
+        let err = power(1_000_000_000).unwrap_err();
+        assert_eq!(err.limit_exceeded(), Some(limits::Limit::Steps));
+        assert!(expt(&[Object::Integer(1), Object::Integer(4_000_000_000)]).is_ok());
+    }
+
+    #[test]
+    fn test_exactness_conversions() {
+        let quarter = div(&[Object::Integer(1), Object::Integer(4)]).unwrap();
+        assert_eq!(
+            exact_to_inexact(std::slice::from_ref(&quarter)).unwrap(),
+            Object::Float(0.25)
+        );
+        assert_eq!(inexact_to_exact(&[Object::Float(0.25)]).unwrap(), quarter);
+        assert_eq!(
+            inexact_to_exact(&[Object::Float(2.0)]).unwrap(),
+            Object::Integer(2)
+        );
+        assert!(inexact_to_exact(&[Object::Float(f64::INFINITY)]).is_err());
+        assert_eq!(
+            expt(&[Object::Integer(2), Object::Integer(-2)]).unwrap(),
+            quarter
+        );
+    }
+
+    #[test]
+    fn test_char_builtins() {
+        assert_eq!(
+            char_to_integer(&[Object::Char('A')]).unwrap(),
+            Object::Integer(65)
+        );
+        assert_eq!(
+            integer_to_char(&[Object::Integer(955)]).unwrap(),
+            Object::Char('λ')
+        );
+        assert!(integer_to_char(&[Object::Integer(0xD800)]).is_err());
+        assert_eq!(
+            char_upcase(&[Object::Char('ß')]).unwrap(),
+            Object::Char('ß')
+        );
+
+        let chars = string_to_list(&[Object::string("héllo")]).unwrap();
+        assert_eq!(
+            string_ref(&[Object::string("héllo"), Object::Integer(1)]).unwrap(),
+            Object::Char('é')
+        );
+        assert_eq!(list_to_string(&[chars]).unwrap(), Object::string("héllo"));
+    }
+
+    #[test]
+    fn test_string_procedures() {
+        let env = global_env();
+        let eval = |program| crate::eval::eval_str(program, &env).unwrap().to_string();
+
+        assert_eq!(eval("(string-length \"héllo\")"), "5");
+        assert_eq!(eval("(substring \"héllo world\" 1 5)"), "\"éllo\"");
+        assert_eq!(
+            eval("(substring (substring \"hello world\" 6) 1)"),
+            "\"orld\""
+        );
+        assert_eq!(
+            eval("(string-append \"a\" (string-copy \"b\") \"c\")"),
+            "\"abc\""
+        );
+        assert!(crate::eval::eval_str("(substring \"abc\" 2 4)", &env).is_err());
+        assert!(crate::eval::eval_str("(substring \"abc\" -1)", &env).is_err());
+    }
+
+    #[test]
+    fn test_equivalence() {
+        let env = global_env();
+        let eval = |program| crate::eval::eval_str(program, &env).unwrap().to_string();
+
+        assert_eq!(
+            eval("(let ((l '(1 2))) (list (eq? l l) (eq? l '(1 2)) (eqv? l '(1 2)) (equal? l '(1 2))))"),
+            "(#t #f #f #t)"
+        );
+        assert_eq!(
+            eval("(let ((s \"ab\")) (list (eq? s s) (eqv? s \"ab\") (equal? s \"ab\")))"),
+            "(#t #f #t)"
+        );
+        assert_eq!(
+            eval("(list (eq? 'a 'a) (eqv? 2 2) (eqv? 2 2.0) (eqv? 0.0 -0.0) (eqv? 1/2 1/2) (eq? #\\a #\\a))"),
+            "(#t #t #f #f #t #t)"
+        );
+        let big = "100000000000000000000";
+        assert_eq!(
+            eval(&format!(
+                "(let ((n {big})) (list (eq? n n) (eq? n {big}) (eqv? n {big})))"
+            )),
+            "(#t #f #t)"
+        );
+    }
+
+    #[test]
+    fn test_pair_mutation() {
+        let env = global_env();
+        let eval = |program| crate::eval::eval_str(program, &env).unwrap().to_string();
+
+        assert_eq!(
+            eval(
+                "(define l (list 1 2 3))
+                 (define shared (cdr l))
+                 (set-car! l 'a)
+                 (list-set! l 2 'c)
+                 (set-cdr! shared '(b))
+                 (define a (list 1 2))
+                 (list l (append! a '() (list 3) 4) a (reverse! (list 1 2 3)) (append!) (append! '() 5))"
+            ),
+            "((a 2 b) (1 2 3 . 4) (1 2 3 . 4) (3 2 1) () 5)"
+        );
+        assert_eq!(
+            eval(
+                "(define c (list 1 2 3))
+                 (set-cdr! (cddr c) c)
+                 (define d (list 1 2))
+                 (set-car! d d)
+                 (list c d (list? c) (equal? c c) (length (list c c)))"
+            ),
+            "((1 2 3 . ...) (... 2) #f #t 2)"
+        );
+
+        let error = |program| {
+            crate::eval::eval_str(program, &env)
+                .unwrap_err()
+                .message()
+                .to_string()
+        };
+        assert_eq!(error("(set-car! '() 1)"), "set-car! expects a pair, got ()");
+        assert_eq!(
+            error("(list-set! (list 1) 1 'a)"),
+            "list-set! index out of range: 1"
+        );
+        assert_eq!(
+            error("(reverse! '(1 . 2))"),
+            "reverse! expects a list, got (1 . 2)"
+        );
+        assert_eq!(
+            error("(append! c '(1))"),
+            "append! expects a list, got (1 2 3 . ...)"
+        );
+    }
+
+    #[test]
+    fn test_type_predicates() {
+        let env = global_env();
+        let eval = |program| crate::eval::eval_str(program, &env).unwrap().to_string();
+
+        assert_eq!(
+            eval("(list (number? 1) (number? 1/2) (number? 1.5) (number? \"1\") (string? \"1\") (symbol? 'a) (symbol? \"a\"))"),
+            "(#t #t #t #f #t #t #f)"
+        );
+        assert_eq!(
+            eval("(list (pair? '(1)) (pair? '()) (null? '()) (null? '(1)) (list? '()) (list? '(1 2)) (list? '(1 . 2)))"),
+            "(#t #f #t #f #t #t #f)"
+        );
+        assert_eq!(
+            eval("(list (procedure? car) (procedure? (lambda (x) x)) (procedure? 'car))"),
+            "(#t #t #f)"
+        );
+    }
+
+    #[test]
+    fn test_exit_codes() {
+        assert_eq!(exit(&[]).unwrap_err().exit_code(), Some(0));
+        assert_eq!(
+            exit(&[Object::Integer(3)]).unwrap_err().exit_code(),
+            Some(3)
+        );
+        assert_eq!(
+            exit(&[Object::Bool(false)]).unwrap_err().exit_code(),
+            Some(1)
+        );
+        assert_eq!(exit(&[Object::symbol("x")]).unwrap_err().exit_code(), None);
+    }
+}
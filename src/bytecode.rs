@@ -0,0 +1,843 @@
+//! A binary format for compiled programs, so that scripts can be run
+//! without lexing, parsing and compiling them again.
+//!
+//! A file starts with `MAGIC` and a format version, followed by the
+//! program's top-level forms: compiled chunks, or the forms themselves for
+//! those the compiler leaves to the tree-walking evaluator. Integers are
+//! LEB128 varints, except for `Integer` and `Float` values, which take eight
+//! little-endian bytes.
+//!
+//! A chunk used by several others, such as that of a procedure whose calls
+//! are compiled in place, is written once and referred to by its position
+//! among the chunks written before it, so that it is one chunk again when
+//! the file is loaded.
+//!
+//! A file may be damaged, or made to crash whatever runs it, so `decode`
+//! verifies the code before handing it to the VM: every operand and every
+//! variable a closure captures refers to something that exists, every path
+//! through a chunk keeps the stack as the instruction needs it, and the
+//! same way wherever paths meet, and a chunk's arity fits its slots.
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+use crate::bigint::BigInt;
+use crate::compiler::{Arith, Capture, Chunk, Literals, Op, TopLevel};
+use crate::object::Object;
+use crate::parser::ParseError;
+use crate::rational::Rational;
+use crate::symbol::Symbol;
+
+pub const MAGIC: &[u8] = b"\0lbc";
+/// Bumped whenever the format or the instruction set changes, since files
+/// written by another version cannot be run.
+const VERSION: u8 = 4;
+
+/// Whether `bytes` look like a compiled program rather than source code.
+pub fn is_compiled(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+pub fn encode(program: &[TopLevel]) -> Vec<u8> {
+    let mut out = Writer {
+        bytes: MAGIC.to_vec(),
+        chunks: HashMap::new(),
+    };
+    out.bytes.push(VERSION);
+
+    out.varint(program.len());
+    for form in program {
+        match form {
+            TopLevel::Compiled(chunk) => {
+                out.bytes.push(0);
+                out.chunk(chunk);
+            }
+            TopLevel::Interpreted(form) => {
+                out.bytes.push(1);
+                out.object(form);
+            }
+        }
+    }
+
+    out.bytes
+}
+
+pub fn decode(bytes: &[u8]) -> Result<Vec<TopLevel>, ParseError> {
+    if !is_compiled(bytes) {
+        return Err(ParseError::new("not a compiled program"));
+    }
+    let mut reader = Reader {
+        bytes,
+        pos: MAGIC.len(),
+        literals: Literals::default(),
+        chunks: Vec::new(),
+    };
+    match reader.byte()? {
+        VERSION => {}
+        version => {
+            return Err(ParseError::new(format!(
+                "compiled program has format version {}, expected {}",
+                version, VERSION
+            )))
+        }
+    }
+
+    let len = reader.varint()?;
+    let mut program = Vec::new();
+    for _ in 0..len {
+        program.push(match reader.byte()? {
+            0 => TopLevel::Compiled(Rc::new(reader.chunk()?)),
+            1 => TopLevel::Interpreted(reader.object()?),
+            tag => return Err(invalid("top-level form", tag)),
+        });
+    }
+
+    if reader.pos != bytes.len() {
+        return Err(ParseError::new("trailing bytes after compiled program"));
+    }
+
+    let mut verified = HashSet::new();
+    for form in &program {
+        if let TopLevel::Compiled(chunk) = form {
+            if !(chunk.free.is_empty() && chunk.free_cells.is_empty()) {
+                return Err(ParseError::new(
+                    "top-level form capturing variables in compiled program",
+                ));
+            }
+            verify(chunk, &mut verified)?;
+        }
+    }
+
+    Ok(program)
+}
+
+/// Whether `obj` is plain data, which `encode_value` can write: a boolean,
+/// number, character, string or symbol, or a list of them that does not
+/// contain itself.
+pub(crate) fn is_data(obj: &Object) -> bool {
+    !obj.contains_itself() && is_acyclic_data(obj)
+}
+
+fn is_acyclic_data(obj: &Object) -> bool {
+    let mut obj = obj.clone();
+    loop {
+        match obj {
+            Object::Pair(pair) if is_acyclic_data(&pair.car()) => obj = pair.cdr(),
+            Object::Nil
+            | Object::Bool(_)
+            | Object::Integer(_)
+            | Object::BigInt(_)
+            | Object::Rational(_)
+            | Object::Float(_)
+            | Object::Char(_)
+            | Object::String(_)
+            | Object::Symbol(_) => return true,
+            _ => return false,
+        }
+    }
+}
+
+/// Appends `obj`, which must be plain data, to `out` as in compiled
+/// programs.
+pub(crate) fn encode_value(out: &mut Vec<u8>, obj: &Object) {
+    let mut writer = Writer {
+        bytes: std::mem::take(out),
+        chunks: HashMap::new(),
+    };
+    writer.object(obj);
+    *out = writer.bytes;
+}
+
+/// Reads a value written by `encode_value` at the start of `bytes`, with
+/// the number of bytes it took.
+pub(crate) fn decode_value(bytes: &[u8]) -> Result<(Object, usize), ParseError> {
+    let mut reader = Reader {
+        bytes,
+        pos: 0,
+        literals: Literals::default(),
+        chunks: Vec::new(),
+    };
+    let value = reader.object()?;
+
+    Ok((value, reader.pos))
+}
+
+fn invalid(what: &str, tag: u8) -> ParseError {
+    ParseError::new(format!("invalid {} tag {} in compiled program", what, tag))
+}
+
+struct Writer {
+    bytes: Vec<u8>,
+    /// The position of each chunk written so far among them, so that a
+    /// chunk used by several others is written once.
+    chunks: HashMap<*const Chunk, usize>,
+}
+
+impl Writer {
+    fn varint(&mut self, mut n: usize) {
+        while n >= 0x80 {
+            self.bytes.push(n as u8 | 0x80);
+            n >>= 7;
+        }
+        self.bytes.push(n as u8);
+    }
+
+    fn string(&mut self, s: &str) {
+        self.varint(s.len());
+        self.bytes.extend_from_slice(s.as_bytes());
+    }
+
+    fn object(&mut self, obj: &Object) {
+        match obj {
+            Object::Void => self.bytes.push(0),
+            Object::Nil => self.bytes.push(1),
+            Object::Bool(false) => self.bytes.push(2),
+            Object::Bool(true) => self.bytes.push(3),
+            Object::Integer(n) => {
+                self.bytes.push(4);
+                self.bytes.extend_from_slice(&n.to_le_bytes());
+            }
+            Object::BigInt(n) => {
+                self.bytes.push(5);
+                self.string(&n.to_string());
+            }
+            Object::Rational(n) => {
+                self.bytes.push(6);
+                self.string(&n.numerator().to_string());
+                self.string(&n.denominator().to_string());
+            }
+            Object::Float(n) => {
+                self.bytes.push(7);
+                self.bytes.extend_from_slice(&n.to_le_bytes());
+            }
+            Object::Char(c) => {
+                self.bytes.push(8);
+                self.varint(*c as usize);
+            }
+            Object::String(s) => {
+                self.bytes.push(9);
+                self.string(s);
+            }
+            Object::Symbol(name) => {
+                self.bytes.push(10);
+                self.string(name);
+            }
+            // A list is its length, its elements and what ends it.
+            Object::Pair(_) => {
+                let mut items = Vec::new();
+                let mut tail = obj.clone();
+                while let Object::Pair(pair) = tail {
+                    items.push(pair.car());
+                    tail = pair.cdr();
+                }
+
+                self.bytes.push(11);
+                self.varint(items.len());
+                for item in &items {
+                    self.object(item);
+                }
+                self.object(&tail);
+            }
+            other => unreachable!("source code cannot contain a {}", other.type_name()),
+        }
+    }
+
+    fn chunk(&mut self, chunk: &Chunk) {
+        self.varint(chunk.params);
+        self.bytes.push(chunk.rest as u8);
+        self.varint(chunk.slots);
+        self.varint(chunk.cells);
+        for captures in [&chunk.free, &chunk.free_cells] {
+            self.varint(captures.len());
+            for capture in captures {
+                let (tag, i) = match *capture {
+                    Capture::Local(i) => (0, i),
+                    Capture::Free(i) => (1, i),
+                };
+                self.bytes.push(tag);
+                self.varint(i);
+            }
+        }
+
+        self.varint(chunk.code.len());
+        for op in &chunk.code {
+            self.op(*op);
+        }
+        self.varint(chunk.constants.len());
+        for constant in &chunk.constants {
+            self.object(constant);
+        }
+        self.varint(chunk.symbols.len());
+        for symbol in &chunk.symbols {
+            self.string(symbol);
+        }
+        self.varint(chunk.chunks.len());
+        for inner in &chunk.chunks {
+            match self.chunks.get(&Rc::as_ptr(inner)) {
+                Some(&written) => {
+                    self.bytes.push(1);
+                    self.varint(written);
+                }
+                None => {
+                    self.bytes.push(0);
+                    self.chunk(inner);
+                    self.chunks.insert(Rc::as_ptr(inner), self.chunks.len());
+                }
+            }
+        }
+    }
+
+    fn op(&mut self, op: Op) {
+        let (tag, operands): (u8, &[usize]) = match op {
+            Op::Const(i) => (0, &[i]),
+            Op::Slot(i) => (1, &[i]),
+            Op::SetSlot(i) => (2, &[i]),
+            Op::Cell(i) => (3, &[i]),
+            Op::SetCell(i) => (4, &[i]),
+            Op::Free(i) => (5, &[i]),
+            Op::FreeCell(i) => (6, &[i]),
+            Op::SetFreeCell(i) => (7, &[i]),
+            Op::Global(i) => (8, &[i]),
+            Op::SetGlobal(i) => (9, &[i]),
+            Op::DefineGlobal(i) => (10, &[i]),
+            Op::Pop => (11, &[]),
+            Op::Jump(target) => (12, &[target]),
+            Op::JumpIfFalse(target) => (13, &[target]),
+            Op::Closure(i) => (14, &[i]),
+            Op::Call(argc) => (15, &[argc]),
+            Op::TailCall(argc) => (16, &[argc]),
+            Op::Return => (17, &[]),
+            Op::Arith { op, symbol, tail } => {
+                let op = Arith::ALL.iter().position(|&arith| arith == op);
+                (
+                    18,
+                    &[op.expect("every Arith is listed"), symbol, tail as usize],
+                )
+            }
+            Op::Inline {
+                symbol,
+                chunk,
+                argc,
+                fallback,
+            } => (19, &[symbol, chunk, argc, fallback]),
+        };
+
+        self.bytes.push(tag);
+        for &operand in operands {
+            self.varint(operand);
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    /// The strings read so far, so that a program's copies of a text are
+    /// one string again.
+    literals: Literals,
+    /// The chunks read so far inside others, which later ones may use.
+    chunks: Vec<Rc<Chunk>>,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, len: usize) -> Result<&[u8], ParseError> {
+        let bytes = self
+            .bytes
+            .get(self.pos..self.pos.saturating_add(len))
+            .ok_or_else(|| ParseError::new("truncated compiled program"))?;
+        self.pos += len;
+
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, ParseError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn eight_bytes(&mut self) -> Result<[u8; 8], ParseError> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(bytes)
+    }
+
+    fn varint(&mut self) -> Result<usize, ParseError> {
+        let mut n = 0usize;
+        for shift in (0..usize::BITS).step_by(7) {
+            let byte = self.byte()?;
+            n |= ((byte & 0x7f) as usize) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(n);
+            }
+        }
+
+        Err(ParseError::new("invalid number in compiled program"))
+    }
+
+    fn bool(&mut self) -> Result<bool, ParseError> {
+        match self.byte()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            tag => Err(invalid("boolean", tag)),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, ParseError> {
+        let len = self.varint()?;
+        String::from_utf8(self.take(len)?.to_vec())
+            .map_err(|_| ParseError::new("invalid UTF-8 in compiled program"))
+    }
+
+    fn symbol(&mut self) -> Result<Symbol, ParseError> {
+        Symbol::try_intern(&self.string()?)
+            .ok_or_else(|| ParseError::new("too many distinct symbols in compiled program"))
+    }
+
+    fn bigint(&mut self) -> Result<BigInt, ParseError> {
+        BigInt::parse(&self.string()?)
+            .ok_or_else(|| ParseError::new("invalid integer in compiled program"))
+    }
+
+    fn object(&mut self) -> Result<Object, ParseError> {
+        Ok(match self.byte()? {
+            0 => Object::Void,
+            1 => Object::Nil,
+            2 => Object::Bool(false),
+            3 => Object::Bool(true),
+            4 => Object::Integer(i64::from_le_bytes(self.eight_bytes()?)),
+            5 => Object::from_bigint(self.bigint()?),
+            6 => {
+                let (numerator, denominator) = (self.bigint()?, self.bigint()?);
+                Rational::new(numerator, denominator)
+                    .map(Object::from_rational)
+                    .ok_or_else(|| ParseError::new("invalid rational in compiled program"))?
+            }
+            7 => Object::Float(f64::from_le_bytes(self.eight_bytes()?)),
+            8 => u32::try_from(self.varint()?)
+                .ok()
+                .and_then(char::from_u32)
+                .map(Object::Char)
+                .ok_or_else(|| ParseError::new("invalid character in compiled program"))?,
+            9 => {
+                let s = Object::string(self.string()?);
+                self.literals.share(s)
+            }
+            10 => Object::Symbol(self.symbol()?),
+            11 => {
+                let items = self.items(Self::object)?;
+                let tail = self.object()?;
+                items
+                    .into_iter()
+                    .rev()
+                    .fold(tail, |tail, item| Object::cons(item, tail))
+            }
+            tag => return Err(invalid("object", tag)),
+        })
+    }
+
+    /// Reads `len` and then that many items.
+    fn items<T>(
+        &mut self,
+        mut item: impl FnMut(&mut Self) -> Result<T, ParseError>,
+    ) -> Result<Vec<T>, ParseError> {
+        let len = self.varint()?;
+        let mut items = Vec::new();
+        for _ in 0..len {
+            items.push(item(self)?);
+        }
+
+        Ok(items)
+    }
+
+    fn chunk(&mut self) -> Result<Chunk, ParseError> {
+        let params = self.varint()?;
+        let rest = self.bool()?;
+        let slots = self.varint()?;
+        let cells = self.varint()?;
+        let free = self.items(Self::capture)?;
+        let free_cells = self.items(Self::capture)?;
+        let code = self.items(Self::op)?;
+        let constants = self.items(Self::object)?;
+        let symbols = self.items(Self::symbol)?;
+        let chunks = self.items(Self::inner_chunk)?;
+
+        let chunk = Chunk {
+            params,
+            rest,
+            slots,
+            cells,
+            free,
+            free_cells,
+            code,
+            cache: RefCell::new(vec![None; symbols.len()]),
+            constants,
+            symbols,
+            chunks,
+        };
+        validate(&chunk)?;
+
+        Ok(chunk)
+    }
+
+    fn inner_chunk(&mut self) -> Result<Rc<Chunk>, ParseError> {
+        match self.byte()? {
+            0 => {
+                let chunk = Rc::new(self.chunk()?);
+                self.chunks.push(chunk.clone());
+                Ok(chunk)
+            }
+            1 => {
+                let written = self.varint()?;
+                self.chunks
+                    .get(written)
+                    .cloned()
+                    .ok_or_else(|| ParseError::new("invalid chunk in compiled program"))
+            }
+            tag => Err(invalid("chunk", tag)),
+        }
+    }
+
+    fn capture(&mut self) -> Result<Capture, ParseError> {
+        match self.byte()? {
+            0 => Ok(Capture::Local(self.varint()?)),
+            1 => Ok(Capture::Free(self.varint()?)),
+            tag => Err(invalid("captured variable", tag)),
+        }
+    }
+
+    fn op(&mut self) -> Result<Op, ParseError> {
+        Ok(match self.byte()? {
+            0 => Op::Const(self.varint()?),
+            1 => Op::Slot(self.varint()?),
+            2 => Op::SetSlot(self.varint()?),
+            3 => Op::Cell(self.varint()?),
+            4 => Op::SetCell(self.varint()?),
+            5 => Op::Free(self.varint()?),
+            6 => Op::FreeCell(self.varint()?),
+            7 => Op::SetFreeCell(self.varint()?),
+            8 => Op::Global(self.varint()?),
+            9 => Op::SetGlobal(self.varint()?),
+            10 => Op::DefineGlobal(self.varint()?),
+            11 => Op::Pop,
+            12 => Op::Jump(self.varint()?),
+            13 => Op::JumpIfFalse(self.varint()?),
+            14 => Op::Closure(self.varint()?),
+            15 => Op::Call(self.varint()?),
+            16 => Op::TailCall(self.varint()?),
+            17 => Op::Return,
+            18 => Op::Arith {
+                op: *Arith::ALL
+                    .get(self.varint()?)
+                    .ok_or_else(|| ParseError::new("invalid arithmetic in compiled program"))?,
+                symbol: self.varint()?,
+                tail: self.bool()?,
+            },
+            19 => Op::Inline {
+                symbol: self.varint()?,
+                chunk: self.varint()?,
+                argc: self.varint()?,
+                fallback: self.varint()?,
+            },
+            tag => return Err(invalid("instruction", tag)),
+        })
+    }
+}
+
+/// Checks that the chunk's operands refer to entries that exist, as do the
+/// variables the closures it makes capture, and that its arguments fit in
+/// its slots, so that a damaged file is rejected when it is loaded rather
+/// than crashing the VM.
+fn validate(chunk: &Chunk) -> Result<(), ParseError> {
+    let in_range = |op: &Op| match *op {
+        Op::Const(i) => i < chunk.constants.len(),
+        Op::Global(i) | Op::SetGlobal(i) | Op::DefineGlobal(i) => i < chunk.symbols.len(),
+        Op::Arith { symbol, .. } => symbol < chunk.symbols.len(),
+        Op::Closure(i) => i < chunk.chunks.len(),
+        Op::Inline {
+            symbol,
+            chunk: i,
+            fallback,
+            ..
+        } => symbol < chunk.symbols.len() && i < chunk.chunks.len() && fallback < chunk.code.len(),
+        Op::Jump(target) | Op::JumpIfFalse(target) => target < chunk.code.len(),
+        Op::Slot(i) | Op::SetSlot(i) => i < chunk.slots,
+        Op::Cell(i) | Op::SetCell(i) => i < chunk.cells,
+        Op::Free(i) => i < chunk.free.len(),
+        Op::FreeCell(i) | Op::SetFreeCell(i) => i < chunk.free_cells.len(),
+        _ => true,
+    };
+
+    let returns = matches!(chunk.code.last(), Some(Op::Return | Op::TailCall(_)));
+    if !(chunk.code.iter().all(in_range) && returns) {
+        return Err(ParseError::new("invalid instruction in compiled program"));
+    }
+    let exist = |captures: &[Capture], locals: usize, free: usize| {
+        captures.iter().all(|capture| match *capture {
+            Capture::Local(i) => i < locals,
+            Capture::Free(i) => i < free,
+        })
+    };
+    for inner in &chunk.chunks {
+        if !(exist(&inner.free, chunk.slots, chunk.free.len())
+            && exist(&inner.free_cells, chunk.cells, chunk.free_cells.len()))
+        {
+            return Err(ParseError::new(
+                "closure capturing a variable that does not exist in compiled program",
+            ));
+        }
+    }
+    if chunk.params + chunk.rest as usize > chunk.slots {
+        return Err(ParseError::new(
+            "procedure with more parameters than slots in compiled program",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Follows every path through `chunk`, checking that each instruction
+/// finds the values it uses on the stack. The chunks it makes closures of
+/// are verified once each.
+fn verify(chunk: &Chunk, verified: &mut HashSet<*const Chunk>) -> Result<(), ParseError> {
+    if !verified.insert(chunk) {
+        return Ok(());
+    }
+
+    // The number of values on the stack above the chunk's slots before
+    // each instruction.
+    let mut heights: Vec<Option<usize>> = vec![None; chunk.code.len()];
+    let mut pending = vec![(0, 0)];
+    while let Some((ip, mut height)) = pending.pop() {
+        let error = |problem: &str| {
+            ParseError::new(format!(
+                "{} at instruction {} in compiled program",
+                problem, ip
+            ))
+        };
+        match heights[ip] {
+            Some(seen) if seen == height => continue,
+            Some(_) => return Err(error("paths meeting with different stacks")),
+            None => heights[ip] = Some(height),
+        }
+
+        let pops = |height: &mut usize, n: usize| match height.checked_sub(n) {
+            Some(rest) => {
+                *height = rest;
+                Ok(())
+            }
+            None => Err(error("stack underflow")),
+        };
+
+        let mut next = Some(ip + 1);
+        match chunk.code[ip] {
+            Op::Const(_)
+            | Op::Global(_)
+            | Op::Slot(_)
+            | Op::Cell(_)
+            | Op::Free(_)
+            | Op::FreeCell(_) => height += 1,
+            Op::SetSlot(_)
+            | Op::SetCell(_)
+            | Op::SetFreeCell(_)
+            | Op::SetGlobal(_)
+            | Op::DefineGlobal(_)
+            | Op::Pop => pops(&mut height, 1)?,
+            Op::Jump(target) => next = Some(target),
+            Op::JumpIfFalse(target) => {
+                pops(&mut height, 1)?;
+                pending.push((target, height));
+            }
+            Op::Closure(i) => {
+                verify(&chunk.chunks[i], verified)?;
+                height += 1;
+            }
+            Op::Call(argc) => {
+                pops(&mut height, argc + 1)?;
+                height += 1;
+            }
+            // The call at `fallback` finds the procedure below the
+            // arguments.
+            Op::Inline { argc, fallback, .. } => {
+                if height < argc {
+                    return Err(error("stack underflow"));
+                }
+                pending.push((fallback, height + 1));
+            }
+            // When the guard fails, a tail call ends the chunk here.
+            Op::Arith { .. } => {
+                pops(&mut height, 2)?;
+                height += 1;
+            }
+            Op::TailCall(argc) => {
+                pops(&mut height, argc + 1)?;
+                next = None;
+            }
+            Op::Return => {
+                pops(&mut height, 1)?;
+                next = None;
+            }
+        }
+
+        match next {
+            Some(next) if next < chunk.code.len() => pending.push((next, height)),
+            Some(_) => return Err(error("running past the end")),
+            None => {}
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builtins::global_env;
+    use crate::compiler::compile_program;
+    use crate::parser::parse;
+    use crate::vm;
+
+    fn round_trip(source: &str) -> Vec<TopLevel> {
+        let program = compile_program(parse(source).unwrap());
+        decode(&encode(&program)).unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let program = round_trip(
+            "(define (f x . rest) (let ((y 1/3)) (list x y rest \"s\" #\\a 2.5 '(a . b))))
+             (define big 123456789012345678901234567890)
+             (define-memoized (g x) x)
+             (f (g -1) big)",
+        );
+        assert!(matches!(program[2], TopLevel::Interpreted(_)));
+
+        let result = vm::run_program(&program, &global_env()).unwrap();
+        assert_eq!(
+            result.to_string(),
+            "(-1 1/3 (123456789012345678901234567890) \"s\" #\\a 2.5 (a . b))"
+        );
+
+        // The chunk of a procedure compiled in place of a call is still the
+        // one its closures are made of, which the call checks for.
+        let program = round_trip("(define (sq x) (* x x)) (sq 3)");
+        let [TopLevel::Compiled(define), TopLevel::Compiled(call)] = program.as_slice() else {
+            panic!("the program was not compiled");
+        };
+        assert!(Rc::ptr_eq(&define.chunks[0], &call.chunks[0]));
+        assert_eq!(
+            vm::run_program(&program, &global_env()).unwrap(),
+            Object::Integer(9)
+        );
+    }
+
+    #[test]
+    fn test_is_data() {
+        let env = global_env();
+        let eval = |source| crate::eval::eval_str(source, &env).unwrap();
+
+        let shared = eval("(define shared (list 1)) (list shared shared)");
+        assert!(is_data(&shared));
+        assert!(!is_data(&eval("(list car)")));
+        assert!(!is_data(&eval(
+            "(define l (list 1 2)) (set-cdr! (cdr l) l) l"
+        )));
+        assert!(!is_data(&eval(
+            "(define m (list 1 2)) (set-car! (cdr m) m) m"
+        )));
+        assert_eq!(
+            crate::eval::eval_str("(par-map car (list m))", &env)
+                .unwrap_err()
+                .message(),
+            "par-map: cannot copy a pair to another thread"
+        );
+    }
+
+    #[test]
+    fn test_rejects_damaged_files() {
+        let bytes = encode(&compile_program(parse("(define x 1) x").unwrap()));
+
+        assert!(decode(b"(define x 1)").is_err());
+        assert!(decode(&bytes[..bytes.len() - 1]).is_err());
+
+        let mut version = bytes.clone();
+        version[MAGIC.len()] = VERSION + 1;
+        assert!(decode(&version).is_err());
+    }
+
+    /// The error decoding a program of one chunk with `code` gives.
+    fn rejection(code: Vec<Op>, chunks: Vec<Chunk>) -> String {
+        let chunk = Chunk {
+            code,
+            slots: 1,
+            constants: vec![Object::Integer(1)],
+            chunks: chunks.into_iter().map(Rc::new).collect(),
+            ..Chunk::default()
+        };
+        let bytes = encode(&[TopLevel::Compiled(Rc::new(chunk))]);
+        match decode(&bytes) {
+            Ok(_) => String::from("accepted"),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_verifies_code() {
+        use Op::*;
+
+        let procedure = |code, params, slots, free| Chunk {
+            code,
+            params,
+            slots,
+            free,
+            ..Chunk::default()
+        };
+        assert_eq!(rejection(vec![Const(0), Return], vec![]), "accepted");
+        assert!(rejection(vec![Pop, Const(0), Return], vec![])
+            .contains("stack underflow at instruction 0"));
+        assert!(rejection(vec![Const(0), Call(1), Return], vec![])
+            .contains("stack underflow at instruction 1"));
+        assert!(rejection(
+            vec![Const(0), JumpIfFalse(4), Const(0), Const(0), Return],
+            vec![]
+        )
+        .contains("paths meeting with different stacks at instruction 4"));
+        assert!(rejection(vec![Cell(0), Return], vec![]).contains("invalid instruction"));
+        assert_eq!(
+            rejection(
+                vec![Closure(0), Return],
+                vec![procedure(
+                    vec![Free(0), Return],
+                    0,
+                    0,
+                    vec![Capture::Local(0)]
+                )]
+            ),
+            "accepted"
+        );
+        assert!(rejection(
+            vec![Closure(0), Return],
+            vec![procedure(
+                vec![Free(1), Return],
+                0,
+                0,
+                vec![Capture::Local(0)]
+            )]
+        )
+        .contains("invalid instruction"));
+        assert!(rejection(
+            vec![Closure(0), Return],
+            vec![procedure(
+                vec![Free(0), Return],
+                0,
+                0,
+                vec![Capture::Free(0)]
+            )]
+        )
+        .contains("capturing a variable that does not exist"));
+        assert!(rejection(
+            vec![Closure(0), Return],
+            vec![procedure(vec![Slot(0), Return], 2, 1, vec![])]
+        )
+        .contains("more parameters than slots"));
+    }
+}
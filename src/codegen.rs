@@ -0,0 +1,627 @@
+//! Generates Rust types from data definitions written in Lisp, for programs
+//! that use Lisp as the schema language of data shared with a host.
+//!
+//! A schema is a file of `define-record` and `define-enum` forms:
+//!
+//! ```text
+//! (define-record point (x float) (y float))
+//! (define-enum shape
+//!   (circle (center point) (radius float))
+//!   (polygon (points (list point)))
+//!   empty)
+//! ```
+//!
+//! Field types are `integer`, `float`, `boolean`, `char`, `string`,
+//! `(list T)`, `(optional T)` and the names of the other definitions. Each
+//! definition becomes a Rust struct or enum, with `From` and `TryFrom`
+//! implementations converting it to and from a `Value`. In Lisp, a record
+//! is a list of its name and its fields in order, `(point 1.5 2.0)`, a
+//! variant with fields the same with the variant's name, and one without
+//! fields is just the name. A missing optional field is `#f`. Definitions
+//! may refer to themselves only through a list. Names that are Rust
+//! keywords are written as raw identifiers, or with a trailing underscore
+//! when they cannot be, such as `self`, and the characters an identifier
+//! cannot have, such as `?` and `!`, become underscores in field names.
+//!
+//! These schemas are unrelated to the records of `defstruct` and
+//! `define-record-type`: codegen does not read those forms, and the
+//! generated conversions only take and make the lists above, so a record
+//! made by `make-point` is not a `point` to them.
+
+use std::fmt::Write;
+
+use crate::eval::EvalError;
+use crate::object::Object;
+use crate::parser::parse;
+
+enum Type {
+    Integer,
+    Float,
+    Boolean,
+    Char,
+    String,
+    List(Box<Type>),
+    Optional(Box<Type>),
+    Named(String),
+}
+
+struct Field {
+    name: String,
+    ty: Type,
+}
+
+enum Definition {
+    Record(String, Vec<Field>),
+    Enum(String, Vec<(String, Vec<Field>)>),
+}
+
+impl Definition {
+    fn name(&self) -> &str {
+        match self {
+            Definition::Record(name, _) | Definition::Enum(name, _) => name,
+        }
+    }
+}
+
+/// The helpers the generated conversions call, each emitted when used.
+const TAGGED: &str = r#"
+/// The name a value starts with, and its fields.
+fn tagged(mut value: Value, type_name: &str) -> Result<(String, Vec<Value>), EvalError> {
+    let expected = || EvalError::new(format!("expected a {}", type_name));
+    match &mut value {
+        Value::Symbol(tag) => Ok((std::mem::take(tag), Vec::new())),
+        Value::List(items) => match items.first_mut() {
+            Some(Value::Symbol(tag)) => {
+                let tag = std::mem::take(tag);
+                Ok((tag, items.drain(1..).collect()))
+            }
+            _ => Err(expected()),
+        },
+        _ => Err(expected()),
+    }
+}
+
+fn fields<const N: usize>(values: Vec<Value>, tag: &str) -> Result<[Value; N], EvalError> {
+    let len = values.len();
+    values
+        .try_into()
+        .map_err(|_| EvalError::new(format!("{} expects {} fields, got {}", tag, N, len)))
+}
+"#;
+
+const LIST: &str = r#"
+fn list(mut value: Value) -> Result<Vec<Value>, EvalError> {
+    match &mut value {
+        Value::List(items) => Ok(std::mem::take(items)),
+        Value::Nil => Ok(Vec::new()),
+        _ => Err(EvalError::new("expected a list")),
+    }
+}
+"#;
+
+/// The keywords, strict and reserved, that names are written as raw
+/// identifiers to use.
+const KEYWORDS: &[&str] = &[
+    "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "do", "dyn",
+    "else", "enum", "extern", "false", "final", "fn", "for", "if", "impl", "in", "let", "loop",
+    "macro", "match", "mod", "move", "mut", "override", "priv", "pub", "ref", "return", "static",
+    "struct", "trait", "true", "try", "type", "typeof", "unsafe", "unsized", "use", "virtual",
+    "where", "while", "yield",
+];
+
+/// The keywords that cannot be raw identifiers, which names get a trailing
+/// underscore to use.
+const RESERVED: &[&str] = &["crate", "self", "Self", "super"];
+
+fn error(message: impl Into<String>) -> EvalError {
+    EvalError::new(format!("codegen: {}", message.into()))
+}
+
+fn symbol(obj: &Object, what: &str) -> Result<String, EvalError> {
+    match obj {
+        Object::Symbol(name) => Ok(name.to_string()),
+        other => Err(error(format!("expected {}, got {}", what, other))),
+    }
+}
+
+fn parse_type(obj: &Object) -> Result<Type, EvalError> {
+    if let Object::Symbol(name) = obj {
+        return Ok(match name.as_str() {
+            "integer" => Type::Integer,
+            "float" => Type::Float,
+            "boolean" => Type::Boolean,
+            "char" => Type::Char,
+            "string" => Type::String,
+            name => Type::Named(name.to_string()),
+        });
+    }
+
+    match obj.to_vec().as_deref() {
+        Some([Object::Symbol(head), inner]) if head.as_str() == "list" => {
+            Ok(Type::List(Box::new(parse_type(inner)?)))
+        }
+        Some([Object::Symbol(head), inner]) if head.as_str() == "optional" => {
+            match parse_type(inner)? {
+                // `#f` could not tell a missing boolean from a false one.
+                Type::Boolean => Err(error("optional booleans are not supported")),
+                inner => Ok(Type::Optional(Box::new(inner))),
+            }
+        }
+        _ => Err(error(format!("invalid type {}", obj))),
+    }
+}
+
+fn parse_fields(fields: &[Object]) -> Result<Vec<Field>, EvalError> {
+    fields
+        .iter()
+        .map(|field| match field.to_vec().as_deref() {
+            Some([name, ty]) => Ok(Field {
+                name: symbol(name, "a field name")?,
+                ty: parse_type(ty)?,
+            }),
+            _ => Err(error(format!(
+                "expected a field and its type, got {}",
+                field
+            ))),
+        })
+        .collect()
+}
+
+fn parse_definition(form: &Object) -> Result<Definition, EvalError> {
+    let items = form.to_vec().unwrap_or_default();
+    match items.as_slice() {
+        [Object::Symbol(head), name, fields @ ..] if head.as_str() == "define-record" => Ok(
+            Definition::Record(symbol(name, "a record name")?, parse_fields(fields)?),
+        ),
+        [Object::Symbol(head), name, variants @ ..] if head.as_str() == "define-enum" => {
+            let variants = variants
+                .iter()
+                .map(|variant| match variant.to_vec().as_deref() {
+                    Some([name, fields @ ..]) => {
+                        Ok((symbol(name, "a variant name")?, parse_fields(fields)?))
+                    }
+                    _ => Ok((symbol(variant, "a variant")?, Vec::new())),
+                })
+                .collect::<Result<_, EvalError>>()?;
+            Ok(Definition::Enum(symbol(name, "an enum name")?, variants))
+        }
+        _ => Err(error(format!(
+            "expected define-record or define-enum, got {}",
+            form
+        ))),
+    }
+}
+
+/// `name` as a Rust identifier: with a trailing underscore if it is a keyword
+/// that cannot be a raw identifier, and as one if it is any other keyword.
+fn identifier(name: String) -> String {
+    if RESERVED.contains(&name.as_str()) {
+        format!("{}_", name)
+    } else if KEYWORDS.contains(&name.as_str()) {
+        format!("r#{}", name)
+    } else if name.starts_with(|c: char| c.is_numeric()) {
+        format!("_{}", name)
+    } else {
+        name
+    }
+}
+
+/// `line-item` as a type name, `LineItem`, leaving out the characters an
+/// identifier cannot have, such as `?` and `!`.
+fn type_name(name: &str) -> String {
+    let name = name
+        .split(|c: char| !c.is_alphanumeric())
+        .map(|word| {
+            let mut chars = word.chars();
+            chars
+                .next()
+                .map(|first| first.to_uppercase().chain(chars).collect::<String>())
+                .unwrap_or_default()
+        })
+        .collect();
+    identifier(name)
+}
+
+/// `unit-price` as a field name, `unit_price`, and `valid?` as `valid_`: the
+/// characters an identifier cannot have become underscores.
+fn field_name(name: &str) -> String {
+    let name = name
+        .chars()
+        .map(|c| match c.is_alphanumeric() {
+            true => c,
+            false => '_',
+        })
+        .collect();
+    identifier(name)
+}
+
+/// The name of the local a field is bound to, which must not hide the
+/// helpers.
+fn local_name(name: &str) -> String {
+    match field_name(name) {
+        name if name == "list" => String::from("list_"),
+        name => name,
+    }
+}
+
+fn rust_type(ty: &Type) -> String {
+    match ty {
+        Type::Integer => String::from("i64"),
+        Type::Float => String::from("f64"),
+        Type::Boolean => String::from("bool"),
+        Type::Char => String::from("char"),
+        Type::String => String::from("String"),
+        Type::List(inner) => format!("Vec<{}>", rust_type(inner)),
+        Type::Optional(inner) => format!("Option<{}>", rust_type(inner)),
+        Type::Named(name) => type_name(name),
+    }
+}
+
+fn is_wrapper(ty: &Type) -> bool {
+    matches!(ty, Type::List(_) | Type::Optional(_))
+}
+
+fn to_value(expr: &str, ty: &Type) -> String {
+    match ty {
+        Type::List(inner) if is_wrapper(inner) => format!(
+            "Value::List({}.into_iter().map(|item| {}).collect())",
+            expr,
+            to_value("item", inner)
+        ),
+        Type::List(_) => format!(
+            "Value::List({}.into_iter().map(Value::from).collect())",
+            expr
+        ),
+        Type::Optional(inner) => format!(
+            "match {} {{ Some(item) => {}, None => Value::Bool(false) }}",
+            expr,
+            to_value("item", inner)
+        ),
+        _ => format!("Value::from({})", expr),
+    }
+}
+
+/// The conversion of `expr` to `ty`, as an expression of a `Result`.
+fn from_value(expr: &str, ty: &Type) -> String {
+    match ty {
+        Type::List(inner) => {
+            let convert = match is_wrapper(inner) {
+                true => format!("|item| {}", from_value("item", inner)),
+                false => format!("{}::try_from", rust_type(inner)),
+            };
+            format!(
+                "list({})?.into_iter().map({}).collect::<Result<Vec<_>, EvalError>>()",
+                expr, convert
+            )
+        }
+        Type::Optional(inner) => format!(
+            "match {} {{ Value::Bool(false) => Ok(None), item => {}.map(Some) }}",
+            expr,
+            from_value("item", inner)
+        ),
+        ty => format!("{}::try_from({})", rust_type(ty), expr),
+    }
+}
+
+fn uses_list(ty: &Type) -> bool {
+    match ty {
+        Type::List(_) => true,
+        Type::Optional(inner) => uses_list(inner),
+        _ => false,
+    }
+}
+
+fn check_type(definitions: &[Definition], ty: &Type) -> Result<(), EvalError> {
+    match ty {
+        Type::List(inner) | Type::Optional(inner) => check_type(definitions, inner),
+        Type::Named(name) if !definitions.iter().any(|d| d.name() == name) => {
+            Err(error(format!("unknown type {}", name)))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// The struct fields, or the fields of an enum variant, each starting with
+/// `prefix`.
+fn write_fields(out: &mut String, fields: &[Field], prefix: &str) {
+    for field in fields {
+        let _ = writeln!(
+            out,
+            "{}{}: {},",
+            prefix,
+            field_name(&field.name),
+            rust_type(&field.ty)
+        );
+    }
+}
+
+/// `items` one per line, as rustfmt lays out long lists, for a list whose
+/// first line is indented by `indent`.
+fn multiline(items: &[String], indent: &str) -> String {
+    let lines = items
+        .iter()
+        .map(|item| format!("{}    {},\n", indent, item))
+        .collect::<String>();
+    format!("\n{}{}", lines, indent)
+}
+
+/// The field locals taken out of `values` and the struct literal fields
+/// converting them.
+fn write_conversions(out: &mut String, fields: &[Field], tag: &str) -> Vec<String> {
+    let locals = fields
+        .iter()
+        .map(|field| local_name(&field.name))
+        .collect::<Vec<_>>();
+    let _ = writeln!(
+        out,
+        "        let [{}] = fields(values, {:?})?;",
+        locals.join(", "),
+        tag
+    );
+
+    fields
+        .iter()
+        .zip(&locals)
+        .map(|(field, local)| {
+            let name = field_name(&field.name);
+            format!("{}: {}?", name, from_value(local, &field.ty))
+        })
+        .collect()
+}
+
+fn write_record(out: &mut String, name: &str, fields: &[Field]) {
+    let rust_name = type_name(name);
+    let _ = writeln!(out, "\n#[derive(Debug, Clone, PartialEq)]");
+    let _ = writeln!(out, "pub struct {} {{", rust_name);
+    write_fields(out, fields, "    pub ");
+    let _ = writeln!(out, "}}");
+
+    let mut values = vec![format!("Value::Symbol({:?}.to_string())", name)];
+    values.extend(fields.iter().map(|field| {
+        let expr = format!("value.{}", field_name(&field.name));
+        to_value(&expr, &field.ty)
+    }));
+    let _ = writeln!(out, "\nimpl From<{}> for Value {{", rust_name);
+    let _ = writeln!(out, "    fn from(value: {}) -> Self {{", rust_name);
+    let values = multiline(&values, "        ");
+    let _ = writeln!(out, "        Value::List(vec![{}])", values);
+    let _ = writeln!(out, "    }}\n}}");
+
+    let _ = writeln!(out, "\nimpl TryFrom<Value> for {} {{", rust_name);
+    let _ = writeln!(out, "    type Error = EvalError;\n");
+    let _ = writeln!(
+        out,
+        "    fn try_from(value: Value) -> Result<Self, Self::Error> {{"
+    );
+    let _ = writeln!(
+        out,
+        "        let (tag, values) = tagged(value, {:?})?;",
+        name
+    );
+    let _ = writeln!(out, "        if tag != {:?} {{", name);
+    let _ = writeln!(
+        out,
+        "            return Err(EvalError::new(\"expected a {}\"));",
+        name
+    );
+    let _ = writeln!(out, "        }}");
+    let conversions = write_conversions(out, fields, name);
+    let conversions = multiline(&conversions, "        ");
+    let _ = writeln!(out, "        Ok({} {{{}}})", rust_name, conversions);
+    let _ = writeln!(out, "    }}\n}}");
+}
+
+fn write_enum(out: &mut String, name: &str, variants: &[(String, Vec<Field>)]) {
+    let rust_name = type_name(name);
+    let _ = writeln!(out, "\n#[derive(Debug, Clone, PartialEq)]");
+    let _ = writeln!(out, "pub enum {} {{", rust_name);
+    for (variant, fields) in variants {
+        if fields.is_empty() {
+            let _ = writeln!(out, "    {},", type_name(variant));
+        } else {
+            let _ = writeln!(out, "    {} {{", type_name(variant));
+            write_fields(out, fields, "        ");
+            let _ = writeln!(out, "    }},");
+        }
+    }
+    let _ = writeln!(out, "}}");
+
+    let _ = writeln!(out, "\nimpl From<{}> for Value {{", rust_name);
+    let _ = writeln!(out, "    fn from(value: {}) -> Self {{", rust_name);
+    let _ = writeln!(out, "        match value {{");
+    for (variant, fields) in variants {
+        let tag = format!("Value::Symbol({:?}.to_string())", variant);
+        if fields.is_empty() {
+            let _ = writeln!(
+                out,
+                "            {}::{} => {},",
+                rust_name,
+                type_name(variant),
+                tag
+            );
+            continue;
+        }
+
+        let names = fields
+            .iter()
+            .map(|field| field_name(&field.name))
+            .collect::<Vec<_>>();
+        let mut values = vec![tag];
+        values.extend(
+            fields
+                .iter()
+                .zip(&names)
+                .map(|(field, name)| to_value(name, &field.ty)),
+        );
+        let _ = writeln!(
+            out,
+            "            {}::{} {{ {} }} => Value::List(vec![{}]),",
+            rust_name,
+            type_name(variant),
+            names.join(", "),
+            multiline(&values, "            ")
+        );
+    }
+    let _ = writeln!(out, "        }}\n    }}\n}}");
+
+    let _ = writeln!(out, "\nimpl TryFrom<Value> for {} {{", rust_name);
+    let _ = writeln!(out, "    type Error = EvalError;\n");
+    let _ = writeln!(
+        out,
+        "    fn try_from(value: Value) -> Result<Self, Self::Error> {{"
+    );
+    let _ = writeln!(
+        out,
+        "        let (tag, values) = tagged(value, {:?})?;",
+        name
+    );
+    let _ = writeln!(out, "        match tag.as_str() {{");
+    for (variant, fields) in variants {
+        let _ = writeln!(out, "            {:?} => {{", variant);
+        let mut arm = String::new();
+        let conversions = write_conversions(&mut arm, fields, variant);
+        for line in arm.lines() {
+            let _ = writeln!(out, "        {}", line);
+        }
+        let value = match fields.is_empty() {
+            true => format!("{}::{}", rust_name, type_name(variant)),
+            false => format!(
+                "{}::{} {{{}}}",
+                rust_name,
+                type_name(variant),
+                multiline(&conversions, "                ")
+            ),
+        };
+        let _ = writeln!(out, "                Ok({})", value);
+        let _ = writeln!(out, "            }}");
+    }
+    let _ = writeln!(
+        out,
+        "            _ => Err(EvalError::new(\"expected a {}\")),",
+        name
+    );
+    let _ = writeln!(out, "        }}\n    }}\n}}");
+}
+
+/// The Rust code for the definitions in `schema`.
+pub fn generate(schema: &str) -> Result<String, EvalError> {
+    let forms = parse(schema).map_err(|e| error(e.to_string()))?;
+    let definitions = forms
+        .iter()
+        .map(parse_definition)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut lists = false;
+    for definition in &definitions {
+        let fields: Vec<&Field> = match definition {
+            Definition::Record(_, fields) => fields.iter().collect(),
+            Definition::Enum(_, variants) => variants.iter().flat_map(|(_, f)| f).collect(),
+        };
+        for field in fields {
+            check_type(&definitions, &field.ty)?;
+            lists |= uses_list(&field.ty);
+        }
+    }
+
+    let mut out = String::from(
+        "// Generated by `lisp-rs codegen`. Do not edit.\n\nuse lisp_rs::{EvalError, Value};\n",
+    );
+    for definition in &definitions {
+        match definition {
+            Definition::Record(name, fields) => write_record(&mut out, name, fields),
+            Definition::Enum(name, variants) => write_enum(&mut out, name, variants),
+        }
+    }
+    if !definitions.is_empty() {
+        out.push_str(TAGGED);
+    }
+    if lists {
+        out.push_str(LIST);
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate() {
+        let code = generate(
+            "(define-record line-item (name string) (unit-price float) (type (optional string)))
+             (define-enum status pending (shipped (items (list line-item))))",
+        )
+        .unwrap();
+
+        assert!(code.contains(
+            "pub struct LineItem {\n    pub name: String,\n    pub unit_price: f64,\n    \
+             pub r#type: Option<String>,\n}"
+        ));
+        assert!(code.contains("    Shipped {\n        items: Vec<LineItem>,\n    },"));
+        assert!(code
+            .contains("        let [name, unit_price, r#type] = fields(values, \"line-item\")?;"));
+        assert!(code.contains("Status::Pending => Value::Symbol(\"pending\".to_string()),"));
+        assert!(code.contains("fn list(mut value: Value)"));
+    }
+
+    /// Names that are Rust keywords, or not Rust identifiers at all.
+    const NAMES: &str = "\
+(define-record self (super integer) (crate boolean) (type float) (abstract (optional string))
+  (valid? boolean) (set-done! char) (list (list self)))
+(define-enum do (final (virtual (optional self))) macro? (override (typeof integer) (unsized char)))
+";
+
+    /// The code generated from `NAMES`, compiled with the tests.
+    #[allow(dead_code)]
+    mod names {
+        extern crate self as lisp_rs;
+
+        include!("codegen/names.rs");
+    }
+
+    #[test]
+    fn test_names_are_rust_identifiers() {
+        assert_eq!(generate(NAMES).unwrap(), include_str!("codegen/names.rs"));
+
+        use crate::Value;
+        use names::{Do, Self_};
+        let value = Value::List(vec![
+            Value::Symbol(String::from("self")),
+            Value::Int(1),
+            Value::Bool(true),
+            Value::Float(2.5),
+            Value::Bool(false),
+            Value::Bool(false),
+            Value::Char('x'),
+            Value::List(Vec::new()),
+        ]);
+        let record = Self_::try_from(value.clone()).unwrap();
+        assert_eq!(
+            (record.super_, record.r#type, record.valid_),
+            (1, 2.5, false)
+        );
+        assert_eq!(Value::from(record), value);
+        assert_eq!(
+            Do::try_from(Value::Symbol(String::from("macro?"))).unwrap(),
+            Do::Macro
+        );
+    }
+
+    #[test]
+    fn test_invalid_schemas() {
+        let message = |schema| generate(schema).unwrap_err().message().to_string();
+        assert_eq!(
+            message("(define-record point (x vector))"),
+            "codegen: unknown type vector"
+        );
+        assert_eq!(
+            message("(define-record flag (on (optional boolean)))"),
+            "codegen: optional booleans are not supported"
+        );
+        assert_eq!(
+            message("(define point 1)"),
+            "codegen: expected define-record or define-enum, got (define point 1)"
+        );
+    }
+}
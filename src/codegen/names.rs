@@ -0,0 +1,138 @@
+// Generated by `lisp-rs codegen`. Do not edit.
+
+use lisp_rs::{EvalError, Value};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Self_ {
+    pub super_: i64,
+    pub crate_: bool,
+    pub r#type: f64,
+    pub r#abstract: Option<String>,
+    pub valid_: bool,
+    pub set_done_: char,
+    pub list: Vec<Self_>,
+}
+
+impl From<Self_> for Value {
+    fn from(value: Self_) -> Self {
+        Value::List(vec![
+            Value::Symbol("self".to_string()),
+            Value::from(value.super_),
+            Value::from(value.crate_),
+            Value::from(value.r#type),
+            match value.r#abstract { Some(item) => Value::from(item), None => Value::Bool(false) },
+            Value::from(value.valid_),
+            Value::from(value.set_done_),
+            Value::List(value.list.into_iter().map(Value::from).collect()),
+        ])
+    }
+}
+
+impl TryFrom<Value> for Self_ {
+    type Error = EvalError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        let (tag, values) = tagged(value, "self")?;
+        if tag != "self" {
+            return Err(EvalError::new("expected a self"));
+        }
+        let [super_, crate_, r#type, r#abstract, valid_, set_done_, list_] = fields(values, "self")?;
+        Ok(Self_ {
+            super_: i64::try_from(super_)?,
+            crate_: bool::try_from(crate_)?,
+            r#type: f64::try_from(r#type)?,
+            r#abstract: match r#abstract { Value::Bool(false) => Ok(None), item => String::try_from(item).map(Some) }?,
+            valid_: bool::try_from(valid_)?,
+            set_done_: char::try_from(set_done_)?,
+            list: list(list_)?.into_iter().map(Self_::try_from).collect::<Result<Vec<_>, EvalError>>()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Do {
+    Final {
+        r#virtual: Option<Self_>,
+    },
+    Macro,
+    Override {
+        r#typeof: i64,
+        r#unsized: char,
+    },
+}
+
+impl From<Do> for Value {
+    fn from(value: Do) -> Self {
+        match value {
+            Do::Final { r#virtual } => Value::List(vec![
+                Value::Symbol("final".to_string()),
+                match r#virtual { Some(item) => Value::from(item), None => Value::Bool(false) },
+            ]),
+            Do::Macro => Value::Symbol("macro?".to_string()),
+            Do::Override { r#typeof, r#unsized } => Value::List(vec![
+                Value::Symbol("override".to_string()),
+                Value::from(r#typeof),
+                Value::from(r#unsized),
+            ]),
+        }
+    }
+}
+
+impl TryFrom<Value> for Do {
+    type Error = EvalError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        let (tag, values) = tagged(value, "do")?;
+        match tag.as_str() {
+            "final" => {
+                let [r#virtual] = fields(values, "final")?;
+                Ok(Do::Final {
+                    r#virtual: match r#virtual { Value::Bool(false) => Ok(None), item => Self_::try_from(item).map(Some) }?,
+                })
+            }
+            "macro?" => {
+                let [] = fields(values, "macro?")?;
+                Ok(Do::Macro)
+            }
+            "override" => {
+                let [r#typeof, r#unsized] = fields(values, "override")?;
+                Ok(Do::Override {
+                    r#typeof: i64::try_from(r#typeof)?,
+                    r#unsized: char::try_from(r#unsized)?,
+                })
+            }
+            _ => Err(EvalError::new("expected a do")),
+        }
+    }
+}
+
+/// The name a value starts with, and its fields.
+fn tagged(mut value: Value, type_name: &str) -> Result<(String, Vec<Value>), EvalError> {
+    let expected = || EvalError::new(format!("expected a {}", type_name));
+    match &mut value {
+        Value::Symbol(tag) => Ok((std::mem::take(tag), Vec::new())),
+        Value::List(items) => match items.first_mut() {
+            Some(Value::Symbol(tag)) => {
+                let tag = std::mem::take(tag);
+                Ok((tag, items.drain(1..).collect()))
+            }
+            _ => Err(expected()),
+        },
+        _ => Err(expected()),
+    }
+}
+
+fn fields<const N: usize>(values: Vec<Value>, tag: &str) -> Result<[Value; N], EvalError> {
+    let len = values.len();
+    values
+        .try_into()
+        .map_err(|_| EvalError::new(format!("{} expects {} fields, got {}", tag, N, len)))
+}
+
+fn list(mut value: Value) -> Result<Vec<Value>, EvalError> {
+    match &mut value {
+        Value::List(items) => Ok(std::mem::take(items)),
+        Value::Nil => Ok(Vec::new()),
+        _ => Err(EvalError::new("expected a list")),
+    }
+}
@@ -0,0 +1,645 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::rc::Rc;
+
+use crate::comparator;
+use crate::eval::{apply, EvalError};
+use crate::gc::{self, Edge, Trace};
+use crate::object::Object;
+use crate::printer;
+
+/// A binary min-heap ordered by a Lisp `less?` procedure or a comparator
+/// (numeric `<` by default).
+pub struct Heap {
+    less: Object,
+    items: Vec<Object>,
+}
+
+struct Ordering<'a> {
+    less: &'a Object,
+}
+
+impl Ordering<'_> {
+    fn is_less(&self, items: &[Object], a: usize, b: usize) -> Result<bool, EvalError> {
+        match self.less {
+            Object::Comparator(comparator) => comparator.less(&items[a], &items[b]),
+            less => Ok(apply(less, vec![items[a].clone(), items[b].clone()])?.is_truthy()),
+        }
+    }
+
+    fn push(&self, items: &mut Vec<Object>, item: Object) -> Result<(), EvalError> {
+        items.push(item);
+
+        let mut child = items.len() - 1;
+        while child > 0 {
+            let parent = (child - 1) / 2;
+            if !self.is_less(items, child, parent)? {
+                break;
+            }
+            items.swap(child, parent);
+            child = parent;
+        }
+
+        Ok(())
+    }
+
+    fn pop(&self, items: &mut Vec<Object>) -> Result<Option<Object>, EvalError> {
+        if items.is_empty() {
+            return Ok(None);
+        }
+
+        let last = items.len() - 1;
+        items.swap(0, last);
+        let top = items.pop();
+
+        let mut parent = 0;
+        loop {
+            let mut smallest = parent;
+            for child in [2 * parent + 1, 2 * parent + 2] {
+                if child < items.len() && self.is_less(items, child, smallest)? {
+                    smallest = child;
+                }
+            }
+
+            if smallest == parent {
+                break;
+            }
+            items.swap(parent, smallest);
+            parent = smallest;
+        }
+
+        Ok(top)
+    }
+}
+
+/// Runs `f` on the heap's items. Nothing stays borrowed while `f` runs, so a
+/// comparator touching the same heap cannot trigger a double borrow.
+fn with_items<T, F>(heap: &RefCell<Heap>, f: F) -> Result<T, EvalError>
+where
+    F: FnOnce(&Ordering, &mut Vec<Object>) -> Result<T, EvalError>,
+{
+    let less = heap.borrow().less.clone();
+    let mut items = std::mem::take(&mut heap.borrow_mut().items);
+    let result = f(&Ordering { less: &less }, &mut items);
+    heap.borrow_mut().items = items;
+
+    result
+}
+
+impl Trace for Heap {
+    fn trace(&self, edge: &mut dyn FnMut(Edge)) {
+        edge(Edge::Object(&self.less));
+        for item in &self.items {
+            edge(Edge::Object(item));
+        }
+    }
+
+    fn clear(&mut self) {
+        self.less = Object::Void;
+        self.items.clear();
+    }
+}
+
+fn heap_arg<'a>(name: &str, args: &'a [Object]) -> Result<&'a Rc<RefCell<Heap>>, EvalError> {
+    match args.first() {
+        Some(Object::Heap(heap)) => Ok(heap),
+        _ => Err(EvalError::new(format!("{} expects a heap", name))),
+    }
+}
+
+pub fn make_heap(args: &[Object]) -> Result<Object, EvalError> {
+    let less = match args {
+        [] => Object::Builtin(crate::builtins::lookup("<").unwrap()),
+        [less @ (Object::Lambda(_)
+        | Object::Builtin(_)
+        | Object::Native(_)
+        | Object::Memo(_)
+        | Object::Closure(_)
+        | Object::Comparator(_))] => less.clone(),
+        _ => {
+            return Err(EvalError::new(
+                "make-heap expects an optional less? procedure or comparator",
+            ))
+        }
+    };
+
+    let heap = Rc::new(RefCell::new(Heap {
+        less,
+        items: Vec::new(),
+    }));
+    gc::track(&heap);
+
+    Ok(Object::Heap(heap))
+}
+
+pub fn heap_push(args: &[Object]) -> Result<Object, EvalError> {
+    let heap = heap_arg("heap-push!", args)?;
+    let item = match args {
+        [_, item] => item.clone(),
+        _ => return Err(EvalError::new("heap-push! expects a heap and an item")),
+    };
+
+    with_items(heap, |ordering, items| ordering.push(items, item))?;
+
+    Ok(Object::Void)
+}
+
+pub fn heap_pop(args: &[Object]) -> Result<Object, EvalError> {
+    let heap = heap_arg("heap-pop!", args)?;
+
+    with_items(heap, |ordering, items| ordering.pop(items))?
+        .ok_or_else(|| EvalError::new("heap-pop! on an empty heap"))
+}
+
+pub fn heap_peek(args: &[Object]) -> Result<Object, EvalError> {
+    let heap = heap_arg("heap-peek", args)?;
+    let heap = heap.borrow();
+
+    heap.items
+        .first()
+        .cloned()
+        .ok_or_else(|| EvalError::new("heap-peek on an empty heap"))
+}
+
+pub fn heap_size(args: &[Object]) -> Result<Object, EvalError> {
+    let heap = heap_arg("heap-size", args)?;
+
+    Ok(Object::Integer(heap.borrow().items.len() as i64))
+}
+
+fn deque_arg<'a>(
+    name: &str,
+    args: &'a [Object],
+    len: usize,
+) -> Result<&'a Rc<RefCell<VecDeque<Object>>>, EvalError> {
+    match args.first() {
+        Some(Object::Deque(deque)) if args.len() == len => Ok(deque),
+        _ if args.len() != len => Err(EvalError::new(format!(
+            "{} expects {} arguments, got {}",
+            name,
+            len,
+            args.len()
+        ))),
+        _ => Err(EvalError::new(format!("{} expects a deque", name))),
+    }
+}
+
+pub fn make_deque(args: &[Object]) -> Result<Object, EvalError> {
+    if !args.is_empty() {
+        return Err(EvalError::new("make-deque expects no arguments"));
+    }
+
+    let deque = Rc::new(RefCell::new(VecDeque::new()));
+    gc::track(&deque);
+
+    Ok(Object::Deque(deque))
+}
+
+pub fn deque_push_front(args: &[Object]) -> Result<Object, EvalError> {
+    let deque = deque_arg("deque-push-front!", args, 2)?;
+    deque.borrow_mut().push_front(args[1].clone());
+
+    Ok(Object::Void)
+}
+
+pub fn deque_push_back(args: &[Object]) -> Result<Object, EvalError> {
+    let deque = deque_arg("deque-push-back!", args, 2)?;
+    deque.borrow_mut().push_back(args[1].clone());
+
+    Ok(Object::Void)
+}
+
+pub fn deque_pop_front(args: &[Object]) -> Result<Object, EvalError> {
+    let deque = deque_arg("deque-pop-front!", args, 1)?;
+    let item = deque.borrow_mut().pop_front();
+
+    item.ok_or_else(|| EvalError::new("deque-pop-front! on an empty deque"))
+}
+
+pub fn deque_pop_back(args: &[Object]) -> Result<Object, EvalError> {
+    let deque = deque_arg("deque-pop-back!", args, 1)?;
+    let item = deque.borrow_mut().pop_back();
+
+    item.ok_or_else(|| EvalError::new("deque-pop-back! on an empty deque"))
+}
+
+pub fn deque_size(args: &[Object]) -> Result<Object, EvalError> {
+    let deque = deque_arg("deque-size", args, 1)?;
+
+    Ok(Object::Integer(deque.borrow().len() as i64))
+}
+
+/// A hash table keyed by `equal?`, so procedures and containers are keys by
+/// identity.
+#[derive(Default)]
+pub struct HashTable {
+    entries: HashMap<Key, Object>,
+}
+
+#[derive(Clone, PartialEq, Hash)]
+struct Key(Object);
+
+impl Eq for Key {}
+
+impl HashTable {
+    pub fn get(&self, key: &Object) -> Option<&Object> {
+        self.entries.get(&Key(key.clone()))
+    }
+
+    pub fn insert(&mut self, key: Object, value: Object) {
+        self.entries.insert(Key(key), value);
+    }
+
+    pub fn remove(&mut self, key: &Object) -> Option<Object> {
+        self.entries.remove(&Key(key.clone()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The entries, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (&Object, &Object)> {
+        self.entries.iter().map(|(key, value)| (&key.0, value))
+    }
+
+    /// The entries, sorted by key when ordered printing is on.
+    pub fn entries(&self) -> Vec<(Object, Object)> {
+        let mut entries = self
+            .iter()
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect::<Vec<_>>();
+        if printer::is_ordered() {
+            entries.sort_by(|(a, _), (b, _)| comparator::print_order(a, b));
+        }
+
+        entries
+    }
+}
+
+impl Trace for HashTable {
+    fn trace(&self, edge: &mut dyn FnMut(Edge)) {
+        for (key, value) in &self.entries {
+            edge(Edge::Object(&key.0));
+            edge(Edge::Object(value));
+        }
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+/// Wraps `table` as a new object registered with the collector.
+pub fn hash_table_object(table: HashTable) -> Object {
+    let table = Rc::new(RefCell::new(table));
+    gc::track(&table);
+
+    Object::HashTable(table)
+}
+
+fn table_arg<'a>(
+    name: &str,
+    args: &'a [Object],
+    len: usize,
+) -> Result<&'a Rc<RefCell<HashTable>>, EvalError> {
+    match args.first() {
+        Some(Object::HashTable(table)) if args.len() == len => Ok(table),
+        _ if args.len() != len => Err(EvalError::new(format!(
+            "{} expects {} arguments, got {}",
+            name,
+            len,
+            args.len()
+        ))),
+        _ => Err(EvalError::new(format!("{} expects a hash table", name))),
+    }
+}
+
+pub fn make_hash_table(args: &[Object]) -> Result<Object, EvalError> {
+    if !args.is_empty() {
+        return Err(EvalError::new("make-hash-table expects no arguments"));
+    }
+
+    Ok(hash_table_object(HashTable::default()))
+}
+
+pub fn is_hash_table(args: &[Object]) -> Result<Object, EvalError> {
+    match args {
+        [obj] => Ok(Object::Bool(matches!(obj, Object::HashTable(_)))),
+        _ => Err(EvalError::new("hash-table? expects one argument")),
+    }
+}
+
+pub fn hash_table_set(args: &[Object]) -> Result<Object, EvalError> {
+    let table = table_arg("hash-table-set!", args, 3)?;
+    table.borrow_mut().insert(args[1].clone(), args[2].clone());
+
+    Ok(Object::Void)
+}
+
+/// `(hash-table-ref table key)` fails when `key` is missing;
+/// `(hash-table-ref table key default)` returns `default` instead.
+pub fn hash_table_ref(args: &[Object]) -> Result<Object, EvalError> {
+    let table = table_arg("hash-table-ref", args, args.len().clamp(2, 3))?;
+    let value = table.borrow().get(&args[1]).cloned();
+
+    match (value, args.get(2)) {
+        (Some(value), _) => Ok(value),
+        (None, Some(default)) => Ok(default.clone()),
+        (None, None) => Err(EvalError::new(format!(
+            "hash-table-ref: no entry for {}",
+            args[1]
+        ))),
+    }
+}
+
+/// `(hash-table-delete! table key)` returns whether there was an entry.
+pub fn hash_table_delete(args: &[Object]) -> Result<Object, EvalError> {
+    let table = table_arg("hash-table-delete!", args, 2)?;
+    let removed = table.borrow_mut().remove(&args[1]).is_some();
+
+    Ok(Object::Bool(removed))
+}
+
+pub fn hash_table_count(args: &[Object]) -> Result<Object, EvalError> {
+    let table = table_arg("hash-table-count", args, 1)?;
+
+    Ok(Object::Integer(table.borrow().len() as i64))
+}
+
+pub fn hash_table_keys(args: &[Object]) -> Result<Object, EvalError> {
+    let table = table_arg("hash-table-keys", args, 1)?;
+    let keys = table
+        .borrow()
+        .entries()
+        .into_iter()
+        .map(|(key, _)| key)
+        .collect::<Vec<_>>();
+
+    Ok(Object::list(keys))
+}
+
+/// `(hash-table->alist table)` is the entries as an association list.
+pub fn hash_table_to_alist(args: &[Object]) -> Result<Object, EvalError> {
+    let table = table_arg("hash-table->alist", args, 1)?;
+    let entries = table
+        .borrow()
+        .entries()
+        .into_iter()
+        .map(|(key, value)| Object::cons(key, value))
+        .collect::<Vec<_>>();
+
+    Ok(Object::list(entries))
+}
+
+/// `(alist->hash-table alist)` is a hash table of the entries of an
+/// association list. A key found twice keeps its first value, as `assoc`
+/// would find it.
+pub fn alist_to_hash_table(args: &[Object]) -> Result<Object, EvalError> {
+    let [alist] = args else {
+        return Err(EvalError::new(format!(
+            "alist->hash-table expects 1 argument, got {}",
+            args.len()
+        )));
+    };
+
+    let mut table = HashTable::default();
+    for_each_entry("alist->hash-table", alist, |key, value| {
+        if table.get(key).is_none() {
+            table.insert(key.clone(), value.clone());
+        }
+        Ok(false)
+    })?;
+
+    Ok(hash_table_object(table))
+}
+
+/// Calls `f` on the key and value of each entry of `alist` until it returns
+/// true, returning that entry, or `#f` if it never does.
+fn for_each_entry(
+    name: &str,
+    alist: &Object,
+    mut f: impl FnMut(&Object, &Object) -> Result<bool, EvalError>,
+) -> Result<Object, EvalError> {
+    let not_alist = || {
+        EvalError::new(format!(
+            "{} expects an association list, got {}",
+            name, alist
+        ))
+    };
+    let mut current = alist.clone();
+    loop {
+        match current {
+            Object::Nil => return Ok(Object::Bool(false)),
+            Object::Pair(pair) => {
+                let entry = pair.car();
+                let Object::Pair(key_value) = &entry else {
+                    return Err(not_alist());
+                };
+                if f(&key_value.car(), &key_value.cdr())? {
+                    return Ok(entry);
+                }
+                current = pair.cdr();
+            }
+            _ => return Err(not_alist()),
+        }
+    }
+}
+
+/// `(assq key alist)`, `(assv key alist)` and `(assoc key alist [equal])`
+/// are the first entry of an association list whose key is `eq?`, `eqv?`
+/// or `equal?` to `key`, or for `assoc` the one `equal` is true of, or
+/// `#f`.
+pub fn assq(args: &[Object]) -> Result<Object, EvalError> {
+    let (key, alist) = key_and_alist("assq", args, 2)?;
+
+    for_each_entry("assq", alist, |other, _| Ok(key.is_eq(other)))
+}
+
+pub fn assv(args: &[Object]) -> Result<Object, EvalError> {
+    let (key, alist) = key_and_alist("assv", args, 2)?;
+
+    for_each_entry("assv", alist, |other, _| Ok(key.is_eqv(other)))
+}
+
+pub fn assoc(args: &[Object]) -> Result<Object, EvalError> {
+    let (key, alist) = key_and_alist("assoc", args, 3)?;
+
+    match args.get(2) {
+        Some(equal) => for_each_entry("assoc", alist, |other, _| {
+            Ok(apply(equal, vec![key.clone(), other.clone()])?.is_truthy())
+        }),
+        None => for_each_entry("assoc", alist, |other, _| Ok(key == other)),
+    }
+}
+
+/// The key and association list of `(name key alist ...)`, which takes at
+/// most `max` arguments.
+fn key_and_alist<'a>(
+    name: &str,
+    args: &'a [Object],
+    max: usize,
+) -> Result<(&'a Object, &'a Object), EvalError> {
+    match args {
+        [key, alist, ..] if args.len() <= max => Ok((key, alist)),
+        _ => Err(EvalError::new(format!(
+            "{} expects a key and an association list, got {} arguments",
+            name,
+            args.len()
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builtins::global_env;
+    use crate::eval::eval_str;
+
+    #[test]
+    fn test_heap_pops_in_order() {
+        let env = global_env();
+        let result = eval_str(
+            "(define h (make-heap))
+             (heap-push! h 5)
+             (heap-push! h 1)
+             (heap-push! h 3)
+             (heap-push! h 2)
+             (list (heap-size h) (heap-pop! h) (heap-pop! h) (heap-peek h) (heap-pop! h) (heap-pop! h))",
+            &env,
+        )
+        .unwrap();
+
+        assert_eq!(result.to_string(), "(4 1 2 3 3 5)");
+        assert!(eval_str("(heap-pop! h)", &env).is_err());
+    }
+
+    #[test]
+    fn test_heap_with_custom_comparator() {
+        let env = global_env();
+        let result = eval_str(
+            "(define h (make-heap (lambda (a b) (> a b))))
+             (heap-push! h 1)
+             (heap-push! h 9)
+             (heap-push! h 4)
+             (list (heap-pop! h) (heap-pop! h) (heap-pop! h))",
+            &env,
+        )
+        .unwrap();
+
+        assert_eq!(result.to_string(), "(9 4 1)");
+
+        let result = eval_str(
+            "(define h (make-heap (make-default-comparator)))
+             (heap-push! h \"pear\")
+             (heap-push! h \"apple\")
+             (heap-push! h \"fig\")
+             (list (heap-pop! h) (heap-pop! h) (heap-pop! h))",
+            &env,
+        )
+        .unwrap();
+
+        assert_eq!(result.to_string(), "(\"apple\" \"fig\" \"pear\")");
+    }
+
+    #[test]
+    fn test_deque_ends() {
+        let env = global_env();
+        let result = eval_str(
+            "(define d (make-deque))
+             (deque-push-back! d 2)
+             (deque-push-front! d 1)
+             (deque-push-back! d 3)
+             (list (deque-size d) (deque-pop-front! d) (deque-pop-back! d) (deque-pop-back! d))",
+            &env,
+        )
+        .unwrap();
+
+        assert_eq!(result.to_string(), "(3 1 3 2)");
+        assert!(eval_str("(deque-pop-front! d)", &env).is_err());
+    }
+
+    #[test]
+    fn test_hash_tables() {
+        let env = global_env();
+        let result = eval_str(
+            "(define t (make-hash-table))
+             (hash-table-set! t \"a\" 1)
+             (hash-table-set! t '(1 2) 'list)
+             (hash-table-set! t \"a\" 2)
+             (hash-table-set! t 'gone 3)
+             (list (hash-table-delete! t 'gone)
+                   (hash-table-delete! t 'gone)
+                   (hash-table-count t)
+                   (hash-table-ref t \"a\")
+                   (hash-table-ref t (list 1 2))
+                   (hash-table-ref t 'missing #f)
+                   (hash-table? t))",
+            &env,
+        )
+        .unwrap();
+
+        assert_eq!(result.to_string(), "(#t #f 2 2 list #f #t)");
+        assert!(eval_str("(hash-table-ref t 'missing)", &env).is_err());
+    }
+
+    #[test]
+    fn test_association_lists() {
+        let env = global_env();
+        let result = eval_str(
+            "(define config (list (cons 'port 80) (cons \"host\" 'a) (cons 1.5 'b) (cons 'port 8080)))
+             (define table (alist->hash-table config))
+             (list (assq 'port config)
+                   (assq \"host\" config)
+                   (assoc \"host\" config)
+                   (assv 1.5 config)
+                   (assoc 1 config (lambda (key other) (if (number? other) (> other key) #f)))
+                   (assq 'missing config)
+                   (hash-table-ref table 'port)
+                   (hash-table-count table))",
+            &env,
+        )
+        .unwrap();
+
+        assert_eq!(
+            result.to_string(),
+            "((port . 80) #f (\"host\" . a) (1.5 . b) (1.5 . b) #f 80 3)"
+        );
+        assert_eq!(
+            eval_str("(assoc 'a '((a . 1) b))", &env)
+                .unwrap()
+                .to_string(),
+            "(a . 1)"
+        );
+        assert_eq!(
+            eval_str("(assq 'c '((a . 1) b))", &env)
+                .unwrap_err()
+                .message(),
+            "assq expects an association list, got ((a . 1) b)"
+        );
+    }
+
+    #[test]
+    fn test_hash_tables_print_in_key_order() {
+        let env = global_env();
+        let result = eval_str(
+            "(define t (make-hash-table))
+             (for-each (lambda (k) (hash-table-set! t k (* k k))) '(5 3 10 1 4 2))
+             (hash-table-set! t \"b\" car)
+             (hash-table-set! t 'self t)
+             (list (hash-table-keys t) t)",
+            &env,
+        )
+        .unwrap();
+
+        assert_eq!(
+            result.to_string(),
+            "((\"b\" self 1 2 3 4 5 10) \
+             #<hash-table (\"b\" . #<builtin car>) (self . #<hash-table ...>) (1 . 1) (2 . 4) \
+             (3 . 9) (4 . 16) (5 . 25) (10 . 100)>)"
+        );
+    }
+}
@@ -0,0 +1,516 @@
+//! Comparators, after SRFI 128: a type test, an equality, an ordering and a
+//! hash function bundled together, so that `sort`, heaps and ordered
+//! collections can order any kind of value, including ones whose ordering
+//! is defined in Lisp.
+//!
+//! `(make-comparator type-test equality ordering hash)` takes procedures,
+//! or `#t` for a type test accepting everything or an equality derived from
+//! the ordering, and `#f` for a comparator that cannot order or hash.
+//! `(make-default-comparator)` orders numbers, chars, strings, symbols,
+//! booleans and lists of those.
+
+use std::cmp::Ordering;
+use std::rc::Rc;
+
+use crate::builtins;
+use crate::eval::{apply, EvalError};
+use crate::gc::{Edge, Trace};
+use crate::object::{Builtin, Object};
+
+pub struct Comparator {
+    /// `None` accepts every value.
+    type_test: Option<Object>,
+    /// `None` derives equality from the ordering.
+    equality: Option<Object>,
+    ordering: Option<Object>,
+    hash: Option<Object>,
+}
+
+impl Comparator {
+    pub fn default_comparator() -> Comparator {
+        Comparator {
+            type_test: None,
+            equality: builtins::lookup("equal?").map(Object::Builtin),
+            ordering: Some(Object::Builtin(Builtin {
+                name: "default-ordering",
+                func: default_less,
+            })),
+            hash: builtins::lookup("hash").map(Object::Builtin),
+        }
+    }
+
+    /// A comparator ordering by a `less?` procedure.
+    pub fn from_less(less: Object) -> Comparator {
+        Comparator {
+            type_test: None,
+            equality: None,
+            ordering: Some(less),
+            hash: None,
+        }
+    }
+
+    pub fn is_ordered(&self) -> bool {
+        self.ordering.is_some()
+    }
+
+    pub fn is_hashable(&self) -> bool {
+        self.hash.is_some()
+    }
+
+    pub fn check_type(&self, obj: &Object) -> Result<(), EvalError> {
+        match &self.type_test {
+            Some(test) if !apply(test, vec![obj.clone()])?.is_truthy() => Err(EvalError::new(
+                format!("comparator does not accept {}", obj),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn less(&self, a: &Object, b: &Object) -> Result<bool, EvalError> {
+        let ordering = self
+            .ordering
+            .as_ref()
+            .ok_or_else(|| EvalError::new("comparator has no ordering"))?;
+        self.check_type(a)?;
+        self.check_type(b)?;
+
+        Ok(apply(ordering, vec![a.clone(), b.clone()])?.is_truthy())
+    }
+
+    pub fn equal(&self, a: &Object, b: &Object) -> Result<bool, EvalError> {
+        match &self.equality {
+            Some(equality) => {
+                self.check_type(a)?;
+                self.check_type(b)?;
+                Ok(apply(equality, vec![a.clone(), b.clone()])?.is_truthy())
+            }
+            None => Ok(!self.less(a, b)? && !self.less(b, a)?),
+        }
+    }
+
+    pub fn compare(&self, a: &Object, b: &Object) -> Result<Ordering, EvalError> {
+        if self.less(a, b)? {
+            Ok(Ordering::Less)
+        } else if self.equal(a, b)? {
+            Ok(Ordering::Equal)
+        } else {
+            Ok(Ordering::Greater)
+        }
+    }
+
+    pub fn hash(&self, obj: &Object) -> Result<Object, EvalError> {
+        let hash = self
+            .hash
+            .as_ref()
+            .ok_or_else(|| EvalError::new("comparator has no hash function"))?;
+        self.check_type(obj)?;
+
+        apply(hash, vec![obj.clone()])
+    }
+}
+
+impl Trace for Comparator {
+    fn trace(&self, edge: &mut dyn FnMut(Edge)) {
+        let procedures = [&self.type_test, &self.equality, &self.ordering, &self.hash];
+        for procedure in procedures.into_iter().flatten() {
+            edge(Edge::Object(procedure));
+        }
+    }
+}
+
+/// Where each kind of value comes in the default ordering, before the
+/// values of the same kind are compared with each other.
+fn rank(obj: &Object) -> Option<u8> {
+    Some(match obj {
+        Object::Nil => 0,
+        Object::Pair(_) => 1,
+        Object::Bool(_) => 2,
+        Object::Char(_) => 3,
+        Object::String(_) => 4,
+        Object::Symbol(_) => 5,
+        Object::Integer(_) | Object::BigInt(_) | Object::Rational(_) | Object::Float(_) => 6,
+        _ => return None,
+    })
+}
+
+fn default_compare(a: &Object, b: &Object) -> Result<Ordering, EvalError> {
+    let unordered = |obj: &Object| {
+        EvalError::new(format!(
+            "the default comparator cannot order a {}",
+            obj.type_name()
+        ))
+    };
+    let (rank_a, rank_b) = (
+        rank(a).ok_or_else(|| unordered(a))?,
+        rank(b).ok_or_else(|| unordered(b))?,
+    );
+    if rank_a != rank_b {
+        return Ok(rank_a.cmp(&rank_b));
+    }
+
+    Ok(match (a, b) {
+        (Object::Bool(a), Object::Bool(b)) => a.cmp(b),
+        (Object::Char(a), Object::Char(b)) => a.cmp(b),
+        (Object::String(a), Object::String(b)) => a.as_str().cmp(b.as_str()),
+        (Object::Symbol(a), Object::Symbol(b)) => a.as_str().cmp(b.as_str()),
+        // Lists compare element by element, a prefix first.
+        (Object::Pair(_), Object::Pair(_)) => {
+            let (mut a, mut b) = (a.clone(), b.clone());
+            loop {
+                match (&a, &b) {
+                    (Object::Pair(x), Object::Pair(y)) => {
+                        match default_compare(&x.car(), &y.car())? {
+                            Ordering::Equal => (a, b) = (x.cdr(), y.cdr()),
+                            ordering => return Ok(ordering),
+                        }
+                    }
+                    (a, b) => return default_compare(a, b),
+                }
+            }
+        }
+        (Object::Nil, Object::Nil) => Ordering::Equal,
+        _ => {
+            let less = |a: &Object, b: &Object| {
+                builtins::lookup("<").map(|lt| (lt.func)(&[a.clone(), b.clone()]))
+            };
+            match (less(a, b), less(b, a)) {
+                (Some(Ok(Object::Bool(true))), _) => Ordering::Less,
+                (_, Some(Ok(Object::Bool(true)))) => Ordering::Greater,
+                _ => Ordering::Equal,
+            }
+        }
+    })
+}
+
+/// A total order on any two values, for ordered printing: the default
+/// ordering where it applies, and otherwise by kind and printed form.
+pub(crate) fn print_order(a: &Object, b: &Object) -> Ordering {
+    default_compare(a, b).unwrap_or_else(|_| {
+        let key = |obj: &Object| {
+            (
+                rank(obj).unwrap_or(u8::MAX),
+                obj.type_name(),
+                obj.to_string(),
+            )
+        };
+        key(a).cmp(&key(b))
+    })
+}
+
+fn default_less(args: &[Object]) -> Result<Object, EvalError> {
+    match args {
+        [a, b] => Ok(Object::Bool(default_compare(a, b)? == Ordering::Less)),
+        _ => Err(EvalError::new("default ordering expects two arguments")),
+    }
+}
+
+fn comparator_arg<'a>(name: &str, obj: &'a Object) -> Result<&'a Rc<Comparator>, EvalError> {
+    match obj {
+        Object::Comparator(comparator) => Ok(comparator),
+        other => Err(EvalError::new(format!(
+            "{} expects a comparator, got {}",
+            name,
+            other.type_name()
+        ))),
+    }
+}
+
+pub(crate) fn is_procedure(obj: &Object) -> bool {
+    matches!(
+        obj,
+        Object::Lambda(_)
+            | Object::Builtin(_)
+            | Object::Native(_)
+            | Object::Memo(_)
+            | Object::Closure(_)
+    )
+}
+
+/// A comparator made from `obj`, which is either one already or a `less?`
+/// procedure.
+pub fn order_arg(name: &str, obj: &Object) -> Result<Rc<Comparator>, EvalError> {
+    match obj {
+        Object::Comparator(comparator) => Ok(comparator.clone()),
+        less if is_procedure(less) => Ok(Rc::new(Comparator::from_less(less.clone()))),
+        other => Err(EvalError::new(format!(
+            "{} expects a comparator or a less? procedure, got {}",
+            name, other
+        ))),
+    }
+}
+
+/// `(make-comparator type-test equality ordering hash)`.
+pub fn make_comparator(args: &[Object]) -> Result<Object, EvalError> {
+    let [type_test, equality, ordering, hash] = args else {
+        return Err(EvalError::new(
+            "make-comparator expects a type test, an equality, an ordering and a hash",
+        ));
+    };
+
+    // Each argument is a procedure, or a boolean standing for the default.
+    let procedure = |name: &str, obj: &Object, allowed: bool| match obj {
+        Object::Bool(b) if *b == allowed => Ok(None),
+        obj if is_procedure(obj) => Ok(Some(obj.clone())),
+        other => Err(EvalError::new(format!(
+            "make-comparator expects a procedure or {} as the {}, got {}",
+            if allowed { "#t" } else { "#f" },
+            name,
+            other
+        ))),
+    };
+
+    let comparator = Comparator {
+        type_test: procedure("type test", type_test, true)?,
+        equality: procedure("equality", equality, true)?,
+        ordering: procedure("ordering", ordering, false)?,
+        hash: procedure("hash", hash, false)?,
+    };
+    if comparator.equality.is_none() && comparator.ordering.is_none() {
+        return Err(EvalError::new(
+            "make-comparator needs an equality when there is no ordering",
+        ));
+    }
+
+    Ok(Object::Comparator(Rc::new(comparator)))
+}
+
+pub fn make_default_comparator(args: &[Object]) -> Result<Object, EvalError> {
+    if !args.is_empty() {
+        return Err(EvalError::new(
+            "make-default-comparator expects no arguments",
+        ));
+    }
+
+    Ok(Object::Comparator(
+        Rc::new(Comparator::default_comparator()),
+    ))
+}
+
+pub fn is_comparator(args: &[Object]) -> Result<Object, EvalError> {
+    match args {
+        [obj] => Ok(Object::Bool(matches!(obj, Object::Comparator(_)))),
+        _ => Err(EvalError::new("comparator? expects one argument")),
+    }
+}
+
+pub fn is_ordered(args: &[Object]) -> Result<Object, EvalError> {
+    match args {
+        [obj] => Ok(Object::Bool(
+            comparator_arg("comparator-ordered?", obj)?.is_ordered(),
+        )),
+        _ => Err(EvalError::new("comparator-ordered? expects a comparator")),
+    }
+}
+
+pub fn is_hashable(args: &[Object]) -> Result<Object, EvalError> {
+    match args {
+        [obj] => Ok(Object::Bool(
+            comparator_arg("comparator-hashable?", obj)?.is_hashable(),
+        )),
+        _ => Err(EvalError::new("comparator-hashable? expects a comparator")),
+    }
+}
+
+pub fn comparator_hash(args: &[Object]) -> Result<Object, EvalError> {
+    match args {
+        [comparator, obj] => comparator_arg("comparator-hash", comparator)?.hash(obj),
+        _ => Err(EvalError::new(
+            "comparator-hash expects a comparator and a value",
+        )),
+    }
+}
+
+/// Checks that `holds` is true of every adjacent pair of values, as
+/// `(<? comparator a b c ...)` does.
+fn chain(
+    name: &str,
+    args: &[Object],
+    holds: fn(&Comparator, &Object, &Object) -> Result<bool, EvalError>,
+) -> Result<Object, EvalError> {
+    let (comparator, values) = match args {
+        [comparator, values @ ..] if values.len() >= 2 => {
+            (comparator_arg(name, comparator)?, values)
+        }
+        _ => {
+            return Err(EvalError::new(format!(
+                "{} expects a comparator and at least two values",
+                name
+            )))
+        }
+    };
+
+    for pair in values.windows(2) {
+        if !holds(comparator, &pair[0], &pair[1])? {
+            return Ok(Object::Bool(false));
+        }
+    }
+
+    Ok(Object::Bool(true))
+}
+
+pub fn equal(args: &[Object]) -> Result<Object, EvalError> {
+    chain("=?", args, |c, a, b| c.equal(a, b))
+}
+
+pub fn less(args: &[Object]) -> Result<Object, EvalError> {
+    chain("<?", args, |c, a, b| c.less(a, b))
+}
+
+pub fn greater(args: &[Object]) -> Result<Object, EvalError> {
+    chain(">?", args, |c, a, b| c.less(b, a))
+}
+
+pub fn less_or_equal(args: &[Object]) -> Result<Object, EvalError> {
+    chain("<=?", args, |c, a, b| Ok(!c.less(b, a)?))
+}
+
+pub fn greater_or_equal(args: &[Object]) -> Result<Object, EvalError> {
+    chain(">=?", args, |c, a, b| Ok(!c.less(a, b)?))
+}
+
+/// `(sort list)` with the default comparator, or `(sort list order)` with a
+/// comparator or a `less?` procedure. The sort is stable.
+pub fn sort(args: &[Object]) -> Result<Object, EvalError> {
+    let (list, comparator) = match args {
+        [list] => (list, Rc::new(Comparator::default_comparator())),
+        [list, order] => (list, order_arg("sort", order)?),
+        _ => return Err(EvalError::new("sort expects a list and an optional order")),
+    };
+    let items = list
+        .to_vec()
+        .ok_or_else(|| EvalError::new(format!("sort expects a list, got {}", list)))?;
+
+    merge_sort(items, &comparator).map(Object::list)
+}
+
+/// `(deque-sort! deque)` or `(deque-sort! deque order)` sorts a deque in
+/// place, as `sort` sorts a list.
+pub fn deque_sort(args: &[Object]) -> Result<Object, EvalError> {
+    let (deque, comparator) = match args {
+        [Object::Deque(deque)] => (deque, Rc::new(Comparator::default_comparator())),
+        [Object::Deque(deque), order] => (deque, order_arg("deque-sort!", order)?),
+        _ => {
+            return Err(EvalError::new(
+                "deque-sort! expects a deque and an optional order",
+            ))
+        }
+    };
+
+    // The deque is not borrowed while the comparator runs, which may look
+    // at it; what it is left holding is replaced by the sorted items.
+    let items = deque.borrow().iter().cloned().collect();
+    let sorted = merge_sort(items, &comparator)?;
+    *deque.borrow_mut() = sorted.into();
+
+    Ok(Object::Void)
+}
+
+/// A merge sort, since the standard library's sorts cannot stop at an error
+/// raised by the comparator.
+fn merge_sort(mut items: Vec<Object>, comparator: &Comparator) -> Result<Vec<Object>, EvalError> {
+    if items.len() < 2 {
+        return Ok(items);
+    }
+
+    let right = items.split_off(items.len() / 2);
+    let left = merge_sort(items, comparator)?;
+    let right = merge_sort(right, comparator)?;
+
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    let mut left = left.into_iter().peekable();
+    let mut right = right.into_iter().peekable();
+    while let (Some(a), Some(b)) = (left.peek(), right.peek()) {
+        // Taking from the left unless the right is strictly less keeps
+        // equal elements in their original order.
+        if comparator.less(b, a)? {
+            merged.extend(right.next());
+        } else {
+            merged.extend(left.next());
+        }
+    }
+    merged.extend(left);
+    merged.extend(right);
+
+    Ok(merged)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builtins::global_env;
+    use crate::eval::eval_str;
+
+    fn eval(program: &str) -> String {
+        eval_str(program, &global_env()).unwrap().to_string()
+    }
+
+    #[test]
+    fn test_default_comparator() {
+        assert_eq!(
+            eval("(sort '(3 \"b\" #\\a 1/2 (1 2) b \"a\" (1) () 2.5 #t))"),
+            "(() (1) (1 2) #t #\\a \"a\" \"b\" b 1/2 2.5 3)"
+        );
+        assert_eq!(
+            eval(
+                "(define c (make-default-comparator))
+                 (list (<? c 1 2 3) (<? c 1 3 2) (=? c '(1 \"a\") (list 1 \"a\"))
+                       (>=? c \"b\" \"b\" \"a\") (= (comparator-hash c '(1)) (hash '(1))))"
+            ),
+            "(#t #f #t #t #t)"
+        );
+        assert!(eval_str("(sort (list car cdr))", &global_env()).is_err());
+    }
+
+    #[test]
+    fn test_user_comparators() {
+        // Pairs of a name and a positive age, ordered by age.
+        let people = "(define by-age
+                        (make-comparator (lambda (p) (> (cdr p) 0))
+                                         #t
+                                         (lambda (a b) (< (cdr a) (cdr b)))
+                                         #f))
+                      (define people '((ana . 31) (bo . 25) (cy . 31) (di . 19)))";
+        assert_eq!(
+            eval(&format!("{} (sort people by-age)", people)),
+            "((di . 19) (bo . 25) (ana . 31) (cy . 31))"
+        );
+        assert_eq!(
+            eval(&format!(
+                "{} (list (=? by-age '(ana . 31) '(cy . 31)) (comparator-hashable? by-age))",
+                people
+            )),
+            "(#t #f)"
+        );
+        assert!(eval_str(
+            &format!("{} (<? by-age '(ed . -1) '(bo . 25))", people),
+            &global_env()
+        )
+        .is_err());
+
+        assert_eq!(eval("(sort '(1 3 2) >)"), "(3 2 1)");
+    }
+
+    #[test]
+    fn test_deque_sort() {
+        assert_eq!(
+            eval(
+                "(define d (make-deque))
+                 (for-each (lambda (p) (deque-push-back! d p))
+                           '((ana . 31) (bo . 25) (cy . 31) (di . 19)))
+                 (deque-sort! d (lambda (a b) (< (cdr a) (cdr b))))
+                 (list (deque-pop-front! d) (deque-pop-front! d)
+                       (deque-pop-front! d) (deque-pop-front! d))"
+            ),
+            "((di . 19) (bo . 25) (ana . 31) (cy . 31))"
+        );
+        assert_eq!(
+            eval(
+                "(define d (make-deque))
+                 (deque-push-back! d \"b\") (deque-push-back! d \"a\")
+                 (deque-sort! d)
+                 (deque-pop-front! d)"
+            ),
+            "\"a\""
+        );
+        assert!(eval_str("(deque-sort! '(2 1))", &global_env()).is_err());
+    }
+}
@@ -0,0 +1,1465 @@
+//! Lowers parsed forms to bytecode for the `vm`.
+//!
+//! The compiler handles the core special forms (`quote`, `if`, `define`,
+//! `set!`, `lambda`, `begin` and `let`) and procedure calls. Variables bound
+//! by `lambda` and `let` are resolved at compile time, so the VM never looks
+//! them up by name; every other variable is global. Forms using anything
+//! else, such as `load` or `pipe`, are left to the tree-walking evaluator.
+//!
+//! Variables live in slots on the VM stack. A closure captures only the
+//! variables its code refers to, not the frames they are in, so that it
+//! keeps nothing else alive: it copies their values when it is made, or,
+//! for variables assigned with `set!` or `define`, shares a cell holding
+//! the variable with the code that made it.
+//!
+//! Calls of the integer arithmetic and comparison builtins with two
+//! arguments get an instruction of their own, which the VM runs without
+//! a call when both arguments are integers and the global still holds the
+//! builtin, and as the call it stands for otherwise.
+//!
+//! Procedures that cannot escape get no closure. A `lambda` applied where
+//! it is made, or bound by `let` to a variable the body only calls, is
+//! compiled in place of its calls, like a `let`: its variables take slots
+//! of the caller's frame and nothing is allocated for it. A procedure bound
+//! by `let` is compiled in place only where the variables it refers to mean
+//! what they did where it was made, and if its calls do not add up to more
+//! than `INLINE_BUDGET` forms.
+//!
+//! A program compiled as a whole also has the calls of its small global
+//! procedures, and of those it declares with `(declare (inline name...))`,
+//! compiled in place. Nothing tells at compile time which calls are hot, so
+//! size stands in for it. The code checks that the global still holds the
+//! procedure as defined, and calls whatever it holds otherwise, so that
+//! redefining a procedure still takes effect.
+//!
+//! A literal repeated in a chunk takes one slot of its constant pool, and
+//! the string literals of a program share one copy of each text at runtime.
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+use crate::eval::EvalError;
+use crate::macros;
+use crate::module;
+use crate::object::Object;
+use crate::symbol::Symbol;
+
+/// Special forms only the tree-walking evaluator knows about. A top-level
+/// form that uses one anywhere is not compiled.
+const INTERPRETED_FORMS: &[&str] = &[
+    "define-memoized",
+    "embed-file",
+    "load",
+    "require",
+    "provide",
+    "with-task-scope",
+    "pipe",
+    "trace",
+    "untrace",
+    "profile",
+    "quasiquote",
+    "sql",
+    "define-enum",
+    "case",
+    "defstruct",
+    "define-record-type",
+    "with-retry",
+    "guard",
+    "match",
+    "assert",
+    "assert-equal",
+    "deftest",
+    "run-tests",
+    "time",
+    "the-environment",
+    "let-values",
+    "delay",
+    "cons-stream",
+    "defmacro",
+    "macroexpand",
+    "macroexpand-1",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    /// Pushes `constants[i]`.
+    Const(usize),
+    /// Pushes stack slot `i` of the running chunk.
+    Slot(usize),
+    SetSlot(usize),
+    /// Pushes the value in cell `i` of the running chunk.
+    Cell(usize),
+    SetCell(usize),
+    /// Pushes the value the running closure captured as `free[i]`.
+    Free(usize),
+    /// Pushes the value in the cell the running closure captured as
+    /// `free_cells[i]`.
+    FreeCell(usize),
+    SetFreeCell(usize),
+    /// Pushes the value of global `symbols[i]`.
+    Global(usize),
+    SetGlobal(usize),
+    DefineGlobal(usize),
+    Pop,
+    Jump(usize),
+    JumpIfFalse(usize),
+    /// Pushes a closure over `chunks[i]`, capturing what its `free` and
+    /// `free_cells` list.
+    Closure(usize),
+    /// Calls the procedure below the top `n` values with them as arguments.
+    Call(usize),
+    /// Like `Call` followed by `Return`, without growing the call stack.
+    TailCall(usize),
+    Return,
+    /// Runs the code that follows, which a call of global `symbols[symbol]`
+    /// with the top `argc` values was compiled to, if the global is a
+    /// closure over `chunks[chunk]`. Otherwise puts the global below the
+    /// arguments and goes on at `fallback`, where the call is.
+    Inline {
+        symbol: usize,
+        chunk: usize,
+        argc: usize,
+        fallback: usize,
+    },
+    /// Applies `op` to the top two values, or calls global `symbols[symbol]`
+    /// with them, as a tail call if `tail`, when that is not the builtin or
+    /// they are not both integers.
+    Arith {
+        op: Arith,
+        symbol: usize,
+        tail: bool,
+    },
+}
+
+/// The builtins with an instruction of their own.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Arith {
+    Add,
+    Sub,
+    Mul,
+    Eq,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+}
+
+impl Arith {
+    pub const ALL: [Arith; 8] = [
+        Arith::Add,
+        Arith::Sub,
+        Arith::Mul,
+        Arith::Eq,
+        Arith::Lt,
+        Arith::Gt,
+        Arith::Le,
+        Arith::Ge,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Arith::Add => "+",
+            Arith::Sub => "-",
+            Arith::Mul => "*",
+            Arith::Eq => "=",
+            Arith::Lt => "<",
+            Arith::Gt => ">",
+            Arith::Le => "<=",
+            Arith::Ge => ">=",
+        }
+    }
+
+    /// The result for two integers, or `None` if it does not fit in one.
+    pub fn apply(self, a: i64, b: i64) -> Option<Object> {
+        Some(match self {
+            Arith::Add => Object::Integer(a.checked_add(b)?),
+            Arith::Sub => Object::Integer(a.checked_sub(b)?),
+            Arith::Mul => Object::Integer(a.checked_mul(b)?),
+            Arith::Eq => Object::Bool(a == b),
+            Arith::Lt => Object::Bool(a < b),
+            Arith::Gt => Object::Bool(a > b),
+            Arith::Le => Object::Bool(a <= b),
+            Arith::Ge => Object::Bool(a >= b),
+        })
+    }
+}
+
+/// Where a closure gets a variable it captures, in the code making it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Capture {
+    /// Slot `i`, or for a cell, cell `i`, of the running chunk.
+    Local(usize),
+    /// What the running closure captured as `free[i]`, or for a cell,
+    /// `free_cells[i]`.
+    Free(usize),
+}
+
+/// A compiled procedure body, or a top-level form taking no arguments.
+#[derive(Debug, Default)]
+pub struct Chunk {
+    /// The number of required parameters, stored in the first slots.
+    pub params: usize,
+    /// Whether the arguments past `params` are collected into a list in the
+    /// next slot.
+    pub rest: bool,
+    /// The number of slots the variables take, including internal
+    /// definitions and `let` bindings.
+    pub slots: usize,
+    /// The number of cells, made when the chunk is entered, for the
+    /// variables closures capture that are assigned.
+    pub cells: usize,
+    /// The values a closure over the chunk captures.
+    pub free: Vec<Capture>,
+    /// The cells it shares with the code making it.
+    pub free_cells: Vec<Capture>,
+    pub code: Vec<Op>,
+    pub constants: Vec<Object>,
+    pub symbols: Vec<Symbol>,
+    pub chunks: Vec<Rc<Chunk>>,
+    /// The last value looked up for each symbol, and the environment
+    /// generation it was read in.
+    pub(crate) cache: RefCell<Vec<Option<(u64, Object)>>>,
+}
+
+/// Compiles a top-level form, or returns `None` if it has to be left to the
+/// tree-walking evaluator. Malformed forms are left to it too, so that they
+/// fail with the same errors, when and if they are evaluated.
+pub fn compile(form: &Object) -> Option<Chunk> {
+    compile_sharing(form, &mut Literals::default(), &HashMap::new())
+}
+
+fn compile_sharing(
+    form: &Object,
+    literals: &mut Literals,
+    inline: &HashMap<Symbol, Rc<Inline>>,
+) -> Option<Chunk> {
+    let mut compiler = Compiler {
+        chunk: Chunk::default(),
+        enclosing: Vec::new(),
+        scopes: Vec::new(),
+        literals,
+        inline,
+        inlining: Vec::new(),
+    };
+    compiler.expr(form, true)?;
+    compiler.finish_chunk();
+
+    Some(compiler.chunk)
+}
+
+/// Replaces every `(embed-file "path")` in `form`, outside of quoted data,
+/// with the contents of the file, so that a program compiled ahead of time
+/// carries its files with it.
+pub fn embed_files(form: &Object) -> Result<Object, EvalError> {
+    let list = match form.to_vec() {
+        Some(list) if !list.is_empty() => list,
+        _ => return Ok(form.clone()),
+    };
+
+    match &list[0] {
+        Object::Symbol(head) if *head == "quote" => Ok(form.clone()),
+        Object::Symbol(head) if *head == "embed-file" => module::embed_file(form, &list),
+        _ => Ok(Object::list(
+            list.iter()
+                .map(embed_files)
+                .collect::<Result<Vec<_>, _>>()?,
+        )),
+    }
+}
+
+/// A top-level form of a program, compiled ahead of time if it can be.
+pub enum TopLevel {
+    Compiled(Rc<Chunk>),
+    Interpreted(Object),
+}
+
+/// Compiles each of a program's forms, keeping those that `compile` leaves
+/// to the tree-walking evaluator as they are, as well as those using the
+/// macros the program defines. Calls of the procedures defined before them
+/// that are small or declared inline are compiled in place.
+pub fn compile_program(forms: Vec<Object>) -> Vec<TopLevel> {
+    let macros = macros::defined(&forms);
+    let declared = declared_inline(&forms);
+    let mut literals = Literals::default();
+    let mut inline = HashMap::new();
+
+    forms
+        .into_iter()
+        .map(|form| {
+            let chunk = match macros::uses(&form, |name| macros.contains(&name)) {
+                true => None,
+                false => compile_sharing(&form, &mut literals, &inline),
+            };
+            let Some(chunk) = chunk.map(Rc::new) else {
+                if let Some(name) = defined_name(&form) {
+                    inline.remove(&name);
+                }
+                return TopLevel::Interpreted(form);
+            };
+
+            if let Some(name) = defined_name(&form) {
+                match Inline::of(&form, &chunk, declared.contains(&name)) {
+                    Some(procedure) => inline.insert(name, Rc::new(procedure)),
+                    None => inline.remove(&name),
+                };
+            }
+            TopLevel::Compiled(chunk)
+        })
+        .collect()
+}
+
+/// The largest body, in atoms and lists, of a global procedure compiled in
+/// place of its calls without being declared inline.
+const SMALL_PROCEDURE: usize = 16;
+
+/// How deep calls compiled in place of calls in procedures compiled in
+/// place go.
+const INLINE_DEPTH: usize = 4;
+
+/// A global procedure whose calls are compiled in place.
+struct Inline {
+    params: Vec<Symbol>,
+    body: Vec<Object>,
+    /// The names the body refers to besides its parameters, which have to
+    /// be global where it is compiled in place.
+    free: HashSet<Symbol>,
+    /// The chunk of the procedure, which the code checks the global is a
+    /// closure over.
+    chunk: Rc<Chunk>,
+}
+
+impl Inline {
+    /// The procedure `form`, compiled into `compiled`, defines, if it is
+    /// one whose calls can be compiled in place and is small or `declared`.
+    fn of(form: &Object, compiled: &Chunk, declared: bool) -> Option<Inline> {
+        let list = form.to_vec()?;
+        let [_, Object::Pair(signature), body @ ..] = list.as_slice() else {
+            return None;
+        };
+        let params = fixed_params(&signature.cdr(), body)?;
+        let body = body.to_vec();
+
+        let mut free = HashSet::new();
+        for form in &body {
+            symbols(form, &mut free);
+        }
+        // A definition nested in the body would be global in place of a
+        // call at top level.
+        if free.contains(&Symbol::intern("define")) {
+            return None;
+        }
+        free.retain(|name| !params.contains(name));
+        if !declared && body.iter().map(size).sum::<usize>() > SMALL_PROCEDURE {
+            return None;
+        }
+
+        Some(Inline {
+            params,
+            body,
+            free,
+            chunk: compiled.chunks.last()?.clone(),
+        })
+    }
+}
+
+/// The names a program declares with `(declare (inline name...))`.
+fn declared_inline(forms: &[Object]) -> HashSet<Symbol> {
+    let mut declared = HashSet::new();
+    for form in forms {
+        let Some(list) = form.to_vec() else {
+            continue;
+        };
+        let [Object::Symbol(head), declarations @ ..] = list.as_slice() else {
+            continue;
+        };
+        if *head != "declare" {
+            continue;
+        }
+        for declaration in declarations.iter().filter_map(Object::to_vec) {
+            if let [Object::Symbol(kind), names @ ..] = declaration.as_slice() {
+                if *kind == "inline" {
+                    declared.extend(names.iter().filter_map(|name| match name {
+                        Object::Symbol(name) => Some(*name),
+                        _ => None,
+                    }));
+                }
+            }
+        }
+    }
+
+    declared
+}
+
+/// The string literals of the chunks compiled so far, by their text.
+#[derive(Default)]
+pub(crate) struct Literals(HashMap<String, Object>);
+
+impl Literals {
+    /// `obj`, or the string with the same text met before.
+    pub(crate) fn share(&mut self, obj: Object) -> Object {
+        match &obj {
+            Object::String(s) if !s.is_tainted() => {
+                self.0.entry(s.to_string()).or_insert(obj).clone()
+            }
+            _ => obj,
+        }
+    }
+}
+
+/// Whether two constants can share a slot. Unlike `equal?`, this tells
+/// `0.0` from `-0.0` and never merges lists, which are mutable.
+fn same_literal(a: &Object, b: &Object) -> bool {
+    match (a, b) {
+        (Object::Float(a), Object::Float(b)) => a.to_bits() == b.to_bits(),
+        (Object::String(a), Object::String(b)) => a == b && a.is_tainted() == b.is_tainted(),
+        (
+            Object::Void
+            | Object::Nil
+            | Object::Bool(_)
+            | Object::Integer(_)
+            | Object::BigInt(_)
+            | Object::Rational(_)
+            | Object::Char(_)
+            | Object::Symbol(_),
+            _,
+        ) => a == b,
+        _ => false,
+    }
+}
+
+/// The most forms compiled in place of the calls of one procedure bound by
+/// `let`, above which it gets a closure unless it is called once.
+const INLINE_BUDGET: usize = 200;
+
+struct Compiler<'a> {
+    chunk: Chunk,
+    /// The chunks enclosing the one being compiled, outermost first.
+    enclosing: Vec<Chunk>,
+    /// The scopes of the chunk being compiled, innermost last. Scopes of the
+    /// enclosing chunks come first.
+    scopes: Vec<Scope>,
+    literals: &'a mut Literals,
+    /// The global procedures compiled in place of their calls.
+    inline: &'a HashMap<Symbol, Rc<Inline>>,
+    /// The global procedures being compiled in place, innermost last.
+    inlining: Vec<Symbol>,
+}
+
+struct Scope {
+    /// The names bound and where each one is.
+    names: Vec<(Symbol, Access)>,
+    /// The number of chunks enclosing the one the scope is in.
+    level: usize,
+    /// The names the procedures made in the scope refer to.
+    captured: HashSet<Symbol>,
+    /// The procedures bound that are compiled in place of their calls.
+    procedures: Vec<(Symbol, Rc<Procedure>)>,
+}
+
+/// A procedure bound by `let` that does not escape it.
+struct Procedure {
+    params: Vec<Symbol>,
+    body: Vec<Object>,
+    /// The names the body refers to besides its parameters, including the
+    /// ones the procedures it calls refer to.
+    free: HashSet<Symbol>,
+}
+
+impl Scope {
+    fn get(&self, name: Symbol) -> Option<Access> {
+        self.names
+            .iter()
+            .find(|(bound, _)| *bound == name)
+            .map(|&(_, access)| access)
+    }
+}
+
+/// Where the running code finds a variable.
+#[derive(Debug, Clone, Copy)]
+enum Access {
+    Slot(usize),
+    Cell(usize),
+    Free(usize),
+    FreeCell(usize),
+}
+
+impl Access {
+    fn load(self) -> Op {
+        match self {
+            Access::Slot(i) => Op::Slot(i),
+            Access::Cell(i) => Op::Cell(i),
+            Access::Free(i) => Op::Free(i),
+            Access::FreeCell(i) => Op::FreeCell(i),
+        }
+    }
+
+    /// The op assigning the variable. Captured values are never assigned:
+    /// variables that are get cells.
+    fn store(self) -> Option<Op> {
+        match self {
+            Access::Slot(i) => Some(Op::SetSlot(i)),
+            Access::Cell(i) => Some(Op::SetCell(i)),
+            Access::Free(_) => None,
+            Access::FreeCell(i) => Some(Op::SetFreeCell(i)),
+        }
+    }
+}
+
+/// What the variables of a scope are used for in its body, telling which
+/// ones need cells. Names are not told apart from the variables they may
+/// shadow, which can only give a variable a cell it does not need.
+#[derive(Default)]
+struct Usage {
+    /// The names the procedures made in the body refer to.
+    captured: HashSet<Symbol>,
+    /// The names assigned with `set!` or `define`.
+    assigned: HashSet<Symbol>,
+}
+
+impl Usage {
+    fn of(forms: &[Object]) -> Usage {
+        let mut usage = Usage::default();
+        for form in forms {
+            usage.walk(form, false);
+        }
+
+        usage
+    }
+
+    fn walk(&mut self, form: &Object, in_procedure: bool) {
+        let Object::Pair(pair) = form else {
+            if let (Object::Symbol(name), true) = (form, in_procedure) {
+                self.captured.insert(*name);
+            }
+            return;
+        };
+
+        let mut in_procedure = in_procedure;
+        if let Object::Symbol(head) = pair.car() {
+            let target = match pair.cdr() {
+                Object::Pair(rest) => rest.car(),
+                _ => Object::Nil,
+            };
+            match (head.form_name().unwrap_or_default(), target) {
+                ("quote", _) => return,
+                ("lambda", _) => in_procedure = true,
+                ("define", Object::Pair(signature)) => {
+                    in_procedure = true;
+                    if let Object::Symbol(name) = signature.car() {
+                        self.assigned.insert(name);
+                    }
+                }
+                ("set!" | "define", Object::Symbol(name)) => {
+                    self.assigned.insert(name);
+                }
+                _ => {}
+            }
+        }
+
+        let mut current = form.clone();
+        while let Object::Pair(pair) = current {
+            self.walk(&pair.car(), in_procedure);
+            current = pair.cdr();
+        }
+        self.walk(&current, in_procedure);
+    }
+}
+
+/// Looks for the uses of a procedure bound by `let` in the body, to tell
+/// whether each one can be compiled in place: whether they are all calls
+/// with the right number of arguments, where the names the procedure refers
+/// to are not bound by the `let` or any form around the call.
+struct Calls<'a> {
+    name: Symbol,
+    arity: usize,
+    free: &'a HashSet<Symbol>,
+    /// The names the `let` binds, which the procedure does not see.
+    outer: &'a HashSet<Symbol>,
+    count: usize,
+}
+
+impl Calls<'_> {
+    /// Walks `form`, found inside forms binding `bound`. Returns false on a
+    /// use of the procedure that cannot be compiled in place.
+    fn walk(&mut self, form: &Object, bound: &HashSet<Symbol>) -> bool {
+        let list = match form {
+            Object::Symbol(name) => return *name != self.name || bound.contains(name),
+            Object::Pair(_) => match form.to_vec() {
+                Some(list) => list,
+                None => return false,
+            },
+            _ => return true,
+        };
+        let visible = !bound.contains(&self.name);
+
+        match list.as_slice() {
+            [Object::Symbol(head), ..] if *head == "quote" => true,
+            [Object::Symbol(head), params, body @ ..] if *head == "lambda" => {
+                self.procedure(params, body, bound)
+            }
+            [Object::Symbol(head), Object::Pair(signature), body @ ..] if *head == "define" => {
+                let Object::Symbol(name) = signature.car() else {
+                    return false;
+                };
+                (name != self.name || !visible)
+                    && !self.free.contains(&name)
+                    && self.procedure(&signature.cdr(), body, bound)
+            }
+            [Object::Symbol(head), Object::Symbol(target), rest @ ..]
+                if *head == "define" || *head == "set!" =>
+            {
+                (*target != self.name || !visible)
+                    && (*head == "set!" || !self.free.contains(target))
+                    && rest.iter().all(|form| self.walk(form, bound))
+            }
+            [Object::Symbol(head), bindings, body @ ..] if *head == "let" => {
+                let Some(bindings) = bindings.to_vec() else {
+                    return false;
+                };
+                let mut inner = bound.clone();
+                for binding in bindings {
+                    match binding.to_vec().as_deref() {
+                        Some([Object::Symbol(name), value]) if self.walk(value, bound) => {
+                            inner.insert(*name);
+                        }
+                        _ => return false,
+                    }
+                }
+                body.iter().all(|form| self.walk(form, &inner))
+            }
+            [Object::Symbol(head), args @ ..] if *head == self.name && visible => {
+                let hidden = |name| bound.contains(name) || self.outer.contains(name);
+                if args.len() != self.arity || self.free.iter().any(hidden) {
+                    return false;
+                }
+                self.count += 1;
+                args.iter().all(|form| self.walk(form, bound))
+            }
+            _ => list.iter().all(|form| self.walk(form, bound)),
+        }
+    }
+
+    fn procedure(&mut self, params: &Object, body: &[Object], bound: &HashSet<Symbol>) -> bool {
+        let mut bound = bound.clone();
+        symbols(params, &mut bound);
+        bound.extend(body.iter().filter_map(defined_name));
+        body.iter().all(|form| self.walk(form, &bound))
+    }
+}
+
+impl Compiler<'_> {
+    fn emit(&mut self, op: Op) -> usize {
+        self.chunk.code.push(op);
+        self.chunk.code.len() - 1
+    }
+
+    fn constant(&mut self, obj: Object) {
+        let constants = &mut self.chunk.constants;
+        let index = match constants.iter().position(|c| same_literal(c, &obj)) {
+            Some(index) => index,
+            None => {
+                constants.push(self.literals.share(obj));
+                constants.len() - 1
+            }
+        };
+        self.emit(Op::Const(index));
+    }
+
+    fn symbol(&mut self, name: Symbol) -> usize {
+        match self.chunk.symbols.iter().position(|&s| s == name) {
+            Some(index) => index,
+            None => {
+                self.chunk.symbols.push(name);
+                self.chunk.symbols.len() - 1
+            }
+        }
+    }
+
+    /// Finds where the running code gets `name`, if it is a variable of a
+    /// scope, making every closure between that scope and the code capture
+    /// it.
+    fn resolve(&mut self, name: Symbol) -> Option<Access> {
+        let (level, mut access) = self
+            .scopes
+            .iter()
+            .rev()
+            .find_map(|scope| Some((scope.level, scope.get(name)?)))?;
+
+        for level in level + 1..=self.enclosing.len() {
+            let chunk = match self.enclosing.get_mut(level) {
+                Some(chunk) => chunk,
+                None => &mut self.chunk,
+            };
+            let (list, capture) = match access {
+                Access::Slot(i) => (&mut chunk.free, Capture::Local(i)),
+                Access::Free(i) => (&mut chunk.free, Capture::Free(i)),
+                Access::Cell(i) => (&mut chunk.free_cells, Capture::Local(i)),
+                Access::FreeCell(i) => (&mut chunk.free_cells, Capture::Free(i)),
+            };
+            let index = match list.iter().position(|&c| c == capture) {
+                Some(index) => index,
+                None => {
+                    list.push(capture);
+                    list.len() - 1
+                }
+            };
+            access = match access {
+                Access::Slot(_) | Access::Free(_) => Access::Free(index),
+                Access::Cell(_) | Access::FreeCell(_) => Access::FreeCell(index),
+            };
+        }
+
+        Some(access)
+    }
+
+    /// Finds the procedure compiled in place of calls of `name`, if that is
+    /// what `name` is bound to.
+    fn procedure(&self, name: Symbol) -> Option<Rc<Procedure>> {
+        for scope in self.scopes.iter().rev() {
+            if scope.get(name).is_some() {
+                return None;
+            }
+            if let Some((_, procedure)) = scope.procedures.iter().find(|(n, _)| *n == name) {
+                return Some(procedure.clone());
+            }
+        }
+
+        None
+    }
+
+    /// Whether `name` is bound by a scope, as a variable or a procedure.
+    fn is_local(&self, name: Symbol) -> bool {
+        self.scopes.iter().any(|scope| {
+            scope.get(name).is_some() || scope.procedures.iter().any(|(n, _)| *n == name)
+        })
+    }
+
+    /// The global procedure to compile in place of a call of `name` with
+    /// `argc` arguments, if there is one and it means the same here.
+    fn inline_global(&self, name: Symbol, argc: usize) -> Option<Rc<Inline>> {
+        let inline = self.inline.get(&name)?;
+        let here = inline.params.len() == argc
+            && self.inlining.len() < INLINE_DEPTH
+            && !self.inlining.contains(&name)
+            && !self.is_local(name)
+            && !inline.free.iter().any(|&free| self.is_local(free));
+
+        here.then(|| inline.clone())
+    }
+
+    /// Tells whether `value`, bound to `name` by a `let` binding `outer`,
+    /// is a procedure that can be compiled in place of its calls in `body`.
+    fn local_procedure(
+        &self,
+        name: Symbol,
+        value: &Object,
+        body: &[Object],
+        outer: &HashSet<Symbol>,
+    ) -> Option<Procedure> {
+        let special = [
+            "quote", "if", "define", "set!", "lambda", "begin", "let", "declare",
+        ];
+        if special.contains(&name.as_str()) || INTERPRETED_FORMS.contains(&name.as_str()) {
+            return None;
+        }
+        let (params, procedure_body) = inlinable(value)?;
+
+        let mut mentioned = HashSet::new();
+        for form in &procedure_body {
+            symbols(form, &mut mentioned);
+        }
+        let mut free = HashSet::new();
+        for name in mentioned.into_iter().filter(|name| !params.contains(name)) {
+            if let Some(procedure) = self.procedure(name) {
+                free.extend(procedure.free.iter().copied());
+            }
+            free.insert(name);
+        }
+
+        let mut calls = Calls {
+            name,
+            arity: params.len(),
+            free: &free,
+            outer,
+            count: 0,
+        };
+        let bound = HashSet::new();
+        if !body.iter().all(|form| calls.walk(form, &bound)) {
+            return None;
+        }
+        let size: usize = procedure_body.iter().map(size).sum();
+        if calls.count > 1 && calls.count * size > INLINE_BUDGET {
+            return None;
+        }
+
+        Some(Procedure {
+            params,
+            body: procedure_body,
+            free,
+        })
+    }
+
+    fn slot(&mut self) -> usize {
+        self.chunk.slots += 1;
+        self.chunk.slots - 1
+    }
+
+    fn cell(&mut self) -> usize {
+        self.chunk.cells += 1;
+        self.chunk.cells - 1
+    }
+
+    /// Creates a scope for `names`, bound for `body`. The first `args` of
+    /// them are arguments, which the caller leaves in the first slots.
+    fn scope(&mut self, names: Vec<Symbol>, body: &[Object], args: usize) -> Scope {
+        let usage = Usage::of(body);
+        let mut scope = Scope {
+            names: Vec::new(),
+            level: self.enclosing.len(),
+            captured: usage.captured,
+            procedures: Vec::new(),
+        };
+
+        for (i, name) in names.into_iter().enumerate() {
+            let slot = (i < args).then(|| self.slot());
+            let access = match scope.captured.contains(&name) && usage.assigned.contains(&name) {
+                true => Access::Cell(self.cell()),
+                false => Access::Slot(slot.unwrap_or_else(|| self.slot())),
+            };
+            scope.names.push((name, access));
+        }
+
+        scope
+    }
+
+    /// Binds `name` in the innermost scope, unless it is bound there
+    /// already, and returns the op storing into it.
+    fn bind(&mut self, name: Symbol) -> Option<Op> {
+        let scope = self.scopes.last()?;
+        if let Some(access) = scope.get(name) {
+            return access.store();
+        }
+
+        let access = match scope.captured.contains(&name) {
+            true => Access::Cell(self.cell()),
+            false => Access::Slot(self.slot()),
+        };
+        self.scopes.last_mut()?.names.push((name, access));
+
+        access.store()
+    }
+
+    fn finish_chunk(&mut self) {
+        self.chunk.cache = RefCell::new(vec![None; self.chunk.symbols.len()]);
+    }
+
+    /// Ends the code for an expression in tail position.
+    fn finish(&mut self, tail: bool) {
+        if tail {
+            self.emit(Op::Return);
+        }
+    }
+
+    /// Compiles `expr`. In tail position the code returns from the chunk;
+    /// otherwise it leaves the value on the stack.
+    fn expr(&mut self, expr: &Object, tail: bool) -> Option<()> {
+        let list = match expr {
+            Object::Symbol(name) if name.starts_with("#:") => {
+                self.constant(expr.clone());
+                self.finish(tail);
+                return Some(());
+            }
+            Object::Symbol(name) if self.procedure(*name).is_some() => return None,
+            Object::Symbol(name) => {
+                match self.resolve(*name) {
+                    Some(access) => self.emit(access.load()),
+                    None => {
+                        let index = self.symbol(*name);
+                        self.emit(Op::Global(index))
+                    }
+                };
+                self.finish(tail);
+                return Some(());
+            }
+            Object::Pair(_) => expr.to_vec()?,
+            _ => {
+                self.constant(expr.clone());
+                self.finish(tail);
+                return Some(());
+            }
+        };
+
+        if let Object::Symbol(head) = &list[0] {
+            let form = head.form_name().unwrap_or_default();
+            if INTERPRETED_FORMS.contains(&form) {
+                return None;
+            }
+
+            match form {
+                "quote" => {
+                    let [_, datum] = list.as_slice() else {
+                        return None;
+                    };
+                    self.constant(datum.clone());
+                    self.finish(tail);
+                    return Some(());
+                }
+                "if" => return self.if_form(&list, tail),
+                "define" => return self.define(&list, tail),
+                "set!" => return self.set(&list, tail),
+                "lambda" if list.len() >= 3 => {
+                    self.lambda(&list[1], &list[2..])?;
+                    self.finish(tail);
+                    return Some(());
+                }
+                "lambda" => return None,
+                "begin" if list.len() == 1 => {
+                    self.constant(Object::Void);
+                    self.finish(tail);
+                    return Some(());
+                }
+                "begin" => return self.body(&list[1..], tail),
+                "let" => return self.let_form(&list, tail),
+                "declare" => {
+                    self.constant(Object::Void);
+                    self.finish(tail);
+                    return Some(());
+                }
+                _ => {}
+            }
+        }
+
+        match &list[0] {
+            Object::Symbol(head) => {
+                if let Some(procedure) = self.procedure(*head) {
+                    return self.inline(&procedure.params, &procedure.body, &list[1..], tail);
+                }
+                if let Some(inline) = self.inline_global(*head, list.len() - 1) {
+                    return self.inline_call(*head, &inline, &list[1..], tail);
+                }
+            }
+            lambda => {
+                if let Some((params, body)) = inlinable(lambda) {
+                    if params.len() == list.len() - 1 {
+                        return self.inline(&params, &body, &list[1..], tail);
+                    }
+                }
+            }
+        }
+
+        if let [Object::Symbol(head), a, b] = list.as_slice() {
+            let arith = Arith::ALL.into_iter().find(|arith| *head == arith.name());
+            if let (Some(op), None) = (arith, self.resolve(*head)) {
+                self.expr(a, false)?;
+                self.expr(b, false)?;
+                let symbol = self.symbol(*head);
+                self.emit(Op::Arith { op, symbol, tail });
+                self.finish(tail);
+                return Some(());
+            }
+        }
+
+        for item in &list {
+            self.expr(item, false)?;
+        }
+        let argc = list.len() - 1;
+        self.emit(if tail {
+            Op::TailCall(argc)
+        } else {
+            Op::Call(argc)
+        });
+
+        Some(())
+    }
+
+    /// Compiles a sequence, keeping the value of the last expression.
+    fn body(&mut self, body: &[Object], tail: bool) -> Option<()> {
+        let (last, init) = body.split_last()?;
+        for form in init {
+            self.expr(form, false)?;
+            self.emit(Op::Pop);
+        }
+
+        self.expr(last, tail)
+    }
+
+    fn if_form(&mut self, list: &[Object], tail: bool) -> Option<()> {
+        let (condition, consequent, alternative) = match list {
+            [_, condition, consequent] => (condition, consequent, None),
+            [_, condition, consequent, alternative] => (condition, consequent, Some(alternative)),
+            _ => return None,
+        };
+
+        self.expr(condition, false)?;
+        let to_alternative = self.emit(Op::JumpIfFalse(0));
+        self.expr(consequent, tail)?;
+        // In tail position both branches return, so there is no end to
+        // jump to.
+        let to_end = (!tail).then(|| self.emit(Op::Jump(0)));
+
+        self.chunk.code[to_alternative] = Op::JumpIfFalse(self.chunk.code.len());
+        match alternative {
+            Some(alternative) => self.expr(alternative, tail)?,
+            None => {
+                self.constant(Object::Void);
+                self.finish(tail);
+            }
+        }
+        if let Some(to_end) = to_end {
+            self.chunk.code[to_end] = Op::Jump(self.chunk.code.len());
+        }
+
+        Some(())
+    }
+
+    fn define(&mut self, list: &[Object], tail: bool) -> Option<()> {
+        let name = match list {
+            [_, Object::Symbol(name), value] => {
+                self.expr(value, false)?;
+                *name
+            }
+            [_, Object::Pair(signature), _, ..] => {
+                let Object::Symbol(name) = signature.car() else {
+                    return None;
+                };
+                self.lambda(&signature.cdr(), &list[2..])?;
+                name
+            }
+            _ => return None,
+        };
+
+        match self.bind(name) {
+            Some(op) => {
+                self.emit(op);
+            }
+            None => {
+                let index = self.symbol(name);
+                self.emit(Op::DefineGlobal(index));
+            }
+        }
+        self.constant(Object::Void);
+        self.finish(tail);
+
+        Some(())
+    }
+
+    fn set(&mut self, list: &[Object], tail: bool) -> Option<()> {
+        let [_, Object::Symbol(name), value] = list else {
+            return None;
+        };
+
+        self.expr(value, false)?;
+        match self.resolve(*name) {
+            Some(access) => {
+                let op = access.store()?;
+                self.emit(op)
+            }
+            None => {
+                let index = self.symbol(*name);
+                self.emit(Op::SetGlobal(index))
+            }
+        };
+        self.constant(Object::Void);
+        self.finish(tail);
+
+        Some(())
+    }
+
+    fn let_form(&mut self, list: &[Object], tail: bool) -> Option<()> {
+        if list.len() < 3 {
+            return None;
+        }
+
+        let mut bindings = Vec::new();
+        for binding in list[1].to_vec()? {
+            let binding = binding.to_vec()?;
+            let [Object::Symbol(name), value] = binding.as_slice() else {
+                return None;
+            };
+            bindings.push((*name, value.clone()));
+        }
+        let outer: HashSet<Symbol> = bindings.iter().map(|(name, _)| *name).collect();
+        let distinct = outer.len() == bindings.len();
+
+        let mut names = Vec::new();
+        let mut procedures = Vec::new();
+        for (name, value) in &bindings {
+            let procedure = distinct
+                .then(|| self.local_procedure(*name, value, &list[2..], &outer))
+                .flatten();
+            match procedure {
+                Some(procedure) => procedures.push((*name, Rc::new(procedure))),
+                None => {
+                    self.expr(value, false)?;
+                    names.push(*name);
+                }
+            }
+        }
+
+        let mut scope = self.scope(names, &list[2..], 0);
+        scope.procedures = procedures;
+        self.bound_body(scope, &list[2..], tail)
+    }
+
+    /// Compiles a call of a procedure that does not escape in place, as the
+    /// `let` binding its parameters to the arguments.
+    fn inline(
+        &mut self,
+        params: &[Symbol],
+        body: &[Object],
+        args: &[Object],
+        tail: bool,
+    ) -> Option<()> {
+        for arg in args {
+            self.expr(arg, false)?;
+        }
+        let scope = self.scope(params.to_vec(), body, 0);
+        self.bound_body(scope, body, tail)
+    }
+
+    /// Compiles a call of a global procedure in place, after the check that
+    /// the global still holds it, followed by the call for when it does not.
+    fn inline_call(
+        &mut self,
+        name: Symbol,
+        inline: &Inline,
+        args: &[Object],
+        tail: bool,
+    ) -> Option<()> {
+        for arg in args {
+            self.expr(arg, false)?;
+        }
+        let symbol = self.symbol(name);
+        let chunks = &mut self.chunk.chunks;
+        let chunk = match chunks.iter().position(|c| Rc::ptr_eq(c, &inline.chunk)) {
+            Some(chunk) => chunk,
+            None => {
+                chunks.push(inline.chunk.clone());
+                chunks.len() - 1
+            }
+        };
+        let argc = args.len();
+        let guard = self.emit(Op::Inline {
+            symbol,
+            chunk,
+            argc,
+            fallback: 0,
+        });
+
+        let scope = self.scope(inline.params.clone(), &inline.body, 0);
+        self.inlining.push(name);
+        let body = self.bound_body(scope, &inline.body, tail);
+        self.inlining.pop();
+        body?;
+
+        let to_end = (!tail).then(|| self.emit(Op::Jump(0)));
+        self.chunk.code[guard] = Op::Inline {
+            symbol,
+            chunk,
+            argc,
+            fallback: self.chunk.code.len(),
+        };
+        self.emit(if tail {
+            Op::TailCall(argc)
+        } else {
+            Op::Call(argc)
+        });
+        if let Some(to_end) = to_end {
+            self.chunk.code[to_end] = Op::Jump(self.chunk.code.len());
+        }
+
+        Some(())
+    }
+
+    /// Compiles `body` in `scope`, taking the values of its variables from
+    /// the stack.
+    fn bound_body(&mut self, scope: Scope, body: &[Object], tail: bool) -> Option<()> {
+        for &(_, access) in scope.names.iter().rev() {
+            self.emit(access.store()?);
+        }
+        self.scopes.push(scope);
+        let compiled = self.body(body, tail);
+        self.scopes.pop();
+
+        compiled
+    }
+
+    /// Compiles a procedure into a new chunk and emits the closure for it.
+    fn lambda(&mut self, params: &Object, body: &[Object]) -> Option<()> {
+        let mut names = Vec::new();
+        let mut current = params.clone();
+        let rest = loop {
+            match current {
+                Object::Nil => break None,
+                Object::Symbol(rest) => break Some(rest),
+                Object::Pair(pair) => {
+                    let Object::Symbol(name) = pair.car() else {
+                        return None;
+                    };
+                    names.push(name);
+                    current = pair.cdr();
+                }
+                _ => return None,
+            }
+        };
+
+        let params = names.len();
+        names.extend(rest);
+        let args = names.len();
+        // Internal definitions are bound in the frame before the body runs,
+        // so that they can refer to each other.
+        for form in body {
+            if let Some(name) = defined_name(form) {
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+        }
+
+        let outer = std::mem::replace(
+            &mut self.chunk,
+            Chunk {
+                params,
+                rest: rest.is_some(),
+                ..Chunk::default()
+            },
+        );
+        self.enclosing.push(outer);
+        let scope = self.scope(names, body, args);
+        // Arguments that need a cell are moved into it.
+        for (slot, &(_, access)) in scope.names[..args].iter().enumerate() {
+            if let Access::Cell(cell) = access {
+                self.emit(Op::Slot(slot));
+                self.emit(Op::SetCell(cell));
+            }
+        }
+        self.scopes.push(scope);
+
+        let compiled = self.body(body, true);
+        self.finish_chunk();
+        self.scopes.pop();
+        let outer = self.enclosing.pop()?;
+        let chunk = std::mem::replace(&mut self.chunk, outer);
+        compiled?;
+
+        self.chunk.chunks.push(Rc::new(chunk));
+        self.emit(Op::Closure(self.chunk.chunks.len() - 1));
+
+        Some(())
+    }
+}
+
+/// The parameters and body of `form` if it is a `lambda` that can be
+/// compiled in place of its calls.
+fn inlinable(form: &Object) -> Option<(Vec<Symbol>, Vec<Object>)> {
+    let list = form.to_vec()?;
+    let [Object::Symbol(head), params, body @ ..] = list.as_slice() else {
+        return None;
+    };
+    if *head != "lambda" {
+        return None;
+    }
+
+    Some((fixed_params(params, body)?, body.to_vec()))
+}
+
+/// The parameters of a procedure taking `params`, if it can be compiled in
+/// place of its calls: if it takes a fixed number of arguments and `body`
+/// has forms and no internal definitions.
+fn fixed_params(params: &Object, body: &[Object]) -> Option<Vec<Symbol>> {
+    if body.is_empty() || body.iter().any(|form| defined_name(form).is_some()) {
+        return None;
+    }
+
+    params
+        .to_vec()?
+        .into_iter()
+        .map(|param| match param {
+            Object::Symbol(name) => Some(name),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Adds the symbols in `form` that are not quoted to `found`.
+fn symbols(form: &Object, found: &mut HashSet<Symbol>) {
+    match form {
+        Object::Symbol(name) => {
+            found.insert(*name);
+        }
+        Object::Pair(pair) => {
+            if matches!(pair.car(), Object::Symbol(head) if head == "quote") {
+                return;
+            }
+            let mut current = form.clone();
+            while let Object::Pair(pair) = current {
+                symbols(&pair.car(), found);
+                current = pair.cdr();
+            }
+            symbols(&current, found);
+        }
+        _ => {}
+    }
+}
+
+/// The number of atoms and lists in `form`.
+fn size(form: &Object) -> usize {
+    let mut nodes = 1;
+    let mut current = form.clone();
+    while let Object::Pair(pair) = current {
+        nodes += size(&pair.car());
+        current = pair.cdr();
+    }
+
+    nodes
+}
+
+fn defined_name(form: &Object) -> Option<Symbol> {
+    let Object::Pair(pair) = form else {
+        return None;
+    };
+    let Object::Pair(target) = pair.cdr() else {
+        return None;
+    };
+
+    match (pair.car(), target.car()) {
+        (Object::Symbol(define), Object::Symbol(name)) if define == "define" => Some(name),
+        (Object::Symbol(define), Object::Pair(signature)) if define == "define" => {
+            match signature.car() {
+                Object::Symbol(name) => Some(name),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    fn compile_str(source: &str) -> Option<Chunk> {
+        compile(&parse(source).unwrap()[0])
+    }
+
+    #[test]
+    fn test_locals_are_resolved_at_compile_time() {
+        let chunk = compile_str("(lambda (x) (lambda (y) (+ x y)))").unwrap();
+        let inner = &chunk.chunks[0].chunks[0];
+
+        assert_eq!(
+            inner.code,
+            [
+                Op::Free(0),
+                Op::Slot(0),
+                Op::Arith {
+                    op: Arith::Add,
+                    symbol: 0,
+                    tail: true
+                },
+                Op::Return,
+            ]
+        );
+        assert_eq!(inner.symbols, [Symbol::intern("+")]);
+        assert_eq!(inner.free, [Capture::Local(0)]);
+    }
+
+    #[test]
+    fn test_procedures_that_do_not_escape_get_no_closure() {
+        let chunk = compile_str("((lambda (x) (+ x 1)) 2)").unwrap();
+        assert!(chunk.chunks.is_empty());
+
+        let chunk = compile_str(
+            "(lambda (xs k)
+               (let ((by-k (lambda (x) (* x k))))
+                 (map (lambda (x) (by-k x)) xs)))",
+        )
+        .unwrap();
+        let procedure = &chunk.chunks[0];
+        assert_eq!(procedure.chunks.len(), 1);
+        assert!(procedure.chunks[0].chunks.is_empty());
+
+        let chunk = compile_str("(let ((f (lambda (x) x))) (map f '(1 2)))").unwrap();
+        assert_eq!(chunk.chunks.len(), 1);
+    }
+
+    #[test]
+    fn test_small_procedures_are_compiled_in_place() {
+        let chunks = |source: &str| {
+            compile_program(parse(source).unwrap())
+                .into_iter()
+                .map(|form| match form {
+                    TopLevel::Compiled(chunk) => chunk,
+                    TopLevel::Interpreted(form) => panic!("{} was not compiled", form),
+                })
+                .collect::<Vec<_>>()
+        };
+        let inlined = |chunk: &Chunk| chunk.code.iter().any(|op| matches!(op, Op::Inline { .. }));
+
+        let program = chunks("(define (sq x) (* x x)) (sq 3)");
+        assert!(inlined(&program[1]));
+        assert!(Rc::ptr_eq(&program[0].chunks[0], &program[1].chunks[0]));
+
+        let large = "(define (f x) (list x x x x x x x x x x x x x x x x x x x x)) (f 1)";
+        assert!(!inlined(&chunks(large)[1]));
+        let declared = format!("(declare (inline f)) {}", large);
+        assert!(inlined(&chunks(&declared)[2]));
+
+        assert!(!inlined(
+            &chunks("(define (sq x) (* x x)) (define sq 5) (sq 3)")[2]
+        ));
+        assert!(!inlined(
+            &chunks("(define (sq x) (* x x)) (lambda (x) (let ((* +)) (sq x)))")[1]
+        ));
+    }
+
+    #[test]
+    fn test_interpreted_forms_are_not_compiled() {
+        assert!(compile_str("(define (f) (load \"x.lisp\"))").is_none());
+        assert!(compile_str("(if)").is_none());
+        assert!(compile_str("(if #t 1 2)").is_some());
+    }
+
+    #[test]
+    fn test_literals_are_shared() {
+        let chunk = compile_str("(list \"a\" 1 \"a\" 1 0.0 -0.0 '(1) '(1) 'x 'x)").unwrap();
+        assert_eq!(
+            chunk.code[1..11],
+            [0, 1, 0, 1, 2, 3, 4, 5, 6, 6].map(Op::Const)
+        );
+
+        let program = compile_program(parse("(define a \"text\") (list \"text\")").unwrap());
+        let text = |form: &TopLevel| match form {
+            TopLevel::Compiled(chunk) => match &chunk.constants[0] {
+                Object::String(s) => s.as_ptr(),
+                other => panic!("expected a string, got {}", other),
+            },
+            TopLevel::Interpreted(form) => panic!("{} was not compiled", form),
+        };
+        assert_eq!(text(&program[0]), text(&program[1]));
+    }
+
+    #[test]
+    fn test_embed_files() {
+        let path = std::env::temp_dir().join(format!("lisp-rs-embed-{}.json", std::process::id()));
+        std::fs::write(&path, "{}").unwrap();
+        let source = format!(
+            "(define (schema) (list (embed-file {:?}) '(embed-file \"x\")))",
+            path.display()
+        );
+
+        let form = embed_files(&parse(&source).unwrap()[0]).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let chunk = compile(&form).unwrap();
+        assert_eq!(chunk.chunks[0].constants[0], Object::string("{}"));
+        assert_eq!(
+            form.to_string(),
+            "(define (schema) (list \"{}\" (quote (embed-file \"x\"))))"
+        );
+
+        let err = embed_files(&parse("(embed-file 'x)").unwrap()[0]).unwrap_err();
+        assert_eq!(err.message(), "embed-file expects a literal path string");
+    }
+}
@@ -0,0 +1,160 @@
+use std::cell::Cell;
+use std::rc::Rc;
+
+use crate::eval::{apply, EvalError};
+use crate::object::Object;
+
+thread_local! {
+    static NEXT_ID: Cell<u64> = const { Cell::new(0) };
+}
+
+/// An escape-only continuation: invoking it returns its argument from the
+/// `call/cc` that created it, as long as that call has not returned yet.
+pub struct Continuation {
+    id: u64,
+    active: Cell<bool>,
+}
+
+impl Continuation {
+    pub fn escape(&self, args: Vec<Object>) -> EvalError {
+        if !self.active.get() {
+            return EvalError::new("continuation is no longer active");
+        }
+
+        let value = match <[Object; 1]>::try_from(args) {
+            Ok([value]) => value,
+            Err(args) if args.is_empty() => Object::Void,
+            Err(_) => return EvalError::new("continuation expects at most one value"),
+        };
+
+        EvalError::escape(self.id, value)
+    }
+}
+
+/// `(call/cc f)` calls `f` with a continuation that returns from `call/cc`
+/// when invoked. Continuations only escape upwards: once `call/cc` has
+/// returned, invoking its continuation is an error.
+pub fn call_cc(args: &[Object]) -> Result<Object, EvalError> {
+    let func = match args {
+        [func] => func,
+        _ => return Err(EvalError::new("call/cc expects a procedure")),
+    };
+
+    let id = NEXT_ID.with(|next| {
+        next.set(next.get() + 1);
+        next.get()
+    });
+    let k = Rc::new(Continuation {
+        id,
+        active: Cell::new(true),
+    });
+
+    let result = apply(func, vec![Object::Continuation(k.clone())]);
+    k.active.set(false);
+
+    result.or_else(|e| e.escaped_to(id))
+}
+
+/// `(dynamic-wind before thunk after)` calls the three procedures in order
+/// and returns the result of `thunk`. `after` also runs when `thunk` is left
+/// through an error, a continuation or `exit`, which then carries on
+/// unwinding.
+pub fn dynamic_wind(args: &[Object]) -> Result<Object, EvalError> {
+    let (before, thunk, after) = match args {
+        [before, thunk, after] => (before, thunk, after),
+        _ => return Err(EvalError::new("dynamic-wind expects three procedures")),
+    };
+
+    apply(before, Vec::new())?;
+    let result = apply(thunk, Vec::new());
+    apply(after, Vec::new())?;
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builtins::global_env;
+    use crate::eval::eval_str;
+    use crate::object::Object;
+
+    fn eval(program: &str) -> Object {
+        eval_str(program, &global_env()).unwrap()
+    }
+
+    #[test]
+    fn test_early_exit() {
+        assert_eq!(
+            eval("(+ 1 (call/cc (lambda (k) (+ 10 (k 2)))))"),
+            Object::Integer(3)
+        );
+        assert_eq!(eval("(call/cc (lambda (k) 5))"), Object::Integer(5));
+        assert_eq!(
+            eval(
+                "(define (find-first pred xs)
+                   (call-with-current-continuation
+                     (lambda (return)
+                       (for-each (lambda (x) (if (pred x) (return x))) xs)
+                       #f)))
+                 (find-first (lambda (x) (> x 2)) '(1 2 3 4))"
+            ),
+            Object::Integer(3)
+        );
+    }
+
+    #[test]
+    fn test_nested_continuations_escape_to_their_own_call() {
+        assert_eq!(
+            eval(
+                "(call/cc (lambda (outer)
+                   (+ 1 (call/cc (lambda (inner) (outer 10))))))"
+            ),
+            Object::Integer(10)
+        );
+    }
+
+    #[test]
+    fn test_escaping_after_return_is_an_error() {
+        let env = global_env();
+        eval_str(
+            "(define saved #f) (call/cc (lambda (k) (set! saved k)))",
+            &env,
+        )
+        .unwrap();
+
+        assert!(eval_str("(saved 1)", &env)
+            .unwrap_err()
+            .to_string()
+            .contains("no longer active"));
+    }
+
+    #[test]
+    fn test_dynamic_wind_runs_after_when_unwinding() {
+        let env = global_env();
+        eval_str(
+            "(define log '())
+             (define (note x) (lambda () (set! log (cons x log))))",
+            &env,
+        )
+        .unwrap();
+
+        assert_eq!(
+            eval_str(
+                "(call/cc (lambda (k)
+                   (dynamic-wind (note 'in) (lambda () (k 1) 2) (note 'out))))",
+                &env
+            )
+            .unwrap(),
+            Object::Integer(1)
+        );
+        assert!(eval_str(
+            "(dynamic-wind (note 'in) (lambda () (car '())) (note 'out))",
+            &env
+        )
+        .is_err());
+        assert_eq!(
+            eval_str("log", &env).unwrap().to_string(),
+            "(out in out in)"
+        );
+    }
+}
@@ -0,0 +1,1026 @@
+//! `lisp-rs dap`: a Debug Adapter Protocol server, so editors can debug
+//! Lisp programs in their built-in debugger.
+//!
+//! The adapter runs the program given to `launch` one top-level form at a
+//! time, watching procedure calls and the lists evaluated through a `Hook`.
+//! A breakpoint is set on a list of the program, the first that starts on
+//! its line or else the innermost one spanning it, and stops every time
+//! that list is about to be evaluated, so one in the body of a procedure
+//! stops in each call. The adapter also stops at calls to procedures named
+//! by function breakpoints, and after each step: `stepIn` stops at the next
+//! call, `next` at the next call made by the current frame (or the next
+//! top-level form), and `stepOut` once the current frame has returned.
+//! While stopped it answers with the call stack, the local bindings of
+//! every frame, or the arguments of those that evaluated no list of the
+//! program yet, and the global bindings, and evaluates expressions in the
+//! environment of the frame they are asked for. Lines are 1-based, as the
+//! protocol assumes by default.
+//!
+//! Output of `pp` is sent to the editor as output events. A `disconnect`
+//! while the program runs lets the current top-level form finish, without
+//! stopping again, and skips the rest.
+
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeSet, HashSet};
+use std::fs;
+use std::io;
+use std::io::{BufRead, Write};
+use std::rc::Rc;
+
+use crate::builtins::global_env;
+use crate::collections::{hash_table_object, HashTable};
+use crate::diagnostic::Span;
+use crate::env::Env;
+use crate::eval::{eval, EvalError};
+use crate::hooks;
+use crate::hooks::{Hook, ProcedureNames};
+use crate::json;
+use crate::lsp::{field, integer_field, null, object, read_message, string_field, write_message};
+use crate::module;
+use crate::object::Object;
+use crate::parser::{parse_with_list_spans, ListSpans};
+use crate::printer;
+use crate::symbol::Symbol;
+
+/// The only thread, as the protocol needs thread ids.
+const THREAD_ID: i64 = 1;
+
+/// The variables reference of the global bindings. Those of the frames'
+/// arguments are their frame ids, plus this.
+const GLOBALS: i64 = 1;
+
+/// Printed values longer than this are cut short.
+const MAX_VALUE_LEN: usize = 200;
+
+struct Program {
+    path: String,
+    /// Every top-level form, with where it starts.
+    forms: Vec<(Object, Span)>,
+    /// Where the lists of the forms are.
+    lists: ListSpans,
+    /// The offset each line starts at.
+    lines: Vec<usize>,
+}
+
+impl Program {
+    fn line_of(&self, offset: usize) -> usize {
+        self.lines.partition_point(|&start| start <= offset)
+    }
+
+    /// The offset and first line of the list a breakpoint on `line` is set
+    /// on, if there is one.
+    fn list_at(&self, line: i64) -> Option<(usize, usize)> {
+        let spans = self
+            .lists
+            .spans()
+            .map(|span| (span, self.line_of(span.start), self.line_of(span.end)))
+            .collect::<Vec<_>>();
+        let line = usize::try_from(line).ok()?;
+
+        spans
+            .iter()
+            .filter(|(_, first, _)| *first == line)
+            .min_by_key(|(span, _, _)| span.start)
+            .or_else(|| {
+                spans
+                    .iter()
+                    .filter(|(_, first, last)| (*first..=*last).contains(&line))
+                    .min_by_key(|(span, _, _)| span.end - span.start)
+            })
+            .map(|(span, first, _)| (span.start, *first))
+    }
+}
+
+/// A procedure call being evaluated.
+struct Frame {
+    name: String,
+    arguments: Vec<(String, Object)>,
+    /// The environment of the last list of the program evaluated in the
+    /// call, once there is one.
+    env: Option<Rc<RefCell<Env>>>,
+    /// The line of that list, or of the call until then.
+    line: usize,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Step {
+    Run,
+    In,
+    /// Stop at events at most one frame deeper than this depth.
+    Over(usize),
+    /// Stop at events no deeper than this depth.
+    Out(usize),
+}
+
+enum Resume {
+    Continue,
+    Disconnect,
+}
+
+struct Session {
+    input: RefCell<Box<dyn BufRead>>,
+    output: RefCell<Box<dyn Write>>,
+    seq: Cell<i64>,
+    env: Rc<RefCell<Env>>,
+    program: RefCell<Option<Program>>,
+    stop_on_entry: Cell<bool>,
+    /// The offsets of the lists with breakpoints, in the launched program.
+    breakpoints: RefCell<BTreeSet<usize>>,
+    function_breakpoints: RefCell<HashSet<String>>,
+    names: ProcedureNames,
+    stack: RefCell<Vec<Frame>>,
+    /// The line of the last list of the program evaluated at top level.
+    line: Cell<usize>,
+    step: Cell<Step>,
+    disconnected: Cell<bool>,
+}
+
+fn printed(value: &Object) -> String {
+    let mut printed = value.to_string();
+    if printed.chars().count() > MAX_VALUE_LEN {
+        printed = printed.chars().take(MAX_VALUE_LEN).collect::<String>() + "...";
+    }
+
+    printed
+}
+
+impl Session {
+    fn send(&self, mut members: Vec<(&str, Object)>) -> io::Result<()> {
+        let seq = self.seq.get() + 1;
+        self.seq.set(seq);
+        members.push(("seq", Object::Integer(seq)));
+
+        let mut table = HashTable::default();
+        for (key, value) in members {
+            table.insert(Object::string(key), value);
+        }
+        write_message(&mut *self.output.borrow_mut(), &hash_table_object(table))
+    }
+
+    fn event(&self, event: &str, body: Object) -> io::Result<()> {
+        self.send(vec![
+            ("type", Object::string("event")),
+            ("event", Object::string(event)),
+            ("body", body),
+        ])
+    }
+
+    fn respond(&self, request: &Object, result: Result<Object, String>) -> io::Result<()> {
+        let mut members = vec![
+            ("type", Object::string("response")),
+            (
+                "request_seq",
+                field(request, &["seq"]).unwrap_or(Object::Integer(0)),
+            ),
+            (
+                "command",
+                Object::string(string_field(request, &["command"]).unwrap_or_default()),
+            ),
+        ];
+        match result {
+            Ok(body) => {
+                members.push(("success", Object::Bool(true)));
+                members.push(("body", body));
+            }
+            Err(message) => {
+                members.push(("success", Object::Bool(false)));
+                members.push(("message", Object::string(message)));
+            }
+        }
+
+        self.send(members)
+    }
+
+    /// Sends what `pp` printed since last time.
+    fn flush_output(&self) -> io::Result<()> {
+        let output = printer::take_captured();
+        if output.is_empty() {
+            return Ok(());
+        }
+
+        self.event(
+            "output",
+            object([
+                ("category", Object::string("stdout")),
+                ("output", Object::string(output)),
+            ]),
+        )
+    }
+
+    fn read_request(&self) -> io::Result<Option<Object>> {
+        loop {
+            let body = match read_message(&mut *self.input.borrow_mut())? {
+                Some(body) => body,
+                None => return Ok(None),
+            };
+            match json::parse(&body) {
+                Ok(request) => return Ok(Some(request)),
+                Err(e) => eprintln!("lisp-rs dap: ignoring a message: {}", e.message()),
+            }
+        }
+    }
+
+    /// Answers the requests that mean the same whether the program is
+    /// running or not, returning `None` for any other.
+    fn common(&self, command: &str, arguments: &Object) -> Option<Result<Object, String>> {
+        Some(match command {
+            "threads" => Ok(object([(
+                "threads",
+                Object::list([object([
+                    ("id", Object::Integer(THREAD_ID)),
+                    ("name", Object::string("main")),
+                ])]),
+            )])),
+            "setBreakpoints" => Ok(self.set_breakpoints(arguments)),
+            "setFunctionBreakpoints" => {
+                let names = field(arguments, &["breakpoints"])
+                    .and_then(|breakpoints| breakpoints.to_vec())
+                    .unwrap_or_default()
+                    .iter()
+                    .filter_map(|breakpoint| string_field(breakpoint, &["name"]))
+                    .collect::<Vec<_>>();
+                let verified = names
+                    .iter()
+                    .map(|_| object([("verified", Object::Bool(true))]))
+                    .collect::<Vec<_>>();
+                *self.function_breakpoints.borrow_mut() = names.into_iter().collect();
+                Ok(object([("breakpoints", Object::list(verified))]))
+            }
+            "setExceptionBreakpoints" => Ok(object([("breakpoints", Object::Nil)])),
+            "stackTrace" => Ok(self.stack_trace()),
+            "scopes" => Ok(self.scopes(integer_field(arguments, &["frameId"]).unwrap_or(0))),
+            "variables" => {
+                Ok(self.variables(integer_field(arguments, &["variablesReference"]).unwrap_or(0)))
+            }
+            "evaluate" => {
+                let expression = string_field(arguments, &["expression"]).unwrap_or_default();
+                let env = self.frame_env(integer_field(arguments, &["frameId"]).unwrap_or(0));
+                match crate::eval::eval_str(&expression, &env) {
+                    Ok(value) => Ok(object([
+                        ("result", Object::string(printed(&value))),
+                        ("variablesReference", Object::Integer(0)),
+                    ])),
+                    Err(e) => Err(e.message().to_string()),
+                }
+            }
+            _ => return None,
+        })
+    }
+
+    /// Breakpoints move to the first line of the list they are set on, and
+    /// lines outside of every list cannot have one.
+    fn set_breakpoints(&self, arguments: &Object) -> Object {
+        let requested = field(arguments, &["breakpoints"])
+            .and_then(|breakpoints| breakpoints.to_vec())
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|breakpoint| integer_field(breakpoint, &["line"]))
+            .collect::<Vec<_>>();
+        let program = self.program.borrow();
+        let same_file = match (&*program, string_field(arguments, &["source", "path"])) {
+            (Some(program), Some(path)) => program.path == path,
+            _ => false,
+        };
+
+        let mut lists = BTreeSet::new();
+        let breakpoints = requested
+            .into_iter()
+            .map(|line| {
+                let list = program
+                    .as_ref()
+                    .filter(|_| same_file)
+                    .and_then(|program| program.list_at(line));
+                match list {
+                    Some((start, first)) => {
+                        lists.insert(start);
+                        object([
+                            ("verified", Object::Bool(true)),
+                            ("line", Object::Integer(first as i64)),
+                        ])
+                    }
+                    None => object([
+                        ("verified", Object::Bool(false)),
+                        ("line", Object::Integer(line)),
+                    ]),
+                }
+            })
+            .collect::<Vec<_>>();
+        *self.breakpoints.borrow_mut() = lists;
+
+        object([("breakpoints", Object::list(breakpoints))])
+    }
+
+    fn stack_trace(&self) -> Object {
+        let stack = self.stack.borrow();
+        let source = match &*self.program.borrow() {
+            Some(program) => object([("path", Object::string(program.path.as_str()))]),
+            None => null(),
+        };
+        let frame = |id: usize, name: &str, line: usize| {
+            object([
+                ("id", Object::Integer(id as i64)),
+                ("name", Object::string(name)),
+                ("source", source.clone()),
+                ("line", Object::Integer(line as i64)),
+                ("column", Object::Integer(1)),
+            ])
+        };
+
+        let frames = (1..=stack.len())
+            .rev()
+            .map(|id| frame(id, &stack[id - 1].name, stack[id - 1].line))
+            .chain(std::iter::once(frame(0, "<top level>", self.line.get())))
+            .collect::<Vec<_>>();
+
+        object([
+            ("totalFrames", Object::Integer(frames.len() as i64)),
+            ("stackFrames", Object::list(frames)),
+        ])
+    }
+
+    fn scopes(&self, frame_id: i64) -> Object {
+        let scope = |name: &str, reference: i64| {
+            object([
+                ("name", Object::string(name)),
+                ("variablesReference", Object::Integer(reference)),
+                ("expensive", Object::Bool(false)),
+            ])
+        };
+
+        let mut scopes = Vec::new();
+        match self.frame(frame_id, |frame| frame.env.is_some()) {
+            Some(true) => scopes.push(scope("Locals", GLOBALS + frame_id)),
+            Some(false) => scopes.push(scope("Arguments", GLOBALS + frame_id)),
+            None => {}
+        }
+        scopes.push(scope("Globals", GLOBALS));
+
+        object([("scopes", Object::list(scopes))])
+    }
+
+    fn variables(&self, reference: i64) -> Object {
+        let variable = |name: &str, value: &Object| {
+            object([
+                ("name", Object::string(name)),
+                ("value", Object::string(printed(value))),
+                ("type", Object::string(value.type_name())),
+                ("variablesReference", Object::Integer(0)),
+            ])
+        };
+
+        let variables = if reference == GLOBALS {
+            let env = self.env.borrow();
+            let mut names = env.names();
+            names.sort_by_key(|name| name.as_str());
+            names
+                .into_iter()
+                .filter_map(|name| match env.get(name)? {
+                    Object::Builtin(_) => None,
+                    value => Some(variable(name.as_str(), &value)),
+                })
+                .collect::<Vec<_>>()
+        } else {
+            self.frame(reference - GLOBALS, |frame| match &frame.env {
+                Some(env) => locals(env, &self.env)
+                    .iter()
+                    .map(|(name, value)| variable(name.as_str(), value))
+                    .collect::<Vec<_>>(),
+                None => frame
+                    .arguments
+                    .iter()
+                    .map(|(name, value)| variable(name, value))
+                    .collect(),
+            })
+            .unwrap_or_default()
+        };
+
+        object([("variables", Object::list(variables))])
+    }
+
+    /// Runs `f` on the frame with id `frame_id`, if there is one.
+    fn frame<R>(&self, frame_id: i64, f: impl FnOnce(&Frame) -> R) -> Option<R> {
+        let stack = self.stack.borrow();
+        let index = usize::try_from(frame_id - 1).ok()?;
+        stack.get(index).map(f)
+    }
+
+    /// The environment to evaluate expressions in for the frame with id
+    /// `frame_id`: that of its locals, or the global one.
+    fn frame_env(&self, frame_id: i64) -> Rc<RefCell<Env>> {
+        self.frame(frame_id, |frame| frame.env.clone())
+            .flatten()
+            .unwrap_or_else(|| self.env.clone())
+    }
+
+    /// Whether to stop at an event `depth` frames deep, after a step.
+    fn step_stops_at(&self, depth: usize) -> bool {
+        match self.step.get() {
+            Step::Run => false,
+            Step::In => true,
+            Step::Over(over) => depth <= over + 1,
+            Step::Out(out) => depth <= out,
+        }
+    }
+
+    /// Tells the editor the program stopped and answers its requests until
+    /// it resumes the program.
+    fn stop(&self, reason: &str) -> io::Result<Resume> {
+        self.flush_output()?;
+        self.event(
+            "stopped",
+            object([
+                ("reason", Object::string(reason)),
+                ("threadId", Object::Integer(THREAD_ID)),
+                ("allThreadsStopped", Object::Bool(true)),
+            ]),
+        )?;
+
+        let depth = self.stack.borrow().len();
+        while let Some(request) = self.read_request()? {
+            let command = string_field(&request, &["command"]).unwrap_or_default();
+            let arguments = field(&request, &["arguments"]).unwrap_or_else(null);
+
+            let step = match command.as_str() {
+                "continue" => Step::Run,
+                "next" => Step::Over(depth),
+                "stepIn" => Step::In,
+                "stepOut" => Step::Out(depth.saturating_sub(1)),
+                "disconnect" | "terminate" => {
+                    self.respond(&request, Ok(null()))?;
+                    return Ok(Resume::Disconnect);
+                }
+                _ => {
+                    let result = self
+                        .common(&command, &arguments)
+                        .unwrap_or_else(|| Err(format!("cannot {} while stopped", command)));
+                    self.respond(&request, result)?;
+                    continue;
+                }
+            };
+
+            self.step.set(step);
+            let body = match step {
+                Step::Run => object([("allThreadsContinued", Object::Bool(true))]),
+                _ => null(),
+            };
+            self.respond(&request, Ok(body))?;
+            return Ok(Resume::Continue);
+        }
+
+        Ok(Resume::Disconnect)
+    }
+
+    /// Stops with `stop`, unless disconnected. A failure to talk to the
+    /// editor, or a disconnect, lets the program run on untouched.
+    fn stop_and_wait(&self, reason: &str) {
+        if self.disconnected.get() {
+            return;
+        }
+
+        match self.stop(reason) {
+            Ok(Resume::Continue) => {}
+            Ok(Resume::Disconnect) => self.disconnect(),
+            Err(e) => {
+                eprintln!("lisp-rs dap: {}", e);
+                self.disconnect();
+            }
+        }
+    }
+
+    fn disconnect(&self) {
+        self.disconnected.set(true);
+        self.step.set(Step::Run);
+        self.breakpoints.borrow_mut().clear();
+        self.function_breakpoints.borrow_mut().clear();
+    }
+
+    fn launch(&self, arguments: &Object) -> Result<Object, String> {
+        let path = string_field(arguments, &["program"])
+            .ok_or_else(|| String::from("launch needs a program"))?;
+        let source =
+            fs::read_to_string(&path).map_err(|e| format!("cannot read {}: {}", path, e))?;
+        let (forms, lists) =
+            parse_with_list_spans(&source).map_err(|e| format!("{}: {}", path, e))?;
+
+        let lines = std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(offset, _)| offset + 1))
+            .collect();
+        *self.program.borrow_mut() = Some(Program {
+            path,
+            forms,
+            lists,
+            lines,
+        });
+        self.stop_on_entry.set(matches!(
+            field(arguments, &["stopOnEntry"]),
+            Some(Object::Bool(true))
+        ));
+
+        Ok(null())
+    }
+
+    /// Runs the launched program, returning its exit code.
+    fn run_program(&self) -> io::Result<i32> {
+        let (path, forms) = match &*self.program.borrow() {
+            Some(program) => (
+                program.path.clone(),
+                program
+                    .forms
+                    .iter()
+                    .map(|(form, span)| (form.clone(), span.start, program.line_of(span.start)))
+                    .collect::<Vec<_>>(),
+            ),
+            None => (String::new(), Vec::new()),
+        };
+        if self.stop_on_entry.get() {
+            self.step.set(Step::In);
+        }
+
+        for (form, start, line) in forms {
+            if self.disconnected.get() {
+                break;
+            }
+
+            self.line.set(line);
+            let entry = self.stop_on_entry.replace(false);
+            // A form with a breakpoint stops as it is evaluated.
+            if self.step_stops_at(0) && !self.breakpoints.borrow().contains(&start) {
+                self.stop_and_wait(if entry { "entry" } else { "step" });
+            }
+
+            let result = module::in_file(&path, || eval(&form, &self.env));
+            self.stack.borrow_mut().clear();
+            self.flush_output()?;
+
+            if let Err(e) = result {
+                if let Some(code) = e.exit_code() {
+                    return Ok(code);
+                }
+                self.event(
+                    "output",
+                    object([
+                        ("category", Object::string("stderr")),
+                        ("output", Object::string(format!("{}\n", e))),
+                    ]),
+                )?;
+                return Ok(1);
+            }
+        }
+
+        Ok(0)
+    }
+}
+
+/// The bindings of `env` and its parents short of `global`, innermost
+/// first, without those they shadow.
+fn locals(env: &Rc<RefCell<Env>>, global: &Rc<RefCell<Env>>) -> Vec<(Symbol, Object)> {
+    let mut locals = Vec::new();
+    let mut seen = HashSet::new();
+    let mut frame = Some(env.clone());
+    while let Some(env) = frame.filter(|env| !Rc::ptr_eq(env, global)) {
+        let env = env.borrow();
+        for (name, value) in env.bindings() {
+            if seen.insert(name) {
+                locals.push((name, value));
+            }
+        }
+        frame = env.parent().cloned();
+    }
+
+    locals
+}
+
+/// Watches the procedure calls of the program being debugged.
+struct Watcher(Rc<Session>);
+
+impl Hook for Watcher {
+    fn on_call(&self, func: &Object, args: &[Object]) {
+        let session = &self.0;
+        let name = session.names.name(func);
+        let arguments = match func {
+            Object::Lambda(lambda) => {
+                let mut arguments = lambda
+                    .params
+                    .iter()
+                    .map(|param| param.to_string())
+                    .zip(args.iter().cloned())
+                    .collect::<Vec<_>>();
+                if let Some(rest) = lambda.rest {
+                    let rest_args = args.get(lambda.params.len()..).unwrap_or_default();
+                    arguments.push((rest.to_string(), Object::list(rest_args.to_vec())));
+                }
+                arguments
+            }
+            _ => args
+                .iter()
+                .enumerate()
+                .map(|(i, arg)| (format!("arg{}", i + 1), arg.clone()))
+                .collect(),
+        };
+
+        let hit = session.function_breakpoints.borrow().contains(&name);
+        let mut stack = session.stack.borrow_mut();
+        let line = stack.last().map_or(session.line.get(), |frame| frame.line);
+        stack.push(Frame {
+            name,
+            arguments,
+            env: None,
+            line,
+        });
+        let depth = stack.len();
+        drop(stack);
+
+        if hit {
+            session.stop_and_wait("function breakpoint");
+        } else if session.step_stops_at(depth) {
+            session.stop_and_wait("step");
+        }
+    }
+
+    fn on_return(&self, _func: &Object, _result: &Object) {
+        self.0.stack.borrow_mut().pop();
+    }
+
+    fn on_define(&self, name: &str, value: &Object) {
+        self.0.names.define(name, value);
+    }
+
+    fn on_eval(&self, expr: &Object, env: &Rc<RefCell<Env>>) {
+        let session = &self.0;
+        let program = session.program.borrow();
+        let Some((start, line)) = program.as_ref().and_then(|program| {
+            let start = program.lists.get(expr)?.start;
+            Some((start, program.line_of(start)))
+        }) else {
+            return;
+        };
+        drop(program);
+
+        match session.stack.borrow_mut().last_mut() {
+            Some(frame) => {
+                frame.env = Some(env.clone());
+                frame.line = line;
+            }
+            None => session.line.set(line),
+        }
+        if session.breakpoints.borrow().contains(&start) {
+            session.stop_and_wait("breakpoint");
+        }
+    }
+
+    fn on_error(&self, _err: &EvalError) {
+        self.0.stack.borrow_mut().pop();
+    }
+}
+
+/// Serves one debugging session: configuration requests until
+/// `configurationDone`, the launched program with its stops, and then
+/// requests until `disconnect`. Returns the program's exit code.
+pub fn run(input: impl BufRead + 'static, output: impl Write + 'static) -> io::Result<i32> {
+    let session = Rc::new(Session {
+        input: RefCell::new(Box::new(input)),
+        output: RefCell::new(Box::new(output)),
+        seq: Cell::new(0),
+        env: global_env(),
+        program: RefCell::new(None),
+        stop_on_entry: Cell::new(false),
+        breakpoints: RefCell::new(BTreeSet::new()),
+        function_breakpoints: RefCell::new(HashSet::new()),
+        names: ProcedureNames::default(),
+        stack: RefCell::new(Vec::new()),
+        line: Cell::new(0),
+        step: Cell::new(Step::Run),
+        disconnected: Cell::new(false),
+    });
+
+    // Configuration.
+    loop {
+        let request = match session.read_request()? {
+            Some(request) => request,
+            None => return Ok(0),
+        };
+        let command = string_field(&request, &["command"]).unwrap_or_default();
+        let arguments = field(&request, &["arguments"]).unwrap_or_else(null);
+
+        let result = match command.as_str() {
+            "initialize" => {
+                session.respond(
+                    &request,
+                    Ok(object([
+                        ("supportsConfigurationDoneRequest", Object::Bool(true)),
+                        ("supportsFunctionBreakpoints", Object::Bool(true)),
+                        ("supportsEvaluateForHovers", Object::Bool(true)),
+                    ])),
+                )?;
+                session.event("initialized", null())?;
+                continue;
+            }
+            "launch" => session.launch(&arguments),
+            "configurationDone" => {
+                session.respond(&request, Ok(null()))?;
+                break;
+            }
+            "disconnect" | "terminate" => {
+                session.respond(&request, Ok(null()))?;
+                return Ok(0);
+            }
+            _ => session
+                .common(&command, &arguments)
+                .unwrap_or_else(|| Err(format!("unsupported command {}", command))),
+        };
+        session.respond(&request, result)?;
+    }
+
+    printer::capture_output(true);
+    let hook = hooks::add(Rc::new(Watcher(session.clone())));
+    let exit_code = session.run_program();
+    hooks::remove(hook);
+    printer::capture_output(false);
+    let exit_code = exit_code?;
+
+    session.event(
+        "exited",
+        object([("exitCode", Object::Integer(exit_code as i64))]),
+    )?;
+    session.event("terminated", null())?;
+    if session.disconnected.get() {
+        return Ok(exit_code);
+    }
+
+    while let Some(request) = session.read_request()? {
+        let command = string_field(&request, &["command"]).unwrap_or_default();
+        let arguments = field(&request, &["arguments"]).unwrap_or_else(null);
+        if command == "disconnect" {
+            session.respond(&request, Ok(null()))?;
+            break;
+        }
+
+        let result = session
+            .common(&command, &arguments)
+            .unwrap_or_else(|| Err(format!("the program has exited; cannot {}", command)));
+        session.respond(&request, result)?;
+    }
+
+    Ok(exit_code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env::temp_dir;
+
+    /// An output the test can still read after the session took it.
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn request(seq: usize, command: &str, arguments: &str) -> String {
+        let body = format!(
+            r#"{{"seq":{},"type":"request","command":"{}","arguments":{}}}"#,
+            seq, command, arguments
+        );
+        format!("Content-Length: {}\r\n\r\n{}", body.len(), body)
+    }
+
+    /// Debugs `source` from a temporary file, sending the requests made by
+    /// `requests` from its path, and returns the exit code and the
+    /// messages sent back.
+    fn debug(
+        name: &str,
+        source: &str,
+        requests: impl FnOnce(&str) -> Vec<String>,
+    ) -> (i32, Vec<Object>) {
+        let path = temp_dir().join(format!("lisp-rs-dap-{}-{}.lisp", name, std::process::id()));
+        fs::write(&path, source).unwrap();
+        let input = requests(&path.display().to_string()).concat();
+        let output = Shared::default();
+
+        let exit_code = run(io::Cursor::new(input.into_bytes()), output.clone()).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let output = output.0.borrow();
+        let mut reader = output.as_slice();
+        let mut messages = Vec::new();
+        while let Some(body) = read_message(&mut reader).unwrap() {
+            messages.push(json::parse(&body).unwrap());
+        }
+
+        (exit_code, messages)
+    }
+
+    fn set_breakpoints(seq: usize, path: &str, lines: &[usize]) -> String {
+        let breakpoints = lines
+            .iter()
+            .map(|line| format!(r#"{{"line":{}}}"#, line))
+            .collect::<Vec<_>>()
+            .join(",");
+        request(
+            seq,
+            "setBreakpoints",
+            &format!(
+                r#"{{"source":{{"path":"{}"}},"breakpoints":[{}]}}"#,
+                path, breakpoints
+            ),
+        )
+    }
+
+    #[test]
+    fn test_session() {
+        let (exit_code, messages) = debug(
+            "session",
+            "(define (sq x)\n  (* x x))\n(define n 4)\n(pp (sq n))\n(sq 5)\n",
+            |path| {
+                vec![
+                    request(1, "initialize", "{}"),
+                    request(2, "launch", &format!(r#"{{"program":"{}"}}"#, path)),
+                    set_breakpoints(3, path, &[2, 9]),
+                    request(
+                        4,
+                        "setFunctionBreakpoints",
+                        r#"{"breakpoints":[{"name":"sq"}]}"#,
+                    ),
+                    request(5, "configurationDone", "{}"),
+                    // Stopped in (sq n).
+                    request(6, "stackTrace", "{}"),
+                    request(7, "variables", r#"{"variablesReference":2}"#),
+                    request(8, "setFunctionBreakpoints", r#"{"breakpoints":[]}"#),
+                    request(9, "continue", "{}"),
+                    // Stopped at the breakpoint in the body of sq.
+                    request(10, "stackTrace", "{}"),
+                    request(11, "scopes", r#"{"frameId":1}"#),
+                    request(12, "variables", r#"{"variablesReference":2}"#),
+                    request(13, "evaluate", r#"{"expression":"(+ x 1)","frameId":1}"#),
+                    set_breakpoints(14, path, &[]),
+                    request(15, "next", "{}"),
+                    // Stopped at the call to * in sq.
+                    request(16, "stepOut", "{}"),
+                    // Stopped at the call to pp.
+                    request(17, "continue", "{}"),
+                    request(18, "disconnect", "{}"),
+                ]
+            },
+        );
+        assert_eq!(exit_code, 0);
+
+        let summary = messages
+            .iter()
+            .map(|message| {
+                let kind = string_field(message, &["command"])
+                    .or_else(|| string_field(message, &["event"]))
+                    .unwrap_or_default();
+                match string_field(message, &["body", "reason"]) {
+                    Some(reason) => format!("{}: {}", kind, reason),
+                    None => kind,
+                }
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            [
+                "initialize",
+                "initialized",
+                "launch",
+                "setBreakpoints",
+                "setFunctionBreakpoints",
+                "configurationDone",
+                "stopped: function breakpoint",
+                "stackTrace",
+                "variables",
+                "setFunctionBreakpoints",
+                "continue",
+                "stopped: breakpoint",
+                "stackTrace",
+                "scopes",
+                "variables",
+                "evaluate",
+                "setBreakpoints",
+                "next",
+                "stopped: step",
+                "stepOut",
+                "stopped: step",
+                "continue",
+                "output",
+                "exited",
+                "terminated",
+                "disconnect",
+            ]
+        );
+
+        let breakpoints = field(&messages[3], &["body", "breakpoints"]).unwrap();
+        assert_eq!(
+            json::stringify(&breakpoints).unwrap(),
+            r#"[{"line":2,"verified":true},{"line":9,"verified":false}]"#
+        );
+
+        let lines = |message: &Object| {
+            field(message, &["body", "stackFrames"])
+                .unwrap()
+                .to_vec()
+                .unwrap()
+                .iter()
+                .map(|frame| {
+                    let name = string_field(frame, &["name"]).unwrap();
+                    (name, integer_field(frame, &["line"]).unwrap())
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            lines(&messages[7]),
+            [(String::from("sq"), 4), (String::from("<top level>"), 4)]
+        );
+        assert_eq!(
+            lines(&messages[12]),
+            [(String::from("sq"), 2), (String::from("<top level>"), 4)]
+        );
+
+        let variables = field(&messages[8], &["body", "variables"]).unwrap();
+        assert_eq!(
+            json::stringify(&variables).unwrap(),
+            r#"[{"name":"x","type":"integer","value":"4","variablesReference":0}]"#
+        );
+        let scopes = field(&messages[13], &["body", "scopes"])
+            .unwrap()
+            .to_vec()
+            .unwrap()
+            .iter()
+            .map(|scope| string_field(scope, &["name"]).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(scopes, ["Locals", "Globals"]);
+        assert_eq!(
+            json::stringify(&field(&messages[14], &["body", "variables"]).unwrap()).unwrap(),
+            r#"[{"name":"x","type":"integer","value":"4","variablesReference":0}]"#
+        );
+        assert_eq!(
+            string_field(&messages[15], &["body", "result"]).as_deref(),
+            Some("5")
+        );
+
+        assert_eq!(
+            string_field(&messages[22], &["body", "output"]).as_deref(),
+            Some("16\n")
+        );
+    }
+
+    #[test]
+    fn test_breakpoint_in_procedure_body() {
+        let (exit_code, messages) = debug(
+            "body",
+            "(define (area w h)\n  (let ((a (* w h)))\n    (list a\n          w)))\n(area 2 3)\n(area 4 5)\n",
+            |path| {
+                vec![
+                    request(1, "launch", &format!(r#"{{"program":"{}"}}"#, path)),
+                    // Line 4 has no list of its own, so the breakpoint is on
+                    // the list spanning it.
+                    set_breakpoints(2, path, &[4]),
+                    request(3, "configurationDone", "{}"),
+                    // Stopped in (area 2 3).
+                    request(4, "variables", r#"{"variablesReference":2}"#),
+                    request(5, "continue", "{}"),
+                    // Stopped in (area 4 5).
+                    request(6, "variables", r#"{"variablesReference":2}"#),
+                    request(7, "continue", "{}"),
+                    request(8, "disconnect", "{}"),
+                ]
+            },
+        );
+        assert_eq!(exit_code, 0);
+
+        let breakpoints = field(&messages[1], &["body", "breakpoints"]).unwrap();
+        assert_eq!(
+            json::stringify(&breakpoints).unwrap(),
+            r#"[{"line":3,"verified":true}]"#
+        );
+        let variables = |message: &Object| {
+            field(message, &["body", "variables"])
+                .unwrap()
+                .to_vec()
+                .unwrap()
+                .iter()
+                .map(|variable| {
+                    let name = string_field(variable, &["name"]).unwrap();
+                    format!("{}={}", name, string_field(variable, &["value"]).unwrap())
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            string_field(&messages[3], &["body", "reason"]).as_deref(),
+            Some("breakpoint")
+        );
+        assert_eq!(variables(&messages[4]), ["a=6", "h=3", "w=2"]);
+        assert_eq!(
+            string_field(&messages[6], &["body", "reason"]).as_deref(),
+            Some("breakpoint")
+        );
+        assert_eq!(variables(&messages[7]), ["a=20", "h=5", "w=4"]);
+    }
+}
@@ -0,0 +1,271 @@
+//! Time-zone aware dates and times.
+//!
+//! Datetimes are strings in the RFC 9557 format, an ISO-8601 timestamp with
+//! its UTC offset and IANA time zone, like
+//! `"2024-03-10T12:00:00-04:00[America/New_York]"`. Keeping the zone lets
+//! arithmetic follow the local clock across daylight saving changes.
+//!
+//! Plain clock readings are numbers instead: `(current-time)` in seconds
+//! and `(time-millis)` in milliseconds since the Unix epoch. `(sleep ms)`
+//! waits, and `(time body...)` evaluates its body and prints how long it
+//! took, measured on a clock that is never set back.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use jiff::fmt::strtime;
+use jiff::fmt::temporal::{Pieces, PiecesOffset};
+use jiff::tz::TimeZone;
+use jiff::{Span, Timestamp, Zoned};
+
+use crate::bench;
+use crate::env::Env;
+use crate::eval::{eval, EvalError};
+use crate::object::Object;
+use crate::retry;
+
+fn datetime_error(e: jiff::Error) -> EvalError {
+    EvalError::new(format!("datetime error: {}", e))
+}
+
+/// Parses a datetime with a time zone annotation, or an ISO-8601 timestamp
+/// with a `Z` or numeric offset, which then keeps that fixed offset.
+fn parse(s: &str) -> Result<Zoned, EvalError> {
+    if let Ok(zoned) = s.parse::<Zoned>() {
+        return Ok(zoned);
+    }
+
+    let timestamp: Timestamp = s.parse().map_err(datetime_error)?;
+    let tz = match Pieces::parse(s).map_err(datetime_error)?.offset() {
+        Some(PiecesOffset::Numeric(offset)) => TimeZone::fixed(offset.offset()),
+        _ => TimeZone::UTC,
+    };
+
+    Ok(timestamp.to_zoned(tz))
+}
+
+fn datetime_arg(name: &str, obj: &Object) -> Result<Zoned, EvalError> {
+    match obj {
+        Object::String(s) => parse(s),
+        other => Err(EvalError::new(format!(
+            "{} expects a datetime string, got {}",
+            name, other
+        ))),
+    }
+}
+
+fn datetime(zoned: Zoned) -> Object {
+    Object::string(zoned.to_string())
+}
+
+/// `(datetime-now)` in the system time zone, or `(datetime-now "Europe/Paris")`.
+pub fn datetime_now(args: &[Object]) -> Result<Object, EvalError> {
+    match args {
+        [] => Ok(datetime(Zoned::now())),
+        [Object::String(tz)] => Zoned::now().in_tz(tz).map(datetime).map_err(datetime_error),
+        _ => Err(EvalError::new("datetime-now expects an optional time zone")),
+    }
+}
+
+/// `(string->datetime "2024-03-10T12:00:00Z")` validates and normalizes an
+/// ISO-8601 string.
+pub fn string_to_datetime(args: &[Object]) -> Result<Object, EvalError> {
+    match args {
+        [obj] => datetime_arg("string->datetime", obj).map(datetime),
+        _ => Err(EvalError::new("string->datetime expects one string")),
+    }
+}
+
+/// `(datetime->timezone dt "Asia/Tokyo")` is the same instant on the clocks
+/// of another time zone.
+pub fn datetime_to_timezone(args: &[Object]) -> Result<Object, EvalError> {
+    match args {
+        [dt, Object::String(tz)] => datetime_arg("datetime->timezone", dt)?
+            .in_tz(tz)
+            .map(datetime)
+            .map_err(datetime_error),
+        _ => Err(EvalError::new(
+            "datetime->timezone expects a datetime and a time zone",
+        )),
+    }
+}
+
+/// `(datetime->string dt)` formats `dt` as an ISO-8601 timestamp with its
+/// offset; `(datetime->string dt "%Y-%m-%d %H:%M")` uses a strftime format.
+pub fn datetime_to_string(args: &[Object]) -> Result<Object, EvalError> {
+    match args {
+        [dt] => {
+            let zoned = datetime_arg("datetime->string", dt)?;
+            let iso = zoned.timestamp().display_with_offset(zoned.offset());
+            Ok(Object::string(iso.to_string()))
+        }
+        [dt, Object::String(format)] => {
+            let zoned = datetime_arg("datetime->string", dt)?;
+            strtime::format(format.as_str(), &zoned)
+                .map(Object::string)
+                .map_err(datetime_error)
+        }
+        _ => Err(EvalError::new(
+            "datetime->string expects a datetime and an optional format",
+        )),
+    }
+}
+
+/// `(datetime-add dt 1 'day)`. Calendar units (`year`, `month`, `week`,
+/// `day`) move the local clock, so adding a day across a daylight saving
+/// change keeps the time of day; `hour`, `minute` and `second` add elapsed
+/// time.
+pub fn datetime_add(args: &[Object]) -> Result<Object, EvalError> {
+    let (dt, amount, unit) = match args {
+        [dt, Object::Integer(amount), Object::Symbol(unit)] => (dt, *amount, unit.as_str()),
+        _ => {
+            return Err(EvalError::new(
+                "datetime-add expects a datetime, an integer and a unit",
+            ))
+        }
+    };
+
+    let span = match unit.trim_end_matches('s') {
+        "year" => Span::new().try_years(amount),
+        "month" => Span::new().try_months(amount),
+        "week" => Span::new().try_weeks(amount),
+        "day" => Span::new().try_days(amount),
+        "hour" => Span::new().try_hours(amount),
+        "minute" => Span::new().try_minutes(amount),
+        "second" => Span::new().try_seconds(amount),
+        _ => return Err(EvalError::new(format!("unknown time unit: {}", unit))),
+    }
+    .map_err(datetime_error)?;
+
+    datetime_arg("datetime-add", dt)?
+        .checked_add(span)
+        .map(datetime)
+        .map_err(datetime_error)
+}
+
+/// `(datetime-difference a b)` is the number of seconds from `a` to `b`.
+pub fn datetime_difference(args: &[Object]) -> Result<Object, EvalError> {
+    match args {
+        [a, b] => {
+            let a = datetime_arg("datetime-difference", a)?.timestamp();
+            let b = datetime_arg("datetime-difference", b)?.timestamp();
+            Ok(Object::Integer(b.as_second() - a.as_second()))
+        }
+        _ => Err(EvalError::new("datetime-difference expects two datetimes")),
+    }
+}
+
+pub fn current_time(args: &[Object]) -> Result<Object, EvalError> {
+    match args {
+        [] => Ok(Object::Integer(Timestamp::now().as_second())),
+        _ => Err(EvalError::new("current-time expects no arguments")),
+    }
+}
+
+pub fn time_millis(args: &[Object]) -> Result<Object, EvalError> {
+    match args {
+        [] => Ok(Object::Integer(Timestamp::now().as_millisecond())),
+        _ => Err(EvalError::new("time-millis expects no arguments")),
+    }
+}
+
+/// `(sleep ms)`, which fails early if the evaluation is interrupted.
+pub fn sleep(args: &[Object]) -> Result<Object, EvalError> {
+    let ms = match args {
+        [Object::Integer(ms)] if *ms >= 0 => *ms as f64,
+        [Object::Float(ms)] if *ms >= 0.0 && ms.is_finite() => *ms,
+        _ => return Err(EvalError::new("sleep expects a number of milliseconds")),
+    };
+    retry::wait(ms)?;
+
+    Ok(Object::Void)
+}
+
+/// `(time body...)` prints how long the body took, as `; 1.234 ms`, and
+/// returns the value of its last form.
+pub fn time(list: &[Object], env: &Rc<RefCell<Env>>) -> Result<Object, EvalError> {
+    if list.len() < 2 {
+        return Err(EvalError::new("time expects an expression"));
+    }
+
+    let start = bench::now();
+    let mut value = Object::Void;
+    for form in &list[1..] {
+        value = eval(form, env)?;
+    }
+    println!("; {:.3} ms", bench::now() - start);
+
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builtins::global_env;
+    use crate::eval::eval_str;
+    use crate::object::Object;
+
+    fn eval(program: &str) -> Object {
+        eval_str(program, &global_env()).unwrap()
+    }
+
+    fn string(s: &str) -> Object {
+        Object::string(s.to_string())
+    }
+
+    #[test]
+    fn test_parse_and_convert() {
+        assert_eq!(
+            eval(r#"(string->datetime "2024-01-15T10:30:00Z")"#),
+            string("2024-01-15T10:30:00+00:00[UTC]")
+        );
+        assert_eq!(
+            eval(r#"(datetime->timezone "2024-01-15T10:30:00Z" "Asia/Tokyo")"#),
+            string("2024-01-15T19:30:00+09:00[Asia/Tokyo]")
+        );
+        assert_eq!(
+            eval(r#"(datetime->string "2024-01-15T10:30:00+02:00")"#),
+            string("2024-01-15T10:30:00+02:00")
+        );
+        assert!(eval_str(r#"(string->datetime "yesterday")"#, &global_env()).is_err());
+    }
+
+    #[test]
+    fn test_arithmetic_across_dst() {
+        let before = r#""2024-03-09T12:00:00-05:00[America/New_York]""#;
+        assert_eq!(
+            eval(&format!("(datetime-add {} 1 'day)", before)),
+            string("2024-03-10T12:00:00-04:00[America/New_York]")
+        );
+        assert_eq!(
+            eval(&format!("(datetime-add {} 24 'hours)", before)),
+            string("2024-03-10T13:00:00-04:00[America/New_York]")
+        );
+        assert_eq!(
+            eval(&format!(
+                "(datetime-difference {} (datetime-add {} 1 'day))",
+                before, before
+            )),
+            Object::Integer(23 * 3600)
+        );
+    }
+
+    #[test]
+    fn test_clock() {
+        let env = global_env();
+        let before = eval("(time-millis)");
+        let run = eval_str(
+            "(list (time (sleep 20) (+ 1 2)) (- (time-millis) (* 1000 (current-time))))",
+            &env,
+        )
+        .unwrap();
+        let (Object::Integer(before), Object::Integer(after)) = (before, eval("(time-millis)"))
+        else {
+            panic!("time-millis returned something other than an integer");
+        };
+
+        assert!(after - before >= 20);
+        assert_eq!(run.to_vec().unwrap()[0], Object::Integer(3));
+        assert!(eval_str("(sleep -1)", &env).is_err());
+        assert!(eval_str("(time)", &env).is_err());
+    }
+}
@@ -0,0 +1,414 @@
+//! A step debugger for the terminal, started by `:debug EXPR` in the REPL
+//! or by calling `(break)`.
+//!
+//! While it is on, the debugger stops before evaluating each list, a call
+//! or a special form, shows it, and reads commands until told to go on:
+//! `step` stops at the next list, `next` at the next one outside of the
+//! calls made by the current list, and `continue` runs until the next
+//! `(break)`. `frames` shows the bindings of each environment frame, from
+//! the innermost up to the global one, and any other input is evaluated in
+//! the current environment. It is built on the `on_eval` hook, so code
+//! compiled to bytecode is not stepped through.
+//!
+//! The debugger also remembers the last `HISTORY_LEN` lists evaluated while
+//! it is on, with the bindings of their frames, so `back` can go back
+//! through them and `step` forward again to the present. In the past,
+//! `frames` shows the bindings as they were, and expressions are evaluated
+//! with them, in frames of their own so that changes stay in the past.
+//! The global environment is not remembered, and is always the one of now.
+//! A frame whose bindings did not change from one list to the next is only
+//! recorded once.
+
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::io;
+use std::io::{BufRead, BufReader, Write};
+use std::rc::Rc;
+
+use crate::env::Env;
+use crate::eval::{eval_str, EvalError};
+use crate::hooks;
+use crate::hooks::{Hook, HookId};
+use crate::object::Object;
+use crate::symbol::Symbol;
+
+/// Printed lists longer than this are cut short.
+const MAX_EXPR_LEN: usize = 120;
+
+/// How many of the lists evaluated last the debugger goes back through.
+const HISTORY_LEN: usize = 1000;
+
+const HELP: &str =
+    "step (s), next (n), continue (c), back (b), frames (f) or an expression to evaluate";
+
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    Run,
+    Step,
+    /// Stop at lists evaluated at most this many calls deep.
+    Next(usize),
+}
+
+type Bindings = Rc<Vec<(Symbol, Object)>>;
+
+/// A list evaluated while the debugger was on.
+struct Step {
+    expr: Object,
+    /// The frames it was evaluated in, innermost first and leaving out the
+    /// global one, with their bindings at the time.
+    frames: Vec<(Rc<RefCell<Env>>, Bindings)>,
+    global: Rc<RefCell<Env>>,
+}
+
+impl Step {
+    /// Records `expr` evaluated in `env`, sharing the bindings of the
+    /// frames that did not change since `previous`.
+    fn new(expr: &Object, env: &Rc<RefCell<Env>>, previous: Option<&Step>) -> Self {
+        let mut frames = Vec::new();
+        let mut frame = env.clone();
+        loop {
+            let parent = frame.borrow().parent().cloned();
+            let Some(parent) = parent else {
+                break;
+            };
+            let bindings = frame.borrow().bindings();
+            let unchanged = previous
+                .and_then(|step| step.frames.iter().find(|(env, _)| Rc::ptr_eq(env, &frame)))
+                .map(|(_, recorded)| recorded)
+                .filter(|recorded| {
+                    recorded.len() == bindings.len()
+                        && recorded
+                            .iter()
+                            .zip(&bindings)
+                            .all(|((a, x), (b, y))| a == b && x.is_eq(y))
+                });
+            let bindings = match unchanged {
+                Some(recorded) => recorded.clone(),
+                None => Rc::new(bindings),
+            };
+            frames.push((frame, bindings));
+            frame = parent;
+        }
+
+        Self {
+            expr: expr.clone(),
+            frames,
+            global: frame,
+        }
+    }
+
+    /// The bindings of every frame, innermost first.
+    fn show_frames(&self) -> String {
+        let mut lines = self
+            .frames
+            .iter()
+            .enumerate()
+            .map(|(number, (_, bindings))| {
+                let bindings = bindings
+                    .iter()
+                    .map(|(name, value)| format!("{} = {}", name, shortened(value)))
+                    .collect::<Vec<_>>();
+                format!("#{} {}", number, bindings.join(", "))
+            })
+            .collect::<Vec<_>>();
+        lines.push(format!("#{} <global>", self.frames.len()));
+
+        lines.join("\n")
+    }
+
+    /// Frames with the bindings of the step, to evaluate expressions in.
+    fn replay(&self) -> Rc<RefCell<Env>> {
+        self.frames
+            .iter()
+            .rev()
+            .fold(self.global.clone(), |parent, (_, bindings)| {
+                let mut env = Env::extend(parent);
+                for (name, value) in bindings.iter() {
+                    env.define(*name, value.clone());
+                }
+                Rc::new(RefCell::new(env))
+            })
+    }
+}
+
+struct Debugger {
+    input: RefCell<Box<dyn BufRead>>,
+    output: RefCell<Box<dyn Write>>,
+    mode: Cell<Mode>,
+    /// The number of calls that have not returned.
+    depth: Cell<usize>,
+    /// Started by `(break)`, and gone once continued.
+    transient: bool,
+    /// The lists evaluated last, the current one at the back.
+    history: RefCell<VecDeque<Step>>,
+}
+
+thread_local! {
+    static ACTIVE: RefCell<Option<(HookId, Rc<Debugger>)>> = const { RefCell::new(None) };
+}
+
+fn shortened(obj: &Object) -> String {
+    let printed = obj.to_string();
+    if printed.chars().count() > MAX_EXPR_LEN {
+        printed.chars().take(MAX_EXPR_LEN).collect::<String>() + " ..."
+    } else {
+        printed
+    }
+}
+
+impl Debugger {
+    fn new(input: Box<dyn BufRead>, output: Box<dyn Write>, transient: bool) -> Self {
+        Self {
+            input: RefCell::new(input),
+            output: RefCell::new(output),
+            mode: Cell::new(Mode::Step),
+            depth: Cell::new(0),
+            transient,
+            history: RefCell::new(VecDeque::new()),
+        }
+    }
+
+    fn record(&self, expr: &Object, env: &Rc<RefCell<Env>>) {
+        let mut history = self.history.borrow_mut();
+        let step = Step::new(expr, env, history.back());
+        if history.len() == HISTORY_LEN {
+            history.pop_front();
+        }
+        history.push_back(step);
+    }
+
+    /// Shows `expr` and runs commands until one resumes evaluation.
+    fn stop(&self, expr: &Object, env: &Rc<RefCell<Env>>) -> io::Result<Mode> {
+        let mut output = self.output.borrow_mut();
+        writeln!(output, "-> {}", shortened(expr))?;
+
+        // How many steps back in the history the commands are, and an
+        // environment with the bindings of then.
+        let mut back = 0;
+        let mut past = None;
+        loop {
+            write!(output, "debug> ")?;
+            output.flush()?;
+            let mut line = String::new();
+            if self.input.borrow_mut().read_line(&mut line)? == 0 {
+                writeln!(output)?;
+                return Ok(Mode::Run);
+            }
+
+            let command = line.trim();
+            match command {
+                "" | "s" | "step" if back > 0 => back -= 1,
+                "b" | "back" if back + 1 < self.history.borrow().len() => back += 1,
+                "b" | "back" => {
+                    writeln!(output, "no earlier step is remembered")?;
+                    continue;
+                }
+                "" | "s" | "step" => return Ok(Mode::Step),
+                "n" | "next" => return Ok(Mode::Next(self.depth.get())),
+                "c" | "continue" => return Ok(Mode::Run),
+                "f" | "frames" => {
+                    let history = self.history.borrow();
+                    let step = &history[history.len() - 1 - back];
+                    writeln!(output, "{}", step.show_frames())?;
+                    continue;
+                }
+                "h" | "help" => {
+                    writeln!(output, "{}", HELP)?;
+                    continue;
+                }
+                source => {
+                    let env = match back {
+                        0 => env,
+                        _ => past.get_or_insert_with(|| {
+                            let history = self.history.borrow();
+                            history[history.len() - 1 - back].replay()
+                        }),
+                    };
+                    match eval_str(source, env) {
+                        Ok(value) => writeln!(output, "{}", value)?,
+                        Err(e) => writeln!(output, "{}", e)?,
+                    }
+                    continue;
+                }
+            }
+
+            // Moved through the history.
+            past = None;
+            let history = self.history.borrow();
+            let step = &history[history.len() - 1 - back];
+            match back {
+                0 => writeln!(output, "-> {}", shortened(&step.expr))?,
+                1 => writeln!(output, "-> {} [1 step back]", shortened(&step.expr))?,
+                _ => writeln!(output, "-> {} [{} steps back]", shortened(&step.expr), back)?,
+            }
+        }
+    }
+}
+
+impl Hook for Debugger {
+    fn on_call(&self, _func: &Object, _args: &[Object]) {
+        self.depth.set(self.depth.get() + 1);
+    }
+
+    fn on_return(&self, _func: &Object, _result: &Object) {
+        self.depth.set(self.depth.get().saturating_sub(1));
+    }
+
+    fn on_eval(&self, expr: &Object, env: &Rc<RefCell<Env>>) {
+        self.record(expr, env);
+        let stops = match self.mode.get() {
+            Mode::Run => false,
+            Mode::Step => true,
+            Mode::Next(depth) => self.depth.get() <= depth,
+        };
+        if !stops {
+            return;
+        }
+
+        let mode = self.stop(expr, env).unwrap_or_else(|e| {
+            eprintln!("debugger: {}", e);
+            Mode::Run
+        });
+        self.mode.set(mode);
+        if mode == Mode::Run && self.transient {
+            uninstall();
+        }
+    }
+}
+
+fn install(debugger: Rc<Debugger>) {
+    let id = hooks::add(debugger.clone());
+    ACTIVE.with(|active| *active.borrow_mut() = Some((id, debugger)));
+}
+
+fn uninstall() {
+    if let Some((id, _)) = ACTIVE.with(|active| active.borrow_mut().take()) {
+        hooks::remove(id);
+    }
+}
+
+/// Evaluates `program` in `env` with the debugger on, stopping before its
+/// first list, and reading commands from `input`.
+pub fn debug(
+    program: &str,
+    env: &Rc<RefCell<Env>>,
+    input: impl BufRead + 'static,
+    output: impl Write + 'static,
+) -> Result<Object, EvalError> {
+    if ACTIVE.with(|active| active.borrow().is_some()) {
+        return Err(EvalError::new("already debugging"));
+    }
+
+    install(Rc::new(Debugger::new(
+        Box::new(input),
+        Box::new(output),
+        false,
+    )));
+    let result = eval_str(program, env);
+    uninstall();
+
+    result
+}
+
+/// `(break)` stops in the debugger before the next list is evaluated,
+/// starting one on the terminal if none is on.
+pub fn break_(args: &[Object]) -> Result<Object, EvalError> {
+    if !args.is_empty() {
+        return Err(EvalError::new("break expects no arguments"));
+    }
+
+    let debugger = ACTIVE.with(|active| active.borrow().as_ref().map(|(_, d)| d.clone()));
+    match debugger {
+        Some(debugger) => debugger.mode.set(Mode::Step),
+        None => install(Rc::new(Debugger::new(
+            Box::new(BufReader::new(io::stdin())),
+            Box::new(io::stdout()),
+            true,
+        ))),
+    }
+
+    Ok(Object::Void)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builtins::global_env;
+
+    /// An output the test can still read after the debugger took it.
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn session(program: &str, commands: &str) -> (Result<Object, EvalError>, String) {
+        let env = global_env();
+        eval_str("(define (sq x) (* x x))", &env).unwrap();
+
+        let output = Shared::default();
+        let input = io::Cursor::new(commands.as_bytes().to_vec());
+        let result = debug(program, &env, input, output.clone());
+        let output = String::from_utf8(output.0.borrow().clone()).unwrap();
+        (result, output)
+    }
+
+    #[test]
+    fn test_step() {
+        let (result, output) = session("(+ (sq 3) 1)", "s\ns\nframes\n(+ x 1)\nc\n");
+        assert_eq!(result.unwrap(), Object::Integer(10));
+        assert_eq!(
+            output,
+            "-> (+ (sq 3) 1)\ndebug> -> (sq 3)\ndebug> -> (* x x)\n\
+             debug> #0 x = 3\n#1 <global>\ndebug> 4\ndebug> "
+        );
+    }
+
+    #[test]
+    fn test_next_and_break() {
+        let (result, output) = session(
+            "(begin (sq 2) (sq (begin (break) 5)))",
+            "s\nn\nn\nn\nc\nframes\nc\n",
+        );
+        assert_eq!(result.unwrap(), Object::Integer(25));
+        assert_eq!(
+            output,
+            "-> (begin (sq 2) (sq (begin (break) 5)))\ndebug> -> (sq 2)\ndebug> \
+             -> (sq (begin (break) 5))\ndebug> -> (begin (break) 5)\ndebug> \
+             -> (break)\ndebug> -> (* x x)\ndebug> #0 x = 5\n#1 <global>\ndebug> "
+        );
+    }
+
+    #[test]
+    fn test_back() {
+        let (result, output) = session("(sq 2)", "b\nc\n");
+        assert_eq!(result.unwrap(), Object::Integer(4));
+        assert_eq!(
+            output,
+            "-> (sq 2)\ndebug> no earlier step is remembered\ndebug> "
+        );
+
+        let (result, output) = session(
+            "(begin (define (f x) (set! x (+ x 1)) (break) (+ x 0)) (f 1))",
+            "c\nf\nb\nb\nf\nx\n(list x y)\ns\ns\nx\nc\n",
+        );
+        assert_eq!(result.unwrap(), Object::Integer(2));
+        assert!(output.ends_with(
+            "-> (+ x 0)\ndebug> #0 x = 2\n#1 <global>\n\
+             debug> -> (break) [1 step back]\n\
+             debug> -> (+ x 1) [2 steps back]\n\
+             debug> #0 x = 1\n#1 <global>\ndebug> 1\n\
+             debug> Evaluation error: unbound symbol: y\n\
+             debug> -> (break) [1 step back]\n\
+             debug> -> (+ x 0)\ndebug> 2\ndebug> "
+        ));
+    }
+}
@@ -0,0 +1,225 @@
+//! Diagnostics: error messages that point into the source they are about.
+//!
+//! A `Diagnostic` located with `at` keeps the line its span starts on, so
+//! it can be rendered long after the source is gone, rustc style:
+//!
+//! ```text
+//! error: missing ')'
+//!  --> script.lisp:2:1
+//!   |
+//! 2 | (define (f x)
+//!   | ^
+//!   = hint: the list opened here is never closed
+//! ```
+//!
+//! Notes, such as the calls a runtime error happened in, follow the hint.
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+use core::fmt::Formatter;
+
+/// A range of byte offsets into some source text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    /// The smallest span covering both `self` and `other`.
+    pub fn to(self, other: Span) -> Span {
+        Span::new(self.start.min(other.start), self.end.max(other.end))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Note => "note",
+        })
+    }
+}
+
+/// Where in the source a diagnostic points: a 1-based line and column, the
+/// text of that line, and how many of its characters to underline.
+#[derive(Debug, Clone, PartialEq)]
+struct Location {
+    line: usize,
+    column: usize,
+    text: String,
+    width: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub span: Option<Span>,
+    pub hint: Option<String>,
+    pub notes: Vec<String>,
+    /// The name of the source, such as its file name.
+    pub origin: Option<String>,
+    location: Option<Location>,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, message: impl Into<String>) -> Self {
+        Self {
+            severity,
+            message: message.into(),
+            span: None,
+            hint: None,
+            notes: Vec::new(),
+            origin: None,
+            location: None,
+        }
+    }
+
+    pub fn error(message: impl Into<String>) -> Self {
+        Self::new(Severity::Error, message)
+    }
+
+    pub fn with_hint(mut self, hint: impl Into<String>) -> Self {
+        self.hint = Some(hint.into());
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    /// Names the source, unless it has been named already.
+    pub fn with_origin(mut self, origin: impl Into<String>) -> Self {
+        self.origin.get_or_insert_with(|| origin.into());
+        self
+    }
+
+    /// Points the diagnostic at `span` of `source`. Spans past the end of
+    /// the source point at its end.
+    pub fn at(mut self, source: &str, span: Span) -> Self {
+        let start = floor_char_boundary(source, span.start);
+        let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[start..]
+            .find('\n')
+            .map_or(source.len(), |i| start + i);
+        let end = floor_char_boundary(source, span.end).clamp(start, line_end);
+
+        self.location = Some(Location {
+            line: source[..start].matches('\n').count() + 1,
+            column: source[line_start..start].chars().count() + 1,
+            text: source[line_start..line_end].trim_end().to_string(),
+            width: source[start..end].chars().count().max(1),
+        });
+        self.span = Some(span);
+        self
+    }
+
+    /// The 1-based line and column the diagnostic points at, if located.
+    pub fn line_column(&self) -> Option<(usize, usize)> {
+        self.location.as_ref().map(|l| (l.line, l.column))
+    }
+
+    /// The text of the line the diagnostic points at, if located.
+    pub fn source_line(&self) -> Option<&str> {
+        self.location.as_ref().map(|l| l.text.as_str())
+    }
+}
+
+fn floor_char_boundary(source: &str, mut index: usize) -> usize {
+    index = index.min(source.len());
+    while !source.is_char_boundary(index) {
+        index -= 1;
+    }
+
+    index
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.severity, self.message)?;
+
+        let gutter = match &self.location {
+            Some(location) => {
+                let gutter = " ".repeat(location.line.to_string().len());
+                let origin = self.origin.as_deref().map(|o| format!("{}:", o));
+                write!(
+                    f,
+                    "\n{}--> {}{}:{}\n{} |\n{} | {}\n{} | {}{}",
+                    gutter,
+                    origin.unwrap_or_default(),
+                    location.line,
+                    location.column,
+                    gutter,
+                    location.line,
+                    location.text,
+                    gutter,
+                    " ".repeat(location.column - 1),
+                    "^".repeat(location.width)
+                )?;
+                gutter
+            }
+            None => {
+                if let Some(origin) = &self.origin {
+                    write!(f, "\n --> {}", origin)?;
+                }
+                String::new()
+            }
+        };
+
+        if let Some(hint) = &self.hint {
+            write!(f, "\n{} = hint: {}", gutter, hint)?;
+        }
+        for note in &self.notes {
+            write!(f, "\n{} = note: {}", gutter, note)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let source = "(define x 1)\n(car  'é  x)\n";
+        let diagnostic = Diagnostic::error("car expects a pair")
+            .at(source, Span::new(19, 30))
+            .with_hint("quote less")
+            .with_note("in f")
+            .with_origin("a.lisp")
+            .with_origin("b.lisp");
+
+        assert_eq!(diagnostic.line_column(), Some((2, 7)));
+        assert_eq!(
+            diagnostic.to_string(),
+            "error: car expects a pair\n \
+             --> a.lisp:2:7\n  \
+             |\n\
+             2 | (car  'é  x)\n  \
+             |       ^^^^^^\n  \
+             = hint: quote less\n  \
+             = note: in f"
+        );
+
+        let unlocated = Diagnostic::new(Severity::Warning, "unused").with_origin("a.lisp");
+        assert_eq!(unlocated.to_string(), "warning: unused\n --> a.lisp");
+    }
+}
@@ -0,0 +1,231 @@
+//! `(diff a b)`: the edits that turn one nested structure into another.
+//!
+//! Lists are aligned on their longest common subsequence of `equal?`
+//! elements, so an element added or dropped in the middle is reported as
+//! one insertion or removal rather than as every later element changing.
+//! Elements that take each other's place are compared in turn, down to the
+//! atoms that differ. A path is the list of indices leading to the edited
+//! element: into the old value for changes and removals, and into the new
+//! value for insertions.
+
+use std::fmt;
+use std::fmt::Formatter;
+
+use crate::eval::EvalError;
+use crate::object::Object;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Edit<T> {
+    Changed { path: Vec<usize>, old: T, new: T },
+    Inserted { path: Vec<usize>, value: T },
+    Removed { path: Vec<usize>, value: T },
+}
+
+impl<T> Edit<T> {
+    pub fn path(&self) -> &[usize] {
+        match self {
+            Edit::Changed { path, .. }
+            | Edit::Inserted { path, .. }
+            | Edit::Removed { path, .. } => path,
+        }
+    }
+
+    pub fn map<U>(self, f: impl Fn(T) -> U) -> Edit<U> {
+        match self {
+            Edit::Changed { path, old, new } => Edit::Changed {
+                path,
+                old: f(old),
+                new: f(new),
+            },
+            Edit::Inserted { path, value } => Edit::Inserted {
+                path,
+                value: f(value),
+            },
+            Edit::Removed { path, value } => Edit::Removed {
+                path,
+                value: f(value),
+            },
+        }
+    }
+}
+
+impl Edit<Object> {
+    /// The edit as `(changed path old new)`, `(inserted path value)` or
+    /// `(removed path value)`.
+    pub fn to_object(&self) -> Object {
+        let path = Object::list(self.path().iter().map(|&i| Object::Integer(i as i64)));
+        match self {
+            Edit::Changed { old, new, .. } => {
+                Object::list([Object::symbol("changed"), path, old.clone(), new.clone()])
+            }
+            Edit::Inserted { value, .. } => {
+                Object::list([Object::symbol("inserted"), path, value.clone()])
+            }
+            Edit::Removed { value, .. } => {
+                Object::list([Object::symbol("removed"), path, value.clone()])
+            }
+        }
+    }
+}
+
+impl fmt::Display for Edit<Object> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let path = Object::list(self.path().iter().map(|&i| Object::Integer(i as i64)));
+        match self {
+            Edit::Changed { old, new, .. } => write!(f, "at {}: {} became {}", path, old, new),
+            Edit::Inserted { value, .. } => write!(f, "at {}: {} was inserted", path, value),
+            Edit::Removed { value, .. } => write!(f, "at {}: {} was removed", path, value),
+        }
+    }
+}
+
+/// The edits turning `old` into `new`, empty if they are `equal?`.
+pub fn diff(old: &Object, new: &Object) -> Vec<Edit<Object>> {
+    let mut edits = Vec::new();
+    diff_into(old, new, &mut Vec::new(), &mut edits);
+
+    edits
+}
+
+fn diff_into(old: &Object, new: &Object, path: &mut Vec<usize>, edits: &mut Vec<Edit<Object>>) {
+    if old == new {
+        return;
+    }
+
+    match (old.to_vec(), new.to_vec()) {
+        (Some(old), Some(new)) => diff_lists(&old, &new, path, edits),
+        _ => edits.push(Edit::Changed {
+            path: path.clone(),
+            old: old.clone(),
+            new: new.clone(),
+        }),
+    }
+}
+
+fn diff_lists(
+    old: &[Object],
+    new: &[Object],
+    path: &mut Vec<usize>,
+    edits: &mut Vec<Edit<Object>>,
+) {
+    // common[i][j] is the length of the longest common subsequence of
+    // old[i..] and new[j..].
+    let mut common = vec![vec![0; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = if old[i] == new[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    // Walks the alignment, collecting the unmatched elements between two
+    // matches and pairing them off when the run ends.
+    let (mut i, mut j) = (0, 0);
+    let (mut removed, mut inserted) = (Vec::new(), Vec::new());
+    loop {
+        let matched = i < old.len() && j < new.len() && old[i] == new[j];
+        if matched || (i == old.len() && j == new.len()) {
+            let pairs = removed.len().min(inserted.len());
+            for (&r, &n) in removed.iter().zip(&inserted) {
+                path.push(r);
+                diff_into(&old[r], &new[n], path, edits);
+                path.pop();
+            }
+            for &r in &removed[pairs..] {
+                edits.push(Edit::Removed {
+                    path: with_index(path, r),
+                    value: old[r].clone(),
+                });
+            }
+            for &n in &inserted[pairs..] {
+                edits.push(Edit::Inserted {
+                    path: with_index(path, n),
+                    value: new[n].clone(),
+                });
+            }
+            removed.clear();
+            inserted.clear();
+
+            if !matched {
+                return;
+            }
+            i += 1;
+            j += 1;
+        } else if j == new.len() || (i < old.len() && common[i + 1][j] >= common[i][j + 1]) {
+            removed.push(i);
+            i += 1;
+        } else {
+            inserted.push(j);
+            j += 1;
+        }
+    }
+}
+
+fn with_index(path: &[usize], index: usize) -> Vec<usize> {
+    let mut path = path.to_vec();
+    path.push(index);
+    path
+}
+
+/// `(diff old new)` is the list of edits turning `old` into `new`, each
+/// `(changed path old new)`, `(inserted path value)` or
+/// `(removed path value)`.
+pub fn diff_builtin(args: &[Object]) -> Result<Object, EvalError> {
+    match args {
+        [old, new] => Ok(Object::list(
+            diff(old, new)
+                .iter()
+                .map(Edit::to_object)
+                .collect::<Vec<_>>(),
+        )),
+        _ => Err(EvalError::new("diff expects two arguments")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builtins::global_env;
+    use crate::eval::eval_str;
+
+    fn diff_str(program: &str) -> String {
+        eval_str(program, &global_env()).unwrap().to_string()
+    }
+
+    #[test]
+    fn test_diff() {
+        assert_eq!(diff_str("(diff '(1 (2 3) 4) '(1 (2 3) 4))"), "()");
+        assert_eq!(diff_str("(diff 1 2)"), "((changed () 1 2))");
+        assert_eq!(
+            diff_str("(diff '(a b c d) '(a c d e))"),
+            "((removed (1) b) (inserted (3) e))"
+        );
+        assert_eq!(
+            diff_str("(diff '(define (f x) (+ x 1)) '(define (f x) (+ x 2)))"),
+            "((changed (2 2) 1 2))"
+        );
+        assert_eq!(
+            diff_str("(diff '(1 2 3) '(1 (2) 9 3))"),
+            "((changed (1) 2 (2)) (inserted (2) 9))"
+        );
+        assert_eq!(
+            diff_str("(diff '(a . b) '(a . c))"),
+            "((changed () (a . b) (a . c)))"
+        );
+    }
+
+    #[test]
+    fn test_display() {
+        let old = eval_str("'(1 (2 3))", &global_env()).unwrap();
+        let new = eval_str("'(1 (2 4) 5)", &global_env()).unwrap();
+        let edits = diff(&old, &new)
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+
+        assert_eq!(edits, ["at (1 1): 3 became 4", "at (2): 5 was inserted"]);
+    }
+}
@@ -0,0 +1,254 @@
+//! Enumerations, and `case` to branch on them.
+//!
+//! `(define-enum color red green blue)` defines the constants `red`,
+//! `green` and `blue`, whose values are their names as symbols, and the
+//! predicate `color?`, true of those symbols only. It is also how a
+//! schema for `lisp-rs codegen` describes an enum without fields.
+//!
+//! `case` evaluates the clause listing the value of its key, or its `else`
+//! clause:
+//!
+//! ```text
+//! (case light ((red) 'stop) ((amber) 'slow) ((green) 'go))
+//! ```
+//!
+//! A `case` without an `else` clause that lists variants of an enum should
+//! list all of them; `check` finds the ones that do not before the program
+//! runs, for the warnings shown when a script is run or compiled and by the
+//! language server.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::diagnostic::{Diagnostic, Severity};
+use crate::env::Env;
+use crate::eval::{eval, EvalError};
+use crate::hooks;
+use crate::object::Object;
+use crate::parser::{parse_with_list_spans, ListSpans};
+use crate::symbol::Symbol;
+
+fn variants(list: &[Object]) -> Option<(Symbol, Vec<Symbol>)> {
+    let (name, variants) = match list {
+        [_, Object::Symbol(name), variants @ ..] if !variants.is_empty() => (*name, variants),
+        _ => return None,
+    };
+    let variants = variants
+        .iter()
+        .map(|variant| match variant {
+            Object::Symbol(variant) => Some(*variant),
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>()?;
+
+    Some((name, variants))
+}
+
+/// `(define-enum name variant...)` defines the variants and `name?`.
+pub fn define_enum(list: &[Object], env: &Rc<RefCell<Env>>) -> Result<Object, EvalError> {
+    let (name, variants) = variants(list)
+        .ok_or_else(|| EvalError::new("define-enum expects a name and variant names"))?;
+
+    let symbols = variants.iter().map(|variant| Object::Symbol(*variant));
+    let predicate = eval(
+        &Object::list([
+            Object::symbol("lambda"),
+            Object::list([Object::symbol("value")]),
+            Object::list([
+                Object::symbol("case"),
+                Object::symbol("value"),
+                Object::list([Object::list(symbols.clone()), Object::Bool(true)]),
+                Object::list([Object::symbol("else"), Object::Bool(false)]),
+            ]),
+        ]),
+        env,
+    )?;
+
+    let definitions = symbols
+        .map(|variant| (variant.to_string(), variant))
+        .chain([(format!("{}?", name), predicate)]);
+    for (name, value) in definitions {
+        hooks::define(&name, &value);
+        env.borrow_mut().define(Symbol::intern(&name), value);
+    }
+
+    Ok(Object::Void)
+}
+
+/// Evaluates `(case key clause...)` up to the last form of the clause the
+/// value of `key` selects, which is returned to be evaluated in tail
+/// position; void if no clause does.
+pub fn case(list: &[Object], env: &Rc<RefCell<Env>>) -> Result<Object, EvalError> {
+    let (key, clauses) = match list {
+        [_, key, clauses @ ..] => (eval(key, env)?, clauses),
+        _ => return Err(EvalError::new("case expects a key and clauses")),
+    };
+
+    for clause in clauses {
+        let (datums, body) = match clause.to_vec() {
+            Some(mut body) if body.len() > 1 => (body.remove(0), body),
+            _ => return Err(EvalError::new(format!("invalid case clause: {}", clause))),
+        };
+        let selected = match &datums {
+            Object::Symbol(word) if *word == "else" => true,
+            datums => datums
+                .to_vec()
+                .ok_or_else(|| EvalError::new(format!("invalid case datums: {}", datums)))?
+                .contains(&key),
+        };
+        if let (true, Some((last, init))) = (selected, body.split_last()) {
+            for form in init {
+                eval(form, env)?;
+            }
+            return Ok(last.clone());
+        }
+    }
+
+    Ok(Object::Void)
+}
+
+fn is_form(obj: &Object, name: &str) -> bool {
+    matches!(obj, Object::Pair(pair) if matches!(pair.car(), Object::Symbol(head) if head == name))
+}
+
+/// Calls `f` with every list in `obj` that is code rather than quoted data.
+fn walk(obj: &Object, f: &mut impl FnMut(&Object, &[Object])) {
+    if is_form(obj, "quote") {
+        return;
+    }
+    if let Some(items) = obj.to_vec().filter(|_| matches!(obj, Object::Pair(_))) {
+        f(obj, &items);
+        for item in &items {
+            walk(item, f);
+        }
+    }
+}
+
+fn missing_variants(
+    list: &[Object],
+    enums: &HashMap<Symbol, (Symbol, Vec<Symbol>)>,
+) -> Option<(Symbol, Vec<Symbol>)> {
+    let mut listed = Vec::new();
+    for clause in list.get(2..)? {
+        match clause {
+            Object::Pair(pair) => match &pair.car() {
+                Object::Symbol(word) if *word == "else" => return None,
+                datums => listed.extend(datums.to_vec()?),
+            },
+            _ => return None,
+        }
+    }
+
+    let (name, variants) = listed.iter().find_map(|datum| match datum {
+        Object::Symbol(variant) => enums.get(variant),
+        _ => None,
+    })?;
+    let missing = variants
+        .iter()
+        .filter(|variant| !listed.contains(&Object::Symbol(**variant)))
+        .copied()
+        .collect::<Vec<_>>();
+
+    (!missing.is_empty()).then_some((*name, missing))
+}
+
+/// Warns about every `case` in `program` that lists some variants of an
+/// enum it defines, but not all, and has no `else` clause. Programs that do
+/// not parse get no warnings, as they cannot run.
+pub fn check(program: &str) -> Vec<Diagnostic> {
+    let (forms, spans): (_, ListSpans) = match parse_with_list_spans(program) {
+        Ok(parsed) => parsed,
+        Err(_) => return Vec::new(),
+    };
+
+    // Each variant, with the enum it belongs to.
+    let mut enums = HashMap::new();
+    for (form, _) in &forms {
+        walk(form, &mut |_, list| {
+            if !matches!(&list[0], Object::Symbol(head) if *head == "define-enum") {
+                return;
+            }
+            if let Some((name, variants)) = variants(list) {
+                for variant in &variants {
+                    enums.insert(*variant, (name, variants.clone()));
+                }
+            }
+        });
+    }
+
+    let mut warnings = Vec::new();
+    for (form, span) in &forms {
+        walk(form, &mut |obj, list| {
+            if !matches!(&list[0], Object::Symbol(head) if *head == "case") {
+                return;
+            }
+            if let Some((name, missing)) = missing_variants(list, &enums) {
+                let missing = missing
+                    .iter()
+                    .map(|variant| variant.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                warnings.push(
+                    Diagnostic::new(
+                        Severity::Warning,
+                        format!("case over {} does not handle {}", name, missing),
+                    )
+                    .at(program, spans.get(obj).unwrap_or(*span))
+                    .with_hint("add clauses for them, or an else clause"),
+                );
+            }
+        });
+    }
+
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builtins::global_env;
+    use crate::eval::eval_str;
+
+    #[test]
+    fn test_define_enum_and_case() {
+        let env = global_env();
+        let eval = |program: &str| match eval_str(program, &env) {
+            Ok(value) => value.to_string(),
+            Err(e) => e.message().to_string(),
+        };
+
+        eval("(define-enum light red amber green)");
+        eval("(define (next light) (case light ((red) green) ((amber) red) ((green) amber)))");
+        assert_eq!(eval("(next (next red))"), "amber");
+        assert_eq!(
+            eval("(list (light? amber) (light? 'blue) (light? 1))"),
+            "(#t #f #f)"
+        );
+        assert_eq!(
+            eval("(case 3 ((1 2) 'low) ((3 4) 'high) (else 'odd))"),
+            "high"
+        );
+        assert_eq!(eval("(case 5 ((1 2) 'low) (else 'other))"), "other");
+        assert_eq!(
+            eval("(define-enum light (red))"),
+            "define-enum expects a name and variant names"
+        );
+        assert_eq!(eval("(case 1 (1 'one))"), "invalid case datums: 1");
+    }
+
+    #[test]
+    fn test_check() {
+        let program = "(define-enum light red amber green)\n\
+                       (define (stop? light)\n  (case light ((red) #t) ((amber) #t)))\n\
+                       (define (go? light) (case light ((green) #t) (else #f)))\n\
+                       (case 1 ((1) 'one))\n\
+                       '(case red ((red) 1))";
+        let warnings = check(program);
+
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].message, "case over light does not handle green");
+        assert_eq!(warnings[0].line_column(), Some((3, 3)));
+        assert!(check("(case").is_empty());
+    }
+}
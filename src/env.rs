@@ -0,0 +1,199 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::gc::{Edge, Trace};
+use crate::object::Object;
+use crate::symbol::{BuildSymbolHasher, Symbol};
+use crate::testing::Test;
+
+thread_local! {
+    static GENERATION: Cell<u64> = const { Cell::new(0) };
+    /// The global environment of the evaluation under way, if any.
+    static CURRENT: RefCell<Option<Rc<RefCell<Env>>>> = const { RefCell::new(None) };
+}
+
+/// A counter bumped whenever a variable is bound or assigned by name, so
+/// that the VM can tell when the global values it cached may be stale.
+pub(crate) fn generation() -> u64 {
+    GENERATION.with(Cell::get)
+}
+
+fn bump_generation() {
+    GENERATION.with(|generation| generation.set(generation.get() + 1));
+}
+
+#[derive(Default)]
+pub struct Env {
+    parent: Option<Rc<RefCell<Env>>>,
+    vars: HashMap<Symbol, Object, BuildSymbolHasher>,
+    /// The tests defined by `deftest`, in a global environment.
+    tests: Vec<Rc<Test>>,
+}
+
+/// The global environment `env` is in.
+pub(crate) fn global(env: &Rc<RefCell<Env>>) -> Rc<RefCell<Env>> {
+    let mut global = env.clone();
+    loop {
+        let parent = global.borrow().parent().cloned();
+        match parent {
+            Some(parent) => global = parent,
+            None => return global,
+        }
+    }
+}
+
+/// Runs `f` as an evaluation in the global environment `env` is in, which
+/// `current` returns until it ends.
+pub(crate) fn within<T>(env: &Rc<RefCell<Env>>, f: impl FnOnce() -> T) -> T {
+    struct Restore(Option<Rc<RefCell<Env>>>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            CURRENT.with(|current| *current.borrow_mut() = self.0.take());
+        }
+    }
+
+    let _restore = Restore(CURRENT.with(|current| current.replace(Some(global(env)))));
+    f()
+}
+
+/// Whether an evaluation is under way.
+pub(crate) fn is_within() -> bool {
+    CURRENT.with(|current| current.borrow().is_some())
+}
+
+/// The global environment of the evaluation under way, for `eval` and
+/// `interaction-environment`.
+pub(crate) fn current() -> Option<Rc<RefCell<Env>>> {
+    CURRENT.with(|current| current.borrow().clone())
+}
+
+impl Env {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn extend(parent: Rc<RefCell<Env>>) -> Self {
+        Self {
+            parent: Some(parent),
+            ..Self::default()
+        }
+    }
+
+    pub(crate) fn parent(&self) -> Option<&Rc<RefCell<Env>>> {
+        self.parent.as_ref()
+    }
+
+    pub fn get(&self, name: impl Into<Symbol>) -> Option<Object> {
+        let name = name.into();
+
+        match self.vars.get(&name) {
+            Some(value) => Some(value.clone()),
+            None => self
+                .parent
+                .as_ref()
+                .and_then(|parent| parent.borrow().get(name)),
+        }
+    }
+
+    /// Whether `name` is bound in this frame itself.
+    pub(crate) fn binds(&self, name: Symbol) -> bool {
+        self.vars.contains_key(&name)
+    }
+
+    /// The names bound in this frame and its parents, each once.
+    pub fn names(&self) -> Vec<Symbol> {
+        let mut names = self.vars.keys().copied().collect::<Vec<_>>();
+        if let Some(parent) = &self.parent {
+            let parent = parent.borrow().names();
+            names.extend(
+                parent
+                    .into_iter()
+                    .filter(|name| !self.vars.contains_key(name)),
+            );
+        }
+
+        names
+    }
+
+    /// The bindings of this frame alone, sorted by name.
+    pub(crate) fn bindings(&self) -> Vec<(Symbol, Object)> {
+        let mut bindings = self
+            .vars
+            .iter()
+            .map(|(name, value)| (*name, value.clone()))
+            .collect::<Vec<_>>();
+        bindings.sort_by_key(|(name, _)| name.as_str());
+        bindings
+    }
+
+    pub(crate) fn tests(&self) -> &[Rc<Test>] {
+        &self.tests
+    }
+
+    pub(crate) fn tests_mut(&mut self) -> &mut Vec<Rc<Test>> {
+        &mut self.tests
+    }
+
+    /// Binds `name` in this frame, shadowing any binding in the parents.
+    pub fn define(&mut self, name: impl Into<Symbol>, value: Object) {
+        bump_generation();
+        self.vars.insert(name.into(), value);
+    }
+
+    /// Replaces the value of the nearest existing binding of `name`. Returns
+    /// `false` if the symbol is not bound in this frame or any parent.
+    pub fn set(&mut self, name: impl Into<Symbol>, value: Object) -> bool {
+        let name = name.into();
+        bump_generation();
+
+        if let Some(slot) = self.vars.get_mut(&name) {
+            *slot = value;
+            return true;
+        }
+
+        match &self.parent {
+            Some(parent) => parent.borrow_mut().set(name, value),
+            None => false,
+        }
+    }
+}
+
+impl Trace for Env {
+    fn trace(&self, edge: &mut dyn FnMut(Edge)) {
+        if let Some(parent) = &self.parent {
+            edge(Edge::Env(parent));
+        }
+        for value in self.vars.values() {
+            edge(Edge::Object(value));
+        }
+        for test in &self.tests {
+            test.trace(edge);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.parent = None;
+        self.vars.clear();
+        self.tests.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_updates_parent_binding() {
+        let parent = Rc::new(RefCell::new(Env::new()));
+        parent.borrow_mut().define("x", Object::Integer(1));
+
+        let mut child = Env::extend(parent.clone());
+        assert!(child.set("x", Object::Integer(2)));
+        assert!(!child.set("y", Object::Integer(3)));
+
+        assert_eq!(parent.borrow().get("x"), Some(Object::Integer(2)));
+        assert_eq!(child.get("y"), None);
+    }
+}
@@ -0,0 +1,1274 @@
+use std::cell::RefCell;
+use std::error::Error;
+use std::fmt;
+use std::fmt::Formatter;
+use std::rc::Rc;
+
+use crate::collections::{hash_table_object, HashTable};
+use crate::datetime;
+use crate::diagnostic::{Diagnostic, Span};
+use crate::enums;
+use crate::env::{self, Env};
+use crate::gc;
+use crate::guard;
+use crate::hooks;
+use crate::interrupt;
+use crate::json;
+use crate::limits;
+use crate::limits::Limit;
+use crate::macros;
+use crate::memo::Memo;
+use crate::module;
+use crate::object::{Lambda, Object};
+use crate::parser::{parse_with_list_spans, ListSpans};
+use crate::pattern;
+use crate::process;
+use crate::profile;
+use crate::promise;
+use crate::quasiquote;
+use crate::record;
+use crate::retry;
+use crate::sql;
+use crate::symbol::Symbol;
+use crate::task;
+use crate::testing;
+use crate::trace;
+use crate::values;
+use crate::vm;
+
+#[derive(Debug)]
+pub struct EvalError {
+    err: String,
+    exit_code: Option<i32>,
+    /// Boxed, like the fields below, to keep results small.
+    escape: Option<Box<(u64, Object)>>,
+    /// Whether hooks have been told about this error.
+    reported: bool,
+    /// Where the error happened, once an evaluation of source text knows.
+    diagnostic: Option<Box<Diagnostic>>,
+    trace: Option<Box<Trace>>,
+    limit: Option<Limit>,
+    interrupted: bool,
+    /// Whether the error is data read going over its limits, which `guard`
+    /// can tell apart from other errors.
+    read_limit: bool,
+}
+
+/// The calls deepest in the stack that a trace keeps.
+const MAX_FRAMES: usize = 20;
+
+/// What an error went through as it unwound the evaluation.
+#[derive(Debug, Default)]
+struct Trace {
+    /// The innermost list whose evaluation failed.
+    expr: Option<Object>,
+    /// The calls of procedures whose bodies the error left, innermost first.
+    frames: Vec<Frame>,
+    /// How many more calls there were than `MAX_FRAMES`.
+    omitted: usize,
+    /// What to do about the error, for runtime errors that know.
+    hint: Option<String>,
+}
+
+#[derive(Debug)]
+struct Frame {
+    call: Object,
+    /// The line and column of the call, once located.
+    location: Option<(usize, usize)>,
+    origin: Option<String>,
+}
+
+impl Frame {
+    /// The name of the procedure called.
+    fn name(&self) -> String {
+        match &self.call {
+            Object::Pair(pair) => match pair.car() {
+                Object::Symbol(name) => name.to_string(),
+                _ => String::from("<lambda>"),
+            },
+            _ => String::from("<lambda>"),
+        }
+    }
+
+    fn note(&self) -> String {
+        let name = self.name();
+        match (self.location, &self.origin) {
+            (Some((line, column)), Some(origin)) => {
+                format!("in {}, called at {}:{}:{}", name, origin, line, column)
+            }
+            (Some((line, column)), None) => {
+                format!("in {}, called at {}:{}", name, line, column)
+            }
+            (None, _) => format!("in {}", name),
+        }
+    }
+}
+
+impl EvalError {
+    pub fn new(err: impl Into<String>) -> Self {
+        Self {
+            err: err.into(),
+            exit_code: None,
+            escape: None,
+            reported: false,
+            diagnostic: None,
+            trace: None,
+            limit: None,
+            interrupted: false,
+            read_limit: false,
+        }
+    }
+
+    /// The error raised by `(exit n)`: it unwinds the evaluation and leaves it
+    /// to the host to end the process.
+    pub fn exit(code: i32) -> Self {
+        Self {
+            err: format!("exit with code {}", code),
+            exit_code: Some(code),
+            escape: None,
+            reported: false,
+            diagnostic: None,
+            trace: None,
+            limit: None,
+            interrupted: false,
+            read_limit: false,
+        }
+    }
+
+    /// The error raised by invoking an escape continuation: it unwinds the
+    /// evaluation up to the `call/cc` that created continuation `id`.
+    pub(crate) fn escape(id: u64, value: Object) -> Self {
+        Self {
+            err: String::from("continuation invoked outside of its extent"),
+            exit_code: None,
+            escape: Some(Box::new((id, value))),
+            reported: false,
+            diagnostic: None,
+            trace: None,
+            limit: None,
+            interrupted: false,
+            read_limit: false,
+        }
+    }
+
+    /// The error raised when an evaluation goes over one of its `Limits`.
+    pub(crate) fn limit(limit: Limit, err: String) -> Self {
+        Self {
+            limit: Some(limit),
+            ..Self::new(err)
+        }
+    }
+
+    /// The error raised when data read goes over the `reader::Limits` it is
+    /// read within.
+    pub(crate) fn read_limit(err: String) -> Self {
+        Self {
+            read_limit: true,
+            ..Self::new(err)
+        }
+    }
+
+    /// The error raised when an `InterruptHandle` stops an evaluation.
+    pub(crate) fn interrupted() -> Self {
+        Self {
+            interrupted: true,
+            ..Self::new("evaluation interrupted")
+        }
+    }
+
+    /// An error like one raised on another thread, from what it said there.
+    pub(crate) fn rebuilt(
+        diagnostic: Diagnostic,
+        exit_code: Option<i32>,
+        limit: Option<Limit>,
+        interrupted: bool,
+    ) -> Self {
+        Self {
+            exit_code,
+            limit,
+            interrupted,
+            diagnostic: Some(Box::new(diagnostic.clone())),
+            ..Self::new(diagnostic.message)
+        }
+    }
+
+    /// The message, without the "Evaluation error" prefix.
+    pub fn message(&self) -> &str {
+        &self.err
+    }
+
+    pub fn exit_code(&self) -> Option<i32> {
+        self.exit_code
+    }
+
+    pub fn is_interrupted(&self) -> bool {
+        self.interrupted
+    }
+
+    /// Which limit the evaluation went over, if that is what failed.
+    pub fn limit_exceeded(&self) -> Option<Limit> {
+        self.limit
+    }
+
+    /// The error as a diagnostic, pointing at the list that raised it when
+    /// it came from `eval_str` or `load`, with a note for each procedure
+    /// call it was raised in.
+    pub fn diagnostic(&self) -> Diagnostic {
+        let mut diagnostic = match &self.diagnostic {
+            Some(diagnostic) => (**diagnostic).clone(),
+            None => Diagnostic::error(&self.err),
+        };
+        if let Some(hint) = self.hint().filter(|_| diagnostic.hint.is_none()) {
+            diagnostic = diagnostic.with_hint(hint);
+        }
+        if let Some(trace) = &self.trace {
+            for frame in &trace.frames {
+                diagnostic = diagnostic.with_note(frame.note());
+            }
+            if trace.omitted > 0 {
+                diagnostic = diagnostic.with_note(format!("and {} more calls", trace.omitted));
+            }
+        }
+
+        diagnostic
+    }
+
+    /// The error as data: a hash table with its `kind` (`"error"`,
+    /// `"read-limit"`, `"exit"`, `"escape"`, `"limit"` or `"interrupted"`)
+    /// and `message`,
+    /// and, as far as they are known, the `exit-code` or `limit`, where it
+    /// happened (`origin`, `line`, `column`, `span` and the `source-line`),
+    /// its `hint` and `notes`, and the `backtrace` of calls it was raised
+    /// in, innermost first, with the number of `omitted-calls` past those.
+    /// For tools rendering errors their own way.
+    pub fn to_value(&self) -> Object {
+        let kind = match (self.exit_code, &self.escape, self.limit) {
+            (Some(_), _, _) => "exit",
+            (_, Some(_), _) => "escape",
+            (_, _, Some(_)) => "limit",
+            _ if self.interrupted => "interrupted",
+            _ if self.read_limit => "read-limit",
+            _ => "error",
+        };
+        let mut members = vec![
+            ("kind", Object::string(kind)),
+            ("message", Object::string(self.err.as_str())),
+        ];
+        if let Some(code) = self.exit_code {
+            members.push(("exit-code", Object::Integer(code as i64)));
+        }
+        if let Some(limit) = self.limit {
+            members.push(("limit", Object::string(limit.to_string())));
+        }
+
+        if let Some(diagnostic) = &self.diagnostic {
+            if let Some(origin) = &diagnostic.origin {
+                members.push(("origin", Object::string(origin.as_str())));
+            }
+            if let Some((line, column)) = diagnostic.line_column() {
+                members.push(("line", Object::Integer(line as i64)));
+                members.push(("column", Object::Integer(column as i64)));
+            }
+            if let Some(span) = diagnostic.span {
+                let span = table(vec![
+                    ("start", Object::Integer(span.start as i64)),
+                    ("end", Object::Integer(span.end as i64)),
+                ]);
+                members.push(("span", span));
+            }
+            if let Some(line) = diagnostic.source_line() {
+                members.push(("source-line", Object::string(line)));
+            }
+            let notes = diagnostic
+                .notes
+                .iter()
+                .map(|note| Object::string(note.as_str()));
+            members.push(("notes", Object::list(notes.collect::<Vec<_>>())));
+        }
+        let hint = self.diagnostic.as_ref().and_then(|d| d.hint.as_deref());
+        if let Some(hint) = hint.or(self.hint()) {
+            members.push(("hint", Object::string(hint)));
+        }
+
+        let frames = self.trace.iter().flat_map(|trace| &trace.frames);
+        let backtrace = frames.map(|frame| {
+            let mut members = vec![("name", Object::string(frame.name()))];
+            if let Some(origin) = &frame.origin {
+                members.push(("origin", Object::string(origin.as_str())));
+            }
+            if let Some((line, column)) = frame.location {
+                members.push(("line", Object::Integer(line as i64)));
+                members.push(("column", Object::Integer(column as i64)));
+            }
+            table(members)
+        });
+        members.push(("backtrace", Object::list(backtrace.collect::<Vec<_>>())));
+        if let Some(trace) = self.trace.as_ref().filter(|trace| trace.omitted > 0) {
+            members.push(("omitted-calls", Object::Integer(trace.omitted as i64)));
+        }
+
+        table(members)
+    }
+
+    /// Adds a hint to a runtime error.
+    pub(crate) fn with_hint(mut self, hint: impl Into<String>) -> Self {
+        self.trace.get_or_insert_with(Default::default).hint = Some(hint.into());
+        self
+    }
+
+    fn hint(&self) -> Option<&str> {
+        self.trace.as_ref()?.hint.as_deref()
+    }
+
+    /// `to_value` as JSON.
+    pub fn to_json(&self) -> String {
+        json::stringify(&self.to_value()).expect("error values are plain data")
+    }
+
+    /// Where the error happened, as `Source::locate` gives it, if it is
+    /// known or in `source` or source text being evaluated.
+    pub(crate) fn location(&self, source: Option<&Source>) -> Option<String> {
+        if let Some(diagnostic) = &self.diagnostic {
+            if let Some((line, column)) = diagnostic.line_column() {
+                return Some(match &diagnostic.origin {
+                    Some(origin) => format!("{}:{}:{}", origin, line, column),
+                    None => format!("{}:{}", line, column),
+                });
+            }
+        }
+        let trace = self.trace.as_ref()?;
+        let calls = trace.frames.iter().map(|frame| &frame.call);
+        trace
+            .expr
+            .iter()
+            .chain(calls)
+            .find_map(|list| match source {
+                Some(source) if source.lists.get(list).is_some() => source.locate(list),
+                _ => source_of(list)?.locate(list),
+            })
+    }
+
+    fn is_control_flow(&self) -> bool {
+        self.exit_code.is_some() || self.escape.is_some()
+    }
+
+    /// Whether the evaluation failed, rather than being left by control
+    /// flow or stopped by its host.
+    pub(crate) fn is_failure(&self) -> bool {
+        !self.is_control_flow() && !self.interrupted && self.limit.is_none()
+    }
+
+    /// Records that the error left the evaluation of `expr`, in the body of
+    /// the procedure called by `call`.
+    fn unwound(mut self, expr: &Object, call: Option<Object>) -> Self {
+        if self.is_control_flow() {
+            return self;
+        }
+
+        let trace = self.trace.get_or_insert_with(Default::default);
+        if trace.expr.is_none() && matches!(expr, Object::Pair(_)) {
+            trace.expr = Some(expr.clone());
+        }
+        if let Some(call) = call {
+            if trace.frames.len() < MAX_FRAMES {
+                trace.frames.push(Frame {
+                    call,
+                    location: None,
+                    origin: None,
+                });
+            } else {
+                trace.omitted += 1;
+            }
+        }
+
+        self
+    }
+
+    /// Points the error at the use of a macro when a list its expansion made
+    /// raised it, as that list is in no source.
+    fn expanded(mut self, expansions: &[(Object, Object)]) -> Self {
+        if expansions.is_empty() {
+            return self;
+        }
+        if let Some(expr) = self
+            .trace
+            .as_deref_mut()
+            .and_then(|trace| trace.expr.as_mut())
+        {
+            *expr = macros::origin(expr.clone(), expansions);
+        }
+
+        self
+    }
+
+    /// Points the error at the list of `source` that raised it, or at
+    /// `span`, the top-level form it is in, unless an inner evaluation located it
+    /// already, and locates the calls it was raised in that are in `source`.
+    /// Exits and escapes are not located.
+    pub(crate) fn locate(mut self, source: &str, span: Span, lists: &ListSpans) -> Self {
+        if self.is_control_flow() {
+            return self;
+        }
+
+        let trace = self.trace.as_deref_mut();
+        if self.diagnostic.is_none() {
+            // The failing list may be in a procedure from other source, to
+            // which the innermost call in `source` led.
+            let span = trace
+                .as_ref()
+                .and_then(|trace| {
+                    let calls = trace.frames.iter().map(|frame| &frame.call);
+                    trace
+                        .expr
+                        .iter()
+                        .chain(calls)
+                        .find_map(|list| lists.get(list))
+                })
+                .unwrap_or(span);
+            self.diagnostic = Some(Box::new(Diagnostic::error(&self.err).at(source, span)));
+        }
+        for frame in trace.into_iter().flat_map(|trace| &mut trace.frames) {
+            if frame.location.is_none() {
+                frame.location = lists
+                    .get(&frame.call)
+                    .and_then(|span| Diagnostic::error("").at(source, span).line_column());
+            }
+        }
+
+        self
+    }
+
+    /// Names the source a located error comes from, unless it has a name,
+    /// and the sources of the calls it was raised in located so far.
+    pub fn with_origin(mut self, origin: &str) -> Self {
+        if let Some(diagnostic) = self.diagnostic.take() {
+            self.diagnostic = Some(Box::new(diagnostic.with_origin(origin)));
+        }
+        for frame in self.trace.iter_mut().flat_map(|trace| &mut trace.frames) {
+            if frame.location.is_some() && frame.origin.is_none() {
+                frame.origin = Some(origin.to_string());
+            }
+        }
+
+        self
+    }
+
+    /// Takes the value passed to continuation `id`, if this error is that
+    /// continuation's escape.
+    pub(crate) fn escaped_to(self, id: u64) -> Result<Object, Self> {
+        match self.escape {
+            Some(escape) if escape.0 == id => Ok(escape.1),
+            _ => Err(self),
+        }
+    }
+}
+
+/// A hash table with string keys, as JSON objects are read.
+fn table(members: Vec<(&str, Object)>) -> Object {
+    let mut table = HashTable::default();
+    for (key, value) in members {
+        table.insert(Object::string(key), value);
+    }
+
+    hash_table_object(table)
+}
+
+impl Error for EvalError {}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "Evaluation error: {}", self.err)
+    }
+}
+
+pub fn eval(obj: &Object, env: &Rc<RefCell<Env>>) -> Result<Object, EvalError> {
+    // An evaluation not begun by `eval_str` or an `Interpreter` is in the
+    // global environment of the first it evaluates in.
+    if !env::is_within() {
+        return env::within(env, || eval(obj, env));
+    }
+
+    limits::nested(|| eval_nested(obj, env))
+}
+
+fn eval_nested(obj: &Object, env: &Rc<RefCell<Env>>) -> Result<Object, EvalError> {
+    let mut obj = obj.clone();
+    let mut env = env.clone();
+    // The call of the procedure whose body is being evaluated.
+    let mut call = None;
+    let mut expansions = Vec::new();
+
+    if !hooks::active() {
+        return eval_loop(&mut obj, &mut env, &mut call, &mut expansions, None)
+            .map_err(|err| err.unwound(&obj, call).expanded(&expansions));
+    }
+
+    // The procedures tail called in this evaluation all return its value.
+    let mut calls = Vec::new();
+    let result = eval_loop(
+        &mut obj,
+        &mut env,
+        &mut call,
+        &mut expansions,
+        Some(&mut calls),
+    )
+    .map_err(|err| err.unwound(&obj, call).expanded(&expansions));
+    report(&result, &calls);
+
+    mark_reported(result, true)
+}
+
+fn report(result: &Result<Object, EvalError>, calls: &[Object]) {
+    match result {
+        Ok(value) => {
+            for func in calls.iter().rev() {
+                hooks::ret(func, value);
+            }
+        }
+        Err(err) => {
+            for func in calls.iter().rev() {
+                hooks::unwind(func);
+            }
+            if !err.reported && err.exit_code.is_none() && err.escape.is_none() {
+                hooks::error(err);
+            }
+        }
+    }
+}
+
+/// Marks an error as reported once it has passed through an instrumented
+/// evaluation.
+fn mark_reported<T>(result: Result<T, EvalError>, instrumented: bool) -> Result<T, EvalError> {
+    result.map_err(|mut err| {
+        err.reported |= instrumented;
+        err
+    })
+}
+
+/// Evaluates `obj`, which is replaced by the form in tail position at each
+/// step along with `env`, and `call` by the call of each procedure entered.
+/// `expansions` collects the uses of macros expanded in the procedure's
+/// body and their expansions.
+fn eval_loop(
+    obj: &mut Object,
+    env: &mut Rc<RefCell<Env>>,
+    call: &mut Option<Object>,
+    expansions: &mut Vec<(Object, Object)>,
+    mut calls: Option<&mut Vec<Object>>,
+) -> Result<Object, EvalError> {
+    // Tail positions (`if` branches, the last form of a body) loop here
+    // instead of recursing so that tail-recursive programs run in constant
+    // Rust stack.
+    loop {
+        #[cfg(all(feature = "signals", unix))]
+        crate::signal::poll()?;
+        limits::step()?;
+        interrupt::poll()?;
+
+        let list = match &*obj {
+            Object::Symbol(name) if name.starts_with("#:") => return Ok(obj.clone()),
+            Object::Symbol(name) => {
+                return env
+                    .borrow()
+                    .get(*name)
+                    .ok_or_else(|| EvalError::new(format!("unbound symbol: {}", name)));
+            }
+            Object::Pair(_) => obj
+                .to_vec()
+                .ok_or_else(|| EvalError::new(format!("cannot evaluate improper list: {}", obj)))?,
+            _ => return Ok(obj.clone()),
+        };
+        // Checked on each step, and not with `calls`, as a debugger started
+        // by `(break)` must see the forms in tail position of evaluations
+        // that began before it.
+        if hooks::active() {
+            hooks::eval(obj, env);
+        }
+
+        if let Object::Symbol(head) = &list[0] {
+            match head.form_name().unwrap_or_default() {
+                "quote" => {
+                    check_form_len(&list, 2, "quote")?;
+                    return Ok(list[1].clone());
+                }
+                "if" => {
+                    if list.len() != 3 && list.len() != 4 {
+                        return Err(EvalError::new(
+                            "if expects a condition and one or two branches",
+                        ));
+                    }
+
+                    *obj = if eval(&list[1], env)?.is_truthy() {
+                        list[2].clone()
+                    } else if let Some(alternative) = list.get(3) {
+                        alternative.clone()
+                    } else {
+                        return Ok(Object::Void);
+                    };
+                    continue;
+                }
+                "case" => {
+                    *obj = enums::case(&list, env)?;
+                    continue;
+                }
+                "match" => {
+                    (*obj, *env) = pattern::match_form(&list, env)?;
+                    continue;
+                }
+                "begin" => {
+                    if list.len() == 1 {
+                        return Ok(Object::Void);
+                    }
+
+                    *obj = eval_body(&list[1..], env)?;
+                    continue;
+                }
+                "let" => {
+                    if list.len() < 3 {
+                        return Err(EvalError::new("let expects a binding list and a body"));
+                    }
+
+                    *env = eval_let_bindings(&list[1], env)?;
+                    *obj = eval_body(&list[2..], env)?;
+                    continue;
+                }
+                name => {
+                    if let Some(result) = special_form(name, obj, &list, env) {
+                        return result;
+                    }
+                }
+            }
+        }
+
+        let func = eval(&list[0], env)?;
+        if let Object::Macro(expander) = &func {
+            let expansion = macros::expand(expander, &list)?;
+            expansions.push((obj.clone(), expansion.clone()));
+            *obj = expansion;
+            continue;
+        }
+        let args = list[1..]
+            .iter()
+            .map(|arg| eval(arg, env))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if let Some(calls) = calls.as_deref_mut() {
+            hooks::call(&func, &args);
+            calls.push(func.clone());
+        }
+
+        match func {
+            Object::Builtin(builtin) => return builtin.call(&args),
+            Object::Native(native) => return native.call(args),
+            Object::Memo(memo) => return memo.call(args),
+            Object::Continuation(k) => return Err(k.escape(args)),
+            Object::Closure(closure) => return vm::call(&closure, args),
+            Object::Lambda(lambda) => {
+                *env = bind_arguments(&lambda, args)?;
+                *call = Some(obj.clone());
+                expansions.clear();
+                *obj = eval_body(&lambda.body, env)?;
+            }
+            other => return Err(EvalError::new(format!("not a procedure: {}", other))),
+        }
+    }
+}
+
+/// Parses `program` and evaluates its top-level forms in order, returning the
+/// value of the last one.
+pub fn eval_str(program: &str, env: &Rc<RefCell<Env>>) -> Result<Object, EvalError> {
+    eval_source(program, None, env)
+}
+
+/// Like `eval_str`, naming the source `origin`, such as its file name, in
+/// errors and in the locations of tests defined in it.
+pub fn eval_named(
+    program: &str,
+    origin: &str,
+    env: &Rc<RefCell<Env>>,
+) -> Result<Object, EvalError> {
+    eval_source(program, Some(origin), env).map_err(|e| e.with_origin(origin))
+}
+
+/// Source text being evaluated, with where its lists are.
+pub(crate) struct Source {
+    text: String,
+    origin: Option<String>,
+    lists: ListSpans,
+    /// The forms read from it, kept so that no other list takes the place
+    /// in memory of one of its lists, which are known by their address.
+    forms: Vec<Object>,
+}
+
+impl Source {
+    /// Where `list` is, as `origin:line:column` or `line:column`, if it is
+    /// in this source.
+    pub(crate) fn locate(&self, list: &Object) -> Option<String> {
+        let span = self.lists.get(list)?;
+        let (line, column) = Diagnostic::error("").at(&self.text, span).line_column()?;
+
+        Some(match &self.origin {
+            Some(origin) => format!("{}:{}:{}", origin, line, column),
+            None => format!("{}:{}", line, column),
+        })
+    }
+}
+
+thread_local! {
+    /// The sources of the evaluations of source text under way, innermost
+    /// last.
+    static SOURCES: RefCell<Vec<Rc<Source>>> = const { RefCell::new(Vec::new()) };
+}
+
+/// The source text being evaluated that `list` is in, if any.
+pub(crate) fn source_of(list: &Object) -> Option<Rc<Source>> {
+    SOURCES.with(|sources| {
+        let sources = sources.borrow();
+        sources
+            .iter()
+            .rev()
+            .find(|source| source.lists.get(list).is_some())
+            .cloned()
+    })
+}
+
+fn eval_source(
+    program: &str,
+    origin: Option<&str>,
+    env: &Rc<RefCell<Env>>,
+) -> Result<Object, EvalError> {
+    let (forms, lists) = parse_with_list_spans(program).map_err(|e| {
+        let mut err = EvalError::new(e.to_string());
+        err.diagnostic = Some(Box::new(e.diagnostic(program)));
+        err
+    })?;
+    let (forms, spans): (Vec<_>, Vec<_>) = forms.into_iter().unzip();
+    module::note_embeds(&forms);
+    let source = Rc::new(Source {
+        text: program.to_string(),
+        origin: origin.map(String::from),
+        lists,
+        forms,
+    });
+    SOURCES.with(|sources| sources.borrow_mut().push(source.clone()));
+
+    let mut result = Ok(Object::Void);
+    env::within(env, || {
+        for (form, span) in source.forms.iter().zip(spans) {
+            result = eval(form, env).map_err(|e| e.locate(program, span, &source.lists));
+            if result.is_err() {
+                break;
+            }
+        }
+    });
+
+    SOURCES.with(|sources| sources.borrow_mut().pop());
+    result
+}
+
+/// Applies a procedure to already evaluated arguments.
+pub fn apply(func: &Object, args: Vec<Object>) -> Result<Object, EvalError> {
+    if !hooks::active() {
+        return apply_procedure(func, args);
+    }
+
+    hooks::call(func, &args);
+    let result = apply_procedure(func, args);
+    report(&result, std::slice::from_ref(func));
+
+    mark_reported(result, true)
+}
+
+fn apply_procedure(func: &Object, args: Vec<Object>) -> Result<Object, EvalError> {
+    match func {
+        Object::Builtin(builtin) => builtin.call(&args),
+        Object::Native(native) => native.call(args),
+        Object::Memo(memo) => memo.call(args),
+        Object::Continuation(k) => Err(k.escape(args)),
+        Object::Closure(closure) => vm::call(closure, args),
+        Object::Lambda(lambda) => {
+            let env = bind_arguments(lambda, args)?;
+            let last = eval_body(&lambda.body, &env)?;
+            eval(&last, &env)
+        }
+        other => Err(EvalError::new(format!("not a procedure: {}", other))),
+    }
+}
+
+/// Evaluates the special forms that are not in tail position, if `name`
+/// is one. They are kept out of `eval_loop`, whose frame every nested
+/// evaluation pushes, so that it stays small.
+/// The special forms of the libraries rather than of the language, whose
+/// names a program may still give its own procedures and macros: defined,
+/// or bound locally, the name is called like any other.
+const LIBRARY_FORMS: &[&str] = &[
+    "with-task-scope",
+    "pipe",
+    "sql",
+    "trace",
+    "untrace",
+    "with-retry",
+    "guard",
+    "profile",
+    "assert",
+    "assert-equal",
+    "deftest",
+    "run-tests",
+    "time",
+];
+
+fn special_form(
+    name: &str,
+    form: &Object,
+    list: &[Object],
+    env: &Rc<RefCell<Env>>,
+) -> Option<Result<Object, EvalError>> {
+    if LIBRARY_FORMS.contains(&name) && env.borrow().get(name).is_some() {
+        return None;
+    }
+
+    Some(match name {
+        "define" => eval_define(list, env),
+        "define-memoized" => eval_define_memoized(list, env),
+        // Declarations are for the compiler.
+        "declare" => Ok(Object::Void),
+        "set!" => eval_set(list, env),
+        "load" => {
+            check_form_len(list, 2, "load").and_then(|()| module::load(&eval(&list[1], env)?, env))
+        }
+        "require" => check_form_len(list, 2, "require")
+            .and_then(|()| module::require(&eval(&list[1], env)?, env)),
+        "provide" => module::provide(&list[1..]),
+        "embed-file" => module::embed_file(form, list),
+        "with-task-scope" => task::with_scope(&list[1..], env),
+        "pipe" => process::pipe(&list[1..], env),
+        "define-enum" => enums::define_enum(list, env),
+        "defstruct" => record::defstruct(list, env),
+        "define-record-type" => record::define_record_type(list, env),
+        "quasiquote" => quasiquote::quasiquote(list, env),
+        "sql" => sql::sql(list, env),
+        "trace" => trace::trace(list, env),
+        "with-retry" => retry::with_retry(list, env),
+        "guard" => guard::guard(list, env),
+        "untrace" => trace::untrace(list),
+        "profile" => profile::profile(list, env),
+        "assert" => testing::assert(list, env),
+        "assert-equal" => testing::assert_equal(list, env),
+        "deftest" => testing::deftest(form, list, env),
+        "run-tests" => testing::run_tests(list, env),
+        "time" => datetime::time(list, env),
+        "the-environment" => {
+            check_form_len(list, 1, "the-environment").map(|()| Object::Environment(env.clone()))
+        }
+        "let-values" => values::let_values(list, env),
+        "delay" => promise::delay(list, env),
+        "cons-stream" => promise::cons_stream(list, env),
+        "defmacro" => macros::defmacro(list, env),
+        "macroexpand-1" => macros::macroexpand_1(list, env),
+        "macroexpand" => macros::macroexpand(list, env),
+        "lambda" if list.len() < 3 => {
+            Err(EvalError::new("lambda expects a parameter list and a body"))
+        }
+        "lambda" => make_lambda(None, &list[1], &list[2..], env),
+        _ => return None,
+    })
+}
+
+/// Evaluates every form of `body` except the last one, which is returned
+/// unevaluated so the caller can continue with it in tail position.
+fn eval_body(body: &[Object], env: &Rc<RefCell<Env>>) -> Result<Object, EvalError> {
+    let (last, init) = body
+        .split_last()
+        .ok_or_else(|| EvalError::new("empty body"))?;
+
+    for form in init {
+        eval(form, env)?;
+    }
+
+    Ok(last.clone())
+}
+
+fn eval_define(list: &[Object], env: &Rc<RefCell<Env>>) -> Result<Object, EvalError> {
+    if list.len() < 3 {
+        return Err(EvalError::new("define expects a name and a value"));
+    }
+
+    let (name, value) = match &list[1] {
+        Object::Symbol(name) => {
+            check_form_len(list, 3, "define")?;
+            (*name, eval(&list[2], env)?)
+        }
+        Object::Pair(signature) => {
+            let name = match signature.car() {
+                Object::Symbol(name) => name,
+                other => return Err(EvalError::new(format!("invalid function name: {}", other))),
+            };
+            let lambda = make_lambda(Some(name), &signature.cdr(), &list[2..], env)?;
+            (name, lambda)
+        }
+        other => return Err(EvalError::new(format!("invalid define target: {}", other))),
+    };
+
+    hooks::define(name.as_str(), &value);
+    env.borrow_mut().define(name, value);
+
+    Ok(Object::Void)
+}
+
+/// `(define-memoized (name params...) body...)` defines a function whose
+/// results are cached by argument list, as if wrapped with `memoize`.
+fn eval_define_memoized(list: &[Object], env: &Rc<RefCell<Env>>) -> Result<Object, EvalError> {
+    let (name, params) = match list.get(1) {
+        Some(Object::Pair(signature)) if list.len() >= 3 => match signature.car() {
+            Object::Symbol(name) => (name, signature.cdr()),
+            other => return Err(EvalError::new(format!("invalid function name: {}", other))),
+        },
+        _ => {
+            return Err(EvalError::new(
+                "define-memoized expects a function signature and a body",
+            ))
+        }
+    };
+
+    let func = Object::Memo(Memo::shared(
+        make_lambda(Some(name), &params, &list[2..], env)?,
+        None,
+    ));
+    hooks::define(name.as_str(), &func);
+    env.borrow_mut().define(name, func);
+
+    Ok(Object::Void)
+}
+
+fn eval_set(list: &[Object], env: &Rc<RefCell<Env>>) -> Result<Object, EvalError> {
+    check_form_len(list, 3, "set!")?;
+
+    let name = match &list[1] {
+        Object::Symbol(name) => name,
+        other => {
+            return Err(EvalError::new(format!(
+                "set! expects a symbol, got {}",
+                other
+            )))
+        }
+    };
+    let value = eval(&list[2], env)?;
+
+    if env.borrow_mut().set(*name, value) {
+        Ok(Object::Void)
+    } else {
+        Err(EvalError::new(format!("set! of unbound symbol: {}", name)))
+    }
+}
+
+fn eval_let_bindings(
+    bindings: &Object,
+    env: &Rc<RefCell<Env>>,
+) -> Result<Rc<RefCell<Env>>, EvalError> {
+    let bindings = bindings
+        .to_vec()
+        .ok_or_else(|| EvalError::new("let bindings must be a list"))?;
+    let mut frame = Env::extend(env.clone());
+
+    for binding in bindings {
+        match binding.to_vec().as_deref() {
+            Some([Object::Symbol(name), value]) => frame.define(*name, eval(value, env)?),
+            _ => return Err(EvalError::new(format!("invalid let binding: {}", binding))),
+        }
+    }
+
+    let frame = Rc::new(RefCell::new(frame));
+    gc::track(&frame);
+
+    Ok(frame)
+}
+
+pub(crate) fn make_lambda(
+    name: Option<Symbol>,
+    params: &Object,
+    body: &[Object],
+    env: &Rc<RefCell<Env>>,
+) -> Result<Object, EvalError> {
+    let mut names = Vec::new();
+    let mut current = params.clone();
+
+    let rest = loop {
+        match current {
+            Object::Nil => break None,
+            Object::Symbol(rest) => break Some(rest),
+            Object::Pair(pair) => match pair.car() {
+                Object::Symbol(name) => {
+                    names.push(name);
+                    current = pair.cdr();
+                }
+                other => return Err(EvalError::new(format!("invalid parameter: {}", other))),
+            },
+            other => return Err(EvalError::new(format!("invalid parameter: {}", other))),
+        }
+    };
+
+    Ok(Object::Lambda(Rc::new(Lambda {
+        name,
+        params: names,
+        rest,
+        body: body.to_vec(),
+        env: env.clone(),
+    })))
+}
+
+fn bind_arguments(lambda: &Lambda, mut args: Vec<Object>) -> Result<Rc<RefCell<Env>>, EvalError> {
+    let arity_ok = match lambda.rest {
+        Some(_) => args.len() >= lambda.params.len(),
+        None => args.len() == lambda.params.len(),
+    };
+    if !arity_ok {
+        return Err(EvalError::new(format!(
+            "expected {}{} arguments, got {}",
+            if lambda.rest.is_some() {
+                "at least "
+            } else {
+                ""
+            },
+            lambda.params.len(),
+            args.len()
+        )));
+    }
+
+    let mut frame = Env::extend(lambda.env.clone());
+    if let Some(rest) = &lambda.rest {
+        frame.define(*rest, Object::list(args.split_off(lambda.params.len())));
+    }
+    for (param, arg) in lambda.params.iter().zip(args) {
+        frame.define(*param, arg);
+    }
+
+    let frame = Rc::new(RefCell::new(frame));
+    gc::track(&frame);
+
+    Ok(frame)
+}
+
+fn check_form_len(list: &[Object], len: usize, form: &str) -> Result<(), EvalError> {
+    if list.len() != len {
+        return Err(EvalError::new(format!(
+            "{} expects {} arguments, got {}",
+            form,
+            len - 1,
+            list.len() - 1
+        )));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builtins::global_env;
+
+    fn eval_program(program: &str) -> Result<Object, EvalError> {
+        eval_str(program, &global_env())
+    }
+
+    #[test]
+    fn test_area_of_circle() {
+        let result = eval_program(
+            "(define r 10)
+             (define pi 314)
+             (* pi (* r r))",
+        )
+        .unwrap();
+
+        assert_eq!(result, Object::Integer(31400));
+    }
+
+    #[test]
+    fn test_operators_are_first_class() {
+        let result = eval_program(
+            "(define (combine op a b) (op a b))
+             (define plus +)
+             (list (combine + 1 2) (combine * 3 4) (plus 5 6))",
+        )
+        .unwrap();
+
+        assert_eq!(result.to_string(), "(3 12 11)");
+    }
+
+    #[test]
+    fn test_rest_parameters() {
+        let result = eval_program(
+            "(define (tail a . rest) rest)
+             (list (tail 1 2 3) (tail 1) ((lambda args args) 1 2) (+ 1 2 3 4))",
+        )
+        .unwrap();
+
+        assert_eq!(result.to_string(), "((2 3) () (1 2) 10)");
+        assert!(eval_program("((lambda (a b . rest) a) 1)").is_err());
+    }
+
+    #[test]
+    fn test_tail_recursion() {
+        let result = eval_program(
+            "(define (count-down n) (if (= n 0) 'done (count-down (- n 1))))
+             (count-down 100000)",
+        )
+        .unwrap();
+
+        assert_eq!(result, Object::symbol("done"));
+    }
+
+    #[test]
+    fn test_factorial_uses_bignums() {
+        let result = eval_program(
+            "(define (factorial n) (if (= n 0) 1 (* n (factorial (- n 1)))))
+             (factorial 30)",
+        )
+        .unwrap();
+
+        assert_eq!(result.to_string(), "265252859812191058636308480000000");
+    }
+
+    #[test]
+    fn test_set_mutates_existing_binding() {
+        let result = eval_program(
+            "(define x 1)
+             (set! x (+ x 1))
+             x",
+        )
+        .unwrap();
+
+        assert_eq!(result, Object::Integer(2));
+        assert!(eval_program("(set! undefined-var 1)").is_err());
+    }
+
+    #[test]
+    fn test_closures_observe_mutation() {
+        let result = eval_program(
+            "(define (make-counter)
+               (let ((n 0))
+                 (lambda () (set! n (+ n 1)) n)))
+             (define counter (make-counter))
+             (counter)
+             (counter)
+             (counter)",
+        )
+        .unwrap();
+
+        assert_eq!(result, Object::Integer(3));
+
+        let result = eval_program(
+            "(define x 1)
+             (define (get-x) x)
+             (set! x 42)
+             (get-x)",
+        )
+        .unwrap();
+
+        assert_eq!(result, Object::Integer(42));
+    }
+
+    #[test]
+    fn test_define_shadows_but_set_does_not() {
+        let result = eval_program(
+            "(define x 1)
+             (define (shadow) (define x 2) x)
+             (define (mutate) (set! x 3) x)
+             (list (shadow) x (mutate) x)",
+        )
+        .unwrap();
+
+        assert_eq!(result.to_string(), "(2 1 3 3)");
+    }
+
+    #[test]
+    fn test_library_forms_can_be_shadowed() {
+        let result = eval_program(
+            "(define (time x) (* x 2))
+             (define (sql x) x)
+             (define (check assert) (assert (list 1)))
+             (list (time 5) (sql 5) (check car) (let ((guard +)) (guard 1 2)))",
+        )
+        .unwrap();
+
+        assert_eq!(result.to_string(), "(10 5 1 3)");
+        assert!(eval_program("(assert #f)").is_err());
+    }
+
+    #[test]
+    fn test_errors_point_at_the_failing_list() {
+        let env = global_env();
+        let source = "(define x 1)\n\n(+ x\n   (car '()))";
+        let err = eval_str(source, &env).unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "Evaluation error: car expects a pair, got ()"
+        );
+        assert_eq!(
+            err.with_origin("x.lisp").diagnostic().to_string(),
+            "error: car expects a pair, got ()\n \
+             --> x.lisp:4:4\n  \
+             |\n\
+             4 |    (car '()))\n  \
+             |    ^^^^^^^^^"
+        );
+    }
+
+    #[test]
+    fn test_stack_traces() {
+        let env = global_env();
+        eval_str("(define (head xs) (car xs))", &env).unwrap();
+        let source = "(define (f x)\n  (+ 1 (head x)))\n(define (g) (f 2) 0)\n(g)";
+        let err = eval_str(source, &env).unwrap_err().with_origin("t.lisp");
+
+        assert_eq!(
+            err.diagnostic().to_string(),
+            "error: car expects a pair, got 2\n \
+             --> t.lisp:2:8\n  \
+             |\n\
+             2 |   (+ 1 (head x)))\n  \
+             |        ^^^^^^^^\n  \
+             = note: in head, called at t.lisp:2:8\n  \
+             = note: in f, called at t.lisp:3:13\n  \
+             = note: in g, called at t.lisp:4:1"
+        );
+
+        let err = eval_str(
+            "(define (down n) (if (= n 0) (car n) (+ 1 (down (- n 1))))) (down 30)",
+            &env,
+        );
+        let notes = err.unwrap_err().diagnostic().notes;
+        assert_eq!(notes.len(), MAX_FRAMES + 1);
+        assert!(notes[MAX_FRAMES].starts_with("and "), "{:?}", notes);
+    }
+
+    #[test]
+    fn test_errors_as_data() {
+        let env = global_env();
+        eval_str("(define (head xs) (car xs))", &env).unwrap();
+        let err = eval_str("(define (f x)\n  (+ 1 (head x)))\n(f 2)", &env)
+            .unwrap_err()
+            .with_origin("t.lisp");
+
+        assert_eq!(
+            err.to_json(),
+            "{\"backtrace\":[\
+             {\"column\":8,\"line\":2,\"name\":\"head\",\"origin\":\"t.lisp\"},\
+             {\"column\":1,\"line\":3,\"name\":\"f\",\"origin\":\"t.lisp\"}],\
+             \"column\":8,\"kind\":\"error\",\"line\":2,\
+             \"message\":\"car expects a pair, got 2\",\"notes\":[],\"origin\":\"t.lisp\",\
+             \"source-line\":\"  (+ 1 (head x)))\",\"span\":{\"end\":29,\"start\":21}}"
+        );
+        let value = err.to_value();
+        assert_eq!(
+            crate::lsp::string_field(&value, &["message"]).as_deref(),
+            Some("car expects a pair, got 2")
+        );
+
+        let exit = eval_str("(exit 3)", &env).unwrap_err();
+        assert_eq!(
+            exit.to_json(),
+            "{\"backtrace\":[],\"exit-code\":3,\"kind\":\"exit\",\"message\":\"exit with code 3\"}"
+        );
+    }
+}
@@ -0,0 +1,404 @@
+//! Fibers: cooperative green threads multiplexed on one OS thread.
+//!
+//! `(spawn-fiber thunk)` queues a fiber that runs `thunk` on a stack of its
+//! own, so it can be suspended anywhere, however deep in the evaluator.
+//! Fibers run only when the code outside of them waits: `(run-fibers)` runs
+//! them until each has finished or is blocked, and `join-fiber` and
+//! `channel-receive` run them until there is something to return. Inside a
+//! fiber, `(yield-fiber)` lets the others run, and waiting suspends the
+//! fiber instead. If everything is waiting on something only a waiting
+//! fiber could provide, the wait fails with a deadlock error.
+//!
+//! Channels made with `(make-channel)` are unbounded queues: sending never
+//! blocks, and receiving waits for a value. State kept per thread, such as
+//! the file being loaded, is shared by every fiber.
+
+// On WebAssembly no fiber can be spawned, and the code running them is idle.
+#![cfg_attr(target_arch = "wasm32", allow(dead_code))]
+
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+#[cfg(not(target_arch = "wasm32"))]
+use corosensei::stack::DefaultStack;
+#[cfg(not(target_arch = "wasm32"))]
+use corosensei::{Coroutine, CoroutineResult, Yielder};
+
+use crate::comparator::is_procedure;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::eval::apply;
+use crate::eval::EvalError;
+use crate::limits;
+use crate::object::Object;
+
+/// The stack size of a fiber. Stack pages are only allocated as they are
+/// touched, so most fibers cost far less.
+const STACK_SIZE: usize = 1 << 20;
+
+/// Why a fiber suspended itself: to let the others run, or because it
+/// cannot go on until another fiber does something.
+#[derive(Clone, Copy, PartialEq)]
+enum Wait {
+    Ready,
+    Blocked,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+type Body = Coroutine<(), Wait, Result<Object, EvalError>, DefaultStack>;
+
+/// Fibers need stack switching, which WebAssembly does not offer.
+#[cfg(target_arch = "wasm32")]
+type Body = ();
+
+pub struct Fiber {
+    /// The suspended computation, until the fiber finishes.
+    body: RefCell<Option<Body>>,
+    state: RefCell<FiberState>,
+    /// The evaluations nested on its stack while it is suspended.
+    stack: Cell<limits::Stack>,
+}
+
+enum FiberState {
+    Running,
+    Done(Object),
+    Failed(String),
+}
+
+pub struct Channel {
+    queue: RefCell<VecDeque<Object>>,
+}
+
+#[derive(Default)]
+struct Scheduler {
+    ready: VecDeque<Rc<Fiber>>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+type YielderPtr = *const Yielder<(), Wait>;
+
+#[cfg(target_arch = "wasm32")]
+type YielderPtr = *const ();
+
+thread_local! {
+    static SCHEDULER: RefCell<Scheduler> = RefCell::new(Scheduler::default());
+    /// How the running fiber suspends itself; null outside of fibers.
+    static CURRENT: Cell<YielderPtr> = const { Cell::new(std::ptr::null()) };
+}
+
+fn in_fiber() -> bool {
+    !CURRENT.with(Cell::get).is_null()
+}
+
+/// Suspends the running fiber until the scheduler resumes it.
+#[cfg(not(target_arch = "wasm32"))]
+fn suspend(wait: Wait) {
+    let yielder = CURRENT.with(Cell::get);
+    // SAFETY: CURRENT is only set while the body owning the yielder runs,
+    // and the yielder lives as long as the body.
+    unsafe { (*yielder).suspend(wait) };
+    CURRENT.with(|current| current.set(yielder));
+}
+
+#[cfg(target_arch = "wasm32")]
+fn suspend(_wait: Wait) {}
+
+impl Fiber {
+    fn is_done(&self) -> bool {
+        !matches!(*self.state.borrow(), FiberState::Running)
+    }
+
+    fn result(&self) -> Result<Object, EvalError> {
+        match &*self.state.borrow() {
+            FiberState::Done(value) => Ok(value.clone()),
+            FiberState::Failed(err) => Err(EvalError::new(format!("fiber failed: {}", err))),
+            FiberState::Running => Err(EvalError::new("fiber has not finished")),
+        }
+    }
+}
+
+/// Resumes the next ready fiber until it suspends or finishes, returning
+/// whether it made progress. An exit requested by a fiber ends the program
+/// at once, rather than waiting for the fiber to be joined.
+#[cfg(not(target_arch = "wasm32"))]
+fn step() -> Result<bool, EvalError> {
+    let fiber = match SCHEDULER.with(|scheduler| scheduler.borrow_mut().ready.pop_front()) {
+        Some(fiber) => fiber,
+        None => return Ok(false),
+    };
+
+    let outer = limits::switch_stack(fiber.stack.get());
+    let resumed = fiber.body.borrow_mut().as_mut().map(|body| body.resume(()));
+    fiber.stack.set(limits::switch_stack(outer));
+    let Some(resumed) = resumed else {
+        return Ok(true);
+    };
+    CURRENT.with(|current| current.set(std::ptr::null()));
+
+    match resumed {
+        CoroutineResult::Yield(wait) => {
+            SCHEDULER.with(|scheduler| scheduler.borrow_mut().ready.push_back(fiber));
+            Ok(wait == Wait::Ready)
+        }
+        CoroutineResult::Return(result) => {
+            fiber.body.borrow_mut().take();
+            let (state, exit) = match result {
+                Ok(value) => (FiberState::Done(value), None),
+                Err(e) if e.exit_code().is_some() => (FiberState::Failed(e.to_string()), Some(e)),
+                Err(e) => (FiberState::Failed(e.message().to_string()), None),
+            };
+            *fiber.state.borrow_mut() = state;
+            exit.map_or(Ok(true), Err)
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn step() -> Result<bool, EvalError> {
+    Ok(false)
+}
+
+fn ready_count() -> usize {
+    SCHEDULER.with(|scheduler| scheduler.borrow().ready.len())
+}
+
+/// Lets the other fibers run while the running one waits for time to pass,
+/// returning false outside of fibers or if no other fiber is ready.
+pub(crate) fn pause() -> bool {
+    if !in_fiber() || ready_count() == 0 {
+        return false;
+    }
+    suspend(Wait::Ready);
+    true
+}
+
+/// Runs fibers until `done` holds. Inside a fiber, suspends it instead.
+fn wait_until(done: impl Fn() -> bool) -> Result<(), EvalError> {
+    if in_fiber() {
+        while !done() {
+            suspend(Wait::Blocked);
+        }
+        return Ok(());
+    }
+
+    // Every fiber has had its turn without any progress when `idle` counts
+    // as many blocked fibers as there are.
+    let mut idle = 0;
+    while !done() {
+        if idle >= ready_count() {
+            return Err(EvalError::new("deadlock: every fiber is waiting"));
+        }
+        idle = if step()? { 0 } else { idle + 1 };
+    }
+
+    Ok(())
+}
+
+/// `(spawn-fiber thunk)` queues a fiber running `thunk` and returns it.
+pub fn spawn_fiber(args: &[Object]) -> Result<Object, EvalError> {
+    let thunk = match args {
+        [thunk] if is_procedure(thunk) => thunk.clone(),
+        _ => {
+            return Err(EvalError::new(
+                "spawn-fiber expects a procedure of no arguments",
+            ))
+        }
+    };
+
+    let fiber = Rc::new(Fiber {
+        body: RefCell::new(Some(new_body(thunk)?)),
+        state: RefCell::new(FiberState::Running),
+        stack: Cell::new(limits::Stack::new(STACK_SIZE)),
+    });
+    SCHEDULER.with(|scheduler| scheduler.borrow_mut().ready.push_back(fiber.clone()));
+
+    Ok(Object::Fiber(fiber))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn new_body(thunk: Object) -> Result<Body, EvalError> {
+    let stack = DefaultStack::new(STACK_SIZE)
+        .map_err(|e| EvalError::new(format!("cannot allocate a fiber stack: {}", e)))?;
+
+    Ok(Coroutine::with_stack(stack, move |yielder, ()| {
+        CURRENT.with(|current| current.set(yielder));
+        apply(&thunk, Vec::new())
+    }))
+}
+
+#[cfg(target_arch = "wasm32")]
+fn new_body(_thunk: Object) -> Result<Body, EvalError> {
+    Err(EvalError::new("fibers are not supported on WebAssembly"))
+}
+
+/// `(yield-fiber)` lets the other fibers run. Outside of fibers, it gives
+/// each ready fiber one turn.
+pub fn yield_fiber(args: &[Object]) -> Result<Object, EvalError> {
+    if !args.is_empty() {
+        return Err(EvalError::new("yield-fiber expects no arguments"));
+    }
+
+    if in_fiber() {
+        suspend(Wait::Ready);
+    } else {
+        for _ in 0..ready_count() {
+            step()?;
+        }
+    }
+
+    Ok(Object::Void)
+}
+
+/// `(join-fiber fiber)` waits for a fiber to finish and returns its value,
+/// or fails if it failed.
+pub fn join_fiber(args: &[Object]) -> Result<Object, EvalError> {
+    let fiber = match args {
+        [Object::Fiber(fiber)] => fiber,
+        _ => return Err(EvalError::new("join-fiber expects a fiber")),
+    };
+
+    wait_until(|| fiber.is_done())?;
+    fiber.result()
+}
+
+/// `(run-fibers)` runs fibers until each has finished or is blocked.
+pub fn run_fibers(args: &[Object]) -> Result<Object, EvalError> {
+    if !args.is_empty() {
+        return Err(EvalError::new("run-fibers expects no arguments"));
+    }
+    if in_fiber() {
+        return Err(EvalError::new("run-fibers cannot be called from a fiber"));
+    }
+
+    let mut idle = 0;
+    while idle < ready_count() {
+        idle = if step()? { 0 } else { idle + 1 };
+    }
+
+    Ok(Object::Void)
+}
+
+pub fn fiber_done(args: &[Object]) -> Result<Object, EvalError> {
+    match args {
+        [Object::Fiber(fiber)] => Ok(Object::Bool(fiber.is_done())),
+        _ => Err(EvalError::new("fiber-done? expects a fiber")),
+    }
+}
+
+pub fn make_channel(args: &[Object]) -> Result<Object, EvalError> {
+    match args {
+        [] => Ok(Object::Channel(Rc::new(Channel {
+            queue: RefCell::new(VecDeque::new()),
+        }))),
+        _ => Err(EvalError::new("make-channel expects no arguments")),
+    }
+}
+
+pub fn channel_send(args: &[Object]) -> Result<Object, EvalError> {
+    match args {
+        [Object::Channel(channel), value] => {
+            channel.queue.borrow_mut().push_back(value.clone());
+            Ok(Object::Void)
+        }
+        _ => Err(EvalError::new(
+            "channel-send! expects a channel and a value",
+        )),
+    }
+}
+
+/// `(channel-receive channel)` takes the oldest value sent on a channel,
+/// waiting for one if there is none.
+pub fn channel_receive(args: &[Object]) -> Result<Object, EvalError> {
+    let channel = match args {
+        [Object::Channel(channel)] => channel,
+        _ => return Err(EvalError::new("channel-receive expects a channel")),
+    };
+
+    wait_until(|| !channel.queue.borrow().is_empty())?;
+    Ok(channel
+        .queue
+        .borrow_mut()
+        .pop_front()
+        .unwrap_or(Object::Void))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builtins::global_env;
+    use crate::eval::eval_str;
+
+    #[test]
+    fn test_fibers_interleave() {
+        let env = global_env();
+        let result = eval_str(
+            "(define log '())
+             (define (worker name n)
+               (lambda ()
+                 (define (loop i)
+                   (if (< i n)
+                       (begin (set! log (cons (list name i) log))
+                              (yield-fiber)
+                              (loop (+ i 1)))
+                       name))
+                 (loop 0)))
+             (define a (spawn-fiber (worker 'a 2)))
+             (define b (spawn-fiber (worker 'b 3)))
+             (list (join-fiber b) (fiber-done? a) log)",
+            &env,
+        )
+        .unwrap();
+
+        assert_eq!(result.to_string(), "(b #t ((b 2) (b 1) (a 1) (b 0) (a 0)))");
+    }
+
+    #[test]
+    fn test_channels() {
+        let env = global_env();
+        let result = eval_str(
+            "(define ch (make-channel))
+             (define replies (make-channel))
+             (define (serve)
+               (let ((n (channel-receive ch)))
+                 (channel-send! replies (* n n))
+                 (serve)))
+             (spawn-fiber serve)
+             (define fibers
+               (map (lambda (n) (spawn-fiber (lambda () (channel-send! ch n))))
+                    '(1 2 3)))
+             (run-fibers)
+             (list (channel-receive replies)
+                   (channel-receive replies)
+                   (channel-receive replies))",
+            &env,
+        )
+        .unwrap();
+        assert_eq!(result.to_string(), "(1 4 9)");
+
+        let err = eval_str("(channel-receive replies)", &env).unwrap_err();
+        assert_eq!(err.message(), "deadlock: every fiber is waiting");
+
+        let err = eval_str("(join-fiber (spawn-fiber (lambda () (car '()))))", &env).unwrap_err();
+        assert!(err
+            .message()
+            .starts_with("fiber failed: car expects a pair"));
+    }
+
+    #[test]
+    fn test_many_fibers() {
+        let env = global_env();
+        let result = eval_str(
+            "(define total 0)
+             (define (spawn-all n)
+               (if (> n 0)
+                   (begin (spawn-fiber (lambda () (yield-fiber) (set! total (+ total 1))))
+                          (spawn-all (- n 1)))))
+             (spawn-all 2000)
+             (run-fibers)
+             total",
+            &env,
+        )
+        .unwrap();
+
+        assert_eq!(result.to_string(), "2000");
+    }
+}
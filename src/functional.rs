@@ -0,0 +1,209 @@
+use crate::eval::{apply as apply_procedure, EvalError};
+use crate::object::Object;
+
+fn list_arg(name: &str, obj: &Object) -> Result<Vec<Object>, EvalError> {
+    obj.to_vec()
+        .ok_or_else(|| EvalError::new(format!("{} expects a list, got {}", name, obj)))
+}
+
+/// Splits `lists` into rows of one element per list, stopping at the end of
+/// the shortest list.
+fn rows(name: &str, lists: &[Object]) -> Result<Vec<Vec<Object>>, EvalError> {
+    if lists.is_empty() {
+        return Err(EvalError::new(format!(
+            "{} expects at least one list",
+            name
+        )));
+    }
+
+    let lists = lists
+        .iter()
+        .map(|list| list_arg(name, list))
+        .collect::<Result<Vec<_>, _>>()?;
+    let len = lists.iter().map(Vec::len).min().unwrap_or(0);
+
+    Ok((0..len)
+        .map(|i| lists.iter().map(|list| list[i].clone()).collect())
+        .collect())
+}
+
+/// `(apply f a b '(c d))` calls `f` with `a b c d`.
+pub fn apply(args: &[Object]) -> Result<Object, EvalError> {
+    let (func, rest) = args
+        .split_first()
+        .ok_or_else(|| EvalError::new("apply expects a procedure"))?;
+    let call_args = match rest.split_last() {
+        Some((last, init)) => {
+            let mut call_args = init.to_vec();
+            call_args.extend(list_arg("apply", last)?);
+            call_args
+        }
+        None => Vec::new(),
+    };
+
+    apply_procedure(func, call_args)
+}
+
+pub fn map(args: &[Object]) -> Result<Object, EvalError> {
+    let (func, lists) = args
+        .split_first()
+        .ok_or_else(|| EvalError::new("map expects a procedure and lists"))?;
+
+    let results = rows("map", lists)?
+        .into_iter()
+        .map(|row| apply_procedure(func, row))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(Object::list(results))
+}
+
+pub fn for_each(args: &[Object]) -> Result<Object, EvalError> {
+    let (func, lists) = args
+        .split_first()
+        .ok_or_else(|| EvalError::new("for-each expects a procedure and lists"))?;
+
+    for row in rows("for-each", lists)? {
+        apply_procedure(func, row)?;
+    }
+
+    Ok(Object::Void)
+}
+
+pub fn filter(args: &[Object]) -> Result<Object, EvalError> {
+    let (pred, list) = match args {
+        [pred, list] => (pred, list_arg("filter", list)?),
+        _ => return Err(EvalError::new("filter expects a predicate and a list")),
+    };
+
+    let mut kept = Vec::new();
+    for item in list {
+        if apply_procedure(pred, vec![item.clone()])?.is_truthy() {
+            kept.push(item);
+        }
+    }
+
+    Ok(Object::list(kept))
+}
+
+/// `(reduce f default list)` combines the elements left to right as
+/// `(f elem acc)`, starting from the first element. An empty list yields
+/// `default`.
+pub fn reduce(args: &[Object]) -> Result<Object, EvalError> {
+    let (func, default, list) = match args {
+        [func, default, list] => (func, default, list_arg("reduce", list)?),
+        _ => {
+            return Err(EvalError::new(
+                "reduce expects a procedure, a default and a list",
+            ))
+        }
+    };
+
+    let mut items = list.into_iter();
+    let Some(mut acc) = items.next() else {
+        return Ok(default.clone());
+    };
+    for item in items {
+        acc = apply_procedure(func, vec![item, acc])?;
+    }
+
+    Ok(acc)
+}
+
+/// `(fold-left f init list ...)` computes `(f (f init a1) a2) ...`.
+pub fn fold_left(args: &[Object]) -> Result<Object, EvalError> {
+    let (func, init, lists) = match args {
+        [func, init, lists @ ..] => (func, init, lists),
+        _ => {
+            return Err(EvalError::new(
+                "fold-left expects a procedure, an initial value and lists",
+            ))
+        }
+    };
+
+    rows("fold-left", lists)?
+        .into_iter()
+        .try_fold(init.clone(), |acc, mut row| {
+            row.insert(0, acc);
+            apply_procedure(func, row)
+        })
+}
+
+/// `(fold-right f init list ...)` computes `(f a1 (f a2 ... init))`.
+pub fn fold_right(args: &[Object]) -> Result<Object, EvalError> {
+    let (func, init, lists) = match args {
+        [func, init, lists @ ..] => (func, init, lists),
+        _ => {
+            return Err(EvalError::new(
+                "fold-right expects a procedure, an initial value and lists",
+            ))
+        }
+    };
+
+    rows("fold-right", lists)?
+        .into_iter()
+        .rev()
+        .try_fold(init.clone(), |acc, mut row| {
+            row.push(acc);
+            apply_procedure(func, row)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builtins::global_env;
+    use crate::eval::eval_str;
+    use crate::object::Object;
+
+    fn eval(program: &str) -> String {
+        eval_str(program, &global_env()).unwrap().to_string()
+    }
+
+    #[test]
+    fn test_apply() {
+        assert_eq!(eval("(apply + '(1 2))"), "3");
+        assert_eq!(eval("(apply list 1 2 '(3 4))"), "(1 2 3 4)");
+        assert!(eval_str("(apply + 1 2)", &global_env()).is_err());
+    }
+
+    #[test]
+    fn test_map_filter_for_each() {
+        assert_eq!(eval("(map (lambda (x) (* x x)) '(1 2 3))"), "(1 4 9)");
+        assert_eq!(eval("(map cons '(1 2 3) '(a b))"), "((1 . a) (2 . b))");
+        assert_eq!(eval("(filter (lambda (x) (< x 3)) '(1 5 2 4))"), "(1 2)");
+        assert_eq!(
+            eval(
+                "(define total 0)
+                 (for-each (lambda (x) (set! total (+ total x))) '(1 2 3))
+                 total"
+            ),
+            "6"
+        );
+    }
+
+    #[test]
+    fn test_map_over_huge_lists() {
+        let env = global_env();
+        let xs = Object::list((0..1_000_000).map(Object::Integer));
+        env.borrow_mut().define("xs", xs);
+
+        let mapped = eval_str("(map list xs)", &env).unwrap();
+        let items = mapped.to_vec().unwrap();
+        assert_eq!(items.len(), 1_000_000);
+        assert_eq!(items[999_999].to_string(), "(999999)");
+    }
+
+    #[test]
+    fn test_folds() {
+        assert_eq!(eval("(reduce + 0 '(1 2 3 4))"), "10");
+        assert_eq!(eval("(reduce + 0 '())"), "0");
+        assert_eq!(
+            eval("(fold-left cons '() '(1 2 3))"),
+            "(((() . 1) . 2) . 3)"
+        );
+        assert_eq!(eval("(fold-right cons '() '(1 2 3))"), "(1 2 3)");
+        assert_eq!(
+            eval("(fold-left (lambda (acc a b) (+ acc (* a b))) 0 '(1 2) '(3 4))"),
+            "11"
+        );
+    }
+}
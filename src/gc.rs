@@ -0,0 +1,777 @@
+//! Cycle collection for the reference-counted object graph.
+//!
+//! Objects are freed by `Rc` as soon as nothing refers to them, which covers
+//! everything except cycles, such as a closure stored in the environment it
+//! captures. Every graph cycle passes through a mutable container (an
+//! environment, compiled code's cell, heap, deque, sorted map, hash table,
+//! record, memo cache, task, promise or changed pair), since lambdas and pairs left as they
+//! were made can only point at objects that existed before them. Those
+//! containers are registered here when they are created, and pairs the
+//! first time `set-car!` or `set-cdr!` changes them.
+//!
+//! A collection works like trial deletion: starting from the registered
+//! containers it counts, for every reachable object, how many of its strong
+//! references come from inside the graph. Objects with more references than
+//! that are held from outside, by Rust code or an evaluation in progress,
+//! and keep everything they reach alive. The remaining objects are only
+//! reachable from each other; their containers are cleared, which breaks the
+//! cycles and lets `Rc` free them.
+//!
+//! Rust code keeps what it holds alive through those outside references, a
+//! `Value` included. A `Rooted` handle also registers the objects of a value
+//! as roots of the collector until it is dropped, so that code written
+//! against it keeps working should the collector ever stop counting
+//! references and trace from its roots instead.
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt;
+use std::ops::Deref;
+use std::rc::{Rc, Weak};
+
+use crate::collections::{HashTable, Heap};
+use crate::comparator::Comparator;
+use crate::env::Env;
+use crate::eval::EvalError;
+use crate::interpreter::Value;
+use crate::memo::Memo;
+use crate::object::{Lambda, Object, Pair};
+use crate::promise::Promise;
+use crate::record::Record;
+use crate::sorted_map::SortedMap;
+use crate::task::Task;
+use crate::vm::{Cell, Closure};
+
+/// Collections run automatically once this many containers are tracked, and
+/// again whenever the number of live ones doubles.
+const INITIAL_THRESHOLD: usize = 10_000;
+
+/// A reference from one object to another.
+pub(crate) enum Edge<'a> {
+    Object(&'a Object),
+    Env(&'a Rc<RefCell<Env>>),
+    Cell(&'a Cell),
+}
+
+/// Implemented by everything the collector can look into.
+pub(crate) trait Trace {
+    fn trace(&self, edge: &mut dyn FnMut(Edge));
+
+    /// Drops every reference held by a mutable container.
+    fn clear(&mut self) {}
+}
+
+impl Trace for Lambda {
+    fn trace(&self, edge: &mut dyn FnMut(Edge)) {
+        edge(Edge::Env(&self.env));
+        for form in &self.body {
+            edge(Edge::Object(form));
+        }
+    }
+}
+
+impl Trace for Closure {
+    fn trace(&self, edge: &mut dyn FnMut(Edge)) {
+        for value in &self.free {
+            edge(Edge::Object(value));
+        }
+        for cell in &self.cells {
+            edge(Edge::Cell(cell));
+        }
+        edge(Edge::Env(&self.globals));
+    }
+}
+
+impl Trace for VecDeque<Object> {
+    fn trace(&self, edge: &mut dyn FnMut(Edge)) {
+        for item in self {
+            edge(Edge::Object(item));
+        }
+    }
+
+    fn clear(&mut self) {
+        VecDeque::clear(self);
+    }
+}
+
+#[derive(Clone)]
+enum Node {
+    Env(Rc<RefCell<Env>>),
+    Cell(Cell),
+    Pair(Rc<Pair>),
+    Lambda(Rc<Lambda>),
+    Closure(Rc<Closure>),
+    Memo(Rc<Memo>),
+    Task(Rc<Task>),
+    Heap(Rc<RefCell<Heap>>),
+    Deque(Rc<RefCell<VecDeque<Object>>>),
+    Comparator(Rc<Comparator>),
+    SortedMap(Rc<RefCell<SortedMap>>),
+    HashTable(Rc<RefCell<HashTable>>),
+    Record(Rc<Record>),
+    Promise(Rc<Promise>),
+    Values(Rc<Vec<Object>>),
+}
+
+/// A container could not be inspected because it is mutably borrowed; the
+/// collection is skipped.
+struct Busy;
+
+impl Node {
+    fn from_edge(edge: Edge) -> Option<Node> {
+        Some(match edge {
+            Edge::Env(env) => Node::Env(env.clone()),
+            Edge::Object(Object::Environment(env)) => Node::Env(env.clone()),
+            Edge::Cell(cell) => Node::Cell(cell.clone()),
+            Edge::Object(Object::Pair(pair)) => Node::Pair(pair.clone()),
+            Edge::Object(Object::Lambda(lambda) | Object::Macro(lambda)) => {
+                Node::Lambda(lambda.clone())
+            }
+            Edge::Object(Object::Closure(closure)) => Node::Closure(closure.clone()),
+            Edge::Object(Object::Memo(memo)) => Node::Memo(memo.clone()),
+            Edge::Object(Object::Task(task)) => Node::Task(task.clone()),
+            Edge::Object(Object::Heap(heap)) => Node::Heap(heap.clone()),
+            Edge::Object(Object::Deque(deque)) => Node::Deque(deque.clone()),
+            Edge::Object(Object::Comparator(comparator)) => Node::Comparator(comparator.clone()),
+            Edge::Object(Object::SortedMap(map)) => Node::SortedMap(map.clone()),
+            Edge::Object(Object::HashTable(table)) => Node::HashTable(table.clone()),
+            Edge::Object(Object::Record(record)) => Node::Record(record.clone()),
+            Edge::Object(Object::Promise(promise)) => Node::Promise(promise.clone()),
+            Edge::Object(Object::Values(values)) => Node::Values(values.clone()),
+            Edge::Object(_) => return None,
+        })
+    }
+
+    fn id(&self) -> usize {
+        match self {
+            Node::Env(rc) => Rc::as_ptr(rc) as *const () as usize,
+            Node::Cell(rc) => Rc::as_ptr(rc) as *const () as usize,
+            Node::Pair(rc) => Rc::as_ptr(rc) as *const () as usize,
+            Node::Lambda(rc) => Rc::as_ptr(rc) as *const () as usize,
+            Node::Closure(rc) => Rc::as_ptr(rc) as *const () as usize,
+            Node::Memo(rc) => Rc::as_ptr(rc) as *const () as usize,
+            Node::Task(rc) => Rc::as_ptr(rc) as *const () as usize,
+            Node::Heap(rc) => Rc::as_ptr(rc) as *const () as usize,
+            Node::Deque(rc) => Rc::as_ptr(rc) as *const () as usize,
+            Node::Comparator(rc) => Rc::as_ptr(rc) as *const () as usize,
+            Node::SortedMap(rc) => Rc::as_ptr(rc) as *const () as usize,
+            Node::HashTable(rc) => Rc::as_ptr(rc) as *const () as usize,
+            Node::Record(rc) => Rc::as_ptr(rc) as *const () as usize,
+            Node::Promise(rc) => Rc::as_ptr(rc) as *const () as usize,
+            Node::Values(rc) => Rc::as_ptr(rc) as *const () as usize,
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match self {
+            Node::Env(rc) => Rc::strong_count(rc),
+            Node::Cell(rc) => Rc::strong_count(rc),
+            Node::Pair(rc) => Rc::strong_count(rc),
+            Node::Lambda(rc) => Rc::strong_count(rc),
+            Node::Closure(rc) => Rc::strong_count(rc),
+            Node::Memo(rc) => Rc::strong_count(rc),
+            Node::Task(rc) => Rc::strong_count(rc),
+            Node::Heap(rc) => Rc::strong_count(rc),
+            Node::Deque(rc) => Rc::strong_count(rc),
+            Node::Comparator(rc) => Rc::strong_count(rc),
+            Node::SortedMap(rc) => Rc::strong_count(rc),
+            Node::HashTable(rc) => Rc::strong_count(rc),
+            Node::Record(rc) => Rc::strong_count(rc),
+            Node::Promise(rc) => Rc::strong_count(rc),
+            Node::Values(rc) => Rc::strong_count(rc),
+        }
+    }
+
+    /// Calls `f` with a new handle on every node this one refers to. Handles
+    /// are created one at a time, so at most one extra strong reference to
+    /// a child exists while `f` runs.
+    fn children(&self, f: &mut dyn FnMut(Node)) -> Result<(), Busy> {
+        let mut edge = |edge: Edge| {
+            if let Some(node) = Node::from_edge(edge) {
+                f(node);
+            }
+        };
+
+        match self {
+            Node::Env(env) => env.try_borrow().map_err(|_| Busy)?.trace(&mut edge),
+            Node::Cell(cell) => edge(Edge::Object(&*cell.try_borrow().map_err(|_| Busy)?)),
+            Node::Pair(pair) => pair.trace(&mut edge),
+            Node::Lambda(lambda) => lambda.trace(&mut edge),
+            Node::Closure(closure) => closure.trace(&mut edge),
+            Node::Memo(memo) => memo.trace(&mut edge),
+            Node::Task(task) => task.trace(&mut edge),
+            Node::Heap(heap) => heap.try_borrow().map_err(|_| Busy)?.trace(&mut edge),
+            Node::Deque(deque) => deque.try_borrow().map_err(|_| Busy)?.trace(&mut edge),
+            Node::Comparator(comparator) => comparator.trace(&mut edge),
+            Node::SortedMap(map) => map.try_borrow().map_err(|_| Busy)?.trace(&mut edge),
+            Node::HashTable(table) => table.try_borrow().map_err(|_| Busy)?.trace(&mut edge),
+            Node::Record(record) => {
+                for field in record.fields.try_borrow().map_err(|_| Busy)?.iter() {
+                    edge(Edge::Object(field));
+                }
+            }
+            Node::Promise(promise) => promise.trace(&mut edge),
+            Node::Values(values) => {
+                for value in values.iter() {
+                    edge(Edge::Object(value));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn clear(&self) {
+        // A container that is still borrowed is skipped; garbage cannot be
+        // borrowed by anyone, so this only guards against misuse.
+        match self {
+            Node::Env(env) => {
+                if let Ok(mut env) = env.try_borrow_mut() {
+                    Trace::clear(&mut *env);
+                }
+            }
+            Node::Cell(cell) => {
+                if let Ok(mut value) = cell.try_borrow_mut() {
+                    *value = Object::Void;
+                }
+            }
+            Node::Memo(memo) => memo.clear_cache(),
+            Node::Task(task) => task.clear_state(),
+            Node::Promise(promise) => promise.clear_state(),
+            Node::Heap(heap) => {
+                if let Ok(mut heap) = heap.try_borrow_mut() {
+                    Trace::clear(&mut *heap);
+                }
+            }
+            Node::Deque(deque) => {
+                if let Ok(mut deque) = deque.try_borrow_mut() {
+                    Trace::clear(&mut *deque);
+                }
+            }
+            Node::SortedMap(map) => {
+                if let Ok(mut map) = map.try_borrow_mut() {
+                    Trace::clear(&mut *map);
+                }
+            }
+            Node::HashTable(table) => {
+                if let Ok(mut table) = table.try_borrow_mut() {
+                    Trace::clear(&mut *table);
+                }
+            }
+            Node::Record(record) => {
+                if let Ok(mut fields) = record.fields.try_borrow_mut() {
+                    fields.clear();
+                }
+            }
+            Node::Pair(pair) => pair.clear(),
+            Node::Lambda(_) | Node::Closure(_) | Node::Comparator(_) | Node::Values(_) => {}
+        }
+    }
+}
+
+pub(crate) enum Tracked {
+    Env(Weak<RefCell<Env>>),
+    Cell(Weak<RefCell<Object>>),
+    Pair(Weak<Pair>),
+    Memo(Weak<Memo>),
+    Task(Weak<Task>),
+    Heap(Weak<RefCell<Heap>>),
+    Deque(Weak<RefCell<VecDeque<Object>>>),
+    SortedMap(Weak<RefCell<SortedMap>>),
+    HashTable(Weak<RefCell<HashTable>>),
+    Record(Weak<Record>),
+    Promise(Weak<Promise>),
+}
+
+impl Tracked {
+    fn is_alive(&self) -> bool {
+        match self {
+            Tracked::Env(weak) => weak.strong_count() > 0,
+            Tracked::Cell(weak) => weak.strong_count() > 0,
+            Tracked::Pair(weak) => weak.strong_count() > 0,
+            Tracked::Memo(weak) => weak.strong_count() > 0,
+            Tracked::Task(weak) => weak.strong_count() > 0,
+            Tracked::Heap(weak) => weak.strong_count() > 0,
+            Tracked::Deque(weak) => weak.strong_count() > 0,
+            Tracked::SortedMap(weak) => weak.strong_count() > 0,
+            Tracked::HashTable(weak) => weak.strong_count() > 0,
+            Tracked::Record(weak) => weak.strong_count() > 0,
+            Tracked::Promise(weak) => weak.strong_count() > 0,
+        }
+    }
+
+    fn upgrade(&self) -> Option<Node> {
+        match self {
+            Tracked::Env(weak) => weak.upgrade().map(Node::Env),
+            Tracked::Cell(weak) => weak.upgrade().map(Node::Cell),
+            Tracked::Pair(weak) => weak.upgrade().map(Node::Pair),
+            Tracked::Memo(weak) => weak.upgrade().map(Node::Memo),
+            Tracked::Task(weak) => weak.upgrade().map(Node::Task),
+            Tracked::Heap(weak) => weak.upgrade().map(Node::Heap),
+            Tracked::Deque(weak) => weak.upgrade().map(Node::Deque),
+            Tracked::SortedMap(weak) => weak.upgrade().map(Node::SortedMap),
+            Tracked::HashTable(weak) => weak.upgrade().map(Node::HashTable),
+            Tracked::Record(weak) => weak.upgrade().map(Node::Record),
+            Tracked::Promise(weak) => weak.upgrade().map(Node::Promise),
+        }
+    }
+}
+
+/// Registers a newly created container with the collector.
+pub(crate) trait Track {
+    fn tracked(&self) -> Tracked;
+}
+
+impl Track for Rc<RefCell<Env>> {
+    fn tracked(&self) -> Tracked {
+        Tracked::Env(Rc::downgrade(self))
+    }
+}
+
+impl Track for Cell {
+    fn tracked(&self) -> Tracked {
+        Tracked::Cell(Rc::downgrade(self))
+    }
+}
+
+impl Track for Rc<Pair> {
+    fn tracked(&self) -> Tracked {
+        Tracked::Pair(Rc::downgrade(self))
+    }
+}
+
+impl Track for Rc<Memo> {
+    fn tracked(&self) -> Tracked {
+        Tracked::Memo(Rc::downgrade(self))
+    }
+}
+
+impl Track for Rc<Task> {
+    fn tracked(&self) -> Tracked {
+        Tracked::Task(Rc::downgrade(self))
+    }
+}
+
+impl Track for Rc<RefCell<Heap>> {
+    fn tracked(&self) -> Tracked {
+        Tracked::Heap(Rc::downgrade(self))
+    }
+}
+
+impl Track for Rc<RefCell<VecDeque<Object>>> {
+    fn tracked(&self) -> Tracked {
+        Tracked::Deque(Rc::downgrade(self))
+    }
+}
+
+impl Track for Rc<RefCell<HashTable>> {
+    fn tracked(&self) -> Tracked {
+        Tracked::HashTable(Rc::downgrade(self))
+    }
+}
+
+impl Track for Rc<Record> {
+    fn tracked(&self) -> Tracked {
+        Tracked::Record(Rc::downgrade(self))
+    }
+}
+
+impl Track for Rc<Promise> {
+    fn tracked(&self) -> Tracked {
+        Tracked::Promise(Rc::downgrade(self))
+    }
+}
+
+impl Track for Rc<RefCell<SortedMap>> {
+    fn tracked(&self) -> Tracked {
+        Tracked::SortedMap(Rc::downgrade(self))
+    }
+}
+
+struct Collector {
+    tracked: Vec<Tracked>,
+    threshold: usize,
+    collections: u64,
+    freed: u64,
+    /// The objects of every `Rooted` handle, by handle.
+    roots: BTreeMap<u64, Vec<Object>>,
+    next_root: u64,
+}
+
+thread_local! {
+    static COLLECTOR: RefCell<Collector> = const {
+        RefCell::new(Collector {
+            tracked: Vec::new(),
+            threshold: INITIAL_THRESHOLD,
+            collections: 0,
+            freed: 0,
+            roots: BTreeMap::new(),
+            next_root: 0,
+        })
+    };
+}
+
+/// Values whose objects `Rooted` can hold as roots.
+pub trait Rootable {
+    /// Calls `f` with every object of the value managed by the collector.
+    fn objects(&self, f: &mut dyn FnMut(&Object));
+}
+
+impl Rootable for Object {
+    fn objects(&self, f: &mut dyn FnMut(&Object)) {
+        f(self)
+    }
+}
+
+impl Rootable for Value {
+    fn objects(&self, f: &mut dyn FnMut(&Object)) {
+        match self {
+            Value::Other(obj) => f(obj),
+            Value::List(items) => items.iter().for_each(|item| item.objects(f)),
+            _ => {}
+        }
+    }
+}
+
+/// The registration of a `Rooted` handle's objects, undone when dropped.
+struct Root(u64);
+
+impl Drop for Root {
+    fn drop(&mut self) {
+        // The objects are dropped once the collector is no longer borrowed.
+        let objects = COLLECTOR.with(|collector| collector.borrow_mut().roots.remove(&self.0));
+        drop(objects);
+    }
+}
+
+/// A value held by Rust code that the collector keeps alive, with
+/// everything it refers to, until the handle is dropped. It derefs to the
+/// value.
+pub struct Rooted<T: Rootable = Value> {
+    value: T,
+    _root: Root,
+}
+
+impl<T: Rootable> Rooted<T> {
+    pub fn new(value: T) -> Self {
+        let mut objects = Vec::new();
+        value.objects(&mut |obj| objects.push(obj.clone()));
+        let id = COLLECTOR.with(|collector| {
+            let mut collector = collector.borrow_mut();
+            let id = collector.next_root;
+            collector.next_root += 1;
+            collector.roots.insert(id, objects);
+            id
+        });
+
+        Self {
+            value,
+            _root: Root(id),
+        }
+    }
+
+    /// Gives up the handle, returning the value.
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+}
+
+impl<T: Rootable> Deref for Rooted<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T: Rootable + Clone> Clone for Rooted<T> {
+    fn clone(&self) -> Self {
+        Self::new(self.value.clone())
+    }
+}
+
+impl<T: Rootable + fmt::Debug> fmt::Debug for Rooted<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Rooted").field(&self.value).finish()
+    }
+}
+
+/// Starts tracking `container`, collecting first if enough containers have
+/// been created since the last collection.
+pub(crate) fn track<T: Track>(container: &T) {
+    let due = COLLECTOR.with(|collector| {
+        let mut collector = collector.borrow_mut();
+        collector.tracked.push(container.tracked());
+        if collector.tracked.len() < collector.threshold {
+            return false;
+        }
+
+        // Most containers die through reference counting alone; only run a
+        // full collection when pruning those does not free enough room.
+        collector.tracked.retain(Tracked::is_alive);
+        collector.tracked.len() * 2 >= collector.threshold
+    });
+
+    if due {
+        collect();
+    }
+}
+
+struct Entry {
+    node: Node,
+    /// Strong references to the node, not counting the collector's own.
+    references: usize,
+    /// References coming from other nodes in the graph.
+    internal: usize,
+    live: bool,
+}
+
+/// Runs a collection and returns the number of objects freed.
+pub fn collect() -> usize {
+    let roots: Vec<Node> = COLLECTOR.with(|collector| {
+        let mut collector = collector.borrow_mut();
+        collector.tracked.retain(Tracked::is_alive);
+        collector
+            .tracked
+            .iter()
+            .filter_map(Tracked::upgrade)
+            .collect()
+    });
+
+    let freed = match find_garbage(roots) {
+        Ok(table) => {
+            let garbage: Vec<&Node> = table
+                .values()
+                .filter(|entry| !entry.live)
+                .map(|entry| &entry.node)
+                .collect();
+            for node in &garbage {
+                node.clear();
+            }
+            garbage.len()
+        }
+        Err(Busy) => 0,
+    };
+
+    COLLECTOR.with(|collector| {
+        let mut collector = collector.borrow_mut();
+        collector.collections += 1;
+        collector.freed += freed as u64;
+        collector.tracked.retain(Tracked::is_alive);
+        collector.threshold = (collector.tracked.len() * 2).max(INITIAL_THRESHOLD);
+    });
+
+    freed
+}
+
+/// Adds `node` to the table the first time it is seen and returns its entry.
+fn discover<'a>(
+    table: &'a mut HashMap<usize, Entry>,
+    pending: &mut Vec<usize>,
+    node: Node,
+) -> &'a mut Entry {
+    let id = node.id();
+
+    table.entry(id).or_insert_with(|| {
+        pending.push(id);
+        Entry {
+            // `node` itself is the collector's handle.
+            references: node.strong_count() - 1,
+            node,
+            internal: 0,
+            live: false,
+        }
+    })
+}
+
+/// Scans everything reachable from `roots` and marks the entries that are
+/// reachable from outside the graph as live.
+fn find_garbage(roots: Vec<Node>) -> Result<HashMap<usize, Entry>, Busy> {
+    let mut table = HashMap::new();
+    let mut pending = Vec::new();
+
+    for root in roots {
+        discover(&mut table, &mut pending, root);
+    }
+
+    while let Some(id) = pending.pop() {
+        let node = table[&id].node.clone();
+        node.children(&mut |child| {
+            discover(&mut table, &mut pending, child).internal += 1;
+        })?;
+    }
+
+    let mut live: Vec<usize> = table
+        .iter()
+        .filter(|(_, entry)| entry.references > entry.internal)
+        .map(|(&id, _)| id)
+        .collect();
+    while let Some(id) = live.pop() {
+        let entry = table.get_mut(&id).unwrap();
+        if entry.live {
+            continue;
+        }
+        entry.live = true;
+
+        let node = entry.node.clone();
+        node.children(&mut |child| live.push(child.id()))?;
+    }
+
+    Ok(table)
+}
+
+/// `(gc)` runs a collection and returns the number of objects freed.
+pub fn gc(args: &[Object]) -> Result<Object, EvalError> {
+    if !args.is_empty() {
+        return Err(EvalError::new("gc expects no arguments"));
+    }
+
+    Ok(Object::Integer(collect() as i64))
+}
+
+/// `(gc-stats)` returns an association list with the number of collections
+/// run, the total number of objects they freed, the number of containers
+/// currently tracked and the number of `Rooted` handles held by Rust code.
+pub fn gc_stats(args: &[Object]) -> Result<Object, EvalError> {
+    if !args.is_empty() {
+        return Err(EvalError::new("gc-stats expects no arguments"));
+    }
+
+    COLLECTOR.with(|collector| {
+        let collector = collector.borrow();
+        let entry = |key: &str, value: u64| {
+            Object::cons(Object::symbol(key), Object::Integer(value as i64))
+        };
+
+        Ok(Object::list(vec![
+            entry("collections", collector.collections),
+            entry("freed", collector.freed),
+            entry("tracked", collector.tracked.len() as u64),
+            entry("rooted", collector.roots.len() as u64),
+        ]))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::{collect, Rooted};
+    use crate::builtins::global_env;
+    use crate::eval::eval_str;
+    use crate::interpreter::{Interpreter, Value};
+    use crate::object::Object;
+
+    #[test]
+    fn test_collects_closure_cycles() {
+        let env = global_env();
+        eval_str("(define (f) 1) (define x (list 1 2))", &env).unwrap();
+        let weak = Rc::downgrade(&env);
+
+        // Alive while referenced from Rust.
+        collect();
+        assert!(weak.upgrade().is_some());
+
+        drop(env);
+        assert!(
+            weak.upgrade().is_some(),
+            "closure cycle keeps the env alive"
+        );
+        assert!(collect() > 0);
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn test_collects_pair_cycles() {
+        let env = global_env();
+        let Object::Pair(pair) = eval_str(
+            "(let ((l (list 1 2 3)))
+               (set-cdr! (cddr l) l)
+               (set-car! (cdr l) l)
+               l)",
+            &env,
+        )
+        .unwrap() else {
+            panic!("expected a pair");
+        };
+        let weak = Rc::downgrade(&pair);
+
+        collect();
+        assert!(weak.upgrade().is_some());
+
+        drop(pair);
+        assert!(weak.upgrade().is_some(), "the cycle keeps the list alive");
+        assert!(collect() >= 3);
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn test_keeps_reachable_objects() {
+        let env = global_env();
+        eval_str(
+            "(define (make-counter)
+               (define n 0)
+               (lambda () (set! n (+ n 1)) n))
+             (define counter (make-counter))
+             (counter)",
+            &env,
+        )
+        .unwrap();
+
+        collect();
+        assert_eq!(eval_str("(counter)", &env).unwrap().to_string(), "2");
+    }
+
+    #[test]
+    fn test_gc_builtins() {
+        let env = global_env();
+        let stats = eval_str("(gc) (gc-stats)", &env).unwrap().to_string();
+
+        assert!(stats.starts_with("((collections . "));
+        assert!(eval_str("(gc 1)", &env).is_err());
+    }
+
+    #[test]
+    fn test_rooted_values() {
+        let mut interp = Interpreter::new();
+        let counter = interp
+            .eval_str(
+                "(define (make-counter)
+                   (define n 0)
+                   (define (next) (set! n (+ n 1)) n)
+                   next)
+                 (list 1 (make-counter))",
+            )
+            .unwrap();
+        let Value::List(items) = &counter else {
+            panic!("expected a list, got {:?}", counter);
+        };
+        let Value::Other(Object::Lambda(lambda)) = &items[1] else {
+            panic!("expected a lambda, got {:?}", items[1]);
+        };
+        let env = Rc::downgrade(&lambda.env);
+        let rooted = Rooted::new(counter.clone());
+        let copy = rooted.clone();
+        drop(counter);
+        drop(interp);
+
+        let rooted_count = || {
+            let stats = eval_str("(gc-stats)", &global_env()).unwrap().to_string();
+            stats.contains("(rooted . 2)")
+        };
+        assert!(rooted_count());
+        collect();
+        assert!(env.upgrade().is_some());
+        assert_eq!(*rooted, *copy);
+
+        drop(rooted);
+        let value = copy.into_inner();
+        assert!(!rooted_count());
+        collect();
+        assert!(env.upgrade().is_some(), "the value itself still holds it");
+        drop(value);
+        assert!(env.upgrade().is_some(), "the closure cycle keeps it alive");
+        collect();
+        assert!(env.upgrade().is_none());
+    }
+}
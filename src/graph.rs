@@ -0,0 +1,335 @@
+//! Graph algorithms over adjacency lists.
+//!
+//! A graph is an association list mapping each node to its neighbors,
+//! `((a b c) (b c) (c))`. A neighbor may be written `(node weight)` to give
+//! the edge a weight for `shortest-path`; unweighted edges weigh 1.
+
+use crate::eval::EvalError;
+use crate::object::Object;
+
+struct Graph {
+    nodes: Vec<Object>,
+    edges: Vec<Vec<(usize, f64)>>,
+}
+
+impl Graph {
+    fn parse(graph: &Object) -> Result<Self, EvalError> {
+        let entries = graph
+            .to_vec()
+            .ok_or_else(|| EvalError::new("a graph must be a list of adjacency lists"))?;
+        let mut parsed = Graph {
+            nodes: Vec::new(),
+            edges: Vec::new(),
+        };
+
+        for entry in entries {
+            let entry = entry
+                .to_vec()
+                .filter(|entry| !entry.is_empty())
+                .ok_or_else(|| EvalError::new(format!("invalid adjacency list: {}", entry)))?;
+            let from = parsed.index(&entry[0]);
+
+            for neighbor in &entry[1..] {
+                let (to, weight) = match neighbor.to_vec().as_deref() {
+                    Some([node, w]) => (parsed.index(node), weight(w)?),
+                    _ => (parsed.index(neighbor), 1.0),
+                };
+                parsed.edges[from].push((to, weight));
+            }
+        }
+
+        Ok(parsed)
+    }
+
+    fn index(&mut self, node: &Object) -> usize {
+        match self.position(node) {
+            Some(i) => i,
+            None => {
+                self.nodes.push(node.clone());
+                self.edges.push(Vec::new());
+                self.nodes.len() - 1
+            }
+        }
+    }
+
+    fn position(&self, node: &Object) -> Option<usize> {
+        self.nodes.iter().position(|n| n == node)
+    }
+
+    fn node_list(&self, indices: impl IntoIterator<Item = usize>) -> Object {
+        Object::list(
+            indices
+                .into_iter()
+                .map(|i| self.nodes[i].clone())
+                .collect::<Vec<_>>(),
+        )
+    }
+}
+
+fn weight(obj: &Object) -> Result<f64, EvalError> {
+    let weight = match obj {
+        Object::Integer(n) => *n as f64,
+        Object::Float(n) => *n,
+        Object::BigInt(n) => n.to_f64(),
+        Object::Rational(n) => n.to_f64(),
+        other => return Err(EvalError::new(format!("invalid edge weight: {}", other))),
+    };
+
+    if weight < 0.0 {
+        return Err(EvalError::new("edge weights must not be negative"));
+    }
+
+    Ok(weight)
+}
+
+/// `(make-graph '((a b) (a c 2) (b c)))` builds an adjacency list from
+/// `(from to)` or weighted `(from to weight)` edges.
+pub fn make_graph(args: &[Object]) -> Result<Object, EvalError> {
+    let edges = match args {
+        [edges] => edges
+            .to_vec()
+            .ok_or_else(|| EvalError::new("make-graph expects a list of edges"))?,
+        _ => return Err(EvalError::new("make-graph expects a list of edges")),
+    };
+    let mut nodes: Vec<Object> = Vec::new();
+    let mut neighbors: Vec<Vec<Object>> = Vec::new();
+
+    let index = |node: &Object, nodes: &mut Vec<Object>, neighbors: &mut Vec<Vec<Object>>| {
+        nodes.iter().position(|n| n == node).unwrap_or_else(|| {
+            nodes.push(node.clone());
+            neighbors.push(Vec::new());
+            nodes.len() - 1
+        })
+    };
+
+    for edge in edges {
+        let (from, to) = match edge.to_vec().as_deref() {
+            Some([from, to]) => (from.clone(), to.clone()),
+            Some([from, to, w]) => {
+                weight(w)?;
+                (from.clone(), Object::list(vec![to.clone(), w.clone()]))
+            }
+            _ => return Err(EvalError::new(format!("invalid edge: {}", edge))),
+        };
+
+        let target = match &to {
+            Object::Pair(pair) => pair.car(),
+            node => node.clone(),
+        };
+        let from = index(&from, &mut nodes, &mut neighbors);
+        index(&target, &mut nodes, &mut neighbors);
+        neighbors[from].push(to);
+    }
+
+    Ok(Object::list(
+        nodes
+            .into_iter()
+            .zip(neighbors)
+            .map(|(node, neighbors)| Object::cons(node, Object::list(neighbors)))
+            .collect::<Vec<_>>(),
+    ))
+}
+
+/// Orders the nodes so that every edge points forward, failing on cycles.
+pub fn topological_sort(args: &[Object]) -> Result<Object, EvalError> {
+    let graph = match args {
+        [graph] => Graph::parse(graph)?,
+        _ => return Err(EvalError::new("topological-sort expects a graph")),
+    };
+
+    let mut in_degree = vec![0; graph.nodes.len()];
+    for edges in &graph.edges {
+        for &(to, _) in edges {
+            in_degree[to] += 1;
+        }
+    }
+
+    let mut ready: Vec<usize> = (0..graph.nodes.len())
+        .filter(|&i| in_degree[i] == 0)
+        .rev()
+        .collect();
+    let mut order = Vec::with_capacity(graph.nodes.len());
+
+    while let Some(node) = ready.pop() {
+        order.push(node);
+        for &(to, _) in graph.edges[node].iter().rev() {
+            in_degree[to] -= 1;
+            if in_degree[to] == 0 {
+                ready.push(to);
+            }
+        }
+    }
+
+    if order.len() != graph.nodes.len() {
+        return Err(EvalError::new("topological-sort: the graph has a cycle"));
+    }
+
+    Ok(graph.node_list(order))
+}
+
+/// Tarjan's algorithm, run with an explicit stack so deep graphs cannot
+/// overflow the Rust stack.
+pub fn strongly_connected_components(args: &[Object]) -> Result<Object, EvalError> {
+    let graph = match args {
+        [graph] => Graph::parse(graph)?,
+        _ => {
+            return Err(EvalError::new(
+                "strongly-connected-components expects a graph",
+            ))
+        }
+    };
+
+    let n = graph.nodes.len();
+    let mut index = vec![usize::MAX; n];
+    let mut low = vec![0; n];
+    let mut on_stack = vec![false; n];
+    let mut stack = Vec::new();
+    let mut components = Vec::new();
+    let mut counter = 0;
+
+    for root in 0..n {
+        if index[root] != usize::MAX {
+            continue;
+        }
+
+        let mut work = vec![(root, 0)];
+        while let Some(&mut (node, ref mut next)) = work.last_mut() {
+            if *next == 0 {
+                index[node] = counter;
+                low[node] = counter;
+                counter += 1;
+                stack.push(node);
+                on_stack[node] = true;
+            }
+
+            if let Some(&(to, _)) = graph.edges[node].get(*next) {
+                *next += 1;
+                if index[to] == usize::MAX {
+                    work.push((to, 0));
+                } else if on_stack[to] {
+                    low[node] = low[node].min(index[to]);
+                }
+                continue;
+            }
+
+            work.pop();
+            if let Some(&(parent, _)) = work.last() {
+                low[parent] = low[parent].min(low[node]);
+            }
+
+            if low[node] == index[node] {
+                let mut component = Vec::new();
+                while let Some(member) = stack.pop() {
+                    on_stack[member] = false;
+                    component.push(member);
+                    if member == node {
+                        break;
+                    }
+                }
+                component.reverse();
+                components.push(graph.node_list(component));
+            }
+        }
+    }
+
+    Ok(Object::list(components))
+}
+
+/// Dijkstra's algorithm; returns the list of nodes from `from` to `to`, or
+/// `#f` when `to` is unreachable.
+pub fn shortest_path(args: &[Object]) -> Result<Object, EvalError> {
+    let (graph, from, to) = match args {
+        [graph, from, to] => (Graph::parse(graph)?, from, to),
+        _ => {
+            return Err(EvalError::new(
+                "shortest-path expects a graph and two nodes",
+            ))
+        }
+    };
+
+    let (from, to) = match (graph.position(from), graph.position(to)) {
+        (Some(from), Some(to)) => (from, to),
+        _ => return Ok(Object::Bool(false)),
+    };
+
+    let n = graph.nodes.len();
+    let mut distance = vec![f64::INFINITY; n];
+    let mut previous = vec![None; n];
+    let mut done = vec![false; n];
+    distance[from] = 0.0;
+
+    while let Some(node) = (0..n)
+        .filter(|&i| !done[i] && distance[i].is_finite())
+        .min_by(|&a, &b| distance[a].total_cmp(&distance[b]))
+    {
+        if node == to {
+            break;
+        }
+        done[node] = true;
+
+        for &(next, weight) in &graph.edges[node] {
+            if distance[node] + weight < distance[next] {
+                distance[next] = distance[node] + weight;
+                previous[next] = Some(node);
+            }
+        }
+    }
+
+    if !distance[to].is_finite() {
+        return Ok(Object::Bool(false));
+    }
+
+    let mut path = vec![to];
+    while let Some(node) = previous[*path.last().unwrap()] {
+        path.push(node);
+    }
+    path.reverse();
+
+    Ok(graph.node_list(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builtins::global_env;
+    use crate::eval::eval_str;
+
+    fn eval(program: &str) -> String {
+        eval_str(program, &global_env()).unwrap().to_string()
+    }
+
+    #[test]
+    fn test_make_graph() {
+        assert_eq!(
+            eval("(make-graph '((a b) (a c 2) (b c)))"),
+            "((a b (c 2)) (b c) (c))"
+        );
+    }
+
+    #[test]
+    fn test_topological_sort() {
+        assert_eq!(
+            eval("(topological-sort '((app lib utils) (lib utils) (utils) (docs)))"),
+            "(app lib utils docs)"
+        );
+        assert!(eval_str("(topological-sort '((a b) (b a)))", &global_env()).is_err());
+    }
+
+    #[test]
+    fn test_strongly_connected_components() {
+        assert_eq!(
+            eval("(strongly-connected-components '((a b) (b c) (c a d) (d)))"),
+            "((d) (a b c))"
+        );
+    }
+
+    #[test]
+    fn test_shortest_path() {
+        let graph = "'((a (b 1) (c 5)) (b (c 1)) (c (d 1)) (d))";
+
+        assert_eq!(
+            eval(&format!("(shortest-path {} 'a 'd)", graph)),
+            "(a b c d)"
+        );
+        assert_eq!(eval(&format!("(shortest-path {} 'd 'a)", graph)), "#f");
+    }
+}
@@ -0,0 +1,125 @@
+//! `guard`, which handles the errors raised in its body:
+//!
+//! ```text
+//! (guard (e ((equal? (hash-table-ref e "kind") "read-limit") 'too-big))
+//!   (read-string text #:max-depth 32))
+//! ```
+//!
+//! When the body fails, the clauses are tried in order, like those of
+//! `cond`, with the variable bound to the condition: the error as data, the
+//! hash table `EvalError::to_value` makes, whose `"kind"` tells errors
+//! apart, such as `"read-limit"` for data over the limits it is read within,
+//! and whose `"message"` says what went wrong. The first clause whose test
+//! is true gives the value of its last expression, or of the test if it has
+//! none, and an `(else expr...)` clause always does. When no clause does,
+//! the error is raised again.
+//!
+//! Exits, escapes, interrupts and going over an evaluation limit are not
+//! failures of the body, and leave `guard` without trying the clauses.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::env::Env;
+use crate::eval::{eval, EvalError};
+use crate::object::Object;
+
+/// `(guard (var clause...) body...)`
+pub fn guard(list: &[Object], env: &Rc<RefCell<Env>>) -> Result<Object, EvalError> {
+    let spec = list.get(1).and_then(Object::to_vec);
+    let (Some([Object::Symbol(var), clauses @ ..]), true) = (spec.as_deref(), list.len() > 2)
+    else {
+        return Err(EvalError::new(
+            "guard expects a variable with clauses, and a body",
+        ));
+    };
+    let clauses = clauses
+        .iter()
+        .map(|clause| match clause.to_vec() {
+            Some(clause) if !clause.is_empty() => Ok(clause),
+            _ => Err(EvalError::new(format!("guard: invalid clause: {}", clause))),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let err = match list[2..]
+        .iter()
+        .try_fold(Object::Void, |_, form| eval(form, env))
+    {
+        Ok(value) => return Ok(value),
+        Err(err) if err.is_failure() => err,
+        Err(err) => return Err(err),
+    };
+
+    let mut scope = Env::extend(env.clone());
+    scope.define(*var, err.to_value());
+    let scope = Rc::new(RefCell::new(scope));
+    for clause in &clauses {
+        let test = match &clause[0] {
+            Object::Symbol(name) if *name == "else" => Object::Bool(true),
+            test => eval(test, &scope)?,
+        };
+        if test.is_truthy() {
+            return clause[1..]
+                .iter()
+                .try_fold(test, |_, expr| eval(expr, &scope));
+        }
+    }
+
+    Err(err)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builtins::global_env;
+    use crate::eval::eval_str;
+
+    #[test]
+    fn test_guard() {
+        let env = global_env();
+        let eval = |program: &str| match eval_str(program, &env) {
+            Ok(value) => value.to_string(),
+            Err(e) => e.message().to_string(),
+        };
+
+        assert_eq!(eval("(guard (e (else 'caught)) (+ 1 2))"), "3");
+        assert_eq!(
+            eval("(guard (e (else (hash-table-ref e \"message\"))) (car 1))"),
+            "\"car expects a pair, got 1\""
+        );
+        assert_eq!(
+            eval(
+                "(define (read-small text)
+                   (guard (e ((equal? (hash-table-ref e \"kind\") \"read-limit\") 'too-big))
+                     (read-string text #:max-depth 2)))
+                 (list (read-small \"(1 (2))\") (read-small \"(1 (2 (3)))\"))"
+            ),
+            "((1 (2)) too-big)"
+        );
+        assert_eq!(
+            eval(
+                "(guard (e ((equal? (hash-table-ref e \"kind\") \"read-limit\") 'too-big))
+                   (json-parse \"[[[1]]]\" #:max-depth 2))"
+            ),
+            "too-big"
+        );
+
+        // Errors no clause handles, and errors in the clauses, go on.
+        assert_eq!(
+            eval("(guard (e ((string? e) 'no)) (car 1))"),
+            "car expects a pair, got 1"
+        );
+        assert_eq!(
+            eval("(guard (e (else (cdr 2))) (car 1))"),
+            "cdr expects a pair, got 2"
+        );
+        assert_eq!(eval("(guard (e (#f 1) (7)) (car 1))"), "7");
+        assert_eq!(
+            eval("(+ 1 (call/cc (lambda (k) (guard (e (else 0)) (k 10)))))"),
+            "11"
+        );
+        assert_eq!(
+            eval("(guard e (car 1))"),
+            "guard expects a variable with clauses, and a body"
+        );
+    }
+}
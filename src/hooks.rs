@@ -0,0 +1,280 @@
+//! Hooks into the evaluator for tools such as tracers, profilers and
+//! coverage reports.
+//!
+//! A `Hook` registered with `add` hears about every procedure call and
+//! return, every `define`, every list evaluated and every error the
+//! tree-walking evaluator sees.
+//! Compiled code reports only the calls it makes back into the evaluator.
+//! Tail calls still run in constant Rust stack while hooks are installed,
+//! but each one is remembered until its caller returns, so that every
+//! `on_call` is matched by an `on_return` or an `on_unwind`.
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::env::Env;
+use crate::eval::EvalError;
+use crate::object::{Lambda, Object};
+
+/// Callbacks for evaluator events. Every method does nothing by default.
+/// Hooks are not themselves observed: while one runs, any Lisp code it
+/// evaluates triggers no events.
+pub trait Hook {
+    fn on_call(&self, _func: &Object, _args: &[Object]) {}
+
+    /// `result` is the value of the call to `func`.
+    fn on_return(&self, _func: &Object, _result: &Object) {}
+
+    /// Called instead of `on_return` for a call an error, an exit or a
+    /// continuation escape leaves without a value.
+    fn on_unwind(&self, _func: &Object) {}
+
+    fn on_define(&self, _name: &str, _value: &Object) {}
+
+    /// Called before evaluating each list, a call or a special form, with
+    /// the environment it is evaluated in.
+    fn on_eval(&self, _expr: &Object, _env: &Rc<RefCell<Env>>) {}
+
+    /// Reported once, by the innermost evaluation the error passes through.
+    /// Exits and continuation escapes are not errors and are not reported.
+    fn on_error(&self, _err: &EvalError) {}
+}
+
+/// Identifies a hook for `remove`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HookId(u64);
+
+struct Hooks {
+    hooks: RefCell<Vec<(HookId, Rc<dyn Hook>)>>,
+    next_id: Cell<u64>,
+    /// Set while a hook runs.
+    running: Cell<bool>,
+}
+
+thread_local! {
+    static HOOKS: Hooks = Hooks {
+        hooks: RefCell::new(Vec::new()),
+        next_id: Cell::new(0),
+        running: Cell::new(false),
+    };
+}
+
+pub fn add(hook: Rc<dyn Hook>) -> HookId {
+    HOOKS.with(|hooks| {
+        let id = HookId(hooks.next_id.get());
+        hooks.next_id.set(id.0 + 1);
+        hooks.hooks.borrow_mut().push((id, hook));
+        id
+    })
+}
+
+/// Unregisters a hook, returning whether it was registered.
+pub fn remove(id: HookId) -> bool {
+    HOOKS.with(|hooks| {
+        let mut hooks = hooks.hooks.borrow_mut();
+        let len = hooks.len();
+        hooks.retain(|(hook_id, _)| *hook_id != id);
+        hooks.len() < len
+    })
+}
+
+/// Whether events should be reported, checked before doing any work to
+/// report them.
+pub(crate) fn active() -> bool {
+    HOOKS.with(|hooks| !hooks.running.get() && !hooks.hooks.borrow().is_empty())
+}
+
+/// Runs `event` on every hook, with hooks disabled meanwhile. The list is
+/// copied first, so hooks can add or remove hooks.
+fn notify(event: impl Fn(&dyn Hook)) {
+    let hooks = HOOKS.with(|hooks| {
+        hooks.running.set(true);
+        hooks.hooks.borrow().clone()
+    });
+
+    for (_, hook) in &hooks {
+        event(hook.as_ref());
+    }
+
+    HOOKS.with(|hooks| hooks.running.set(false));
+}
+
+/// The names procedures were defined with, for hooks that show them. It
+/// learns them from the `on_define` events passed to `define`.
+#[derive(Default)]
+pub(crate) struct ProcedureNames(RefCell<HashMap<usize, String>>);
+
+/// An identity for procedures that are not builtins.
+fn procedure_id(obj: &Object) -> Option<usize> {
+    match obj {
+        Object::Lambda(lambda) => Some(Rc::as_ptr(lambda) as *const () as usize),
+        Object::Closure(closure) => Some(Rc::as_ptr(closure) as *const () as usize),
+        Object::Memo(memo) => Some(Rc::as_ptr(memo) as *const () as usize),
+        Object::Native(native) => Some(Rc::as_ptr(native) as *const () as usize),
+        _ => None,
+    }
+}
+
+impl ProcedureNames {
+    pub(crate) fn define(&self, name: &str, value: &Object) {
+        if let Some(id) = procedure_id(value) {
+            self.0.borrow_mut().insert(id, name.to_string());
+        }
+    }
+
+    /// The name of a builtin, the name `func` was defined with, or
+    /// `<lambda>`. A lambda defined before the names were learned is
+    /// looked up in the environment it was made in, once.
+    pub(crate) fn name(&self, func: &Object) -> String {
+        let Some(id) = procedure_id(func) else {
+            return match func {
+                Object::Builtin(builtin) => builtin.name.to_string(),
+                _ => String::from("<lambda>"),
+            };
+        };
+        if let Some(name) = self.0.borrow().get(&id) {
+            return name.clone();
+        }
+
+        let name = match func {
+            Object::Lambda(lambda) => defined_name(lambda),
+            _ => None,
+        }
+        .unwrap_or_else(|| String::from("<lambda>"));
+        self.0.borrow_mut().insert(id, name.clone());
+        name
+    }
+}
+
+/// The name `define` bound a lambda to: the one in the environment it was
+/// made in.
+pub(crate) fn defined_name(lambda: &Rc<Lambda>) -> Option<String> {
+    let env = lambda.env.borrow();
+    env.bindings()
+        .into_iter()
+        .find_map(|(name, value)| match value {
+            Object::Lambda(bound) if Rc::ptr_eq(&bound, lambda) => Some(name.to_string()),
+            _ => None,
+        })
+}
+
+pub(crate) fn call(func: &Object, args: &[Object]) {
+    notify(|hook| hook.on_call(func, args));
+}
+
+pub(crate) fn ret(func: &Object, result: &Object) {
+    notify(|hook| hook.on_return(func, result));
+}
+
+pub(crate) fn unwind(func: &Object) {
+    notify(|hook| hook.on_unwind(func));
+}
+
+pub(crate) fn define(name: &str, value: &Object) {
+    if active() {
+        notify(|hook| hook.on_define(name, value));
+    }
+}
+
+pub(crate) fn eval(expr: &Object, env: &Rc<RefCell<Env>>) {
+    notify(|hook| hook.on_eval(expr, env));
+}
+
+pub(crate) fn error(err: &EvalError) {
+    notify(|hook| hook.on_error(err));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builtins::global_env;
+    use crate::eval::eval_str;
+
+    #[derive(Default)]
+    struct Recorder {
+        events: RefCell<Vec<String>>,
+    }
+
+    impl Hook for Recorder {
+        fn on_call(&self, func: &Object, args: &[Object]) {
+            let args = Object::list(args.iter().cloned());
+            self.events
+                .borrow_mut()
+                .push(format!("call {} {}", func, args));
+        }
+
+        fn on_return(&self, _func: &Object, result: &Object) {
+            self.events.borrow_mut().push(format!("return {}", result));
+        }
+
+        fn on_define(&self, name: &str, _value: &Object) {
+            self.events.borrow_mut().push(format!("define {}", name));
+        }
+
+        fn on_error(&self, err: &EvalError) {
+            self.events
+                .borrow_mut()
+                .push(format!("error {}", err.message()));
+        }
+    }
+
+    #[test]
+    fn test_events() {
+        let env = global_env();
+        let recorder = Rc::new(Recorder::default());
+        let id = add(recorder.clone());
+
+        eval_str("(define (f x) (+ x 1)) (f 2) (car '())", &env).unwrap_err();
+        assert!(remove(id));
+        assert!(!remove(id));
+        eval_str("(f 3)", &env).unwrap();
+
+        assert_eq!(
+            *recorder.events.borrow(),
+            [
+                "define f",
+                "call #<procedure> (2)",
+                "call #<builtin +> (2 1)",
+                "return 3",
+                "return 3",
+                "call #<builtin car> (())",
+                "error car expects a pair, got ()",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tail_calls_are_matched() {
+        #[derive(Default)]
+        struct Depth {
+            depth: Cell<i64>,
+            deepest: Cell<i64>,
+        }
+
+        impl Hook for Depth {
+            fn on_call(&self, _func: &Object, _args: &[Object]) {
+                self.depth.set(self.depth.get() + 1);
+                self.deepest.set(self.deepest.get().max(self.depth.get()));
+            }
+
+            fn on_return(&self, _func: &Object, _result: &Object) {
+                self.depth.set(self.depth.get() - 1);
+            }
+        }
+
+        let env = global_env();
+        let depth = Rc::new(Depth::default());
+        let id = add(depth.clone());
+        let result = eval_str(
+            "(define (count n acc) (if (= n 0) acc (count (- n 1) (+ acc 1))))
+             (count 100000 0)",
+            &env,
+        );
+        remove(id);
+
+        assert_eq!(result.unwrap().to_string(), "100000");
+        assert_eq!(depth.depth.get(), 0);
+        assert!(depth.deepest.get() > 100000);
+    }
+}
@@ -0,0 +1,244 @@
+//! Message catalogs for localized programs.
+//!
+//! `(define-messages 'fr 'greeting "Bonjour, ~a !")` adds messages to a
+//! locale's catalog and `(msg 'greeting name)` looks them up in the current
+//! locale. A regional locale such as `fr-CA` falls back to its language,
+//! `fr`, and then to the fallback locale, `en` unless changed.
+//!
+//! Each `Interpreter` has a catalog and locale of its own. Evaluations
+//! outside of one share those of their thread.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::env;
+use std::rc::Rc;
+
+use crate::eval::EvalError;
+use crate::object::Object;
+use crate::symbol::Symbol;
+use crate::taint;
+
+pub(crate) struct Catalog {
+    locale: Symbol,
+    fallback: Symbol,
+    messages: HashMap<Symbol, HashMap<Symbol, String>>,
+}
+
+impl Default for Catalog {
+    fn default() -> Self {
+        Self {
+            locale: system_locale(),
+            fallback: Symbol::intern("en"),
+            messages: HashMap::new(),
+        }
+    }
+}
+
+thread_local! {
+    /// The catalog of the interpreter evaluating, or that of the thread.
+    static CATALOG: RefCell<Rc<RefCell<Catalog>>> = RefCell::default();
+}
+
+/// Runs `f` with the messages and locale of `catalog`.
+pub(crate) fn with_catalog<T>(catalog: &Rc<RefCell<Catalog>>, f: impl FnOnce() -> T) -> T {
+    struct Restore(Rc<RefCell<Catalog>>);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            CATALOG.with(|current| current.replace(self.0.clone()));
+        }
+    }
+
+    let _restore = Restore(CATALOG.with(|current| current.replace(catalog.clone())));
+    f()
+}
+
+fn catalog() -> Rc<RefCell<Catalog>> {
+    CATALOG.with(|current| current.borrow().clone())
+}
+
+/// The locale named by `LC_ALL`, `LC_MESSAGES` or `LANG`, turning
+/// `fr_CA.UTF-8` into `fr-CA`.
+fn system_locale() -> Symbol {
+    let name = ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|var| env::var(var).ok())
+        .find(|value| !value.is_empty() && value != "C" && value != "POSIX");
+
+    match name {
+        Some(name) => {
+            let name = name.split(['.', '@']).next().unwrap_or_default();
+            Symbol::intern(&name.replace('_', "-"))
+        }
+        None => Symbol::intern("en"),
+    }
+}
+
+/// The locales searched for a message, most specific first.
+fn lookup_order(catalog: &Catalog) -> Vec<Symbol> {
+    let mut order = vec![catalog.locale];
+    if let Some((language, _)) = catalog.locale.split_once('-') {
+        order.push(Symbol::intern(language));
+    }
+    order.push(catalog.fallback);
+
+    order
+}
+
+fn locale_arg(name: &str, obj: &Object) -> Result<Symbol, EvalError> {
+    match obj {
+        Object::Symbol(locale) => Ok(*locale),
+        Object::String(locale) => Ok(Symbol::intern(locale)),
+        other => Err(EvalError::new(format!(
+            "{} expects a locale, got {}",
+            name, other
+        ))),
+    }
+}
+
+/// `(define-messages 'fr 'greeting "Bonjour, ~a !" 'bye "Au revoir")`.
+pub fn define_messages(args: &[Object]) -> Result<Object, EvalError> {
+    let (locale, pairs) = match args {
+        [locale, pairs @ ..] if pairs.len() % 2 == 0 => {
+            (locale_arg("define-messages", locale)?, pairs)
+        }
+        _ => {
+            return Err(EvalError::new(
+                "define-messages expects a locale followed by keys and messages",
+            ))
+        }
+    };
+
+    let mut messages = Vec::new();
+    for pair in pairs.chunks(2) {
+        match pair {
+            [Object::Symbol(key), Object::String(message)] => {
+                messages.push((*key, message.to_string()))
+            }
+            _ => {
+                return Err(EvalError::new(format!(
+                    "define-messages: expected a key and a message string, got {} {}",
+                    pair[0], pair[1]
+                )))
+            }
+        }
+    }
+
+    catalog()
+        .borrow_mut()
+        .messages
+        .entry(locale)
+        .or_default()
+        .extend(messages);
+
+    Ok(Object::Void)
+}
+
+/// `(set-locale! 'fr-CA)`, or `(set-locale! 'fr-CA 'fr)` to also change the
+/// fallback locale.
+pub fn set_locale(args: &[Object]) -> Result<Object, EvalError> {
+    let (locale, fallback) = match args {
+        [locale] => (locale_arg("set-locale!", locale)?, None),
+        [locale, fallback] => (
+            locale_arg("set-locale!", locale)?,
+            Some(locale_arg("set-locale!", fallback)?),
+        ),
+        _ => {
+            return Err(EvalError::new(
+                "set-locale! expects a locale and an optional fallback",
+            ))
+        }
+    };
+
+    let catalog = catalog();
+    let mut catalog = catalog.borrow_mut();
+    catalog.locale = locale;
+    if let Some(fallback) = fallback {
+        catalog.fallback = fallback;
+    }
+
+    Ok(Object::Void)
+}
+
+pub fn current_locale(args: &[Object]) -> Result<Object, EvalError> {
+    if !args.is_empty() {
+        return Err(EvalError::new("current-locale expects no arguments"));
+    }
+
+    Ok(Object::Symbol(catalog().borrow().locale))
+}
+
+/// `(msg 'greeting "Ana")` returns the message for `greeting` in the current
+/// locale, with each `~a` replaced by the next argument and `~~` by `~`.
+pub fn msg(args: &[Object]) -> Result<Object, EvalError> {
+    let (key, values) = match args {
+        [Object::Symbol(key), values @ ..] => (*key, values),
+        _ => return Err(EvalError::new("msg expects a message key")),
+    };
+
+    let catalog = catalog();
+    let catalog = catalog.borrow();
+    let template = lookup_order(&catalog).into_iter().find_map(|locale| {
+        catalog
+            .messages
+            .get(&locale)
+            .and_then(|messages| messages.get(&key))
+            .cloned()
+    });
+    let template = template.ok_or_else(|| EvalError::new(format!("no message for {}", key)))?;
+
+    let tainted = values.iter().any(taint::contains);
+    fill(&template, values).map(|s| taint::string(s, tainted))
+}
+
+fn fill(template: &str, values: &[Object]) -> Result<String, EvalError> {
+    let mut out = String::new();
+    let mut values = values.iter();
+    let mut chars = template.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '~' {
+            out.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('~') => out.push('~'),
+            Some('a') => match values.next() {
+                Some(Object::String(s)) => out.push_str(s),
+                Some(value) => out.push_str(&value.to_string()),
+                None => return Err(EvalError::new("msg: not enough arguments for message")),
+            },
+            _ => return Err(EvalError::new("msg: invalid ~ directive in message")),
+        }
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builtins::global_env;
+    use crate::eval::eval_str;
+    use crate::object::Object;
+
+    #[test]
+    fn test_locale_fallback() {
+        let env = global_env();
+        eval_str(
+            r#"(define-messages 'en 'greeting "Hello, ~a!" 'bye "Bye")
+               (define-messages 'fr 'greeting "Bonjour, ~a !")
+               (set-locale! 'fr-CA)"#,
+            &env,
+        )
+        .unwrap();
+
+        let msg = |program| eval_str(program, &env).unwrap();
+        assert_eq!(
+            msg(r#"(msg 'greeting "Ana")"#),
+            Object::string("Bonjour, Ana !")
+        );
+        assert_eq!(msg("(msg 'bye)"), Object::string("Bye"));
+        assert!(eval_str("(msg 'missing)", &env).is_err());
+        assert!(eval_str("(msg 'greeting)", &env).is_err());
+    }
+}
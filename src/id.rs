@@ -0,0 +1,123 @@
+//! Unique identifiers: random and time-ordered UUIDs, and ULIDs.
+
+use jiff::Timestamp;
+
+use crate::eval::EvalError;
+use crate::object::Object;
+
+/// The Crockford base32 alphabet used by ULIDs.
+const CROCKFORD: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+fn random_bytes<const N: usize>() -> Result<[u8; N], EvalError> {
+    let mut bytes = [0; N];
+    getrandom::fill(&mut bytes)
+        .map_err(|e| EvalError::new(format!("cannot get random bytes: {}", e)))?;
+
+    Ok(bytes)
+}
+
+/// Milliseconds since the Unix epoch, truncated to 48 bits.
+fn timestamp() -> u64 {
+    let millis = Timestamp::now().as_millisecond().max(0) as u64;
+
+    millis & 0xffff_ffff_ffff
+}
+
+fn no_args(name: &str, args: &[Object]) -> Result<(), EvalError> {
+    if !args.is_empty() {
+        return Err(EvalError::new(format!("{} expects no arguments", name)));
+    }
+
+    Ok(())
+}
+
+/// Sets the version and variant bits and formats `bytes` as
+/// `xxxxxxxx-xxxx-Vxxx-xxxx-xxxxxxxxxxxx`.
+fn format_uuid(mut bytes: [u8; 16], version: u8) -> String {
+    bytes[6] = (bytes[6] & 0x0f) | (version << 4);
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+    let mut uuid = String::with_capacity(36);
+    for (i, byte) in bytes.iter().enumerate() {
+        if matches!(i, 4 | 6 | 8 | 10) {
+            uuid.push('-');
+        }
+        uuid.push_str(&format!("{:02x}", byte));
+    }
+
+    uuid
+}
+
+/// `(uuid4)` returns a random UUID string.
+pub fn uuid4(args: &[Object]) -> Result<Object, EvalError> {
+    no_args("uuid4", args)?;
+
+    Ok(Object::string(format_uuid(random_bytes()?, 4)))
+}
+
+/// `(uuid7)` returns a UUID string that starts with the current time, so
+/// that UUIDs created later sort after earlier ones.
+pub fn uuid7(args: &[Object]) -> Result<Object, EvalError> {
+    no_args("uuid7", args)?;
+
+    let mut bytes: [u8; 16] = random_bytes()?;
+    bytes[..6].copy_from_slice(&timestamp().to_be_bytes()[2..]);
+
+    Ok(Object::string(format_uuid(bytes, 7)))
+}
+
+/// `(ulid)` returns a 26 character ULID: a 48-bit millisecond timestamp
+/// and 80 random bits, in Crockford base32.
+pub fn ulid(args: &[Object]) -> Result<Object, EvalError> {
+    no_args("ulid", args)?;
+
+    Ok(Object::string(encode_ulid(timestamp(), random_bytes()?)))
+}
+
+fn encode_ulid(timestamp: u64, random: [u8; 10]) -> String {
+    let mut value = u128::from(timestamp) << 80;
+    for (i, byte) in random.iter().enumerate() {
+        value |= u128::from(*byte) << (8 * (9 - i));
+    }
+
+    (0..26)
+        .rev()
+        .map(|i| CROCKFORD[((value >> (5 * i)) & 0x1f) as usize] as char)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(obj: Object) -> String {
+        match obj {
+            Object::String(s) => s.to_string(),
+            other => panic!("expected a string, got {}", other),
+        }
+    }
+
+    #[test]
+    fn test_uuid_layout() {
+        let uuid = string(uuid4(&[]).unwrap());
+        assert_eq!(uuid.len(), 36);
+        assert_eq!(&uuid[14..15], "4");
+        assert!(matches!(&uuid[19..20], "8" | "9" | "a" | "b"));
+        assert_ne!(uuid, string(uuid4(&[]).unwrap()));
+
+        let uuid = string(uuid7(&[]).unwrap());
+        assert_eq!(&uuid[14..15], "7");
+        assert!(uuid4(&[Object::Integer(1)]).is_err());
+    }
+
+    #[test]
+    fn test_ulid_encoding() {
+        assert_eq!(encode_ulid(0, [0; 10]), "0".repeat(26));
+        assert_eq!(
+            encode_ulid(0xffff_ffff_ffff, [0xff; 10]),
+            "7ZZZZZZZZZZZZZZZZZZZZZZZZZ"
+        );
+        assert_eq!(&encode_ulid(1, [0; 10])[..10], "0000000001");
+        assert_eq!(string(ulid(&[]).unwrap()).len(), 26);
+    }
+}
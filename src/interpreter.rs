@@ -0,0 +1,1033 @@
+//! The library surface for embedding the interpreter in a Rust program.
+//!
+//! An `Interpreter` owns a global environment with every builtin and the
+//! procedures of the prelude defined, and the settings its programs
+//! change, the message catalog and the float format, which other
+//! interpreters on the thread do not see.
+//! Programs are evaluated with `eval_str`, and their results come back as
+//! `Value`s, which convert to and from plain Rust types. Rust closures can
+//! be exposed to Lisp code with `register_fn`.
+
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, VecDeque};
+use std::convert::Infallible;
+use std::rc::Rc;
+
+use crate::builtins::{builtin_env, global_env};
+use crate::collections::{hash_table_object, HashTable};
+use crate::diff::{self, Edit};
+use crate::env::{self, Env};
+use crate::eval::{self, EvalError};
+use crate::gc;
+use crate::i18n::{self, Catalog};
+use crate::interrupt::{self, InterruptHandle};
+use crate::limits::{self, Limits};
+use crate::object::{self, Object, Pair};
+use crate::parser::{self, ReaderConfig};
+use crate::printer::{self, FloatFormat};
+use crate::session::{self, Snapshot};
+use crate::taint;
+
+pub struct Interpreter {
+    env: Rc<RefCell<Env>>,
+    limits: Limits,
+    interrupt: InterruptHandle,
+    reader: Option<Rc<ReaderConfig>>,
+    /// The messages of `define-messages` and the locale.
+    catalog: Rc<RefCell<Catalog>>,
+    float_format: Rc<Cell<FloatFormat>>,
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        Self::with_limits(Limits::default())
+    }
+
+    /// An interpreter whose evaluations, each call of `eval_str`, `call` or
+    /// `apply`, fail with an error naming the limit once they go over one
+    /// of `limits`.
+    pub fn with_limits(limits: Limits) -> Self {
+        Self::with_env(global_env(), limits)
+    }
+
+    /// An interpreter with `limits` and the builtins only, leaving out the
+    /// procedures of the prelude, for hosts that bring a library of their own.
+    pub fn without_prelude(limits: Limits) -> Self {
+        Self::with_env(builtin_env(), limits)
+    }
+
+    fn with_env(env: Rc<RefCell<Env>>, limits: Limits) -> Self {
+        Self {
+            env,
+            limits,
+            interrupt: InterruptHandle::new(),
+            reader: None,
+            catalog: Rc::default(),
+            float_format: Rc::new(Cell::new(FloatFormat::Shortest)),
+        }
+    }
+
+    /// This interpreter, parsing the programs it evaluates with the keywords
+    /// and reader macros of `reader`.
+    pub fn with_reader(mut self, reader: ReaderConfig) -> Self {
+        self.reader = Some(Rc::new(reader));
+        self
+    }
+
+    /// A handle through which another thread can interrupt the evaluation
+    /// this interpreter is running.
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.interrupt.clone()
+    }
+
+    /// Runs one evaluation, within the limits and watching for interrupts,
+    /// with the message catalog and float format of this interpreter.
+    fn guarded<T>(&self, f: impl FnOnce() -> T) -> T {
+        let run = || {
+            i18n::with_catalog(&self.catalog, || {
+                printer::with_float_format(&self.float_format, || {
+                    interrupt::watch(&self.interrupt, || {
+                        env::within(&self.env, || limits::enforce(self.limits, f))
+                    })
+                })
+            })
+        };
+        match &self.reader {
+            Some(reader) => parser::with_reader(reader, run),
+            None => run(),
+        }
+    }
+
+    /// Evaluates every form in `program`, returning the value of the last.
+    pub fn eval_str(&mut self, program: &str) -> Result<Value, EvalError> {
+        self.guarded(|| eval::eval_str(program, &self.env))
+            .map(Value::from)
+    }
+
+    /// Binds `name` in the global environment.
+    pub fn define(&mut self, name: &str, value: impl Into<Value>) {
+        self.env
+            .borrow_mut()
+            .define(name, Object::from(value.into()));
+    }
+
+    /// Binds `name` to a value from an untrusted source. When taint tracking
+    /// is enabled with `taint::enable`, its strings are marked as tainted.
+    pub fn define_untrusted(&mut self, name: &str, value: impl Into<Value>) {
+        let value = taint::mark(Object::from(value.into()));
+        self.env.borrow_mut().define(name, value);
+    }
+
+    /// The value of the global variable `name`, if it is bound.
+    pub fn get(&self, name: &str) -> Option<Value> {
+        self.env.borrow().get(name).map(Value::from)
+    }
+
+    /// Calls the Lisp procedure bound to the global variable `name`.
+    pub fn call(&mut self, name: &str, args: &[Value]) -> Result<Value, EvalError> {
+        let func = self
+            .env
+            .borrow()
+            .get(name)
+            .ok_or_else(|| EvalError::new(format!("unbound symbol: {}", name)))?;
+
+        let args = args.iter().cloned().map(Object::from).collect();
+
+        self.guarded(|| eval::apply(&func, args)).map(Value::from)
+    }
+
+    /// Calls a procedure that Lisp code handed to Rust, such as a callback
+    /// passed to a registered function.
+    pub fn apply(&mut self, func: &Value, args: &[Value]) -> Result<Value, EvalError> {
+        let args = args.iter().cloned().map(Object::from).collect();
+
+        let func = Object::from(func.clone());
+
+        self.guarded(|| eval::apply(&func, args)).map(Value::from)
+    }
+
+    /// Exposes `func` to Lisp code as the procedure `name`. It receives every
+    /// argument, so it can be variadic; `arg` and `rest` convert them.
+    pub fn register_fn<F>(&mut self, name: &str, func: F)
+    where
+        F: Fn(&[Value]) -> Result<Value, EvalError> + 'static,
+    {
+        self.register(name, None, Box::new(func));
+    }
+
+    /// Like `register_fn`, with the number of arguments checked before
+    /// `func` is called.
+    pub fn register_fn_with_arity<F>(&mut self, name: &str, arity: Arity, func: F)
+    where
+        F: Fn(&[Value]) -> Result<Value, EvalError> + 'static,
+    {
+        self.register(name, Some(arity), Box::new(func));
+    }
+
+    fn register(&mut self, name: &str, arity: Option<Arity>, func: Box<NativeFn>) {
+        let native = Native {
+            name: name.to_string(),
+            arity,
+            func: NativeFunc::Values(func),
+        };
+        self.env
+            .borrow_mut()
+            .define(name, Object::Native(Rc::new(native)));
+    }
+
+    /// The definitions made in this interpreter, as a program that makes
+    /// them again in another one through `restore`.
+    pub fn snapshot(&self) -> Snapshot {
+        session::snapshot(&self.env)
+    }
+
+    /// Makes the definitions of a snapshot's source again.
+    pub fn restore(&mut self, source: &str) -> Result<(), EvalError> {
+        self.guarded(|| session::restore(source, &self.env))
+    }
+
+    /// The global environment, for use with the lower-level `eval` and `vm`
+    /// functions.
+    pub fn env(&self) -> &Rc<RefCell<Env>> {
+        &self.env
+    }
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// How many arguments a registered function accepts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arity {
+    Exact(usize),
+    AtLeast(usize),
+    /// Between the two counts, inclusive.
+    Range(usize, usize),
+}
+
+impl Arity {
+    fn check(&self, name: &str, count: usize) -> Result<(), EvalError> {
+        let (accepted, expected) = match *self {
+            Arity::Exact(n) => (count == n, n.to_string()),
+            Arity::AtLeast(n) => (count >= n, format!("at least {}", n)),
+            Arity::Range(min, max) => ((min..=max).contains(&count), format!("{} to {}", min, max)),
+        };
+
+        match accepted {
+            true => Ok(()),
+            false => Err(EvalError::new(format!(
+                "{} expects {} arguments, got {}",
+                name, expected, count
+            ))),
+        }
+    }
+}
+
+type NativeFn = dyn Fn(&[Value]) -> Result<Value, EvalError>;
+type ObjectFn = dyn Fn(&[Object]) -> Result<Object, EvalError>;
+
+enum NativeFunc {
+    /// Registered by the host.
+    Values(Box<NativeFn>),
+    /// Made by the interpreter itself, working on objects directly.
+    Objects(Box<ObjectFn>),
+}
+
+/// A Rust closure callable from Lisp code. The collector cannot see into
+/// closures, so objects they capture are never part of a collected cycle.
+pub struct Native {
+    name: String,
+    arity: Option<Arity>,
+    func: NativeFunc,
+}
+
+impl Native {
+    pub(crate) fn new<F>(name: &str, arity: Arity, func: F) -> Self
+    where
+        F: Fn(&[Object]) -> Result<Object, EvalError> + 'static,
+    {
+        Self {
+            name: name.to_string(),
+            arity: Some(arity),
+            func: NativeFunc::Objects(Box::new(func)),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub(crate) fn arity(&self) -> Option<Arity> {
+        self.arity
+    }
+
+    pub(crate) fn call(&self, args: Vec<Object>) -> Result<Object, EvalError> {
+        if let Some(arity) = &self.arity {
+            arity.check(&self.name, args.len())?;
+        }
+
+        match &self.func {
+            NativeFunc::Values(func) => {
+                let args = args.into_iter().map(Value::from).collect::<Vec<_>>();
+                func(&args).map(Object::from)
+            }
+            NativeFunc::Objects(func) => func(&args),
+        }
+    }
+}
+
+/// Converts the argument at `index` of a registered function.
+pub fn arg<T>(args: &[Value], index: usize) -> Result<T, EvalError>
+where
+    T: TryFrom<Value, Error = EvalError>,
+{
+    let value = args
+        .get(index)
+        .ok_or_else(|| EvalError::new(format!("missing argument {}", index + 1)))?;
+
+    T::try_from(value.clone())
+        .map_err(|err| EvalError::new(format!("argument {}: {}", index + 1, err.message())))
+}
+
+/// Converts the arguments from `index` on, for variadic functions.
+pub fn rest<T>(args: &[Value], index: usize) -> Result<Vec<T>, EvalError>
+where
+    T: TryFrom<Value, Error = EvalError>,
+{
+    (index..args.len()).map(|index| arg(args, index)).collect()
+}
+
+/// A Lisp value as seen from Rust. Proper lists become `List`s; values with
+/// no Rust counterpart, such as procedures, bignums or dotted pairs, are kept
+/// as `Other` so that they can be passed back unchanged.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Void,
+    Nil,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Char(char),
+    String(String),
+    Symbol(String),
+    List(Vec<Value>),
+    Other(Object),
+}
+
+/// Takes the lists within this one apart one at a time, since dropping
+/// deeply nested lists recursively would overflow the stack.
+impl Drop for Value {
+    fn drop(&mut self) {
+        let Value::List(items) = self else {
+            return;
+        };
+        if !items.iter().any(|item| matches!(item, Value::List(_))) {
+            return;
+        }
+
+        let mut pending = std::mem::take(items);
+        while let Some(mut item) = pending.pop() {
+            if let Value::List(items) = &mut item {
+                pending.append(items);
+            }
+        }
+    }
+}
+
+impl Value {
+    fn type_name(&self) -> &'static str {
+        match self {
+            Value::Void => "void",
+            Value::Nil => "nil",
+            Value::Bool(_) => "boolean",
+            Value::Int(_) => "integer",
+            Value::Float(_) => "float",
+            Value::Char(_) => "char",
+            Value::String(_) => "string",
+            Value::Symbol(_) => "symbol",
+            Value::List(_) => "list",
+            Value::Other(obj) => obj.type_name(),
+        }
+    }
+
+    /// The edits that turn `self` into `other`, as `(diff self other)`
+    /// computes them.
+    pub fn diff(&self, other: &Value) -> Vec<Edit<Value>> {
+        let (old, new) = (Object::from(self.clone()), Object::from(other.clone()));
+
+        diff::diff(&old, &new)
+            .into_iter()
+            .map(|edit| edit.map(Value::from))
+            .collect()
+    }
+
+    /// A copy of the value for `target`, an interpreter other than the one
+    /// that made it, sharing no mutable state with it: hash tables and
+    /// deques are copied, along with everything in them, keeping what they
+    /// share and any cycles. Builtins are kept if `target` has them under the
+    /// same name. Procedures, which close over the environment they were
+    /// made in, records, whose types belong to the interpreter defining them,
+    /// and objects holding procedures or resources of their interpreter,
+    /// such as heaps, fibers or key-value stores, cannot be copied.
+    pub fn deep_copy_into(&self, target: &mut Interpreter) -> Result<Value, EvalError> {
+        let target = target.env.borrow();
+        rebuild(
+            self,
+            |value| match value {
+                Value::List(items) => Ok(Node::List(items.iter().collect())),
+                Value::Other(obj) => {
+                    let copy = copy_object(obj, &target, &mut HashMap::new())?;
+                    Ok(Node::Done(Value::from(copy)))
+                }
+                plain => Ok(Node::Done(plain.clone())),
+            },
+            Value::List,
+        )
+    }
+}
+
+/// An item of a nested list as `rebuild` finds it: done with, or a list
+/// whose items are still to be.
+enum Node<I, T> {
+    Done(T),
+    List(Vec<I>),
+}
+
+/// Turns `root` into a `T`, with `node` taking each item apart and `list`
+/// putting the lists back together. The lists are followed with a stack of
+/// their own rather than by recursion, so that however deep they nest, they
+/// cannot overflow the stack.
+fn rebuild<I, T, E>(
+    root: I,
+    mut node: impl FnMut(I) -> Result<Node<I, T>, E>,
+    list: impl Fn(Vec<T>) -> T,
+) -> Result<T, E> {
+    // The lists entered, each with the items left and those made so far.
+    let mut lists: Vec<(std::vec::IntoIter<I>, Vec<T>)> = Vec::new();
+    let mut next = root;
+    loop {
+        let mut made = loop {
+            match node(next)? {
+                Node::Done(made) => break made,
+                Node::List(items) => {
+                    let mut items = items.into_iter();
+                    match items.next() {
+                        Some(first) => {
+                            let made = Vec::with_capacity(items.len() + 1);
+                            lists.push((items, made));
+                            next = first;
+                        }
+                        None => break list(Vec::new()),
+                    }
+                }
+            }
+        };
+        loop {
+            let Some((items, made_so_far)) = lists.last_mut() else {
+                return Ok(made);
+            };
+            made_so_far.push(made);
+            if let Some(item) = items.next() {
+                next = item;
+                break;
+            }
+            let (_, items) = lists.pop().expect("a list was entered");
+            made = list(items);
+        }
+    }
+}
+
+/// What is left to do to copy an object as `copy_object` does.
+enum Task {
+    /// Copies an object, leaving its copy on top of the copies made.
+    Copy(Object),
+    /// Replaces the top two copies made with a pair of the two, the copy
+    /// of the pair at the address given.
+    Cons(usize),
+    /// Makes the top copy made the car, or the cdr, of a copied pair.
+    SetCar(Rc<Pair>),
+    SetCdr(Rc<Pair>),
+    /// Adds the top two copies made to a copied table as a key and its
+    /// value.
+    Insert(Rc<RefCell<HashTable>>),
+    /// Adds the top copy made to the end of a copied deque.
+    PushBack(Rc<RefCell<VecDeque<Object>>>),
+}
+
+/// Copies `obj` as `Value::deep_copy_into` does, for an interpreter whose
+/// global environment is `target`. `copies` maps the address of each pair
+/// and container copied so far to its copy. What is left to copy is kept
+/// on a stack of tasks rather than by recursion, so that deeply nested
+/// lists cannot overflow the stack.
+fn copy_object(
+    obj: &Object,
+    target: &Env,
+    copies: &mut HashMap<usize, Object>,
+) -> Result<Object, EvalError> {
+    let mut tasks = vec![Task::Copy(obj.clone())];
+    let mut made = Vec::new();
+    let pop = |made: &mut Vec<Object>| made.pop().expect("a copy was made");
+    while let Some(task) = tasks.pop() {
+        let obj = match task {
+            Task::Copy(obj) => obj,
+            Task::Cons(address) => {
+                let cdr = pop(&mut made);
+                let copy = Object::cons(pop(&mut made), cdr);
+                copies.insert(address, copy.clone());
+                made.push(copy);
+                continue;
+            }
+            Task::SetCar(pair) => {
+                pair.set_car(pop(&mut made));
+                continue;
+            }
+            Task::SetCdr(pair) => {
+                pair.set_cdr(pop(&mut made));
+                continue;
+            }
+            Task::Insert(table) => {
+                let value = pop(&mut made);
+                let key = pop(&mut made);
+                table.borrow_mut().insert(key, value);
+                continue;
+            }
+            Task::PushBack(deque) => {
+                let item = pop(&mut made);
+                deque.borrow_mut().push_back(item);
+                continue;
+            }
+        };
+
+        let address = match &obj {
+            Object::Pair(pair) => Rc::as_ptr(pair) as usize,
+            Object::HashTable(table) => Rc::as_ptr(table) as *const u8 as usize,
+            Object::Deque(deque) => Rc::as_ptr(deque) as *const u8 as usize,
+            _ => 0,
+        };
+        if let Some(copy) = copies.get(&address) {
+            made.push(copy.clone());
+            continue;
+        }
+
+        // Tasks are pushed in the reverse of the order they are done in.
+        match &obj {
+            Object::Void
+            | Object::Nil
+            | Object::Bool(_)
+            | Object::Integer(_)
+            | Object::BigInt(_)
+            | Object::Rational(_)
+            | Object::Float(_)
+            | Object::Char(_)
+            | Object::String(_)
+            | Object::Symbol(_) => made.push(obj.clone()),
+            Object::Builtin(builtin) => match target.get(builtin.name) {
+                Some(Object::Builtin(found)) if found.name == builtin.name => {
+                    made.push(obj.clone())
+                }
+                _ => {
+                    return Err(EvalError::new(format!(
+                        "the other interpreter has no builtin {}",
+                        builtin.name
+                    )))
+                }
+            },
+            // Once lists may contain themselves, each pair is made before
+            // its parts are copied, so that those leading back to it get the
+            // copy.
+            Object::Pair(pair) if object::may_cycle() => {
+                let copy = Object::cons(Object::Nil, Object::Nil);
+                copies.insert(address, copy.clone());
+                let Object::Pair(copied) = &copy else {
+                    unreachable!("cons makes pairs")
+                };
+                tasks.push(Task::SetCdr(copied.clone()));
+                tasks.push(Task::Copy(pair.cdr()));
+                tasks.push(Task::SetCar(copied.clone()));
+                tasks.push(Task::Copy(pair.car()));
+                made.push(copy);
+            }
+            Object::Pair(pair) => {
+                tasks.push(Task::Cons(address));
+                tasks.push(Task::Copy(pair.cdr()));
+                tasks.push(Task::Copy(pair.car()));
+            }
+            // The copy is known before its entries are copied, so that those
+            // that contain the table get the copy.
+            Object::HashTable(table) => {
+                let copy = hash_table_object(HashTable::default());
+                copies.insert(address, copy.clone());
+                let Object::HashTable(copied) = &copy else {
+                    unreachable!("hash_table_object makes hash tables")
+                };
+                let entries: Vec<_> = table
+                    .borrow()
+                    .iter()
+                    .map(|(key, value)| (key.clone(), value.clone()))
+                    .collect();
+                for (key, value) in entries.into_iter().rev() {
+                    tasks.push(Task::Insert(copied.clone()));
+                    tasks.push(Task::Copy(value));
+                    tasks.push(Task::Copy(key));
+                }
+                made.push(copy);
+            }
+            Object::Deque(deque) => {
+                let copied = Rc::new(RefCell::new(VecDeque::new()));
+                gc::track(&copied);
+                copies.insert(address, Object::Deque(copied.clone()));
+                for item in deque.borrow().iter().rev() {
+                    tasks.push(Task::PushBack(copied.clone()));
+                    tasks.push(Task::Copy(item.clone()));
+                }
+                made.push(Object::Deque(copied));
+            }
+            other => {
+                return Err(EvalError::new(format!(
+                    "{} cannot be copied to another interpreter",
+                    object::with_article(other.type_name())
+                )))
+            }
+        }
+    }
+
+    Ok(made.pop().expect("the object was copied"))
+}
+
+/// Lists that contain themselves are kept as `Other`, as are the lists in
+/// them, which lead back to them.
+impl From<Object> for Value {
+    fn from(obj: Object) -> Self {
+        if obj.contains_itself() {
+            return Value::Other(obj);
+        }
+        let converted: Result<Value, Infallible> = rebuild(
+            obj,
+            |obj| {
+                Ok(Node::Done(match obj {
+                    Object::Void => Value::Void,
+                    Object::Nil => Value::Nil,
+                    Object::Bool(b) => Value::Bool(b),
+                    Object::Integer(n) => Value::Int(n),
+                    Object::Float(n) => Value::Float(n),
+                    Object::Char(c) => Value::Char(c),
+                    Object::String(s) => Value::String(s.to_string()),
+                    Object::Symbol(name) => Value::Symbol(name.to_string()),
+                    Object::Pair(_) => match obj.to_vec() {
+                        Some(items) => return Ok(Node::List(items)),
+                        None => Value::Other(obj),
+                    },
+                    other => Value::Other(other),
+                }))
+            },
+            Value::List,
+        );
+        let Ok(value) = converted;
+        value
+    }
+}
+
+impl From<Value> for Object {
+    fn from(value: Value) -> Self {
+        let converted: Result<Object, Infallible> = rebuild(
+            value,
+            |mut value| {
+                Ok(Node::Done(match &mut value {
+                    Value::Void => Object::Void,
+                    Value::Nil => Object::Nil,
+                    Value::Bool(b) => Object::Bool(*b),
+                    Value::Int(n) => Object::Integer(*n),
+                    Value::Float(n) => Object::Float(*n),
+                    Value::Char(c) => Object::Char(*c),
+                    Value::String(s) => Object::string(std::mem::take(s)),
+                    Value::Symbol(name) => Object::symbol(name),
+                    Value::List(items) => return Ok(Node::List(std::mem::take(items))),
+                    Value::Other(obj) => std::mem::replace(obj, Object::Nil),
+                }))
+            },
+            Object::list,
+        );
+        let Ok(obj) = converted;
+        obj
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Bool(b)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(n: i64) -> Self {
+        Value::Int(n)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(n: f64) -> Self {
+        Value::Float(n)
+    }
+}
+
+impl From<char> for Value {
+    fn from(c: char) -> Self {
+        Value::Char(c)
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::String(s)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::String(s.to_string())
+    }
+}
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+    fn from(items: Vec<T>) -> Self {
+        Value::List(items.into_iter().map(Into::into).collect())
+    }
+}
+
+fn mismatch(expected: &str, value: &Value) -> EvalError {
+    EvalError::new(format!("expected {}, got {}", expected, value.type_name()))
+}
+
+impl TryFrom<Value> for bool {
+    type Error = EvalError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Bool(b) => Ok(b),
+            other => Err(mismatch("a boolean", &other)),
+        }
+    }
+}
+
+impl TryFrom<Value> for i64 {
+    type Error = EvalError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Int(n) => Ok(n),
+            other => Err(mismatch("an integer", &other)),
+        }
+    }
+}
+
+/// Integers convert to floats too, as they do in arithmetic.
+impl TryFrom<Value> for f64 {
+    type Error = EvalError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Float(n) => Ok(n),
+            Value::Int(n) => Ok(n as f64),
+            other => Err(mismatch("a number", &other)),
+        }
+    }
+}
+
+impl TryFrom<Value> for char {
+    type Error = EvalError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Char(c) => Ok(c),
+            other => Err(mismatch("a char", &other)),
+        }
+    }
+}
+
+impl TryFrom<Value> for String {
+    type Error = EvalError;
+
+    fn try_from(mut value: Value) -> Result<Self, Self::Error> {
+        match &mut value {
+            Value::String(s) => Ok(std::mem::take(s)),
+            other => Err(mismatch("a string", other)),
+        }
+    }
+}
+
+/// The empty list converts to an empty `Vec`.
+impl<T: TryFrom<Value, Error = EvalError>> TryFrom<Value> for Vec<T> {
+    type Error = EvalError;
+
+    fn try_from(mut value: Value) -> Result<Self, Self::Error> {
+        match &mut value {
+            Value::List(items) => std::mem::take(items).into_iter().map(T::try_from).collect(),
+            Value::Nil => Ok(Vec::new()),
+            other => Err(mismatch("a list", other)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_eval_str() {
+        let mut interp = Interpreter::new();
+        assert_eq!(interp.eval_str("(+ 1 2)").unwrap(), Value::Int(3));
+
+        interp.define("xs", vec![1i64, 2, 3]);
+        let squares = interp.eval_str("(map (lambda (x) (* x x)) xs)").unwrap();
+        assert_eq!(Vec::<i64>::try_from(squares).unwrap(), [1, 4, 9]);
+
+        assert!(interp.eval_str("(car '())").is_err());
+    }
+
+    #[test]
+    fn test_conversions() {
+        let mut interp = Interpreter::new();
+        let value = interp
+            .eval_str("(list \"a\" 'b 1.5 #\\c '(1 . 2) car)")
+            .unwrap();
+        let Value::List(items) = &value else {
+            panic!("expected a list, got {:?}", value);
+        };
+        assert_eq!(items[0], Value::String(String::from("a")));
+        assert_eq!(items[1], Value::Symbol(String::from("b")));
+        assert_eq!(f64::try_from(items[2].clone()).unwrap(), 1.5);
+        assert_eq!(char::try_from(items[3].clone()).unwrap(), 'c');
+        assert!(matches!(items[4], Value::Other(_)));
+
+        // Opaque values can be handed back to Lisp code.
+        interp.define("f", items[5].clone());
+        assert_eq!(interp.eval_str("(f '(7 8))").unwrap(), Value::Int(7));
+
+        let err = i64::try_from(Value::from("seven")).unwrap_err();
+        assert!(err.to_string().contains("expected an integer, got string"));
+        assert!(Vec::<String>::try_from(Value::Nil).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_call() {
+        let mut interp = Interpreter::new();
+        interp
+            .eval_str(
+                "(define (handler event count) (list event (* count 2)))
+                 (define (make-adder n) (lambda (x) (+ x n)))",
+            )
+            .unwrap();
+
+        assert_eq!(
+            interp
+                .call("handler", &[Value::from("click"), Value::Int(21)])
+                .unwrap(),
+            Value::from(vec![Value::from("click"), Value::Int(42)])
+        );
+
+        let add = interp.call("make-adder", &[Value::Int(10)]).unwrap();
+        assert_eq!(
+            interp.apply(&add, &[Value::Int(5)]).unwrap(),
+            Value::Int(15)
+        );
+
+        let err = interp.call("missing", &[]).unwrap_err();
+        assert!(err.to_string().contains("unbound symbol: missing"));
+        assert!(interp.call("handler", &[Value::Int(1)]).is_err());
+        interp.define("n", 1i64);
+        assert!(interp.call("n", &[]).is_err());
+    }
+
+    #[test]
+    fn test_register_fn() {
+        let mut interp = Interpreter::new();
+        interp.register_fn("join", |args| {
+            let separator: String = arg(args, 0)?;
+            let words: Vec<String> = rest(args, 1)?;
+            Ok(Value::from(words.join(&separator)))
+        });
+        interp.register_fn_with_arity("scale", Arity::Range(1, 2), |args| {
+            let factor = if args.len() == 2 { arg(args, 1)? } else { 2.0 };
+            Ok(Value::Float(arg::<f64>(args, 0)? * factor))
+        });
+
+        assert_eq!(
+            interp.eval_str("(join \", \" \"a\" \"b\" \"c\")").unwrap(),
+            Value::from("a, b, c")
+        );
+        assert_eq!(interp.eval_str("(join \"-\")").unwrap(), Value::from(""));
+        assert_eq!(
+            interp.eval_str("(map scale '(1 2.5))").unwrap(),
+            Value::from(vec![2.0, 5.0])
+        );
+
+        let err = interp.eval_str("(join \"-\" \"a\" 1)").unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("argument 3: expected a string, got integer"));
+        let err = interp.eval_str("(scale 1 2 3)").unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("scale expects 1 to 2 arguments, got 3"));
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = Value::List(vec![Value::Int(1), Value::from("a")]);
+        let new = Value::List(vec![Value::Int(1), Value::from("b"), Value::Bool(true)]);
+
+        assert_eq!(
+            old.diff(&new),
+            [
+                Edit::Changed {
+                    path: vec![1],
+                    old: Value::from("a"),
+                    new: Value::from("b"),
+                },
+                Edit::Inserted {
+                    path: vec![2],
+                    value: Value::Bool(true),
+                },
+            ]
+        );
+        assert!(new.diff(&new).is_empty());
+    }
+
+    #[test]
+    fn test_with_reader() {
+        let reader = ReaderConfig::new().dispatch('v', |text| {
+            Ok(Object::list([
+                Object::symbol("quote"),
+                Object::Integer(text.len() as i64),
+            ]))
+        });
+        let mut interp = Interpreter::new().with_reader(reader);
+        assert_eq!(interp.eval_str("(+ #vabc 1)").unwrap(), Value::Int(4));
+        assert!(Interpreter::new().eval_str("(+ #vabc 1)").is_err());
+    }
+
+    #[test]
+    fn test_interpreters_keep_their_settings() {
+        let mut a = Interpreter::new();
+        let mut b = Interpreter::new();
+        a.eval_str(
+            "(set-float-format! 'fixed 2)
+             (define-messages 'en 'secret \"a's\")
+             (set-locale! 'en)",
+        )
+        .unwrap();
+
+        let format =
+            |interp: &mut Interpreter| Object::from(interp.eval_str("(float-format)").unwrap());
+        assert_eq!(format(&mut a).to_string(), "(fixed 2)");
+        assert_eq!(format(&mut b).to_string(), "(shortest)");
+        assert!(b.eval_str("(msg 'secret)").is_err());
+        assert_eq!(a.eval_str("(msg 'secret)").unwrap(), Value::from("a's"));
+        assert_eq!(printer::float_format(), FloatFormat::Shortest);
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let mut session = Interpreter::new();
+        session.register_fn("host-fn", |_| Ok(Value::Void));
+        session
+            .eval_str("(define base 40) (define (answer) (+ base 2))")
+            .unwrap();
+        let snapshot = session.snapshot();
+        assert_eq!(
+            snapshot.skipped(),
+            [(
+                String::from("host-fn"),
+                String::from("a procedure the host registered")
+            )]
+        );
+
+        let mut resumed = Interpreter::new();
+        resumed.restore(&snapshot.source()).unwrap();
+        assert_eq!(resumed.call("answer", &[]).unwrap(), Value::Int(42));
+    }
+
+    #[test]
+    fn test_deep_copy_into() {
+        let mut source = Interpreter::new();
+        let mut target = Interpreter::new();
+
+        let table = source
+            .eval_str(
+                "(define t (make-hash-table))
+                 (hash-table-set! t 'self t)
+                 (hash-table-set! t 'items (list 1 '(2 . 3) \"s\" car))
+                 t",
+            )
+            .unwrap();
+        let copy = table.deep_copy_into(&mut target).unwrap();
+        target.define("t", copy);
+        source.eval_str("(hash-table-set! t 'changed #t)").unwrap();
+
+        let copied = target
+            .eval_str("(list (eq? (hash-table-ref t 'self) t) (hash-table-ref t 'items) (hash-table-count t))")
+            .unwrap();
+        assert_eq!(
+            Object::from(copied).to_string(),
+            "(#t (1 (2 . 3) \"s\" #<builtin car>) 2)"
+        );
+
+        let lambda = source.eval_str("(lambda (x) x)").unwrap();
+        assert_eq!(
+            lambda.deep_copy_into(&mut target).unwrap_err().message(),
+            "a procedure cannot be copied to another interpreter"
+        );
+        let mut bare = Interpreter::with_env(Rc::new(RefCell::new(Env::new())), Limits::default());
+        let builtin = source.eval_str("(list car)").unwrap();
+        assert_eq!(
+            builtin.deep_copy_into(&mut bare).unwrap_err().message(),
+            "the other interpreter has no builtin car"
+        );
+
+        let circular = source
+            .eval_str(
+                "(define l (list 1 2))
+                 (set-cdr! (cdr l) l)
+                 (define m (list 1 (list 2)))
+                 (set-car! (cadr m) m)
+                 (list l m)",
+            )
+            .unwrap();
+        assert!(matches!(circular, Value::Other(_)));
+        let copy = circular.deep_copy_into(&mut target).unwrap();
+        target.define("c", copy);
+        assert_eq!(
+            Object::from(
+                target
+                    .eval_str(
+                        "(list (eq? (car c) (cddr (car c))) (eq? (cadr c) (car (cadr (cadr c)))))"
+                    )
+                    .unwrap()
+            )
+            .to_string(),
+            "(#t #t)"
+        );
+    }
+
+    #[test]
+    fn test_deeply_nested_values() {
+        let mut source = Interpreter::new();
+        let nested = source
+            .eval_str(
+                "(define (nest n acc) (if (= n 0) acc (nest (- n 1) (list acc))))
+                 (nest 200000 '())",
+            )
+            .unwrap();
+        let copy = nested.deep_copy_into(&mut Interpreter::new()).unwrap();
+        let mut depth = 0;
+        let mut value = Object::from(copy);
+        while let Object::Pair(pair) = value {
+            depth += 1;
+            value = pair.car();
+        }
+        assert_eq!((depth, value), (200000, Object::Nil));
+    }
+}
@@ -0,0 +1,97 @@
+//! Interrupting an evaluation from another thread, for hosts that cancel
+//! work on a timeout or when the user presses Ctrl-C.
+//!
+//! An `InterruptHandle` shares a flag with its `Interpreter`, which watches
+//! it while it evaluates: the evaluator checks it at each step and the VM at
+//! each call, and the evaluation then fails with an error for which
+//! `is_interrupted` is true. A builtin running when the flag is set, such as
+//! a `sleep`, finishes first. An interrupt arriving between evaluations is
+//! dropped, so the next one runs in full.
+
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::eval::EvalError;
+
+/// Interrupts the evaluation its interpreter is running. It can be cloned
+/// and sent to other threads.
+#[derive(Debug, Clone, Default)]
+pub struct InterruptHandle(Arc<AtomicBool>);
+
+impl InterruptHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn interrupt(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+thread_local! {
+    static WATCHED: RefCell<Option<InterruptHandle>> = const { RefCell::new(None) };
+}
+
+/// Runs `f` watching `handle`, unless an evaluation it is nested in is
+/// watching one already.
+pub(crate) fn watch<T>(handle: &InterruptHandle, f: impl FnOnce() -> T) -> T {
+    let nested = WATCHED.with(|watched| {
+        let mut watched = watched.borrow_mut();
+        if watched.is_some() {
+            return true;
+        }
+        handle.0.store(false, Ordering::Relaxed);
+        *watched = Some(handle.clone());
+        false
+    });
+    if nested {
+        return f();
+    }
+
+    let result = f();
+    WATCHED.with(|watched| *watched.borrow_mut() = None);
+
+    result
+}
+
+/// Fails if the watched handle was interrupted, clearing it.
+pub(crate) fn poll() -> Result<(), EvalError> {
+    WATCHED.with(|watched| match &*watched.borrow() {
+        Some(handle) if handle.0.swap(false, Ordering::Relaxed) => Err(EvalError::interrupted()),
+        _ => Ok(()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interpreter::Interpreter;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_interrupt() {
+        let mut interpreter = Interpreter::new();
+        interpreter
+            .eval_str("(define (forever n) (forever (+ n 1)))")
+            .unwrap();
+
+        let handle = interpreter.interrupt_handle();
+        let interrupter = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            handle.interrupt();
+        });
+        let err = interpreter.eval_str("(forever 0)").unwrap_err();
+        interrupter.join().unwrap();
+        assert!(err.is_interrupted());
+        assert_eq!(err.message(), "evaluation interrupted");
+
+        // Interrupts between evaluations are dropped.
+        interpreter.interrupt_handle().interrupt();
+        assert!(interpreter.eval_str("(+ 1 2)").is_ok());
+        assert!(!interpreter
+            .eval_str("(car 1)")
+            .unwrap_err()
+            .is_interrupted());
+    }
+}
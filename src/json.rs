@@ -0,0 +1,489 @@
+//! Reading and writing JSON.
+//!
+//! `(json-parse text)` turns objects into hash tables with string keys,
+//! arrays into lists, and `null` into the symbol `null`. Numbers without a
+//! fraction or exponent become integers. `(json-stringify obj)` does the
+//! reverse, also accepting symbols and chars as strings; object keys are
+//! written in sorted order so that the output is deterministic.
+//! `json-parse` takes the options of `read` limiting what it reads, such
+//! as `#:max-depth n`. It makes no symbol but `null`, so no input it reads
+//! grows the symbol table.
+
+use std::fmt::Write;
+
+use crate::bigint::BigInt;
+use crate::collections::{hash_table_object, HashTable};
+use crate::eval::EvalError;
+use crate::object::Object;
+use crate::reader::Limits;
+use crate::taint;
+
+/// How deeply arrays and objects may nest, so that neither reading nor
+/// writing can overflow the stack.
+const MAX_DEPTH: usize = 512;
+
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+    limits: Limits,
+    /// The values read so far.
+    nodes: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: &str) -> EvalError {
+        EvalError::new(self.at(message))
+    }
+
+    /// The error for going over the limits.
+    fn exceeded(&self, message: &str) -> EvalError {
+        EvalError::read_limit(self.at(message))
+    }
+
+    fn at(&self, message: &str) -> String {
+        format!("json-parse: {} at offset {}", message, self.pos)
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.peek_at(self.pos)
+    }
+
+    fn peek_at(&self, pos: usize) -> Option<u8> {
+        self.text.as_bytes().get(pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, literal: &str) -> Result<(), EvalError> {
+        match self.text[self.pos..].starts_with(literal) {
+            true => {
+                self.pos += literal.len();
+                Ok(())
+            }
+            false => Err(self.error("invalid literal")),
+        }
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Object, EvalError> {
+        if depth > MAX_DEPTH {
+            return Err(self.error("nesting too deep"));
+        }
+
+        self.skip_whitespace();
+        self.nodes += 1;
+        let start = self.pos;
+        if matches!(self.peek(), Some(b'{' | b'[')) {
+            self.limits
+                .check_list(depth + 1, self.nodes)
+                .map_err(|e| self.exceeded(&e))?;
+        }
+
+        let value = match self.peek() {
+            Some(b'{') => return self.object(depth),
+            Some(b'[') => return self.array(depth),
+            Some(b'"') => Ok(Object::string(self.string()?)),
+            Some(b't') => self.expect("true").map(|_| Object::Bool(true)),
+            Some(b'f') => self.expect("false").map(|_| Object::Bool(false)),
+            Some(b'n') => self.expect("null").map(|_| Object::symbol("null")),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            Some(_) => Err(self.error("unexpected character")),
+            None => Err(self.error("unexpected end of input")),
+        }?;
+        self.limits
+            .check_atom(self.pos - start, self.nodes)
+            .map_err(|e| self.exceeded(&e))?;
+
+        Ok(value)
+    }
+
+    fn object(&mut self, depth: usize) -> Result<Object, EvalError> {
+        self.pos += 1;
+        let mut table = HashTable::default();
+
+        self.skip_whitespace();
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            return Ok(hash_table_object(table));
+        }
+
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some(b'"') {
+                return Err(self.error("expected a string key"));
+            }
+            let start = self.pos;
+            let key = self.string()?;
+            self.limits
+                .check_atom(self.pos - start, self.nodes)
+                .map_err(|e| self.exceeded(&e))?;
+
+            self.skip_whitespace();
+            if self.peek() != Some(b':') {
+                return Err(self.error("expected ':'"));
+            }
+            self.pos += 1;
+            table.insert(Object::string(key), self.value(depth + 1)?);
+
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    return Ok(hash_table_object(table));
+                }
+                _ => return Err(self.error("expected ',' or '}'")),
+            }
+        }
+    }
+
+    fn array(&mut self, depth: usize) -> Result<Object, EvalError> {
+        self.pos += 1;
+        let mut items = Vec::new();
+
+        self.skip_whitespace();
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            return Ok(Object::Nil);
+        }
+
+        loop {
+            items.push(self.value(depth + 1)?);
+
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {
+                    self.pos += 1;
+                    return Ok(Object::list(items));
+                }
+                _ => return Err(self.error("expected ',' or ']'")),
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, EvalError> {
+        self.pos += 1;
+        let mut s = String::new();
+
+        loop {
+            let rest = &self.text[self.pos..];
+            let Some(c) = rest.chars().next() else {
+                return Err(self.error("unterminated string"));
+            };
+            self.pos += c.len_utf8();
+
+            match c {
+                '"' => return Ok(s),
+                '\\' => s.push(self.escape()?),
+                c if (c as u32) < 0x20 => {
+                    return Err(self.error("control character in string"));
+                }
+                c => s.push(c),
+            }
+        }
+    }
+
+    fn escape(&mut self) -> Result<char, EvalError> {
+        let c = self
+            .peek()
+            .ok_or_else(|| self.error("unterminated string"))?;
+        self.pos += 1;
+
+        Ok(match c {
+            b'"' => '"',
+            b'\\' => '\\',
+            b'/' => '/',
+            b'b' => '\u{8}',
+            b'f' => '\u{c}',
+            b'n' => '\n',
+            b'r' => '\r',
+            b't' => '\t',
+            b'u' => {
+                let high = self.hex()?;
+                let code = match high {
+                    0xD800..=0xDBFF => {
+                        self.expect("\\u")
+                            .map_err(|_| self.error("unpaired surrogate"))?;
+                        let low = self.hex()?;
+                        if !(0xDC00..=0xDFFF).contains(&low) {
+                            return Err(self.error("unpaired surrogate"));
+                        }
+                        0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+                    }
+                    code => code,
+                };
+                char::from_u32(code).ok_or_else(|| self.error("unpaired surrogate"))?
+            }
+            _ => return Err(self.error("invalid escape")),
+        })
+    }
+
+    fn hex(&mut self) -> Result<u32, EvalError> {
+        let digits = self
+            .text
+            .get(self.pos..self.pos + 4)
+            .filter(|digits| digits.bytes().all(|b| b.is_ascii_hexdigit()))
+            .ok_or_else(|| self.error("invalid \\u escape"))?;
+        self.pos += 4;
+
+        Ok(u32::from_str_radix(digits, 16).unwrap())
+    }
+
+    fn number(&mut self) -> Result<Object, EvalError> {
+        let start = self.pos;
+        let digits = |parser: &mut Self| {
+            let from = parser.pos;
+            while matches!(parser.peek(), Some(b'0'..=b'9')) {
+                parser.pos += 1;
+            }
+            parser.pos > from
+        };
+
+        if self.peek() == Some(b'-') {
+            self.pos += 1;
+        }
+        // A leading zero can only be the whole integer part.
+        let integer_start = self.pos;
+        if !digits(self)
+            || (self.peek_at(integer_start) == Some(b'0') && self.pos - integer_start > 1)
+        {
+            return Err(self.error("invalid number"));
+        }
+
+        let mut integer = true;
+        if self.peek() == Some(b'.') {
+            self.pos += 1;
+            integer = false;
+            if !digits(self) {
+                return Err(self.error("invalid number"));
+            }
+        }
+        if matches!(self.peek(), Some(b'e' | b'E')) {
+            self.pos += 1;
+            integer = false;
+            if matches!(self.peek(), Some(b'+' | b'-')) {
+                self.pos += 1;
+            }
+            if !digits(self) {
+                return Err(self.error("invalid number"));
+            }
+        }
+
+        let literal = &self.text[start..self.pos];
+        match integer {
+            true => BigInt::parse(literal)
+                .map(Object::from_bigint)
+                .ok_or_else(|| self.error("invalid number")),
+            false => literal
+                .parse()
+                .map(Object::Float)
+                .map_err(|_| self.error("invalid number")),
+        }
+    }
+}
+
+pub fn parse(text: &str) -> Result<Object, EvalError> {
+    parse_within(text, Limits::default())
+}
+
+/// Parses `text`, failing as soon as it goes over `limits`.
+pub fn parse_within(text: &str, limits: Limits) -> Result<Object, EvalError> {
+    let mut parser = Parser {
+        text,
+        pos: 0,
+        limits,
+        nodes: 0,
+    };
+    let value = parser.value(0)?;
+
+    parser.skip_whitespace();
+    match parser.peek() {
+        Some(_) => Err(parser.error("trailing characters")),
+        None => Ok(value),
+    }
+}
+
+fn write_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+fn unrepresentable(obj: &Object) -> EvalError {
+    EvalError::new(format!("json-stringify cannot represent {}", obj))
+}
+
+fn write_value(out: &mut String, obj: &Object, depth: usize) -> Result<(), EvalError> {
+    if depth > MAX_DEPTH {
+        return Err(EvalError::new("json-stringify: nesting too deep"));
+    }
+
+    match obj {
+        Object::Symbol(name) if *name == "null" => out.push_str("null"),
+        Object::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+        Object::Integer(n) => {
+            let _ = write!(out, "{}", n);
+        }
+        Object::BigInt(_) => {
+            let _ = write!(out, "{}", obj);
+        }
+        Object::Rational(n) => write_value(out, &Object::Float(n.to_f64()), depth)?,
+        Object::Float(n) if n.is_finite() => {
+            let _ = write!(out, "{:?}", n);
+        }
+        Object::String(s) => write_string(out, s),
+        Object::Symbol(name) => write_string(out, name),
+        Object::Char(c) => write_string(out, c.encode_utf8(&mut [0; 4])),
+        Object::Nil => out.push_str("[]"),
+        Object::Pair(_) => {
+            let items = obj.to_vec().ok_or_else(|| unrepresentable(obj))?;
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_value(out, item, depth + 1)?;
+            }
+            out.push(']');
+        }
+        Object::HashTable(table) => {
+            let table = table.borrow();
+            let mut entries = table
+                .iter()
+                .map(|(key, value)| match key {
+                    Object::String(key) => Ok((key.as_str(), value)),
+                    Object::Symbol(key) => Ok((key.as_str(), value)),
+                    _ => Err(EvalError::new(format!(
+                        "json-stringify: object keys must be strings, got {}",
+                        key
+                    ))),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            entries.sort_by_key(|(key, _)| *key);
+
+            out.push('{');
+            for (i, (key, value)) in entries.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_string(out, key);
+                out.push(':');
+                write_value(out, value, depth + 1)?;
+            }
+            out.push('}');
+        }
+        other => return Err(unrepresentable(other)),
+    }
+
+    Ok(())
+}
+
+pub fn stringify(obj: &Object) -> Result<String, EvalError> {
+    let mut out = String::new();
+    write_value(&mut out, obj, 0)?;
+
+    Ok(out)
+}
+
+pub fn json_parse(args: &[Object]) -> Result<Object, EvalError> {
+    let [Object::String(text), options @ ..] = args else {
+        return Err(EvalError::new("json-parse expects a string"));
+    };
+    let value = parse_within(text, Limits::from_options("json-parse", options)?)?;
+
+    Ok(match text.is_tainted() {
+        true => taint::taint_all(value),
+        false => value,
+    })
+}
+
+pub fn json_stringify(args: &[Object]) -> Result<Object, EvalError> {
+    match args {
+        [obj] => stringify(obj).map(|s| taint::string(s, taint::contains(obj))),
+        _ => Err(EvalError::new("json-stringify expects one argument")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builtins::global_env;
+    use crate::eval::eval_str;
+
+    #[test]
+    fn test_parse() {
+        let env = global_env();
+        let text = r#"{"name": "lisp", "tags": [1, -2.5e1, true, null, []], "nested": {}}"#;
+        env.borrow_mut().define("text", Object::string(text));
+        let result = eval_str(
+            r#"(define config (json-parse text))
+               (list (hash-table-ref config "name")
+                     (hash-table-ref config "tags")
+                     (hash-table-count (hash-table-ref config "nested")))"#,
+            &env,
+        )
+        .unwrap();
+        assert_eq!(result.to_string(), "(\"lisp\" (1 -25.0 #t null ()) 0)");
+
+        assert_eq!(
+            parse(r#""\u00e9\ud83d\ude00\n""#).unwrap(),
+            Object::string("é😀\n")
+        );
+        assert_eq!(
+            parse("123456789012345678901234567890").unwrap().to_string(),
+            "123456789012345678901234567890"
+        );
+        for invalid in [
+            "",
+            "[1,]",
+            "{\"a\" 1}",
+            "01",
+            "1.",
+            "\"\\ud800\"",
+            "[1] 2",
+            "tru",
+        ] {
+            assert!(parse(invalid).is_err(), "{:?} should not parse", invalid);
+        }
+        assert!(parse(&"[".repeat(100_000)).is_err());
+    }
+
+    #[test]
+    fn test_stringify() {
+        let env = global_env();
+        env.borrow_mut().define("quoted", Object::string("x\"y"));
+        let result = eval_str(
+            r#"(define t (make-hash-table))
+               (hash-table-set! t "b" (list 1 2.5 quoted #t 'null))
+               (hash-table-set! t 'a '())
+               (json-stringify t)"#,
+            &env,
+        )
+        .unwrap();
+        assert_eq!(
+            result,
+            Object::string(r#"{"a":[],"b":[1,2.5,"x\"y",true,null]}"#)
+        );
+
+        let text = r#"{"items":[{"id":1},{"id":2}],"ok":false}"#;
+        assert_eq!(stringify(&parse(text).unwrap()).unwrap(), text);
+        assert!(eval_str("(json-stringify car)", &env).is_err());
+        assert!(eval_str("(json-stringify '(1 . 2))", &env).is_err());
+    }
+}
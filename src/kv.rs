@@ -0,0 +1,389 @@
+//! Persistent key-value stores, so scripts can keep state between runs
+//! without a database.
+//!
+//! A store is a log file: after `MAGIC` and a format version, every
+//! `kv-set!` appends a record with the key and the value, and every
+//! `kv-delete!` one with the key. Opening a store replays the log into a
+//! hash table, which serves the reads. Keys and values are plain data, as
+//! in compiled programs, and are written the same way. Each record reaches
+//! the disk before the call writing it returns, and a record cut short by
+//! a crash is dropped the next time the store is opened. `kv-compact!`
+//! rewrites the log with only the live entries.
+//!
+//! The store keeps copies of what it is given and gives out copies of what
+//! it holds, so changing a list with `set-car!` after storing or reading
+//! it leaves the store, and the log, as they were.
+
+use std::cell::RefCell;
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::bytecode::{decode_value, encode_value, is_data};
+use crate::collections::HashTable;
+use crate::eval::EvalError;
+use crate::object::Object;
+use crate::taint;
+
+const MAGIC: &[u8] = b"\0lkv";
+const VERSION: u8 = 1;
+
+const SET: u8 = 1;
+const DELETE: u8 = 2;
+
+pub struct KvStore {
+    path: PathBuf,
+    log: RefCell<File>,
+    entries: RefCell<HashTable>,
+}
+
+fn error(path: &Path, e: impl std::fmt::Display) -> EvalError {
+    EvalError::new(format!("kv store {}: {}", path.display(), e))
+}
+
+/// The entries a log holds, and how long its valid part is.
+fn replay(path: &Path, bytes: &[u8]) -> Result<(HashTable, usize), EvalError> {
+    let mut entries = HashTable::default();
+    if bytes.len() <= MAGIC.len() && MAGIC.starts_with(bytes) {
+        return Ok((entries, 0));
+    }
+    if !bytes.starts_with(MAGIC) {
+        return Err(error(path, "not a key-value store"));
+    }
+    if bytes[MAGIC.len()] != VERSION {
+        return Err(error(
+            path,
+            format!(
+                "format version {}, expected {}",
+                bytes[MAGIC.len()],
+                VERSION
+            ),
+        ));
+    }
+
+    let mut pos = MAGIC.len() + 1;
+    while let Some(header) = bytes.get(pos..pos + 5) {
+        let len = u32::from_le_bytes([header[1], header[2], header[3], header[4]]) as usize;
+        let record = match bytes.get(pos + 5..pos + 5 + len) {
+            Some(record) => record,
+            None => break,
+        };
+
+        let (key, used) = decode_value(record).map_err(|e| error(path, e))?;
+        match header[0] {
+            SET => {
+                let (value, _) = decode_value(&record[used..]).map_err(|e| error(path, e))?;
+                entries.insert(key, value);
+            }
+            DELETE => {
+                entries.remove(&key);
+            }
+            tag => return Err(error(path, format!("invalid record tag {}", tag))),
+        }
+        pos += 5 + len;
+    }
+
+    Ok((entries, pos))
+}
+
+/// A copy of the plain data `obj` sharing no pairs with it, as it is read
+/// back from the log.
+fn copied(obj: &Object) -> Object {
+    let mut bytes = Vec::new();
+    encode_value(&mut bytes, obj);
+    // Only ever given what `encode_value` wrote.
+    decode_value(&bytes).map_or(Object::Void, |(obj, _)| obj)
+}
+
+fn record(tag: u8, key: &Object, value: Option<&Object>) -> Vec<u8> {
+    let mut payload = Vec::new();
+    encode_value(&mut payload, key);
+    if let Some(value) = value {
+        encode_value(&mut payload, value);
+    }
+
+    let mut record = vec![tag];
+    record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    record.extend_from_slice(&payload);
+    record
+}
+
+impl KvStore {
+    /// Opens the store at `path`, creating it if there is none.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, EvalError> {
+        let path = path.into();
+        let bytes = match fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(error(&path, e)),
+        };
+        let (entries, valid) = replay(&path, &bytes)?;
+
+        let log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|e| error(&path, e))?;
+        if valid < bytes.len() || valid == 0 {
+            log.set_len(valid as u64).map_err(|e| error(&path, e))?;
+        }
+        let store = Self {
+            path,
+            log: RefCell::new(log),
+            entries: RefCell::new(entries),
+        };
+        if valid == 0 {
+            store.append(&[MAGIC, &[VERSION]].concat())?;
+        }
+
+        Ok(store)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn append(&self, bytes: &[u8]) -> Result<(), EvalError> {
+        let mut log = self.log.borrow_mut();
+        log.write_all(bytes)
+            .and_then(|()| log.sync_data())
+            .map_err(|e| error(&self.path, e))
+    }
+
+    pub fn get(&self, key: &Object) -> Option<Object> {
+        self.entries.borrow().get(key).map(copied)
+    }
+
+    pub fn set(&self, key: Object, value: Object) -> Result<(), EvalError> {
+        for obj in [&key, &value] {
+            if !is_data(obj) {
+                return Err(EvalError::new(format!(
+                    "kv-set! cannot store a {}",
+                    obj.type_name()
+                )));
+            }
+        }
+
+        self.append(&record(SET, &key, Some(&value)))?;
+        self.entries
+            .borrow_mut()
+            .insert(copied(&key), copied(&value));
+        Ok(())
+    }
+
+    /// Returns whether there was an entry for `key`.
+    pub fn delete(&self, key: &Object) -> Result<bool, EvalError> {
+        if self.entries.borrow().get(key).is_none() {
+            return Ok(false);
+        }
+
+        self.append(&record(DELETE, key, None))?;
+        self.entries.borrow_mut().remove(key);
+        Ok(true)
+    }
+
+    pub fn keys(&self) -> Vec<Object> {
+        self.entries
+            .borrow()
+            .entries()
+            .into_iter()
+            .map(|(key, _)| copied(&key))
+            .collect()
+    }
+
+    /// Rewrites the log with one record per entry, replacing the old log
+    /// only once the new one is on disk.
+    pub fn compact(&self) -> Result<(), EvalError> {
+        let mut bytes = [MAGIC, &[VERSION]].concat();
+        for (key, value) in self.entries.borrow().iter() {
+            bytes.extend(record(SET, key, Some(value)));
+        }
+
+        let mut temporary = self.path.clone().into_os_string();
+        temporary.push(".compacting");
+        let temporary = PathBuf::from(temporary);
+        File::create(&temporary)
+            .and_then(|mut file| {
+                file.write_all(&bytes)?;
+                file.sync_all()
+            })
+            .and_then(|()| fs::rename(&temporary, &self.path))
+            .map_err(|e| error(&self.path, e))?;
+
+        *self.log.borrow_mut() = OpenOptions::new()
+            .append(true)
+            .open(&self.path)
+            .map_err(|e| error(&self.path, e))?;
+        Ok(())
+    }
+}
+
+fn store_arg<'a>(name: &str, args: &'a [Object]) -> Result<&'a KvStore, EvalError> {
+    match args.first() {
+        Some(Object::KvStore(store)) => Ok(store),
+        _ => Err(EvalError::new(format!("{} expects a kv store", name))),
+    }
+}
+
+fn check_len(name: &str, args: &[Object], len: usize) -> Result<(), EvalError> {
+    if args.len() != len {
+        return Err(EvalError::new(format!(
+            "{} expects {} arguments, got {}",
+            name,
+            len,
+            args.len()
+        )));
+    }
+
+    Ok(())
+}
+
+/// `(kv-open path)` opens the store in the file at `path`, creating it if
+/// there is none.
+pub fn kv_open(args: &[Object]) -> Result<Object, EvalError> {
+    match args {
+        [Object::String(path)] => {
+            taint::check("kv-open", &args[0])?;
+            Ok(Object::KvStore(Rc::new(KvStore::open(path.as_str())?)))
+        }
+        _ => Err(EvalError::new("kv-open expects a path string")),
+    }
+}
+
+/// `(kv-get store key)` is `#f` when `key` is missing;
+/// `(kv-get store key default)` is `default` instead.
+pub fn kv_get(args: &[Object]) -> Result<Object, EvalError> {
+    check_len("kv-get", args, args.len().clamp(2, 3))?;
+    let store = store_arg("kv-get", args)?;
+
+    Ok(store
+        .get(&args[1])
+        .unwrap_or_else(|| args.get(2).cloned().unwrap_or(Object::Bool(false))))
+}
+
+pub fn kv_set(args: &[Object]) -> Result<Object, EvalError> {
+    check_len("kv-set!", args, 3)?;
+    store_arg("kv-set!", args)?.set(args[1].clone(), args[2].clone())?;
+
+    Ok(Object::Void)
+}
+
+/// `(kv-delete! store key)` returns whether there was an entry.
+pub fn kv_delete(args: &[Object]) -> Result<Object, EvalError> {
+    check_len("kv-delete!", args, 2)?;
+
+    Ok(Object::Bool(
+        store_arg("kv-delete!", args)?.delete(&args[1])?,
+    ))
+}
+
+pub fn kv_keys(args: &[Object]) -> Result<Object, EvalError> {
+    check_len("kv-keys", args, 1)?;
+
+    Ok(Object::list(store_arg("kv-keys", args)?.keys()))
+}
+
+pub fn kv_compact(args: &[Object]) -> Result<Object, EvalError> {
+    check_len("kv-compact!", args, 1)?;
+    store_arg("kv-compact!", args)?.compact()?;
+
+    Ok(Object::Void)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builtins::global_env;
+    use crate::eval::eval_str;
+    use std::env::temp_dir;
+
+    fn temporary(name: &str) -> PathBuf {
+        let path = temp_dir().join(format!("lisp-rs-kv-{}-{}", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn test_persistence() {
+        let path = temporary("persistence");
+        // The reader has no string escapes, so the quote comes from here.
+        let env = global_env();
+        let name = Object::list([Object::string("ana \" quoted"), Object::Char('a')]);
+        env.borrow_mut().define("name", name.clone());
+        let program = format!(
+            "(define db (kv-open {:?}))
+             (kv-set! db 'visits 1)
+             (kv-set! db name 1/2)
+             (kv-set! db 'visits (+ (kv-get db 'visits) 1))
+             (kv-set! db 'gone #t)
+             (kv-delete! db 'gone)",
+            path.display().to_string()
+        );
+        eval_str(&program, &env).unwrap();
+
+        let env = global_env();
+        env.borrow_mut().define("name", name);
+        let program = format!("(define db (kv-open {:?}))", path.display().to_string());
+        eval_str(&program, &env).unwrap();
+        let get = |key| eval_str(&format!("(kv-get db {} 'none)", key), &env).unwrap();
+        assert_eq!(get("'visits"), Object::Integer(2));
+        assert_eq!(get("'gone").to_string(), "none");
+        assert_eq!(get("name").to_string(), "1/2");
+        let keys = eval_str("(kv-keys db)", &env).unwrap();
+        assert_eq!(keys.to_vec().unwrap().len(), 2);
+
+        let size = fs::metadata(&path).unwrap().len();
+        eval_str("(kv-compact! db)", &env).unwrap();
+        assert!(fs::metadata(&path).unwrap().len() < size);
+        eval_str("(kv-set! db 'visits 3)", &env).unwrap();
+        assert_eq!(
+            KvStore::open(&path).unwrap().get(&Object::symbol("visits")),
+            Some(Object::Integer(3))
+        );
+
+        let err = eval_str("(kv-set! db 'f car)", &env).unwrap_err();
+        assert_eq!(err.message(), "kv-set! cannot store a procedure");
+
+        // Changing a list stored or read changes neither the store nor the log.
+        eval_str(
+            "(define l (list 1 2))
+             (kv-set! db 'l l)
+             (set-car! l 99)
+             (set-car! (kv-get db 'l) 98)",
+            &env,
+        )
+        .unwrap();
+        assert_eq!(get("'l").to_string(), "(1 2)");
+        assert_eq!(
+            KvStore::open(&path).unwrap().get(&Object::symbol("l")),
+            Some(get("'l"))
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_torn_record() {
+        let path = temporary("torn");
+        let store = KvStore::open(&path).unwrap();
+        store
+            .set(Object::Integer(1), Object::string("one"))
+            .unwrap();
+        store
+            .set(Object::Integer(2), Object::string("two"))
+            .unwrap();
+        drop(store);
+
+        let bytes = fs::read(&path).unwrap();
+        fs::write(&path, &bytes[..bytes.len() - 2]).unwrap();
+        let store = KvStore::open(&path).unwrap();
+        assert_eq!(store.keys(), [Object::Integer(1)]);
+        store.set(Object::Integer(3), Object::Nil).unwrap();
+        drop(store);
+
+        let store = KvStore::open(&path).unwrap();
+        assert_eq!(store.get(&Object::Integer(3)), Some(Object::Nil));
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,23 +1,178 @@
-use std::collections::HashSet;
-use std::error::Error;
-use std::fmt;
-use std::fmt::Formatter;
-use std::str::Chars;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::error::Error;
+use core::fmt;
+use core::fmt::Formatter;
+
+use crate::bigint::BigInt;
+use crate::diagnostic::{Diagnostic, Span};
+use crate::symbol::{Symbol, MAX_INTERNED_BYTES};
 
 pub fn tokenizer(input: &str) -> Result<Vec<Token>, TokenError> {
     let mut tokenizer = Tokenizer::new(input);
     let mut tokens = Vec::new();
 
-    while let Some(token) = tokenizer.next_token() {
+    while let Some(token) = tokenizer.next_token()? {
         tokens.push(token);
     }
 
     Ok(tokens)
 }
 
+/// Like `tokenizer`, with the span of every token in `input`.
+pub fn tokenizer_with_spans(input: &str) -> Result<Vec<(Token, Span)>, TokenError> {
+    Tokenizer::new(input).spanned_tokens()
+}
+
+/// Like `tokenizer_with_spans`, but an invalid token is skipped up to the
+/// next delimiter and tokenizing goes on, so every error is reported.
+pub fn tokenizer_with_recovery(input: &str) -> (Vec<(Token, Span)>, Vec<TokenError>) {
+    Tokenizer::new(input).tokens_with_recovery()
+}
+
+/// What a piece of source is, for editors to color it by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenClass {
+    /// The name of a special form, such as `define` or `if`.
+    Keyword,
+    Symbol,
+    Number,
+    String,
+    Char,
+    Boolean,
+    Comment,
+    Parenthesis,
+    /// A quote or the dot of a dotted pair.
+    Punctuation,
+    /// A literal for a reader macro, such as `#d2024-01-01`.
+    Literal,
+    Whitespace,
+    /// Text that is not a valid token.
+    Error,
+}
+
+/// The special forms `highlight` classifies as keywords.
+pub(crate) const KEYWORDS: &[&str] = &[
+    "assert",
+    "assert-equal",
+    "begin",
+    "case",
+    "cons-stream",
+    "declare",
+    "define",
+    "define-enum",
+    "define-memoized",
+    "define-record-type",
+    "defmacro",
+    "defstruct",
+    "deftest",
+    "delay",
+    "embed-file",
+    "guard",
+    "if",
+    "lambda",
+    "let",
+    "let-values",
+    "load",
+    "macroexpand",
+    "macroexpand-1",
+    "match",
+    "pipe",
+    "profile",
+    "provide",
+    "quasiquote",
+    "quote",
+    "require",
+    "run-tests",
+    "set!",
+    "sql",
+    "the-environment",
+    "time",
+    "trace",
+    "untrace",
+    "with-retry",
+    "with-task-scope",
+];
+
+/// Classifies every byte of `input`: the spans returned are in order and
+/// cover it without gaps, whitespace, comments and invalid tokens included,
+/// so an editor can color the input from them alone.
+pub fn highlight(input: &str) -> Vec<(Span, TokenClass)> {
+    Tokenizer::new(input).highlight()
+}
+
+fn classify(token: &Token, keywords: &[String]) -> TokenClass {
+    match token {
+        Token::Symbol(name) if KEYWORDS.contains(&name.as_str()) => TokenClass::Keyword,
+        Token::Symbol(name) if keywords.iter().any(|keyword| *name == **keyword) => {
+            TokenClass::Keyword
+        }
+        Token::Symbol(_) => TokenClass::Symbol,
+        Token::Float(_) | Token::Integer(_) | Token::BigInteger(_) | Token::Rational(_) => {
+            TokenClass::Number
+        }
+        Token::String(_) => TokenClass::String,
+        Token::Char(_) => TokenClass::Char,
+        Token::Boolean(_) => TokenClass::Boolean,
+        Token::Comment(_) => TokenClass::Comment,
+        Token::Dispatch(..) => TokenClass::Literal,
+        Token::LeftParenthesis | Token::RightParenthesis => TokenClass::Parenthesis,
+        Token::Quote | Token::Quasiquote | Token::Unquote | Token::UnquoteSplicing | Token::Dot => {
+            TokenClass::Punctuation
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct TokenError {
     err: String,
+    need_more_input: bool,
+    span: Option<Span>,
+}
+
+impl TokenError {
+    fn new(err: impl Into<String>) -> Self {
+        Self {
+            err: err.into(),
+            need_more_input: false,
+            span: None,
+        }
+    }
+
+    fn need_more_input() -> Self {
+        Self {
+            err: String::from("need more input"),
+            need_more_input: true,
+            span: None,
+        }
+    }
+
+    /// The message, without the "Tokenizer error" prefix.
+    pub fn message(&self) -> &str {
+        &self.err
+    }
+
+    /// Where in the buffered input the offending token is.
+    pub fn span(&self) -> Option<Span> {
+        self.span
+    }
+
+    /// The error as a diagnostic pointing into `source`, the input the
+    /// tokenizer was given.
+    pub fn diagnostic(&self, source: &str) -> Diagnostic {
+        let diagnostic = Diagnostic::error(&self.err);
+        match self.span {
+            Some(span) => diagnostic.at(source, span),
+            None => diagnostic,
+        }
+    }
+
+    /// Whether an incremental tokenizer stopped because the buffered input
+    /// ends in the middle of a token; push more input and ask again.
+    pub fn is_need_more_input(&self) -> bool {
+        self.need_more_input
+    }
 }
 
 impl Error for TokenError {}
@@ -32,76 +187,327 @@ impl fmt::Display for TokenError {
 pub enum Token {
     Float(f64),
     Integer(i64),
-    Symbol(String),
+    BigInteger(BigInt),
+    Rational(String),
+    Symbol(Symbol),
     LeftParenthesis,
     RightParenthesis,
+    Quote,
+    /// A backquote, which quotes a template: the forms inside it after an
+    /// `Unquote` (a comma) or an `UnquoteSplicing` (`,@`) are evaluated.
+    Quasiquote,
+    Unquote,
+    UnquoteSplicing,
+    Dot,
     String(String),
-    BinaryOp(String),
-    // UnaryOp(String),
-    Keyword(String),
+    Char(char),
+    Boolean(bool),
+    /// The text of a `;` comment after its first semicolon. Only produced
+    /// by tokenizers made with `keep_comments`.
+    Comment(String),
+    /// `#` and one of the characters a tokenizer was made to dispatch on
+    /// with `with_dispatch`, and the text after it up to the next
+    /// delimiter, for a reader macro to make a value of.
+    Dispatch(char, String),
 }
 
-pub struct Tokenizer<'a> {
-    input: Chars<'a>,
-    keywords: HashSet<&'a str>,
+/// The characters after `#` that keep their meaning whatever a tokenizer
+/// dispatches on: those of `#t`, `#f`, `#\a` and `#:keyword`.
+pub const RESERVED_DISPATCH: &[char] = &['t', 'f', '\\', ':'];
+
+pub struct Tokenizer {
+    buffer: String,
+    position: usize,
+    /// Where the last token read started.
+    token_start: usize,
+    finished: bool,
+    keep_comments: bool,
     current_character: Option<char>,
-    binary_operators: HashSet<char>,
-    // unary_operators: HashSet<char>,
+    /// The characters after `#` read as `Token::Dispatch`.
+    dispatch: Vec<char>,
+    /// The names `highlight` classifies as keywords besides `KEYWORDS`.
+    keywords: Vec<String>,
 }
 
-impl<'a> Tokenizer<'a> {
-    pub fn new(input: &'a str) -> Self {
-        let mut chars = input.chars();
-        let current_character = chars.next();
-        let keywords = ["define", "if"].into_iter().collect();
-        let binary_operators = ['+', '-', '*', '/'].into_iter().collect();
+impl Tokenizer {
+    pub fn new(input: &str) -> Self {
+        let mut tokenizer = Self::incremental();
+        tokenizer.push_str(input);
+        tokenizer.finish();
 
+        tokenizer
+    }
+
+    /// Creates a tokenizer that is fed with `push_str`. Until `finish` is
+    /// called, `next_token` reports `need more input` instead of cutting a
+    /// token short at the end of the buffer.
+    pub fn incremental() -> Self {
         Self {
-            input: chars,
-            current_character,
-            keywords,
-            binary_operators,
+            buffer: String::new(),
+            position: 0,
+            token_start: 0,
+            finished: false,
+            keep_comments: false,
+            current_character: None,
+            dispatch: Vec::new(),
+            keywords: Vec::new(),
+        }
+    }
+
+    /// Makes `next_token` return comments as `Token::Comment` instead of
+    /// skipping them, for tools such as the formatter.
+    pub fn keep_comments(mut self) -> Self {
+        self.keep_comments = true;
+        self
+    }
+
+    /// Makes `next_token` read `#` followed by one of `chars` as a
+    /// `Token::Dispatch`. They take the place of the radix prefixes, so
+    /// that dispatching on `d` gives up `#d10`; those in
+    /// `RESERVED_DISPATCH` are left out.
+    pub fn with_dispatch(mut self, chars: &[char]) -> Self {
+        self.dispatch = chars
+            .iter()
+            .filter(|c| !RESERVED_DISPATCH.contains(c))
+            .copied()
+            .collect();
+        self
+    }
+
+    /// Makes `highlight` classify `keywords` as keywords, as it does the
+    /// special forms, for hosts that add forms of their own.
+    pub fn with_keywords(mut self, keywords: &[&str]) -> Self {
+        self.keywords = keywords.iter().map(|keyword| keyword.to_string()).collect();
+        self
+    }
+
+    /// Reads the tokens left, each with its span.
+    pub fn spanned_tokens(mut self) -> Result<Vec<(Token, Span)>, TokenError> {
+        let mut tokens = Vec::new();
+
+        while let Some(token) = self.next_token()? {
+            tokens.push((token, self.last_span()));
+        }
+
+        Ok(tokens)
+    }
+
+    /// Like `spanned_tokens`, but an invalid token is skipped up to the
+    /// next delimiter and tokenizing goes on, so every error is reported.
+    pub fn tokens_with_recovery(mut self) -> (Vec<(Token, Span)>, Vec<TokenError>) {
+        let mut tokens = Vec::new();
+        let mut errors = Vec::new();
+
+        loop {
+            match self.next_token() {
+                Ok(Some(token)) => tokens.push((token, self.last_span())),
+                Ok(None) => break,
+                Err(e) => {
+                    self.skip_past(e.span());
+                    errors.push(e);
+                }
+            }
         }
+
+        (tokens, errors)
+    }
+
+    /// Classifies every byte of the input left, as the free function
+    /// `highlight` does.
+    pub fn highlight(self) -> Vec<(Span, TokenClass)> {
+        let mut tokenizer = self.keep_comments();
+        let end = tokenizer.buffer.len();
+        let mut classes = Vec::new();
+        let mut covered = tokenizer.position;
+
+        loop {
+            let (span, class) = match tokenizer.next_token() {
+                Ok(Some(token)) => (tokenizer.last_span(), classify(&token, &tokenizer.keywords)),
+                Ok(None) => break,
+                Err(e) => {
+                    // The error covers what recovery skips, not just the token.
+                    let start = e.span().map_or(tokenizer.offset(), |span| span.start);
+                    tokenizer.skip_past(e.span());
+                    (Span::new(start, tokenizer.offset()), TokenClass::Error)
+                }
+            };
+
+            if covered < span.start {
+                classes.push((Span::new(covered, span.start), TokenClass::Whitespace));
+            }
+            classes.push((span, class));
+            covered = span.end;
+        }
+        if covered < end {
+            classes.push((Span::new(covered, end), TokenClass::Whitespace));
+        }
+
+        classes
+    }
+
+    /// The byte offset of the next unread character in the buffered input.
+    pub fn offset(&self) -> usize {
+        self.position
+    }
+
+    /// The span of the token `next_token` returned last.
+    pub fn last_span(&self) -> Span {
+        Span::new(self.token_start, self.position)
+    }
+
+    pub fn push_str(&mut self, input: &str) {
+        self.buffer.drain(..self.position);
+        self.token_start = self.token_start.saturating_sub(self.position);
+        self.position = 0;
+        self.buffer.push_str(input);
+        self.current_character = self.buffer.chars().next();
+    }
+
+    /// Marks the end of the input: tokens running up to the end of the
+    /// buffer are complete from now on.
+    pub fn finish(&mut self) {
+        self.finished = true;
     }
 
-    pub fn next_token(&mut self) -> Option<Token> {
+    pub fn next_token(&mut self) -> Result<Option<Token>, TokenError> {
         self.eat_whitespace();
+        while self.current_character == Some(';') {
+            self.token_start = self.position;
+            let comment = self.read_comment()?;
+            if self.keep_comments {
+                return Ok(Some(Token::Comment(comment)));
+            }
+
+            self.eat_whitespace();
+        }
+
+        let start = self.position;
+        self.token_start = start;
+        let c = match self.current_character {
+            Some(c) => c,
+            None if self.finished => return Ok(None),
+            None => return Err(TokenError::need_more_input()),
+        };
+
+        let token = self.read_token(c);
+        let may_continue = !matches!(c, '(' | ')' | '\'' | '`' | '"');
+        let cut_short = may_continue && self.current_character.is_none() && !self.finished;
+
+        if cut_short || matches!(&token, Err(e) if e.is_need_more_input()) {
+            self.position = start;
+            self.current_character = Some(c);
+            return Err(TokenError::need_more_input());
+        }
+
+        token.map_err(|mut e| {
+            e.span = Some(Span::new(start, self.position.max(start + c.len_utf8())));
+            e
+        })
+    }
 
-        match self.current_character? {
+    fn read_token(&mut self, c: char) -> Result<Option<Token>, TokenError> {
+        let token = match c {
             '(' => {
                 self.advance();
-                Some(Token::LeftParenthesis)
+                Token::LeftParenthesis
             }
             ')' => {
                 self.advance();
-                Some(Token::RightParenthesis)
+                Token::RightParenthesis
             }
-            '"' => Some(Token::String(self.read_string())),
-            c if c.is_numeric() => {
-                let val = self.read_number();
-                if val.contains('.') {
-                    Some(Token::Float(val.parse::<f64>().unwrap()))
-                } else {
-                    Some(Token::Integer(val.parse::<i64>().unwrap()))
-                }
+            '\'' => {
+                self.advance();
+                Token::Quote
             }
-            c if c.is_alphabetic() || self.binary_operators.contains(&c) => {
-                let sym = self.read_symbol();
+            '`' => {
+                self.advance();
+                Token::Quasiquote
+            }
+            ',' => match self.advance() {
+                Some('@') => {
+                    self.advance();
+                    Token::UnquoteSplicing
+                }
+                _ => Token::Unquote,
+            },
+            '"' => match self.read_string() {
+                Some(string) => Token::String(string),
+                None if self.finished => return Err(TokenError::new("unterminated string")),
+                None => return Err(TokenError::need_more_input()),
+            },
+            '#' => {
+                match self.advance() {
+                    Some('\\') => {
+                        return match self.read_character() {
+                            Some(c) => Ok(Some(Token::Char(c))),
+                            None => Err(TokenError::new("unknown character name")),
+                        };
+                    }
+                    Some(c) if self.dispatch.contains(&c) => {
+                        self.advance();
+                        return Ok(Some(Token::Dispatch(c, self.read_to_delimiter())));
+                    }
+                    _ => {}
+                }
 
-                if self.keywords.contains(sym.as_str()) {
-                    Some(Token::Keyword(sym))
-                } else if self.binary_operators.contains(&sym.chars().next().unwrap()) {
-                    Some(Token::BinaryOp(sym))
+                let literal = self.read_symbol()?;
+                match literal.as_str() {
+                    "t" => Token::Boolean(true),
+                    "f" => Token::Boolean(false),
+                    _ => match literal.chars().next() {
+                        Some('x' | 'X') => integer_token(&literal[1..], 16)?,
+                        Some('b' | 'B') => integer_token(&literal[1..], 2)?,
+                        Some('o' | 'O') => integer_token(&literal[1..], 8)?,
+                        Some('d' | 'D') => integer_token(&literal[1..], 10)?,
+                        // `#:name` keywords are symbols that evaluate to themselves.
+                        Some(':') if literal.len() > 1 => {
+                            Token::Symbol(read_symbol_name(&format!("#{}", literal))?)
+                        }
+                        _ => return Err(TokenError::new(format!("unknown literal #{}", literal))),
+                    },
+                }
+            }
+            _ if self.starts_number() => number_or_symbol(&self.read_to_delimiter())?,
+            c if c.is_numeric() => {
+                return Err(TokenError::new(format!(
+                    "unexpected character {:?}; numbers are written with the digits 0 to 9",
+                    c
+                )))
+            }
+            c if is_symbol_initial(c) || matches!(c, '+' | '-' | '.') => {
+                let symbol = self.read_symbol()?;
+                if symbol == "." {
+                    Token::Dot
                 } else {
-                    Some(Token::Symbol(sym))
+                    Token::Symbol(read_symbol_name(&symbol)?)
                 }
             }
-            _ => None,
+            c => return Err(TokenError::new(format!("unexpected character {:?}", c))),
+        };
+
+        Ok(Some(token))
+    }
+
+    /// Moves past an invalid token: to the end of `span`, and then on to
+    /// the next delimiter.
+    fn skip_past(&mut self, span: Option<Span>) {
+        let end = span.map_or(self.position + 1, |span| span.end);
+        self.position = end.max(self.position).min(self.buffer.len());
+        while !self.buffer.is_char_boundary(self.position) {
+            self.position += 1;
+        }
+        self.current_character = self.buffer[self.position..].chars().next();
+
+        while self.current_character.is_some_and(|c| !is_delimiter(c)) {
+            self.advance();
         }
     }
 
     fn advance(&mut self) -> Option<char> {
-        self.current_character = self.input.next();
+        if let Some(c) = self.current_character {
+            self.position += c.len_utf8();
+        }
+        self.current_character = self.buffer[self.position..].chars().next();
 
         self.current_character
     }
@@ -116,49 +522,252 @@ impl<'a> Tokenizer<'a> {
         }
     }
 
-    fn read_symbol(&mut self) -> String {
+    fn peek(&self) -> Option<char> {
+        let c = self.current_character?;
+
+        self.buffer[self.position + c.len_utf8()..].chars().next()
+    }
+
+    fn read_symbol(&mut self) -> Result<String, TokenError> {
         let mut symbol = String::new();
         while let Some(c) = self.current_character {
-            if c.is_whitespace() || c == '(' || c == ')' {
+            if is_delimiter(c) {
                 break;
             }
 
+            if !is_symbol_subsequent(c) {
+                return Err(TokenError::new(format!(
+                    "invalid character {:?} in symbol {}",
+                    c, symbol
+                )));
+            }
+
             symbol.push(c);
             self.advance();
         }
 
-        symbol
+        Ok(symbol)
     }
 
-    fn read_number(&mut self) -> String {
-        let mut number = String::new();
-        while let Some(c) = self.current_character {
-            if !c.is_numeric() && c != '.' {
+    /// Reads up to the next delimiter: the text of a `Token::Dispatch`,
+    /// which may hold any other character, or of a token that starts like a
+    /// number, which `number_or_symbol` makes sense of.
+    fn read_to_delimiter(&mut self) -> String {
+        let mut text = String::new();
+        while let Some(c) = self.current_character.filter(|&c| !is_delimiter(c)) {
+            text.push(c);
+            self.advance();
+        }
+
+        text
+    }
+
+    /// Whether the input goes on with a number: a digit, after a sign, a
+    /// decimal point or both.
+    fn starts_number(&self) -> bool {
+        let rest = &self.buffer[self.position..];
+        let rest = rest.strip_prefix(['+', '-']).unwrap_or(rest);
+        let rest = rest.strip_prefix('.').unwrap_or(rest);
+
+        rest.starts_with(|c: char| c.is_ascii_digit())
+    }
+
+    /// Reads the body of a `#\\` character literal: either a single character
+    /// or a character name such as `space` or `newline`.
+    fn read_character(&mut self) -> Option<char> {
+        let first = self.advance()?;
+        let mut name = String::from(first);
+
+        while let Some(c) = self.advance() {
+            if !c.is_alphanumeric() {
                 break;
             }
 
-            number.push(c);
-            self.advance();
+            name.push(c);
+        }
+
+        if name.chars().count() == 1 {
+            return Some(first);
         }
 
-        number
+        match name.as_str() {
+            "space" => Some(' '),
+            "newline" => Some('\n'),
+            "tab" => Some('\t'),
+            "return" => Some('\r'),
+            "nul" => Some('\0'),
+            _ => None,
+        }
     }
 
-    fn read_string(&mut self) -> String {
+    /// Reads a comment up to the end of the line, leaving the newline.
+    fn read_comment(&mut self) -> Result<String, TokenError> {
+        let start = self.position;
+        let mut comment = String::new();
+
+        while let Some(c) = self.advance() {
+            if c == '\n' {
+                return Ok(comment.trim_end().to_string());
+            }
+
+            comment.push(c);
+        }
+
+        if !self.finished {
+            self.position = start;
+            self.current_character = Some(';');
+            return Err(TokenError::need_more_input());
+        }
+
+        Ok(comment.trim_end().to_string())
+    }
+
+    /// Reads a string literal, returning `None` if the input ends before the
+    /// closing quote. It takes the escapes strings are printed with: `\"`,
+    /// `\\`, `\n`, `\t`, `\r`, `\0`, `\'` and `\u{hex}`; a backslash
+    /// followed by anything else is kept as written.
+    fn read_string(&mut self) -> Option<String> {
         let mut string = String::new();
         self.advance();
 
+        while let Some(c) = self.current_character {
+            match c {
+                '"' => {
+                    self.advance();
+                    return Some(string);
+                }
+                '\\' => {
+                    let escaped = self.advance()?;
+                    match escaped {
+                        '"' | '\\' | '\'' => string.push(escaped),
+                        'n' => string.push('\n'),
+                        't' => string.push('\t'),
+                        'r' => string.push('\r'),
+                        '0' => string.push('\0'),
+                        'u' if self.peek() == Some('{') => {
+                            self.read_unicode_escape(&mut string)?;
+                            continue;
+                        }
+                        _ => {
+                            string.push('\\');
+                            string.push(escaped);
+                        }
+                    }
+                }
+                _ => string.push(c),
+            }
+            self.advance();
+        }
+
+        None
+    }
+
+    /// Reads the `u{hex}` of a `\u{hex}` escape into `string`, or keeps it
+    /// as written if it names no character, and moves past it.
+    fn read_unicode_escape(&mut self, string: &mut String) -> Option<()> {
+        let mut written = String::from("\\u");
+        self.advance();
         while let Some(c) = self.current_character {
             if c == '"' {
-                self.advance();
                 break;
             }
-
-            string.push(c);
+            written.push(c);
             self.advance();
+            if c == '}' {
+                let hex = &written[3..written.len() - 1];
+                match u32::from_str_radix(hex, 16).ok().and_then(char::from_u32) {
+                    Some(c) if !hex.starts_with('+') => string.push(c),
+                    _ => string.push_str(&written),
+                }
+                return Some(());
+            }
+        }
+        self.current_character?;
+        string.push_str(&written);
+
+        Some(())
+    }
+}
+
+/// Characters that may start a symbol: letters of any script, such as `λ`
+/// or `π`, and `!$%&*/:<=>?^_~`. `+`, `-` and `.` may too, as long as they
+/// do not start a number, followed by a digit or, after a sign, by `.` and
+/// a digit.
+fn is_symbol_initial(c: char) -> bool {
+    c.is_alphabetic() || "!$%&*/:<=>?^_~".contains(c)
+}
+
+/// Characters that may follow the first one in a symbol: those that may
+/// start one, `+-.@`, digits of any script, such as the `₁` of `x₁`, and
+/// combining accents, which follow the letter they are written over.
+fn is_symbol_subsequent(c: char) -> bool {
+    is_symbol_initial(c)
+        || c.is_numeric()
+        || "+-.@".contains(c)
+        || ('\u{300}'..='\u{36f}').contains(&c)
+}
+
+fn is_delimiter(c: char) -> bool {
+    c.is_whitespace() || matches!(c, '(' | ')' | '"' | '\'' | '`' | ',' | ';')
+}
+
+/// The symbol for a name read from the input, which fails once the symbol
+/// table is full rather than let the input grow it without bound.
+fn read_symbol_name(name: &str) -> Result<Symbol, TokenError> {
+    Symbol::try_intern(name).ok_or_else(|| {
+        TokenError::new(format!(
+            "too many distinct symbols: the symbol table holds {} bytes of names at most",
+            MAX_INTERNED_BYTES
+        ))
+    })
+}
+
+/// The token for text that starts like a number: the number, or a symbol
+/// naming an operation on one, an integer followed by a sign such as `1+`
+/// or `-1+`. Anything else starting like a number is a malformed one.
+fn number_or_symbol(literal: &str) -> Result<Token, TokenError> {
+    match literal.strip_suffix(['+', '-']).map(number_token) {
+        Some(Ok(Token::Integer(_) | Token::BigInteger(_))) => {
+            Ok(Token::Symbol(read_symbol_name(literal)?))
         }
+        _ => number_token(literal),
+    }
+}
+
+fn number_token(literal: &str) -> Result<Token, TokenError> {
+    if literal.contains('/') {
+        return Ok(Token::Rational(literal.to_string()));
+    }
 
-        string
+    let digits = literal.strip_prefix(['+', '-']).unwrap_or(literal);
+    if !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()) {
+        return integer_token(literal, 10);
+    }
+
+    let is_float = literal
+        .bytes()
+        .all(|b| b.is_ascii_digit() || matches!(b, b'.' | b'e' | b'E' | b'+' | b'-'));
+
+    match literal.parse::<f64>() {
+        Ok(n) if is_float => Ok(Token::Float(n)),
+        _ => Err(TokenError::new(format!(
+            "invalid number literal: {}",
+            literal
+        ))),
+    }
+}
+
+fn integer_token(digits: &str, radix: u32) -> Result<Token, TokenError> {
+    match i64::from_str_radix(digits, radix) {
+        Ok(n) => Ok(Token::Integer(n)),
+        Err(_) => BigInt::parse_radix(digits, radix)
+            .map(Token::BigInteger)
+            .ok_or_else(|| {
+                TokenError::new(format!(
+                    "invalid base {} integer literal: {}",
+                    radix, digits
+                ))
+            }),
     }
 }
 
@@ -173,7 +782,7 @@ mod tests {
         for operator in operators {
             let expected_tokens = vec![
                 Token::LeftParenthesis,
-                Token::BinaryOp(operator.to_string()),
+                Token::Symbol(Symbol::intern(&operator.to_string())),
                 Token::Integer(1),
                 Token::Integer(2),
                 Token::RightParenthesis,
@@ -186,6 +795,314 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_keywords() {
+        assert_eq!(
+            tokenizer("#:iterations").unwrap(),
+            vec![Token::Symbol(Symbol::intern("#:iterations"))]
+        );
+        assert!(tokenizer("#:").is_err());
+    }
+
+    #[test]
+    fn test_quote_and_booleans() {
+        let tokens = tokenizer("(set! done '(#t #f))").unwrap_or_default();
+
+        assert_eq!(
+            tokens,
+            vec![
+                Token::LeftParenthesis,
+                Token::Symbol(Symbol::intern("set!")),
+                Token::Symbol(Symbol::intern("done")),
+                Token::Quote,
+                Token::LeftParenthesis,
+                Token::Boolean(true),
+                Token::Boolean(false),
+                Token::RightParenthesis,
+                Token::RightParenthesis,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_quasiquote_tokens() {
+        assert_eq!(
+            tokenizer("`(a ,b ,@c)").unwrap_or_default(),
+            vec![
+                Token::Quasiquote,
+                Token::LeftParenthesis,
+                Token::Symbol(Symbol::intern("a")),
+                Token::Unquote,
+                Token::Symbol(Symbol::intern("b")),
+                Token::UnquoteSplicing,
+                Token::Symbol(Symbol::intern("c")),
+                Token::RightParenthesis,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_character_literals() {
+        let tokens = tokenizer(r"(#\a #\space #\newline #\( #\Z)").unwrap_or_default();
+
+        assert_eq!(
+            tokens,
+            vec![
+                Token::LeftParenthesis,
+                Token::Char('a'),
+                Token::Char(' '),
+                Token::Char('\n'),
+                Token::Char('('),
+                Token::Char('Z'),
+                Token::RightParenthesis,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_number_literals() {
+        let tokens = tokenizer("1e10 6.02e23 1.5E-3 #x1F #b1010 #o755 #x-ff").unwrap();
+
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Float(1e10),
+                Token::Float(6.02e23),
+                Token::Float(1.5e-3),
+                Token::Integer(31),
+                Token::Integer(10),
+                Token::Integer(493),
+                Token::Integer(-255),
+            ]
+        );
+
+        let big = tokenizer("#xffffffffffffffffff").unwrap();
+        assert_eq!(
+            big,
+            vec![Token::BigInteger(
+                BigInt::parse("4722366482869645213695").unwrap()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_malformed_number_literals() {
+        for literal in ["1.2.3", "12abc", "1e", "#b102", "#x", "1-2", "1e+", "-.5x"] {
+            assert!(tokenizer(literal).is_err(), "{} should not lex", literal);
+        }
+    }
+
+    #[test]
+    fn test_incremental_input() {
+        let mut tokenizer = Tokenizer::incremental();
+        let next = |tokenizer: &mut Tokenizer| match tokenizer.next_token() {
+            Err(e) if e.is_need_more_input() => None,
+            other => Some(other.unwrap()),
+        };
+
+        tokenizer.push_str("(def");
+        assert_eq!(next(&mut tokenizer), Some(Some(Token::LeftParenthesis)));
+        assert_eq!(next(&mut tokenizer), None);
+
+        tokenizer.push_str("ine x \"a b");
+        assert_eq!(
+            next(&mut tokenizer),
+            Some(Some(Token::Symbol(Symbol::intern("define"))))
+        );
+        assert_eq!(
+            next(&mut tokenizer),
+            Some(Some(Token::Symbol(Symbol::intern("x"))))
+        );
+        assert_eq!(next(&mut tokenizer), None);
+
+        tokenizer.push_str("c\") 4");
+        assert_eq!(
+            next(&mut tokenizer),
+            Some(Some(Token::String(String::from("a bc"))))
+        );
+        assert_eq!(next(&mut tokenizer), Some(Some(Token::RightParenthesis)));
+        assert_eq!(next(&mut tokenizer), None);
+
+        tokenizer.push_str("2");
+        tokenizer.finish();
+        assert_eq!(next(&mut tokenizer), Some(Some(Token::Integer(42))));
+        assert_eq!(next(&mut tokenizer), Some(None));
+    }
+
+    #[test]
+    fn test_comments() {
+        let source = "(a ; first\n b) ;; last";
+        assert_eq!(
+            tokenizer(source).unwrap().len(),
+            4,
+            "comments are skipped by default"
+        );
+
+        let mut tokenizer = Tokenizer::new(source).keep_comments();
+        let mut tokens = Vec::new();
+        while let Some(token) = tokenizer.next_token().unwrap() {
+            tokens.push(token);
+        }
+        assert_eq!(tokens[2], Token::Comment(String::from(" first")));
+        assert_eq!(tokens[5], Token::Comment(String::from("; last")));
+    }
+
+    #[test]
+    fn test_unterminated_string() {
+        assert!(tokenizer("\"abc").is_err());
+        assert!(tokenizer("\"abc\\\"").is_err());
+    }
+
+    #[test]
+    fn test_string_escapes() {
+        let string = |source: &str| match tokenizer(source).unwrap().as_slice() {
+            [Token::String(s)] => s.clone(),
+            other => panic!("expected a string, got {:?}", other),
+        };
+
+        // Strings read back as they are printed.
+        let text = "say \"hi\"\\\n\tit's\r\0\u{7f} é";
+        assert_eq!(string(&format!("{:?}", text)), text);
+
+        assert_eq!(string(r#""\u{3bb}""#), "λ");
+        assert_eq!(string(r#""C:\dir""#), "C:\\dir");
+        assert_eq!(string(r#""\u{zz}\u{110000}""#), r"\u{zz}\u{110000}");
+        assert_eq!(string(r#""\u{41""#), r"\u{41");
+    }
+
+    #[test]
+    fn test_symbol_names() {
+        let tokens =
+            tokenizer("list->vector null? set-car! my-var +my-var -> ... a.b x@y λ").unwrap();
+        let names = tokens
+            .into_iter()
+            .map(|token| match token {
+                Token::Symbol(name) => name,
+                other => panic!("expected a symbol, got {:?}", other),
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            names,
+            [
+                "list->vector",
+                "null?",
+                "set-car!",
+                "my-var",
+                "+my-var",
+                "->",
+                "...",
+                "a.b",
+                "x@y",
+                "λ"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_mixed_scripts() {
+        let source = "(define (área πr² x₁) \"¿qué? 日本語 😀\") (λ #\\λ 'Straße) (δ\u{301}x)";
+        let tokens = tokenizer(source).unwrap();
+        let symbols = tokens
+            .iter()
+            .filter_map(|token| match token {
+                Token::Symbol(name) => Some(name.as_str()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            symbols,
+            ["define", "área", "πr²", "x₁", "λ", "Straße", "δ\u{301}x"]
+        );
+        assert!(tokens.contains(&Token::String(String::from("¿qué? 日本語 😀"))));
+        assert!(tokens.contains(&Token::Char('λ')));
+
+        // Digits of other scripts do not make numbers, nor start symbols.
+        for source in ["٣", "(+ ١ 2)", "½"] {
+            let message = tokenizer(source).unwrap_err().message().to_string();
+            assert!(message.contains("the digits 0 to 9"), "{}", message);
+        }
+        assert!(tokenizer("1٣").is_err());
+        assert!(tokenizer("“quoted”").is_err());
+
+        // Spans of what follows multibyte characters stay on boundaries.
+        let spans = tokenizer_with_spans("(λ \"π\" x)").unwrap();
+        assert_eq!(
+            spans[3],
+            (Token::Symbol(Symbol::intern("x")), Span::new(9, 10))
+        );
+    }
+
+    #[test]
+    fn test_signed_numbers_and_operators() {
+        let tokens = tokenizer("(- -5 +3.5 .5 -.5 <=)").unwrap();
+
+        assert_eq!(
+            tokens,
+            vec![
+                Token::LeftParenthesis,
+                Token::Symbol(Symbol::intern("-")),
+                Token::Integer(-5),
+                Token::Float(3.5),
+                Token::Float(0.5),
+                Token::Float(-0.5),
+                Token::Symbol(Symbol::intern("<=")),
+                Token::RightParenthesis,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_numbers_end_at_a_delimiter() {
+        let tokens = tokenizer("(1+ -1+ 1- 2)").unwrap();
+
+        assert_eq!(
+            tokens,
+            vec![
+                Token::LeftParenthesis,
+                Token::Symbol(Symbol::intern("1+")),
+                Token::Symbol(Symbol::intern("-1+")),
+                Token::Symbol(Symbol::intern("1-")),
+                Token::Integer(2),
+                Token::RightParenthesis,
+            ]
+        );
+        assert_eq!(
+            tokenizer("1-2").unwrap_err().message(),
+            "invalid number literal: 1-2"
+        );
+    }
+
+    #[test]
+    fn test_invalid_symbols() {
+        for input in ["@foo", "a[0]", "{x}", "#\\bogus", "#q"] {
+            assert!(tokenizer(input).is_err(), "{} should not lex", input);
+        }
+    }
+
+    #[test]
+    fn test_highlight() {
+        let source = "(define x 'a) ; note\n  (f #t \"s\" 1.5 #\\a (1 . 2) #q @b)";
+        let classes = highlight(source);
+        let text = |class| {
+            classes
+                .iter()
+                .filter(|(_, c)| *c == class)
+                .map(|(span, _)| &source[span.start..span.end])
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(classes.first().unwrap().0.start, 0);
+        assert_eq!(classes.last().unwrap().0.end, source.len());
+        assert!(classes.windows(2).all(|w| w[0].0.end == w[1].0.start));
+        assert_eq!(text(TokenClass::Keyword), ["define"]);
+        assert_eq!(text(TokenClass::Symbol), ["x", "a", "f"]);
+        assert_eq!(text(TokenClass::Number), ["1.5", "1", "2"]);
+        assert_eq!(text(TokenClass::Comment), ["; note"]);
+        assert_eq!(text(TokenClass::Punctuation), ["'", "."]);
+        assert_eq!(text(TokenClass::Error), ["#q", "@b"]);
+    }
+
     #[test]
     fn test_area_of_circle() {
         let lisp_program = "(
@@ -201,23 +1118,23 @@ mod tests {
             vec![
                 Token::LeftParenthesis,
                 Token::LeftParenthesis,
-                Token::Keyword(String::from("define")),
-                Token::Symbol(String::from("r")),
+                Token::Symbol(Symbol::intern("define")),
+                Token::Symbol(Symbol::intern("r")),
                 Token::Integer(10),
                 Token::RightParenthesis,
                 Token::LeftParenthesis,
-                Token::Keyword(String::from("define")),
-                Token::Symbol(String::from("pi")),
+                Token::Symbol(Symbol::intern("define")),
+                Token::Symbol(Symbol::intern("pi")),
                 #[allow(clippy::approx_constant)]
                 Token::Float(3.14),
                 Token::RightParenthesis,
                 Token::LeftParenthesis,
-                Token::BinaryOp("*".to_string()),
-                Token::Symbol(String::from("pi")),
+                Token::Symbol(Symbol::intern("*")),
+                Token::Symbol(Symbol::intern("pi")),
                 Token::LeftParenthesis,
-                Token::BinaryOp("*".to_string()),
-                Token::Symbol(String::from("r")),
-                Token::Symbol(String::from("r")),
+                Token::Symbol(Symbol::intern("*")),
+                Token::Symbol(Symbol::intern("r")),
+                Token::Symbol(Symbol::intern("r")),
                 Token::RightParenthesis,
                 Token::RightParenthesis,
                 Token::RightParenthesis,
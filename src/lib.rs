@@ -1 +1,213 @@
+//! A Lisp interpreter.
+//!
+//! With the default `std` feature off, the crate is `no_std` and needs only
+//! `alloc`: it then provides the tokenizer, interned symbols, diagnostics
+//! and the numeric tower, the pieces a host can use to read Lisp data on a
+//! target without an operating system. It cannot run Lisp programs: the
+//! parser, the evaluator and the builtins all need `std`.
+
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
+pub mod ast;
+#[cfg(feature = "std")]
+pub mod bench;
+pub mod bigint;
+#[cfg(feature = "std")]
+pub mod builtins;
+#[cfg(feature = "std")]
+pub mod bytecode;
+#[cfg(feature = "std")]
+pub mod codegen;
+#[cfg(feature = "std")]
+pub mod collections;
+#[cfg(feature = "std")]
+pub mod comparator;
+#[cfg(feature = "std")]
+pub mod compiler;
+#[cfg(feature = "std")]
+pub mod continuation;
+#[cfg(feature = "std")]
+pub mod dap;
+#[cfg(feature = "std")]
+pub mod datetime;
+#[cfg(feature = "std")]
+pub mod debugger;
+pub mod diagnostic;
+#[cfg(feature = "std")]
+pub mod diff;
+#[cfg(feature = "std")]
+pub mod enums;
+#[cfg(feature = "std")]
+pub mod env;
+#[cfg(feature = "std")]
+pub mod eval;
+#[cfg(feature = "std")]
+pub mod fiber;
+#[cfg(feature = "std")]
+pub mod functional;
+#[cfg(feature = "std")]
+pub mod gc;
+#[cfg(feature = "std")]
+pub mod graph;
+#[cfg(feature = "std")]
+pub mod guard;
+#[cfg(feature = "std")]
+pub mod hooks;
+#[cfg(feature = "std")]
+pub mod i18n;
+#[cfg(feature = "std")]
+pub mod id;
+#[cfg(feature = "std")]
+pub mod interpreter;
+#[cfg(feature = "std")]
+pub mod interrupt;
+#[cfg(feature = "std")]
+pub mod json;
+#[cfg(feature = "std")]
+pub mod kv;
 pub mod lexer;
+#[cfg(feature = "std")]
+pub mod limits;
+#[cfg(feature = "std")]
+pub mod lsp;
+#[cfg(feature = "std")]
+pub mod macros;
+#[cfg(feature = "std")]
+pub mod math;
+#[cfg(feature = "std")]
+pub mod memo;
+#[cfg(feature = "std")]
+pub mod module;
+#[cfg(feature = "std")]
+pub mod net;
+#[cfg(feature = "std")]
+pub mod object;
+#[cfg(feature = "std")]
+pub mod optimizer;
+#[cfg(feature = "std")]
+pub mod parallel;
+#[cfg(feature = "std")]
+pub mod parser;
+#[cfg(feature = "std")]
+pub mod pattern;
+#[cfg(feature = "std")]
+pub mod prelude;
+#[cfg(feature = "std")]
+pub mod printer;
+#[cfg(feature = "std")]
+pub mod process;
+#[cfg(feature = "std")]
+pub mod profile;
+#[cfg(feature = "std")]
+pub mod promise;
+#[cfg(feature = "std")]
+pub mod quasiquote;
+#[cfg(feature = "std")]
+pub mod ratelimit;
+pub mod rational;
+#[cfg(feature = "std")]
+pub mod reader;
+#[cfg(feature = "std")]
+pub mod record;
+#[cfg(feature = "std")]
+pub mod reference;
+#[cfg(feature = "regex")]
+pub mod regex;
+#[cfg(feature = "std")]
+pub mod repl;
+#[cfg(feature = "std")]
+pub mod retry;
+#[cfg(feature = "std")]
+pub mod scheduler;
+#[cfg(feature = "serde")]
+pub mod serde;
+#[cfg(feature = "std")]
+pub mod server;
+#[cfg(feature = "std")]
+pub mod session;
+#[cfg(feature = "std")]
+pub mod shared;
+#[cfg(all(feature = "signals", unix))]
+pub mod signal;
+#[cfg(feature = "std")]
+pub mod signature;
+#[cfg(feature = "std")]
+pub mod sorted_map;
+#[cfg(feature = "std")]
+pub mod sql;
+pub mod symbol;
+#[cfg(feature = "std")]
+pub mod taint;
+#[cfg(feature = "std")]
+pub mod task;
+#[cfg(feature = "std")]
+pub mod template;
+#[cfg(feature = "terminal")]
+pub mod terminal;
+#[cfg(feature = "std")]
+pub mod testing;
+#[cfg(feature = "std")]
+pub mod text;
+#[cfg(feature = "std")]
+pub mod trace;
+#[cfg(feature = "std")]
+pub mod tutorial;
+#[cfg(feature = "std")]
+pub mod values;
+#[cfg(feature = "std")]
+pub mod vm;
+#[cfg(feature = "std")]
+pub mod wasm;
+#[cfg(feature = "watch")]
+pub mod watch;
+
+#[cfg(feature = "std")]
+pub use eval::EvalError;
+#[cfg(feature = "std")]
+pub use gc::Rooted;
+#[cfg(feature = "std")]
+pub use interpreter::{Arity, Interpreter, Value};
+
+#[cfg(test)]
+mod tests {
+    use crate::bigint::BigInt;
+    use crate::lexer::{tokenizer, tokenizer_with_spans, Token};
+    use crate::rational::Rational;
+    use crate::symbol::Symbol;
+
+    /// What a host built without `std` has: data read into tokens, with
+    /// their spans and diagnostics, and exact arithmetic on the numbers.
+    #[test]
+    fn test_alloc_only_core() {
+        let source = "(reading 3/4 100000000000000000000)";
+        let tokens = tokenizer_with_spans(source).unwrap();
+        let big = BigInt::parse("100000000000000000000").unwrap();
+        assert_eq!(
+            tokens
+                .iter()
+                .map(|(token, _)| token.clone())
+                .collect::<Vec<_>>(),
+            vec![
+                Token::LeftParenthesis,
+                Token::Symbol(Symbol::intern("reading")),
+                Token::Rational(String::from("3/4")),
+                Token::BigInteger(big.clone()),
+                Token::RightParenthesis,
+            ]
+        );
+        let span = tokens[1].1;
+        assert_eq!(&source[span.start..span.end], "reading");
+
+        let three_quarters = Rational::new(BigInt::from_i64(3), BigInt::from_i64(4)).unwrap();
+        let sum = &three_quarters + &Rational::from_integer(big);
+        assert_eq!(sum.to_string(), "400000000000000000003/4");
+
+        let err = tokenizer("(reading \"3/4").unwrap_err();
+        assert_eq!(err.message(), "unterminated string");
+        let err = tokenizer("(reading #q)").unwrap_err();
+        assert_eq!(err.diagnostic("(reading #q)").line_column(), Some((1, 10)));
+    }
+}
@@ -0,0 +1,325 @@
+//! Limits on how deep, how long and how much an evaluation may go, for hosts
+//! running programs they cannot trust to end.
+//!
+//! However it is limited, an evaluation nests at most `DEFAULT_DEPTH`
+//! evaluations unless given another depth, so that deep recursion fails
+//! with an error rather than overflowing the stack. That depth is sized for
+//! a stack of `STACK_SIZE`, which `lisp-rs` runs its commands and the
+//! threads it starts on; a host evaluating on a thread of its own gives it
+//! that stack, starting it with `thread_builder`, or a smaller `depth`.
+//! Fibers, on smaller stacks, nest as deep as fits on theirs.
+//!
+//! Each `Interpreter` keeps the limits it was created `with_limits`, and
+//! sets them for the thread by calling `enforce` around each evaluation;
+//! they are taken down when it ends, even by a panic, so that they never
+//! apply to the evaluations of another interpreter. The evaluator
+//! counts a step each time it loops, and the VM each time it calls a
+//! procedure, while `expt` counts the steps its exact powers would take
+//! before computing them; the depth is the number of evaluations nested in each other,
+//! which is what uses the Rust stack. Allocations are those counted by
+//! `CountingAllocator`, so that limit only applies when it is the global
+//! allocator. Going over a limit raises an error that `limit_exceeded`
+//! tells apart from the others.
+
+use std::cell::Cell;
+use std::fmt;
+use std::thread;
+
+use crate::bench;
+use crate::eval::EvalError;
+
+/// The stack `DEFAULT_DEPTH` is sized for. Stack pages are only allocated
+/// as they are touched, so most evaluations use far less of it.
+pub const STACK_SIZE: usize = 64 << 20;
+
+/// The stack a nested evaluation takes at most, with the builtins it goes
+/// through: about 3 KiB in release builds and 11 KiB in debug ones.
+const FRAME_SIZE: usize = match cfg!(debug_assertions) {
+    true => 16 << 10,
+    false => 4 << 10,
+};
+
+/// How deeply evaluations nest on a stack of `STACK_SIZE` when no `depth`
+/// limit is given.
+pub const DEFAULT_DEPTH: usize = STACK_SIZE / FRAME_SIZE;
+
+/// A thread builder with a stack of `STACK_SIZE`, for the threads that
+/// evaluate.
+pub fn thread_builder() -> thread::Builder {
+    thread::Builder::new().stack_size(STACK_SIZE)
+}
+
+/// The most an evaluation may use of each resource, with `None` for no limit.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Limits {
+    /// Nested evaluations, which each take a few kilobytes of Rust stack.
+    /// `None` is `DEFAULT_DEPTH`, counted on the stack the evaluation runs
+    /// on, and a host giving more runs it on a stack big enough for them.
+    pub depth: Option<usize>,
+    pub steps: Option<u64>,
+    pub allocations: Option<u64>,
+}
+
+/// The limit an evaluation went over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    Depth,
+    Steps,
+    Allocations,
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Limit::Depth => "depth",
+            Limit::Steps => "step",
+            Limit::Allocations => "allocation",
+        })
+    }
+}
+
+#[derive(Clone, Copy)]
+struct State {
+    limits: Limits,
+    depth: usize,
+    steps: u64,
+    /// The allocation count when the evaluation started.
+    allocations: u64,
+}
+
+/// The evaluations nested on a stack, and how many fit on it.
+#[derive(Clone, Copy)]
+pub(crate) struct Stack {
+    depth: usize,
+    max: usize,
+}
+
+impl Stack {
+    /// A stack of `size` bytes, on which nothing is being evaluated yet.
+    pub(crate) fn new(size: usize) -> Self {
+        Stack {
+            depth: 0,
+            max: size / FRAME_SIZE,
+        }
+    }
+}
+
+thread_local! {
+    /// That of the limited evaluation running, if any.
+    static STATE: Cell<Option<State>> = const { Cell::new(None) };
+    /// The stack being evaluated on.
+    static STACK: Cell<Stack> = const {
+        Cell::new(Stack {
+            depth: 0,
+            max: DEFAULT_DEPTH,
+        })
+    };
+}
+
+/// Makes `stack` the one being evaluated on, returning the one that was,
+/// for fibers to switch stacks.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn switch_stack(stack: Stack) -> Stack {
+    STACK.with(|current| current.replace(stack))
+}
+
+/// Puts back the state of the evaluation another was nested in when it
+/// ends, however it does.
+struct Restore(Option<State>);
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        STATE.with(|current| current.set(self.0));
+    }
+}
+
+/// Runs `f` with `limits` counted from zero, restoring the limits of any
+/// evaluation it is nested in afterwards.
+pub(crate) fn enforce<T>(limits: Limits, f: impl FnOnce() -> T) -> T {
+    if limits == Limits::default() {
+        return f();
+    }
+
+    let state = State {
+        limits,
+        depth: 0,
+        steps: 0,
+        allocations: bench::allocations().unwrap_or(0),
+    };
+    let _restore = Restore(STATE.with(|current| current.replace(Some(state))));
+    f()
+}
+
+fn exceeded(limit: Limit, max: impl fmt::Display) -> EvalError {
+    EvalError::limit(
+        limit,
+        format!("evaluation exceeded its {} limit of {}", limit, max),
+    )
+}
+
+/// Counts an evaluation step, checking the step and allocation limits.
+pub(crate) fn step() -> Result<(), EvalError> {
+    charge(1)
+}
+
+/// Counts `steps` steps at once, for a builtin about to do as much work as
+/// they take, so that it fails over the step limit before doing it rather
+/// than after.
+pub(crate) fn charge(steps: u64) -> Result<(), EvalError> {
+    STATE.with(|current| {
+        let Some(mut state) = current.get() else {
+            return Ok(());
+        };
+
+        state.steps = state.steps.saturating_add(steps);
+        current.set(Some(state));
+        if let Some(max) = state.limits.steps.filter(|max| state.steps > *max) {
+            return Err(exceeded(Limit::Steps, max));
+        }
+        if let (Some(max), Some(count)) = (state.limits.allocations, bench::allocations()) {
+            if count.saturating_sub(state.allocations) > max {
+                return Err(exceeded(Limit::Allocations, max));
+            }
+        }
+
+        Ok(())
+    })
+}
+
+/// Runs `f` one evaluation deeper, unless that goes over the depth limit,
+/// or the default depth if there is none.
+pub(crate) fn nested<T>(f: impl FnOnce() -> Result<T, EvalError>) -> Result<T, EvalError> {
+    let mut stack = STACK.with(Cell::get);
+    STATE.with(|current| {
+        let state = current.get();
+        match state.and_then(|state| state.limits.depth.map(|max| (state, max))) {
+            Some((state, max)) if state.depth >= max => Err(exceeded(Limit::Depth, max)),
+            None if stack.depth >= stack.max => Err(exceeded(Limit::Depth, stack.max)),
+            _ => {
+                if let Some(mut state) = state {
+                    state.depth += 1;
+                    current.set(Some(state));
+                }
+                Ok(())
+            }
+        }
+    })?;
+    stack.depth += 1;
+    STACK.with(|current| current.set(stack));
+
+    struct Unnest;
+    impl Drop for Unnest {
+        fn drop(&mut self) {
+            STATE.with(|current| {
+                if let Some(mut state) = current.get() {
+                    state.depth = state.depth.saturating_sub(1);
+                    current.set(Some(state));
+                }
+            });
+            STACK.with(|current| {
+                let mut stack = current.get();
+                stack.depth = stack.depth.saturating_sub(1);
+                current.set(stack);
+            });
+        }
+    }
+
+    let _unnest = Unnest;
+    f()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builtins::global_env;
+    use crate::eval::eval_str;
+    use crate::interpreter::{Interpreter, Value};
+    use crate::object::Object;
+
+    fn limited(limits: Limits) -> Interpreter {
+        let mut interpreter = Interpreter::with_limits(limits);
+        interpreter
+            .eval_str("(define (down n) (if (= n 0) 0 (+ 1 (down (- n 1)))))")
+            .unwrap();
+        interpreter
+    }
+
+    #[test]
+    fn test_depth_limit() {
+        let mut interpreter = limited(Limits {
+            depth: Some(200),
+            ..Limits::default()
+        });
+        assert_eq!(interpreter.eval_str("(down 20)").unwrap(), Value::from(20));
+
+        let err = interpreter.eval_str("(down 1000)").unwrap_err();
+        assert_eq!(err.limit_exceeded(), Some(Limit::Depth));
+        assert_eq!(err.message(), "evaluation exceeded its depth limit of 200");
+        // The count starts over with each evaluation.
+        assert!(interpreter.eval_str("(down 20)").is_ok());
+    }
+
+    #[test]
+    fn test_default_depth() {
+        let deep = thread_builder()
+            .spawn(|| {
+                let env = global_env();
+                let program = "(define (f n) (if (= n 0) 0 (+ 1 (f (- n 1))))) (f 100000)";
+                let err = eval_str(program, &env).unwrap_err();
+                assert_eq!(err.limit_exceeded(), Some(Limit::Depth));
+                assert_eq!(
+                    err.message(),
+                    format!("evaluation exceeded its depth limit of {}", DEFAULT_DEPTH)
+                );
+                // The depth is counted from zero again afterwards.
+                assert_eq!(eval_str("(f 100)", &env).unwrap(), Object::Integer(100));
+
+                let err = eval_str("(par-map f '(1 100000))", &env).unwrap_err();
+                assert!(err.message().contains("depth limit"), "{}", err.message());
+
+                let mut interpreter = Interpreter::new();
+                interpreter.eval_str(program).unwrap_err().limit_exceeded()
+            })
+            .unwrap();
+        assert_eq!(deep.join().unwrap(), Some(Limit::Depth));
+    }
+
+    #[test]
+    fn test_step_limit() {
+        let mut interpreter = limited(Limits {
+            steps: Some(10_000),
+            ..Limits::default()
+        });
+        interpreter
+            .eval_str("(define (forever n) (forever (+ n 1)))")
+            .unwrap();
+
+        let err = interpreter.eval_str("(forever 0)").unwrap_err();
+        assert_eq!(err.limit_exceeded(), Some(Limit::Steps));
+        assert!(interpreter.call("down", &[Value::from(5)]).is_ok());
+        let err = interpreter.call("forever", &[Value::from(0)]).unwrap_err();
+        assert_eq!(err.limit_exceeded(), Some(Limit::Steps));
+
+        let err = Interpreter::new().eval_str("(car 1)").unwrap_err();
+        assert_eq!(err.limit_exceeded(), None);
+    }
+
+    #[test]
+    fn test_limits_end_with_a_panic() {
+        let mut interpreter = limited(Limits {
+            steps: Some(10_000),
+            ..Limits::default()
+        });
+        interpreter.register_fn("fail", |_| panic!("the host failed"));
+        let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            interpreter.eval_str("(down 3) (fail)")
+        }));
+        assert!(panicked.is_err());
+
+        let mut other = Interpreter::new();
+        other
+            .eval_str("(define (count n) (if (= n 0) n (count (- n 1))))")
+            .unwrap();
+        assert_eq!(other.eval_str("(count 50000)").unwrap(), Value::from(0));
+    }
+}
@@ -0,0 +1,527 @@
+//! `lisp-rs lsp`: a Language Server Protocol server over stdin and stdout.
+//!
+//! Editors send whole documents (full text sync). On every change the
+//! server parses the document with `parse_with_recovery` and publishes its
+//! diagnostics, along with the warnings of `enums::check`. It answers go-to-definition for names bound by `define` in
+//! any open document, preferring the one asked about, and lists the
+//! `define`s of a document as its symbols. Positions count UTF-16 code
+//! units, as the protocol does by default.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::io::{BufRead, Write};
+
+use crate::collections::{hash_table_object, HashTable};
+use crate::diagnostic::{Severity, Span};
+use crate::enums;
+use crate::json;
+use crate::lexer::{tokenizer_with_recovery, Token};
+use crate::object::Object;
+use crate::parser::parse_with_recovery;
+
+/// LSP's `SymbolKind`s for functions and variables.
+const FUNCTION: i64 = 12;
+const VARIABLE: i64 = 13;
+
+/// JSON-RPC's error code for an unknown method.
+const METHOD_NOT_FOUND: i64 = -32601;
+
+/// Reads one `Content-Length` framed message, or `None` at the end of the
+/// input.
+pub(crate) fn read_message(input: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+
+    let length = length
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length"))?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+
+    String::from_utf8(body)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+pub(crate) fn write_message(output: &mut impl Write, message: &Object) -> io::Result<()> {
+    let body = json::stringify(message).map_err(|e| io::Error::other(e.to_string()))?;
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+/// A JSON object with the given members.
+pub(crate) fn object<const N: usize>(members: [(&str, Object); N]) -> Object {
+    let mut table = HashTable::default();
+    for (key, value) in members {
+        table.insert(Object::string(key), value);
+    }
+
+    hash_table_object(table)
+}
+
+/// The member at `path` of nested JSON objects.
+pub(crate) fn field(obj: &Object, path: &[&str]) -> Option<Object> {
+    path.iter().try_fold(obj.clone(), |obj, key| match obj {
+        Object::HashTable(table) => table.borrow().get(&Object::string(*key)).cloned(),
+        _ => None,
+    })
+}
+
+pub(crate) fn string_field(obj: &Object, path: &[&str]) -> Option<String> {
+    match field(obj, path)? {
+        Object::String(s) => Some(s.as_str().to_string()),
+        _ => None,
+    }
+}
+
+pub(crate) fn integer_field(obj: &Object, path: &[&str]) -> Option<i64> {
+    match field(obj, path)? {
+        Object::Integer(n) => Some(n),
+        _ => None,
+    }
+}
+
+pub(crate) fn null() -> Object {
+    Object::symbol("null")
+}
+
+/// The LSP position of byte `offset` in `text`.
+fn position(text: &str, offset: usize) -> Object {
+    let before = &text[..offset.min(text.len())];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let line = before.matches('\n').count();
+    let character = before[line_start..].encode_utf16().count();
+
+    object([
+        ("line", Object::Integer(line as i64)),
+        ("character", Object::Integer(character as i64)),
+    ])
+}
+
+/// The byte offset of an LSP position in `text`, clamped to its line.
+fn offset(text: &str, line: i64, character: i64) -> usize {
+    let line_start = match line {
+        0 => 0,
+        _ => match text.match_indices('\n').nth(line as usize - 1) {
+            Some((i, _)) => i + 1,
+            None => return text.len(),
+        },
+    };
+
+    let mut units = 0;
+    for (i, c) in text[line_start..].char_indices() {
+        if c == '\n' || units >= character {
+            return line_start + i;
+        }
+        units += c.len_utf16() as i64;
+    }
+
+    text.len()
+}
+
+fn range(text: &str, span: Span) -> Object {
+    object([
+        ("start", position(text, span.start)),
+        ("end", position(text, span.end)),
+    ])
+}
+
+/// A name bound by `define`: the span of the name itself and of the whole
+/// `define` form.
+struct Definition {
+    name: String,
+    name_span: Span,
+    form_span: Span,
+    is_function: bool,
+}
+
+fn is_define(token: Option<&(Token, Span)>) -> bool {
+    matches!(token, Some((Token::Symbol(s), _)) if *s == "define" || *s == "define-memoized")
+}
+
+fn definitions(text: &str) -> Vec<Definition> {
+    let (tokens, _) = tokenizer_with_recovery(text);
+    let mut definitions = Vec::new();
+
+    for (i, (token, open)) in tokens.iter().enumerate() {
+        if *token != Token::LeftParenthesis || !is_define(tokens.get(i + 1)) {
+            continue;
+        }
+
+        let (name, is_function) = match (tokens.get(i + 2), tokens.get(i + 3)) {
+            (Some((Token::Symbol(name), span)), value) => {
+                let is_lambda = matches!(
+                    (value, tokens.get(i + 4)),
+                    (Some((Token::LeftParenthesis, _)), Some((Token::Symbol(s), _))) if *s == "lambda"
+                );
+                ((name, *span), is_lambda)
+            }
+            (Some((Token::LeftParenthesis, _)), Some((Token::Symbol(name), span))) => {
+                ((name, *span), true)
+            }
+            _ => continue,
+        };
+
+        // The form ends at the matching parenthesis, or with the text.
+        let mut depth = 0;
+        let mut end = text.len();
+        for (token, span) in &tokens[i..] {
+            match token {
+                Token::LeftParenthesis => depth += 1,
+                Token::RightParenthesis => depth -= 1,
+                _ => {}
+            }
+            if depth == 0 {
+                end = span.end;
+                break;
+            }
+        }
+
+        definitions.push(Definition {
+            name: name.0.to_string(),
+            name_span: name.1,
+            form_span: Span::new(open.start, end),
+            is_function,
+        });
+    }
+
+    definitions
+}
+
+/// The symbol under byte `offset`, if any.
+fn symbol_at(text: &str, offset: usize) -> Option<String> {
+    let (tokens, _) = tokenizer_with_recovery(text);
+
+    tokens.into_iter().find_map(|(token, span)| match token {
+        Token::Symbol(name) if span.start <= offset && offset <= span.end => Some(name.to_string()),
+        _ => None,
+    })
+}
+
+fn publish_diagnostics(output: &mut impl Write, uri: &str, text: &str) -> io::Result<()> {
+    let (_, diagnostics) = parse_with_recovery(text);
+    let diagnostics = diagnostics
+        .into_iter()
+        .chain(enums::check(text))
+        .map(|diagnostic| {
+            let span = diagnostic.span.unwrap_or(Span::new(0, 0));
+            let message = match &diagnostic.hint {
+                Some(hint) => format!("{} ({})", diagnostic.message, hint),
+                None => diagnostic.message.clone(),
+            };
+            object([
+                ("range", range(text, span)),
+                (
+                    "severity",
+                    Object::Integer(match diagnostic.severity {
+                        Severity::Error => 1,
+                        Severity::Warning => 2,
+                        Severity::Note => 3,
+                    }),
+                ),
+                ("source", Object::string("lisp-rs")),
+                ("message", Object::string(message)),
+            ])
+        })
+        .collect::<Vec<_>>();
+
+    write_message(
+        output,
+        &object([
+            ("jsonrpc", Object::string("2.0")),
+            ("method", Object::string("textDocument/publishDiagnostics")),
+            (
+                "params",
+                object([
+                    ("uri", Object::string(uri)),
+                    ("diagnostics", Object::list(diagnostics)),
+                ]),
+            ),
+        ]),
+    )
+}
+
+struct Server {
+    /// The text of every open document, by URI.
+    documents: BTreeMap<String, String>,
+    shutdown: bool,
+}
+
+impl Server {
+    fn definition(&self, params: &Object) -> Option<Object> {
+        let uri = string_field(params, &["textDocument", "uri"])?;
+        let text = self.documents.get(&uri)?;
+        let line = integer_field(params, &["position", "line"])?;
+        let character = integer_field(params, &["position", "character"])?;
+        let name = symbol_at(text, offset(text, line, character))?;
+
+        // The document asked about comes first.
+        let documents = self
+            .documents
+            .get_key_value(&uri)
+            .into_iter()
+            .chain(self.documents.iter().filter(|(other, _)| **other != uri));
+        for (uri, text) in documents {
+            if let Some(definition) = definitions(text).into_iter().find(|d| d.name == name) {
+                return Some(object([
+                    ("uri", Object::string(uri.as_str())),
+                    ("range", range(text, definition.name_span)),
+                ]));
+            }
+        }
+
+        None
+    }
+
+    fn document_symbols(&self, params: &Object) -> Option<Object> {
+        let uri = string_field(params, &["textDocument", "uri"])?;
+        let text = self.documents.get(&uri)?;
+
+        let symbols = definitions(text)
+            .into_iter()
+            .map(|definition| {
+                let kind = if definition.is_function {
+                    FUNCTION
+                } else {
+                    VARIABLE
+                };
+                object([
+                    ("name", Object::string(definition.name)),
+                    ("kind", Object::Integer(kind)),
+                    ("range", range(text, definition.form_span)),
+                    ("selectionRange", range(text, definition.name_span)),
+                ])
+            })
+            .collect::<Vec<_>>();
+
+        Some(Object::list(symbols))
+    }
+
+    /// Handles a notification, or a request, returning its result or an
+    /// error code and message.
+    fn handle(
+        &mut self,
+        output: &mut impl Write,
+        method: &str,
+        params: &Object,
+    ) -> io::Result<Result<Object, (i64, String)>> {
+        let result = match method {
+            "initialize" => object([
+                (
+                    "capabilities",
+                    object([
+                        ("textDocumentSync", Object::Integer(1)),
+                        ("definitionProvider", Object::Bool(true)),
+                        ("documentSymbolProvider", Object::Bool(true)),
+                    ]),
+                ),
+                ("serverInfo", object([("name", Object::string("lisp-rs"))])),
+            ]),
+            "textDocument/didOpen" | "textDocument/didChange" => {
+                let uri = string_field(params, &["textDocument", "uri"]).unwrap_or_default();
+                let text = match method {
+                    "textDocument/didOpen" => string_field(params, &["textDocument", "text"]),
+                    // With full sync, the last change holds the whole text.
+                    _ => field(params, &["contentChanges"])
+                        .and_then(|changes| changes.to_vec())
+                        .and_then(|changes| changes.last().cloned())
+                        .and_then(|change| string_field(&change, &["text"])),
+                };
+                if let Some(text) = text {
+                    publish_diagnostics(output, &uri, &text)?;
+                    self.documents.insert(uri, text);
+                }
+                null()
+            }
+            "textDocument/didClose" => {
+                let uri = string_field(params, &["textDocument", "uri"]).unwrap_or_default();
+                self.documents.remove(&uri);
+                publish_diagnostics(output, &uri, "")?;
+                null()
+            }
+            "textDocument/definition" => self.definition(params).unwrap_or_else(null),
+            "textDocument/documentSymbol" => self.document_symbols(params).unwrap_or_else(null),
+            "shutdown" => {
+                self.shutdown = true;
+                null()
+            }
+            _ => {
+                return Ok(Err((
+                    METHOD_NOT_FOUND,
+                    format!("unknown method {}", method),
+                )))
+            }
+        };
+
+        Ok(Ok(result))
+    }
+}
+
+/// Serves one editor session until the `exit` notification or the end of
+/// the input. Returns the exit code: 0 if the editor asked the server to
+/// shut down first, as the protocol requires, and 1 otherwise.
+pub fn run(mut input: impl BufRead, mut output: impl Write) -> io::Result<i32> {
+    let mut server = Server {
+        documents: BTreeMap::new(),
+        shutdown: false,
+    };
+
+    while let Some(body) = read_message(&mut input)? {
+        let message = match json::parse(&body) {
+            Ok(message) => message,
+            Err(e) => {
+                eprintln!("lisp-rs lsp: ignoring a message: {}", e.message());
+                continue;
+            }
+        };
+
+        let method = string_field(&message, &["method"]).unwrap_or_default();
+        if method == "exit" {
+            return Ok(if server.shutdown { 0 } else { 1 });
+        }
+
+        let params = field(&message, &["params"]).unwrap_or_else(null);
+        let result = server.handle(&mut output, &method, &params)?;
+
+        // Notifications have no id and get no response.
+        let id = match field(&message, &["id"]) {
+            Some(id) => id,
+            None => continue,
+        };
+        let response = match result {
+            Ok(result) => object([
+                ("jsonrpc", Object::string("2.0")),
+                ("id", id),
+                ("result", result),
+            ]),
+            Err((code, message)) => object([
+                ("jsonrpc", Object::string("2.0")),
+                ("id", id),
+                (
+                    "error",
+                    object([
+                        ("code", Object::Integer(code)),
+                        ("message", Object::string(message)),
+                    ]),
+                ),
+            ]),
+        };
+        write_message(&mut output, &response)?;
+    }
+
+    Ok(if server.shutdown { 0 } else { 1 })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(messages: &[&str]) -> Vec<u8> {
+        messages
+            .iter()
+            .map(|body| format!("Content-Length: {}\r\n\r\n{}", body.len(), body))
+            .collect::<String>()
+            .into_bytes()
+    }
+
+    fn bodies(output: &[u8]) -> Vec<String> {
+        let mut input = output;
+        let mut bodies = Vec::new();
+        while let Some(body) = read_message(&mut input).unwrap() {
+            bodies.push(body);
+        }
+        bodies
+    }
+
+    #[test]
+    fn test_positions() {
+        let text = "(a)\n(é 𝄞 b)\n";
+        assert_eq!(
+            json::stringify(&position(text, 13)).unwrap(),
+            "{\"character\":6,\"line\":1}"
+        );
+        assert_eq!(offset(text, 1, 6), 13);
+        assert_eq!(offset(text, 1, 99), 15);
+        assert_eq!(offset(text, 9, 0), text.len());
+    }
+
+    #[test]
+    fn test_session() {
+        let text = "(define (sq x)\\n  (* x x))\\n(define n (sq 3))\\n(sq n) (car '(1 . ))";
+        let open = format!(
+            r#"{{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{{"textDocument":{{"uri":"file:///a.lisp","text":"{}"}}}}}}"#,
+            text
+        );
+        let input = frame(&[
+            r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{}}"#,
+            r#"{"jsonrpc":"2.0","method":"initialized","params":{}}"#,
+            &open,
+            r#"{"jsonrpc":"2.0","id":2,"method":"textDocument/definition","params":{"textDocument":{"uri":"file:///a.lisp"},"position":{"line":3,"character":2}}}"#,
+            r#"{"jsonrpc":"2.0","id":3,"method":"textDocument/documentSymbol","params":{"textDocument":{"uri":"file:///a.lisp"}}}"#,
+            r#"{"jsonrpc":"2.0","id":"x","method":"textDocument/hover","params":{}}"#,
+            r#"{"jsonrpc":"2.0","id":4,"method":"shutdown"}"#,
+            r#"{"jsonrpc":"2.0","method":"exit"}"#,
+        ]);
+        let mut output = Vec::new();
+
+        assert_eq!(run(input.as_slice(), &mut output).unwrap(), 0);
+        let range = |l1, c1, l2, c2| {
+            format!(
+                "{{\"end\":{{\"character\":{},\"line\":{}}},\"start\":{{\"character\":{},\"line\":{}}}}}",
+                c2, l2, c1, l1
+            )
+        };
+        assert_eq!(
+            bodies(&output),
+            [
+                String::from(
+                    "{\"id\":1,\"jsonrpc\":\"2.0\",\"result\":{\"capabilities\":\
+                     {\"definitionProvider\":true,\"documentSymbolProvider\":true,\
+                     \"textDocumentSync\":1},\"serverInfo\":{\"name\":\"lisp-rs\"}}}"
+                ),
+                format!(
+                    "{{\"jsonrpc\":\"2.0\",\"method\":\"textDocument/publishDiagnostics\",\
+                     \"params\":{{\"diagnostics\":[{{\"message\":\"missing ')' \
+                     (the list opened here is never closed)\",\"range\":{},\"severity\":1,\
+                     \"source\":\"lisp-rs\"}},{{\"message\":\"expected a single form after '.'\",\
+                     \"range\":{},\"severity\":1,\"source\":\"lisp-rs\"}}],\
+                     \"uri\":\"file:///a.lisp\"}}}}",
+                    range(3, 7, 3, 8),
+                    range(3, 16, 3, 17)
+                ),
+                format!(
+                    "{{\"id\":2,\"jsonrpc\":\"2.0\",\"result\":{{\"range\":{},\"uri\":\"file:///a.lisp\"}}}}",
+                    range(0, 9, 0, 11)
+                ),
+                format!(
+                    "{{\"id\":3,\"jsonrpc\":\"2.0\",\"result\":[\
+                     {{\"kind\":12,\"name\":\"sq\",\"range\":{},\"selectionRange\":{}}},\
+                     {{\"kind\":13,\"name\":\"n\",\"range\":{},\"selectionRange\":{}}}]}}",
+                    range(0, 0, 1, 10),
+                    range(0, 9, 0, 11),
+                    range(2, 0, 2, 17),
+                    range(2, 8, 2, 9)
+                ),
+                String::from(
+                    "{\"error\":{\"code\":-32601,\"message\":\"unknown method textDocument/hover\"},\
+                     \"id\":\"x\",\"jsonrpc\":\"2.0\"}"
+                ),
+                String::from("{\"id\":4,\"jsonrpc\":\"2.0\",\"result\":null}"),
+            ]
+        );
+    }
+}
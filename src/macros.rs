@@ -0,0 +1,301 @@
+//! Macros, procedures that turn forms into other forms before they are
+//! evaluated.
+//!
+//! `(defmacro (name params...) body...)` defines `name` as a macro. A list
+//! starting with it is a use of the macro: the body is evaluated with the
+//! parameters bound to the forms of the use, not evaluated, and its value,
+//! the expansion, is evaluated in place of the use. Quasiquote makes
+//! templates of expansions:
+//!
+//! ```text
+//! (defmacro (unless test . body) `(if ,test #f (begin ,@body)))
+//! (unless (> 1 2) 'small)   ; => small
+//! ```
+//!
+//! `(macroexpand-1 form)` is the expansion of a use, or `form` itself if it
+//! is not one, and `(macroexpand form)` expands it again and again until it
+//! is not. Neither expands the forms inside the one they are given.
+//!
+//! A macro is expanded each time the use is evaluated, in the environment
+//! the macro was defined in. An error raised while evaluating an expansion
+//! points at the source it came from: at a list of the use if the macro put
+//! it in the expansion as it was, and otherwise at the use.
+//!
+//! Macros are not hygienic: the symbols of an expansion mean what they mean
+//! where the use is, so a binding the macro makes captures the variables of
+//! the same name in the forms it is given, and a binding around the use
+//! shadows the procedures the expansion calls. `(gensym)` makes a symbol no
+//! other is `eq?` to, for the bindings a macro makes:
+//!
+//! ```text
+//! (defmacro (swap! a b)
+//!   (let ((tmp (gensym)))
+//!     `(let ((,tmp ,a)) (set! ,a ,b) (set! ,b ,tmp))))
+//! ```
+//!
+//! A symbol made by `gensym` prints as its name, `g` followed by a number
+//! unless it is given another prefix, but reading that name back gives a
+//! different symbol.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::env::Env;
+use crate::eval::{apply, eval, make_lambda, EvalError};
+use crate::object::{Lambda, Object};
+use crate::symbol::Symbol;
+
+/// `(defmacro (name params...) body...)`.
+pub fn defmacro(list: &[Object], env: &Rc<RefCell<Env>>) -> Result<Object, EvalError> {
+    let (Some(Object::Pair(signature)), true) = (list.get(1), list.len() > 2) else {
+        return Err(EvalError::new(
+            "defmacro expects (name parameters...) and a body",
+        ));
+    };
+    let Object::Symbol(name) = signature.car() else {
+        return Err(EvalError::new(format!(
+            "defmacro expects a symbol to name the macro, got {}",
+            signature.car()
+        )));
+    };
+    let Object::Lambda(expander) = make_lambda(None, &signature.cdr(), &list[2..], env)? else {
+        unreachable!("make_lambda makes lambdas");
+    };
+
+    env.borrow_mut().define(name, Object::Macro(expander));
+    Ok(Object::Void)
+}
+
+/// The expansion of the use `list` of the macro `expander`.
+pub(crate) fn expand(expander: &Rc<Lambda>, list: &[Object]) -> Result<Object, EvalError> {
+    apply(&Object::Lambda(expander.clone()), list[1..].to_vec())
+}
+
+/// The expansion of `form` if it is a use of a macro bound in `env`.
+fn expand_1(form: &Object, env: &Rc<RefCell<Env>>) -> Result<Option<Object>, EvalError> {
+    let Some(list) = form.to_vec() else {
+        return Ok(None);
+    };
+    let Some(Object::Symbol(head)) = list.first() else {
+        return Ok(None);
+    };
+    let Some(Object::Macro(expander)) = env.borrow().get(*head) else {
+        return Ok(None);
+    };
+
+    expand(&expander, &list).map(Some)
+}
+
+fn form_arg(name: &str, list: &[Object], env: &Rc<RefCell<Env>>) -> Result<Object, EvalError> {
+    match list {
+        [_, form] => eval(form, env),
+        _ => Err(EvalError::new(format!("{} expects one form", name))),
+    }
+}
+
+/// `(macroexpand-1 form)`.
+pub fn macroexpand_1(list: &[Object], env: &Rc<RefCell<Env>>) -> Result<Object, EvalError> {
+    let form = form_arg("macroexpand-1", list, env)?;
+
+    Ok(expand_1(&form, env)?.unwrap_or(form))
+}
+
+/// `(macroexpand form)`.
+pub fn macroexpand(list: &[Object], env: &Rc<RefCell<Env>>) -> Result<Object, EvalError> {
+    let mut form = form_arg("macroexpand", list, env)?;
+    while let Some(expansion) = expand_1(&form, env)? {
+        form = expansion;
+    }
+
+    Ok(form)
+}
+
+/// `(gensym [prefix])` is a new uninterned symbol.
+pub fn gensym(args: &[Object]) -> Result<Object, EvalError> {
+    let prefix = match args {
+        [] => "g",
+        [Object::String(prefix)] => prefix.as_str(),
+        [Object::Symbol(prefix)] => prefix.as_str(),
+        _ => {
+            return Err(EvalError::new(
+                "gensym expects an optional string or symbol prefix",
+            ))
+        }
+    };
+    Ok(Object::Symbol(Symbol::uninterned(prefix)))
+}
+
+/// Whether `form` uses, anywhere in it, a macro bound in `env`, which the
+/// VM leaves to the tree-walking evaluator.
+pub(crate) fn uses_macro(form: &Object, env: &Rc<RefCell<Env>>) -> bool {
+    let env = env.borrow();
+
+    uses(form, |name| matches!(env.get(name), Some(Object::Macro(_))))
+}
+
+/// The macros the top-level forms of a program define.
+pub(crate) fn defined(forms: &[Object]) -> Vec<Symbol> {
+    forms
+        .iter()
+        .filter_map(|form| match form.to_vec()?.as_slice() {
+            [Object::Symbol(head), Object::Pair(signature), ..] if *head == "defmacro" => {
+                match signature.car() {
+                    Object::Symbol(name) => Some(name),
+                    _ => None,
+                }
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Whether a list in `form` starts with a symbol `is_macro` is true of.
+pub(crate) fn uses(form: &Object, is_macro: impl Fn(Symbol) -> bool) -> bool {
+    let mut pending = vec![form.clone()];
+    while let Some(form) = pending.pop() {
+        if let Object::Pair(pair) = form {
+            let car = pair.car();
+            if let Object::Symbol(head) = car {
+                if is_macro(head) {
+                    return true;
+                }
+            }
+            pending.push(car);
+            pending.push(pair.cdr());
+        }
+    }
+
+    false
+}
+
+/// Whether the list `target` is one of the lists of `tree`.
+fn contains(tree: &Object, target: &Object) -> bool {
+    let Object::Pair(target) = target else {
+        return false;
+    };
+    let mut pending = vec![tree.clone()];
+    while let Some(tree) = pending.pop() {
+        if let Object::Pair(pair) = tree {
+            if Rc::ptr_eq(&pair, target) {
+                return true;
+            }
+            pending.push(pair.car());
+            pending.push(pair.cdr());
+        }
+    }
+
+    false
+}
+
+/// The list an error raised in `expr` is pointed at, given the uses of
+/// macros and their expansions evaluated on the way to it, in order: the
+/// use whose expansion made `expr`, if one did.
+pub(crate) fn origin(mut expr: Object, expansions: &[(Object, Object)]) -> Object {
+    for (use_, expansion) in expansions.iter().rev() {
+        let made = contains(expansion, &expr);
+        let written = match use_ {
+            Object::Pair(pair) => contains(&pair.cdr(), &expr),
+            _ => false,
+        };
+        if made && !written {
+            expr = use_.clone();
+        }
+    }
+
+    expr
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builtins::global_env;
+    use crate::eval::eval_str;
+
+    #[test]
+    fn test_defmacro() {
+        let env = global_env();
+        let result = eval_str(
+            "(defmacro (swap! a b) `(let ((tmp ,a)) (set! ,a ,b) (set! ,b tmp)))
+             (defmacro (unless test . body) `(if ,test #f (begin ,@body)))
+             (define x 1)
+             (define y 2)
+             (swap! x y)
+             (list x y (unless (> x y) 'never) (unless (< x y) 'small))",
+            &env,
+        )
+        .unwrap();
+        assert_eq!(result.to_string(), "(2 1 #f small)");
+
+        assert_eq!(
+            eval_str("(macroexpand-1 '(unless #f (swap! x y)))", &env)
+                .unwrap()
+                .to_string(),
+            "(if #f #f (begin (swap! x y)))"
+        );
+        assert_eq!(
+            eval_str(
+                "(defmacro (my-unless . args) `(unless ,@args))
+                 (list (macroexpand '(my-unless #f 1)) (macroexpand-1 '(f 1)) (macroexpand 2))",
+                &env
+            )
+            .unwrap()
+            .to_string(),
+            "((if #f #f (begin 1)) (f 1) 2)"
+        );
+        assert_eq!(
+            eval_str("(map unless '(1))", &env).unwrap_err().message(),
+            "not a procedure: #<macro>"
+        );
+        assert_eq!(
+            eval_str("(defmacro (1 x) x)", &env).unwrap_err().message(),
+            "defmacro expects a symbol to name the macro, got 1"
+        );
+    }
+
+    #[test]
+    fn test_gensym() {
+        let env = global_env();
+        let result = eval_str(
+            "(defmacro (swap! a b)
+               (let ((tmp (gensym)))
+                 `(let ((,tmp ,a)) (set! ,a ,b) (set! ,b ,tmp))))
+             (define tmp 1)
+             (define other 2)
+             (swap! tmp other)
+             (define g (gensym \"tmp\"))
+             (list tmp other (eq? g g) (eq? (gensym) (gensym)) (symbol? g) (eq? g 'tmp))",
+            &env,
+        )
+        .unwrap();
+        assert_eq!(result.to_string(), "(2 1 #t #f #t #f)");
+        assert!(eval_str("g", &env).unwrap().to_string().starts_with("tmp"));
+
+        // A gensym is not taken for the special form its prefix names.
+        let result = eval_str(
+            "(define f (gensym 'if))
+             (eval (list 'define (list f 'x) '(* x 2)))
+             (eval (list f 7))",
+            &env,
+        );
+        assert_eq!(result.unwrap().to_string(), "14");
+        assert_eq!(
+            eval_str("(gensym 1)", &env).unwrap_err().message(),
+            "gensym expects an optional string or symbol prefix"
+        );
+    }
+
+    #[test]
+    fn test_errors_point_at_the_source() {
+        let env = global_env();
+        eval_str("(defmacro (twice form) `(begin (car '()) ,form))", &env).unwrap();
+
+        // A list the expansion made is reported at the use.
+        let err = eval_str("(define z 1)\n(twice 1)", &env).unwrap_err();
+        assert!(err.diagnostic().to_string().contains(" --> 2:1\n"));
+
+        // A list written in the use is reported where it is written.
+        eval_str("(defmacro (second form) `(begin 1 ,form))", &env).unwrap();
+        let err = eval_str("(second\n  (cdr 5))", &env).unwrap_err();
+        assert_eq!(err.message(), "cdr expects a pair, got 5");
+        assert!(err.diagnostic().to_string().contains(" --> 2:3\n"));
+    }
+}
@@ -1,3 +1,659 @@
+use std::cell::RefCell;
+use std::env;
+use std::fs;
+use std::io;
+use std::io::BufReader;
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::rc::Rc;
+
+use lisp_rs::bench::CountingAllocator;
+use lisp_rs::builtins::global_env;
+use lisp_rs::bytecode;
+use lisp_rs::codegen;
+use lisp_rs::compiler::{compile_program, embed_files};
+use lisp_rs::dap;
+use lisp_rs::enums;
+use lisp_rs::env::Env;
+use lisp_rs::eval::eval_named;
+use lisp_rs::hooks;
+use lisp_rs::limits;
+use lisp_rs::lsp;
+use lisp_rs::module;
+use lisp_rs::object::Object;
+use lisp_rs::optimizer::optimize_forms;
+use lisp_rs::parser::parse;
+use lisp_rs::prelude;
+use lisp_rs::printer::{format_source, DEFAULT_WIDTH};
+use lisp_rs::profile::Profiler;
+use lisp_rs::reference;
+#[cfg(feature = "rustyline")]
+use lisp_rs::repl::RustylineEditor;
+use lisp_rs::repl::{self, BasicEditor, LineEditor};
+use lisp_rs::server;
+use lisp_rs::taint;
+use lisp_rs::testing;
+use lisp_rs::trace::ChromeTrace;
+use lisp_rs::tutorial::{self, Progress};
+use lisp_rs::vm;
+#[cfg(feature = "watch")]
+use lisp_rs::watch::Watcher;
+
+const DEFAULT_PORT: u16 = 7888;
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
 fn main() {
-    println!("Hello, world!");
+    // Commands evaluate on a stack of the size the default depth limit is
+    // sized for, so that deep recursion fails with an error.
+    match limits::thread_builder()
+        .spawn(command)
+        .map(|thread| thread.join())
+    {
+        Ok(Ok(())) => {}
+        // The panic has been reported already.
+        Ok(Err(_)) => process::exit(101),
+        Err(e) => {
+            eprintln!("cannot start a thread: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+fn command() {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let mut trace_out = None;
+    let mut profiled = false;
+    loop {
+        match args.first().map(String::as_str) {
+            Some("--audit") => {
+                args.remove(0);
+                taint::enable(true);
+            }
+            Some("--no-prelude") => {
+                args.remove(0);
+                prelude::enable(false);
+            }
+            Some("--profile") => {
+                args.remove(0);
+                profiled = true;
+            }
+            Some("--trace-out") if args.len() > 1 => {
+                args.remove(0);
+                trace_out = Some(args.remove(0));
+            }
+            _ => break,
+        }
+    }
+
+    match args.split_first() {
+        Some((command, files)) if command == "fmt" => process::exit(fmt(files)),
+        Some((command, args)) if command == "compile" => process::exit(compile(args)),
+        Some((command, args)) if command == "codegen" => process::exit(generate(args)),
+        Some((command, args)) if command == "serve" => process::exit(serve(args)),
+        Some((command, [])) if command == "lsp" => process::exit(language_server()),
+        Some((command, args)) if command == "dap" => process::exit(debug_adapter(args)),
+        Some((command, args)) if command == "test" => process::exit(test(args)),
+        Some((command, names)) if command == "explain" => process::exit(explain(names)),
+        Some((command, args)) if command == "reference" => process::exit(write_reference(args)),
+        Some((command, args)) if command == "learn" => process::exit(learn(args)),
+        #[cfg(feature = "watch")]
+        Some((command, args)) if command == "watch" => process::exit(watch(args)),
+        Some((script, script_args)) => process::exit(match trace_out {
+            Some(trace_out) => run_traced(script, script_args, &trace_out),
+            None if profiled => run_profiled(script, script_args),
+            None => run_script(script, script_args),
+        }),
+        None => repl(),
+    }
+}
+
+/// Runs a script, or a program made by `lisp-rs compile` on the VM.
+fn run_script(path: &str, args: &[String]) -> i32 {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            eprintln!("cannot read {}: {}", path, e);
+            return 1;
+        }
+    };
+
+    let env = global_env();
+    define_args(&env, args);
+
+    let result = if bytecode::is_compiled(&bytes) {
+        match bytecode::decode(&bytes) {
+            Ok(program) => module::in_file(path, || vm::run_program(&program, &env)),
+            Err(e) => {
+                eprintln!("{}: {}", path, e);
+                return 1;
+            }
+        }
+    } else {
+        match String::from_utf8(bytes) {
+            Ok(source) => {
+                warn(path, &source);
+                module::in_file(path, || eval_named(&source, path, &env))
+            }
+            Err(_) => {
+                eprintln!("{} is not UTF-8", path);
+                return 1;
+            }
+        }
+    };
+
+    match result {
+        Ok(_) => 0,
+        Err(e) => match e.exit_code() {
+            Some(code) => code,
+            None => {
+                eprintln!("{}", e.with_origin(path).diagnostic());
+                1
+            }
+        },
+    }
+}
+
+/// Prints the warnings about `source` found before it runs.
+fn warn(path: &str, source: &str) {
+    for warning in enums::check(source) {
+        eprintln!("{}", warning.with_origin(path));
+    }
+}
+
+/// Runs a script with `--trace-out`, writing its calls to `trace_out` as
+/// Chrome trace events.
+fn run_traced(path: &str, args: &[String], trace_out: &str) -> i32 {
+    let trace = Rc::new(ChromeTrace::new());
+    let hook = hooks::add(trace.clone());
+    let code = run_script(path, args);
+    hooks::remove(hook);
+
+    match fs::write(trace_out, trace.to_json()) {
+        Ok(()) => code,
+        Err(e) => {
+            eprintln!("cannot write {}: {}", trace_out, e);
+            1
+        }
+    }
+}
+
+/// Runs a script with `--profile`, printing how many times each procedure
+/// was called and how long it took to stderr.
+fn run_profiled(path: &str, args: &[String]) -> i32 {
+    let profiler = Rc::new(Profiler::new());
+    let hook = hooks::add(profiler.clone());
+    let code = run_script(path, args);
+    hooks::remove(hook);
+
+    eprint!("{}", profiler.report());
+    code
+}
+
+/// `lisp-rs watch SCRIPT ARGS...` runs a script, and runs it again each
+/// time a file in its directory changes. Each run is on a thread of its
+/// own, so that it starts afresh, with no modules loaded.
+#[cfg(feature = "watch")]
+fn watch(args: &[String]) -> i32 {
+    let Some((script, script_args)) = args.split_first() else {
+        eprintln!("usage: lisp-rs watch SCRIPT [ARGS...]");
+        return 2;
+    };
+    let dir = match Path::new(script).parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let mut watcher = match Watcher::new(dir) {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            eprintln!("cannot watch {}: {}", dir.display(), e);
+            return 1;
+        }
+    };
+
+    let (audit, prelude) = (taint::is_enabled(), prelude::is_enabled());
+    loop {
+        let (script, script_args) = (script.clone(), script_args.to_vec());
+        let run = limits::thread_builder()
+            .spawn(move || {
+                taint::enable(audit);
+                prelude::enable(prelude);
+                run_script(&script, &script_args)
+            })
+            .map(|run| run.join());
+        match run {
+            Ok(Ok(code)) => eprintln!("[exited with {}, waiting for changes]", code),
+            Ok(Err(_)) => eprintln!("[panicked, waiting for changes]"),
+            Err(e) => {
+                eprintln!("cannot start a thread: {}", e);
+                return 1;
+            }
+        }
+
+        // What the script wrote itself does not count as a change.
+        let _ = watcher.poll();
+        match watcher.wait() {
+            Ok(changed) => eprintln!("[{} changed, running again]", changed[0].display()),
+            Err(e) => {
+                eprintln!("{}", e.message());
+                return 1;
+            }
+        }
+    }
+}
+
+/// `lisp-rs test [DIR]` runs the tests defined in the `*-test.lisp` files
+/// under DIR, the current directory by default, each file in a fresh
+/// environment, and fails if any of them fails.
+fn test(args: &[String]) -> i32 {
+    let dir = match args {
+        [] => Path::new("."),
+        [dir] => Path::new(dir),
+        _ => {
+            eprintln!("usage: lisp-rs test [DIR]");
+            return 2;
+        }
+    };
+    let files = match testing::discover(dir) {
+        Ok(files) => files,
+        Err(e) => {
+            eprintln!("cannot read {}: {}", dir.display(), e);
+            return 1;
+        }
+    };
+    if files.is_empty() {
+        println!("no *-test.lisp files in {}", dir.display());
+        return 0;
+    }
+
+    let (mut passed, mut failed, mut broken) = (0, 0, 0);
+    for path in &files {
+        let path = path.display().to_string();
+        println!("running {}", path);
+        let source = match fs::read_to_string(&path) {
+            Ok(source) => source,
+            Err(e) => {
+                eprintln!("cannot read {}: {}", path, e);
+                broken += 1;
+                continue;
+            }
+        };
+
+        let env = global_env();
+        let result = module::in_file(&path, || eval_named(&source, &path, &env))
+            .and_then(|_| testing::run(&env, &mut io::stdout()));
+        match result {
+            Ok(summary) => {
+                passed += summary.passed;
+                failed += summary.failed;
+            }
+            Err(e) => match e.exit_code() {
+                Some(code) => return code,
+                None => {
+                    eprintln!("{}", e.diagnostic());
+                    broken += 1;
+                }
+            },
+        }
+        println!();
+    }
+
+    print!(
+        "{} files: {} tests passed, {} failed",
+        files.len(),
+        passed,
+        failed
+    );
+    match broken {
+        0 => println!(),
+        _ => println!(", {} files could not be run", broken),
+    }
+    match failed + broken {
+        0 => 0,
+        _ => 1,
+    }
+}
+
+/// `lisp-rs fmt FILE...` prints the files formatted; with `--check` it
+/// prints nothing and fails if any of them is not formatted.
+fn fmt(args: &[String]) -> i32 {
+    let check = args.iter().any(|arg| arg == "--check");
+    let files: Vec<&String> = args.iter().filter(|arg| *arg != "--check").collect();
+    if files.is_empty() {
+        eprintln!("usage: lisp-rs fmt [--check] FILE...");
+        return 2;
+    }
+
+    let mut status = 0;
+    for path in files {
+        let source = match fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(e) => {
+                eprintln!("cannot read {}: {}", path, e);
+                return 1;
+            }
+        };
+
+        match format_source(&source, DEFAULT_WIDTH) {
+            Ok(formatted) if check => {
+                if formatted != source {
+                    eprintln!("{} is not formatted", path);
+                    status = 1;
+                }
+            }
+            Ok(formatted) => print!("{}", formatted),
+            Err(e) => {
+                eprintln!("{}: {}", path, e);
+                status = 1;
+            }
+        }
+    }
+
+    status
+}
+
+/// `lisp-rs compile [--no-optimize] FILE [-o OUTPUT]` saves the compiled
+/// program, by default next to the source with an `.lbc` extension, after
+/// running the optimizer over it unless `--no-optimize` is given.
+fn compile(args: &[String]) -> i32 {
+    let (optimize, args) = match args.split_first() {
+        Some((flag, args)) if flag == "--no-optimize" => (false, args),
+        _ => (true, args),
+    };
+    let (source_path, output) = match args {
+        [source] => (source, Path::new(source).with_extension("lbc")),
+        [source, flag, output] if flag == "-o" => (source, PathBuf::from(output)),
+        _ => {
+            eprintln!("usage: lisp-rs compile [--no-optimize] FILE [-o OUTPUT]");
+            return 2;
+        }
+    };
+
+    let source = match fs::read_to_string(source_path) {
+        Ok(source) => source,
+        Err(e) => {
+            eprintln!("cannot read {}: {}", source_path, e);
+            return 1;
+        }
+    };
+    warn(source_path, &source);
+    let forms = match parse(&source) {
+        Ok(forms) => forms,
+        Err(e) => {
+            eprintln!("{}: {}", source_path, e);
+            return 1;
+        }
+    };
+
+    let embedded = module::in_file(source_path, || {
+        forms.iter().map(embed_files).collect::<Result<Vec<_>, _>>()
+    });
+    let forms = match embedded {
+        Ok(forms) => forms,
+        Err(e) => {
+            eprintln!("{}: {}", source_path, e.message());
+            return 1;
+        }
+    };
+    let forms = match optimize {
+        true => optimize_forms(forms),
+        false => forms,
+    };
+
+    let bytes = bytecode::encode(&compile_program(forms));
+    match fs::write(&output, bytes) {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("cannot write {}: {}", output.display(), e);
+            1
+        }
+    }
+}
+
+/// `lisp-rs explain NAME...` prints what the special forms and procedures
+/// named do and how they are called.
+fn explain(names: &[String]) -> i32 {
+    if names.is_empty() {
+        eprintln!("usage: lisp-rs explain NAME...");
+        return 2;
+    }
+
+    let mut status = 0;
+    for (i, name) in names.iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        match reference::explain(name) {
+            Some(entry) => print!("{}", entry.describe()),
+            None => {
+                eprintln!("no special form or procedure is called {}", name);
+                status = 1;
+            }
+        }
+    }
+    status
+}
+
+/// `lisp-rs reference [-o OUTPUT]` writes the language reference, as
+/// Markdown, to OUTPUT, or prints it.
+fn write_reference(args: &[String]) -> i32 {
+    let output = match args {
+        [] => None,
+        [flag, output] if flag == "-o" => Some(output),
+        _ => {
+            eprintln!("usage: lisp-rs reference [-o OUTPUT]");
+            return 2;
+        }
+    };
+
+    let reference = reference::markdown();
+    match output {
+        Some(output) => match fs::write(output, reference) {
+            Ok(()) => 0,
+            Err(e) => {
+                eprintln!("cannot write {}: {}", output, e);
+                1
+            }
+        },
+        None => {
+            print!("{}", reference);
+            0
+        }
+    }
+}
+
+/// `lisp-rs learn` takes the tutorial, carrying on from where it was left,
+/// or from the start with `--restart`. The progress is kept in
+/// `~/.lisp-rs-learn`.
+fn learn(args: &[String]) -> i32 {
+    let restart = match args {
+        [] => false,
+        [flag] if flag == "--restart" => true,
+        _ => {
+            eprintln!("usage: lisp-rs learn [--restart]");
+            return 2;
+        }
+    };
+    let path = env::var_os("HOME")
+        .map(PathBuf::from)
+        .unwrap_or_default()
+        .join(".lisp-rs-learn");
+    let mut progress = match restart {
+        true => Progress::default(),
+        false => Progress::load(&path),
+    };
+    if progress.is_finished() {
+        println!("You have taken every lesson; `lisp-rs learn --restart` starts again.");
+        return 0;
+    }
+
+    match tutorial::run(
+        editor().as_mut(),
+        &mut io::stdout(),
+        &mut progress,
+        Some(&path),
+    ) {
+        Ok(code) => code.unwrap_or(0),
+        Err(e) => {
+            eprintln!("learn: {}", e);
+            1
+        }
+    }
+}
+
+/// `lisp-rs codegen SCHEMA [-o OUTPUT]` writes the Rust types for the
+/// definitions in a schema to OUTPUT, or prints them.
+fn generate(args: &[String]) -> i32 {
+    let (schema_path, output) = match args {
+        [schema] => (schema, None),
+        [schema, flag, output] if flag == "-o" => (schema, Some(output)),
+        _ => {
+            eprintln!("usage: lisp-rs codegen SCHEMA [-o OUTPUT]");
+            return 2;
+        }
+    };
+
+    let schema = match fs::read_to_string(schema_path) {
+        Ok(schema) => schema,
+        Err(e) => {
+            eprintln!("cannot read {}: {}", schema_path, e);
+            return 1;
+        }
+    };
+    let code = match codegen::generate(&schema) {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("{}: {}", schema_path, e.message());
+            return 1;
+        }
+    };
+
+    match output {
+        Some(output) => match fs::write(output, code) {
+            Ok(()) => 0,
+            Err(e) => {
+                eprintln!("cannot write {}: {}", output, e);
+                1
+            }
+        },
+        None => {
+            print!("{}", code);
+            0
+        }
+    }
+}
+
+/// `lisp-rs serve [--port N]` runs an eval server on localhost, port 7888
+/// unless given.
+fn serve(args: &[String]) -> i32 {
+    let port = match args {
+        [] => DEFAULT_PORT,
+        [flag, port] if flag == "--port" => match port.parse::<u16>() {
+            Ok(port) => port,
+            Err(_) => {
+                eprintln!("invalid port: {}", port);
+                return 2;
+            }
+        },
+        _ => {
+            eprintln!("usage: lisp-rs serve [--port N]");
+            return 2;
+        }
+    };
+
+    let listener = match TcpListener::bind(("127.0.0.1", port)) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("cannot listen on port {}: {}", port, e);
+            return 1;
+        }
+    };
+    eprintln!("listening on 127.0.0.1:{}", port);
+
+    let env = global_env();
+    define_args(&env, &[]);
+    server::serve(listener, &env)
+}
+
+/// `lisp-rs lsp` serves the Language Server Protocol over stdin and stdout.
+fn language_server() -> i32 {
+    let stdin = io::stdin();
+    match lsp::run(stdin.lock(), io::stdout()) {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("lisp-rs lsp: {}", e);
+            1
+        }
+    }
+}
+
+/// `lisp-rs dap [--port N]` serves the Debug Adapter Protocol over stdin
+/// and stdout, or over one connection to a port on localhost if given.
+fn debug_adapter(args: &[String]) -> i32 {
+    let result = match args {
+        [] => dap::run(io::stdin().lock(), io::stdout()),
+        [flag, port] if flag == "--port" => {
+            let port = match port.parse::<u16>() {
+                Ok(port) => port,
+                Err(_) => {
+                    eprintln!("invalid port: {}", port);
+                    return 2;
+                }
+            };
+            let listener = match TcpListener::bind(("127.0.0.1", port)) {
+                Ok(listener) => listener,
+                Err(e) => {
+                    eprintln!("cannot listen on port {}: {}", port, e);
+                    return 1;
+                }
+            };
+            eprintln!("listening on 127.0.0.1:{}", port);
+
+            listener
+                .accept()
+                .and_then(|(stream, _)| Ok((BufReader::new(stream.try_clone()?), stream)))
+                .and_then(|(input, output)| dap::run(input, output))
+        }
+        _ => {
+            eprintln!("usage: lisp-rs dap [--port N]");
+            return 2;
+        }
+    };
+
+    match result {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("lisp-rs dap: {}", e);
+            1
+        }
+    }
+}
+
+fn define_args(env: &Rc<RefCell<Env>>, args: &[String]) {
+    let args = Object::list(args.iter().cloned().map(Object::string).collect::<Vec<_>>());
+    env.borrow_mut().define("*args*", taint::mark(args));
+}
+
+fn repl() {
+    let env = global_env();
+    define_args(&env, &[]);
+
+    if let Some(code) = repl::run(editor().as_mut(), &env) {
+        process::exit(code);
+    }
+}
+
+#[cfg(feature = "rustyline")]
+fn editor() -> Box<dyn LineEditor> {
+    match RustylineEditor::new() {
+        Ok(editor) => Box::new(editor),
+        Err(_) => Box::new(BasicEditor::stdio()),
+    }
+}
+
+#[cfg(not(feature = "rustyline"))]
+fn editor() -> Box<dyn LineEditor> {
+    Box::new(BasicEditor::stdio())
 }
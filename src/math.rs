@@ -0,0 +1,407 @@
+//! Numeric functions beyond arithmetic: `abs`, `min` and `max`, rounding,
+//! integer division, square roots and the functions of `f64`, and random
+//! numbers.
+//!
+//! Exact arguments give exact results where they can: `(floor 7/2)` is 3,
+//! `(sqrt 16)` is 4, while `(sqrt 2)` and `(floor 3.5)` are floats. Of the
+//! integer divisions, `remainder` has the sign of the dividend and `modulo`
+//! that of the divisor: `(remainder -7 2)` is -1 and `(modulo -7 2)` is 1.
+//!
+//! `(random n)` is an integer below `n`, or a float if `n` is one, from a
+//! generator seeded at random unless `(set-random-seed! n)` seeds it, for a
+//! sequence that can be repeated.
+
+use std::cell::Cell;
+
+use crate::bigint::BigInt;
+use crate::builtins::{check_arity, numbers, to_f64, Numbers};
+use crate::eval::EvalError;
+use crate::object::Object;
+use crate::rational::Rational;
+
+fn expected_number(name: &str, obj: &Object) -> EvalError {
+    EvalError::new(format!(
+        "{} expects a number, got {}",
+        name,
+        obj.type_name()
+    ))
+}
+
+fn float_arg(name: &str, obj: &Object) -> Result<f64, EvalError> {
+    to_f64(obj).ok_or_else(|| expected_number(name, obj))
+}
+
+pub fn abs(args: &[Object]) -> Result<Object, EvalError> {
+    check_arity("abs", args, 1)?;
+
+    Ok(match &args[0] {
+        Object::Integer(n) => match n.checked_abs() {
+            Some(n) => Object::Integer(n),
+            None => Object::from_bigint(BigInt::from_i64(*n).abs()),
+        },
+        Object::BigInt(n) => Object::from_bigint(n.abs()),
+        Object::Rational(n) if n.numerator().is_negative() => Object::from_rational(-&**n),
+        n @ Object::Rational(_) => n.clone(),
+        Object::Float(n) => Object::Float(n.abs()),
+        other => return Err(expected_number("abs", other)),
+    })
+}
+
+fn less(name: &str, a: &Object, b: &Object) -> Result<bool, EvalError> {
+    Ok(match numbers(name, &[a.clone(), b.clone()])? {
+        Numbers::Integers(a, b) => a < b,
+        Numbers::BigInts(a, b) => a < b,
+        Numbers::Rationals(a, b) => a < b,
+        Numbers::Floats(a, b) => a < b,
+    })
+}
+
+/// The argument that `replaces(best, arg)` never replaces, inexact if any
+/// argument is.
+fn extremum(name: &str, args: &[Object], replaces: fn(bool) -> bool) -> Result<Object, EvalError> {
+    let (first, rest) = args
+        .split_first()
+        .ok_or_else(|| EvalError::new(format!("{} expects at least one number", name)))?;
+    float_arg(name, first)?;
+
+    let mut best = first;
+    for arg in rest {
+        if replaces(less(name, arg, best)?) {
+            best = arg;
+        }
+    }
+
+    match args.iter().any(|arg| matches!(arg, Object::Float(_))) {
+        true => float_arg(name, best).map(Object::Float),
+        false => Ok(best.clone()),
+    }
+}
+
+pub fn min(args: &[Object]) -> Result<Object, EvalError> {
+    extremum("min", args, |less| less)
+}
+
+pub fn max(args: &[Object]) -> Result<Object, EvalError> {
+    extremum("max", args, |less| !less)
+}
+
+#[derive(Clone, Copy)]
+enum Rounding {
+    Floor,
+    Ceiling,
+    Round,
+    Truncate,
+}
+
+fn round_with(name: &str, args: &[Object], rounding: Rounding) -> Result<Object, EvalError> {
+    check_arity(name, args, 1)?;
+
+    let n = match &args[0] {
+        n @ (Object::Integer(_) | Object::BigInt(_)) => return Ok(n.clone()),
+        Object::Float(n) => {
+            return Ok(Object::Float(match rounding {
+                Rounding::Floor => n.floor(),
+                Rounding::Ceiling => n.ceil(),
+                Rounding::Round => n.round_ties_even(),
+                Rounding::Truncate => n.trunc(),
+            }))
+        }
+        Object::Rational(n) => n,
+        other => return Err(expected_number(name, other)),
+    };
+
+    // The denominator is positive, and the remainder has the sign of the
+    // numerator.
+    let (quotient, remainder) = n
+        .numerator()
+        .div_rem(n.denominator())
+        .ok_or_else(|| EvalError::new("division by zero"))?;
+    let one = BigInt::from_i64(if n.numerator().is_negative() { -1 } else { 1 });
+    let away = match rounding {
+        Rounding::Floor => remainder.is_negative(),
+        Rounding::Ceiling => !remainder.is_negative(),
+        Rounding::Truncate => false,
+        Rounding::Round => {
+            let twice = &remainder.abs() + &remainder.abs();
+            match twice.cmp(n.denominator()) {
+                std::cmp::Ordering::Greater => true,
+                std::cmp::Ordering::Less => false,
+                // A tie goes to the even neighbor.
+                std::cmp::Ordering::Equal => quotient
+                    .div_rem(&BigInt::from_i64(2))
+                    .is_some_and(|(_, r)| !r.is_zero()),
+            }
+        }
+    };
+
+    Ok(Object::from_bigint(match away {
+        true => &quotient + &one,
+        false => quotient,
+    }))
+}
+
+pub fn floor(args: &[Object]) -> Result<Object, EvalError> {
+    round_with("floor", args, Rounding::Floor)
+}
+
+pub fn ceiling(args: &[Object]) -> Result<Object, EvalError> {
+    round_with("ceiling", args, Rounding::Ceiling)
+}
+
+pub fn round(args: &[Object]) -> Result<Object, EvalError> {
+    round_with("round", args, Rounding::Round)
+}
+
+pub fn truncate(args: &[Object]) -> Result<Object, EvalError> {
+    round_with("truncate", args, Rounding::Truncate)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Division {
+    Quotient,
+    Remainder,
+    Modulo,
+}
+
+fn divide(name: &str, args: &[Object], division: Division) -> Result<Object, EvalError> {
+    let zero = || EvalError::new("division by zero");
+    match numbers(name, args)? {
+        Numbers::Integers(_, 0) => Err(zero()),
+        Numbers::Integers(a, b) => Ok(match division {
+            Division::Quotient => match a.checked_div(b) {
+                Some(n) => Object::Integer(n),
+                None => Object::from_bigint(-&BigInt::from_i64(a)),
+            },
+            // Only `i64::MIN` by -1 overflows, leaving nothing.
+            Division::Remainder => Object::Integer(a.checked_rem(b).unwrap_or(0)),
+            Division::Modulo => {
+                let r = a.checked_rem(b).unwrap_or(0);
+                Object::Integer(match r != 0 && (r < 0) != (b < 0) {
+                    true => r + b,
+                    false => r,
+                })
+            }
+        }),
+        Numbers::BigInts(a, b) => {
+            let (quotient, remainder) = a.div_rem(&b).ok_or_else(zero)?;
+            Ok(Object::from_bigint(match division {
+                Division::Quotient => quotient,
+                Division::Remainder => remainder,
+                Division::Modulo
+                    if !remainder.is_zero() && remainder.is_negative() != b.is_negative() =>
+                {
+                    &remainder + &b
+                }
+                Division::Modulo => remainder,
+            }))
+        }
+        _ => Err(EvalError::new(format!(
+            "{} expects integers, got {} and {}",
+            name,
+            args[0].type_name(),
+            args[1].type_name()
+        ))),
+    }
+}
+
+pub fn quotient(args: &[Object]) -> Result<Object, EvalError> {
+    divide("quotient", args, Division::Quotient)
+}
+
+pub fn remainder(args: &[Object]) -> Result<Object, EvalError> {
+    divide("remainder", args, Division::Remainder)
+}
+
+pub fn modulo(args: &[Object]) -> Result<Object, EvalError> {
+    divide("modulo", args, Division::Modulo)
+}
+
+/// `(sqrt n)`, exact for exact squares.
+pub fn sqrt(args: &[Object]) -> Result<Object, EvalError> {
+    check_arity("sqrt", args, 1)?;
+
+    let exact = |n: &BigInt| {
+        let root = BigInt::from_i64(n.to_i64()?.checked_isqrt()?);
+        (&root * &root == *n).then_some(root)
+    };
+    if let Object::Integer(n) = &args[0] {
+        if let Some(root) = exact(&BigInt::from_i64(*n)) {
+            return Ok(Object::from_bigint(root));
+        }
+    }
+    if let Object::Rational(n) = &args[0] {
+        if let (Some(num), Some(den)) = (exact(n.numerator()), exact(n.denominator())) {
+            if let Some(root) = Rational::new(num, den) {
+                return Ok(Object::from_rational(root));
+            }
+        }
+    }
+
+    float_arg("sqrt", &args[0]).map(|n| Object::Float(n.sqrt()))
+}
+
+/// Defines a builtin applying an `f64` function to one number.
+macro_rules! float_function {
+    ($name:ident, $symbol:literal) => {
+        pub fn $name(args: &[Object]) -> Result<Object, EvalError> {
+            check_arity($symbol, args, 1)?;
+            float_arg($symbol, &args[0]).map(|n| Object::Float(n.$name()))
+        }
+    };
+}
+
+float_function!(exp, "exp");
+float_function!(sin, "sin");
+float_function!(cos, "cos");
+float_function!(tan, "tan");
+float_function!(asin, "asin");
+float_function!(acos, "acos");
+
+/// `(log n)`, or `(log n base)`.
+pub fn log(args: &[Object]) -> Result<Object, EvalError> {
+    match args {
+        [n] => Ok(Object::Float(float_arg("log", n)?.ln())),
+        [n, base] => Ok(Object::Float(
+            float_arg("log", n)?.log(float_arg("log", base)?),
+        )),
+        _ => Err(EvalError::new("log expects a number and an optional base")),
+    }
+}
+
+/// `(atan y)`, or `(atan y x)` for the angle of the point `(x, y)`.
+pub fn atan(args: &[Object]) -> Result<Object, EvalError> {
+    match args {
+        [y] => Ok(Object::Float(float_arg("atan", y)?.atan())),
+        [y, x] => Ok(Object::Float(
+            float_arg("atan", y)?.atan2(float_arg("atan", x)?),
+        )),
+        _ => Err(EvalError::new("atan expects one or two numbers")),
+    }
+}
+
+thread_local! {
+    /// The state of the random number generator, once it is seeded.
+    static SEED: Cell<Option<u64>> = const { Cell::new(None) };
+}
+
+/// The next number from a splitmix64 generator.
+fn next_random() -> Result<u64, EvalError> {
+    let state = match SEED.with(Cell::get) {
+        Some(state) => state,
+        None => {
+            let mut bytes = [0; 8];
+            getrandom::fill(&mut bytes)
+                .map_err(|e| EvalError::new(format!("cannot get random bytes: {}", e)))?;
+            u64::from_le_bytes(bytes)
+        }
+    };
+    let state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    SEED.with(|seed| seed.set(Some(state)));
+
+    let mut z = state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    Ok(z ^ (z >> 31))
+}
+
+/// `(random n)` is an integer from 0 to below `n`, or a float if `n` is.
+pub fn random(args: &[Object]) -> Result<Object, EvalError> {
+    match args {
+        [Object::Integer(n)] if *n > 0 => {
+            let n = *n as u64;
+            // Numbers past the last whole multiple of `n` would favor the
+            // small results, and are drawn again.
+            let limit = u64::MAX - u64::MAX % n;
+            loop {
+                let r = next_random()?;
+                if r < limit {
+                    return Ok(Object::Integer((r % n) as i64));
+                }
+            }
+        }
+        [Object::Float(n)] if *n > 0.0 => {
+            let unit = (next_random()? >> 11) as f64 / (1u64 << 53) as f64;
+            Ok(Object::Float(unit * n))
+        }
+        _ => Err(EvalError::new("random expects a positive integer or float")),
+    }
+}
+
+pub fn set_random_seed(args: &[Object]) -> Result<Object, EvalError> {
+    match args {
+        [Object::Integer(seed)] => {
+            SEED.with(|state| state.set(Some(*seed as u64)));
+            Ok(Object::Void)
+        }
+        _ => Err(EvalError::new("set-random-seed! expects an integer")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builtins::global_env;
+    use crate::eval::eval_str;
+
+    fn eval(program: &str) -> String {
+        match eval_str(program, &global_env()) {
+            Ok(value) => value.to_string(),
+            Err(e) => e.message().to_string(),
+        }
+    }
+
+    #[test]
+    fn test_exact_results() {
+        assert_eq!(
+            eval("(list (abs -5) (abs -1/2) (abs -2.5) (min 3 1 2) (max 1 2.0) (sqrt 16) (sqrt 4/9))"),
+            "(5 1/2 2.5 1 2.0 4 2/3)"
+        );
+        assert_eq!(
+            eval("(list (floor 7/2) (ceiling 7/2) (round 7/2) (round 5/2) (truncate -7/2) (floor -7/2))"),
+            "(3 4 4 2 -3 -4)"
+        );
+        assert_eq!(
+            eval("(list (floor 3.5) (round 2.5) (sqrt 2.25) (abs -9223372036854775808))"),
+            "(3.0 2.0 1.5 9223372036854775808)"
+        );
+        assert_eq!(eval("(min 'a 1)"), "min expects a number, got symbol");
+    }
+
+    #[test]
+    fn test_integer_division() {
+        assert_eq!(
+            eval(
+                "(list (quotient -7 2) (remainder -7 2) (modulo -7 2) (modulo 7 -2) (modulo 6 3))"
+            ),
+            "(-3 -1 1 -1 0)"
+        );
+        assert_eq!(
+            eval("(list (remainder 100000000000000000000 -3) (modulo 100000000000000000000 -3))"),
+            "(1 -2)"
+        );
+        assert_eq!(eval("(modulo 1 0)"), "division by zero");
+        assert_eq!(
+            eval("(modulo 1.5 2)"),
+            "modulo expects integers, got float and integer"
+        );
+    }
+
+    #[test]
+    fn test_random() {
+        let env = global_env();
+        let draws = "(list (random 100) (random 100) (random 1.0))";
+        eval_str("(set-random-seed! 42)", &env).unwrap();
+        let first = eval_str(draws, &env).unwrap();
+        eval_str("(set-random-seed! 42)", &env).unwrap();
+        assert_eq!(eval_str(draws, &env).unwrap(), first);
+
+        for _ in 0..100 {
+            let n = eval_str("(random 3)", &env).unwrap().to_string();
+            assert!(["0", "1", "2"].contains(&n.as_str()));
+        }
+        assert!(eval_str("(random 0)", &env).is_err());
+        assert_eq!(
+            eval("(list (exp 0) (sin 0) (cos 0) (log 8 2) (atan 1 1))"),
+            format!("(1.0 0.0 1.0 3.0 {:?})", std::f64::consts::FRAC_PI_4)
+        );
+    }
+}
@@ -0,0 +1,208 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::rc::Rc;
+
+use crate::eval::{apply, EvalError};
+use crate::gc::{self, Edge, Trace};
+use crate::object::Object;
+
+/// A procedure that caches its results by argument list. With a capacity
+/// the cache keeps only the most recently used entries.
+pub struct Memo {
+    func: Object,
+    cache: RefCell<Cache>,
+}
+
+struct Cache {
+    capacity: Option<usize>,
+    entries: HashMap<Key, (Object, u64)>,
+    recency: BTreeMap<u64, Key>,
+    clock: u64,
+}
+
+/// An argument list used as a cache key. Equality is `Object` equality, so
+/// procedures and containers are compared (and hashed) by identity.
+#[derive(Clone, PartialEq, Hash)]
+struct Key(Vec<Object>);
+
+impl Eq for Key {}
+
+impl Memo {
+    /// The procedure whose results are remembered.
+    pub(crate) fn func(&self) -> &Object {
+        &self.func
+    }
+
+    pub fn new(func: Object, capacity: Option<usize>) -> Self {
+        Self {
+            func,
+            cache: RefCell::new(Cache {
+                capacity,
+                entries: HashMap::new(),
+                recency: BTreeMap::new(),
+                clock: 0,
+            }),
+        }
+    }
+
+    pub fn call(&self, args: Vec<Object>) -> Result<Object, EvalError> {
+        let key = Key(args);
+        if let Some(value) = self.cache.borrow_mut().get(&key) {
+            return Ok(value);
+        }
+
+        // The cache is not borrowed while the function runs, so recursive
+        // calls through the memoized procedure can fill it in.
+        let value = apply(&self.func, key.0.clone())?;
+        self.cache.borrow_mut().insert(key, value.clone());
+
+        Ok(value)
+    }
+}
+
+impl Memo {
+    /// Wraps `func` in a memoizing procedure known to the collector.
+    pub fn shared(func: Object, capacity: Option<usize>) -> Rc<Memo> {
+        let memo = Rc::new(Memo::new(func, capacity));
+        gc::track(&memo);
+        memo
+    }
+
+    pub(crate) fn clear_cache(&self) {
+        if let Ok(mut cache) = self.cache.try_borrow_mut() {
+            cache.entries.clear();
+            cache.recency.clear();
+        }
+    }
+}
+
+impl Trace for Memo {
+    fn trace(&self, edge: &mut dyn FnMut(Edge)) {
+        edge(Edge::Object(&self.func));
+        if let Ok(cache) = self.cache.try_borrow() {
+            for (key, (value, _)) in &cache.entries {
+                key.0.iter().for_each(|arg| edge(Edge::Object(arg)));
+                edge(Edge::Object(value));
+            }
+        }
+    }
+}
+
+impl Cache {
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    fn get(&mut self, key: &Key) -> Option<Object> {
+        let now = self.tick();
+        let (value, used) = self.entries.get_mut(key)?;
+
+        let previous = std::mem::replace(used, now);
+        let value = value.clone();
+        if let Some(key) = self.recency.remove(&previous) {
+            self.recency.insert(now, key);
+        }
+
+        Some(value)
+    }
+
+    fn insert(&mut self, key: Key, value: Object) {
+        if self.capacity == Some(0) {
+            return;
+        }
+
+        let now = self.tick();
+        if let Some((_, previous)) = self.entries.insert(key.clone(), (value, now)) {
+            self.recency.remove(&previous);
+        }
+        self.recency.insert(now, key);
+
+        if self
+            .capacity
+            .is_some_and(|capacity| self.entries.len() > capacity)
+        {
+            if let Some((_, oldest)) = self.recency.pop_first() {
+                self.entries.remove(&oldest);
+            }
+        }
+    }
+}
+
+/// `(memoize f)` caches every result of `f`; `(memoize f n)` keeps only the
+/// `n` most recently used results.
+pub fn memoize(args: &[Object]) -> Result<Object, EvalError> {
+    let (func, capacity) = match args {
+        [func @ (Object::Lambda(_)
+        | Object::Builtin(_)
+        | Object::Native(_)
+        | Object::Memo(_)
+        | Object::Closure(_))] => (func, None),
+        [func @ (Object::Lambda(_)
+        | Object::Builtin(_)
+        | Object::Native(_)
+        | Object::Memo(_)
+        | Object::Closure(_)), Object::Integer(n)]
+            if *n >= 0 =>
+        {
+            (func, Some(*n as usize))
+        }
+        _ => {
+            return Err(EvalError::new(
+                "memoize expects a procedure and an optional cache size",
+            ))
+        }
+    };
+
+    Ok(Object::Memo(Memo::shared(func.clone(), capacity)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builtins::global_env;
+    use crate::eval::eval_str;
+    use crate::object::Object;
+
+    #[test]
+    fn test_memoize_caches_results() {
+        let env = global_env();
+        let result = eval_str(
+            "(define calls 0)
+             (define square (memoize (lambda (x) (set! calls (+ calls 1)) (* x x))))
+             (list (square 3) (square 3) (square 4) calls)",
+            &env,
+        )
+        .unwrap();
+
+        assert_eq!(result.to_string(), "(9 9 16 2)");
+    }
+
+    #[test]
+    fn test_bounded_cache_evicts_least_recently_used() {
+        let env = global_env();
+        let result = eval_str(
+            "(define calls 0)
+             (define id (memoize (lambda (x) (set! calls (+ calls 1)) x) 2))
+             (id 1) (id 2) (id 1) (id 3) (id 1) (id 2)
+             calls",
+            &env,
+        )
+        .unwrap();
+
+        assert_eq!(result, Object::Integer(4));
+    }
+
+    #[test]
+    fn test_define_memoized_recursion() {
+        let env = global_env();
+        let result = eval_str(
+            "(define-memoized (fib n)
+               (if (< n 2) n (+ (fib (- n 1)) (fib (- n 2)))))
+             (fib 90)",
+            &env,
+        )
+        .unwrap();
+
+        assert_eq!(result, Object::Integer(2880067194370816120));
+    }
+}
@@ -0,0 +1,638 @@
+//! `load`, `require` and `provide`, and the loaders that find modules.
+//!
+//! Module names are looked up with every registered `Loader` in turn, and
+//! then on the filesystem. Applications register loaders to serve modules
+//! from elsewhere: `EmbeddedLoader` serves sources compiled into the
+//! binary with `include_str!`, and a loader of their own can fetch them
+//! over HTTPS or out of an archive.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::rc::{Rc, Weak};
+
+use crate::builtins::global_env;
+use crate::env::Env;
+use crate::eval::{eval_named, EvalError};
+use crate::object::{Object, Pair};
+use crate::symbol::Symbol;
+use crate::taint;
+
+type Exports = Rc<Vec<(Symbol, Object)>>;
+
+/// A source of modules other than the filesystem.
+pub trait Loader {
+    /// The path identifying the module `path` names, if this loader has
+    /// it. `path` is already joined to the directory of the module loading
+    /// it, as it would be on the filesystem.
+    fn find(&self, path: &Path) -> Option<PathBuf>;
+
+    /// The source of a module `find` found.
+    fn read(&self, path: &Path) -> Result<String, EvalError>;
+}
+
+/// Serves modules from sources built into the program, such as a standard
+/// library bundled with `include_str!`.
+#[derive(Default)]
+pub struct EmbeddedLoader {
+    sources: HashMap<PathBuf, &'static str>,
+}
+
+impl EmbeddedLoader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the module at `path`, such as `"std/list.lisp"`.
+    pub fn with(mut self, path: impl AsRef<Path>, source: &'static str) -> Self {
+        self.sources.insert(normalize(path.as_ref()), source);
+        self
+    }
+}
+
+impl Loader for EmbeddedLoader {
+    fn find(&self, path: &Path) -> Option<PathBuf> {
+        let path = normalize(path);
+        self.sources.contains_key(&path).then_some(path)
+    }
+
+    fn read(&self, path: &Path) -> Result<String, EvalError> {
+        match self.sources.get(path) {
+            Some(source) => Ok(source.to_string()),
+            None => Err(EvalError::new(format!("cannot read {}", path.display()))),
+        }
+    }
+}
+
+/// `path` without `.` components, and with `..` removing the component
+/// before it.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normal = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normal.pop();
+            }
+            component => normal.push(component),
+        }
+    }
+
+    normal
+}
+
+struct Loading {
+    path: PathBuf,
+    provides: Vec<Symbol>,
+}
+
+/// Bookkeeping shared by `load`, `require` and `provide`: the files currently
+/// being evaluated (innermost last), the exports of every module that
+/// finished loading and the environments that required each one, for
+/// `reload-module` to update.
+#[derive(Default)]
+struct Modules {
+    loaders: Vec<Rc<dyn Loader>>,
+    loading: Vec<Loading>,
+    loaded: HashMap<PathBuf, Exports>,
+    importers: HashMap<PathBuf, Vec<Weak<RefCell<Env>>>>,
+    /// The directory of the file each `embed-file` form read from one is
+    /// in, by the address of the form.
+    embeds: HashMap<usize, (Weak<Pair>, PathBuf)>,
+}
+
+thread_local! {
+    static MODULES: RefCell<Modules> = RefCell::new(Modules::default());
+}
+
+/// Registers a loader, tried after those registered before it and before
+/// the filesystem.
+pub fn add_loader(loader: Rc<dyn Loader>) {
+    MODULES.with(|modules| modules.borrow_mut().loaders.push(loader));
+}
+
+/// Evaluates every form of a file in `env`, as if it had been typed there.
+pub fn load(path: &Object, env: &Rc<RefCell<Env>>) -> Result<Object, EvalError> {
+    taint::check("load", path)?;
+    let found = find(path, None)?;
+    let source = found.read()?;
+
+    let origin = found.path.display().to_string();
+    in_file(&found.path, || eval_named(&source, &origin, env))
+}
+
+/// Runs `f`, which evaluates the file at `path`, with that file as the one
+/// being loaded, so that the relative paths it loads and requires are
+/// looked up next to it rather than in the working directory.
+pub fn in_file<T>(
+    path: impl AsRef<Path>,
+    f: impl FnOnce() -> Result<T, EvalError>,
+) -> Result<T, EvalError> {
+    with_loading(path.as_ref().to_path_buf(), f).map(|(result, _)| result)
+}
+
+/// Evaluates a module file in a fresh namespace (once per path) and binds the
+/// symbols it `provide`s into `env`.
+pub fn require(path: &Object, env: &Rc<RefCell<Env>>) -> Result<Object, EvalError> {
+    taint::check("require", path)?;
+    let found = find(path, Some("require"))?;
+    let path = found.path.clone();
+
+    let exports = match MODULES.with(|modules| modules.borrow().loaded.get(&path).cloned()) {
+        Some(exports) => exports,
+        None => {
+            check_cycle(&path)?;
+            let exports = load_module(&found)?;
+            MODULES.with(|modules| {
+                modules
+                    .borrow_mut()
+                    .loaded
+                    .insert(path.clone(), exports.clone())
+            });
+            exports
+        }
+    };
+
+    MODULES.with(|modules| {
+        let mut modules = modules.borrow_mut();
+        let importers = modules.importers.entry(path).or_default();
+        if !importers
+            .iter()
+            .any(|importer| importer.as_ptr() == Rc::as_ptr(env))
+        {
+            importers.push(Rc::downgrade(env));
+        }
+    });
+
+    bind(&exports, env);
+
+    Ok(Object::Void)
+}
+
+/// `(reload-module name)` evaluates a required module again and rebinds its
+/// exports wherever it was required, so code calling them through global
+/// names picks up the new definitions. If the module fails to load, nothing
+/// changes. Modules it requires are not reloaded, and names it stopped
+/// providing keep their old values.
+pub fn reload_module(args: &[Object]) -> Result<Object, EvalError> {
+    let path = match args {
+        [path] => path,
+        _ => return Err(EvalError::new("reload-module expects a module name")),
+    };
+    taint::check("reload-module", path)?;
+    let found = find(path, Some("reload"))?;
+    let path = found.path.clone();
+
+    if MODULES.with(|modules| !modules.borrow().loaded.contains_key(&path)) {
+        return Err(EvalError::new(format!(
+            "cannot reload {}: it was never required",
+            path.display()
+        )));
+    }
+
+    check_cycle(&path)?;
+    let exports = load_module(&found)?;
+
+    let importers = MODULES.with(|modules| {
+        let mut modules = modules.borrow_mut();
+        modules.loaded.insert(path.clone(), exports.clone());
+
+        let importers = modules.importers.entry(path).or_default();
+        importers.retain(|importer| importer.strong_count() > 0);
+        importers.clone()
+    });
+
+    for env in importers.iter().filter_map(Weak::upgrade) {
+        bind(&exports, &env);
+    }
+
+    Ok(Object::Void)
+}
+
+fn bind(exports: &Exports, env: &Rc<RefCell<Env>>) {
+    let mut env = env.borrow_mut();
+    for (name, value) in exports.iter() {
+        env.define(*name, value.clone());
+    }
+}
+
+/// `(embed-file "path")` is the contents of a file, read when the form is
+/// evaluated or, by `compiler::embed_files`, when the program is compiled.
+/// The path is looked up the way `load` looks it up, but next to the file
+/// containing the form even when it is evaluated after that file is
+/// loaded, in a procedure called from elsewhere.
+pub fn embed_file(form: &Object, list: &[Object]) -> Result<Object, EvalError> {
+    let path = match list {
+        [_, path @ Object::String(_)] => path,
+        _ => return Err(EvalError::new("embed-file expects a literal path string")),
+    };
+    let dir = match form {
+        Object::Pair(pair) => MODULES.with(|modules| {
+            match modules.borrow().embeds.get(&(Rc::as_ptr(pair) as usize)) {
+                Some((form, dir)) if form.strong_count() > 0 => Some(dir.clone()),
+                _ => None,
+            }
+        }),
+        _ => None,
+    };
+    let found = find_from(path, dir, None)?;
+
+    found.read().map(Object::string)
+}
+
+/// Notes the directory of the file being loaded for the `embed-file` forms
+/// in `forms`, read from it, for `embed_file` to look their paths up in.
+pub(crate) fn note_embeds(forms: &[Object]) {
+    let Some(dir) = current_dir() else {
+        return;
+    };
+
+    let mut embeds = Vec::new();
+    let mut pending = forms.to_vec();
+    while let Some(form) = pending.pop() {
+        let Some(list) = form.to_vec() else {
+            continue;
+        };
+        match (&form, list.first()) {
+            (_, Some(Object::Symbol(head))) if *head == "quote" => {}
+            (Object::Pair(pair), Some(Object::Symbol(head))) if *head == "embed-file" => {
+                embeds.push((Rc::as_ptr(pair) as usize, Rc::downgrade(pair)));
+            }
+            _ => pending.extend(list),
+        }
+    }
+    if embeds.is_empty() {
+        return;
+    }
+
+    MODULES.with(|modules| {
+        let table = &mut modules.borrow_mut().embeds;
+        table.retain(|_, (form, _)| form.strong_count() > 0);
+        for (address, form) in embeds {
+            table.insert(address, (form, dir.clone()));
+        }
+    });
+}
+
+/// Marks symbols of the module currently being required as exported.
+pub fn provide(names: &[Object]) -> Result<Object, EvalError> {
+    let names = names
+        .iter()
+        .map(|name| match name {
+            Object::Symbol(name) => Ok(*name),
+            other => Err(EvalError::new(format!(
+                "provide expects symbols, got {}",
+                other
+            ))),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    MODULES.with(|modules| match modules.borrow_mut().loading.last_mut() {
+        Some(loading) => {
+            loading.provides.extend(names);
+            Ok(Object::Void)
+        }
+        None => Err(EvalError::new("provide used outside of a module")),
+    })
+}
+
+fn load_module(found: &Found) -> Result<Exports, EvalError> {
+    let path = &found.path;
+    let source = found.read()?;
+    let env = global_env();
+    let origin = path.display().to_string();
+    let (_, provides) = with_loading(path.to_path_buf(), || eval_named(&source, &origin, &env))?;
+
+    let env = env.borrow();
+    let exports = provides
+        .into_iter()
+        .map(|name| match env.get(name) {
+            Some(value) => Ok((name, value)),
+            None => Err(EvalError::new(format!(
+                "{} provides undefined symbol {}",
+                path.display(),
+                name
+            ))),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(Rc::new(exports))
+}
+
+/// Runs `f` with `path` pushed on the loading stack, returning its result and
+/// the symbols provided while it ran.
+fn with_loading<T, F>(path: PathBuf, f: F) -> Result<(T, Vec<Symbol>), EvalError>
+where
+    F: FnOnce() -> Result<T, EvalError>,
+{
+    MODULES.with(|modules| {
+        modules.borrow_mut().loading.push(Loading {
+            path,
+            provides: Vec::new(),
+        })
+    });
+
+    let result = f();
+    let loading = MODULES.with(|modules| modules.borrow_mut().loading.pop());
+
+    result.map(|result| (result, loading.map(|l| l.provides).unwrap_or_default()))
+}
+
+fn check_cycle(path: &Path) -> Result<(), EvalError> {
+    MODULES.with(|modules| {
+        let modules = modules.borrow();
+        let start = match modules.loading.iter().position(|l| l.path == path) {
+            Some(start) => start,
+            None => return Ok(()),
+        };
+
+        let cycle = modules.loading[start..]
+            .iter()
+            .map(|l| l.path.display().to_string())
+            .chain(std::iter::once(path.display().to_string()))
+            .collect::<Vec<_>>();
+
+        Err(EvalError::new(format!(
+            "cyclic require: {}",
+            cycle.join(" -> ")
+        )))
+    })
+}
+
+/// The directory of the file currently being loaded, if any.
+fn current_dir() -> Option<PathBuf> {
+    MODULES.with(|modules| {
+        modules
+            .borrow()
+            .loading
+            .last()
+            .and_then(|l| l.path.parent().map(Path::to_path_buf))
+    })
+}
+
+/// Turns the argument of `load`/`require` into a path. Strings are used as
+/// given, a symbol `foo` names the file `foo.lisp` and a list of symbols
+/// `(my lib)` names `my/lib.lisp`. Relative paths are looked up in `dir`,
+/// or else next to the file currently being loaded, if any.
+fn resolve(path: &Object, dir: Option<PathBuf>) -> Result<PathBuf, EvalError> {
+    let expected = || {
+        EvalError::new(format!(
+            "expected a path string, symbol or list of symbols, got {}",
+            path
+        ))
+    };
+
+    let path = match path {
+        Object::String(path) => PathBuf::from(path.as_str()),
+        Object::Symbol(name) => PathBuf::from(format!("{}.lisp", name)),
+        Object::Pair(_) => {
+            let mut parts = path
+                .to_vec()
+                .ok_or_else(expected)?
+                .into_iter()
+                .map(|part| match part {
+                    Object::Symbol(name) => Ok(name.to_string()),
+                    _ => Err(expected()),
+                })
+                .collect::<Result<PathBuf, _>>()?;
+            parts.set_extension("lisp");
+            parts
+        }
+        _ => return Err(expected()),
+    };
+
+    if path.is_absolute() {
+        return Ok(path);
+    }
+
+    Ok(match dir.or_else(current_dir) {
+        Some(base) => base.join(path),
+        None => path,
+    })
+}
+
+/// A module found by a loader, or on the filesystem when `loader` is
+/// `None`.
+struct Found {
+    path: PathBuf,
+    loader: Option<Rc<dyn Loader>>,
+}
+
+impl Found {
+    fn read(&self) -> Result<String, EvalError> {
+        match &self.loader {
+            Some(loader) => loader.read(&self.path),
+            None => fs::read_to_string(&self.path)
+                .map_err(|e| EvalError::new(format!("cannot read {}: {}", self.path.display(), e))),
+        }
+    }
+}
+
+/// Finds the module `path` names. Given a `verb`, a module found on the
+/// filesystem is identified by its canonical path, so that every name for
+/// it means the same module, and an error saying it cannot be `verb`ed is
+/// returned if there is no such file.
+fn find(path: &Object, verb: Option<&str>) -> Result<Found, EvalError> {
+    find_from(path, None, verb)
+}
+
+/// Finds the module `path` names, as `find` does, looking a relative path
+/// up in `dir` if given.
+fn find_from(path: &Object, dir: Option<PathBuf>, verb: Option<&str>) -> Result<Found, EvalError> {
+    let path = resolve(path, dir)?;
+
+    let loaders = MODULES.with(|modules| modules.borrow().loaders.clone());
+    for loader in loaders {
+        if let Some(path) = loader.find(&path) {
+            return Ok(Found {
+                path,
+                loader: Some(loader),
+            });
+        }
+    }
+
+    let path = match verb {
+        Some(verb) => path
+            .canonicalize()
+            .map_err(|e| EvalError::new(format!("cannot {} {}: {}", verb, path.display(), e)))?,
+        None => path,
+    };
+
+    Ok(Found { path, loader: None })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::eval_str;
+    use std::env::temp_dir;
+
+    fn write_module(dir: &Path, name: &str, source: &str) {
+        fs::write(dir.join(name), source).unwrap();
+    }
+
+    fn module_dir(name: &str) -> PathBuf {
+        let dir = temp_dir().join(format!("lisp-rs-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_load_defines_into_current_env() {
+        let dir = module_dir("load");
+        write_module(&dir, "defs.lisp", "(define x 40) (define (add2 n) (+ n 2))");
+
+        let env = global_env();
+        let program = format!("(load {:?}) (add2 x)", dir.join("defs.lisp").display());
+
+        assert_eq!(eval_str(&program, &env).unwrap(), Object::Integer(42));
+    }
+
+    #[test]
+    fn test_require_imports_only_provided_symbols() {
+        let dir = module_dir("require");
+        write_module(
+            &dir,
+            "math.lisp",
+            "(provide square)
+             (define (helper x) (* x x))
+             (define (square x) (helper x))",
+        );
+        write_module(
+            &dir,
+            "main.lisp",
+            "(require 'math) (define result (square 7))",
+        );
+
+        let env = global_env();
+        let program = format!("(load {:?}) result", dir.join("main.lisp").display());
+
+        assert_eq!(eval_str(&program, &env).unwrap(), Object::Integer(49));
+        assert!(env.borrow().get("square").is_some());
+        assert!(env.borrow().get("helper").is_none());
+    }
+
+    #[test]
+    fn test_paths_are_relative_to_the_file_being_run() {
+        let dir = module_dir("relative");
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        write_module(
+            &dir,
+            "lib/util.lisp",
+            "(provide twice) (define (twice x) (* 2 x))",
+        );
+        write_module(
+            &dir,
+            "lib/greet.lisp",
+            "(provide greet) (require \"util.lisp\") (define (greet) (twice 21))",
+        );
+        let main = dir.join("main.lisp");
+        write_module(&dir, "main.lisp", "(require \"lib/greet.lisp\") (greet)");
+
+        // As `lisp-rs run` runs it, from a working directory elsewhere.
+        let env = global_env();
+        let source = fs::read_to_string(&main).unwrap();
+        let result = in_file(&main, || eval_named(&source, "main.lisp", &env));
+        assert_eq!(result.unwrap(), Object::Integer(42));
+    }
+
+    #[test]
+    fn test_embed_file_is_relative_to_the_form() {
+        let dir = module_dir("embed");
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        write_module(&dir, "lib/schema.json", "{}");
+        write_module(
+            &dir,
+            "lib/schema.lisp",
+            "(provide schema) (define (schema) (embed-file \"schema.json\"))",
+        );
+
+        // Called from a file elsewhere, after the module has loaded.
+        let main = dir.join("main.lisp");
+        write_module(&dir, "main.lisp", "(require \"lib/schema.lisp\") (schema)");
+        let env = global_env();
+        let source = fs::read_to_string(&main).unwrap();
+        let result = in_file(&main, || eval_named(&source, "main.lisp", &env));
+        assert_eq!(result.unwrap(), Object::string("{}"));
+        assert_eq!(eval_str("(schema)", &env).unwrap(), Object::string("{}"));
+    }
+
+    #[test]
+    fn test_require_detects_cycles() {
+        let dir = module_dir("cycle");
+        write_module(
+            &dir,
+            "a.lisp",
+            "(provide a) (require \"b.lisp\") (define a 1)",
+        );
+        write_module(
+            &dir,
+            "b.lisp",
+            "(provide b) (require \"a.lisp\") (define b 2)",
+        );
+
+        let env = global_env();
+        let program = format!("(require {:?})", dir.join("a.lisp").display());
+        let err = eval_str(&program, &env).unwrap_err();
+
+        assert!(err.to_string().contains("cyclic require"));
+    }
+
+    #[test]
+    fn test_reload_module_rebinds_exports() {
+        let dir = module_dir("reload");
+        fs::create_dir_all(dir.join("my")).unwrap();
+        write_module(&dir, "my/lib.lisp", "(provide greet) (define (greet) 1)");
+
+        let env = global_env();
+        let program = format!(
+            "(require {:?}) (define (run) (greet)) (run)",
+            dir.join("my/lib.lisp").display()
+        );
+        assert_eq!(eval_str(&program, &env).unwrap(), Object::Integer(1));
+
+        // Modules are found by list names too.
+        let reload = format!("(load {:?})", dir.join("reload.lisp").display());
+        write_module(&dir, "reload.lisp", "(reload-module '(my lib))");
+
+        write_module(&dir, "my/lib.lisp", "(provide greet) (define (greet) 2)");
+        eval_str(&reload, &env).unwrap();
+        assert_eq!(eval_str("(run)", &env).unwrap(), Object::Integer(2));
+
+        // A module that fails to load leaves the old exports in place.
+        write_module(&dir, "my/lib.lisp", "(provide greet) (car '())");
+        assert!(eval_str(&reload, &env).is_err());
+        assert_eq!(eval_str("(run)", &env).unwrap(), Object::Integer(2));
+
+        let program = format!("(reload-module {:?})", dir.join("reload.lisp").display());
+        let err = eval_str(&program, &env).unwrap_err();
+        assert!(err.to_string().contains("it was never required"));
+    }
+
+    #[test]
+    fn test_embedded_loader() {
+        add_loader(Rc::new(
+            EmbeddedLoader::new()
+                .with(
+                    "std/greet.lisp",
+                    "(provide greet) (require \"./util.lisp\") (define (greet) (twice 21))",
+                )
+                .with(
+                    "std/util.lisp",
+                    "(provide twice) (define (twice x) (* 2 x))",
+                ),
+        ));
+
+        let env = global_env();
+        assert_eq!(
+            eval_str("(require '(std greet)) (greet)", &env).unwrap(),
+            Object::Integer(42)
+        );
+        assert!(env.borrow().get("twice").is_none());
+
+        // Names the loaders do not have are still looked up on disk.
+        let err = eval_str("(require '(std missing))", &env).unwrap_err();
+        assert!(err.to_string().contains("cannot require std/missing.lisp"));
+    }
+}
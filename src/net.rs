@@ -0,0 +1,272 @@
+//! TCP sockets, for small servers and clients written in Lisp.
+//!
+//! `(tcp-connect host port)` connects to a server, and `(tcp-listen host
+//! port)` listens for connections, on a free port if `port` is 0, which
+//! `(socket-port socket)` tells. `(tcp-accept listener)` waits for the next
+//! connection and returns a socket connected to it. `(socket-read-line
+//! socket)` reads a line, without its terminator, or returns `#f` once the
+//! other end has closed the connection, and `(socket-write socket string)`
+//! writes a string as it is. `(socket-close socket)` closes a socket, after
+//! which it can no longer be used:
+//!
+//! ```text
+//! (define server (tcp-listen "127.0.0.1" 7000))
+//! (define client (tcp-accept server))
+//! (socket-write client (string-append "you said: " (socket-read-line client)))
+//! (socket-close client)
+//! ```
+//!
+//! Every call blocks until it is done. Under taint tracking, the lines read
+//! are tainted, and `tcp-connect` does not accept a tainted host.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::rc::Rc;
+
+use crate::eval::EvalError;
+use crate::object::Object;
+use crate::taint;
+
+pub struct Socket {
+    state: RefCell<State>,
+}
+
+enum State {
+    Listening(TcpListener, SocketAddr),
+    Connected(BufReader<TcpStream>, SocketAddr),
+    Closed,
+}
+
+impl Socket {
+    fn object(state: State) -> Object {
+        Object::Socket(Rc::new(Socket {
+            state: RefCell::new(state),
+        }))
+    }
+}
+
+impl fmt::Display for Socket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &*self.state.borrow() {
+            State::Listening(_, address) => write!(f, "#<socket listening on {}>", address),
+            State::Connected(_, peer) => write!(f, "#<socket connected to {}>", peer),
+            State::Closed => write!(f, "#<socket closed>"),
+        }
+    }
+}
+
+fn error(name: &str, e: std::io::Error) -> EvalError {
+    EvalError::new(format!("{}: {}", name, e))
+}
+
+/// The host and port of `(name host port)`.
+fn address<'a>(name: &str, args: &'a [Object]) -> Result<(&'a str, u16), EvalError> {
+    match args {
+        [Object::String(host), Object::Integer(port)] => match u16::try_from(*port) {
+            Ok(port) => Ok((host.as_str(), port)),
+            Err(_) => Err(EvalError::new(format!(
+                "{}: port {} is out of range",
+                name, port
+            ))),
+        },
+        _ => Err(EvalError::new(format!(
+            "{} expects a host and a port",
+            name
+        ))),
+    }
+}
+
+fn socket_arg<'a>(name: &str, args: &'a [Object], len: usize) -> Result<&'a Socket, EvalError> {
+    match args.first() {
+        Some(Object::Socket(socket)) if args.len() == len => Ok(socket),
+        _ => Err(EvalError::new(format!("{} expects a socket", name))),
+    }
+}
+
+fn connected(name: &str) -> EvalError {
+    EvalError::new(format!("{} expects a connected socket", name))
+}
+
+pub fn tcp_connect(args: &[Object]) -> Result<Object, EvalError> {
+    let (host, port) = address("tcp-connect", args)?;
+    taint::check("tcp-connect", &args[0])?;
+    let stream = TcpStream::connect((host, port)).map_err(|e| error("tcp-connect", e))?;
+    let peer = stream.peer_addr().map_err(|e| error("tcp-connect", e))?;
+
+    Ok(Socket::object(State::Connected(
+        BufReader::new(stream),
+        peer,
+    )))
+}
+
+pub fn tcp_listen(args: &[Object]) -> Result<Object, EvalError> {
+    let (host, port) = address("tcp-listen", args)?;
+    let listener = TcpListener::bind((host, port)).map_err(|e| error("tcp-listen", e))?;
+    let address = listener.local_addr().map_err(|e| error("tcp-listen", e))?;
+
+    Ok(Socket::object(State::Listening(listener, address)))
+}
+
+pub fn tcp_accept(args: &[Object]) -> Result<Object, EvalError> {
+    let socket = socket_arg("tcp-accept", args, 1)?;
+    let (stream, peer) = match &*socket.state.borrow() {
+        State::Listening(listener, _) => listener.accept().map_err(|e| error("tcp-accept", e))?,
+        _ => return Err(EvalError::new("tcp-accept expects a listening socket")),
+    };
+
+    Ok(Socket::object(State::Connected(
+        BufReader::new(stream),
+        peer,
+    )))
+}
+
+pub fn socket_read_line(args: &[Object]) -> Result<Object, EvalError> {
+    let socket = socket_arg("socket-read-line", args, 1)?;
+    let mut state = socket.state.borrow_mut();
+    let State::Connected(reader, _) = &mut *state else {
+        return Err(connected("socket-read-line"));
+    };
+
+    let mut line = String::new();
+    if reader
+        .read_line(&mut line)
+        .map_err(|e| error("socket-read-line", e))?
+        == 0
+    {
+        return Ok(Object::Bool(false));
+    }
+    let len = line.trim_end_matches(['\n', '\r']).len();
+    line.truncate(len);
+
+    Ok(taint::mark(Object::string(line)))
+}
+
+pub fn socket_write(args: &[Object]) -> Result<Object, EvalError> {
+    let socket = socket_arg("socket-write", args, 2)?;
+    let Object::String(s) = &args[1] else {
+        return Err(EvalError::new("socket-write expects a socket and a string"));
+    };
+    let state = socket.state.borrow();
+    let State::Connected(reader, _) = &*state else {
+        return Err(connected("socket-write"));
+    };
+
+    let mut stream = reader.get_ref();
+    stream
+        .write_all(s.as_str().as_bytes())
+        .and_then(|()| stream.flush())
+        .map_err(|e| error("socket-write", e))?;
+
+    Ok(Object::Void)
+}
+
+/// `(socket-port socket)` is the local port of a socket.
+pub fn socket_port(args: &[Object]) -> Result<Object, EvalError> {
+    let socket = socket_arg("socket-port", args, 1)?;
+    let address = match &*socket.state.borrow() {
+        State::Listening(listener, _) => listener.local_addr(),
+        State::Connected(reader, _) => reader.get_ref().local_addr(),
+        State::Closed => return Err(EvalError::new("socket-port: the socket is closed")),
+    };
+
+    Ok(Object::Integer(
+        address.map_err(|e| error("socket-port", e))?.port() as i64,
+    ))
+}
+
+pub fn socket_close(args: &[Object]) -> Result<Object, EvalError> {
+    let socket = socket_arg("socket-close", args, 1)?;
+    if let State::Connected(reader, _) = socket.state.replace(State::Closed) {
+        // The other end may have gone already, which leaves nothing to do.
+        let _ = reader.get_ref().shutdown(Shutdown::Both);
+    }
+
+    Ok(Object::Void)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    use crate::builtins::global_env;
+    use crate::eval::eval_str;
+
+    #[test]
+    fn test_server() {
+        let env = global_env();
+        let port = eval_str(
+            "(define server (tcp-listen \"127.0.0.1\" 0)) (socket-port server)",
+            &env,
+        )
+        .unwrap()
+        .to_string();
+
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
+            stream.write_all(b"hello\r\nworld\n").unwrap();
+            let mut reply = String::new();
+            BufReader::new(stream).read_line(&mut reply).unwrap();
+            reply
+        });
+
+        let lines = eval_str(
+            "(define client (tcp-accept server))
+             (define first (socket-read-line client))
+             (define second (socket-read-line client))
+             (socket-write client (string-append first \" \" second \"!\"))
+             (socket-close client)
+             (list first second)",
+            &env,
+        )
+        .unwrap();
+        assert_eq!(lines.to_string(), "(\"hello\" \"world\")");
+        assert_eq!(client.join().unwrap(), "hello world!");
+
+        assert_eq!(
+            eval_str("(socket-read-line client)", &env)
+                .unwrap_err()
+                .message(),
+            "socket-read-line expects a connected socket"
+        );
+        assert!(eval_str("server", &env)
+            .unwrap()
+            .to_string()
+            .starts_with("#<socket listening on 127.0.0.1:"));
+    }
+
+    #[test]
+    fn test_client() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = String::new();
+            BufReader::new(&stream).read_line(&mut request).unwrap();
+            stream.write_all(request.to_uppercase().as_bytes()).unwrap();
+        });
+
+        let env = global_env();
+        let program = format!(
+            "(define s (tcp-connect \"127.0.0.1\" {}))
+             (socket-write s \"ping\n\")
+             (list (socket-read-line s) (socket-read-line s))",
+            port
+        );
+        assert_eq!(
+            eval_str(&program, &env).unwrap().to_string(),
+            "(\"PING\" #f)"
+        );
+        server.join().unwrap();
+
+        assert_eq!(
+            eval_str("(tcp-connect \"127.0.0.1\" 70000)", &env)
+                .unwrap_err()
+                .message(),
+            "tcp-connect: port 70000 is out of range"
+        );
+    }
+}
@@ -0,0 +1,769 @@
+use std::cell::{Cell, RefCell};
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+use std::fmt::Formatter;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+use std::sync::Arc;
+
+use crate::bigint::BigInt;
+use crate::collections::{HashTable, Heap};
+use crate::comparator::Comparator;
+use crate::continuation::Continuation;
+use crate::env::Env;
+use crate::eval::EvalError;
+use crate::fiber::{Channel, Fiber};
+use crate::gc::{self, Edge, Trace};
+use crate::interpreter::Native;
+use crate::kv::KvStore;
+use crate::memo::Memo;
+use crate::net::Socket;
+use crate::printer;
+use crate::promise::Promise;
+use crate::ratelimit::RateLimiter;
+use crate::rational::Rational;
+use crate::record::Record;
+use crate::signature;
+use crate::sorted_map::SortedMap;
+use crate::symbol::Symbol;
+use crate::task::Task;
+use crate::text::Str;
+use crate::vm::Closure;
+
+pub type BuiltinFn = fn(&[Object]) -> Result<Object, EvalError>;
+
+#[derive(Clone)]
+pub enum Object {
+    Void,
+    Nil,
+    Bool(bool),
+    Integer(i64),
+    BigInt(Rc<BigInt>),
+    Rational(Rc<Rational>),
+    Float(f64),
+    Char(char),
+    String(Str),
+    Symbol(Symbol),
+    Pair(Rc<Pair>),
+    Lambda(Rc<Lambda>),
+    /// A macro defined by `defmacro`, whose expander is called on the
+    /// unevaluated forms of each use.
+    Macro(Rc<Lambda>),
+    Builtin(Builtin),
+    Native(Rc<Native>),
+    Memo(Rc<Memo>),
+    Continuation(Rc<Continuation>),
+    Closure(Rc<Closure>),
+    Task(Rc<Task>),
+    Fiber(Rc<Fiber>),
+    Channel(Rc<Channel>),
+    Heap(Rc<RefCell<Heap>>),
+    Deque(Rc<RefCell<VecDeque<Object>>>),
+    Comparator(Rc<Comparator>),
+    SortedMap(Rc<RefCell<SortedMap>>),
+    HashTable(Rc<RefCell<HashTable>>),
+    KvStore(Rc<KvStore>),
+    Record(Rc<Record>),
+    RateLimiter(Arc<RateLimiter>),
+    Socket(Rc<Socket>),
+    Promise(Rc<Promise>),
+    /// An environment to `eval` code in, from `the-environment` or
+    /// `interaction-environment`.
+    Environment(Rc<RefCell<Env>>),
+    /// The values returned at once by `values`, other than exactly one.
+    Values(Rc<Vec<Object>>),
+}
+
+/// A pair, whose car and cdr can be replaced with `set-car!` and
+/// `set-cdr!`. Both are read by cloning them out, so no borrow of a pair
+/// outlives the call reading it.
+pub struct Pair {
+    car: RefCell<Object>,
+    cdr: RefCell<Object>,
+    /// Whether the pair has been registered with the cycle collector, which
+    /// it is the first time it is changed.
+    tracked: Cell<bool>,
+}
+
+thread_local! {
+    /// Set once any pair has been changed: until then no list can contain
+    /// itself, and walking one needs no guard against cycles.
+    static MUTATED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Whether a list may contain itself.
+pub(crate) fn may_cycle() -> bool {
+    MUTATED.with(Cell::get)
+}
+
+impl Pair {
+    pub fn car(&self) -> Object {
+        self.car.borrow().clone()
+    }
+
+    pub fn cdr(&self) -> Object {
+        self.cdr.borrow().clone()
+    }
+
+    pub fn set_car(self: &Rc<Self>, car: Object) {
+        self.changed();
+        drop(self.car.replace(car));
+    }
+
+    pub fn set_cdr(self: &Rc<Self>, cdr: Object) {
+        self.changed();
+        drop(self.cdr.replace(cdr));
+    }
+
+    /// A changed pair can close a cycle, which only the collector frees.
+    fn changed(self: &Rc<Self>) {
+        MUTATED.with(|mutated| mutated.set(true));
+        if !self.tracked.replace(true) {
+            gc::track(self);
+        }
+    }
+
+    /// Drops the car and cdr, for the collector breaking a cycle.
+    pub(crate) fn clear(&self) {
+        drop(self.car.replace(Object::Nil));
+        drop(self.cdr.replace(Object::Nil));
+    }
+}
+
+/// The car and cdr are looked at in place: a clone would count as a
+/// reference from outside the graph.
+impl Trace for Pair {
+    fn trace(&self, edge: &mut dyn FnMut(Edge)) {
+        edge(Edge::Object(&self.car.borrow()));
+        edge(Edge::Object(&self.cdr.borrow()));
+    }
+}
+
+/// Unlinks the pairs this one solely owns one at a time, since dropping a
+/// long or deeply nested list recursively would overflow the stack.
+impl Drop for Pair {
+    fn drop(&mut self) {
+        let (car, cdr) = (self.car.get_mut(), self.cdr.get_mut());
+        if !matches!(car, Object::Pair(_)) && !matches!(cdr, Object::Pair(_)) {
+            return;
+        }
+
+        let mut pending = vec![
+            std::mem::replace(car, Object::Nil),
+            std::mem::replace(cdr, Object::Nil),
+        ];
+        while let Some(obj) = pending.pop() {
+            if let Object::Pair(pair) = obj {
+                if let Ok(mut pair) = Rc::try_unwrap(pair) {
+                    pending.push(std::mem::replace(pair.car.get_mut(), Object::Nil));
+                    pending.push(std::mem::replace(pair.cdr.get_mut(), Object::Nil));
+                }
+            }
+        }
+    }
+}
+
+pub struct Lambda {
+    /// The name `(define (name params...) body...)` gave it.
+    pub name: Option<Symbol>,
+    pub params: Vec<Symbol>,
+    /// Receives the arguments past `params` as a list, for `(a b . rest)`
+    /// and `args` parameter lists.
+    pub rest: Option<Symbol>,
+    pub body: Vec<Object>,
+    pub env: Rc<RefCell<Env>>,
+}
+
+#[derive(Clone)]
+pub struct Builtin {
+    pub name: &'static str,
+    pub func: BuiltinFn,
+}
+
+impl Builtin {
+    /// Calls the builtin, explaining how it is called if it fails for
+    /// being given the wrong number of arguments.
+    pub fn call(&self, args: &[Object]) -> Result<Object, EvalError> {
+        (self.func)(args).map_err(|e| signature::explain_arity(self.name, args.len(), e))
+    }
+}
+
+impl Object {
+    pub fn cons(car: Object, cdr: Object) -> Object {
+        Object::Pair(Rc::new(Pair {
+            car: RefCell::new(car),
+            cdr: RefCell::new(cdr),
+            tracked: Cell::new(false),
+        }))
+    }
+
+    pub fn list<I>(items: I) -> Object
+    where
+        I: IntoIterator<Item = Object>,
+        I::IntoIter: DoubleEndedIterator,
+    {
+        items
+            .into_iter()
+            .rev()
+            .fold(Object::Nil, |tail, item| Object::cons(item, tail))
+    }
+
+    /// Wraps an arbitrary-precision result, demoting it to `Integer` when it
+    /// fits in an `i64` so that small numbers keep a single representation.
+    pub fn from_bigint(n: BigInt) -> Object {
+        match n.to_i64() {
+            Some(n) => Object::Integer(n),
+            None => Object::BigInt(Rc::new(n)),
+        }
+    }
+
+    /// Wraps an exact fraction, demoting whole numbers to integers.
+    pub fn from_rational(n: Rational) -> Object {
+        if n.is_integer() {
+            Object::from_bigint(n.numerator().clone())
+        } else {
+            Object::Rational(Rc::new(n))
+        }
+    }
+
+    pub fn string(s: impl Into<Str>) -> Object {
+        Object::String(s.into())
+    }
+
+    pub fn symbol(name: &str) -> Object {
+        Object::Symbol(Symbol::intern(name))
+    }
+
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Object::Bool(false))
+    }
+
+    /// Whether a list in `self` contains itself, through its cars or its
+    /// cdrs. Lists shared by several others without looping are not.
+    pub(crate) fn contains_itself(&self) -> bool {
+        if !may_cycle() {
+            return false;
+        }
+
+        enum Visit {
+            Enter(Object),
+            Leave(*const Pair),
+        }
+        // The pairs on the way from `self` to the one looked at, and those
+        // already found to close no cycle.
+        let (mut path, mut done) = (HashSet::new(), HashSet::new());
+        let mut pending = vec![Visit::Enter(self.clone())];
+        while let Some(visit) = pending.pop() {
+            match visit {
+                Visit::Enter(Object::Pair(pair)) => {
+                    let address = Rc::as_ptr(&pair);
+                    if path.contains(&address) {
+                        return true;
+                    }
+                    if !done.insert(address) {
+                        continue;
+                    }
+                    path.insert(address);
+                    pending.push(Visit::Leave(address));
+                    pending.push(Visit::Enter(pair.cdr()));
+                    pending.push(Visit::Enter(pair.car()));
+                }
+                Visit::Enter(_) => {}
+                Visit::Leave(address) => {
+                    path.remove(&address);
+                }
+            }
+        }
+
+        false
+    }
+
+    /// Collects the elements of a proper list, or returns `None` when the
+    /// object is not a `Nil`-terminated chain of pairs, or is a circular one.
+    pub fn to_vec(&self) -> Option<Vec<Object>> {
+        let mut items = Vec::new();
+        let mut current = self.clone();
+        // Goes through the list at half the speed, to meet `current` if the
+        // list loops back on itself.
+        let mut slow = self.clone();
+        let check = may_cycle();
+
+        loop {
+            match current {
+                Object::Nil => return Some(items),
+                Object::Pair(pair) => {
+                    items.push(pair.car());
+                    current = pair.cdr();
+                }
+                _ => return None,
+            }
+            if check && items.len() % 2 == 0 {
+                if let Object::Pair(pair) = &slow {
+                    let next = pair.cdr();
+                    slow = next;
+                }
+                if matches!((&slow, &current), (Object::Pair(a), Object::Pair(b)) if Rc::ptr_eq(a, b))
+                {
+                    return None;
+                }
+            }
+        }
+    }
+
+    /// Whether both are the same object, as `eq?`: pairs, strings and
+    /// numbers too large for an `i64` are compared by identity, and
+    /// everything else as by `eqv?`.
+    pub fn is_eq(&self, other: &Object) -> bool {
+        match (self, other) {
+            (Object::BigInt(a), Object::BigInt(b)) => Rc::ptr_eq(a, b),
+            (Object::Rational(a), Object::Rational(b)) => Rc::ptr_eq(a, b),
+            _ => self.is_eqv(other),
+        }
+    }
+
+    /// Equivalence, as `eqv?`: numbers of the same exactness and value are
+    /// equivalent, but `2` and `2.0`, or `0.0` and `-0.0`, are not. Pairs
+    /// and strings are compared by identity, the rest as by `equal?`.
+    pub fn is_eqv(&self, other: &Object) -> bool {
+        match (self, other) {
+            (Object::Pair(a), Object::Pair(b)) => Rc::ptr_eq(a, b),
+            (Object::String(a), Object::String(b)) => a.ptr_eq(b),
+            (Object::Float(a), Object::Float(b)) => a.to_bits() == b.to_bits(),
+            _ => self.atom_eq(other),
+        }
+    }
+
+    pub fn is_procedure(&self) -> bool {
+        matches!(
+            self,
+            Object::Lambda(_)
+                | Object::Builtin(_)
+                | Object::Native(_)
+                | Object::Memo(_)
+                | Object::Continuation(_)
+                | Object::Closure(_)
+        )
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Object::Void => "void",
+            Object::Nil => "nil",
+            Object::Bool(_) => "boolean",
+            Object::Integer(_) | Object::BigInt(_) => "integer",
+            Object::Rational(_) => "rational",
+            Object::Float(_) => "float",
+            Object::Char(_) => "char",
+            Object::String(_) => "string",
+            Object::Symbol(_) => "symbol",
+            Object::Pair(_) => "pair",
+            Object::Lambda(_)
+            | Object::Builtin(_)
+            | Object::Native(_)
+            | Object::Memo(_)
+            | Object::Continuation(_)
+            | Object::Closure(_) => "procedure",
+            Object::Macro(_) => "macro",
+            Object::Task(_) => "task",
+            Object::Fiber(_) => "fiber",
+            Object::Channel(_) => "channel",
+            Object::Heap(_) => "heap",
+            Object::Deque(_) => "deque",
+            Object::Comparator(_) => "comparator",
+            Object::SortedMap(_) => "sorted-map",
+            Object::HashTable(_) => "hash-table",
+            Object::KvStore(_) => "kv-store",
+            Object::Record(record) => record.kind.name.as_str(),
+            Object::RateLimiter(_) => "rate-limiter",
+            Object::Socket(_) => "socket",
+            Object::Promise(_) => "promise",
+            Object::Environment(_) => "environment",
+            Object::Values(_) => "values",
+        }
+    }
+}
+
+/// `name`, such as that of a type, after "a" or "an".
+pub(crate) fn with_article(name: &str) -> String {
+    match name.starts_with(['a', 'e', 'i', 'o', 'u']) {
+        true => format!("an {}", name),
+        false => format!("a {}", name),
+    }
+}
+
+/// Structural equality, as `equal?`. Pairs are compared with an explicit
+/// stack, so that huge lists do not overflow the call stack.
+impl PartialEq for Object {
+    fn eq(&self, other: &Self) -> bool {
+        let mut pending = vec![(self.clone(), other.clone())];
+        // The pairs of pairs compared already, once lists may be circular:
+        // comparing them again finds nothing new.
+        let mut compared = may_cycle().then(HashSet::new);
+        while let Some(pair) = pending.pop() {
+            match pair {
+                (Object::Pair(a), Object::Pair(b)) => {
+                    if let Some(compared) = &mut compared {
+                        if !compared.insert((Rc::as_ptr(&a), Rc::as_ptr(&b))) {
+                            continue;
+                        }
+                    }
+                    pending.push((a.cdr(), b.cdr()));
+                    pending.push((a.car(), b.car()));
+                }
+                (a, b) if !a.atom_eq(&b) => return false,
+                _ => {}
+            }
+        }
+
+        true
+    }
+}
+
+/// Hashes consistently with `PartialEq`: structurally for data, and by
+/// identity for procedures and containers.
+impl Hash for Object {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let mut pending = vec![self.clone()];
+        let mut hashed = may_cycle().then(HashSet::new);
+        while let Some(obj) = pending.pop() {
+            std::mem::discriminant(&obj).hash(state);
+            match obj {
+                Object::Pair(pair) => {
+                    if let Some(hashed) = &mut hashed {
+                        if !hashed.insert(Rc::as_ptr(&pair)) {
+                            continue;
+                        }
+                    }
+                    pending.push(pair.cdr());
+                    pending.push(pair.car());
+                }
+                atom => atom.hash_atom(state),
+            }
+        }
+    }
+}
+
+impl Object {
+    fn hash_atom<H: Hasher>(&self, state: &mut H) {
+        match self {
+            Object::Bool(b) => b.hash(state),
+            Object::Integer(n) => n.hash(state),
+            Object::BigInt(n) => n.to_string().hash(state),
+            Object::Rational(n) => n.to_string().hash(state),
+            // `0.0 == -0.0`, so both must hash alike.
+            Object::Float(n) if *n == 0.0 => 0u64.hash(state),
+            Object::Float(n) => n.to_bits().hash(state),
+            Object::Char(c) => c.hash(state),
+            Object::String(s) => s.hash(state),
+            Object::Symbol(s) => s.hash(state),
+            Object::Builtin(builtin) => builtin.name.hash(state),
+            Object::Native(native) => Rc::as_ptr(native).hash(state),
+            Object::Lambda(lambda) | Object::Macro(lambda) => Rc::as_ptr(lambda).hash(state),
+            Object::Memo(memo) => Rc::as_ptr(memo).hash(state),
+            Object::Continuation(k) => Rc::as_ptr(k).hash(state),
+            Object::Closure(closure) => Rc::as_ptr(closure).hash(state),
+            Object::Task(task) => Rc::as_ptr(task).hash(state),
+            Object::Fiber(fiber) => Rc::as_ptr(fiber).hash(state),
+            Object::Channel(channel) => Rc::as_ptr(channel).hash(state),
+            Object::Heap(heap) => Rc::as_ptr(heap).hash(state),
+            Object::Comparator(comparator) => Rc::as_ptr(comparator).hash(state),
+            Object::SortedMap(map) => Rc::as_ptr(map).hash(state),
+            Object::HashTable(table) => Rc::as_ptr(table).hash(state),
+            Object::Deque(deque) => Rc::as_ptr(deque).hash(state),
+            Object::KvStore(store) => Rc::as_ptr(store).hash(state),
+            Object::Record(record) => Rc::as_ptr(record).hash(state),
+            Object::RateLimiter(limiter) => Arc::as_ptr(limiter).hash(state),
+            Object::Socket(socket) => Rc::as_ptr(socket).hash(state),
+            Object::Promise(promise) => Rc::as_ptr(promise).hash(state),
+            Object::Environment(env) => Rc::as_ptr(env).hash(state),
+            Object::Values(values) => values.hash(state),
+            Object::Void | Object::Nil | Object::Pair(_) => {}
+        }
+    }
+
+    fn atom_eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Object::Void, Object::Void) => true,
+            (Object::Nil, Object::Nil) => true,
+            (Object::Bool(a), Object::Bool(b)) => a == b,
+            (Object::Integer(a), Object::Integer(b)) => a == b,
+            (Object::BigInt(a), Object::BigInt(b)) => a == b,
+            (Object::Rational(a), Object::Rational(b)) => a == b,
+            (Object::Float(a), Object::Float(b)) => a == b,
+            (Object::Char(a), Object::Char(b)) => a == b,
+            (Object::String(a), Object::String(b)) => a == b,
+            (Object::Symbol(a), Object::Symbol(b)) => a == b,
+            (Object::Lambda(a), Object::Lambda(b)) => Rc::ptr_eq(a, b),
+            (Object::Macro(a), Object::Macro(b)) => Rc::ptr_eq(a, b),
+            (Object::Builtin(a), Object::Builtin(b)) => a.name == b.name,
+            (Object::Native(a), Object::Native(b)) => Rc::ptr_eq(a, b),
+            (Object::Memo(a), Object::Memo(b)) => Rc::ptr_eq(a, b),
+            (Object::Continuation(a), Object::Continuation(b)) => Rc::ptr_eq(a, b),
+            (Object::Closure(a), Object::Closure(b)) => Rc::ptr_eq(a, b),
+            (Object::Task(a), Object::Task(b)) => Rc::ptr_eq(a, b),
+            (Object::Fiber(a), Object::Fiber(b)) => Rc::ptr_eq(a, b),
+            (Object::Channel(a), Object::Channel(b)) => Rc::ptr_eq(a, b),
+            (Object::Heap(a), Object::Heap(b)) => Rc::ptr_eq(a, b),
+            (Object::Comparator(a), Object::Comparator(b)) => Rc::ptr_eq(a, b),
+            (Object::KvStore(a), Object::KvStore(b)) => Rc::ptr_eq(a, b),
+            (Object::SortedMap(a), Object::SortedMap(b)) => Rc::ptr_eq(a, b),
+            (Object::HashTable(a), Object::HashTable(b)) => Rc::ptr_eq(a, b),
+            (Object::Deque(a), Object::Deque(b)) => Rc::ptr_eq(a, b),
+            (Object::Record(a), Object::Record(b)) => Rc::ptr_eq(a, b),
+            (Object::RateLimiter(a), Object::RateLimiter(b)) => Arc::ptr_eq(a, b),
+            (Object::Socket(a), Object::Socket(b)) => Rc::ptr_eq(a, b),
+            (Object::Promise(a), Object::Promise(b)) => Rc::ptr_eq(a, b),
+            (Object::Environment(a), Object::Environment(b)) => Rc::ptr_eq(a, b),
+            (Object::Values(a), Object::Values(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+/// What is left to write of a list.
+enum Step {
+    Object(Object),
+    /// The rest of a list whose earlier elements have been written.
+    Tail(Object),
+    Close,
+}
+
+/// The pairs of the lists being written, once lists may be circular, in
+/// the order they were reached.
+#[derive(Default)]
+struct Open {
+    pairs: HashSet<*const Pair>,
+    order: Vec<*const Pair>,
+    /// How many pairs were open as each list being written was started.
+    starts: Vec<usize>,
+}
+
+impl Open {
+    /// Opens a pair, or returns false if it is open already, which means
+    /// a list contains itself.
+    fn open(&mut self, pair: &Rc<Pair>) -> bool {
+        let pair = Rc::as_ptr(pair);
+        if !self.pairs.insert(pair) {
+            return false;
+        }
+        self.order.push(pair);
+        true
+    }
+
+    fn close(&mut self) {
+        let start = self.starts.pop().unwrap_or_default();
+        for pair in self.order.drain(start..) {
+            self.pairs.remove(&pair);
+        }
+    }
+}
+
+impl fmt::Display for Object {
+    /// Lists are written with an explicit stack rather than by recursion, so
+    /// that deeply nested ones can be printed. A list that contains itself
+    /// is written with `...` where it repeats.
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let mut open = may_cycle().then(Open::default);
+        let mut steps = vec![Step::Object(self.clone())];
+        while let Some(step) = steps.pop() {
+            match step {
+                Step::Object(Object::Pair(pair)) => {
+                    if let Some(open) = &mut open {
+                        if !open.open(&pair) {
+                            write!(f, "...")?;
+                            continue;
+                        }
+                        open.starts.push(open.order.len() - 1);
+                    }
+                    write!(f, "(")?;
+                    steps.push(Step::Tail(pair.cdr()));
+                    steps.push(Step::Object(pair.car()));
+                }
+                Step::Object(atom) => atom.fmt_atom(f)?,
+                Step::Tail(Object::Nil) | Step::Close => {
+                    if let Some(open) = &mut open {
+                        open.close();
+                    }
+                    write!(f, ")")?
+                }
+                Step::Tail(Object::Pair(pair)) => {
+                    if let Some(open) = &mut open {
+                        if !open.open(&pair) {
+                            open.close();
+                            write!(f, " . ...)")?;
+                            continue;
+                        }
+                    }
+                    write!(f, " ")?;
+                    steps.push(Step::Tail(pair.cdr()));
+                    steps.push(Step::Object(pair.car()));
+                }
+                Step::Tail(other) => {
+                    write!(f, " . ")?;
+                    steps.push(Step::Close);
+                    steps.push(Step::Object(other));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Object {
+    fn fmt_atom(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Object::Void => Ok(()),
+            Object::Nil => write!(f, "()"),
+            Object::Bool(true) => write!(f, "#t"),
+            Object::Bool(false) => write!(f, "#f"),
+            Object::Integer(n) => write!(f, "{}", n),
+            Object::BigInt(n) => write!(f, "{}", n),
+            Object::Rational(n) => write!(f, "{}", n),
+            Object::Float(n) => f.write_str(&printer::format_float(*n)),
+            Object::Char(' ') => write!(f, "#\\space"),
+            Object::Char('\n') => write!(f, "#\\newline"),
+            Object::Char('\t') => write!(f, "#\\tab"),
+            Object::Char('\r') => write!(f, "#\\return"),
+            Object::Char('\0') => write!(f, "#\\nul"),
+            Object::Char(c) => write!(f, "#\\{}", c),
+            Object::String(s) => write!(f, "{:?}", s),
+            Object::Symbol(s) => write!(f, "{}", s),
+            Object::Pair(_) => unreachable!("pairs are written by Display"),
+            Object::Lambda(_) | Object::Memo(_) | Object::Closure(_) => write!(f, "#<procedure>"),
+            Object::Macro(_) => write!(f, "#<macro>"),
+            Object::Continuation(_) => write!(f, "#<continuation>"),
+            Object::Builtin(builtin) => write!(f, "#<builtin {}>", builtin.name),
+            Object::Native(native) => write!(f, "#<native {}>", native.name()),
+            Object::Task(_) => write!(f, "#<task>"),
+            Object::Fiber(_) => write!(f, "#<fiber>"),
+            Object::Channel(_) => write!(f, "#<channel>"),
+            Object::Heap(_) => write!(f, "#<heap>"),
+            Object::Comparator(_) => write!(f, "#<comparator>"),
+            Object::SortedMap(_) => write!(f, "#<sorted-map>"),
+            Object::HashTable(table) => fmt_hash_table(table, f),
+            Object::Deque(_) => write!(f, "#<deque>"),
+            Object::KvStore(store) => write!(f, "#<kv-store {}>", store.path().display()),
+            Object::Record(record) => fmt_record(record, f),
+            Object::RateLimiter(limiter) => {
+                write!(f, "#<rate-limiter {}/s>", limiter.per_second())
+            }
+            Object::Socket(socket) => write!(f, "{}", socket),
+            Object::Promise(_) => write!(f, "#<promise>"),
+            Object::Environment(_) => write!(f, "#<environment>"),
+            Object::Values(values) => {
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+thread_local! {
+    /// The hash tables and records being printed, so that one containing
+    /// itself is not printed forever.
+    static PRINTING: RefCell<Vec<*const ()>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Runs `write` with `ptr` marked as being printed, unless it already is.
+fn fmt_once(ptr: *const (), write: impl FnOnce() -> fmt::Result) -> Option<fmt::Result> {
+    if PRINTING.with(|printing| printing.borrow().contains(&ptr)) {
+        return None;
+    }
+
+    PRINTING.with(|printing| printing.borrow_mut().push(ptr));
+    let result = write();
+    PRINTING.with(|printing| printing.borrow_mut().pop());
+
+    Some(result)
+}
+
+/// Writes `#<hash-table (key . value) ...>`, or just `#<hash-table ...>` for
+/// a table inside itself.
+fn fmt_hash_table(table: &Rc<RefCell<HashTable>>, f: &mut Formatter<'_>) -> fmt::Result {
+    // Sorting the entries may print them, so the table is marked first.
+    fmt_once(Rc::as_ptr(table) as *const (), || {
+        let entries = table.borrow().entries();
+        write!(f, "#<hash-table")?;
+        for (key, value) in entries {
+            write!(f, " {}", Object::cons(key, value))?;
+        }
+        write!(f, ">")
+    })
+    .unwrap_or_else(|| write!(f, "#<hash-table ...>"))
+}
+
+/// Writes `#<name field ...>`, or `#<name ...>` for a record inside itself.
+fn fmt_record(record: &Rc<Record>, f: &mut Formatter<'_>) -> fmt::Result {
+    fmt_once(Rc::as_ptr(record) as *const (), || {
+        write!(f, "#<{}", record.kind.name)?;
+        for field in record.fields.borrow().iter() {
+            write!(f, " {}", field)?;
+        }
+        write!(f, ">")
+    })
+    .unwrap_or_else(|| write!(f, "#<{} ...>", record.kind.name))
+}
+
+impl fmt::Debug for Object {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display_lists() {
+        let list = Object::list(vec![Object::Integer(1), Object::Integer(2)]);
+        assert_eq!(list.to_string(), "(1 2)");
+
+        let pair = Object::cons(Object::Integer(1), Object::Integer(2));
+        assert_eq!(pair.to_string(), "(1 . 2)");
+    }
+
+    #[test]
+    fn test_display_chars() {
+        let chars = Object::list(vec![Object::Char('a'), Object::Char(' ')]);
+        assert_eq!(chars.to_string(), r"(#\a #\space)");
+    }
+
+    #[test]
+    fn test_to_vec() {
+        let list = Object::list(vec![Object::symbol("a"), Object::symbol("b")]);
+        assert_eq!(
+            list.to_vec(),
+            Some(vec![Object::symbol("a"), Object::symbol("b")])
+        );
+
+        let pair = Object::cons(Object::Integer(1), Object::Integer(2));
+        assert_eq!(pair.to_vec(), None);
+    }
+
+    /// `(((...)))`, nested `depth` levels deep.
+    fn nested(depth: usize) -> Object {
+        (0..depth).fold(Object::Nil, |inner, _| Object::cons(inner, Object::Nil))
+    }
+
+    #[test]
+    fn test_huge_structures() {
+        let long = || Object::list((0..1_000_000).map(Object::Integer));
+        assert!(long() == long());
+        assert!(long() != Object::list((0..999_999).map(Object::Integer)));
+        assert_eq!(long().to_string().len(), 6_888_891);
+
+        let deep = nested(100_000);
+        assert!(deep == nested(100_000));
+        assert!(deep != nested(99_999));
+        let printed = deep.to_string();
+        assert_eq!(printed.len(), 200_002);
+        assert!(printed.starts_with("((((") && printed.ends_with("))))"));
+    }
+}
@@ -0,0 +1,404 @@
+//! A pass rewriting programs into simpler ones that do the same, run before
+//! compiling them for the `vm`, or on its own through `optimize`.
+//!
+//! It folds the calls of arithmetic builtins and comparisons whose
+//! arguments are all numbers, as `(* 60 60 24)` to `86400`, keeps only the
+//! branch an `if` takes when its test is a literal, and inlines the body of
+//! a small lambda applied on the spot to literals, as
+//! `((lambda (x) (* x x)) 3)` to `9`. Each rewrite is redone on what it
+//! makes, so that these add up.
+//!
+//! Only the core forms the compiler knows are rewritten: quoted data and
+//! the forms of every other special form are left as they are. A builtin is
+//! not folded if the program binds its name anywhere, by a definition,
+//! `set!` or a parameter of its own; the pass assumes that code it is not
+//! given, such as a file loaded at run time, does not redefine it either.
+//! A call that fails, such as `(/ 1 0)`, is left to fail when it runs. The
+//! uses of the macros the program defines with `defmacro` are left alone
+//! too, as their forms are given to the macro and not evaluated.
+
+use std::collections::HashSet;
+
+use crate::ast::{self, Expr, Folder, Node, Visitor};
+use crate::builtins::builtins;
+use crate::lexer::KEYWORDS;
+use crate::object::{BuiltinFn, Object};
+use crate::symbol::Symbol;
+
+/// The builtins folded, which return the same for the same arguments and
+/// do nothing else.
+const PURE: &[&str] = &[
+    "+",
+    "-",
+    "*",
+    "/",
+    "expt",
+    "abs",
+    "min",
+    "max",
+    "floor",
+    "ceiling",
+    "round",
+    "truncate",
+    "quotient",
+    "remainder",
+    "modulo",
+    "sqrt",
+    "exp",
+    "log",
+    "sin",
+    "cos",
+    "tan",
+    "asin",
+    "acos",
+    "atan",
+    "exact->inexact",
+    "inexact->exact",
+    "=",
+    "<",
+    ">",
+    "<=",
+    ">=",
+];
+
+/// The special forms the pass looks into.
+const CORE_FORMS: &[&str] = &["begin", "define", "if", "lambda", "let", "set!"];
+
+/// How many nodes the body of a lambda inlined may have.
+const INLINE_SIZE: usize = 16;
+
+/// Optimizes the forms of a program.
+pub fn optimize(forms: Vec<Node>) -> Vec<Node> {
+    let mut optimizer = Optimizer {
+        bound: bound_names(&forms),
+        macros: forms
+            .iter()
+            .filter(|form| form.head() == Some("defmacro"))
+            .filter_map(|form| form.children().get(1)?.head())
+            .collect(),
+    };
+
+    forms
+        .into_iter()
+        .map(|form| ast::fold(form, &mut optimizer))
+        .collect()
+}
+
+/// Optimizes forms as the parser returns them, for the compiler.
+pub fn optimize_forms(forms: Vec<Object>) -> Vec<Object> {
+    let nodes = forms.iter().map(Node::from_object).collect();
+
+    optimize(nodes).iter().map(Node::to_object).collect()
+}
+
+struct Optimizer {
+    bound: HashSet<Symbol>,
+    macros: HashSet<&'static str>,
+}
+
+impl Folder for Optimizer {
+    fn enter(&mut self, node: &Node) -> bool {
+        !is_opaque(node) && !node.head().is_some_and(|name| self.macros.contains(name))
+    }
+
+    fn fold(&mut self, node: Node) -> Node {
+        let Expr::List(items) = &node.expr else {
+            return node;
+        };
+
+        let rewritten = match node.head() {
+            Some("if") => self.branch(items),
+            Some(name) if PURE.contains(&name) && !self.bound.contains(&Symbol::intern(name)) => {
+                call(name, items)
+            }
+            _ => self.inline(items),
+        };
+        match rewritten {
+            Some(rewritten) => ast::fold(
+                Node {
+                    span: rewritten.span.or(node.span),
+                    ..rewritten
+                },
+                self,
+            ),
+            None => node,
+        }
+    }
+}
+
+impl Optimizer {
+    /// The branch `(if test then [else])` takes, if its test is a literal.
+    fn branch(&self, items: &[Node]) -> Option<Node> {
+        let taken = match items {
+            [_, test, then, rest @ ..] if rest.len() <= 1 => match literal(test)? {
+                Object::Bool(false) => rest.first()?,
+                _ => then,
+            },
+            _ => return None,
+        };
+
+        Some(taken.clone())
+    }
+
+    /// The body of `((lambda (params...) body) args...)` with the arguments
+    /// in place of the parameters, if they are all numbers, booleans or
+    /// characters and the body is a small expression binding nothing.
+    fn inline(&self, items: &[Node]) -> Option<Node> {
+        let (lambda, args) = items.split_first()?;
+        let Expr::List(lambda) = &lambda.expr else {
+            return None;
+        };
+        let [head, params, body] = lambda.as_slice() else {
+            return None;
+        };
+        if atom(head) != Some(&Object::symbol("lambda")) {
+            return None;
+        }
+        let Expr::List(params) = &params.expr else {
+            return None;
+        };
+        let params = params
+            .iter()
+            .map(|param| match atom(param)? {
+                Object::Symbol(name) => Some(*name),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()?;
+        if params.len() != args.len()
+            || !args
+                .iter()
+                .all(|arg| literal(arg).is_some_and(|value| is_copyable(&value)))
+            || size(body) > INLINE_SIZE
+            || !is_expression(body)
+        {
+            return None;
+        }
+
+        Some(substitute(body, &params, args))
+    }
+}
+
+/// `(name args...)` with its value in place, if its arguments are all
+/// numbers and the call succeeds.
+fn call(name: &str, items: &[Node]) -> Option<Node> {
+    let args = items[1..]
+        .iter()
+        .map(|arg| literal(arg).filter(is_number))
+        .collect::<Option<Vec<_>>>()?;
+    let func = builtin(name)?;
+
+    match func(&args) {
+        Ok(value) if is_number(&value) || matches!(value, Object::Bool(_)) => {
+            Some(Node::atom(value))
+        }
+        _ => None,
+    }
+}
+
+fn builtin(name: &str) -> Option<BuiltinFn> {
+    builtins()
+        .find(|(builtin, _)| *builtin == name)
+        .map(|&(_, func)| func)
+}
+
+fn atom(node: &Node) -> Option<&Object> {
+    match &node.expr {
+        Expr::Atom(value) => Some(value),
+        _ => None,
+    }
+}
+
+/// The value of a node that evaluates to itself, or is quoted.
+fn literal(node: &Node) -> Option<Object> {
+    match &node.expr {
+        Expr::Atom(Object::Symbol(_) | Object::Nil) => None,
+        Expr::Atom(value) => Some(value.clone()),
+        Expr::List(items) if node.head() == Some("quote") && items.len() == 2 => {
+            Some(items[1].to_object())
+        }
+        _ => None,
+    }
+}
+
+fn is_number(value: &Object) -> bool {
+    matches!(
+        value,
+        Object::Integer(_) | Object::BigInt(_) | Object::Rational(_) | Object::Float(_)
+    )
+}
+
+/// Whether a literal can be copied into several places without a program
+/// telling the copies apart.
+fn is_copyable(value: &Object) -> bool {
+    is_number(value) || matches!(value, Object::Bool(_) | Object::Char(_))
+}
+
+/// Whether a node is quoted data or the form of a special form the pass
+/// does not know.
+fn is_opaque(node: &Node) -> bool {
+    match node.head() {
+        Some(name) => name == "quote" || (KEYWORDS.contains(&name) && !CORE_FORMS.contains(&name)),
+        None => false,
+    }
+}
+
+/// Whether a node binds no names: it is made only of calls, `if`, `begin`
+/// and literals.
+fn is_expression(node: &Node) -> bool {
+    match node.head() {
+        Some("quote") => true,
+        Some(name) if KEYWORDS.contains(&name) && !matches!(name, "if" | "begin") => false,
+        _ => node.children().into_iter().all(is_expression),
+    }
+}
+
+fn size(node: &Node) -> usize {
+    1 + node.children().into_iter().map(size).sum::<usize>()
+}
+
+/// `node` with `args` in place of the symbols `params`, outside quoted data.
+fn substitute(node: &Node, params: &[Symbol], args: &[Node]) -> Node {
+    let expr = match &node.expr {
+        Expr::Atom(Object::Symbol(name)) => match params.iter().position(|param| param == name) {
+            Some(i) => return args[i].clone(),
+            None => node.expr.clone(),
+        },
+        _ if node.head() == Some("quote") => node.expr.clone(),
+        Expr::Atom(_) => node.expr.clone(),
+        Expr::List(items) => Expr::List(
+            items
+                .iter()
+                .map(|item| substitute(item, params, args))
+                .collect(),
+        ),
+        Expr::Dotted(items, tail) => Expr::Dotted(
+            items
+                .iter()
+                .map(|item| substitute(item, params, args))
+                .collect(),
+            Box::new(substitute(tail, params, args)),
+        ),
+    };
+
+    Node {
+        expr,
+        span: node.span,
+    }
+}
+
+/// The names a program may bind: those defined, set or made parameters by
+/// the core forms, and every symbol in the forms of other special forms.
+fn bound_names(forms: &[Node]) -> HashSet<Symbol> {
+    struct Bindings(HashSet<Symbol>);
+
+    impl Bindings {
+        fn add(&mut self, node: &Node) {
+            match &node.expr {
+                Expr::Atom(Object::Symbol(name)) => {
+                    self.0.insert(*name);
+                }
+                Expr::Atom(_) => {}
+                _ => node
+                    .children()
+                    .into_iter()
+                    .for_each(|child| self.add(child)),
+            }
+        }
+    }
+
+    impl Visitor for Bindings {
+        fn enter(&mut self, node: &Node) -> bool {
+            let children = node.children();
+            match node.head() {
+                Some("quote") => return false,
+                Some(name) if KEYWORDS.contains(&name) && !CORE_FORMS.contains(&name) => {
+                    self.add(node);
+                    return false;
+                }
+                Some("define" | "set!" | "lambda") if children.len() > 1 => match &children[1].expr
+                {
+                    // The name and parameters of `(define (name params...) body...)`.
+                    Expr::List(_) | Expr::Dotted(..) | Expr::Atom(Object::Symbol(_)) => {
+                        self.add(children[1])
+                    }
+                    Expr::Atom(_) => {}
+                },
+                Some("let") if children.len() > 1 => {
+                    for binding in children[1].children() {
+                        if let Some(name) = binding.children().first() {
+                            self.add(name);
+                        }
+                    }
+                }
+                _ => {}
+            }
+            true
+        }
+    }
+
+    let mut bindings = Bindings(HashSet::new());
+    for form in forms {
+        ast::walk(form, &mut bindings);
+    }
+
+    bindings.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builtins::global_env;
+    use crate::parser::parse;
+
+    fn optimized(program: &str) -> String {
+        let forms = optimize_forms(parse(program).unwrap());
+        let printed = forms.iter().map(Object::to_string).collect::<Vec<_>>();
+
+        printed.join(" ")
+    }
+
+    #[test]
+    fn test_folding() {
+        assert_eq!(optimized("(define day (* 60 60 24))"), "(define day 86400)");
+        assert_eq!(optimized("(if (< 1 2) (sqrt 16) (car '()))"), "4");
+        assert_eq!(
+            optimized("(if #f 1) (if '() 1 2) (+ x 1) (/ 1 0)"),
+            "(if #f 1) 1 (+ x 1) (/ 1 0)"
+        );
+        assert_eq!(
+            optimized("(define (f n) ((lambda (x y) (if (> x y) x (* y n))) 4 (+ 1 2)))"),
+            "(define (f n) 4)"
+        );
+        // Quoted data, the forms of other special forms and calls to
+        // procedures rebound anywhere in the program are left alone.
+        assert_eq!(
+            optimized("'(+ 1 2) (assert (= 1 1)) (let ((+ -)) (+ 1 2)) (* 2 3)"),
+            "(quote (+ 1 2)) (assert (= 1 1)) (let ((+ -)) (+ 1 2)) 6"
+        );
+        assert_eq!(
+            optimized("(defmacro (m x) `',x) (m (+ 1 2))"),
+            "(defmacro (m x) (quasiquote (quote (unquote x)))) (m (+ 1 2))"
+        );
+        assert_eq!(
+            optimized("(define (max . xs) 0) (max 1 2) ((lambda (x) (lambda () x)) 1)"),
+            "(define (max . xs) 0) (max 1 2) ((lambda (x) (lambda () x)) 1)"
+        );
+    }
+
+    #[test]
+    fn test_same_results() {
+        let program = "
+            (define (scale x) (* x (/ 10 4)))
+            (define (pick n) (if (= 1 1.0) ((lambda (a) (+ a n)) 2) 'never))
+            (list (scale 2) (pick 3) ((lambda (x) (if x 'yes 'no)) #f))";
+        let expected = crate::eval::eval_str(program, &global_env()).unwrap();
+        let mut result = Object::Void;
+        let env = global_env();
+        for form in optimize_forms(parse(program).unwrap()) {
+            result = crate::vm::eval(&form, &env).unwrap();
+        }
+
+        assert_eq!(result.to_string(), expected.to_string());
+        assert_eq!(result.to_string(), "(5 5 no)");
+    }
+}
@@ -0,0 +1,441 @@
+//! `par-map`, which maps a procedure over a list on several threads, for
+//! CPU-bound work.
+//!
+//! Values are reference counted with `Rc` and cannot be shared between
+//! threads, so each worker thread runs an interpreter of its own and gets
+//! copies: of the items, which must be plain data like the results, and of
+//! the procedure, rebuilt from its source. The variables the procedure uses
+//! are copied along with it, when they are bound to data, builtins or other
+//! procedures written in Lisp; procedures registered by the host and other
+//! values are not copied, and using one is an error. Rate limiters are the
+//! exception, being safe to share: workers use the limiter itself.
+//!
+//! Each procedure is rebuilt in a copy of the frames its variables were
+//! found in, so that two procedures using variables of the same name bound
+//! in different places, such as a global and a `let` around the call, each
+//! see their own. The global frame is the worker's global environment. As
+//! the workers only see copies, changes they make to variables are lost,
+//! and the limits and interrupt handle of an `Interpreter` do not apply to
+//! them.
+
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::num::NonZero;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use crate::builtins::global_env;
+use crate::bytecode::{decode_value, encode_value, is_data};
+use crate::env::Env;
+use crate::eval::{apply, eval, EvalError};
+use crate::gc;
+use crate::limits;
+use crate::object::{Lambda, Object};
+use crate::ratelimit;
+use crate::symbol::Symbol;
+
+/// Marks the definition of a shared rate limiter, `(#:rate-limiter id)`,
+/// which workers look up rather than evaluate.
+const SHARED_LIMITER: &str = "#:rate-limiter";
+
+/// Marks a procedure, `(#:closure frame . source)`, which workers evaluate
+/// in their copy of the frame it was made in.
+const CLOSURE: &str = "#:closure";
+
+/// The encoded result of each item, or the error applying `f` to it, once
+/// a worker has.
+type Results = Mutex<Vec<Option<Result<Vec<u8>, String>>>>;
+
+fn error(message: impl std::fmt::Display) -> EvalError {
+    EvalError::new(format!("par-map: {}", message))
+}
+
+/// `(lambda params body...)`, the source `lambda` was evaluated from.
+fn source(lambda: &Lambda) -> Object {
+    let rest = lambda.rest.map_or(Object::Nil, Object::Symbol);
+    let params = lambda.params.iter().rev().fold(rest, |tail, param| {
+        Object::cons(Object::Symbol(*param), tail)
+    });
+
+    Object::cons(
+        Object::symbol("lambda"),
+        Object::cons(params, Object::list(lambda.body.iter().cloned())),
+    )
+}
+
+fn symbols(obj: &Object, found: &mut Vec<Symbol>) {
+    let mut obj = obj.clone();
+    loop {
+        match obj {
+            Object::Symbol(name) => return found.push(name),
+            Object::Pair(pair) => {
+                symbols(&pair.car(), found);
+                obj = pair.cdr();
+            }
+            _ => return,
+        }
+    }
+}
+
+/// What a procedure needs to be rebuilt on a worker: the frames its
+/// variables are bound in, as `(frame . parent)` with `#f` for the global
+/// frame's parent, and the variables, as `(frame name . value)`.
+#[derive(Default)]
+struct Capture {
+    frames: Vec<Rc<RefCell<Env>>>,
+    parents: Vec<Object>,
+    seen: HashSet<(usize, Symbol)>,
+    definitions: Vec<Object>,
+}
+
+impl Capture {
+    /// The number of `env` among the frames, adding it and its parents,
+    /// parents first, if they are not there yet.
+    fn frame(&mut self, env: &Rc<RefCell<Env>>) -> usize {
+        if let Some(id) = self.frames.iter().position(|frame| Rc::ptr_eq(frame, env)) {
+            return id;
+        }
+        let parent = env.borrow().parent().cloned();
+        let parent = match parent {
+            Some(parent) => Object::Integer(self.frame(&parent) as i64),
+            None => Object::Bool(false),
+        };
+        self.frames.push(env.clone());
+        let id = self.frames.len() - 1;
+        self.parents
+            .push(Object::cons(Object::Integer(id as i64), parent));
+        id
+    }
+
+    /// `(#:closure frame . source)` for `lambda`, capturing the variables it
+    /// uses.
+    fn closure(&mut self, lambda: &Lambda) -> Result<Object, EvalError> {
+        let made_in = self.frame(&lambda.env);
+        let mut used = Vec::new();
+        for form in &lambda.body {
+            symbols(form, &mut used);
+        }
+
+        for name in used {
+            if lambda.params.contains(&name) || lambda.rest == Some(name) {
+                continue;
+            }
+            // Special forms, and variables bound inside the body, are bound
+            // in no frame.
+            let Some(bound_in) = bound_in(&lambda.env, name) else {
+                continue;
+            };
+            let id = self.frame(&bound_in);
+            if !self.seen.insert((id, name)) {
+                continue;
+            }
+            let Some(value) = bound_in.borrow().get(name) else {
+                continue;
+            };
+            let value = match value {
+                // Every worker has the standard builtins.
+                Object::Builtin(builtin)
+                    if name == builtin.name && bound_in.borrow().parent().is_none() =>
+                {
+                    continue
+                }
+                Object::Builtin(builtin) => Object::symbol(builtin.name),
+                Object::Lambda(inner) => self.closure(&inner)?,
+                Object::RateLimiter(limiter) => Object::list([
+                    Object::symbol(SHARED_LIMITER),
+                    Object::Integer(ratelimit::share(&limiter) as i64),
+                ]),
+                value if is_data(&value) => Object::list([Object::symbol("quote"), value]),
+                value => {
+                    return Err(error(format!(
+                        "cannot copy {}, a {}, to another thread",
+                        name,
+                        value.type_name()
+                    )))
+                }
+            };
+            self.definitions.push(Object::cons(
+                Object::Integer(id as i64),
+                Object::cons(Object::Symbol(name), value),
+            ));
+        }
+
+        Ok(Object::cons(
+            Object::symbol(CLOSURE),
+            Object::cons(Object::Integer(made_in as i64), source(lambda)),
+        ))
+    }
+}
+
+/// The frame of `env` or its parents binding `name`.
+fn bound_in(env: &Rc<RefCell<Env>>, name: Symbol) -> Option<Rc<RefCell<Env>>> {
+    let mut frame = env.clone();
+    loop {
+        if frame.borrow().binds(name) {
+            return Some(frame);
+        }
+        let parent = frame.borrow().parent().cloned()?;
+        frame = parent;
+    }
+}
+
+/// Evaluates a value captured by `Capture` on a worker, whose global
+/// environment is `env` and whose copies of the frames are `frames`.
+fn rebuild(
+    value: &Object,
+    env: &Rc<RefCell<Env>>,
+    frames: &[Rc<RefCell<Env>>],
+) -> Result<Object, EvalError> {
+    match value.to_vec().as_deref() {
+        Some([Object::Symbol(head), Object::Integer(id)]) if *head == SHARED_LIMITER => {
+            ratelimit::shared(*id as u64)
+                .map(Object::RateLimiter)
+                .ok_or_else(|| error("a shared rate limiter is gone"))
+        }
+        Some([Object::Symbol(head), Object::Integer(id), ..]) if *head == CLOSURE => {
+            let Object::Pair(pair) = value else {
+                unreachable!("a list of three or more items is a pair")
+            };
+            let Object::Pair(rest) = pair.cdr() else {
+                unreachable!("a list of three or more items has a cdr")
+            };
+            let frame = frames
+                .get(*id as usize)
+                .ok_or_else(|| error("a captured frame is missing"))?;
+            eval(&rest.cdr(), frame)
+        }
+        _ => eval(value, env),
+    }
+}
+
+fn encode(obj: &Object) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    encode_value(&mut bytes, obj);
+    bytes
+}
+
+fn decode(bytes: &[u8]) -> Object {
+    // Only ever given what `encode` wrote.
+    decode_value(bytes).map_or(Object::Void, |(obj, _)| obj)
+}
+
+/// Runs the procedure `program` defines on the items left, on one worker.
+fn work(
+    program: &[u8],
+    items: &[Vec<u8>],
+    next: &AtomicUsize,
+    failed: &AtomicBool,
+    results: &Results,
+) {
+    let env = global_env();
+    let program = decode(program).to_vec();
+    let Some([parents, definitions, func]) = program.as_deref() else {
+        return;
+    };
+    let mut frames = Vec::new();
+    for parent in parents.to_vec().unwrap_or_default() {
+        let frame = match parent {
+            Object::Pair(pair) => match pair.cdr() {
+                Object::Integer(id) => match frames.get(id as usize) {
+                    Some(parent) => {
+                        let frame = Rc::new(RefCell::new(Env::extend(Rc::clone(parent))));
+                        gc::track(&frame);
+                        frame
+                    }
+                    None => return,
+                },
+                _ => env.clone(),
+            },
+            _ => return,
+        };
+        frames.push(frame);
+    }
+    let func = definitions
+        .to_vec()
+        .unwrap_or_default()
+        .into_iter()
+        .try_for_each(|definition| {
+            let Some((Object::Integer(id), Object::Pair(binding))) = (match &definition {
+                Object::Pair(pair) => Some((pair.car(), pair.cdr())),
+                _ => None,
+            }) else {
+                return Ok(());
+            };
+            let value = rebuild(&binding.cdr(), &env, &frames)?;
+            if let (Object::Symbol(name), Some(frame)) = (binding.car(), frames.get(id as usize)) {
+                frame.borrow_mut().define(name, value);
+            }
+            Ok(())
+        })
+        .and_then(|()| match func {
+            Object::Symbol(_) => eval(func, &env),
+            closure => rebuild(closure, &env, &frames),
+        });
+
+    while !failed.load(Ordering::Relaxed) {
+        let i = next.fetch_add(1, Ordering::Relaxed);
+        let Some(item) = items.get(i) else {
+            return;
+        };
+
+        let result = match &func {
+            Ok(func) => apply(func, vec![decode(item)]).map_err(|e| e.message().to_string()),
+            Err(e) => Err(e.message().to_string()),
+        }
+        .and_then(|result| match is_data(&result) {
+            true => Ok(encode(&result)),
+            false => Err(format!(
+                "cannot copy a {} back from another thread",
+                result.type_name()
+            )),
+        });
+        if result.is_err() {
+            failed.store(true, Ordering::Relaxed);
+        }
+        results.lock().unwrap_or_else(|e| e.into_inner())[i] = Some(result);
+    }
+}
+
+/// `(par-map f list)` is `(map f list)`, with `f` applied on several
+/// threads at once.
+pub fn par_map(args: &[Object]) -> Result<Object, EvalError> {
+    let (func, items) = match args {
+        [func, list] => match list.to_vec() {
+            Some(items) => (func, items),
+            None => return Err(error(format!("expects a list, got {}", list))),
+        },
+        _ => return Err(EvalError::new("par-map expects a procedure and a list")),
+    };
+
+    let mut capture = Capture::default();
+    let func = match func {
+        Object::Lambda(lambda) => capture.closure(lambda)?,
+        Object::Builtin(builtin) => Object::symbol(builtin.name),
+        other => {
+            return Err(error(format!(
+                "cannot copy a {} to another thread",
+                other.type_name()
+            )))
+        }
+    };
+    let program = Object::list([
+        Object::list(capture.parents),
+        Object::list(capture.definitions),
+        func,
+    ]);
+    if !is_data(&program) {
+        return Err(error("cannot copy the procedure to another thread"));
+    }
+    let program = encode(&program);
+
+    let items = items
+        .iter()
+        .map(|item| match is_data(item) {
+            true => Ok(encode(item)),
+            false => Err(error(format!(
+                "cannot copy a {} to another thread",
+                item.type_name()
+            ))),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let workers = thread::available_parallelism()
+        .map_or(1, NonZero::get)
+        .min(items.len());
+    let next = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let results: Results = Mutex::new(vec![None; items.len()]);
+    thread::scope(|scope| {
+        let workers = (0..workers)
+            .map(|_| {
+                limits::thread_builder()
+                    .spawn_scoped(scope, || work(&program, &items, &next, &failed, &results))
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| error(format!("cannot start a thread: {}", e)))?;
+        for worker in workers {
+            worker
+                .join()
+                .map_err(|_| error("a worker thread panicked"))?;
+        }
+        Ok(())
+    })?;
+
+    // The first error in the list is reported, whichever thread saw it first.
+    let results = results
+        .into_inner()
+        .unwrap_or_else(|e| e.into_inner())
+        .into_iter()
+        .take_while(|result| result.is_some())
+        .flatten()
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(error)?;
+    if results.len() < items.len() {
+        return Err(error("a worker thread stopped early"));
+    }
+
+    Ok(Object::list(results.iter().map(|bytes| decode(bytes))))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builtins::global_env;
+    use crate::eval::eval_str;
+
+    fn eval(program: &str) -> String {
+        match eval_str(program, &global_env()) {
+            Ok(value) => value.to_string(),
+            Err(e) => e.message().to_string(),
+        }
+    }
+
+    #[test]
+    fn test_par_map() {
+        assert_eq!(
+            eval(
+                "(define k 10)
+                 (define (fib n) (if (< n 2) n (+ (fib (- n 1)) (fib (- n 2)))))
+                 (par-map (lambda (n) (+ k (fib n))) '(1 2 3 4 5 6 7 8 9 10))"
+            ),
+            "(11 11 12 13 15 18 23 31 44 65)"
+        );
+        assert_eq!(eval("(par-map car '((1) (2 3)))"), "(1 2)");
+        assert_eq!(
+            eval(
+                "(define limiter (make-rate-limiter 1000))
+                 (par-map (lambda (n) (acquire limiter) n) '(1 2 3))"
+            ),
+            "(1 2 3)"
+        );
+        assert_eq!(eval("(par-map car '())"), "()");
+        // Variables of the same name bound in different places each keep
+        // their own value.
+        assert_eq!(
+            eval(
+                "(define n 100)
+                 (define (add1 x) (+ x n))
+                 (let ((n 1) (first car))
+                   (par-map (lambda (x) (+ n (add1 x) (first '(0)))) '(1 2)))"
+            ),
+            "(102 103)"
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            eval("(par-map (lambda (x) (car x)) '((1) 2 3))"),
+            "par-map: car expects a pair, got 2"
+        );
+        assert_eq!(
+            eval("(define table (make-hash-table)) (par-map (lambda (x) table) '(1))"),
+            "par-map: cannot copy table, a hash-table, to another thread"
+        );
+        assert_eq!(
+            eval("(par-map (lambda (x) car) '(1))"),
+            "par-map: cannot copy a procedure back from another thread"
+        );
+    }
+}
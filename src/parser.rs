@@ -0,0 +1,798 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fmt::Formatter;
+use std::rc::Rc;
+use std::vec::IntoIter;
+
+use crate::bigint::BigInt;
+use crate::diagnostic::{Diagnostic, Span};
+use crate::lexer::{Token, TokenClass, Tokenizer, RESERVED_DISPATCH};
+use crate::object::Object;
+use crate::rational::Rational;
+use crate::symbol::Symbol;
+
+/// A reader macro: makes a value of the text after its dispatch character,
+/// or fails with a message.
+pub type ReaderMacro = dyn Fn(&str) -> Result<Object, String>;
+
+/// What a host adds to the syntax: keywords for `highlight` to show as it
+/// shows the special forms, and reader macros, each run on the text after
+/// `#` and its dispatch character:
+///
+/// ```text
+/// let reader = ReaderConfig::new()
+///     .keyword("defroute")
+///     .dispatch('d', |text| date(text).map_err(|e| e.to_string()));
+/// let interpreter = Interpreter::new().with_reader(reader);
+/// ```
+///
+/// Parsing uses the configuration installed with `with_reader`, and the
+/// ordinary syntax otherwise.
+#[derive(Default)]
+pub struct ReaderConfig {
+    keywords: Vec<String>,
+    macros: Vec<(char, Box<ReaderMacro>)>,
+}
+
+impl ReaderConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a keyword.
+    pub fn keyword(mut self, name: &str) -> Self {
+        self.keywords.push(name.to_string());
+        self
+    }
+
+    /// Reads `#c...` with `read`, in place of the meaning `#c` had, if any.
+    /// Defining a macro for `c` again replaces it.
+    ///
+    /// Panics if `c` is one of `lexer::RESERVED_DISPATCH`, or a character
+    /// that ends a token.
+    pub fn dispatch(
+        mut self,
+        c: char,
+        read: impl Fn(&str) -> Result<Object, String> + 'static,
+    ) -> Self {
+        assert!(
+            !RESERVED_DISPATCH.contains(&c) && !c.is_whitespace() && !"()\"'`,;".contains(c),
+            "#{} cannot have a reader macro",
+            c
+        );
+        self.macros.retain(|(dispatch, _)| *dispatch != c);
+        self.macros.push((c, Box::new(read)));
+        self
+    }
+
+    /// A tokenizer of `input` reading the dispatch characters of this
+    /// configuration and classifying its keywords.
+    pub fn tokenizer(&self, input: &str) -> Tokenizer {
+        self.configure(Tokenizer::new(input))
+    }
+
+    pub(crate) fn configure(&self, tokenizer: Tokenizer) -> Tokenizer {
+        let dispatch = self.macros.iter().map(|(c, _)| *c).collect::<Vec<_>>();
+        let keywords = self.keywords.iter().map(String::as_str).collect::<Vec<_>>();
+        tokenizer.with_dispatch(&dispatch).with_keywords(&keywords)
+    }
+
+    /// Classifies every byte of `input`, as `lexer::highlight` does, with
+    /// the keywords and literals of this configuration.
+    pub fn highlight(&self, input: &str) -> Vec<(Span, TokenClass)> {
+        self.tokenizer(input).highlight()
+    }
+
+    fn read(&self, c: char, text: &str) -> Result<Object, String> {
+        match self.macros.iter().find(|(dispatch, _)| *dispatch == c) {
+            Some((_, read)) => read(text),
+            None => Err(String::from("no reader macro is defined for it")),
+        }
+    }
+}
+
+thread_local! {
+    static READER: RefCell<Option<Rc<ReaderConfig>>> = const { RefCell::new(None) };
+}
+
+/// Runs `f` with every parse on this thread using `reader`.
+pub fn with_reader<T>(reader: &Rc<ReaderConfig>, f: impl FnOnce() -> T) -> T {
+    struct Restore(Option<Rc<ReaderConfig>>);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            READER.with(|current| *current.borrow_mut() = self.0.take());
+        }
+    }
+
+    let _restore = Restore(READER.with(|current| current.replace(Some(reader.clone()))));
+    f()
+}
+
+fn reader() -> Option<Rc<ReaderConfig>> {
+    READER.with(|current| current.borrow().clone())
+}
+
+/// `tokenizer` configured by the reader in use, if any.
+pub(crate) fn configured(tokenizer: Tokenizer) -> Tokenizer {
+    match reader() {
+        Some(reader) => reader.configure(tokenizer),
+        None => tokenizer,
+    }
+}
+
+pub fn parse(program: &str) -> Result<Vec<Object>, ParseError> {
+    parse_with_spans(program).map(|forms| forms.into_iter().map(|(form, _)| form).collect())
+}
+
+/// Parses `program` into its top-level forms, each with its span.
+pub fn parse_with_spans(program: &str) -> Result<Vec<(Object, Span)>, ParseError> {
+    parse_spanned(program, false).map(|(forms, _)| forms)
+}
+
+/// The spans of the lists of some parsed source, by the address of their
+/// first pair, and those of the elements of lists, by the address of the
+/// pair holding each. Only the lists that are still alive can be looked up.
+#[derive(Default)]
+pub(crate) struct ListSpans {
+    lists: HashMap<usize, Span>,
+    cars: HashMap<usize, Span>,
+}
+
+impl ListSpans {
+    pub(crate) fn get(&self, list: &Object) -> Option<Span> {
+        match list {
+            Object::Pair(pair) => self.lists.get(&(Rc::as_ptr(pair) as usize)).copied(),
+            _ => None,
+        }
+    }
+
+    /// The spans of all the lists.
+    pub(crate) fn spans(&self) -> impl Iterator<Item = Span> + '_ {
+        self.lists.values().copied()
+    }
+
+    /// The span of the car of `pair`, an element of a list.
+    pub(crate) fn car(&self, pair: &Object) -> Option<Span> {
+        match pair {
+            Object::Pair(pair) => self.cars.get(&(Rc::as_ptr(pair) as usize)).copied(),
+            _ => None,
+        }
+    }
+}
+
+/// Parses `program` like `parse_with_spans`, also returning the span of
+/// every list in it.
+pub(crate) fn parse_with_list_spans(
+    program: &str,
+) -> Result<(Vec<(Object, Span)>, ListSpans), ParseError> {
+    parse_spanned(program, true)
+}
+
+fn parse_spanned(
+    program: &str,
+    list_spans: bool,
+) -> Result<(Vec<(Object, Span)>, ListSpans), ParseError> {
+    let tokens = configured(Tokenizer::new(program))
+        .spanned_tokens()
+        .map_err(|e| ParseError::at(e.message(), e.span()))?;
+    let mut parser = Parser::new(tokens.into_iter().map(|(token, span)| (token, Some(span))));
+    if list_spans {
+        parser.lists = Some(ListSpans::default());
+    }
+    let mut forms = Vec::new();
+
+    while let Some((form, span)) = parser.next_form()? {
+        forms.push((form, span.unwrap_or(Span::new(0, 0))));
+    }
+
+    Ok((forms, parser.lists.unwrap_or_default()))
+}
+
+/// How deeply lists and quoted forms may nest. Parsing a level takes a few
+/// stack frames, so without a limit a long enough run of `(` would overflow
+/// the stack, even that of a thread; no program written by hand comes near
+/// it.
+pub const MAX_DEPTH: usize = 256;
+
+/// The name of the symbol `parse_with_recovery` puts in place of a form it
+/// could not parse. No source text reads as this symbol.
+pub const PLACEHOLDER: &str = "#<error>";
+
+/// Parses as much of `program` as it can, for tools that want every error
+/// in a file and whatever forms can still be made out. An invalid token or
+/// a misplaced `)` or `.` becomes the `PLACEHOLDER` symbol, and lists left
+/// open at the end of the input are closed. The diagnostics are in source
+/// order.
+pub fn parse_with_recovery(program: &str) -> (Vec<(Object, Span)>, Vec<Diagnostic>) {
+    let (tokens, token_errors) = configured(Tokenizer::new(program)).tokens_with_recovery();
+    let mut errors = token_errors
+        .iter()
+        .map(|e| ParseError::at(e.message(), e.span()))
+        .collect::<Vec<_>>();
+
+    // Each invalid token leaves a placeholder where it was.
+    let placeholders = token_errors
+        .iter()
+        .filter_map(|e| e.span())
+        .map(|span| (Token::Symbol(Symbol::intern(PLACEHOLDER)), Some(span)));
+    let mut tokens = tokens
+        .into_iter()
+        .map(|(token, span)| (token, Some(span)))
+        .chain(placeholders)
+        .collect::<Vec<_>>();
+    tokens.sort_by_key(|(_, span)| span.map(|span| span.start));
+
+    let mut parser = Parser::new(tokens.into_iter());
+    parser.errors = Some(Vec::new());
+    let mut forms = Vec::new();
+    loop {
+        match parser.next_form() {
+            Ok(Some((form, span))) => forms.push((form, span.unwrap_or(Span::new(0, 0)))),
+            Ok(None) => break,
+            // Only nesting too deep is not recovered from.
+            Err(e) => {
+                errors.push(e);
+                break;
+            }
+        }
+    }
+
+    errors.extend(parser.errors.unwrap_or_default());
+    errors.sort_by_key(|e| e.span.map(|span| span.start));
+    let diagnostics = errors.iter().map(|e| e.diagnostic(program)).collect();
+
+    (forms, diagnostics)
+}
+
+/// Parses already lexed tokens, e.g. those collected from an incremental
+/// tokenizer. Their errors have no spans.
+pub fn parse_tokens(tokens: Vec<Token>) -> Result<Vec<Object>, ParseError> {
+    let mut parser = Parser::new(tokens.into_iter().map(|token| (token, None)));
+    let mut forms = Vec::new();
+
+    while let Some((form, _)) = parser.next_form()? {
+        forms.push(form);
+    }
+
+    Ok(forms)
+}
+
+#[derive(Debug)]
+pub struct ParseError {
+    err: String,
+    span: Option<Span>,
+    hint: Option<String>,
+    /// Whether the input went over the `reader::Limits` it was read within.
+    over_limit: bool,
+}
+
+impl ParseError {
+    pub(crate) fn new(err: impl Into<String>) -> Self {
+        Self {
+            err: err.into(),
+            span: None,
+            hint: None,
+            over_limit: false,
+        }
+    }
+
+    pub(crate) fn over_limit(err: impl Into<String>) -> Self {
+        Self {
+            over_limit: true,
+            ..Self::new(err)
+        }
+    }
+
+    pub(crate) fn is_over_limit(&self) -> bool {
+        self.over_limit
+    }
+
+    fn at(err: impl Into<String>, span: Option<Span>) -> Self {
+        Self {
+            span,
+            ..Self::new(err)
+        }
+    }
+
+    fn with_hint(mut self, hint: impl Into<String>) -> Self {
+        self.hint = Some(hint.into());
+        self
+    }
+
+    /// What is wrong, without where.
+    pub fn message(&self) -> &str {
+        &self.err
+    }
+
+    /// Where in the source the error is, if known.
+    pub fn span(&self) -> Option<Span> {
+        self.span
+    }
+
+    /// The error as a diagnostic pointing into `source`, the parsed text.
+    pub fn diagnostic(&self, source: &str) -> Diagnostic {
+        let mut diagnostic = Diagnostic::error(&self.err);
+        if let Some(span) = self.span {
+            diagnostic = diagnostic.at(source, span);
+        }
+        if let Some(hint) = &self.hint {
+            diagnostic = diagnostic.with_hint(hint);
+        }
+
+        diagnostic
+    }
+}
+
+impl Error for ParseError {}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "Parse error: {}", self.err)
+    }
+}
+
+struct Parser {
+    tokens: IntoIter<(Token, Option<Span>)>,
+    /// The span of the token read last.
+    last: Option<Span>,
+    /// The errors recovered from, when recovering.
+    errors: Option<Vec<ParseError>>,
+    /// The spans of the lists parsed, when wanted.
+    lists: Option<ListSpans>,
+    /// How many lists and quoted forms the one being parsed is in.
+    depth: usize,
+}
+
+impl Parser {
+    fn new(tokens: impl Iterator<Item = (Token, Option<Span>)>) -> Self {
+        Self {
+            tokens: tokens
+                .filter(|(token, _)| !matches!(token, Token::Comment(_)))
+                .collect::<Vec<_>>()
+                .into_iter(),
+            last: None,
+            errors: None,
+            lists: None,
+            depth: 0,
+        }
+    }
+
+    /// Records `err` and returns a placeholder when recovering, or fails
+    /// with it otherwise.
+    fn recover(&mut self, err: ParseError) -> Result<Object, ParseError> {
+        match &mut self.errors {
+            Some(errors) => {
+                errors.push(err);
+                Ok(Object::symbol(PLACEHOLDER))
+            }
+            None => Err(err),
+        }
+    }
+
+    fn next_token(&mut self) -> Option<Token> {
+        let (token, span) = self.tokens.next()?;
+        self.last = span;
+
+        Some(token)
+    }
+
+    /// The next form and its span.
+    fn next_form(&mut self) -> Result<Option<(Object, Option<Span>)>, ParseError> {
+        let token = match self.next_token() {
+            Some(token) => token,
+            None => return Ok(None),
+        };
+
+        let start = self.last;
+        let form = self.parse_token(token)?;
+        let span = start.zip(self.last).map(|(start, end)| start.to(end));
+
+        Ok(Some((form, span)))
+    }
+
+    fn parse_token(&mut self, token: Token) -> Result<Object, ParseError> {
+        match token {
+            Token::LeftParenthesis => self.nested(Self::parse_list),
+            Token::RightParenthesis => self.recover(
+                ParseError::at("unexpected ')'", self.last)
+                    .with_hint("there is no '(' for it to close"),
+            ),
+            Token::Dot => self.recover(ParseError::at("unexpected '.'", self.last)),
+            Token::Quote => self.nested(|parser| parser.parse_prefixed("quote")),
+            Token::Quasiquote => self.nested(|parser| parser.parse_prefixed("quasiquote")),
+            Token::Unquote => self.nested(|parser| parser.parse_prefixed("unquote")),
+            Token::UnquoteSplicing => {
+                self.nested(|parser| parser.parse_prefixed("unquote-splicing"))
+            }
+            Token::Integer(n) => Ok(Object::Integer(n)),
+            Token::BigInteger(n) => Ok(Object::from_bigint(n)),
+            Token::Rational(literal) => match parse_rational(&literal) {
+                Some(n) => Ok(Object::from_rational(n)),
+                None => self.recover(ParseError::at(
+                    format!("invalid rational literal: {}", literal),
+                    self.last,
+                )),
+            },
+            Token::Float(n) => Ok(Object::Float(n)),
+            Token::Boolean(b) => Ok(Object::Bool(b)),
+            Token::String(s) => Ok(Object::string(s)),
+            Token::Char(c) => Ok(Object::Char(c)),
+            Token::Symbol(s) => Ok(Object::Symbol(s)),
+            Token::Dispatch(c, text) => {
+                let read = reader().ok_or_else(|| String::from("no reader is in use"));
+                match read.and_then(|reader| reader.read(c, &text)) {
+                    Ok(obj) => Ok(obj),
+                    Err(e) => {
+                        self.recover(ParseError::at(format!("#{}{}: {}", c, text, e), self.last))
+                    }
+                }
+            }
+            Token::Comment(_) => unreachable!("comments are removed by Parser::new"),
+        }
+    }
+
+    /// Parses a list or a quoted form with `parse`, one level deeper.
+    fn nested(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<Object, ParseError>,
+    ) -> Result<Object, ParseError> {
+        if self.depth == MAX_DEPTH {
+            return Err(ParseError::at(
+                format!("nested deeper than {} levels", MAX_DEPTH),
+                self.last,
+            ));
+        }
+
+        self.depth += 1;
+        let form = parse(self);
+        self.depth -= 1;
+        form
+    }
+
+    /// The form after a quote or one of its relatives, as `(name form)`.
+    fn parse_prefixed(&mut self, name: &str) -> Result<Object, ParseError> {
+        let prefix = self.last;
+        let quoted = match self.next_token() {
+            Some(token) => self.parse_token(token)?,
+            None => self.recover(ParseError::at(
+                format!("expected a form after {}", name),
+                prefix,
+            ))?,
+        };
+
+        Ok(Object::list(vec![Object::symbol(name), quoted]))
+    }
+
+    fn parse_list(&mut self) -> Result<Object, ParseError> {
+        let open = self.last;
+        let mut items = Vec::new();
+        let mut spans = Vec::new();
+
+        loop {
+            match self.next_token() {
+                Some(Token::RightParenthesis) => {
+                    return Ok(self.spanned(Object::list(items), open, spans))
+                }
+                Some(Token::Dot) if !items.is_empty() => {
+                    let list = self.parse_dotted_tail(items, open)?;
+                    return Ok(self.spanned(list, open, spans));
+                }
+                Some(token) => {
+                    let start = self.last;
+                    items.push(self.parse_token(token)?);
+                    if self.lists.is_some() {
+                        spans.push(start.zip(self.last).map(|(start, end)| start.to(end)));
+                    }
+                }
+                None => {
+                    self.recover(missing_parenthesis(open))?;
+                    return Ok(Object::list(items));
+                }
+            }
+        }
+    }
+
+    /// Records the span of `list`, from `open` to the last token, and the
+    /// `spans` of its elements, when recording them.
+    fn spanned(&mut self, list: Object, open: Option<Span>, spans: Vec<Option<Span>>) -> Object {
+        let Some(lists) = &mut self.lists else {
+            return list;
+        };
+        if let (Object::Pair(pair), Some(open), Some(close)) = (&list, open, self.last) {
+            lists
+                .lists
+                .insert(Rc::as_ptr(pair) as usize, open.to(close));
+        }
+        let mut current = list.clone();
+        for span in spans {
+            let Object::Pair(pair) = current else { break };
+            if let Some(span) = span {
+                lists.cars.insert(Rc::as_ptr(&pair) as usize, span);
+            }
+            current = pair.cdr();
+        }
+
+        list
+    }
+
+    /// Finishes `(a b . tail)` once the dot has been read. `open` is the
+    /// span of the opening parenthesis.
+    fn parse_dotted_tail(
+        &mut self,
+        items: Vec<Object>,
+        open: Option<Span>,
+    ) -> Result<Object, ParseError> {
+        let dot = self.last;
+        let tail = match self.next_token() {
+            Some(Token::RightParenthesis | Token::Dot) | None => None,
+            Some(token) => Some(self.parse_token(token)?),
+        };
+
+        match (tail, self.next_token()) {
+            (Some(tail), Some(Token::RightParenthesis)) => Ok(items
+                .into_iter()
+                .rev()
+                .fold(tail, |tail, item| Object::cons(item, tail))),
+            (tail, next) => {
+                self.recover(ParseError::at("expected a single form after '.'", dot))?;
+
+                // Skip the rest of the list.
+                let mut next = next;
+                loop {
+                    match next {
+                        Some(Token::RightParenthesis) => break,
+                        Some(token) => self.parse_token(token)?,
+                        None => {
+                            self.recover(missing_parenthesis(open))?;
+                            break;
+                        }
+                    };
+                    next = self.next_token();
+                }
+
+                let mut items = items;
+                items.extend(tail);
+                items.push(Object::symbol(PLACEHOLDER));
+                Ok(Object::list(items))
+            }
+        }
+    }
+}
+
+fn missing_parenthesis(open: Option<Span>) -> ParseError {
+    ParseError::at("missing ')'", open).with_hint("the list opened here is never closed")
+}
+
+fn parse_rational(literal: &str) -> Option<Rational> {
+    let (numerator, denominator) = literal.split_once('/')?;
+
+    Rational::new(BigInt::parse(numerator)?, BigInt::parse(denominator)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nested_list() {
+        let forms = parse("(define (sq x) (* x x)) 42").unwrap();
+
+        assert_eq!(
+            forms,
+            vec![
+                Object::list(vec![
+                    Object::symbol("define"),
+                    Object::list(vec![Object::symbol("sq"), Object::symbol("x")]),
+                    Object::list(vec![
+                        Object::symbol("*"),
+                        Object::symbol("x"),
+                        Object::symbol("x"),
+                    ]),
+                ]),
+                Object::Integer(42),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rational_literals() {
+        let forms = parse("1/3 4/2").unwrap();
+        assert_eq!(forms[0].to_string(), "1/3");
+        assert_eq!(forms[1], Object::Integer(2));
+
+        assert!(parse("1/0").is_err());
+        assert!(parse("1/2/3").is_err());
+    }
+
+    #[test]
+    fn test_dotted_pairs() {
+        let forms = parse("(a . b) (1 2 . 3) (a . (b c))").unwrap();
+        assert_eq!(
+            forms[0],
+            Object::cons(Object::symbol("a"), Object::symbol("b"))
+        );
+        assert_eq!(forms[1].to_string(), "(1 2 . 3)");
+        assert_eq!(forms[2].to_string(), "(a b c)");
+
+        assert!(parse("(. a)").is_err());
+        assert!(parse("(a .)").is_err());
+        assert!(parse("(a . b c)").is_err());
+    }
+
+    #[test]
+    fn test_prefixed_forms() {
+        let forms = parse("'a `(b ,c ,@d)").unwrap();
+        assert_eq!(forms[0].to_string(), "(quote a)");
+        assert_eq!(
+            forms[1].to_string(),
+            "(quasiquote (b (unquote c) (unquote-splicing d)))"
+        );
+
+        assert!(parse("(a ,)").is_err());
+    }
+
+    #[test]
+    fn test_unbalanced_parentheses() {
+        assert!(parse("(+ 1 2").is_err());
+        assert!(parse("(+ 1 2))").is_err());
+    }
+
+    #[test]
+    fn test_spans() {
+        let forms = parse_with_spans("42 (f 'x\n  (g))").unwrap();
+        assert_eq!(forms[0].1, Span::new(0, 2));
+        assert_eq!(forms[1].1, Span::new(3, 15));
+
+        let err = parse("(+ 1 2)\n(define (f x)\n  x").unwrap_err();
+        assert_eq!(err.span(), Some(Span::new(8, 9)));
+        assert_eq!(
+            err.hint.as_deref(),
+            Some("the list opened here is never closed")
+        );
+
+        let source = "(a))";
+        let err = parse(source).unwrap_err();
+        assert_eq!(err.span(), Some(Span::new(3, 4)));
+        assert_eq!(err.diagnostic(source).line_column(), Some((1, 4)));
+
+        let err = parse("(a @b)").unwrap_err();
+        assert_eq!(err.span(), Some(Span::new(3, 4)));
+    }
+
+    #[test]
+    fn test_recovery() {
+        let source = "(define x 1/0)\n(f @ 2))\n(g '(a . b c) .\n(h (i 1)";
+        let (forms, diagnostics) = parse_with_recovery(source);
+
+        let forms = forms
+            .iter()
+            .map(|(form, _)| form.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            forms,
+            [
+                "(define x #<error>)",
+                "(f #<error> 2)",
+                "#<error>",
+                "(g (quote (a b #<error>)) (h (i 1)) #<error>)",
+            ]
+        );
+
+        let messages = diagnostics
+            .iter()
+            .map(|d| (d.line_column().unwrap(), d.message.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            [
+                ((1, 11), "invalid rational literal: 1/0"),
+                ((2, 4), "unexpected character '@'"),
+                ((2, 8), "unexpected ')'"),
+                ((3, 1), "missing ')'"),
+                ((3, 8), "expected a single form after '.'"),
+                ((3, 15), "expected a single form after '.'"),
+                ((4, 1), "missing ')'"),
+            ]
+        );
+
+        let (forms, diagnostics) = parse_with_recovery("(a b) 'c");
+        assert_eq!(forms.len(), 2);
+        assert!(diagnostics.is_empty());
+    }
+
+    #[test]
+    fn test_nesting_limit() {
+        let nested = |depth| format!("{}{}", "(".repeat(depth), ")".repeat(depth));
+        assert!(parse(&nested(MAX_DEPTH)).is_ok());
+
+        let too_deep = format!("nested deeper than {} levels", MAX_DEPTH);
+        for program in [
+            nested(MAX_DEPTH + 1),
+            "(".repeat(100_000),
+            "'".repeat(100_000) + "a",
+        ] {
+            assert_eq!(parse(&program).unwrap_err().message(), too_deep);
+            let (_, diagnostics) = parse_with_recovery(&program);
+            assert_eq!(diagnostics.last().unwrap().message, too_deep);
+        }
+    }
+
+    /// Parses inputs made of the characters that mean most to the lexer,
+    /// none of which may panic; `fuzz/` has targets for a fuzzer to look
+    /// further.
+    #[test]
+    fn test_any_input() {
+        let alphabet = "()'`,@.#\\\";0123456789eExXbo/+-:tfaλ٣½é \n\0"
+            .chars()
+            .collect::<Vec<_>>();
+        let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+        for _ in 0..20_000 {
+            let mut program = String::new();
+            for _ in 0..state % 16 {
+                // A xorshift generator, enough to mix the characters.
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                program.push(alphabet[(state >> 32) as usize % alphabet.len()]);
+            }
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+
+            let _ = parse(&program);
+            let _ = parse_with_recovery(&program);
+        }
+    }
+
+    #[test]
+    fn test_reader_macros() {
+        let reader = Rc::new(
+            ReaderConfig::new()
+                .keyword("defroute")
+                .dispatch('d', |text| {
+                    let parts = text
+                        .split('-')
+                        .map(|part| part.parse().map(Object::Integer))
+                        .collect::<Result<Vec<_>, _>>()
+                        .map_err(|_| String::from("expected a date"))?;
+                    Ok(Object::list(parts))
+                }),
+        );
+
+        let program = "(defroute \"/\" #d2024-01-02 #t)";
+        assert_eq!(
+            format!("{:?}", with_reader(&reader, || parse(program))),
+            format!("{:?}", parse("(defroute \"/\" (2024 1 2) #t)"))
+        );
+        assert_eq!(
+            with_reader(&reader, || parse("(f #dtoday)"))
+                .unwrap_err()
+                .message(),
+            "#dtoday: expected a date"
+        );
+        // Outside with_reader, #d is the decimal prefix again.
+        assert_eq!(parse("#d12").unwrap()[0], Object::Integer(12));
+        assert_eq!(
+            parse("#x12 #q1").unwrap_err().message(),
+            "unknown literal #q1"
+        );
+
+        let classes = reader
+            .highlight("(defroute #d2024-01-02)")
+            .into_iter()
+            .map(|(_, class)| class)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            classes,
+            [
+                TokenClass::Parenthesis,
+                TokenClass::Keyword,
+                TokenClass::Whitespace,
+                TokenClass::Literal,
+                TokenClass::Parenthesis
+            ]
+        );
+    }
+}
@@ -0,0 +1,162 @@
+//! `match`, which picks the first clause whose pattern has the shape of a
+//! value and binds the variables in it to the parts they match:
+//!
+//! ```text
+//! (match items
+//!   (() 'empty)
+//!   ((x) x)
+//!   ((x . rest) #:when (> x 0) (+ x (sum rest)))
+//!   (_ 'other))
+//! ```
+//!
+//! `_` matches anything, and any other symbol does as well, binding itself
+//! to what it matched; a variable used twice must match equal values.
+//! Numbers, strings, characters, booleans, `()` and quoted data match the
+//! values equal to them. A list of patterns matches a list of as many
+//! values, and a dotted list one with at least as many, the pattern after
+//! the dot matching the rest. `#:when` after a pattern adds a guard, which
+//! must be true for the clause to be picked and can use its variables.
+//! The body of the clause is evaluated with the variables bound, and the
+//! value of `match` is the value of its last form. It is an error if no
+//! clause matches.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::env::Env;
+use crate::eval::{eval, EvalError};
+use crate::gc;
+use crate::object::Object;
+use crate::quasiquote::form_argument;
+use crate::symbol::Symbol;
+
+type Bindings = Vec<(Symbol, Object)>;
+
+/// Whether `value` matches `pattern`, adding the variables it binds.
+fn bind(pattern: &Object, value: &Object, bindings: &mut Bindings) -> bool {
+    let (mut pattern, mut value) = (pattern.clone(), value.clone());
+    loop {
+        if let Some(datum) = form_argument(&pattern, "quote") {
+            return datum == value;
+        }
+        match (&pattern, &value) {
+            (Object::Symbol(name), _) if *name == "_" => return true,
+            (Object::Symbol(name), _) if !name.as_str().starts_with("#:") => {
+                return match bindings.iter().find(|(bound, _)| bound == name) {
+                    Some((_, bound)) => *bound == value,
+                    None => {
+                        bindings.push((*name, value));
+                        true
+                    }
+                };
+            }
+            (Object::Pair(p), Object::Pair(v)) => {
+                if !bind(&p.car(), &v.car(), bindings) {
+                    return false;
+                }
+                (pattern, value) = (p.cdr(), v.cdr());
+            }
+            (Object::Pair(_), _) => return false,
+            (pattern, value) => return pattern == value,
+        }
+    }
+}
+
+/// Evaluates `(match value clause...)` up to the last form of the clause
+/// it picks, which is returned to be evaluated in tail position, along with
+/// the environment binding the variables of its pattern.
+pub fn match_form(
+    list: &[Object],
+    env: &Rc<RefCell<Env>>,
+) -> Result<(Object, Rc<RefCell<Env>>), EvalError> {
+    let (value, clauses) = match list {
+        [_, value, clauses @ ..] => (eval(value, env)?, clauses),
+        _ => return Err(EvalError::new("match expects a value and clauses")),
+    };
+
+    for clause in clauses {
+        let invalid = || EvalError::new(format!("invalid match clause: {}", clause));
+        let clause = clause.to_vec().ok_or_else(invalid)?;
+        let (pattern, guard, body) = match clause.as_slice() {
+            [pattern, Object::Symbol(when), guard, body @ ..] if *when == "#:when" => {
+                (pattern, Some(guard), body)
+            }
+            [pattern, body @ ..] => (pattern, None, body),
+            [] => return Err(invalid()),
+        };
+        let Some((last, init)) = body.split_last() else {
+            return Err(invalid());
+        };
+
+        let mut bindings = Vec::new();
+        if !bind(pattern, &value, &mut bindings) {
+            continue;
+        }
+        let mut frame = Env::extend(env.clone());
+        for (name, value) in bindings {
+            frame.define(name, value);
+        }
+        let frame = Rc::new(RefCell::new(frame));
+        gc::track(&frame);
+
+        if let Some(guard) = guard {
+            if !eval(guard, &frame)?.is_truthy() {
+                continue;
+            }
+        }
+        for form in init {
+            eval(form, &frame)?;
+        }
+        return Ok((last.clone(), frame));
+    }
+
+    Err(EvalError::new(format!("no match clause matches {}", value)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builtins::global_env;
+    use crate::eval::eval_str;
+
+    #[test]
+    fn test_match() {
+        let env = global_env();
+        let eval = |program: &str| match eval_str(program, &env) {
+            Ok(value) => value.to_string(),
+            Err(e) => e.message().to_string(),
+        };
+
+        eval(
+            "(define (describe x)
+               (match x
+                 (() 'empty)
+                 (0 'zero)
+                 (\"hi\" 'greeting)
+                 ('(a b) 'quoted)
+                 ((a a) 'twice)
+                 ((op x y) #:when (equal? op 'add) (+ x y))
+                 ((x . rest) rest)
+                 (_ 'other)))",
+        );
+        assert_eq!(
+            eval(
+                "(list (describe '()) (describe 0) (describe \"hi\") (describe '(a b))
+                       (describe '(7 7)) (describe '(add 1 2)) (describe '(sub 1 2))
+                       (describe 'x))"
+            ),
+            "(empty zero greeting quoted twice 3 (1 2) other)"
+        );
+        assert_eq!(eval("(match 5 ((x) x))"), "no match clause matches 5");
+        assert_eq!(eval("(match 5 (x))"), "invalid match clause: (x)");
+    }
+
+    #[test]
+    fn test_tail_position() {
+        let env = global_env();
+        let program = "(define (count-down n)
+                         (match n (0 'done) (_ (count-down (- n 1)))))
+                       (count-down 100000)";
+
+        assert_eq!(eval_str(program, &env).unwrap().to_string(), "done");
+    }
+}
@@ -0,0 +1,83 @@
+; The part of the standard library written in Lisp, evaluated in every
+; global environment once the builtins are defined. The docstrings of its
+; procedures are their entries in the reference.
+
+(define (not x)
+  "#t if x is false, and #f otherwise."
+  (if x #f #t))
+
+(define (caar pair) "The car of the car of pair." (car (car pair)))
+(define (cadr pair) "The car of the cdr of pair." (car (cdr pair)))
+(define (cdar pair) "The cdr of the car of pair." (cdr (car pair)))
+(define (cddr pair) "The cdr of the cdr of pair." (cdr (cdr pair)))
+(define (caddr pair)
+  "The car of the cdr of the cdr of pair."
+  (car (cdr (cdr pair))))
+
+(define (length list)
+  "The number of elements of list."
+  (fold-left (lambda (n item) (+ n 1)) 0 list))
+
+(define (reverse list)
+  "The elements of list in reverse order."
+  (fold-left (lambda (reversed item) (cons item reversed)) '() list))
+
+(define (append . lists)
+  "The lists joined together."
+  (fold-right (lambda (list tail) (fold-right cons tail list)) '() lists))
+
+(define (list-tail list k)
+  "list without its first k elements."
+  (if (= k 0) list (list-tail (cdr list) (- k 1))))
+
+(define (list-ref list k)
+  "The element of list at index k."
+  (car (list-tail list k)))
+
+(define (member x list)
+  "The first pair of list whose car is equal to x, or #f."
+  (if (null? list)
+      #f
+      (if (equal? x (car list)) list (member x (cdr list)))))
+
+(define the-empty-stream '())
+
+(define (stream-null? stream)
+  "Whether stream is the empty stream."
+  (null? stream))
+
+(define (stream-pair? stream)
+  "Whether stream is a stream with a first element."
+  (if (pair? stream) (promise? (cdr stream)) #f))
+
+(define (stream-car stream)
+  "The first element of stream."
+  (car stream))
+
+(define (stream-cdr stream)
+  "stream without its first element, evaluated the first time it is asked for."
+  (force (cdr stream)))
+
+(define (stream-ref stream k)
+  "The element of stream at index k."
+  (if (= k 0) (stream-car stream) (stream-ref (stream-cdr stream) (- k 1))))
+
+(define (stream-head stream k)
+  "The list of the first k elements of stream, or of all of them if it has fewer."
+  (if (if (= k 0) #t (stream-null? stream))
+      '()
+      (cons (stream-car stream) (stream-head (stream-cdr stream) (- k 1)))))
+
+(define (stream-map f stream)
+  "The stream of the results of applying f to the elements of stream."
+  (if (stream-null? stream)
+      the-empty-stream
+      (cons-stream (f (stream-car stream)) (stream-map f (stream-cdr stream)))))
+
+(define (stream-filter pred stream)
+  "The stream of the elements of stream for which pred is true."
+  (if (stream-null? stream)
+      the-empty-stream
+      (if (pred (stream-car stream))
+          (cons-stream (stream-car stream) (stream-filter pred (stream-cdr stream)))
+          (stream-filter pred (stream-cdr stream)))))
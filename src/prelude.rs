@@ -0,0 +1,73 @@
+//! The prelude: library procedures written in Lisp rather than as builtins,
+//! such as `cadr`, `length`, `append`, `member` and `list-tail`.
+//!
+//! Its source is part of the binary, and `global_env` evaluates it after
+//! defining the builtins, so every interpreter has it. It can be left out
+//! for the programs run on a thread with `enable(false)`, as `--no-prelude`
+//! does, or for one interpreter with `Interpreter::without_prelude`.
+
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use crate::env::Env;
+use crate::eval::eval_str;
+
+pub const SOURCE: &str = include_str!("prelude.lisp");
+
+thread_local! {
+    static ENABLED: Cell<bool> = const { Cell::new(true) };
+}
+
+pub fn enable(enabled: bool) {
+    ENABLED.with(|cell| cell.set(enabled));
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.with(Cell::get)
+}
+
+/// Defines the procedures of the prelude in `env`.
+pub fn load(env: &Rc<RefCell<Env>>) {
+    if let Err(e) = eval_str(SOURCE, env) {
+        panic!("the prelude failed: {}", e.message());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builtins::{builtin_env, global_env};
+    use crate::eval::eval_str;
+
+    #[test]
+    fn test_prelude() {
+        let env = global_env();
+        let eval = |program: &str| match eval_str(program, &env) {
+            Ok(value) => value.to_string(),
+            Err(e) => e.message().to_string(),
+        };
+
+        assert_eq!(
+            eval("(list (cadr '(1 2 3)) (caddr '(1 2 3)) (caar '((1) 2)))"),
+            "(2 3 1)"
+        );
+        assert_eq!(
+            eval("(list (length '(a b c)) (reverse '(1 2 3)) (not 1) (not #f))"),
+            "(3 (3 2 1) #f #t)"
+        );
+        assert_eq!(eval("(append '(1) '() '(2 3) '(4))"), "(1 2 3 4)");
+        assert_eq!(
+            eval("(list (list-tail '(1 2 3) 2) (list-ref '(a b c) 1))"),
+            "((3) b)"
+        );
+        assert_eq!(
+            eval("(list (member 2 '(1 2 3)) (member 4 '(1 2)) (assoc \"b\" '((\"a\" . 1) (\"b\" . 2))))"),
+            "((2 3) #f (\"b\" . 2))"
+        );
+        assert_eq!(
+            eval("(length (list-tail (reverse (append '(1) '(2))) 0))"),
+            "2"
+        );
+
+        assert!(eval_str("(cadr '(1 2))", &builtin_env()).is_err());
+    }
+}
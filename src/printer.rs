@@ -0,0 +1,601 @@
+//! A pretty-printer that breaks and indents S-expressions which do not fit
+//! on one line, and the settings every printed value follows: the float
+//! format and the ordered printing mode.
+//!
+//! Floats print in the shortest form that reads back as the same number
+//! unless `(set-float-format! 'fixed 2)` asks for a number of decimals or
+//! `(set-float-format! 'scientific 3)` for scientific notation with that
+//! many digits after the point.
+//!
+//! Hash tables keep their entries in no particular order, which can change
+//! from one run to the next. With ordered printing on, they are printed,
+//! and listed by `hash-table-keys` and `hash-table->alist`, in sorted key
+//! order instead, so that golden-output tests and diffs are stable. The
+//! mode is on by default in this crate's own tests.
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashSet;
+use std::rc::Rc;
+
+use crate::eval::EvalError;
+use crate::lexer::{Token, Tokenizer};
+use crate::object::{self, Object, Pair};
+use crate::parser::ParseError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FloatFormat {
+    /// The fewest digits that read back as the same float.
+    Shortest,
+    /// This many digits after the decimal point.
+    Fixed(usize),
+    /// Scientific notation, with this many digits after the point.
+    Scientific(usize),
+}
+
+thread_local! {
+    static ORDERED: Cell<bool> = const { Cell::new(cfg!(test)) };
+    /// The float format of the interpreter evaluating, or that of the
+    /// thread.
+    static FLOAT_FORMAT: RefCell<Rc<Cell<FloatFormat>>> =
+        RefCell::new(Rc::new(Cell::new(FloatFormat::Shortest)));
+    /// What `pp` printed while its output is captured.
+    static CAPTURED: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Makes `pp` collect its output for `take_captured` instead of printing
+/// it, for hosts such as the debug adapter that own standard output.
+pub(crate) fn capture_output(capture: bool) {
+    CAPTURED.with(|captured| *captured.borrow_mut() = capture.then(String::new));
+}
+
+/// The output captured since the last call.
+pub(crate) fn take_captured() -> String {
+    CAPTURED.with(|captured| {
+        captured
+            .borrow_mut()
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    })
+}
+
+/// Runs `f` printing floats in the format kept in `format`, which
+/// `set_float_format` changes meanwhile.
+pub(crate) fn with_float_format<T>(format: &Rc<Cell<FloatFormat>>, f: impl FnOnce() -> T) -> T {
+    struct Restore(Rc<Cell<FloatFormat>>);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            FLOAT_FORMAT.with(|current| current.replace(self.0.clone()));
+        }
+    }
+
+    let _restore = Restore(FLOAT_FORMAT.with(|current| current.replace(format.clone())));
+    f()
+}
+
+pub fn set_float_format(format: FloatFormat) {
+    FLOAT_FORMAT.with(|current| current.borrow().set(format));
+}
+
+pub fn float_format() -> FloatFormat {
+    FLOAT_FORMAT.with(|current| current.borrow().get())
+}
+
+/// Formats `n` the way the current float format says.
+pub fn format_float(n: f64) -> String {
+    match float_format() {
+        FloatFormat::Shortest => format!("{:?}", n),
+        FloatFormat::Fixed(decimals) => format!("{:.*}", decimals, n),
+        FloatFormat::Scientific(decimals) => format!("{:.*e}", decimals, n),
+    }
+}
+
+/// The most digits `set-float-format!` accepts, more than an `f64` holds.
+const MAX_DECIMALS: i64 = 100;
+
+/// `(set-float-format! 'shortest)`, `(set-float-format! 'fixed decimals)` or
+/// `(set-float-format! 'scientific decimals)`.
+pub fn set_float_format_builtin(args: &[Object]) -> Result<Object, EvalError> {
+    let decimals = |n: &Object| match n {
+        Object::Integer(n) if (0..=MAX_DECIMALS).contains(n) => Ok(*n as usize),
+        _ => Err(EvalError::new(format!(
+            "set-float-format! expects a number of decimals from 0 to {}, got {}",
+            MAX_DECIMALS, n
+        ))),
+    };
+
+    let format = match args {
+        [Object::Symbol(s)] if *s == "shortest" => FloatFormat::Shortest,
+        [Object::Symbol(s), n] if *s == "fixed" => FloatFormat::Fixed(decimals(n)?),
+        [Object::Symbol(s), n] if *s == "scientific" => FloatFormat::Scientific(decimals(n)?),
+        _ => {
+            return Err(EvalError::new(
+                "set-float-format! expects 'shortest, or 'fixed or 'scientific and a number of decimals",
+            ))
+        }
+    };
+    set_float_format(format);
+
+    Ok(Object::Void)
+}
+
+/// `(float-format)` is the current float format, as the arguments to
+/// `set-float-format!` that select it, e.g. `(fixed 2)`.
+pub fn float_format_builtin(args: &[Object]) -> Result<Object, EvalError> {
+    if !args.is_empty() {
+        return Err(EvalError::new("float-format expects no arguments"));
+    }
+
+    Ok(match float_format() {
+        FloatFormat::Shortest => Object::list([Object::symbol("shortest")]),
+        FloatFormat::Fixed(n) => Object::list([Object::symbol("fixed"), Object::Integer(n as i64)]),
+        FloatFormat::Scientific(n) => {
+            Object::list([Object::symbol("scientific"), Object::Integer(n as i64)])
+        }
+    })
+}
+
+pub fn set_ordered(ordered: bool) {
+    ORDERED.with(|cell| cell.set(ordered));
+}
+
+pub fn is_ordered() -> bool {
+    ORDERED.with(Cell::get)
+}
+
+/// `(set-ordered-printing! #t)` turns ordered printing on, `#f` off.
+pub fn set_ordered_printing(args: &[Object]) -> Result<Object, EvalError> {
+    match args {
+        [Object::Bool(ordered)] => {
+            set_ordered(*ordered);
+            Ok(Object::Void)
+        }
+        _ => Err(EvalError::new("set-ordered-printing! expects a boolean")),
+    }
+}
+
+/// The width used by `pp` and the REPL.
+pub const DEFAULT_WIDTH: usize = 80;
+
+/// Forms whose first few arguments stay on the opening line, with the body
+/// indented by two columns below them, and how many such arguments they take.
+const BODY_FORMS: &[(&str, usize)] = &[
+    ("define", 1),
+    ("define-memoized", 1),
+    ("deftest", 1),
+    ("lambda", 1),
+    ("let", 1),
+    ("match", 1),
+    ("let*", 1),
+    ("let-values", 1),
+    ("letrec", 1),
+    ("when", 1),
+    ("unless", 1),
+    ("do", 2),
+    ("begin", 0),
+    ("time", 0),
+];
+
+/// Lists nested deeper than this are laid out on one line by `Display`,
+/// which unlike the layout code does not recurse.
+const MAX_LAYOUT_DEPTH: usize = 64;
+
+/// The forms written with a prefix, and their prefixes.
+const PREFIXES: &[(&str, &str)] = &[
+    ("quote", "'"),
+    ("quasiquote", "`"),
+    ("unquote", ","),
+    ("unquote-splicing", ",@"),
+];
+
+fn prefix(name: &str) -> Option<&'static str> {
+    PREFIXES
+        .iter()
+        .find(|(form, _)| *form == name)
+        .map(|(_, prefix)| *prefix)
+}
+
+/// What the printer lays out: either an object or source code, for which it
+/// keeps the spelling of atoms and the comments.
+enum Node {
+    Atom(String),
+    /// A quoted form, after `'` or another of the prefixes of `PREFIXES`.
+    Quote(&'static str, Box<Node>),
+    List(Vec<Node>, Option<Box<Node>>),
+    /// A comment, and whether it followed other code on its line.
+    Comment {
+        text: String,
+        trailing: bool,
+    },
+}
+
+impl Node {
+    /// `open` has the pairs of the lists being laid out, once lists may
+    /// contain themselves: a list met again inside itself is written `...`,
+    /// as `Display` writes it.
+    fn from_object(obj: &Object, depth: usize, open: &mut Option<HashSet<*const Pair>>) -> Node {
+        if depth > MAX_LAYOUT_DEPTH {
+            return Node::Atom(obj.to_string());
+        }
+
+        let mut items = Vec::new();
+        let mut tail = obj.clone();
+        let mut opened = Vec::new();
+        let mut cycle = false;
+        while let Object::Pair(pair) = &tail {
+            if let Some(open) = open {
+                if !open.insert(Rc::as_ptr(pair)) {
+                    cycle = true;
+                    break;
+                }
+                opened.push(Rc::as_ptr(pair));
+            }
+            items.push(pair.car());
+            let next = pair.cdr();
+            tail = next;
+        }
+
+        let node = match (items.as_slice(), &tail) {
+            ([], Object::Pair(_)) => Node::Atom(String::from("...")),
+            ([Object::Symbol(name), datum], Object::Nil) if prefix(name).is_some() => {
+                let prefix = prefix(name).unwrap_or_default();
+                Node::Quote(prefix, Box::new(Node::from_object(datum, depth + 1, open)))
+            }
+            ([], _) => Node::Atom(obj.to_string()),
+            _ => Node::List(
+                items
+                    .iter()
+                    .map(|item| Node::from_object(item, depth + 1, open))
+                    .collect(),
+                match &tail {
+                    _ if cycle => Some(Box::new(Node::Atom(String::from("...")))),
+                    Object::Nil => None,
+                    tail => Some(Box::new(Node::from_object(tail, depth + 1, open))),
+                },
+            ),
+        };
+        if let Some(open) = open {
+            for pair in opened {
+                open.remove(&pair);
+            }
+        }
+
+        node
+    }
+}
+
+/// Formats `obj` so that lines stay within `width` columns where possible.
+/// Lists that fit are printed as `Display` would; those that do not put
+/// their arguments on separate lines, aligned under the first one, or
+/// indented by two columns for binding forms such as `define` and `let`.
+pub fn pretty_print(obj: &Object, width: usize) -> String {
+    let mut out = String::new();
+    print(
+        &Node::from_object(obj, 0, &mut object::may_cycle().then(HashSet::new)),
+        width,
+        &mut out,
+    );
+    out
+}
+
+/// Reformats source code with `pretty_print`'s layout, keeping comments and
+/// single blank lines between top-level forms.
+pub fn format_source(source: &str, width: usize) -> Result<String, ParseError> {
+    let mut reader = Reader {
+        source,
+        tokenizer: Tokenizer::new(source).keep_comments(),
+    };
+    let mut out = String::new();
+
+    while let Some((token, text, newlines)) = reader.next()? {
+        let node = reader.read_node(token, &text, newlines)?;
+        if !out.is_empty() {
+            match node {
+                Node::Comment { trailing: true, .. } => out.push(' '),
+                _ if newlines > 1 => out.push_str("\n\n"),
+                _ => out.push('\n'),
+            }
+        }
+        print(&node, width, &mut out);
+    }
+
+    if !out.is_empty() {
+        out.push('\n');
+    }
+
+    Ok(out)
+}
+
+/// Builds nodes from source tokens, keeping their original text.
+struct Reader<'a> {
+    source: &'a str,
+    tokenizer: Tokenizer,
+}
+
+impl Reader<'_> {
+    /// The next token, its text and the number of line breaks before it.
+    fn next(&mut self) -> Result<Option<(Token, String, usize)>, ParseError> {
+        let start = self.tokenizer.offset();
+        let token = match self.tokenizer.next_token() {
+            Ok(Some(token)) => token,
+            Ok(None) => return Ok(None),
+            Err(e) => return Err(ParseError::new(e.to_string())),
+        };
+
+        let text = &self.source[start..self.tokenizer.offset()];
+        let code = text.trim_start();
+        let newlines = text[..text.len() - code.len()].matches('\n').count();
+
+        Ok(Some((token, code.to_string(), newlines)))
+    }
+
+    fn read_node(&mut self, token: Token, text: &str, newlines: usize) -> Result<Node, ParseError> {
+        match token {
+            Token::LeftParenthesis => self.read_list(),
+            Token::RightParenthesis => Err(ParseError::new("unexpected ')'")),
+            Token::Dot => Err(ParseError::new("unexpected '.'")),
+            Token::Quote | Token::Quasiquote | Token::Unquote | Token::UnquoteSplicing => {
+                let prefix = match token {
+                    Token::Quote => "'",
+                    Token::Quasiquote => "`",
+                    Token::Unquote => ",",
+                    _ => ",@",
+                };
+                match self.next()? {
+                    Some((Token::Comment(_), _, _)) => {
+                        Err(ParseError::new("cannot format a comment after a quote"))
+                    }
+                    Some((token, text, newlines)) => Ok(Node::Quote(
+                        prefix,
+                        Box::new(self.read_node(token, &text, newlines)?),
+                    )),
+                    None => Err(ParseError::new("expected a form after quote")),
+                }
+            }
+            Token::Comment(text) => Ok(Node::Comment {
+                text,
+                trailing: newlines == 0,
+            }),
+            _ => Ok(Node::Atom(text.to_string())),
+        }
+    }
+
+    /// Reads up to the closing parenthesis. Comments after a `.` are kept
+    /// with the elements before it.
+    fn read_list(&mut self) -> Result<Node, ParseError> {
+        let mut items = Vec::new();
+        let mut dotted = false;
+        let mut tail = None;
+
+        loop {
+            let node = match self.next()? {
+                Some((Token::RightParenthesis, _, _)) => break,
+                Some((Token::Dot, _, _)) if !dotted && !items.is_empty() => {
+                    dotted = true;
+                    continue;
+                }
+                Some((token, text, newlines)) => self.read_node(token, &text, newlines)?,
+                None => return Err(ParseError::new("missing ')'")),
+            };
+
+            match node {
+                Node::Comment { .. } => items.push(node),
+                _ if !dotted => items.push(node),
+                _ if tail.is_none() => tail = Some(Box::new(node)),
+                _ => return Err(ParseError::new("expected a single form after '.'")),
+            }
+        }
+
+        if dotted && tail.is_none() {
+            return Err(ParseError::new("expected a single form after '.'"));
+        }
+
+        Ok(Node::List(items, tail))
+    }
+}
+
+/// The node on one line, or `None` if it contains a comment and so cannot be.
+fn flat(node: &Node) -> Option<String> {
+    match node {
+        Node::Atom(atom) => Some(atom.clone()),
+        Node::Quote(prefix, datum) => Some(format!("{}{}", prefix, flat(datum)?)),
+        Node::List(items, tail) => {
+            let mut parts = items.iter().map(flat).collect::<Option<Vec<_>>>()?;
+            if let Some(tail) = tail {
+                parts.push(format!(". {}", flat(tail)?));
+            }
+            Some(format!("({})", parts.join(" ")))
+        }
+        Node::Comment { .. } => None,
+    }
+}
+
+fn current_column(out: &str) -> usize {
+    let line_start = out.rfind('\n').map_or(0, |i| i + 1);
+    out[line_start..].chars().count()
+}
+
+fn newline(indent: usize, out: &mut String) {
+    out.push('\n');
+    out.extend(std::iter::repeat_n(' ', indent));
+}
+
+/// Appends `node` to `out`, continuing its last line.
+fn print(node: &Node, width: usize, out: &mut String) {
+    let column = current_column(out);
+    if let Some(line) = flat(node) {
+        if column + line.chars().count() <= width {
+            out.push_str(&line);
+            return;
+        }
+    }
+
+    match node {
+        Node::Atom(atom) => out.push_str(atom),
+        Node::Quote(prefix, datum) => {
+            out.push_str(prefix);
+            print(datum, width, out);
+        }
+        Node::Comment { text, .. } => {
+            out.push(';');
+            out.push_str(text);
+        }
+        Node::List(items, tail) => print_list(items, tail.as_deref(), column, width, out),
+    }
+}
+
+fn print_list(items: &[Node], tail: Option<&Node>, column: usize, width: usize, out: &mut String) {
+    let head = match items.first() {
+        Some(Node::Atom(head)) => Some(head.as_str()),
+        _ => None,
+    };
+
+    // How many elements stay on the opening line, and the indentation of
+    // the others.
+    let (inline, indent) = match head {
+        Some(head) => match BODY_FORMS.iter().find(|(form, _)| *form == head) {
+            Some(&(_, header)) => (1 + header, column + 2),
+            // Calls with a short operator keep the first argument on the
+            // same line and align the others under it.
+            None if items.len() > 1 => (2, column + 2 + head.chars().count()),
+            None => (1, column + 1),
+        },
+        None => (1, column + 1),
+    };
+
+    out.push('(');
+    // A comment runs to the end of the line, so whatever follows it must
+    // start on a new one.
+    let mut after_comment = false;
+    for (i, item) in items.iter().enumerate() {
+        let is_comment = matches!(item, Node::Comment { .. });
+        if i > 0 {
+            match item {
+                Node::Comment { trailing: true, .. } if !after_comment => out.push(' '),
+                _ if i < inline && !after_comment && !is_comment => out.push(' '),
+                _ => newline(indent, out),
+            }
+        }
+        print(item, width, out);
+        after_comment = is_comment;
+    }
+
+    if let Some(tail) = tail {
+        newline(indent, out);
+        out.push_str(". ");
+        print(tail, width, out);
+        after_comment = false;
+    }
+    if after_comment {
+        newline(indent, out);
+    }
+    out.push(')');
+}
+
+/// `(pp expr)` prints `expr` with `pretty_print`, or `(pp expr width)` with
+/// a given line width.
+pub fn pp(args: &[Object]) -> Result<Object, EvalError> {
+    let (obj, width) = match args {
+        [obj] => (obj, DEFAULT_WIDTH),
+        [obj, Object::Integer(width)] if *width > 0 => (obj, *width as usize),
+        _ => {
+            return Err(EvalError::new(
+                "pp expects an expression and an optional width",
+            ))
+        }
+    };
+
+    print_line(&pretty_print(obj, width));
+
+    Ok(Object::Void)
+}
+
+/// Prints `line` to stdout, or to the capture while one is on.
+pub(crate) fn print_line(line: &str) {
+    CAPTURED.with(|captured| match captured.borrow_mut().as_mut() {
+        Some(captured) => {
+            captured.push_str(line);
+            captured.push('\n');
+        }
+        None => println!("{}", line),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    fn pretty(source: &str, width: usize) -> String {
+        pretty_print(&parse(source).unwrap()[0], width)
+    }
+
+    #[test]
+    fn test_short_forms_stay_on_one_line() {
+        assert_eq!(pretty("(a (b   c) \"s\" 'd)", 80), "(a (b c) \"s\" 'd)");
+        assert_eq!(pretty("(1 2 . 3)", 80), "(1 2 . 3)");
+        assert_eq!(pretty("`(a ,b ,@c)", 80), "`(a ,b ,@c)");
+    }
+
+    #[test]
+    fn test_breaks_long_forms() {
+        assert_eq!(
+            pretty("(define (fact n) (if (< n 2) 1 (* n (fact (- n 1)))))", 30),
+            "(define (fact n)\n  (if (< n 2)\n      1\n      (* n (fact (- n 1)))))"
+        );
+        assert_eq!(
+            pretty("((lambda (x) x) 100000 200000)", 20),
+            "((lambda (x) x)\n 100000\n 200000)"
+        );
+    }
+
+    #[test]
+    fn test_deep_structures() {
+        let deep = (0..100_000).fold(Object::Nil, |inner, _| Object::cons(inner, Object::Nil));
+        let printed = pretty_print(&deep, 80);
+        assert_eq!(printed.matches('(').count(), 100_001);
+        assert_eq!(printed.matches(')').count(), 100_001);
+    }
+
+    #[test]
+    fn test_circular_lists() {
+        let env = crate::builtins::global_env();
+        let eval = |source| crate::eval::eval_str(source, &env).unwrap();
+
+        let l = eval("(define l (list 1 2 3)) (set-cdr! (cddr l) l) l");
+        assert_eq!(pretty_print(&l, 80), "(1 2 3 . ...)");
+        let m = eval("(define m (list 1 2 3)) (set-car! (cdr m) m) m");
+        assert_eq!(pretty_print(&m, 80), m.to_string());
+        assert_eq!(pretty_print(&m, 80), "(1 ... 3)");
+        let shared = eval("(define s (list 1)) (list s s)");
+        assert_eq!(pretty_print(&shared, 80), "((1) (1))");
+    }
+
+    #[test]
+    fn test_format_source_keeps_comments() {
+        let source = ";; Squares.\n(define   (sq x) ; one argument\n  (* x   x))\n\n\n\n(sq #x1F)";
+        assert_eq!(
+            format_source(source, 80).unwrap(),
+            ";; Squares.\n(define (sq x) ; one argument\n  (* x x))\n\n(sq #x1F)\n"
+        );
+        assert!(format_source("(a", 80).is_err());
+    }
+
+    #[test]
+    fn test_float_formats() {
+        let env = crate::builtins::global_env();
+        let eval = |source: &str| crate::eval::eval_str(source, &env).unwrap().to_string();
+
+        assert_eq!(eval("(list 0.1 1e21 2.0)"), "(0.1 1e21 2.0)");
+        eval("(set-float-format! 'fixed 2)");
+        assert_eq!(eval("(list 3.14159 1.0 -0.005)"), "(3.14 1.00 -0.01)");
+        assert_eq!(eval("(float-format)"), "(fixed 2)");
+        eval("(set-float-format! 'scientific 3)");
+        assert_eq!(eval("12345.678"), "1.235e4");
+        eval("(set-float-format! 'shortest)");
+        assert_eq!(eval("12345.678"), "12345.678");
+
+        assert!(crate::eval::eval_str("(set-float-format! 'fixed -1)", &env).is_err());
+        assert!(crate::eval::eval_str("(set-float-format! 'round)", &env).is_err());
+    }
+}
@@ -0,0 +1,301 @@
+//! Running other programs and reading the environment, for scripts that do
+//! the work of shell scripts.
+//!
+//! `(getenv name)` is the value of an environment variable, or `#f`, and
+//! `(setenv name value)` sets one, or removes it when `value` is `#f`.
+//! `(system command)` runs a command line with the shell, letting it use the
+//! terminal, and returns its exit status. `(process-run program args)` runs
+//! a program with a list of arguments, without a shell, and returns a hash
+//! table of its `"status"`, `"stdout"` and `"stderr"`. The status is `#f`
+//! for a program killed by a signal. `pipe` connects programs the way a
+//! shell pipeline does.
+//!
+//! Under taint tracking, the values of environment variables and the output
+//! of programs are tainted, and none of these accept tainted strings.
+
+use std::cell::RefCell;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::rc::Rc;
+
+use crate::env::Env;
+use crate::eval::{eval, EvalError};
+use crate::lsp::object;
+use crate::object::Object;
+use crate::taint;
+
+/// Builds the command for one `(program arg ...)` stage. The program name is
+/// taken literally; the arguments are evaluated and must be strings, symbols
+/// or numbers.
+fn command(stage: &Object, env: &Rc<RefCell<Env>>) -> Result<Command, EvalError> {
+    let parts = stage
+        .to_vec()
+        .filter(|parts| !parts.is_empty())
+        .ok_or_else(|| EvalError::new(format!("invalid pipe stage: {}", stage)))?;
+
+    taint::check("pipe", &parts[0])?;
+    let program = match &parts[0] {
+        Object::Symbol(name) => name.to_string(),
+        Object::String(name) => name.to_string(),
+        other => return Err(EvalError::new(format!("invalid program name: {}", other))),
+    };
+
+    let mut command = Command::new(program);
+    for arg in &parts[1..] {
+        let arg = eval(arg, env)?;
+        taint::check("pipe", &arg)?;
+        match arg {
+            Object::String(s) => command.arg(s.as_str()),
+            Object::Symbol(s) => command.arg(s.as_str()),
+            n @ (Object::Integer(_) | Object::BigInt(_) | Object::Float(_)) => {
+                command.arg(n.to_string())
+            }
+            other => {
+                return Err(EvalError::new(format!(
+                    "invalid command argument: {}",
+                    other
+                )))
+            }
+        };
+    }
+
+    Ok(command)
+}
+
+/// `(pipe (ls "-l") (grep "lisp") (wc "-l"))` runs the stages with each
+/// one's stdout connected to the next one's stdin, and returns the output of
+/// the last stage as a string. Like a shell pipeline, only the exit status
+/// of the last stage is checked.
+pub fn pipe(stages: &[Object], env: &Rc<RefCell<Env>>) -> Result<Object, EvalError> {
+    if stages.is_empty() {
+        return Err(EvalError::new("pipe expects at least one command"));
+    }
+
+    let mut children: Vec<Child> = Vec::new();
+    for stage in stages {
+        let mut command = command(stage, env)?;
+        if let Some(stdout) = children.last_mut().and_then(|child| child.stdout.take()) {
+            command.stdin(stdout);
+        }
+
+        let child = command
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| EvalError::new(format!("cannot run {}: {}", stage, e)))?;
+        children.push(child);
+    }
+
+    let last = children.pop().unwrap();
+    let output = last
+        .wait_with_output()
+        .map_err(|e| EvalError::new(format!("pipe failed: {}", e)))?;
+    for mut child in children {
+        let _ = child.wait();
+    }
+
+    if !output.status.success() {
+        return Err(EvalError::new(format!(
+            "{} exited with {}",
+            stages[stages.len() - 1],
+            output.status
+        )));
+    }
+
+    Ok(taint::mark(Object::string(
+        String::from_utf8_lossy(&output.stdout).into_owned(),
+    )))
+}
+
+/// A string argument of `name`, which must not be tainted.
+fn trusted_string(name: &str, obj: &Object) -> Result<String, EvalError> {
+    taint::check(name, obj)?;
+    match obj {
+        Object::String(s) => Ok(s.to_string()),
+        other => Err(EvalError::new(format!(
+            "{} expects a string, got {}",
+            name, other
+        ))),
+    }
+}
+
+fn status(status: ExitStatus) -> Object {
+    match status.code() {
+        Some(code) => Object::Integer(code.into()),
+        None => Object::Bool(false),
+    }
+}
+
+/// `(getenv name)`.
+pub fn getenv(args: &[Object]) -> Result<Object, EvalError> {
+    let [name] = args else {
+        return Err(EvalError::new("getenv expects a variable name"));
+    };
+    let name = trusted_string("getenv", name)?;
+
+    Ok(match std::env::var_os(name) {
+        Some(value) => taint::mark(Object::string(value.to_string_lossy().into_owned())),
+        None => Object::Bool(false),
+    })
+}
+
+/// `(setenv name value)`, or `(setenv name #f)` to remove the variable.
+pub fn setenv(args: &[Object]) -> Result<Object, EvalError> {
+    let [name, value] = args else {
+        return Err(EvalError::new("setenv expects a variable name and a value"));
+    };
+    let name = trusted_string("setenv", name)?;
+    if name.is_empty() || name.contains(['=', '\0']) {
+        return Err(EvalError::new(format!(
+            "setenv: invalid variable name {:?}",
+            name
+        )));
+    }
+
+    match value {
+        Object::Bool(false) => std::env::remove_var(name),
+        value => {
+            let value = trusted_string("setenv", value)?;
+            if value.contains('\0') {
+                return Err(EvalError::new("setenv: the value contains a NUL character"));
+            }
+            std::env::set_var(name, value);
+        }
+    }
+    Ok(Object::Void)
+}
+
+/// `(system command)` runs `command` with `sh -c`, or `cmd /C` on Windows.
+pub fn system(args: &[Object]) -> Result<Object, EvalError> {
+    let [command] = args else {
+        return Err(EvalError::new("system expects a command line"));
+    };
+    let command = trusted_string("system", command)?;
+
+    let (shell, flag) = match cfg!(windows) {
+        true => ("cmd", "/C"),
+        false => ("sh", "-c"),
+    };
+    let exit = Command::new(shell)
+        .args([flag, &command])
+        .status()
+        .map_err(|e| EvalError::new(format!("cannot run {:?}: {}", command, e)))?;
+
+    Ok(status(exit))
+}
+
+/// `(process-run program)` or `(process-run program args)`.
+pub fn process_run(args: &[Object]) -> Result<Object, EvalError> {
+    let (program, arguments) = match args {
+        [program] => (program, Vec::new()),
+        [program, arguments] => match arguments.to_vec() {
+            Some(arguments) => (program, arguments),
+            None => {
+                return Err(EvalError::new(format!(
+                    "process-run expects a list of arguments, got {}",
+                    arguments
+                )))
+            }
+        },
+        _ => {
+            return Err(EvalError::new(
+                "process-run expects a program and a list of arguments",
+            ))
+        }
+    };
+    let program = trusted_string("process-run", program)?;
+    let arguments = arguments
+        .iter()
+        .map(|arg| trusted_string("process-run", arg))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let output = Command::new(&program)
+        .args(arguments)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| EvalError::new(format!("cannot run {}: {}", program, e)))?;
+    let text =
+        |bytes: &[u8]| taint::mark(Object::string(String::from_utf8_lossy(bytes).into_owned()));
+
+    Ok(object([
+        ("status", status(output.status)),
+        ("stdout", text(&output.stdout)),
+        ("stderr", text(&output.stderr)),
+    ]))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use crate::builtins::global_env;
+    use crate::eval::eval_str;
+    use crate::object::Object;
+
+    #[test]
+    fn test_pipe_connects_stages() {
+        let result = eval_str(
+            "(define pattern \"b\")
+             (pipe (printf \"a\\nb\\nbb\\n\") (grep pattern) (wc \"-l\"))",
+            &global_env(),
+        )
+        .unwrap();
+
+        match result {
+            Object::String(s) => assert_eq!(s.trim(), "2"),
+            other => panic!("expected a string, got {}", other),
+        }
+    }
+
+    #[test]
+    fn test_pipe_reports_failures() {
+        let env = global_env();
+
+        assert!(eval_str("(pipe (false))", &env).is_err());
+        assert!(eval_str("(pipe (no-such-program-lisp-rs))", &env).is_err());
+        assert!(eval_str("(pipe)", &env).is_err());
+    }
+
+    #[test]
+    fn test_environment() {
+        let env = global_env();
+        let eval = |program: &str| match eval_str(program, &env) {
+            Ok(value) => value.to_string(),
+            Err(e) => e.message().to_string(),
+        };
+
+        assert_eq!(
+            eval("(setenv \"LISP_RS_TEST_VAR\" \"a b\") (getenv \"LISP_RS_TEST_VAR\")"),
+            "\"a b\""
+        );
+        assert_eq!(
+            eval("(setenv \"LISP_RS_TEST_VAR\" #f) (getenv \"LISP_RS_TEST_VAR\")"),
+            "#f"
+        );
+        assert_eq!(
+            eval("(setenv \"A=B\" \"c\")"),
+            "setenv: invalid variable name \"A=B\""
+        );
+    }
+
+    #[test]
+    fn test_running_programs() {
+        let env = global_env();
+        let eval = |program: &str| match eval_str(program, &env) {
+            Ok(value) => value.to_string(),
+            Err(e) => e.message().to_string(),
+        };
+
+        assert_eq!(eval("(system \"exit 3\")"), "3");
+        assert_eq!(
+            eval("(define result (process-run \"sh\" '(\"-c\" \"echo out; echo err >&2; exit 1\")))
+                  (list (hash-table-ref result \"status\") (hash-table-ref result \"stdout\") (hash-table-ref result \"stderr\"))"),
+            "(1 \"out\\n\" \"err\\n\")"
+        );
+        assert_eq!(
+            eval("(hash-table-ref (process-run \"true\") \"status\")"),
+            "0"
+        );
+        assert!(eval("(process-run \"no-such-program-lisp-rs\")").starts_with("cannot run"));
+        assert_eq!(
+            eval("(process-run \"echo\" '(1))"),
+            "process-run expects a string, got 1"
+        );
+    }
+}
@@ -0,0 +1,239 @@
+//! A profiler counting the calls to each procedure and the time spent in
+//! them, for `(profile expr)` and `--profile`.
+//!
+//! `Profiler` is a `Hook`. Its report has a line for each procedure called,
+//! builtins included, with the number of calls, the total time spent in
+//! them and the self time, the part not spent in the procedures they
+//! called, sorted by self time:
+//!
+//! ```text
+//! procedure     calls    total ms     self ms
+//! fib           21891     491.530     445.073
+//! <             21891      17.797      17.797
+//! ```
+//!
+//! A procedure calling itself counts towards its total time once, for the
+//! outermost call, and a call an error unwinds ends as the error leaves it.
+//! Only calls the tree-walking evaluator sees are counted: compiled code
+//! reports the calls it makes back into it.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::rc::Rc;
+
+use crate::bench;
+use crate::env::Env;
+use crate::eval::{eval, EvalError};
+use crate::hooks::{self, Hook, ProcedureNames};
+use crate::object::Object;
+use crate::printer;
+
+#[derive(Default)]
+pub struct Profiler {
+    names: ProcedureNames,
+    /// The calls that have not returned, innermost last.
+    calls: RefCell<Vec<Call>>,
+    stats: RefCell<HashMap<String, Stats>>,
+}
+
+struct Call {
+    func: Object,
+    name: String,
+    /// In milliseconds, as `bench::now` gives it.
+    start: f64,
+    /// The time spent in the calls it made.
+    children: f64,
+}
+
+#[derive(Default, Clone, Copy)]
+struct Stats {
+    calls: u64,
+    total: f64,
+    own: f64,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ends the innermost call at `now`, adding it to the stats.
+    fn end(&self, now: f64) {
+        let mut calls = self.calls.borrow_mut();
+        let Some(call) = calls.pop() else {
+            return;
+        };
+        let elapsed = now - call.start;
+        if let Some(caller) = calls.last_mut() {
+            caller.children += elapsed;
+        }
+        let recursive = calls.iter().any(|outer| outer.name == call.name);
+
+        let mut stats = self.stats.borrow_mut();
+        let stats = stats.entry(call.name).or_default();
+        stats.calls += 1;
+        stats.own += elapsed - call.children;
+        if !recursive {
+            stats.total += elapsed;
+        }
+    }
+
+    /// The report of the calls so far, ending those that have not returned.
+    pub fn report(&self) -> String {
+        let now = bench::now();
+        while !self.calls.borrow().is_empty() {
+            self.end(now);
+        }
+
+        let stats = self.stats.borrow();
+        let mut rows = stats.iter().collect::<Vec<_>>();
+        rows.sort_by(|(a_name, a), (b_name, b)| {
+            b.own.total_cmp(&a.own).then_with(|| a_name.cmp(b_name))
+        });
+        let width = rows
+            .iter()
+            .map(|(name, _)| name.chars().count())
+            .chain(["procedure".len()])
+            .max()
+            .unwrap_or_default();
+
+        let mut report = format!(
+            "{:<width$}  {:>8}  {:>10}  {:>10}\n",
+            "procedure", "calls", "total ms", "self ms"
+        );
+        for (name, stats) in rows {
+            let _ = writeln!(
+                report,
+                "{:<width$}  {:>8}  {:>10.3}  {:>10.3}",
+                name, stats.calls, stats.total, stats.own
+            );
+        }
+        report
+    }
+}
+
+impl Hook for Profiler {
+    fn on_call(&self, func: &Object, _args: &[Object]) {
+        self.calls.borrow_mut().push(Call {
+            func: func.clone(),
+            name: self.names.name(func),
+            start: bench::now(),
+            children: 0.0,
+        });
+    }
+
+    fn on_return(&self, func: &Object, _result: &Object) {
+        self.on_unwind(func);
+    }
+
+    /// Ends the innermost open call of `func` and any still open above it.
+    /// The return of a call begun before the profiler was added is ignored.
+    fn on_unwind(&self, func: &Object) {
+        let now = bench::now();
+        let position = self
+            .calls
+            .borrow()
+            .iter()
+            .rposition(|call| call.func == *func);
+        if let Some(position) = position {
+            while self.calls.borrow().len() > position {
+                self.end(now);
+            }
+        }
+    }
+
+    fn on_define(&self, name: &str, value: &Object) {
+        self.names.define(name, value);
+    }
+}
+
+/// `(profile expr)` evaluates `expr`, prints the profile of the calls it
+/// made, and returns its value.
+pub fn profile(list: &[Object], env: &Rc<RefCell<Env>>) -> Result<Object, EvalError> {
+    let [_, expr] = list else {
+        return Err(EvalError::new("profile expects one expression"));
+    };
+
+    let profiler = Rc::new(Profiler::new());
+    let hook = hooks::add(profiler.clone());
+    let result = eval(expr, env);
+    hooks::remove(hook);
+
+    printer::print_line(profiler.report().trim_end());
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builtins::global_env;
+    use crate::eval::eval_str;
+
+    /// The calls column of a report, by procedure.
+    fn calls(report: &str) -> Vec<(String, String)> {
+        let mut rows = report
+            .lines()
+            .skip(1)
+            .map(|line| {
+                let columns = line.split_whitespace().collect::<Vec<_>>();
+                (columns[0].to_string(), columns[1].to_string())
+            })
+            .collect::<Vec<_>>();
+        rows.sort();
+        rows
+    }
+
+    #[test]
+    fn test_profile() {
+        let env = global_env();
+        eval_str(
+            "(define (fib n) (if (< n 2) n (+ (fib (- n 1)) (fib (- n 2)))))",
+            &env,
+        )
+        .unwrap();
+
+        printer::capture_output(true);
+        let result = eval_str("(profile (fib 5))", &env);
+        let output = printer::take_captured();
+        printer::capture_output(false);
+
+        assert_eq!(result.unwrap().to_string(), "5");
+        assert!(output.starts_with("procedure     calls    total ms     self ms\n"));
+        assert_eq!(
+            calls(&output),
+            [("+", "7"), ("-", "14"), ("<", "15"), ("fib", "15")]
+                .map(|(name, calls)| (name.to_string(), calls.to_string()))
+        );
+        assert_eq!(
+            eval_str("(profile)", &env).unwrap_err().message(),
+            "profile expects one expression"
+        );
+    }
+
+    #[test]
+    fn test_unwound_calls() {
+        let env = global_env();
+        let profiler = Rc::new(Profiler::new());
+        let hook = hooks::add(profiler.clone());
+        let result = eval_str(
+            "(define n 0)
+             (define (flaky) (set! n (+ n 1)) (if (< n 3) (car n) n))
+             (define (run) (with-retry (#:delay 0) (flaky)))
+             (run)",
+            &env,
+        );
+        hooks::remove(hook);
+
+        assert_eq!(result.unwrap().to_string(), "3");
+        assert!(profiler.calls.borrow().is_empty());
+        let rows = calls(&profiler.report());
+        for row in [("car", "2"), ("flaky", "3"), ("run", "1")] {
+            assert!(
+                rows.contains(&(row.0.to_string(), row.1.to_string())),
+                "{:?}",
+                rows
+            );
+        }
+    }
+}
@@ -0,0 +1,192 @@
+//! Promises, and the streams built from them.
+//!
+//! `(delay expr)` makes a promise to evaluate `expr`, which `(force
+//! promise)` keeps: the first force evaluates it in the environment of the
+//! `delay`, and later ones return the same value without evaluating it
+//! again. `(cons-stream a b)` is `(cons a (delay b))`, a stream whose rest is
+//! only computed once `stream-cdr` asks for it, so that streams can go on
+//! forever:
+//!
+//! ```text
+//! (define (integers-from n) (cons-stream n (integers-from (+ n 1))))
+//! (stream-head (integers-from 1) 3)   ; => (1 2 3)
+//! ```
+//!
+//! The procedures working on streams, such as `stream-car`, `stream-cdr` and
+//! `stream-map`, are in the prelude.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::env::Env;
+use crate::eval::{eval, EvalError};
+use crate::gc::{self, Edge, Trace};
+use crate::object::Object;
+
+pub struct Promise {
+    state: RefCell<State>,
+}
+
+enum State {
+    Delayed(Object, Rc<RefCell<Env>>),
+    Forced(Object),
+}
+
+impl Promise {
+    fn object(state: State) -> Object {
+        let promise = Rc::new(Promise {
+            state: RefCell::new(state),
+        });
+        gc::track(&promise);
+
+        Object::Promise(promise)
+    }
+
+    /// The value of the promise, evaluating it unless it has been already.
+    pub fn force(&self) -> Result<Object, EvalError> {
+        let (expr, env) = match &*self.state.borrow() {
+            State::Forced(value) => return Ok(value.clone()),
+            State::Delayed(expr, env) => (expr.clone(), env.clone()),
+        };
+        let value = eval(&expr, &env)?;
+
+        // Forcing the promise while evaluating it may have kept it already,
+        // in which case the value it was kept with stands.
+        let mut state = self.state.borrow_mut();
+        match &*state {
+            State::Forced(value) => Ok(value.clone()),
+            State::Delayed(..) => {
+                *state = State::Forced(value.clone());
+                Ok(value)
+            }
+        }
+    }
+
+    pub(crate) fn clear_state(&self) {
+        if let Ok(mut state) = self.state.try_borrow_mut() {
+            *state = State::Forced(Object::Void);
+        }
+    }
+}
+
+impl Trace for Promise {
+    fn trace(&self, edge: &mut dyn FnMut(Edge)) {
+        if let Ok(state) = self.state.try_borrow() {
+            match &*state {
+                State::Delayed(expr, env) => {
+                    edge(Edge::Object(expr));
+                    edge(Edge::Env(env));
+                }
+                State::Forced(value) => edge(Edge::Object(value)),
+            }
+        }
+    }
+}
+
+/// `(delay expr)`.
+pub fn delay(list: &[Object], env: &Rc<RefCell<Env>>) -> Result<Object, EvalError> {
+    match list {
+        [_, expr] => Ok(Promise::object(State::Delayed(expr.clone(), env.clone()))),
+        _ => Err(EvalError::new("delay expects one expression")),
+    }
+}
+
+/// `(cons-stream a b)`, the pair of the value of `a` and a promise of `b`.
+pub fn cons_stream(list: &[Object], env: &Rc<RefCell<Env>>) -> Result<Object, EvalError> {
+    match list {
+        [_, first, rest] => Ok(Object::cons(
+            eval(first, env)?,
+            Promise::object(State::Delayed(rest.clone(), env.clone())),
+        )),
+        _ => Err(EvalError::new(
+            "cons-stream expects a first item and a rest",
+        )),
+    }
+}
+
+/// `(force obj)`, which is `obj` itself unless it is a promise.
+pub fn force(args: &[Object]) -> Result<Object, EvalError> {
+    match args {
+        [Object::Promise(promise)] => promise.force(),
+        [obj] => Ok(obj.clone()),
+        _ => Err(EvalError::new("force expects one argument")),
+    }
+}
+
+/// `(make-promise obj)`, a promise already kept with `obj`.
+pub fn make_promise(args: &[Object]) -> Result<Object, EvalError> {
+    match args {
+        [promise @ Object::Promise(_)] => Ok(promise.clone()),
+        [obj] => Ok(Promise::object(State::Forced(obj.clone()))),
+        _ => Err(EvalError::new("make-promise expects one argument")),
+    }
+}
+
+pub fn is_promise(args: &[Object]) -> Result<Object, EvalError> {
+    match args {
+        [obj] => Ok(Object::Bool(matches!(obj, Object::Promise(_)))),
+        _ => Err(EvalError::new("promise? expects one argument")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builtins::global_env;
+    use crate::eval::eval_str;
+
+    #[test]
+    fn test_delay_and_force() {
+        let env = global_env();
+        let eval = |program: &str| match eval_str(program, &env) {
+            Ok(value) => value.to_string(),
+            Err(e) => e.message().to_string(),
+        };
+
+        eval("(define count 0) (define p (delay (begin (set! count (+ count 1)) count)))");
+        assert_eq!(eval("(list (promise? p) count)"), "(#t 0)");
+        assert_eq!(eval("(list (force p) (force p) count)"), "(1 1 1)");
+        assert_eq!(eval("(list (force 5) (force (make-promise 'x)))"), "(5 x)");
+        assert_eq!(eval("(let ((x 2)) (force (delay (* x 21))))"), "42");
+
+        // A promise forcing itself keeps the value it is kept with first.
+        eval(
+            "(define depth 0)
+             (define q (delay (begin (set! depth (+ depth 1))
+                                     (if (< depth 3) (force q) depth))))",
+        );
+        assert_eq!(eval("(force q)"), "3");
+        assert_eq!(
+            eval("(force (delay (car '())))"),
+            "car expects a pair, got ()"
+        );
+    }
+
+    #[test]
+    fn test_streams() {
+        let env = global_env();
+        let eval = |program: &str| match eval_str(program, &env) {
+            Ok(value) => value.to_string(),
+            Err(e) => e.message().to_string(),
+        };
+
+        eval(
+            "(define (integers-from n) (cons-stream n (integers-from (+ n 1))))
+             (define naturals (integers-from 0))
+             (define evens (stream-filter (lambda (n) (= (remainder n 2) 0)) naturals))",
+        );
+        assert_eq!(eval("(stream-head naturals 5)"), "(0 1 2 3 4)");
+        assert_eq!(
+            eval("(stream-head (stream-map (lambda (n) (* n n)) evens) 4)"),
+            "(0 4 16 36)"
+        );
+        assert_eq!(eval("(stream-ref naturals 100)"), "100");
+        assert_eq!(
+            eval("(stream-head (cons-stream 1 (cons-stream 2 the-empty-stream)) 5)"),
+            "(1 2)"
+        );
+        assert_eq!(
+            eval("(list (stream-null? the-empty-stream) (stream-pair? naturals))"),
+            "(#t #t)"
+        );
+    }
+}
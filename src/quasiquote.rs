@@ -0,0 +1,121 @@
+//! `quasiquote`, written with a backquote: a quoted template in which the
+//! forms after a comma are evaluated, and those after `,@` are evaluated
+//! to lists spliced into the list around them:
+//!
+//! ```text
+//! `(1 ,(+ 1 1) ,@(list 3 4))  ; => (1 2 3 4)
+//! ```
+//!
+//! Templates can nest; the commas inside an inner template belong to it,
+//! and are only evaluated along with it.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::env::Env;
+use crate::eval::{eval, EvalError};
+use crate::object::Object;
+
+/// The argument of `obj` if it is the form `(name argument)`.
+pub(crate) fn form_argument(obj: &Object, name: &str) -> Option<Object> {
+    match obj {
+        Object::Pair(pair) => match (pair.car(), pair.cdr()) {
+            (Object::Symbol(head), Object::Pair(rest))
+                if head == name && rest.cdr() == Object::Nil =>
+            {
+                Some(rest.car())
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn expand(template: &Object, depth: usize, env: &Rc<RefCell<Env>>) -> Result<Object, EvalError> {
+    if let Some(form) = form_argument(template, "unquote") {
+        return match depth {
+            1 => eval(&form, env),
+            _ => Ok(Object::list([
+                Object::symbol("unquote"),
+                expand(&form, depth - 1, env)?,
+            ])),
+        };
+    }
+    if let Some(form) = form_argument(template, "quasiquote") {
+        return Ok(Object::list([
+            Object::symbol("quasiquote"),
+            expand(&form, depth + 1, env)?,
+        ]));
+    }
+    if form_argument(template, "unquote-splicing").is_some() && depth == 1 {
+        return Err(EvalError::new("unquote-splicing outside of a list"));
+    }
+    // Only lists have parts to expand.
+    if !matches!(template, Object::Pair(_)) {
+        return Ok(template.clone());
+    }
+
+    let mut items = Vec::new();
+    let mut rest = template.clone();
+    let tail = loop {
+        let pair = match &rest {
+            // `(a . ,b)` is `(a unquote b)`, whose tail is an unquote.
+            Object::Pair(_) if form_argument(&rest, "unquote").is_some() => {
+                break expand(&rest, depth, env)?
+            }
+            Object::Pair(pair) => pair.clone(),
+            other => break other.clone(),
+        };
+
+        match form_argument(&pair.car(), "unquote-splicing") {
+            Some(form) if depth == 1 => {
+                let spliced = eval(&form, env)?;
+                let spliced = spliced.to_vec().ok_or_else(|| {
+                    EvalError::new(format!("unquote-splicing expects a list, got {}", spliced))
+                })?;
+                items.extend(spliced);
+            }
+            _ => items.push(expand(&pair.car(), depth, env)?),
+        }
+        rest = pair.cdr();
+    };
+
+    Ok(items
+        .into_iter()
+        .rev()
+        .fold(tail, |tail, item| Object::cons(item, tail)))
+}
+
+/// `(quasiquote template)` is `template` with its unquoted forms replaced.
+pub fn quasiquote(list: &[Object], env: &Rc<RefCell<Env>>) -> Result<Object, EvalError> {
+    match list {
+        [_, template] => expand(template, 1, env),
+        _ => Err(EvalError::new("quasiquote expects one template")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builtins::global_env;
+    use crate::eval::eval_str;
+
+    fn eval(program: &str) -> String {
+        match eval_str(program, &global_env()) {
+            Ok(value) => value.to_string(),
+            Err(e) => e.message().to_string(),
+        }
+    }
+
+    #[test]
+    fn test_quasiquote() {
+        assert_eq!(eval("`(1 ,(+ 1 1) ,@(list 3 4) 5)"), "(1 2 3 4 5)");
+        assert_eq!(eval("(define x 'y) `(a . ,x)"), "(a . y)");
+        assert_eq!(
+            eval("`(a `(b ,(c ,(+ 1 2))))"),
+            "(a (quasiquote (b (unquote (c 3)))))"
+        );
+        assert_eq!(eval("`,(car '(1))"), "1");
+        assert_eq!(eval("`(,@'() . 2)"), "2");
+        assert_eq!(eval("`(1 ,@2)"), "unquote-splicing expects a list, got 2");
+    }
+}
@@ -0,0 +1,180 @@
+//! Rate limiters, for scripts that must not call a service too often.
+//!
+//! `(make-rate-limiter 10)` lets through 10 calls a second: `(acquire
+//! limiter)` waits until the next call may go ahead, and `(try-acquire
+//! limiter)` returns whether it may now, without waiting. A limiter is a
+//! token bucket, holding one token by default; `#:burst n` lets it keep up
+//! to `n`, so that after a quiet spell that many calls go ahead at once.
+//!
+//! Inside a fiber, `acquire` lets the other fibers run while it waits. A
+//! limiter can be shared by threads: `par-map` workers using one wait on
+//! the limiter itself, not a copy of it.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+
+use crate::bench;
+use crate::eval::EvalError;
+use crate::fiber;
+use crate::object::Object;
+use crate::retry;
+
+pub struct RateLimiter {
+    per_second: f64,
+    burst: f64,
+    /// The tokens held, and when that was last brought up to date.
+    bucket: Mutex<(f64, f64)>,
+}
+
+impl RateLimiter {
+    pub fn new(per_second: f64, burst: u32) -> Self {
+        let burst = f64::from(burst.max(1));
+        Self {
+            per_second,
+            burst,
+            bucket: Mutex::new((burst, bench::now())),
+        }
+    }
+
+    pub fn per_second(&self) -> f64 {
+        self.per_second
+    }
+
+    /// Takes a token, or returns in how many milliseconds there is one.
+    fn take(&self) -> Result<(), f64> {
+        let mut bucket = self.bucket.lock().unwrap_or_else(|e| e.into_inner());
+        let (tokens, updated) = *bucket;
+        let now = bench::now();
+        let tokens = (tokens + (now - updated) / 1000.0 * self.per_second).min(self.burst);
+        if tokens >= 1.0 {
+            *bucket = (tokens - 1.0, now);
+            Ok(())
+        } else {
+            *bucket = (tokens, now);
+            Err((1.0 - tokens) / self.per_second * 1000.0)
+        }
+    }
+
+    /// Waits until a token can be taken, and takes it.
+    pub fn acquire(&self) -> Result<(), EvalError> {
+        while let Err(ms) = self.take() {
+            if !fiber::pause() {
+                retry::wait(ms)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn try_acquire(&self) -> bool {
+        self.take().is_ok()
+    }
+}
+
+/// Limiters in use by other threads, by the number they were shared as.
+static SHARED: Mutex<Option<HashMap<u64, Weak<RateLimiter>>>> = Mutex::new(None);
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// A name another thread can find `limiter` by with `shared`, for as long
+/// as it is alive.
+pub(crate) fn share(limiter: &Arc<RateLimiter>) -> u64 {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let mut shared = SHARED.lock().unwrap_or_else(|e| e.into_inner());
+    let shared = shared.get_or_insert_with(HashMap::new);
+    shared.retain(|_, limiter| limiter.strong_count() > 0);
+    shared.insert(id, Arc::downgrade(limiter));
+    id
+}
+
+pub(crate) fn shared(id: u64) -> Option<Arc<RateLimiter>> {
+    let shared = SHARED.lock().unwrap_or_else(|e| e.into_inner());
+    shared.as_ref()?.get(&id)?.upgrade()
+}
+
+/// `(make-rate-limiter per-second [#:burst n])`
+pub fn make_rate_limiter(args: &[Object]) -> Result<Object, EvalError> {
+    let per_second = match args.first() {
+        Some(Object::Integer(n)) if *n > 0 => *n as f64,
+        Some(Object::Float(f)) if *f > 0.0 => *f,
+        _ => {
+            return Err(EvalError::new(
+                "make-rate-limiter expects a positive number of calls a second",
+            ))
+        }
+    };
+    let burst = match &args[1..] {
+        [] => 1,
+        [Object::Symbol(key), Object::Integer(n)] if key == "#:burst" && *n > 0 => {
+            u32::try_from(*n).unwrap_or(u32::MAX)
+        }
+        _ => {
+            return Err(EvalError::new(
+                "make-rate-limiter accepts only #:burst with a positive integer",
+            ))
+        }
+    };
+
+    Ok(Object::RateLimiter(Arc::new(RateLimiter::new(
+        per_second, burst,
+    ))))
+}
+
+pub fn acquire(args: &[Object]) -> Result<Object, EvalError> {
+    match args {
+        [Object::RateLimiter(limiter)] => limiter.acquire().map(|()| Object::Void),
+        _ => Err(EvalError::new("acquire expects a rate limiter")),
+    }
+}
+
+pub fn try_acquire(args: &[Object]) -> Result<Object, EvalError> {
+    match args {
+        [Object::RateLimiter(limiter)] => Ok(Object::Bool(limiter.try_acquire())),
+        _ => Err(EvalError::new("try-acquire expects a rate limiter")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builtins::global_env;
+    use crate::eval::eval_str;
+
+    #[test]
+    fn test_rate_limiter() {
+        let env = global_env();
+        let eval = |program: &str| match eval_str(program, &env) {
+            Ok(value) => value.to_string(),
+            Err(e) => e.message().to_string(),
+        };
+
+        eval("(define limiter (make-rate-limiter 50 #:burst 2))");
+        assert_eq!(
+            eval("(list (try-acquire limiter) (try-acquire limiter) (try-acquire limiter))"),
+            "(#t #t #f)"
+        );
+        let start = bench::now();
+        eval("(acquire limiter) (acquire limiter)");
+        // Two tokens, at 20 ms each, less the time since the last was taken.
+        assert!(bench::now() - start >= 30.0);
+        assert_eq!(
+            eval("(make-rate-limiter 0)"),
+            "make-rate-limiter expects a positive number of calls a second"
+        );
+    }
+
+    #[test]
+    fn test_threads() {
+        let limiter = Arc::new(RateLimiter::new(100.0, 1));
+        let id = share(&limiter);
+        let start = bench::now();
+        std::thread::scope(|scope| {
+            for _ in 0..3 {
+                scope.spawn(|| shared(id).unwrap().acquire().unwrap());
+            }
+        });
+
+        assert!(bench::now() - start >= 15.0);
+        drop(limiter);
+        assert!(shared(id).is_none());
+    }
+}
@@ -0,0 +1,219 @@
+use core::cmp::Ordering;
+use core::fmt;
+use core::fmt::Formatter;
+use core::ops::{Add, Mul, Neg, Sub};
+
+use crate::bigint::BigInt;
+
+/// An exact fraction kept in lowest terms with a positive denominator.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rational {
+    numerator: BigInt,
+    denominator: BigInt,
+}
+
+impl Rational {
+    /// Builds `numerator/denominator` in lowest terms, or `None` if the
+    /// denominator is zero.
+    pub fn new(numerator: BigInt, denominator: BigInt) -> Option<Self> {
+        if denominator.is_zero() {
+            return None;
+        }
+
+        let divisor = gcd(&numerator, &denominator);
+        let (mut numerator, _) = numerator.div_rem(&divisor)?;
+        let (mut denominator, _) = denominator.div_rem(&divisor)?;
+
+        if denominator.is_negative() {
+            numerator = -&numerator;
+            denominator = -&denominator;
+        }
+
+        Some(Self {
+            numerator,
+            denominator,
+        })
+    }
+
+    pub fn from_integer(n: BigInt) -> Self {
+        Self {
+            numerator: n,
+            denominator: BigInt::from_i64(1),
+        }
+    }
+
+    /// Converts a finite float into the exact fraction it represents.
+    pub fn from_f64(value: f64) -> Option<Self> {
+        if !value.is_finite() {
+            return None;
+        }
+
+        let bits = value.to_bits();
+        let sign = if bits >> 63 == 0 { 1 } else { -1 };
+        let exponent = ((bits >> 52) & 0x7ff) as i32;
+        let mantissa = if exponent == 0 {
+            (bits & 0xf_ffff_ffff_ffff) << 1
+        } else {
+            (bits & 0xf_ffff_ffff_ffff) | 0x10_0000_0000_0000
+        };
+        let exponent = exponent - 1075;
+
+        let numerator = BigInt::from_i64(sign * mantissa as i64);
+        let power = BigInt::from_i64(2).pow(exponent.unsigned_abs());
+
+        if exponent >= 0 {
+            Some(Self::from_integer(&numerator * &power))
+        } else {
+            Self::new(numerator, power)
+        }
+    }
+
+    pub fn numerator(&self) -> &BigInt {
+        &self.numerator
+    }
+
+    pub fn denominator(&self) -> &BigInt {
+        &self.denominator
+    }
+
+    pub fn is_integer(&self) -> bool {
+        self.denominator == BigInt::from_i64(1)
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.numerator.is_zero()
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        self.numerator.to_f64() / self.denominator.to_f64()
+    }
+
+    /// Divides two fractions, or returns `None` when `other` is zero.
+    pub fn checked_div(&self, other: &Self) -> Option<Self> {
+        Self::new(
+            &self.numerator * &other.denominator,
+            &self.denominator * &other.numerator,
+        )
+    }
+
+    pub fn pow(&self, exp: u32) -> Self {
+        Self {
+            numerator: self.numerator.pow(exp),
+            denominator: self.denominator.pow(exp),
+        }
+    }
+
+    pub fn recip(&self) -> Option<Self> {
+        Self::new(self.denominator.clone(), self.numerator.clone())
+    }
+}
+
+fn gcd(a: &BigInt, b: &BigInt) -> BigInt {
+    let mut a = a.abs();
+    let mut b = b.abs();
+
+    while !b.is_zero() {
+        let (_, remainder) = a.div_rem(&b).unwrap();
+        a = b;
+        b = remainder;
+    }
+
+    a
+}
+
+impl Add for &Rational {
+    type Output = Rational;
+
+    fn add(self, other: &Rational) -> Rational {
+        Rational::new(
+            &(&self.numerator * &other.denominator) + &(&other.numerator * &self.denominator),
+            &self.denominator * &other.denominator,
+        )
+        .unwrap()
+    }
+}
+
+impl Sub for &Rational {
+    type Output = Rational;
+
+    fn sub(self, other: &Rational) -> Rational {
+        self + &-other
+    }
+}
+
+impl Mul for &Rational {
+    type Output = Rational;
+
+    fn mul(self, other: &Rational) -> Rational {
+        Rational::new(
+            &self.numerator * &other.numerator,
+            &self.denominator * &other.denominator,
+        )
+        .unwrap()
+    }
+}
+
+impl Neg for &Rational {
+    type Output = Rational;
+
+    fn neg(self) -> Rational {
+        Rational {
+            numerator: -&self.numerator,
+            denominator: self.denominator.clone(),
+        }
+    }
+}
+
+impl Ord for Rational {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (&self.numerator * &other.denominator).cmp(&(&other.numerator * &self.denominator))
+    }
+}
+
+impl PartialOrd for Rational {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for Rational {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.numerator, self.denominator)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ratio(n: i64, d: i64) -> Rational {
+        Rational::new(BigInt::from_i64(n), BigInt::from_i64(d)).unwrap()
+    }
+
+    #[test]
+    fn test_normalization() {
+        assert_eq!(ratio(2, 4).to_string(), "1/2");
+        assert_eq!(ratio(3, -9).to_string(), "-1/3");
+        assert!(Rational::new(BigInt::from_i64(1), BigInt::zero()).is_none());
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        assert_eq!((&ratio(1, 3) + &ratio(1, 6)).to_string(), "1/2");
+        assert_eq!((&ratio(1, 3) - &ratio(1, 2)).to_string(), "-1/6");
+        assert_eq!((&ratio(2, 3) * &ratio(3, 4)).to_string(), "1/2");
+        assert!(ratio(1, 3) < ratio(1, 2));
+        assert!((&ratio(1, 3) + &ratio(2, 3)).is_integer());
+    }
+
+    #[test]
+    fn test_from_f64_is_exact() {
+        assert_eq!(Rational::from_f64(0.5).unwrap().to_string(), "1/2");
+        assert_eq!(Rational::from_f64(-3.0).unwrap().to_string(), "-3/1");
+        assert_eq!(
+            Rational::from_f64(0.1).unwrap().to_string(),
+            "3602879701896397/36028797018963968"
+        );
+        assert!(Rational::from_f64(f64::NAN).is_none());
+    }
+}
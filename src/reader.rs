@@ -0,0 +1,391 @@
+//! The reader at run time, for programs that treat code as data.
+//!
+//! `(read-string "(+ 1 2)")` returns the first datum written in a string,
+//! unevaluated, and `(read)` the next one from standard input, reading as
+//! many lines as it needs; it returns void at the end of the input.
+//! `(eval datum)` evaluates a datum as code in the global environment, so
+//! that `(eval (read-string "(+ 1 2)"))` is 3, and `(eval datum env)` in
+//! `env`, from `(the-environment)` where it is evaluated or
+//! `(interaction-environment)`. Under taint tracking, the
+//! strings read from standard input or from a tainted string are tainted.
+//!
+//! Data from untrusted sources can be read within `Limits`, given to `read`,
+//! `read-string` and `json-parse` as options: `#:max-depth n` for how
+//! deeply lists may nest, `#:max-atom-length n` for the longest atom, in
+//! bytes of source text, and `#:max-nodes n` for how many atoms and lists a
+//! datum may hold. Reading stops as soon as one is exceeded, with an
+//! ordinary error of kind `"read-limit"` rather than one that ends the
+//! evaluation, so that `guard` can handle it:
+//!
+//! ```text
+//! (read-string "((((x))))" #:max-depth 3)
+//! ; error: read-string: nested deeper than 3 levels
+//! (guard (e ((equal? (hash-table-ref e "kind") "read-limit") 'too-deep))
+//!   (read-string "((((x))))" #:max-depth 3))
+//! ; too-deep
+//! ```
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{self, BufRead};
+use std::rc::Rc;
+
+use crate::builtins::check_arity;
+use crate::env::{self, Env};
+use crate::eval::{eval, EvalError};
+use crate::lexer::{tokenizer_with_spans, Token};
+use crate::object::Object;
+use crate::parser::parse_tokens;
+use crate::repl::FormReader;
+use crate::taint;
+
+/// Limits on the data read from untrusted input, none of them set by
+/// default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Limits {
+    pub max_depth: Option<usize>,
+    pub max_atom_length: Option<usize>,
+    pub max_nodes: Option<usize>,
+}
+
+impl Limits {
+    /// The limits given by the options of a call to `name`, such as
+    /// `#:max-depth 10`.
+    pub(crate) fn from_options(name: &str, options: &[Object]) -> Result<Self, EvalError> {
+        let mut limits = Self::default();
+        for option in options.chunks(2) {
+            let (limit, n) = match option {
+                [Object::Symbol(key), Object::Integer(n)] if *n >= 0 => match key.as_str() {
+                    "#:max-depth" => (&mut limits.max_depth, *n),
+                    "#:max-atom-length" => (&mut limits.max_atom_length, *n),
+                    "#:max-nodes" => (&mut limits.max_nodes, *n),
+                    _ => return Err(Self::invalid(name)),
+                },
+                _ => return Err(Self::invalid(name)),
+            };
+            *limit = Some(n as usize);
+        }
+
+        Ok(limits)
+    }
+
+    fn invalid(name: &str) -> EvalError {
+        EvalError::new(format!(
+            "{} accepts only #:max-depth, #:max-atom-length and #:max-nodes \
+             with non-negative integers",
+            name
+        ))
+    }
+
+    /// Checks a list or an array opened `depth` deep, counting it as the
+    /// `nodes`th node.
+    pub(crate) fn check_list(&self, depth: usize, nodes: usize) -> Result<(), String> {
+        match self.max_depth {
+            Some(max) if depth > max => Err(format!("nested deeper than {} levels", max)),
+            _ => self.check_nodes(nodes),
+        }
+    }
+
+    /// Checks an atom `length` bytes long, counting it as the `nodes`th
+    /// node.
+    pub(crate) fn check_atom(&self, length: usize, nodes: usize) -> Result<(), String> {
+        match self.max_atom_length {
+            Some(max) if length > max => Err(format!("atom longer than {} bytes", max)),
+            _ => self.check_nodes(nodes),
+        }
+    }
+
+    fn check_nodes(&self, nodes: usize) -> Result<(), String> {
+        match self.max_nodes {
+            Some(max) if nodes > max => Err(format!("more than {} nodes", max)),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Checks tokens against `Limits` as they are read, before they are
+/// parsed, so that no datum over them is ever built.
+#[derive(Debug, Default)]
+pub(crate) struct Budget {
+    limits: Limits,
+    /// The nodes of the datum being read so far.
+    nodes: usize,
+    /// How deeply the next datum is nested, counting quotes, which wrap it
+    /// in a list.
+    depth: usize,
+    /// For each list open, the quotes before it.
+    lists: Vec<usize>,
+    /// The quotes read since the last datum started.
+    quotes: usize,
+}
+
+impl Budget {
+    pub(crate) fn new(limits: Limits) -> Self {
+        Self {
+            limits,
+            ..Self::default()
+        }
+    }
+
+    pub(crate) fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    pub(crate) fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// Counts `token`, whose source text is `length` bytes long.
+    pub(crate) fn token(&mut self, token: &Token, length: usize) -> Result<(), String> {
+        match token {
+            Token::Quote | Token::Quasiquote | Token::Unquote | Token::UnquoteSplicing => {
+                self.depth += 1;
+                self.quotes += 1;
+                self.nodes += 1;
+                self.limits.check_list(self.depth, self.nodes)
+            }
+            Token::LeftParenthesis => {
+                self.depth += 1;
+                self.lists.push(std::mem::take(&mut self.quotes));
+                self.nodes += 1;
+                self.limits.check_list(self.depth, self.nodes)
+            }
+            Token::RightParenthesis => {
+                self.depth = self.depth.saturating_sub(1);
+                self.quotes = self.lists.pop().unwrap_or(0);
+                self.end_datum();
+                Ok(())
+            }
+            Token::Dot | Token::Comment(_) => Ok(()),
+            _ => {
+                self.nodes += 1;
+                let checked = self.limits.check_atom(length, self.nodes);
+                self.end_datum();
+                checked
+            }
+        }
+    }
+
+    /// Closes the quotes around a datum just read.
+    fn end_datum(&mut self) {
+        self.depth = self.depth.saturating_sub(std::mem::take(&mut self.quotes));
+        if self.depth == 0 {
+            self.nodes = 0;
+        }
+    }
+}
+
+struct Input {
+    reader: FormReader,
+    /// Data read from lines already consumed, but not returned yet.
+    read: VecDeque<Object>,
+}
+
+thread_local! {
+    static INPUT: RefCell<Input> = RefCell::new(Input {
+        reader: FormReader::new(),
+        read: VecDeque::new(),
+    });
+}
+
+/// `(read-string s options...)`.
+pub fn read_string(args: &[Object]) -> Result<Object, EvalError> {
+    let [Object::String(s), options @ ..] = args else {
+        return Err(EvalError::new("read-string expects a string"));
+    };
+    let error = |message: &str| EvalError::new(format!("read-string: {}", message));
+    let mut budget = Budget::new(Limits::from_options("read-string", options)?);
+
+    let tokens = tokenizer_with_spans(s).map_err(|e| error(e.message()))?;
+    for (token, span) in &tokens {
+        budget
+            .token(token, span.end - span.start)
+            .map_err(|e| EvalError::read_limit(format!("read-string: {}", e)))?;
+    }
+    let datum = parse_tokens(tokens.into_iter().map(|(token, _)| token).collect())
+        .map_err(|e| error(e.message()))?
+        .into_iter()
+        .next()
+        .ok_or_else(|| EvalError::new("read-string: the string holds no datum"))?;
+
+    Ok(match s.is_tainted() {
+        true => taint::mark_read(taint::taint_all(datum)),
+        false => datum,
+    })
+}
+
+/// Reads the next datum from `input`, or returns void at its end.
+fn read_from(input: &mut dyn BufRead, limits: Limits) -> Result<Object, EvalError> {
+    INPUT.with(|state| state.borrow_mut().reader.set_limits(limits));
+    loop {
+        if let Some(datum) = INPUT.with(|state| state.borrow_mut().read.pop_front()) {
+            return Ok(taint::mark_read(taint::mark(datum)));
+        }
+
+        let mut line = String::new();
+        let read = input
+            .read_line(&mut line)
+            .map_err(|e| EvalError::new(format!("read: {}", e)))?;
+        let line = line.strip_suffix('\n').unwrap_or(&line);
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        let end = INPUT.with(|state| {
+            let mut state = state.borrow_mut();
+            if read == 0 {
+                return match state.reader.is_empty() {
+                    true => Ok(true),
+                    false => {
+                        state.reader = FormReader::with_limits(limits);
+                        Err(EvalError::new("read: the input ends inside a datum"))
+                    }
+                };
+            }
+            match state.reader.push_line(line) {
+                Ok(Some(data)) => state.read.extend(data),
+                Ok(None) => {}
+                Err(e) if e.is_over_limit() => {
+                    return Err(EvalError::read_limit(format!("read: {}", e.message())))
+                }
+                Err(e) => return Err(EvalError::new(format!("read: {}", e.message()))),
+            }
+            Ok(false)
+        })?;
+        if end {
+            return Ok(Object::Void);
+        }
+    }
+}
+
+/// `(read options...)`.
+pub fn read(args: &[Object]) -> Result<Object, EvalError> {
+    read_from(&mut io::stdin().lock(), Limits::from_options("read", args)?)
+}
+
+/// The global environment of the evaluation under way.
+fn interaction_env(name: &str) -> Result<Rc<RefCell<Env>>, EvalError> {
+    env::current().ok_or_else(|| EvalError::new(format!("{}: no evaluation is under way", name)))
+}
+
+/// `(eval datum [environment])` evaluates `datum` as code in
+/// `environment`, or in the global environment.
+pub fn eval_datum(args: &[Object]) -> Result<Object, EvalError> {
+    let (datum, env) = match args {
+        [datum] => (datum, interaction_env("eval")?),
+        [datum, Object::Environment(env)] => (datum, env.clone()),
+        _ => {
+            return Err(EvalError::new(
+                "eval expects a datum and an optional environment",
+            ))
+        }
+    };
+    taint::check_eval(datum)?;
+
+    eval(datum, &env)
+}
+
+/// `(interaction-environment)`.
+pub fn interaction_environment(args: &[Object]) -> Result<Object, EvalError> {
+    check_arity("interaction-environment", args, 0)?;
+    interaction_env("interaction-environment").map(Object::Environment)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builtins::global_env;
+    use crate::eval::eval_str;
+
+    #[test]
+    fn test_read_string_and_eval() {
+        let env = global_env();
+        let eval = |program: &str| match eval_str(program, &env) {
+            Ok(value) => value.to_string(),
+            Err(e) => e.message().to_string(),
+        };
+
+        assert_eq!(eval("(read-string \"(+ 1 2) ignored\")"), "(+ 1 2)");
+        assert_eq!(eval("(eval (read-string \"(+ 1 2)\"))"), "3");
+        assert_eq!(
+            eval("(define x 'global) (let ((x 'local)) (eval 'x))"),
+            "global"
+        );
+        assert_eq!(
+            eval("(eval (list 'define 'y (list 'quote '(a b)))) y"),
+            "(a b)"
+        );
+        assert_eq!(eval("(map eval '((+ 1 2) (car '(a b))))"), "(3 a)");
+        assert_eq!(eval("(apply eval (list '(* 6 7)))"), "42");
+        assert_eq!(
+            eval("(let ((x 'local)) (eval 'x (the-environment)))"),
+            "local"
+        );
+        assert_eq!(
+            eval("(let ((x 'local)) (eval 'x (interaction-environment)))"),
+            "global"
+        );
+        assert_eq!(
+            eval("(eval 'x 'env)"),
+            "eval expects a datum and an optional environment"
+        );
+        assert_eq!(eval("(read-string \"(1 2\")"), "read-string: missing ')'");
+        assert_eq!(
+            eval("(read-string \" ; nothing\")"),
+            "read-string: the string holds no datum"
+        );
+    }
+
+    #[test]
+    fn test_read() {
+        let mut input = io::Cursor::new("(define\n  x 1) \"two\"\n(3\n");
+        let mut next = || read_from(&mut input, Limits::default()).map(|datum| datum.to_string());
+
+        assert_eq!(next().unwrap(), "(define x 1)");
+        assert_eq!(next().unwrap(), "\"two\"");
+        assert_eq!(
+            next().unwrap_err().message(),
+            "read: the input ends inside a datum"
+        );
+        assert_eq!(next().unwrap(), "");
+    }
+
+    #[test]
+    fn test_limits() {
+        let env = global_env();
+        let error = |program| eval_str(program, &env).unwrap_err().message().to_string();
+
+        assert!(eval_str(
+            "(read-string \"'((x)) ; (((\" #:max-depth 3 #:max-nodes 4)",
+            &env
+        )
+        .is_ok());
+        assert_eq!(
+            error("(read-string \"'((x))\" #:max-depth 2)"),
+            "read-string: nested deeper than 2 levels"
+        );
+        assert_eq!(
+            error("(read-string \"(a b c longer)\" #:max-atom-length 5)"),
+            "read-string: atom longer than 5 bytes"
+        );
+        assert_eq!(
+            error("(read-string \"(1 (2 3))\" #:max-nodes 4)"),
+            "read-string: more than 4 nodes"
+        );
+        assert_eq!(
+            error("(json-parse \"[[1], [[2]]]\" #:max-depth 2)"),
+            "json-parse: nested deeper than 2 levels at offset 7"
+        );
+        assert!(eval_str("(json-parse \"[1, 2]\" #:max-nodes 3)", &env).is_ok());
+        assert!(eval_str("(read-string \"x\" #:max-depth -1)", &env).is_err());
+
+        let mut input = io::Cursor::new("(1 2)\n(1 2 3)\n(4)\n");
+        let limits = Limits {
+            max_nodes: Some(3),
+            ..Limits::default()
+        };
+        assert_eq!(read_from(&mut input, limits).unwrap().to_string(), "(1 2)");
+        assert_eq!(
+            read_from(&mut input, limits).unwrap_err().message(),
+            "read: more than 3 nodes"
+        );
+        assert_eq!(read_from(&mut input, limits).unwrap().to_string(), "(4)");
+    }
+}
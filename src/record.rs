@@ -0,0 +1,319 @@
+//! Records, for data with named fields.
+//!
+//! `(defstruct point x y)` defines a record type `point` with the fields
+//! `x` and `y`, along with its procedures: the constructor `make-point`,
+//! taking a value for every field, the predicate `point?`, the accessors
+//! `point-x` and `point-y` and the mutators `set-point-x!` and
+//! `set-point-y!`.
+//!
+//! `define-record-type` is the R7RS form, which names every procedure and
+//! can leave out mutators, or fields from the constructor, which then start
+//! out void:
+//!
+//! ```text
+//! (define-record-type <point> (make-point x y) point?
+//!   (x point-x set-point-x!)
+//!   (y point-y))
+//! ```
+//!
+//! Records print as `#<point 1 2>` and are only `equal?` to themselves, as
+//! they can change.
+//!
+//! They have nothing to do with the `define-record` schemas of `codegen`,
+//! whose Rust types convert to and from lists rather than records.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::env::Env;
+use crate::eval::EvalError;
+use crate::gc;
+use crate::hooks;
+use crate::interpreter::{Arity, Native};
+use crate::object::Object;
+use crate::symbol::Symbol;
+
+pub struct RecordType {
+    pub name: Symbol,
+    pub fields: Vec<Symbol>,
+}
+
+pub struct Record {
+    pub kind: Rc<RecordType>,
+    pub(crate) fields: RefCell<Vec<Object>>,
+}
+
+/// A field of a record type, with the names of its procedures.
+struct Field {
+    name: Symbol,
+    accessor: Symbol,
+    mutator: Option<Symbol>,
+}
+
+fn define(env: &Rc<RefCell<Env>>, native: Native) {
+    let name = Symbol::intern(native.name());
+    let value = Object::Native(Rc::new(native));
+    hooks::define(name.as_str(), &value);
+    env.borrow_mut().define(name, value);
+}
+
+/// The record `obj`, if it is one of type `kind`.
+fn downcast<'a>(obj: &'a Object, kind: &Rc<RecordType>) -> Option<&'a Record> {
+    match obj {
+        Object::Record(record) if Rc::ptr_eq(&record.kind, kind) => Some(record),
+        _ => None,
+    }
+}
+
+/// Defines the procedures of a record type with `fields`, whose
+/// constructor takes the values of the fields at `initialized`.
+fn define_type(
+    env: &Rc<RefCell<Env>>,
+    name: Symbol,
+    constructor: (Symbol, Vec<usize>),
+    predicate: Symbol,
+    fields: Vec<Field>,
+) {
+    let kind = Rc::new(RecordType {
+        name,
+        fields: fields.iter().map(|field| field.name).collect(),
+    });
+
+    let (constructor, initialized) = constructor;
+    let k = kind.clone();
+    define(
+        env,
+        Native::new(
+            constructor.as_str(),
+            Arity::Exact(initialized.len()),
+            move |args| {
+                let mut fields = vec![Object::Void; k.fields.len()];
+                for (&i, arg) in initialized.iter().zip(args) {
+                    fields[i] = arg.clone();
+                }
+                let record = Rc::new(Record {
+                    kind: k.clone(),
+                    fields: RefCell::new(fields),
+                });
+                gc::track(&record);
+                Ok(Object::Record(record))
+            },
+        ),
+    );
+
+    let k = kind.clone();
+    define(
+        env,
+        Native::new(predicate.as_str(), Arity::Exact(1), move |args| {
+            Ok(Object::Bool(downcast(&args[0], &k).is_some()))
+        }),
+    );
+
+    for (i, field) in fields.into_iter().enumerate() {
+        let (k, accessor) = (kind.clone(), field.accessor);
+        define(
+            env,
+            Native::new(accessor.as_str(), Arity::Exact(1), move |args| {
+                let record =
+                    downcast(&args[0], &k).ok_or_else(|| expected(accessor, &k, &args[0]))?;
+                let value = record.fields.borrow()[i].clone();
+                Ok(value)
+            }),
+        );
+
+        if let Some(mutator) = field.mutator {
+            let k = kind.clone();
+            define(
+                env,
+                Native::new(mutator.as_str(), Arity::Exact(2), move |args| {
+                    let record =
+                        downcast(&args[0], &k).ok_or_else(|| expected(mutator, &k, &args[0]))?;
+                    record.fields.borrow_mut()[i] = args[1].clone();
+                    Ok(Object::Void)
+                }),
+            );
+        }
+    }
+}
+
+fn expected(procedure: Symbol, kind: &RecordType, got: &Object) -> EvalError {
+    EvalError::new(format!(
+        "{} expects a {}, got {}",
+        procedure, kind.name, got
+    ))
+}
+
+fn symbol(obj: &Object) -> Option<Symbol> {
+    match obj {
+        Object::Symbol(name) => Some(*name),
+        _ => None,
+    }
+}
+
+fn named(format: &str, name: Symbol) -> Symbol {
+    Symbol::intern(&format.replace("{}", name.as_str()))
+}
+
+/// `(defstruct name field...)`
+pub fn defstruct(list: &[Object], env: &Rc<RefCell<Env>>) -> Result<Object, EvalError> {
+    let invalid = || EvalError::new("defstruct expects a name and field names");
+    let (name, fields) = match list {
+        [_, name, fields @ ..] => (symbol(name).ok_or_else(invalid)?, fields),
+        _ => return Err(invalid()),
+    };
+    let fields = fields
+        .iter()
+        .map(|field| {
+            let field = symbol(field).ok_or_else(invalid)?;
+            let prefix = format!("{}-{}", name, field);
+            Ok(Field {
+                name: field,
+                accessor: Symbol::intern(&prefix),
+                mutator: Some(named("set-{}!", Symbol::intern(&prefix))),
+            })
+        })
+        .collect::<Result<Vec<_>, EvalError>>()?;
+
+    define_type(
+        env,
+        name,
+        (named("make-{}", name), (0..fields.len()).collect()),
+        named("{}?", name),
+        fields,
+    );
+
+    Ok(Object::Void)
+}
+
+/// `(define-record-type name (constructor field...) predicate
+/// (field accessor [mutator])...)`, where `<name>` names the type `name`.
+pub fn define_record_type(list: &[Object], env: &Rc<RefCell<Env>>) -> Result<Object, EvalError> {
+    let invalid = || {
+        EvalError::new("define-record-type expects a name, a constructor, a predicate and fields")
+    };
+    let (name, constructor, predicate, specs) = match list {
+        [_, name, constructor, predicate, specs @ ..] => (
+            symbol(name).ok_or_else(invalid)?,
+            constructor.to_vec().ok_or_else(invalid)?,
+            symbol(predicate).ok_or_else(invalid)?,
+            specs,
+        ),
+        _ => return Err(invalid()),
+    };
+    let name = Symbol::intern(
+        name.as_str()
+            .strip_prefix('<')
+            .and_then(|name| name.strip_suffix('>'))
+            .unwrap_or(name.as_str()),
+    );
+
+    let fields = specs
+        .iter()
+        .map(|spec| {
+            let names = spec
+                .to_vec()
+                .and_then(|names| names.iter().map(symbol).collect::<Option<Vec<_>>>());
+            match names.as_deref() {
+                Some(&[name, accessor]) => Ok(Field {
+                    name,
+                    accessor,
+                    mutator: None,
+                }),
+                Some(&[name, accessor, mutator]) => Ok(Field {
+                    name,
+                    accessor,
+                    mutator: Some(mutator),
+                }),
+                _ => Err(EvalError::new(format!(
+                    "invalid field {} of record type {}",
+                    spec, name
+                ))),
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let (constructor_name, initialized) = match constructor.split_first() {
+        Some((constructor, initialized)) => (symbol(constructor).ok_or_else(invalid)?, initialized),
+        None => return Err(invalid()),
+    };
+    let initialized = initialized
+        .iter()
+        .map(|field| {
+            fields
+                .iter()
+                .position(|f| Some(f.name) == symbol(field))
+                .ok_or_else(|| {
+                    EvalError::new(format!("{} is not a field of record type {}", field, name))
+                })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    define_type(
+        env,
+        name,
+        (constructor_name, initialized),
+        predicate,
+        fields,
+    );
+
+    Ok(Object::Void)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builtins::global_env;
+    use crate::eval::eval_str;
+
+    #[test]
+    fn test_defstruct() {
+        let env = global_env();
+        let eval = |program: &str| match eval_str(program, &env) {
+            Ok(value) => value.to_string(),
+            Err(e) => e.message().to_string(),
+        };
+
+        eval("(defstruct point x y) (define p (make-point 1 2))");
+        assert_eq!(
+            eval("(list (point-x p) (point-y p) (point? p) (point? 1))"),
+            "(1 2 #t #f)"
+        );
+        eval("(set-point-x! p 10)");
+        assert_eq!(eval("p"), "#<point 10 2>");
+        assert_eq!(eval("(point-x 'p)"), "point-x expects a point, got p");
+        assert_eq!(
+            eval("(make-point 1)"),
+            "make-point expects 2 arguments, got 1"
+        );
+
+        // Types with the same name are still different types.
+        eval("(define old p) (defstruct point x y)");
+        assert_eq!(eval("(point? old)"), "#f");
+    }
+
+    #[test]
+    fn test_define_record_type() {
+        let env = global_env();
+        let eval = |program: &str| match eval_str(program, &env) {
+            Ok(value) => value.to_string(),
+            Err(e) => e.message().to_string(),
+        };
+
+        eval(
+            "(define-record-type <account> (open-account owner) account?
+               (owner account-owner)
+               (balance account-balance set-account-balance!))",
+        );
+        eval("(define a (open-account \"ana\"))");
+        eval("(set-account-balance! a 5)");
+        assert_eq!(eval("a"), "#<account \"ana\" 5>");
+        assert_eq!(
+            eval("(list (account-owner a) (account-balance a))"),
+            "(\"ana\" 5)"
+        );
+        assert_eq!(eval("(account? a)"), "#t");
+        assert_eq!(
+            eval("(define-record-type t (make-t y) t? (x t-x))"),
+            "y is not a field of record type t"
+        );
+    }
+}
@@ -0,0 +1,1008 @@
+//! The language reference, generated from the implementation.
+//!
+//! Every special form is described in `FORMS`, by how it is written and
+//! what it does, and every builtin in `DOCS`, its parameters coming from its
+//! signature. The procedures of the prelude are described by their
+//! docstrings: a lambda whose body starts with a string, followed by other
+//! forms, is documented by that string. Tests check that nothing is left
+//! out, so the reference cannot fall behind the code.
+//!
+//! `lisp-rs explain NAME` prints the entry for a name, and `lisp-rs
+//! reference` the whole reference as Markdown, with a section per entry:
+//!
+//! ```text
+//! $ lisp-rs explain substring
+//! (substring string start [end])
+//! builtin
+//!
+//! The characters of a string from index start up to end, or to its end.
+//!
+//!     string  string
+//!     start   integer
+//!     end     integer, optional
+//! ```
+
+use std::fmt::Write;
+
+use crate::builtins::{builtins, global_env};
+use crate::eval::EvalError;
+use crate::hooks;
+use crate::object::Object;
+use crate::printer;
+use crate::signature::{self, Parameter, Signature};
+
+/// The special forms, each as it is written and what it does.
+const FORMS: &[(&str, &str)] = &[
+    (
+        "(assert expr [message])",
+        "Fails, quoting expr and the message if given, unless expr is true.",
+    ),
+    (
+        "(assert-equal expected expr)",
+        "Fails unless the value of expr is equal? to expected.",
+    ),
+    (
+        "(begin body...)",
+        "Evaluates the forms of body in order and returns the value of the last.",
+    ),
+    (
+        "(case key ((datum...) body...)... [(else body...)])",
+        "Evaluates the body of the first clause listing the value of key, or of the else clause.",
+    ),
+    (
+        "(cons-stream first rest)",
+        "A stream: the pair of the value of first and a promise of rest, evaluated when stream-cdr asks for it.",
+    ),
+    (
+        "(declare (inline name...)...)",
+        "Does nothing when evaluated; a compiled program has the calls of the procedures named compiled in place, whatever their size.",
+    ),
+    (
+        "(define name value)",
+        "Binds name to value in the current environment; (define (name params...) body...) defines a procedure.",
+    ),
+    (
+        "(define-enum name variant...)",
+        "Defines each variant as a constant, and name? as the predicate recognizing them.",
+    ),
+    (
+        "(define-memoized (name params...) body...)",
+        "Defines a procedure whose results are cached by argument list, as if wrapped with memoize.",
+    ),
+    (
+        "(define-record-type <name> (constructor field...) predicate (field accessor [modifier])...)",
+        "Defines a record type with a constructor, a predicate, and an accessor and an optional modifier per field.",
+    ),
+    (
+        "(defmacro (name params...) body...)",
+        "Defines a macro: a use of name is replaced by the value of body, evaluated with params bound to the forms of the use.",
+    ),
+    (
+        "(defstruct name field...)",
+        "Defines a record type with make-name, name?, and a name-field accessor and set-name-field! modifier per field.",
+    ),
+    (
+        "(deftest name body...)",
+        "Defines a test, run by run-tests, replacing any other test of the same name.",
+    ),
+    (
+        "(delay expr)",
+        "A promise to evaluate expr in the current environment when it is first forced.",
+    ),
+    (
+        "(embed-file path)",
+        "The contents of a file, read when the form is evaluated or when the program is compiled.",
+    ),
+    (
+        "(guard (var clause...) body...)",
+        "Evaluates body, and when it fails tries the clauses like cond, with var bound to the error.",
+    ),
+    (
+        "(if test then [else])",
+        "Evaluates then if test is true, and else otherwise.",
+    ),
+    (
+        "(lambda params body...)",
+        "A procedure taking the parameters of params, a list possibly ending in a dotted rest parameter, or a symbol receiving every argument.",
+    ),
+    (
+        "(let ((name value)...) body...)",
+        "Evaluates body with each name bound to its value, evaluated in the enclosing environment.",
+    ),
+    (
+        "(let-values ((formals expr)...) body...)",
+        "Evaluates body with the values of each expr bound to its formals, a parameter list like that of a lambda.",
+    ),
+    (
+        "(load path)",
+        "Evaluates every form of a file in the current environment, as if it had been typed there.",
+    ),
+    (
+        "(macroexpand form)",
+        "The value of form, expanded as a use of a macro until it no longer is one.",
+    ),
+    (
+        "(macroexpand-1 form)",
+        "The expansion of the value of form if it is a use of a macro, and that value otherwise.",
+    ),
+    (
+        "(match expr (pattern [#:when guard] body...)...)",
+        "Evaluates the body of the first clause whose pattern fits the value of expr, with the symbols of the pattern bound to what they matched.",
+    ),
+    (
+        "(pipe (program arg...)...)",
+        "Runs the programs with the output of each connected to the input of the next, and returns the output of the last as a string.",
+    ),
+    (
+        "(profile expr)",
+        "Evaluates expr, prints the number of calls to each procedure and the time spent in it, and returns the value of expr.",
+    ),
+    (
+        "(provide name...)",
+        "Exports names from the module being required.",
+    ),
+    (
+        "(quasiquote template)",
+        "The template, written `template, with the value of each ,expr in it and the items of each ,@expr.",
+    ),
+    (
+        "(quote datum)",
+        "The datum itself, unevaluated; also written 'datum.",
+    ),
+    (
+        "(require path)",
+        "Evaluates a module file once, in a namespace of its own, and binds the names it provides.",
+    ),
+    (
+        "(run-tests)",
+        "Runs the tests defined in the global environment, printing how they went, and returns whether they all passed.",
+    ),
+    (
+        "(set! name value)",
+        "Changes the value of name where it is bound.",
+    ),
+    (
+        "(sql `(statement...))",
+        "The text of a parameterized SQL statement followed by its parameters, the unquoted values of the template.",
+    ),
+    (
+        "(the-environment)",
+        "The environment the form is evaluated in, for eval.",
+    ),
+    (
+        "(time body...)",
+        "Evaluates body, prints how long it took in milliseconds and returns the value of its last form.",
+    ),
+    (
+        "(trace name...)",
+        "Prints every call to the procedures named, with its arguments and result, and returns the names traced.",
+    ),
+    (
+        "(untrace [name...])",
+        "Stops tracing the procedures named, or every procedure, and returns the names still traced.",
+    ),
+    (
+        "(with-retry (option value...) body...)",
+        "Evaluates body again when it fails, waiting longer between attempts, as set by #:max, #:backoff, #:delay, #:max-delay, #:jitter and #:retry-if.",
+    ),
+    (
+        "(with-task-scope body...)",
+        "Evaluates body, then joins the tasks spawned in it; if any fails, those not yet run are cancelled.",
+    ),
+];
+
+/// What each builtin does.
+const DOCS: &[(&str, &str)] = &[
+    ("+", "The sum of the numbers."),
+    ("-", "The difference of a and b."),
+    ("*", "The product of the numbers."),
+    (
+        "/",
+        "The quotient of a and b, a rational unless b divides a evenly, or a float if either is.",
+    ),
+    ("expt", "base raised to the power exponent."),
+    ("abs", "The absolute value of x."),
+    ("min", "The least of the numbers."),
+    ("max", "The greatest of the numbers."),
+    ("floor", "The greatest integer at most x."),
+    ("ceiling", "The least integer at least x."),
+    ("round", "The integer closest to x, rounding halves to even."),
+    ("truncate", "x without its fraction."),
+    ("quotient", "n divided by d, rounded towards zero."),
+    ("remainder", "What is left of n after dividing it by d, with the sign of n."),
+    ("modulo", "n modulo d, with the sign of d."),
+    ("sqrt", "The square root of x, exact for exact squares."),
+    ("exp", "e raised to the power x."),
+    ("log", "The natural logarithm of x, or its logarithm in base."),
+    ("sin", "The sine of x radians."),
+    ("cos", "The cosine of x radians."),
+    ("tan", "The tangent of x radians."),
+    ("asin", "The arc sine of x, in radians."),
+    ("acos", "The arc cosine of x, in radians."),
+    (
+        "atan",
+        "The arc tangent of y, or the angle of the point (x, y), in radians.",
+    ),
+    (
+        "random",
+        "A random integer from 0 to below limit, or a float if limit is one.",
+    ),
+    (
+        "set-random-seed!",
+        "Seeds the generator random uses, so that what follows repeats.",
+    ),
+    ("exact->inexact", "x as a float."),
+    ("inexact->exact", "x as an exact number."),
+    ("=", "Whether a and b are equal numbers."),
+    ("<", "Whether a is less than b."),
+    (">", "Whether a is greater than b."),
+    ("<=", "Whether a is at most b."),
+    (">=", "Whether a is at least b."),
+    ("cons", "A pair of car and cdr."),
+    ("car", "The first element of a pair."),
+    ("cdr", "The second element of a pair, the rest of a list."),
+    ("set-car!", "Replaces the car of pair with obj."),
+    ("set-cdr!", "Replaces the cdr of pair with obj."),
+    ("list-set!", "Replaces the element of list at index k with obj."),
+    (
+        "append!",
+        "The lists joined together by linking each one's last pair to the next, changing them.",
+    ),
+    ("reverse!", "list in reverse order, made by relinking its pairs."),
+    ("list", "A list of the items."),
+    (
+        "eq?",
+        "Whether a and b are the same object, or equal symbols, booleans, small numbers or chars.",
+    ),
+    ("eqv?", "Whether a and b are eq?, or equal numbers of the same exactness."),
+    ("equal?", "Whether a and b are structurally equal."),
+    ("number?", "Whether obj is a number."),
+    ("string?", "Whether obj is a string."),
+    ("symbol?", "Whether obj is a symbol."),
+    ("pair?", "Whether obj is a pair."),
+    ("null?", "Whether obj is the empty list."),
+    ("list?", "Whether obj is a list ending in the empty list."),
+    ("procedure?", "Whether obj can be called."),
+    (
+        "diff",
+        "The edits turning old into new, each (changed path old new), (inserted path value) or (removed path value).",
+    ),
+    ("hash", "A hash of obj, the same for values that are equal?."),
+    (
+        "copy",
+        "A copy of obj with fresh lists and deques all the way down.",
+    ),
+    ("apply", "Calls procedure with the arguments, the last a list of more."),
+    ("values", "Returns the values at once, for call-with-values and let-values."),
+    (
+        "call-with-values",
+        "Calls consumer with the values producer returns when called with no arguments.",
+    ),
+    (
+        "force",
+        "The value of a promise, evaluated the first time it is forced; other values are returned as they are.",
+    ),
+    ("make-promise", "A promise already kept with value."),
+    ("promise?", "Whether obj is a promise."),
+    (
+        "map",
+        "The results of calling procedure on the elements of the lists, across them.",
+    ),
+    ("par-map", "map, with procedure applied on several threads at once."),
+    ("for-each", "Calls procedure on the elements of the lists, in order."),
+    ("filter", "The elements of list for which predicate is true."),
+    (
+        "reduce",
+        "Combines the elements of list from the left as (procedure element accumulated), or returns default if it is empty.",
+    ),
+    (
+        "fold-left",
+        "Combines initial with the elements of the lists from the left, as (procedure accumulated element).",
+    ),
+    (
+        "fold-right",
+        "Combines the elements of the lists with initial from the right, as (procedure element accumulated).",
+    ),
+    (
+        "call/cc",
+        "Calls receiver with a continuation that returns from call/cc when invoked, while call/cc has not returned.",
+    ),
+    ("call-with-current-continuation", "The same as call/cc."),
+    ("call-with-escape-continuation", "The same as call/cc."),
+    (
+        "dynamic-wind",
+        "Calls before, thunk and after in order and returns the value of thunk; after also runs when thunk is left early.",
+    ),
+    (
+        "memoize",
+        "procedure with its results cached, or only the capacity most recently used ones.",
+    ),
+    (
+        "benchmark",
+        "Calls thunk repeatedly, #:iterations times, and returns its timings in milliseconds and allocations.",
+    ),
+    ("break", "Stops in the debugger before the next list is evaluated."),
+    ("gc", "Collects cyclic garbage and returns how many objects were freed."),
+    ("gc-stats", "Statistics of the collector, as an association list."),
+    ("char->integer", "The code point of a char."),
+    ("integer->char", "The char of a code point."),
+    ("char-upcase", "The upper case form of a char."),
+    ("char-downcase", "The lower case form of a char."),
+    ("string-ref", "The char at an index of a string."),
+    ("string-length", "The number of chars in a string."),
+    (
+        "substring",
+        "The characters of a string from index start up to end, or to its end.",
+    ),
+    ("string-append", "The strings joined together."),
+    ("string-copy", "A string equal to string, sharing nothing with it."),
+    ("string->list", "The chars of a string."),
+    ("list->string", "A string of the chars of a list."),
+    ("tainted?", "Whether obj holds a string from an untrusted source."),
+    ("taint", "string marked as untrusted."),
+    ("untaint", "string marked as trusted, once it has been checked."),
+    (
+        "make-comparator",
+        "A comparator from a type test, an equality, an ordering and a hash procedure, each possibly #f.",
+    ),
+    ("comparator?", "Whether obj is a comparator."),
+    ("comparator-ordered?", "Whether a comparator can order values."),
+    ("comparator-hashable?", "Whether a comparator can hash values."),
+    ("comparator-hash", "The hash of obj by a comparator."),
+    ("=?", "Whether the values are equal by a comparator."),
+    ("<?", "Whether the values are in increasing order by a comparator."),
+    (">?", "Whether the values are in decreasing order by a comparator."),
+    ("<=?", "Whether the values are in non-decreasing order by a comparator."),
+    (">=?", "Whether the values are in non-increasing order by a comparator."),
+    (
+        "sort",
+        "The elements of list in order, by a comparator or a less? procedure; the sort is stable.",
+    ),
+    (
+        "deque-sort!",
+        "Sorts the items of a deque in place, by a comparator or a less? procedure; the sort is stable.",
+    ),
+    ("make-heap", "An empty priority queue, smallest first by order."),
+    ("heap-push!", "Adds an item to a heap."),
+    ("heap-pop!", "Removes the smallest item of a heap and returns it."),
+    ("heap-peek", "The smallest item of a heap."),
+    ("heap-size", "The number of items in a heap."),
+    ("make-deque", "An empty double-ended queue."),
+    ("deque-push-front!", "Adds an item at the front of a deque."),
+    ("deque-push-back!", "Adds an item at the back of a deque."),
+    ("deque-pop-front!", "Removes the item at the front of a deque and returns it."),
+    ("deque-pop-back!", "Removes the item at the back of a deque and returns it."),
+    ("deque-size", "The number of items in a deque."),
+    ("make-hash-table", "An empty hash table."),
+    ("hash-table?", "Whether obj is a hash table."),
+    ("hash-table-set!", "Sets the value of a key in a hash table."),
+    (
+        "hash-table-ref",
+        "The value of a key in a hash table, or default if it is missing.",
+    ),
+    (
+        "hash-table-delete!",
+        "Removes a key from a hash table, returning whether it was there.",
+    ),
+    ("hash-table-count", "The number of entries in a hash table."),
+    ("hash-table-keys", "The keys of a hash table."),
+    ("hash-table->alist", "The entries of a hash table, as an association list."),
+    (
+        "alist->hash-table",
+        "A hash table of the entries of an association list, the first of each key.",
+    ),
+    (
+        "assq",
+        "The first entry of an association list whose key is eq? to key, or #f.",
+    ),
+    (
+        "assv",
+        "The first entry of an association list whose key is eqv? to key, or #f.",
+    ),
+    (
+        "assoc",
+        "The first entry of an association list whose key is equal? to key, or equal says is, or #f.",
+    ),
+    (
+        "gensym",
+        "A new symbol no other is eq? to, named prefix, or g, and a number.",
+    ),
+    (
+        "kv-open",
+        "The key-value store kept in the file at path, created if there is none.",
+    ),
+    (
+        "kv-get",
+        "The value of a key in a store, or default, #f unless given, if it is missing.",
+    ),
+    ("kv-set!", "Sets the value of a key in a store, on disk once it returns."),
+    (
+        "kv-delete!",
+        "Removes a key from a store, returning whether it was there.",
+    ),
+    ("kv-keys", "The keys of a store."),
+    ("kv-compact!", "Rewrites the file of a store with only its current entries."),
+    ("tcp-connect", "A socket connected to a server at host and port."),
+    (
+        "tcp-listen",
+        "A socket listening for connections at host and port, any free port if port is 0.",
+    ),
+    (
+        "tcp-accept",
+        "Waits for a connection to a listening socket and returns a socket connected to it.",
+    ),
+    (
+        "socket-read-line",
+        "The next line read from a socket, without its terminator, or #f once the other end closed it.",
+    ),
+    ("socket-write", "Writes a string to a socket."),
+    ("socket-port", "The local port of a socket."),
+    ("socket-close", "Closes a socket."),
+    (
+        "render-template",
+        "A Mustache-like template filled in with the values of context.",
+    ),
+    (
+        "json-parse",
+        "The value written in a JSON text, within the limits of #:max-depth, #:max-atom-length and #:max-nodes.",
+    ),
+    ("json-stringify", "value written as a JSON text."),
+    ("make-sorted-map", "An empty map keeping its keys in order."),
+    ("sorted-map-set!", "Sets the value of a key in a sorted map."),
+    (
+        "sorted-map-ref",
+        "The value of a key in a sorted map, or default if it is missing.",
+    ),
+    (
+        "sorted-map-delete!",
+        "Removes a key from a sorted map, returning whether it was there.",
+    ),
+    ("sorted-map-size", "The number of entries in a sorted map."),
+    ("sorted-map->list", "The entries of a sorted map, in key order."),
+    ("sorted-map-keys", "The keys of a sorted map, in order."),
+    (
+        "sorted-map-floor-key",
+        "The greatest key of a sorted map at most key, or #f.",
+    ),
+    (
+        "sorted-map-ceiling-key",
+        "The least key of a sorted map at least key, or #f.",
+    ),
+    (
+        "sorted-map-submap",
+        "A new sorted map of the entries with keys from from up to to, either of which may be #f.",
+    ),
+    (
+        "make-graph",
+        "A graph of the edges, each (from to) or (from to weight).",
+    ),
+    (
+        "topological-sort",
+        "The nodes of a graph ordered so that every edge points forward.",
+    ),
+    (
+        "shortest-path",
+        "The nodes of the lightest path from from to to, or #f if there is none.",
+    ),
+    (
+        "strongly-connected-components",
+        "The groups of nodes of a graph that can all reach each other.",
+    ),
+    (
+        "make-default-comparator",
+        "The comparator ordering numbers, strings, chars, symbols and lists of them.",
+    ),
+    (
+        "datetime-now",
+        "The current date and time, in the system time zone or the one named.",
+    ),
+    (
+        "string->datetime",
+        "An ISO 8601 timestamp, validated and normalized.",
+    ),
+    (
+        "datetime->string",
+        "A datetime as an ISO 8601 timestamp, or in a strftime format.",
+    ),
+    (
+        "datetime->timezone",
+        "The same instant on the clocks of another time zone.",
+    ),
+    (
+        "datetime-add",
+        "A datetime moved by an amount of a unit, from year down to second.",
+    ),
+    ("datetime-difference", "The number of seconds from a to b."),
+    ("current-time", "The seconds since the Unix epoch, as a float."),
+    ("time-millis", "The milliseconds since the Unix epoch."),
+    ("sleep", "Waits for a number of milliseconds."),
+    (
+        "define-messages",
+        "Defines the messages of a locale, as keys each followed by its text.",
+    ),
+    (
+        "set-locale!",
+        "Changes the locale messages are looked up in, and optionally the fallback one.",
+    ),
+    ("current-locale", "The locale messages are looked up in."),
+    (
+        "msg",
+        "The message for key in the current locale, with each ~a replaced by the next value.",
+    ),
+    ("uuid4", "A random UUID."),
+    ("uuid7", "A UUID starting with the current time, sorting after earlier ones."),
+    ("ulid", "A ULID: a millisecond timestamp and 80 random bits."),
+    ("spawn", "Runs thunk as a task, and returns the task."),
+    ("join", "Waits for a task and returns its value."),
+    ("spawn-fiber", "Queues a fiber running thunk, and returns it."),
+    ("yield-fiber", "Lets the other fibers run."),
+    ("join-fiber", "Waits for a fiber to finish and returns its value."),
+    ("run-fibers", "Runs fibers until each has finished or is blocked."),
+    ("fiber-done?", "Whether a fiber has finished."),
+    ("make-channel", "A channel for fibers to send values on."),
+    ("channel-send!", "Sends a value on a channel."),
+    (
+        "channel-receive",
+        "Takes the oldest value sent on a channel, waiting for one if there is none.",
+    ),
+    (
+        "make-rate-limiter",
+        "A limiter letting through per-second calls a second, #:burst at once.",
+    ),
+    ("acquire", "Waits until a limiter lets a call through."),
+    ("try-acquire", "Whether a limiter lets a call through now."),
+    (
+        "schedule",
+        "Calls thunk at the times of a cron schedule while the scheduler runs, and returns the first.",
+    ),
+    ("run-scheduler", "Calls the scheduled jobs at their times until stopped."),
+    ("stop-scheduler", "Stops the scheduler once the current job returns."),
+    ("pp", "Prints value laid out over lines of at most width columns."),
+    (
+        "set-ordered-printing!",
+        "Whether hash tables print their entries in key order.",
+    ),
+    (
+        "set-float-format!",
+        "How floats print: shortest, or fixed or scientific with a number of decimals.",
+    ),
+    ("float-format", "How floats print, as the arguments to set-float-format!."),
+    (
+        "reload-module",
+        "Evaluates a required module again and rebinds its exports wherever it was required.",
+    ),
+    (
+        "read",
+        "The next datum from standard input, or void at its end, within the limits of #:max-depth, #:max-atom-length and #:max-nodes.",
+    ),
+    (
+        "read-string",
+        "The first datum written in a string, unevaluated, within the limits of #:max-depth, #:max-atom-length and #:max-nodes.",
+    ),
+    (
+        "eval",
+        "Evaluates a datum as code in an environment, or in the global environment.",
+    ),
+    (
+        "interaction-environment",
+        "The global environment, for eval.",
+    ),
+    (
+        "command-line",
+        "The script and its arguments, as a list of strings.",
+    ),
+    (
+        "getenv",
+        "The value of an environment variable, or #f if it is not set.",
+    ),
+    (
+        "setenv",
+        "Sets an environment variable, or removes it if value is #f.",
+    ),
+    (
+        "system",
+        "Runs a shell command and returns its exit status.",
+    ),
+    (
+        "process-run",
+        "Runs a program and returns a hash table of its status, stdout and stderr.",
+    ),
+    ("exit", "Ends the program with an exit code, 0 unless given."),
+    (
+        "procedure-signature",
+        "The parameters of a procedure and their types, as a hash table.",
+    ),
+    (
+        "help",
+        "Prints the reference of a procedure, or of the special form or procedure a symbol names.",
+    ),
+    ("terminal-size", "The columns and rows of the terminal."),
+    ("clear-screen!", "Clears the terminal."),
+    ("cursor-move!", "Moves the cursor to a column and row, counted from zero."),
+    ("cursor-hide!", "Hides the cursor."),
+    ("cursor-show!", "Shows the cursor."),
+    ("set-color!", "Sets the colors of the text written next."),
+    ("reset-color!", "Goes back to the default colors."),
+    (
+        "raw-mode!",
+        "Whether the terminal passes keys on as they are pressed, without echoing them.",
+    ),
+    ("read-key", "Waits for a key, returned as a char or a symbol such as up."),
+    (
+        "on-signal",
+        "Calls handler with the name of a signal each time it is received.",
+    ),
+    (
+        "watch-path",
+        "Calls handler with the path of each file changing under path, until it calls stop-watching.",
+    ),
+    ("stop-watching", "Stops the watch-path running."),
+    ("regex-match?", "Whether pattern matches somewhere in text."),
+    (
+        "regex-find-all",
+        "The matches of pattern in text, each with what its capture groups matched if it has any.",
+    ),
+    (
+        "regex-replace",
+        "text with every match of pattern replaced by a string using $1 for groups, or by what a procedure returns for it.",
+    ),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    SpecialForm,
+    Builtin,
+    /// A procedure of the prelude.
+    Procedure,
+}
+
+impl Kind {
+    fn name(self) -> &'static str {
+        match self {
+            Kind::SpecialForm => "special form",
+            Kind::Builtin => "builtin",
+            Kind::Procedure => "procedure",
+        }
+    }
+}
+
+/// The reference of one special form or procedure.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub name: String,
+    pub kind: Kind,
+    /// How it is written, as `(substring string start [end])`.
+    pub usage: String,
+    pub doc: String,
+    /// The parameters of procedures.
+    pub signature: Option<Signature>,
+}
+
+/// The docstring of a lambda: the string its body starts with, if other
+/// forms follow it.
+pub fn docstring(procedure: &Object) -> Option<&str> {
+    match procedure {
+        Object::Lambda(lambda) => match lambda.body.as_slice() {
+            [Object::String(doc), _, ..] => Some(doc.as_str()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn builtin_doc(name: &str) -> Option<&'static str> {
+    DOCS.iter()
+        .find(|(builtin, _)| *builtin == name)
+        .map(|(_, doc)| *doc)
+}
+
+fn form(usage: &str, doc: &str) -> Entry {
+    let name = usage[1..].split([' ', ')']).next().unwrap_or_default();
+    Entry {
+        name: name.to_string(),
+        kind: Kind::SpecialForm,
+        usage: usage.to_string(),
+        doc: doc.to_string(),
+        signature: None,
+    }
+}
+
+fn builtin_entry(name: &str) -> Entry {
+    let signature = signature::builtin(name);
+    Entry {
+        name: name.to_string(),
+        kind: Kind::Builtin,
+        usage: signature
+            .as_ref()
+            .map_or_else(|| format!("({})", name), Signature::usage),
+        doc: builtin_doc(name).unwrap_or_default().to_string(),
+        signature,
+    }
+}
+
+/// The entry for a procedure bound to `name`: that of the reference for a
+/// builtin, and otherwise one of its parameters and docstring. `None` if
+/// `procedure` is not a procedure.
+pub fn procedure_entry(name: &str, procedure: &Object) -> Option<Entry> {
+    if let Object::Builtin(builtin) = procedure {
+        return Some(builtin_entry(builtin.name));
+    }
+    let signature = Signature {
+        name: Some(name.to_string()),
+        ..signature::of(procedure)?
+    };
+
+    Some(Entry {
+        name: name.to_string(),
+        kind: Kind::Procedure,
+        usage: signature.usage(),
+        doc: docstring(procedure).unwrap_or_default().to_string(),
+        signature: Some(signature),
+    })
+}
+
+/// `(help x)` prints the entry for a procedure, or for the special form or
+/// procedure of the reference a symbol names.
+pub fn help(args: &[Object]) -> Result<Object, EvalError> {
+    let entry = match args {
+        [Object::Symbol(name)] => explain(name.as_str()).ok_or_else(|| {
+            EvalError::new(format!("no special form or procedure is called {}", name))
+        })?,
+        [procedure] => {
+            let name = match procedure {
+                Object::Lambda(lambda) => hooks::defined_name(lambda),
+                _ => None,
+            };
+            procedure_entry(name.as_deref().unwrap_or("<lambda>"), procedure).ok_or_else(|| {
+                EvalError::new(format!(
+                    "help expects a procedure or a symbol, got {}",
+                    procedure
+                ))
+            })?
+        }
+        _ => {
+            return Err(EvalError::new(format!(
+                "help expects 1 argument, got {}",
+                args.len()
+            )))
+        }
+    };
+    printer::print_line(entry.describe().trim_end());
+
+    Ok(Object::Void)
+}
+
+/// Every entry of the reference, in order of name.
+pub fn entries() -> Vec<Entry> {
+    let mut entries = FORMS
+        .iter()
+        .map(|(usage, doc)| form(usage, doc))
+        .collect::<Vec<_>>();
+
+    entries.extend(builtins().map(|&(name, _)| builtin_entry(name)));
+
+    let env = global_env();
+    let mut procedures = env
+        .borrow()
+        .bindings()
+        .into_iter()
+        .filter(|(_, value)| matches!(value, Object::Lambda(_)))
+        .collect::<Vec<_>>();
+    procedures.sort_by(|(a, _), (b, _)| a.as_str().cmp(b.as_str()));
+    entries.extend(
+        procedures
+            .into_iter()
+            .filter_map(|(name, procedure)| procedure_entry(name.as_str(), &procedure)),
+    );
+
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    entries
+}
+
+/// The entry for `name`, if it is a special form or a procedure of the
+/// reference.
+pub fn explain(name: &str) -> Option<Entry> {
+    entries().into_iter().find(|entry| entry.name == name)
+}
+
+/// The parameters of `signature` and what each takes.
+fn parameters(signature: &Signature) -> Vec<(&Parameter, &'static str)> {
+    let required = signature.required.iter().map(|p| (p, ""));
+    let optional = signature.optional.iter().map(|p| (p, ", optional"));
+    let rest = signature.rest.iter().map(|p| (p, ", any number"));
+
+    required.chain(optional).chain(rest).collect()
+}
+
+impl Entry {
+    /// The entry as text for a terminal.
+    pub fn describe(&self) -> String {
+        let mut out = format!("{}\n{}\n", self.usage, self.kind.name());
+        // Procedures defined without a docstring have nothing more to say.
+        if !self.doc.is_empty() {
+            let _ = write!(out, "\n{}\n", self.doc);
+        }
+        let parameters = self.signature.as_ref().map(parameters).unwrap_or_default();
+        if !parameters.is_empty() {
+            let width = parameters
+                .iter()
+                .map(|(p, _)| p.name.len())
+                .max()
+                .unwrap_or(0);
+            out.push('\n');
+            for (parameter, note) in parameters {
+                let _ = writeln!(
+                    out,
+                    "    {:width$}  {}{}",
+                    parameter.name,
+                    parameter.kind,
+                    note,
+                    width = width
+                );
+            }
+        }
+
+        out
+    }
+
+    /// The entry as a section of a Markdown document.
+    pub fn markdown(&self) -> String {
+        let mut out = format!(
+            "## `{}`\n\n{}: `{}`\n\n{}\n",
+            self.name,
+            self.kind.name(),
+            self.usage,
+            self.doc
+        );
+        let parameters = self.signature.as_ref().map(parameters).unwrap_or_default();
+        if !parameters.is_empty() {
+            out.push('\n');
+            for (parameter, note) in parameters {
+                let _ = writeln!(out, "- `{}`: {}{}", parameter.name, parameter.kind, note);
+            }
+        }
+
+        out
+    }
+}
+
+/// The whole reference as a Markdown document.
+pub fn markdown() -> String {
+    let entries = entries();
+    let mut out = String::from("# Reference\n\n");
+    for kind in [Kind::SpecialForm, Kind::Builtin, Kind::Procedure] {
+        let names = entries
+            .iter()
+            .filter(|entry| entry.kind == kind)
+            .map(|entry| format!("`{}`", entry.name))
+            .collect::<Vec<_>>();
+        let _ = writeln!(
+            out,
+            "{}s: {}.\n",
+            capitalized(kind.name()),
+            names.join(", ")
+        );
+    }
+    let sections = entries.iter().map(Entry::markdown).collect::<Vec<_>>();
+    out.push_str(&sections.join("\n"));
+
+    out
+}
+
+fn capitalized(s: &str) -> String {
+    let mut chars = s.chars();
+    chars
+        .next()
+        .map(|first| first.to_uppercase().chain(chars).collect())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::KEYWORDS;
+
+    #[test]
+    fn test_everything_is_documented() {
+        let entries = entries();
+        for entry in &entries {
+            assert!(!entry.doc.is_empty(), "{} is not documented", entry.name);
+        }
+        for keyword in KEYWORDS {
+            assert!(
+                entries
+                    .iter()
+                    .any(|entry| entry.name == *keyword && entry.kind == Kind::SpecialForm),
+                "the special form {} is not in the reference",
+                keyword
+            );
+        }
+        assert_eq!(
+            entries
+                .iter()
+                .filter(|entry| entry.kind == Kind::SpecialForm)
+                .count(),
+            KEYWORDS.len()
+        );
+        assert!(entries.iter().any(|entry| entry.name == "assoc"));
+        for (name, _) in DOCS {
+            assert!(
+                signature::builtin(name).is_some(),
+                "{} is not a builtin",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn test_explain() {
+        assert_eq!(
+            explain("substring").unwrap().describe(),
+            "(substring string start [end])
+builtin
+
+The characters of a string from index start up to end, or to its end.
+
+    string  string
+    start   integer
+    end     integer, optional
+"
+        );
+        assert_eq!(
+            explain("let-values").unwrap().describe(),
+            "(let-values ((formals expr)...) body...)
+special form
+
+Evaluates body with the values of each expr bound to its formals, a parameter list like that of a lambda.
+"
+        );
+        assert!(explain("member")
+            .unwrap()
+            .describe()
+            .starts_with("(member x list)\nprocedure\n"));
+        assert!(explain("no-such-thing").is_none());
+    }
+
+    #[test]
+    fn test_help() {
+        let env = global_env();
+        let help = |program: &str| {
+            printer::capture_output(true);
+            let result = crate::eval::eval_str(program, &env);
+            let output = printer::take_captured();
+            printer::capture_output(false);
+            result.map(|_| output).map_err(|e| e.message().to_string())
+        };
+
+        assert_eq!(
+            help("(define (double x) \"Doubles x.\" (* 2 x)) (help double)").unwrap(),
+            "(double x)\nprocedure\n\nDoubles x.\n\n    x  any\n"
+        );
+        assert!(help("(help car)")
+            .unwrap()
+            .starts_with("(car pair)\nbuiltin\n"));
+        assert!(help("(help 'let)").unwrap().starts_with("(let "));
+        assert_eq!(
+            help("(help (lambda () 1))").unwrap(),
+            "(<lambda>)\nprocedure\n"
+        );
+        assert_eq!(
+            help("(help 'nothing)").unwrap_err(),
+            "no special form or procedure is called nothing"
+        );
+        assert_eq!(
+            help("(help 1)").unwrap_err(),
+            "help expects a procedure or a symbol, got 1"
+        );
+    }
+
+    #[test]
+    fn test_markdown() {
+        let reference = markdown();
+        assert!(reference.starts_with("# Reference\n\nSpecial forms: `assert`, "));
+        assert!(reference.contains(
+            "\n## `append`\n\nprocedure: `(append lists...)`\n\n\
+             The lists joined together.\n\n- `lists`: any, any number\n"
+        ));
+    }
+}
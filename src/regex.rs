@@ -0,0 +1,212 @@
+//! Regular expressions, enabled with the `regex` feature.
+//!
+//! Patterns have the syntax of the `regex` crate. `(regex-match? pattern
+//! text)` is whether the pattern matches somewhere in `text`.
+//! `(regex-find-all pattern text)` lists its matches, left to right: each
+//! the string matched if the pattern has no capture groups, and otherwise
+//! the list of that string and those its groups matched, `#f` for a group
+//! that took no part in the match:
+//!
+//! ```text
+//! (regex-find-all "(\w+)@(\w+)" "ana@home, bo@work")
+//! ; => (("ana@home" "ana" "home") ("bo@work" "bo" "work"))
+//! ```
+//!
+//! `(regex-replace pattern text replacement)` replaces every match, with a
+//! string in which `$1` or `${name}` stands for what a group matched, or
+//! with what a procedure returns when called with the match as
+//! `regex-find-all` lists it.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use ::regex::{Captures, Regex};
+
+use crate::eval::{apply, EvalError};
+use crate::object::{BuiltinFn, Object};
+use crate::taint;
+
+pub const BUILTINS: &[(&str, BuiltinFn)] = &[
+    ("regex-match?", regex_match),
+    ("regex-find-all", regex_find_all),
+    ("regex-replace", regex_replace),
+];
+
+/// How many compiled patterns are kept, so that a loop matching with the
+/// same pattern compiles it once.
+const CACHE_SIZE: usize = 64;
+
+thread_local! {
+    static CACHE: RefCell<HashMap<String, Regex>> = RefCell::new(HashMap::new());
+}
+
+fn compile(name: &str, pattern: &str) -> Result<Regex, EvalError> {
+    if let Some(regex) = CACHE.with(|cache| cache.borrow().get(pattern).cloned()) {
+        return Ok(regex);
+    }
+    let regex = Regex::new(pattern)
+        .map_err(|e| EvalError::new(format!("{}: invalid pattern: {}", name, e)))?;
+
+    CACHE.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        if cache.len() >= CACHE_SIZE {
+            cache.clear();
+        }
+        cache.insert(pattern.to_string(), regex.clone());
+    });
+    Ok(regex)
+}
+
+/// The compiled pattern and the text of `(name pattern text ...)`.
+fn pattern_and_text<'a>(
+    name: &str,
+    args: &'a [Object],
+    len: usize,
+) -> Result<(Regex, &'a str), EvalError> {
+    match args {
+        [Object::String(pattern), Object::String(text), ..] if args.len() == len => {
+            Ok((compile(name, pattern.as_str())?, text.as_str()))
+        }
+        _ if args.len() != len => Err(EvalError::new(format!(
+            "{} expects {} arguments, got {}",
+            name,
+            len,
+            args.len()
+        ))),
+        _ => Err(EvalError::new(format!(
+            "{} expects a pattern and a text, both strings",
+            name
+        ))),
+    }
+}
+
+/// A match as `regex-find-all` lists it.
+fn match_object(captures: &Captures) -> Object {
+    if captures.len() == 1 {
+        return Object::string(&captures[0]);
+    }
+
+    Object::list(
+        captures
+            .iter()
+            .map(|group| match group {
+                Some(group) => Object::string(group.as_str()),
+                None => Object::Bool(false),
+            })
+            .collect::<Vec<_>>(),
+    )
+}
+
+fn regex_match(args: &[Object]) -> Result<Object, EvalError> {
+    let (regex, text) = pattern_and_text("regex-match?", args, 2)?;
+
+    Ok(Object::Bool(regex.is_match(text)))
+}
+
+fn regex_find_all(args: &[Object]) -> Result<Object, EvalError> {
+    let (regex, text) = pattern_and_text("regex-find-all", args, 2)?;
+
+    let matches = Object::list(
+        regex
+            .captures_iter(text)
+            .map(|c| match_object(&c))
+            .collect::<Vec<_>>(),
+    );
+    Ok(match taint::contains(&args[1]) {
+        true => taint::taint_all(matches),
+        false => matches,
+    })
+}
+
+fn regex_replace(args: &[Object]) -> Result<Object, EvalError> {
+    let (regex, text) = pattern_and_text("regex-replace", args, 3)?;
+    let text_tainted = taint::contains(&args[1]);
+    let mut tainted = text_tainted;
+
+    match &args[2] {
+        Object::String(replacement) => Ok(taint::string(
+            regex.replace_all(text, replacement.as_str()).as_ref(),
+            tainted || replacement.is_tainted(),
+        )),
+        procedure => {
+            let mut replaced = String::with_capacity(text.len());
+            let mut end = 0;
+            for captures in regex.captures_iter(text) {
+                let whole = captures.get(0).expect("a match has a whole");
+                replaced.push_str(&text[end..whole.start()]);
+                let group = match_object(&captures);
+                let group = match text_tainted {
+                    true => taint::taint_all(group),
+                    false => group,
+                };
+                match apply(procedure, vec![group])? {
+                    Object::String(s) => {
+                        replaced.push_str(s.as_str());
+                        tainted |= s.is_tainted();
+                    }
+                    other => {
+                        return Err(EvalError::new(format!(
+                            "regex-replace: the replacement procedure returned {}, not a string",
+                            other
+                        )))
+                    }
+                }
+                end = whole.end();
+            }
+            replaced.push_str(&text[end..]);
+
+            Ok(taint::string(replaced, tainted))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builtins::global_env;
+    use crate::eval::eval_str;
+
+    fn eval(program: &str) -> String {
+        match eval_str(program, &global_env()) {
+            Ok(value) => value.to_string(),
+            Err(e) => e.message().to_string(),
+        }
+    }
+
+    #[test]
+    fn test_match_and_find() {
+        assert_eq!(
+            eval(
+                r#"(list (regex-match? "^a.c$" "abc") (regex-match? "b" "abc") (regex-match? "^b" "abc"))"#
+            ),
+            "(#t #t #f)"
+        );
+        assert_eq!(
+            eval(r#"(regex-find-all "[0-9]+" "12 apples, 3 pears")"#),
+            r#"("12" "3")"#
+        );
+        assert_eq!(
+            eval(r#"(regex-find-all "(\w+)@(\w+)|(-)" "ana@home - bo@work")"#),
+            r#"(("ana@home" "ana" "home" #f) ("-" #f #f "-") ("bo@work" "bo" "work" #f))"#
+        );
+        assert!(eval(r#"(regex-match? "(" "x")"#).starts_with("regex-match?: invalid pattern"));
+    }
+
+    #[test]
+    fn test_replace() {
+        assert_eq!(
+            eval(r#"(regex-replace "(\w+)@(\w+)" "ana@home, bo@work" "$2:$1")"#),
+            r#""home:ana, work:bo""#
+        );
+        assert_eq!(
+            eval(
+                r#"(regex-replace "([a-z])([a-z]*)" "ana and bo"
+                     (lambda (m) (string-append (caddr m) (cadr m) "ay")))"#
+            ),
+            r#""naaay ndaay obay""#
+        );
+        assert_eq!(
+            eval(r#"(regex-replace "a" "banana" (lambda (m) 1))"#),
+            "regex-replace: the replacement procedure returned 1, not a string"
+        );
+    }
+}
@@ -0,0 +1,601 @@
+//! The read-eval-print loop, independent of where its input comes from.
+
+use std::cell::RefCell;
+use std::fs;
+use std::io;
+use std::io::{BufRead, Write};
+use std::rc::Rc;
+
+use crate::bench::now;
+use crate::builtins::global_env;
+use crate::debugger;
+use crate::env::Env;
+use crate::eval::{eval, eval_str, EvalError};
+use crate::lexer::{Token, Tokenizer, KEYWORDS};
+use crate::module;
+use crate::object::Object;
+use crate::parser::{configured, parse_tokens, ParseError};
+use crate::printer::{pretty_print, DEFAULT_WIDTH};
+use crate::reader::{Budget, Limits};
+use crate::reference;
+use crate::session;
+use crate::signature;
+
+const PROMPT: &str = "lisp-rs> ";
+
+const COMMANDS: &str = ":env, :load FILE, :save FILE, :restore FILE, :type EXPR, :time EXPR, :debug EXPR, :doc NAME and :quit";
+
+/// The prompt for a line continuing an entry, showing how many lists are
+/// still open.
+fn continuation_prompt(depth: usize) -> String {
+    format!("...{}> ", depth)
+}
+
+/// A source of REPL input lines, such as a terminal line editor or a text
+/// box in a graphical frontend.
+pub trait LineEditor {
+    /// Shows `prompt` and reads one line, without its line terminator.
+    /// Returns `None` at the end of input, and an error of kind
+    /// `Interrupted` to cancel the entry being typed.
+    fn read_line(&mut self, prompt: &str) -> io::Result<Option<String>>;
+
+    /// Called with every complete entry, for editors that keep a history.
+    fn add_history(&mut self, _entry: &str) {}
+
+    /// Called once with the environment the REPL evaluates in, for editors
+    /// that complete names.
+    fn complete_from(&mut self, _env: &Rc<RefCell<Env>>) {}
+}
+
+/// The special forms and names bound in `env` that start with `prefix`,
+/// sorted.
+pub fn completions(env: &Env, prefix: &str) -> Vec<String> {
+    let mut names = env
+        .names()
+        .iter()
+        .map(|name| name.as_str())
+        .chain(KEYWORDS.iter().copied())
+        .filter(|name| name.starts_with(prefix))
+        .map(String::from)
+        .collect::<Vec<_>>();
+    names.sort();
+    names.dedup();
+
+    names
+}
+
+/// The parameters left to type when `line` ends with a procedure call's
+/// opening parenthesis, name and a space, as `(substring ` gives
+/// `string start [end])`, from the procedure's signature.
+pub fn argument_hint(env: &Env, line: &str) -> Option<String> {
+    let name = line[line.rfind('(')? + 1..].strip_suffix(' ')?;
+    if name.is_empty() || name.contains(|c: char| c.is_whitespace() || c == '"' || c == '\'') {
+        return None;
+    }
+    let usage = signature::of(&env.get(name)?)?.usage();
+
+    Some(match usage.split_once(' ') {
+        Some((_, parameters)) => parameters.to_string(),
+        None => String::from(")"),
+    })
+}
+
+/// A minimal editor over any reader and writer, with no line editing or
+/// history.
+pub struct BasicEditor<R, W> {
+    input: R,
+    output: W,
+}
+
+impl<R: BufRead, W: Write> BasicEditor<R, W> {
+    pub fn new(input: R, output: W) -> Self {
+        Self { input, output }
+    }
+}
+
+impl BasicEditor<io::StdinLock<'static>, io::Stdout> {
+    pub fn stdio() -> Self {
+        Self::new(io::stdin().lock(), io::stdout())
+    }
+}
+
+impl<R: BufRead, W: Write> LineEditor for BasicEditor<R, W> {
+    fn read_line(&mut self, prompt: &str) -> io::Result<Option<String>> {
+        write!(self.output, "{}", prompt)?;
+        self.output.flush()?;
+
+        let mut line = String::new();
+        if self.input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+
+        let len = line.trim_end_matches(['\n', '\r']).len();
+        line.truncate(len);
+
+        Ok(Some(line))
+    }
+}
+
+/// Completes the name before the cursor with `completions`.
+#[cfg(feature = "rustyline")]
+#[derive(Default)]
+pub struct Completer {
+    env: Option<Rc<RefCell<Env>>>,
+}
+
+#[cfg(feature = "rustyline")]
+impl rustyline::completion::Completer for Completer {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &rustyline::Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let start = line[..pos]
+            .rfind(|c: char| c.is_whitespace() || matches!(c, '(' | ')' | '\'' | '"'))
+            .map_or(0, |i| i + 1);
+        let names = match &self.env {
+            Some(env) => completions(&env.borrow(), &line[start..pos]),
+            None => Vec::new(),
+        };
+
+        Ok((start, names))
+    }
+}
+
+#[cfg(feature = "rustyline")]
+impl rustyline::hint::Hinter for Completer {
+    type Hint = String;
+
+    fn hint(&self, line: &str, pos: usize, _ctx: &rustyline::Context<'_>) -> Option<String> {
+        match &self.env {
+            Some(env) if pos == line.len() => argument_hint(&env.borrow(), line),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "rustyline")]
+impl rustyline::highlight::Highlighter for Completer {}
+
+#[cfg(feature = "rustyline")]
+impl rustyline::validate::Validator for Completer {}
+
+#[cfg(feature = "rustyline")]
+impl rustyline::Helper for Completer {}
+
+/// A terminal editor with line editing, history and tab completion, backed
+/// by rustyline.
+#[cfg(feature = "rustyline")]
+pub struct RustylineEditor {
+    editor: rustyline::Editor<Completer, rustyline::history::DefaultHistory>,
+}
+
+#[cfg(feature = "rustyline")]
+impl RustylineEditor {
+    pub fn new() -> io::Result<Self> {
+        let mut editor = rustyline::Editor::new().map_err(io::Error::other)?;
+        editor.set_helper(Some(Completer::default()));
+
+        Ok(Self { editor })
+    }
+}
+
+#[cfg(feature = "rustyline")]
+impl LineEditor for RustylineEditor {
+    fn read_line(&mut self, prompt: &str) -> io::Result<Option<String>> {
+        use rustyline::error::ReadlineError;
+
+        match self.editor.readline(prompt) {
+            Ok(line) => Ok(Some(line)),
+            // Ctrl-C abandons the entry being typed, like in a shell.
+            Err(ReadlineError::Interrupted) => Err(io::ErrorKind::Interrupted.into()),
+            Err(ReadlineError::Eof) => Ok(None),
+            Err(e) => Err(io::Error::other(e)),
+        }
+    }
+
+    fn add_history(&mut self, entry: &str) {
+        let _ = self.editor.add_history_entry(entry);
+    }
+
+    fn complete_from(&mut self, env: &Rc<RefCell<Env>>) {
+        if let Some(completer) = self.editor.helper_mut() {
+            completer.env = Some(env.clone());
+        }
+    }
+}
+
+/// What a REPL command asks the REPL to do.
+#[derive(Debug, PartialEq)]
+enum Command {
+    Print(String),
+    Quit,
+}
+
+/// Runs a command typed at the prompt instead of a form: `:env` lists the
+/// bindings made in the session, `:load FILE` loads a file into it, `:save
+/// FILE` writes its definitions to a file and `:restore FILE` makes the
+/// definitions saved in one again, `:type`
+/// and `:time` evaluate an expression and show its type or how long it
+/// took, `:debug` steps through the evaluation of one in the debugger,
+/// `:doc NAME` shows the documentation of a procedure or special form, and
+/// `:quit` ends the session.
+fn run_command(line: &str, env: &Rc<RefCell<Env>>) -> Result<Command, EvalError> {
+    let (command, argument) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let argument = argument.trim();
+
+    match (command, argument.is_empty()) {
+        (":quit", true) => Ok(Command::Quit),
+        (":env", true) => {
+            let fresh = global_env();
+            let bindings = env.borrow().bindings();
+            let bindings = bindings
+                .into_iter()
+                .filter(|(name, value)| !session::is_predefined(*name, value, env, &fresh))
+                .map(|(name, value)| format!("{} = {}", name, pretty_print(&value, DEFAULT_WIDTH)))
+                .collect::<Vec<_>>();
+            Ok(Command::Print(bindings.join("\n")))
+        }
+        (":load", false) => {
+            module::load(&Object::string(argument.trim_matches('"')), env)?;
+            Ok(Command::Print(format!("loaded {}", argument)))
+        }
+        (":save", false) => {
+            let snapshot = session::snapshot(env);
+            fs::write(argument, snapshot.source())
+                .map_err(|e| EvalError::new(format!("cannot write {}: {}", argument, e)))?;
+            let mut report = format!("saved {} definitions to {}", snapshot.len(), argument);
+            for (name, reason) in snapshot.skipped() {
+                report.push_str(&format!("\nnot saved: {}, {}", name, reason));
+            }
+            Ok(Command::Print(report))
+        }
+        (":restore", false) => {
+            let source = fs::read_to_string(argument)
+                .map_err(|e| EvalError::new(format!("cannot read {}: {}", argument, e)))?;
+            session::restore(&source, env)?;
+            Ok(Command::Print(format!("restored {}", argument)))
+        }
+        (":type", false) => {
+            let value = eval_str(argument, env)?;
+            Ok(Command::Print(value.type_name().to_string()))
+        }
+        (":time", false) => {
+            let start = now();
+            let value = eval_str(argument, env)?;
+            let elapsed = format!("; {:.3} ms", now() - start);
+            Ok(Command::Print(match value {
+                Object::Void => elapsed,
+                value => format!("{}\n{}", pretty_print(&value, DEFAULT_WIDTH), elapsed),
+            }))
+        }
+        (":debug", false) => {
+            let value = debugger::debug(argument, env, io::stdin().lock(), io::stdout())?;
+            Ok(Command::Print(pretty_print(&value, DEFAULT_WIDTH)))
+        }
+        (":doc", false) => {
+            let value = env.borrow().get(argument);
+            let entry = match value {
+                Some(value) => reference::procedure_entry(argument, &value),
+                None => reference::explain(argument),
+            };
+            match entry {
+                Some(entry) => Ok(Command::Print(entry.describe().trim_end().to_string())),
+                None => Err(EvalError::new(format!(
+                    "no special form or procedure is called {}",
+                    argument
+                ))),
+            }
+        }
+        _ => Err(EvalError::new(format!(
+            "unknown command {}; the commands are {}",
+            line, COMMANDS
+        ))),
+    }
+}
+
+/// Collects input a line at a time until it holds complete forms, so that a
+/// form can span lines, or network reads.
+pub struct FormReader {
+    tokenizer: Tokenizer,
+    pending: Vec<Token>,
+    depth: i32,
+    budget: Budget,
+}
+
+impl FormReader {
+    pub fn new() -> Self {
+        Self::with_limits(Limits::default())
+    }
+
+    /// A reader failing on any form over `limits`, before parsing it. It
+    /// reads the reader macros of the reader in use when it is made.
+    pub fn with_limits(limits: Limits) -> Self {
+        Self {
+            tokenizer: configured(Tokenizer::incremental()),
+            pending: Vec::new(),
+            depth: 0,
+            budget: Budget::new(limits),
+        }
+    }
+
+    /// Changes the limits the next forms are read within.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.budget.set_limits(limits);
+    }
+
+    /// Whether no partial form is waiting for more input.
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// How many lists the partial form has left open.
+    pub fn depth(&self) -> usize {
+        self.depth.max(0) as usize
+    }
+
+    /// Adds a line, without its terminator. Returns the forms once every
+    /// open parenthesis has been closed, or `None` while more input is
+    /// needed. An error discards the partial input.
+    pub fn push_line(&mut self, line: &str) -> Result<Option<Vec<Object>>, ParseError> {
+        self.tokenizer.push_str(line);
+        self.tokenizer.push_str("\n");
+        loop {
+            match self.tokenizer.next_token() {
+                Ok(Some(token)) => {
+                    let span = self.tokenizer.last_span();
+                    if let Err(e) = self.budget.token(&token, span.end - span.start) {
+                        *self = Self::with_limits(self.budget.limits());
+                        return Err(ParseError::over_limit(e));
+                    }
+                    match token {
+                        Token::LeftParenthesis => self.depth += 1,
+                        Token::RightParenthesis => self.depth -= 1,
+                        _ => {}
+                    }
+                    self.pending.push(token);
+                }
+                Err(e) if e.is_need_more_input() => break,
+                Ok(None) => break,
+                Err(e) => {
+                    *self = Self::with_limits(self.budget.limits());
+                    return Err(ParseError::new(e.to_string()));
+                }
+            }
+        }
+
+        if self.depth > 0 || self.pending.is_empty() {
+            return Ok(None);
+        }
+
+        self.depth = 0;
+        parse_tokens(std::mem::take(&mut self.pending)).map(Some)
+    }
+}
+
+impl Default for FormReader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Reads forms from `editor` until the end of input, printing each result.
+/// Returns the exit code requested with `(exit n)`, if any.
+pub fn run(editor: &mut dyn LineEditor, env: &Rc<RefCell<Env>>) -> Option<i32> {
+    let mut reader = FormReader::new();
+    let mut entry = String::new();
+    editor.complete_from(env);
+
+    loop {
+        let prompt = if reader.is_empty() {
+            PROMPT.to_string()
+        } else {
+            continuation_prompt(reader.depth())
+        };
+        let line = match editor.read_line(&prompt) {
+            Ok(Some(line)) => line,
+            Ok(None) => return None,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {
+                reader = FormReader::new();
+                entry.clear();
+                continue;
+            }
+            Err(e) => {
+                eprintln!("cannot read input: {}", e);
+                return Some(1);
+            }
+        };
+
+        if reader.is_empty() && line.trim_start().starts_with(':') {
+            editor.add_history(line.trim());
+            match run_command(line.trim(), env) {
+                Ok(Command::Print(output)) if output.is_empty() => {}
+                Ok(Command::Print(output)) => println!("{}", output),
+                Ok(Command::Quit) => return None,
+                Err(e) => match e.exit_code() {
+                    Some(code) => return Some(code),
+                    None => eprintln!("{}", e),
+                },
+            }
+            continue;
+        }
+
+        entry.push_str(&line);
+        entry.push('\n');
+        let forms = match reader.push_line(&line) {
+            Ok(Some(forms)) => forms,
+            Ok(None) => continue,
+            Err(e) => {
+                eprintln!("{}", e);
+                entry.clear();
+                continue;
+            }
+        };
+
+        editor.add_history(entry.trim_end());
+        entry.clear();
+
+        for form in forms {
+            match eval(&form, env) {
+                Ok(Object::Void) => {}
+                Ok(result) => println!("{}", pretty_print(&result, DEFAULT_WIDTH)),
+                Err(e) => match e.exit_code() {
+                    Some(code) => return Some(code),
+                    None => eprintln!("{}", e),
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feeds canned lines and records what the REPL asked for. `^C`
+    /// stands for Ctrl-C.
+    struct Script {
+        lines: Vec<&'static str>,
+        prompts: Vec<String>,
+        history: Vec<String>,
+    }
+
+    impl LineEditor for Script {
+        fn read_line(&mut self, prompt: &str) -> io::Result<Option<String>> {
+            self.prompts.push(prompt.to_string());
+            match self.lines.first() {
+                Some(&"^C") => {
+                    self.lines.remove(0);
+                    Err(io::ErrorKind::Interrupted.into())
+                }
+                Some(_) => Ok(Some(self.lines.remove(0).to_string())),
+                None => Ok(None),
+            }
+        }
+
+        fn add_history(&mut self, entry: &str) {
+            self.history.push(entry.to_string());
+        }
+    }
+
+    #[test]
+    fn test_run_with_custom_editor() {
+        let env = global_env();
+        let mut editor = Script {
+            lines: vec![
+                "(define (sq x)",
+                "  (* x x))",
+                "(define (f)",
+                "  (let ((a",
+                "^C",
+                "(define y (sq 4))",
+            ],
+            prompts: Vec::new(),
+            history: Vec::new(),
+        };
+
+        assert_eq!(run(&mut editor, &env), None);
+        assert_eq!(env.borrow().get("y"), Some(Object::Integer(16)));
+        assert_eq!(
+            editor.prompts,
+            [PROMPT, "...1> ", PROMPT, "...1> ", "...4> ", PROMPT, PROMPT]
+        );
+        assert_eq!(
+            editor.history,
+            ["(define (sq x)\n  (* x x))", "(define y (sq 4))"]
+        );
+    }
+
+    #[test]
+    fn test_completions() {
+        let env = global_env();
+        env.borrow_mut().define("car-wash", Object::Void);
+
+        assert_eq!(completions(&env.borrow(), "car"), ["car", "car-wash"]);
+        assert_eq!(
+            completions(&env.borrow(), "defin"),
+            [
+                "define",
+                "define-enum",
+                "define-memoized",
+                "define-messages",
+                "define-record-type"
+            ]
+        );
+
+        let hint = |line| argument_hint(&env.borrow(), line);
+        assert_eq!(
+            hint("(list (substring ").as_deref(),
+            Some("string start [end])")
+        );
+        assert_eq!(hint("(gc ").as_deref(), Some(")"));
+        assert_eq!(hint("(substring \"a\" "), None);
+        assert_eq!(hint("(no-such-procedure "), None);
+    }
+
+    #[test]
+    fn test_commands() {
+        let env = global_env();
+        eval_str("(define x 1) (define (f) x)", &env).unwrap();
+
+        let print = |line| match run_command(line, &env).unwrap() {
+            Command::Print(output) => output,
+            Command::Quit => String::from("quit"),
+        };
+        // Bindings from the prelude only show once redefined.
+        assert_eq!(print(":env"), "f = #<procedure>\nx = 1");
+        assert!(!print(":env").contains("car"));
+        let redefined = global_env();
+        eval_str("(define (cadr pair) 0)", &redefined).unwrap();
+        assert!(matches!(
+            run_command(":env", &redefined).unwrap(),
+            Command::Print(output) if output == "cadr = #<procedure>"
+        ));
+        assert_eq!(print(":type (f)"), "integer");
+        assert!(print(":time (+ x 1)").starts_with("2\n; "));
+        assert_eq!(print(":doc f"), "(f)\nprocedure");
+        assert!(print(":doc if").starts_with("(if test then [else])\nspecial form\n\n"));
+        assert_eq!(
+            run_command(":doc x", &env).unwrap_err().message(),
+            "no special form or procedure is called x"
+        );
+        assert_eq!(print(":quit"), "quit");
+
+        let path =
+            std::env::temp_dir().join(format!("lisp-rs-session-{}.lisp", std::process::id()));
+        let path = path.to_str().unwrap();
+        eval_str("(define l (let ((y 2)) (lambda () y)))", &env).unwrap();
+        assert_eq!(
+            print(&format!(":save {}", path)),
+            format!(
+                "saved 2 definitions to {}\nnot saved: l, a closure over local variables",
+                path
+            )
+        );
+        let restored = global_env();
+        assert_eq!(
+            run_command(&format!(":restore {}", path), &restored).unwrap(),
+            Command::Print(format!("restored {}", path))
+        );
+        assert_eq!(eval_str("(+ x (f))", &restored).unwrap().to_string(), "2");
+        std::fs::remove_file(path).unwrap();
+        assert!(run_command(":type", &env)
+            .unwrap_err()
+            .message()
+            .starts_with("unknown command"));
+    }
+
+    #[test]
+    fn test_exit_stops_the_loop() {
+        let mut editor = BasicEditor::new("(exit 7)\n(car '())\n".as_bytes(), Vec::new());
+
+        assert_eq!(run(&mut editor, &global_env()), Some(7));
+        assert_eq!(editor.output, b"lisp-rs> ");
+
+        let mut editor = BasicEditor::new(":quit\n(exit 7)\n".as_bytes(), Vec::new());
+        assert_eq!(run(&mut editor, &global_env()), None);
+    }
+}
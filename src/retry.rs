@@ -0,0 +1,257 @@
+//! `with-retry`, which evaluates its body again when it fails, for calls to
+//! services that fail now and then:
+//!
+//! ```text
+//! (with-retry (#:max 5 #:backoff 'exponential #:delay 200)
+//!   (fetch-report url))
+//! ```
+//!
+//! The options, all of which can be left out, are `#:max`, how many times
+//! the body is evaluated at most, 3 by default; `#:backoff`, how the wait
+//! between attempts grows, `'constant`, `'linear` or `'exponential`, the
+//! default, which doubles it every time; `#:delay`, the first wait in
+//! milliseconds, 100 by default; `#:max-delay`, the longest wait, 10 seconds
+//! by default; `#:jitter`, whether to shorten each wait by a random amount
+//! of up to half, so that clients that failed together do not all retry
+//! together; and `#:retry-if`, a procedure given the message of each error,
+//! which retries only the errors it returns true for.
+//!
+//! `with-retry` fails with the error of the last attempt. Exits, escapes,
+//! interrupts and going over a limit end it at once, as they are not
+//! failures of the body.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::bench;
+use crate::env::Env;
+use crate::eval::{apply, eval, EvalError};
+use crate::interrupt;
+use crate::object::Object;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Backoff {
+    Constant,
+    Linear,
+    Exponential,
+}
+
+struct Options {
+    max: i64,
+    backoff: Backoff,
+    delay: f64,
+    max_delay: f64,
+    jitter: bool,
+    retry_if: Option<Object>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            max: 3,
+            backoff: Backoff::Exponential,
+            delay: 100.0,
+            max_delay: 10_000.0,
+            jitter: false,
+            retry_if: None,
+        }
+    }
+}
+
+fn error(message: impl std::fmt::Display) -> EvalError {
+    EvalError::new(format!("with-retry: {}", message))
+}
+
+fn milliseconds(key: &str, value: &Object) -> Result<f64, EvalError> {
+    let ms = match value {
+        Object::Integer(n) => *n as f64,
+        Object::Float(f) => *f,
+        _ => f64::NAN,
+    };
+    match ms >= 0.0 {
+        true => Ok(ms),
+        false => Err(error(format!(
+            "{} expects a number of milliseconds, got {}",
+            key, value
+        ))),
+    }
+}
+
+fn parse_options(options: &Object, env: &Rc<RefCell<Env>>) -> Result<Options, EvalError> {
+    let items = options
+        .to_vec()
+        .ok_or_else(|| error(format!("expects a list of options, got {}", options)))?;
+
+    let mut parsed = Options::default();
+    for pair in items.chunks(2) {
+        let (key, value) = match pair {
+            [Object::Symbol(key), form] => (key.as_str(), eval(form, env)?),
+            _ => return Err(error(format!("invalid option {}", pair[0]))),
+        };
+        match (key, &value) {
+            ("#:max", Object::Integer(n)) if *n > 0 => parsed.max = *n,
+            ("#:max", _) => {
+                return Err(error(format!(
+                    "#:max expects a positive integer, got {}",
+                    value
+                )))
+            }
+            ("#:backoff", Object::Symbol(name)) if *name == "constant" => {
+                parsed.backoff = Backoff::Constant
+            }
+            ("#:backoff", Object::Symbol(name)) if *name == "linear" => {
+                parsed.backoff = Backoff::Linear
+            }
+            ("#:backoff", Object::Symbol(name)) if *name == "exponential" => {
+                parsed.backoff = Backoff::Exponential
+            }
+            ("#:backoff", _) => {
+                return Err(error(format!(
+                    "#:backoff expects constant, linear or exponential, got {}",
+                    value
+                )))
+            }
+            ("#:delay", _) => parsed.delay = milliseconds(key, &value)?,
+            ("#:max-delay", _) => parsed.max_delay = milliseconds(key, &value)?,
+            ("#:jitter", _) => parsed.jitter = value.is_truthy(),
+            ("#:retry-if", _) => parsed.retry_if = Some(value),
+            _ => return Err(error(format!("unknown option {}", key))),
+        }
+    }
+
+    Ok(parsed)
+}
+
+/// The wait in milliseconds before attempt `attempt + 1`, without jitter.
+fn delay(options: &Options, attempt: i64) -> f64 {
+    let wait = match options.backoff {
+        Backoff::Constant => options.delay,
+        Backoff::Linear => options.delay * attempt as f64,
+        Backoff::Exponential => options.delay * 2f64.powi((attempt - 1).min(1024) as i32),
+    };
+    wait.min(options.max_delay)
+}
+
+/// A random number from 0 to 1.
+fn random() -> Result<f64, EvalError> {
+    let mut bytes = [0; 4];
+    getrandom::fill(&mut bytes).map_err(|e| error(format!("cannot get random bytes: {}", e)))?;
+
+    Ok(u32::from_le_bytes(bytes) as f64 / u32::MAX as f64)
+}
+
+/// Waits `ms` milliseconds, failing early if the evaluation is interrupted
+/// and running the handlers of signals that arrive meanwhile.
+pub(crate) fn wait(ms: f64) -> Result<(), EvalError> {
+    let deadline = bench::now() + ms;
+    loop {
+        interrupt::poll()?;
+        #[cfg(all(feature = "signals", unix))]
+        crate::signal::poll()?;
+        let left = deadline - bench::now();
+        if left <= 0.0 {
+            return Ok(());
+        }
+        // Threads cannot sleep on wasm32, where this waits busily instead.
+        #[cfg(not(target_arch = "wasm32"))]
+        std::thread::sleep(std::time::Duration::from_secs_f64(left.min(10.0) / 1000.0));
+    }
+}
+
+fn retries(options: &Options, e: &EvalError) -> Result<bool, EvalError> {
+    if !e.is_failure() {
+        return Ok(false);
+    }
+    match &options.retry_if {
+        Some(predicate) => {
+            let message = Object::string(e.message().to_string());
+            Ok(apply(predicate, vec![message])?.is_truthy())
+        }
+        None => Ok(true),
+    }
+}
+
+/// `(with-retry (option value...) body...)`
+pub fn with_retry(list: &[Object], env: &Rc<RefCell<Env>>) -> Result<Object, EvalError> {
+    let (options, body) = match list {
+        [_, options, body @ ..] if !body.is_empty() => (parse_options(options, env)?, body),
+        _ => return Err(EvalError::new("with-retry expects options and a body")),
+    };
+
+    let mut attempt = 1;
+    loop {
+        let e = match body
+            .iter()
+            .try_fold(Object::Void, |_, form| eval(form, env))
+        {
+            Ok(value) => return Ok(value),
+            Err(e) => e,
+        };
+        if attempt == options.max || !retries(&options, &e)? {
+            return Err(e);
+        }
+
+        let mut ms = delay(&options, attempt);
+        if options.jitter {
+            ms *= 1.0 - random()? / 2.0;
+        }
+        wait(ms)?;
+        attempt += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builtins::global_env;
+    use crate::eval::eval_str;
+
+    #[test]
+    fn test_with_retry() {
+        let env = global_env();
+        let eval = |program: &str| match eval_str(program, &env) {
+            Ok(value) => value.to_string(),
+            Err(e) => e.message().to_string(),
+        };
+
+        eval("(define tries 0) (define (flaky) (set! tries (+ tries 1)) (car tries))");
+        assert_eq!(
+            eval("(with-retry (#:max 4 #:delay 0) (flaky))"),
+            "car expects a pair, got 4"
+        );
+        assert_eq!(eval("tries"), "4");
+        assert_eq!(
+            eval(
+                "(with-retry (#:delay 0) (set! tries (+ tries 1)) (if (< tries 6) (car 1) tries))"
+            ),
+            "6"
+        );
+
+        // Errors the predicate rejects fail at once.
+        eval("(set! tries 0)");
+        assert_eq!(
+            eval("(with-retry (#:delay 0 #:retry-if (lambda (message) #f)) (flaky))"),
+            "car expects a pair, got 1"
+        );
+        assert_eq!(eval("tries"), "1");
+        assert_eq!(
+            eval("(with-retry (#:backoff 'random) 1)"),
+            "with-retry: #:backoff expects constant, linear or exponential, got random"
+        );
+    }
+
+    #[test]
+    fn test_delay() {
+        let mut options = Options {
+            max_delay: 500.0,
+            ..Options::default()
+        };
+        let delays = |options: &Options| (1..=4).map(|n| delay(options, n)).collect::<Vec<_>>();
+
+        assert_eq!(delays(&options), [100.0, 200.0, 400.0, 500.0]);
+        options.backoff = Backoff::Linear;
+        assert_eq!(delays(&options), [100.0, 200.0, 300.0, 400.0]);
+        options.backoff = Backoff::Constant;
+        assert_eq!(delays(&options), [100.0; 4]);
+    }
+}
@@ -0,0 +1,295 @@
+//! A cron-like scheduler, for small daemons written in Lisp.
+//!
+//! `(schedule "*/5 * * * *" thunk)` calls `thunk` every five minutes once
+//! `(run-scheduler)` runs, and returns when that will first be. The five
+//! fields of a schedule are the minute, hour, day of the month, month and
+//! day of the week, from 0 for Sunday to 6, or 7 for Sunday again; each is
+//! `*`, a number, a range like `1-5`, any of those with a step like `*/15`
+//! or `0-30/10`, or a comma-separated list of them. As in cron, a time
+//! whose day of the month or day of the week is listed matches when both
+//! fields are restricted. Times are in the system time zone.
+//!
+//! `run-scheduler` waits for the next job and calls it, until there are no
+//! jobs or `(stop-scheduler)` is called, from a job or a signal handler
+//! added with `on-signal`; it then returns once the job running finishes,
+//! so a daemon can shut down cleanly on `sigterm`. A job that fails is
+//! reported on standard error, and runs again at its next time.
+
+use std::cell::{Cell, RefCell};
+
+use jiff::civil::{DateTime, Time};
+use jiff::{ToSpan, Zoned};
+
+use crate::comparator::is_procedure;
+use crate::eval::{apply, EvalError};
+use crate::object::Object;
+use crate::retry;
+
+/// The longest wait between checks for `stop-scheduler`, in milliseconds.
+const POLL_INTERVAL: f64 = 100.0;
+
+/// A schedule, as a bit for each value of each field that matches.
+#[derive(Debug, Clone, PartialEq)]
+struct Cron {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Whether the day of the month, and the day of the week, is `*`.
+    any_day: bool,
+    any_weekday: bool,
+}
+
+fn field(text: &str, min: u32, max: u32) -> Result<u64, String> {
+    let mut bits = 0;
+    for part in text.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, Some(step)),
+            None => (part, None),
+        };
+        let number = |s: &str| {
+            s.parse::<u32>()
+                .ok()
+                .filter(|n| (min..=max).contains(n))
+                .ok_or_else(|| format!("{} is not from {} to {}", s, min, max))
+        };
+        let (start, end) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((start, end)) => (number(start)?, number(end)?),
+            // `5/15` counts from 5 to the end.
+            None if step.is_some() => (number(range)?, max),
+            None => (number(range)?, number(range)?),
+        };
+        let step = match step {
+            Some(step) => step
+                .parse::<u32>()
+                .ok()
+                .filter(|&step| step > 0)
+                .ok_or_else(|| format!("invalid step {}", step))?,
+            None => 1,
+        };
+        if start > end {
+            return Err(format!("invalid range {}", range));
+        }
+        for n in (start..=end).step_by(step as usize) {
+            bits |= 1 << n;
+        }
+    }
+
+    Ok(bits)
+}
+
+impl Cron {
+    fn parse(spec: &str) -> Result<Self, String> {
+        let fields = spec.split_whitespace().collect::<Vec<_>>();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return Err("a schedule has five fields".to_string());
+        };
+        let mut weekday_bits = field(weekdays, 0, 7)?;
+        // Sunday is both 0 and 7.
+        if weekday_bits & 1 << 7 != 0 {
+            weekday_bits |= 1;
+        }
+
+        Ok(Self {
+            minutes: field(minutes, 0, 59)?,
+            hours: field(hours, 0, 23)?,
+            days: field(days, 1, 31)?,
+            months: field(months, 1, 12)?,
+            weekdays: weekday_bits,
+            any_day: days == "*",
+            any_weekday: weekdays == "*",
+        })
+    }
+
+    fn matches_day(&self, time: DateTime) -> bool {
+        let day = self.days & 1 << time.day() != 0;
+        let weekday = self.weekdays & 1 << time.weekday().to_sunday_zero_offset() != 0;
+        match self.any_day || self.any_weekday {
+            true => day && weekday,
+            false => day || weekday,
+        }
+    }
+
+    /// The first time after `time` that matches, if one does within a few
+    /// years, as some never do, like the 31st of February.
+    fn next_after(&self, time: DateTime) -> Option<DateTime> {
+        let mut next = time
+            .with()
+            .second(0)
+            .subsec_nanosecond(0)
+            .build()
+            .ok()?
+            .checked_add(1.minute())
+            .ok()?;
+        let last_year = next.year() + 8;
+
+        while next.year() <= last_year {
+            let midnight = |date: jiff::civil::Date| date.to_datetime(Time::midnight());
+            next = if self.months & 1 << next.month() == 0 {
+                midnight(next.date().first_of_month().checked_add(1.month()).ok()?)
+            } else if !self.matches_day(next) {
+                midnight(next.date().tomorrow().ok()?)
+            } else if self.hours & 1 << next.hour() == 0 {
+                next.with()
+                    .minute(0)
+                    .build()
+                    .ok()?
+                    .checked_add(1.hour())
+                    .ok()?
+            } else if self.minutes & 1 << next.minute() == 0 {
+                next.checked_add(1.minute()).ok()?
+            } else {
+                return Some(next);
+            };
+        }
+
+        None
+    }
+}
+
+struct Job {
+    spec: String,
+    cron: Cron,
+    thunk: Object,
+    next: Zoned,
+}
+
+thread_local! {
+    static JOBS: RefCell<Vec<Job>> = const { RefCell::new(Vec::new()) };
+    static STOPPING: Cell<bool> = const { Cell::new(false) };
+}
+
+fn next_run(spec: &str, cron: &Cron, after: &Zoned) -> Result<Zoned, EvalError> {
+    cron.next_after(after.datetime())
+        .and_then(|next| next.to_zoned(after.time_zone().clone()).ok())
+        .ok_or_else(|| EvalError::new(format!("schedule {:?} never matches", spec)))
+}
+
+/// `(schedule spec thunk)` returns the datetime of the first call.
+pub fn schedule(args: &[Object]) -> Result<Object, EvalError> {
+    let (spec, thunk) = match args {
+        [Object::String(spec), thunk] if is_procedure(thunk) => (spec.to_string(), thunk),
+        _ => {
+            return Err(EvalError::new(
+                "schedule expects a schedule string and a procedure",
+            ))
+        }
+    };
+    let cron = Cron::parse(&spec)
+        .map_err(|e| EvalError::new(format!("invalid schedule {:?}: {}", spec, e)))?;
+    let next = next_run(&spec, &cron, &Zoned::now())?;
+
+    let first = Object::string(next.to_string());
+    JOBS.with(|jobs| {
+        jobs.borrow_mut().push(Job {
+            spec,
+            cron,
+            thunk: thunk.clone(),
+            next,
+        })
+    });
+
+    Ok(first)
+}
+
+pub fn stop_scheduler(args: &[Object]) -> Result<Object, EvalError> {
+    if !args.is_empty() {
+        return Err(EvalError::new("stop-scheduler expects no arguments"));
+    }
+    STOPPING.with(|stopping| stopping.set(true));
+    Ok(Object::Void)
+}
+
+/// `(run-scheduler)` calls the jobs at their times until it is stopped.
+pub fn run_scheduler(args: &[Object]) -> Result<Object, EvalError> {
+    if !args.is_empty() {
+        return Err(EvalError::new("run-scheduler expects no arguments"));
+    }
+
+    STOPPING.with(|stopping| stopping.set(false));
+    while !STOPPING.with(Cell::get) {
+        let due = JOBS.with(|jobs| {
+            let jobs = jobs.borrow();
+            let (i, job) = jobs.iter().enumerate().min_by_key(|(_, job)| &job.next)?;
+            Some((i, job.next.clone(), job.thunk.clone()))
+        });
+        let Some((i, next, thunk)) = due else {
+            break;
+        };
+
+        let now = Zoned::now();
+        if now < next {
+            let ms = (next.timestamp().as_millisecond() - now.timestamp().as_millisecond()) as f64;
+            retry::wait(ms.min(POLL_INTERVAL))?;
+            continue;
+        }
+
+        JOBS.with(|jobs| -> Result<(), EvalError> {
+            let mut jobs = jobs.borrow_mut();
+            let job = &mut jobs[i];
+            job.next = next_run(&job.spec, &job.cron, &now)?;
+            Ok(())
+        })?;
+        match apply(&thunk, Vec::new()) {
+            Err(e) if e.is_failure() => eprintln!("scheduled job failed: {}", e.message()),
+            result => {
+                result?;
+            }
+        }
+    }
+
+    Ok(Object::Void)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builtins::global_env;
+    use crate::eval::eval_str;
+
+    fn next(spec: &str, after: &str) -> String {
+        let cron = Cron::parse(spec).unwrap();
+        match cron.next_after(after.parse().unwrap()) {
+            Some(next) => next.to_string(),
+            None => "never".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_next_after() {
+        let after = "2024-03-10T12:07:30";
+        assert_eq!(next("*/5 * * * *", after), "2024-03-10T12:10:00");
+        assert_eq!(next("0 9-17 * * 1-5", after), "2024-03-11T09:00:00");
+        assert_eq!(next("30 0 1,15 * *", after), "2024-03-15T00:30:00");
+        // With both days restricted either matches, and Sunday comes first.
+        assert_eq!(next("0 0 20 * 7", after), "2024-03-17T00:00:00");
+        assert_eq!(next("0 0 29 2 *", after), "2028-02-29T00:00:00");
+        assert_eq!(next("0 0 31 2 *", after), "never");
+
+        assert!(Cron::parse("* * *").is_err());
+        assert!(Cron::parse("60 * * * *").is_err());
+        assert!(Cron::parse("*/0 * * * *").is_err());
+    }
+
+    #[test]
+    fn test_run_scheduler() {
+        let env = global_env();
+        let eval = |program: &str| match eval_str(program, &env) {
+            Ok(value) => value.to_string(),
+            Err(e) => e.message().to_string(),
+        };
+
+        // Without jobs, it returns at once.
+        assert_eq!(eval("(run-scheduler)"), "");
+        assert_eq!(
+            eval("(schedule \"0 0 31 2 *\" (lambda () 1))"),
+            "schedule \"0 0 31 2 *\" never matches"
+        );
+        assert_eq!(
+            eval("(schedule \"* * * * mon\" (lambda () 1))"),
+            "invalid schedule \"* * * * mon\": mon is not from 0 to 7"
+        );
+    }
+}
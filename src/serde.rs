@@ -0,0 +1,234 @@
+//! Conversions between `Value`s and any type implementing serde's traits,
+//! enabled by the `serde` feature.
+//!
+//! Lists become sequences and association lists with string keys, such as
+//! `(("name" . "ana") ("age" . 31))`, become maps, so a Rust struct turns
+//! into an association list of its fields and back. This means a list of
+//! lists that each start with a string is taken for a map too. The void
+//! value is the unit, symbols become strings, and an empty map becomes the
+//! empty list.
+
+use ::serde::de::{self, DeserializeOwned, MapAccess, SeqAccess, Visitor};
+use ::serde::ser::{self, SerializeMap, SerializeSeq};
+use ::serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::fmt::Formatter;
+
+use crate::eval::EvalError;
+use crate::interpreter::Value;
+use crate::object::Object;
+
+/// Converts `value` to a Lisp value.
+pub fn to_value<T: Serialize + ?Sized>(value: &T) -> Result<Value, EvalError> {
+    let json = serde_json::to_value(value).map_err(|err| EvalError::new(err.to_string()))?;
+
+    Value::deserialize(json).map_err(|err| EvalError::new(err.to_string()))
+}
+
+/// Converts a Lisp value to a `T`.
+pub fn from_value<T: DeserializeOwned>(value: &Value) -> Result<T, EvalError> {
+    let json = serde_json::to_value(value).map_err(|err| EvalError::new(err.to_string()))?;
+
+    T::deserialize(json).map_err(|err| EvalError::new(err.to_string()))
+}
+
+/// The entries of an association list with string keys, if `items` is one.
+/// An entry whose value is a list is itself a list, as in `("tags" "a" "b")`.
+fn entries(items: &[Value]) -> Option<Vec<(String, Value)>> {
+    items
+        .iter()
+        .map(|item| match item {
+            Value::Other(Object::Pair(pair)) => match pair.car() {
+                Object::String(key) => Some((key.as_str().to_string(), Value::from(pair.cdr()))),
+                _ => None,
+            },
+            Value::List(entry) => match entry.split_first() {
+                Some((Value::String(key), [])) => Some((key.clone(), Value::Nil)),
+                Some((Value::String(key), rest)) => Some((key.clone(), Value::List(rest.to_vec()))),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect()
+}
+
+impl Serialize for Value {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Value::Void => serializer.serialize_unit(),
+            Value::Nil => serializer.serialize_seq(Some(0))?.end(),
+            Value::Bool(b) => serializer.serialize_bool(*b),
+            Value::Int(n) => serializer.serialize_i64(*n),
+            Value::Float(n) => serializer.serialize_f64(*n),
+            Value::Char(c) => serializer.serialize_char(*c),
+            Value::String(s) | Value::Symbol(s) => serializer.serialize_str(s),
+            Value::List(items) => match entries(items) {
+                Some(entries) => {
+                    let mut map = serializer.serialize_map(Some(entries.len()))?;
+                    for (key, value) in &entries {
+                        map.serialize_entry(key, value)?;
+                    }
+                    map.end()
+                }
+                None => {
+                    let mut seq = serializer.serialize_seq(Some(items.len()))?;
+                    for item in items {
+                        seq.serialize_element(item)?;
+                    }
+                    seq.end()
+                }
+            },
+            Value::Other(obj) => Err(ser::Error::custom(format!("cannot serialize {}", obj))),
+        }
+    }
+}
+
+struct ValueVisitor;
+
+impl<'de> Visitor<'de> for ValueVisitor {
+    type Value = Value;
+
+    fn expecting(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("a value representable in Lisp")
+    }
+
+    fn visit_bool<E>(self, b: bool) -> Result<Value, E> {
+        Ok(Value::Bool(b))
+    }
+
+    fn visit_i64<E>(self, n: i64) -> Result<Value, E> {
+        Ok(Value::Int(n))
+    }
+
+    fn visit_u64<E: de::Error>(self, n: u64) -> Result<Value, E> {
+        i64::try_from(n)
+            .map(Value::Int)
+            .map_err(|_| E::custom(format!("integer {} is too large", n)))
+    }
+
+    fn visit_f64<E>(self, n: f64) -> Result<Value, E> {
+        Ok(Value::Float(n))
+    }
+
+    fn visit_char<E>(self, c: char) -> Result<Value, E> {
+        Ok(Value::Char(c))
+    }
+
+    fn visit_str<E>(self, s: &str) -> Result<Value, E> {
+        Ok(Value::from(s))
+    }
+
+    fn visit_string<E>(self, s: String) -> Result<Value, E> {
+        Ok(Value::String(s))
+    }
+
+    fn visit_unit<E>(self) -> Result<Value, E> {
+        Ok(Value::Void)
+    }
+
+    fn visit_none<E>(self) -> Result<Value, E> {
+        Ok(Value::Void)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Value, D::Error> {
+        Value::deserialize(deserializer)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Value, A::Error> {
+        let mut items = Vec::new();
+        while let Some(item) = seq.next_element()? {
+            items.push(item);
+        }
+
+        // The empty list is `Nil`, as it is when converted from an object.
+        Ok(match items.is_empty() {
+            true => Value::Nil,
+            false => Value::List(items),
+        })
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Value, A::Error> {
+        let mut entries = Vec::new();
+        while let Some((key, value)) = map.next_entry::<String, Value>()? {
+            let entry = Object::cons(Object::string(key), Object::from(value));
+            entries.push(Value::Other(entry));
+        }
+
+        Ok(match entries.is_empty() {
+            true => Value::Nil,
+            false => Value::List(entries),
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for Value {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Value, D::Error> {
+        deserializer.deserialize_any(ValueVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::Interpreter;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Player {
+        name: String,
+        score: i64,
+        tags: Vec<String>,
+        ratio: f64,
+    }
+
+    #[test]
+    fn test_structs_round_trip() {
+        let player = Player {
+            name: String::from("ana"),
+            score: 31,
+            tags: vec![String::from("a"), String::from("b")],
+            ratio: 0.5,
+        };
+        let mut interp = Interpreter::new();
+        interp.define("player", to_value(&player).unwrap());
+
+        assert_eq!(
+            interp
+                .eval_str("(begin player)")
+                .map(Object::from)
+                .unwrap()
+                .to_string(),
+            "((\"name\" . \"ana\") (\"score\" . 31) (\"tags\" \"a\" \"b\") (\"ratio\" . 0.5))"
+        );
+        let value = interp
+            .eval_str("(list (cons \"name\" \"bo\") (cons \"score\" 7) (list \"tags\") (cons \"ratio\" 2))")
+            .unwrap();
+        assert_eq!(
+            from_value::<Player>(&value).unwrap(),
+            Player {
+                name: String::from("bo"),
+                score: 7,
+                tags: Vec::new(),
+                ratio: 2.0,
+            }
+        );
+
+        let err = from_value::<Player>(&Value::Int(1)).unwrap_err();
+        assert!(err.to_string().contains("expected struct Player"));
+    }
+
+    #[test]
+    fn test_json() {
+        let json = serde_json::json!({"items": [1, 2.5, "x", true], "empty": []});
+        let value = to_value(&json).unwrap();
+        assert_eq!(
+            Object::from(value.clone()).to_string(),
+            "((\"items\" 1 2.5 \"x\" #t) (\"empty\"))"
+        );
+        assert_eq!(from_value::<serde_json::Value>(&value).unwrap(), json);
+
+        assert_eq!(to_value(&()).unwrap(), Value::Void);
+        assert!(to_value(&u64::MAX).is_err());
+        let procedure = Interpreter::new().eval_str("car").unwrap();
+        assert!(from_value::<serde_json::Value>(&procedure).is_err());
+    }
+}
@@ -0,0 +1,131 @@
+//! `lisp-rs serve`: an eval server for editors and other tools.
+//!
+//! Clients send Lisp source over TCP, one line at a time; a form may span
+//! several lines, and a line may hold several forms. Every complete form is
+//! evaluated in the server's one global environment, and answered with a
+//! line of JSON: `{"value":"..."}` with the printed result (`null` for
+//! no value), or `{"error":"..."}`. Clients are served one at a time, in
+//! the order they connect. `(exit n)` stops the server.
+
+use std::cell::RefCell;
+use std::io;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::rc::Rc;
+
+use crate::env::Env;
+use crate::eval::eval;
+use crate::json;
+use crate::object::Object;
+use crate::repl::FormReader;
+
+fn reply(output: &mut impl Write, key: &str, text: Option<String>) -> io::Result<()> {
+    let text = match text {
+        Some(text) => json::stringify(&Object::string(text)).unwrap_or_default(),
+        None => String::from("null"),
+    };
+
+    writeln!(output, "{{\"{}\":{}}}", key, text)?;
+    output.flush()
+}
+
+/// Serves one client until it disconnects. Returns the exit code if the
+/// client evaluated `(exit n)`.
+pub fn session(
+    input: impl BufRead,
+    mut output: impl Write,
+    env: &Rc<RefCell<Env>>,
+) -> io::Result<Option<i32>> {
+    let mut reader = FormReader::new();
+
+    for line in input.lines() {
+        let forms = match reader.push_line(&line?) {
+            Ok(Some(forms)) => forms,
+            Ok(None) => continue,
+            Err(e) => {
+                reply(&mut output, "error", Some(e.to_string()))?;
+                continue;
+            }
+        };
+
+        for form in forms {
+            match eval(&form, env) {
+                Ok(Object::Void) => reply(&mut output, "value", None)?,
+                Ok(value) => reply(&mut output, "value", Some(value.to_string()))?,
+                Err(e) => match e.exit_code() {
+                    Some(code) => return Ok(Some(code)),
+                    None => reply(&mut output, "error", Some(e.to_string()))?,
+                },
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+/// Accepts clients on `listener` until one of them evaluates `(exit n)`,
+/// returning `n`. A client whose connection fails is dropped.
+pub fn serve(listener: TcpListener, env: &Rc<RefCell<Env>>) -> i32 {
+    for stream in listener.incoming() {
+        let result = stream.and_then(|stream| {
+            let input = BufReader::new(stream.try_clone()?);
+            session(input, stream, env)
+        });
+
+        match result {
+            Ok(Some(code)) => return code,
+            Ok(None) => {}
+            Err(e) => eprintln!("connection failed: {}", e),
+        }
+    }
+
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builtins::global_env;
+    use std::net::TcpStream;
+
+    #[test]
+    fn test_session() {
+        let env = global_env();
+        let input =
+            "(define (sq x)\n  (* x x)) (sq 3)\n(car '()) (string-append \"a\" \"b\")\n)\n(sq 4)\n";
+        let mut output = Vec::new();
+
+        assert_eq!(session(input.as_bytes(), &mut output, &env).unwrap(), None);
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "{\"value\":null}\n\
+             {\"value\":\"9\"}\n\
+             {\"error\":\"Evaluation error: car expects a pair, got ()\"}\n\
+             {\"value\":\"\\\"ab\\\"\"}\n\
+             {\"error\":\"Parse error: unexpected ')'\"}\n\
+             {\"value\":\"16\"}\n"
+        );
+    }
+
+    #[test]
+    fn test_serve() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || serve(listener, &global_env()));
+
+        let first = TcpStream::connect(addr).unwrap();
+        writeln!(&first, "(define x 41)").unwrap();
+        let mut lines = BufReader::new(first.try_clone().unwrap()).lines();
+        assert_eq!(lines.next().unwrap().unwrap(), "{\"value\":null}");
+        drop(lines);
+        drop(first);
+
+        // The environment outlives connections.
+        let second = TcpStream::connect(addr).unwrap();
+        writeln!(&second, "(+ x 1)\n(exit 3)").unwrap();
+        let mut lines = BufReader::new(second.try_clone().unwrap()).lines();
+        assert_eq!(lines.next().unwrap().unwrap(), "{\"value\":\"42\"}");
+
+        assert_eq!(server.join().unwrap(), 3);
+    }
+}
@@ -0,0 +1,301 @@
+//! Saving the definitions of a session as a program that makes them again,
+//! for the REPL's `:save` and `:restore` and `Interpreter::snapshot`.
+//!
+//! A snapshot has a definition for each global variable the session bound,
+//! or bound again, leaving out the builtins and the procedures of the
+//! prelude that are as they were. Data is written as quoted literals, hash
+//! tables through `alist->hash-table`, lambdas and macros defined at the
+//! top level as the `lambda` and `defmacro` forms that make them, and
+//! builtins bound to other names as those names:
+//!
+//! ```text
+//! ;; lisp-rs session
+//! (define limit 10)
+//! (define names '("ada" "grace"))
+//! (define square (lambda (x) (* x x)))
+//! ; not saved: server, a socket
+//! ```
+//!
+//! Values that cannot be written so, such as sockets or closures over local
+//! variables, have a comment in their place, and `Snapshot::skipped` lists
+//! them. Restoring a snapshot is evaluating it.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::builtins::global_env;
+use crate::env::Env;
+use crate::eval::{eval_str, EvalError};
+use crate::object::{self, Lambda, Object};
+use crate::symbol::Symbol;
+
+/// The definitions of a session, made by `snapshot`.
+pub struct Snapshot {
+    definitions: Vec<String>,
+    skipped: Vec<(String, String)>,
+}
+
+impl Snapshot {
+    /// The program making the definitions again.
+    pub fn source(&self) -> String {
+        self.to_string()
+    }
+
+    /// The number of definitions saved.
+    pub fn len(&self) -> usize {
+        self.definitions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.definitions.is_empty()
+    }
+
+    /// The names left out, each with what its value is.
+    pub fn skipped(&self) -> &[(String, String)] {
+        &self.skipped
+    }
+}
+
+impl fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, ";; lisp-rs session")?;
+        for definition in &self.definitions {
+            writeln!(f, "{}", definition)?;
+        }
+        for (name, reason) in &self.skipped {
+            writeln!(f, "; not saved: {}, {}", name, reason)?;
+        }
+        Ok(())
+    }
+}
+
+/// The definitions made in the global environment `env`.
+pub fn snapshot(env: &Rc<RefCell<Env>>) -> Snapshot {
+    let fresh = global_env();
+    let mut snapshot = Snapshot {
+        definitions: Vec::new(),
+        skipped: Vec::new(),
+    };
+
+    for (name, value) in env.borrow().bindings() {
+        if is_predefined(name, &value, env, &fresh) {
+            continue;
+        }
+        match definition(name, &value, env) {
+            Ok(definition) => snapshot.definitions.push(definition),
+            Err(reason) => snapshot.skipped.push((name.to_string(), reason)),
+        }
+    }
+
+    snapshot
+}
+
+/// Whether `name` is bound to `value` in the global environment `env` as it
+/// is in `fresh`, a new one: to the builtin of that name, or to the value
+/// of the same definition in the prelude.
+pub(crate) fn is_predefined(
+    name: Symbol,
+    value: &Object,
+    env: &Rc<RefCell<Env>>,
+    fresh: &Rc<RefCell<Env>>,
+) -> bool {
+    if matches!(value, Object::Builtin(builtin) if builtin.name == name.as_str()) {
+        return true;
+    }
+    let Some(original) = fresh.borrow().get(name) else {
+        return false;
+    };
+
+    match definition(name, value, env) {
+        Ok(definition) => self::definition(name, &original, fresh) == Ok(definition),
+        Err(_) => false,
+    }
+}
+
+/// Evaluates a snapshot's source in `env`.
+pub fn restore(source: &str, env: &Rc<RefCell<Env>>) -> Result<(), EvalError> {
+    eval_str(source, env).map(|_| ())
+}
+
+/// The form defining `name` as `value` in the global environment `env`, or
+/// why there is none.
+fn definition(name: Symbol, value: &Object, env: &Rc<RefCell<Env>>) -> Result<String, String> {
+    let expr = match value {
+        Object::Lambda(lambda) => {
+            let params = match (lambda.params.is_empty(), lambda.rest) {
+                (true, Some(rest)) => rest.to_string(),
+                _ => signature(&[], lambda),
+            };
+            format!("(lambda {}{})", params, body(lambda, env)?)
+        }
+        Object::Macro(expander) => {
+            return Ok(format!(
+                "(defmacro {}{})",
+                signature(&[name], expander),
+                body(expander, env)?
+            ))
+        }
+        Object::Builtin(builtin) => match global_env().borrow().get(builtin.name) {
+            Some(Object::Builtin(found)) if found.name == builtin.name => builtin.name.to_string(),
+            _ => return Err(String::from("a builtin")),
+        },
+        Object::Native(_) => return Err(String::from("a procedure the host registered")),
+        Object::HashTable(table) => {
+            let mut entries = table
+                .borrow()
+                .iter()
+                .map(|(key, value)| {
+                    let mut entry = String::from("(");
+                    write_datum(key, &mut entry, &mut Vec::new())?;
+                    entry.push_str(" . ");
+                    write_datum(value, &mut entry, &mut Vec::new())?;
+                    entry.push(')');
+                    Ok(entry)
+                })
+                .collect::<Result<Vec<_>, String>>()?;
+            entries.sort();
+            format!("(alist->hash-table '({}))", entries.join(" "))
+        }
+        Object::Pair(_) | Object::Nil | Object::Symbol(_) => {
+            let mut datum = String::from("'");
+            write_datum(value, &mut datum, &mut Vec::new())?;
+            datum
+        }
+        _ => {
+            let mut datum = String::new();
+            write_datum(value, &mut datum, &mut Vec::new())?;
+            datum
+        }
+    };
+
+    Ok(format!("(define {} {})", name, expr))
+}
+
+/// The list of `head` and the parameters of `lambda`, as `(name a . rest)`.
+fn signature(head: &[Symbol], lambda: &Lambda) -> String {
+    let mut items = head
+        .iter()
+        .chain(&lambda.params)
+        .map(Symbol::to_string)
+        .collect::<Vec<_>>();
+    if let Some(rest) = lambda.rest {
+        items.extend([String::from("."), rest.to_string()]);
+    }
+
+    format!("({})", items.join(" "))
+}
+
+/// The forms of the body of a lambda made at the top level of `env`, each
+/// after a space.
+fn body(lambda: &Lambda, env: &Rc<RefCell<Env>>) -> Result<String, String> {
+    if !Rc::ptr_eq(&lambda.env, env) {
+        return Err(String::from("a closure over local variables"));
+    }
+    let mut out = String::new();
+    for form in &lambda.body {
+        out.push(' ');
+        write_datum(form, &mut out, &mut Vec::new())?;
+    }
+
+    Ok(out)
+}
+
+/// Writes `value` as the reader reads it back. `lists` has the address of
+/// each list being written, to tell a list that contains itself.
+fn write_datum(value: &Object, out: &mut String, lists: &mut Vec<usize>) -> Result<(), String> {
+    match value {
+        Object::Nil
+        | Object::Bool(_)
+        | Object::Integer(_)
+        | Object::BigInt(_)
+        | Object::Rational(_)
+        | Object::Char(_)
+        | Object::String(_) => out.push_str(&value.to_string()),
+        Object::Float(n) if n.is_finite() => out.push_str(&format!("{:?}", n)),
+        Object::Float(_) => return Err(String::from("an infinite or NaN float")),
+        Object::Symbol(symbol) if symbol.is_interned() => out.push_str(symbol),
+        Object::Symbol(_) => return Err(String::from("a symbol made by gensym")),
+        Object::Pair(_) => {
+            let start = lists.len();
+            out.push('(');
+            let mut current = value.clone();
+            while let Object::Pair(pair) = current {
+                let address = Rc::as_ptr(&pair) as usize;
+                if lists.contains(&address) {
+                    return Err(String::from("a list containing itself"));
+                }
+                if lists.len() > start {
+                    out.push(' ');
+                }
+                lists.push(address);
+                write_datum(&pair.car(), out, lists)?;
+                current = pair.cdr();
+            }
+            if !matches!(current, Object::Nil) {
+                out.push_str(" . ");
+                write_datum(&current, out, lists)?;
+            }
+            out.push(')');
+            lists.truncate(start);
+        }
+        other => return Err(object::with_article(other.type_name())),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot() {
+        let env = global_env();
+        eval_str(
+            "(define limit 10)
+             (define names '(\"ada\" (grace . 1.5) #\\a))
+             (define (square x) (* x x))
+             (define (log . items) items)
+             (define first car)
+             (define table (alist->hash-table '((b . 2) (a . 1))))
+             (defmacro (unless test . body) `(if ,test #f (begin ,@body)))
+             (define counter (let ((n 0)) (lambda () n)))
+             (define quoted (list->string (list #\\\" #\\\\ #\\newline #\\tab)))
+             (define (length xs) 0)",
+            &env,
+        )
+        .unwrap();
+
+        let snapshot = snapshot(&env);
+        assert_eq!(
+            snapshot.source(),
+            ";; lisp-rs session
+(define first car)
+(define length (lambda (xs) 0))
+(define limit 10)
+(define log (lambda items items))
+(define names '(\"ada\" (grace . 1.5) #\\a))
+(define quoted \"\\\"\\\\\\n\\t\")
+(define square (lambda (x) (* x x)))
+(define table (alist->hash-table '((a . 1) (b . 2))))
+(defmacro (unless test . body) (quasiquote (if (unquote test) #f (begin (unquote-splicing body)))))
+; not saved: counter, a closure over local variables
+"
+        );
+        assert_eq!(snapshot.len(), 9);
+
+        let restored = global_env();
+        restore(&snapshot.source(), &restored).unwrap();
+        assert_eq!(
+            eval_str(
+                "(list limit names (square 3) (log 1 2) (first '(1)) (hash-table-ref table 'a) (length '(1)) (unless #f 'yes) (equal? quoted (list->string (list #\\\" #\\\\ #\\newline #\\tab))))",
+                &restored
+            )
+            .unwrap()
+            .to_string(),
+            "(10 (\"ada\" (grace . 1.5) #\\a) 9 (1 2) 1 1 0 yes #t)"
+        );
+        assert!(super::snapshot(&global_env()).is_empty());
+    }
+}
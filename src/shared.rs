@@ -0,0 +1,362 @@
+//! Using an interpreter from several threads.
+//!
+//! Lisp values are reference counted with `Rc` and environments are shared
+//! through `RefCell`s, which keeps the evaluator free of atomic operations
+//! and locks but ties an `Interpreter` and its values to one thread. A
+//! `SharedInterpreter` owns an interpreter on a thread of its own instead,
+//! and is a `Send + Sync` handle to it: the host sends it work from any
+//! thread and gets the results back as `Datum`s, the values that are plain
+//! data, numbers, text, lists and hash tables of them, and so can leave the
+//! interpreter's thread.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::mpsc;
+
+use crate::bigint::BigInt;
+use crate::collections::{hash_table_object, HashTable};
+use crate::diagnostic::Diagnostic;
+use crate::eval::EvalError;
+use crate::interpreter::{Interpreter, Value};
+use crate::interrupt::InterruptHandle;
+use crate::limits::{self, Limit};
+use crate::object::{self, Object};
+use crate::rational::Rational;
+
+/// A Lisp value made only of data, which can be sent between threads.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Datum {
+    Void,
+    Nil,
+    Bool(bool),
+    Int(i64),
+    /// An integer too large for an `Int`.
+    BigInt(BigInt),
+    Rational(Rational),
+    Float(f64),
+    Char(char),
+    String(String),
+    Symbol(String),
+    List(Vec<Datum>),
+    /// A list ending in something other than the empty list: its items and
+    /// what its last pair holds in place of the rest.
+    DottedList(Vec<Datum>, Box<Datum>),
+    /// The entries of a hash table, in no particular order.
+    HashTable(Vec<(Datum, Datum)>),
+}
+
+impl From<Datum> for Value {
+    fn from(datum: Datum) -> Self {
+        match datum {
+            Datum::Void => Value::Void,
+            Datum::Nil => Value::Nil,
+            Datum::Bool(b) => Value::Bool(b),
+            Datum::Int(n) => Value::Int(n),
+            Datum::BigInt(n) => Value::Other(Object::from_bigint(n)),
+            Datum::Rational(n) => Value::Other(Object::from_rational(n)),
+            Datum::Float(n) => Value::Float(n),
+            Datum::Char(c) => Value::Char(c),
+            Datum::String(s) => Value::String(s),
+            Datum::Symbol(name) => Value::Symbol(name),
+            Datum::List(items) => Value::List(items.into_iter().map(Value::from).collect()),
+            Datum::DottedList(items, tail) => {
+                let tail = Object::from(Value::from(*tail));
+                Value::Other(items.into_iter().rev().fold(tail, |tail, item| {
+                    Object::cons(Object::from(Value::from(item)), tail)
+                }))
+            }
+            Datum::HashTable(entries) => {
+                let mut table = HashTable::default();
+                for (key, value) in entries {
+                    table.insert(
+                        Object::from(Value::from(key)),
+                        Object::from(Value::from(value)),
+                    );
+                }
+                Value::Other(hash_table_object(table))
+            }
+        }
+    }
+}
+
+/// Fails for values that are not data, such as procedures, which only mean
+/// something to the interpreter that made them, and for lists and hash
+/// tables that contain themselves.
+impl TryFrom<Value> for Datum {
+    type Error = EvalError;
+
+    fn try_from(mut value: Value) -> Result<Self, Self::Error> {
+        Ok(match &mut value {
+            Value::Void => Datum::Void,
+            Value::Nil => Datum::Nil,
+            Value::Bool(b) => Datum::Bool(*b),
+            Value::Int(n) => Datum::Int(*n),
+            Value::Float(n) => Datum::Float(*n),
+            Value::Char(c) => Datum::Char(*c),
+            Value::String(s) => Datum::String(std::mem::take(s)),
+            Value::Symbol(name) => Datum::Symbol(std::mem::take(name)),
+            Value::List(items) => Datum::List(
+                std::mem::take(items)
+                    .into_iter()
+                    .map(Datum::try_from)
+                    .collect::<Result<_, _>>()?,
+            ),
+            Value::Other(obj) => datum(obj, &mut Vec::new())?,
+        })
+    }
+}
+
+/// `obj` as a datum. `tables` holds the hash tables it is inside of, so
+/// that one containing itself is refused rather than followed forever.
+fn datum(obj: &Object, tables: &mut Vec<*const RefCell<HashTable>>) -> Result<Datum, EvalError> {
+    let refused = |what: &str| EvalError::new(format!("{} cannot leave its interpreter", what));
+
+    Ok(match obj {
+        Object::BigInt(n) => Datum::BigInt((**n).clone()),
+        Object::Rational(n) => Datum::Rational((**n).clone()),
+        Object::Pair(_) if obj.contains_itself() => {
+            return Err(refused("a list containing itself"))
+        }
+        Object::Pair(_) => {
+            let mut items = Vec::new();
+            let mut tail = obj.clone();
+            while let Object::Pair(pair) = tail {
+                items.push(datum(&pair.car(), tables)?);
+                tail = pair.cdr();
+            }
+            match tail {
+                Object::Nil => Datum::List(items),
+                tail => Datum::DottedList(items, Box::new(datum(&tail, tables)?)),
+            }
+        }
+        Object::HashTable(table) => {
+            if tables.contains(&Rc::as_ptr(table)) {
+                return Err(refused("a hash table containing itself"));
+            }
+            tables.push(Rc::as_ptr(table));
+            let mut entries = Vec::new();
+            for (key, value) in table.borrow().iter() {
+                entries.push((datum(key, tables)?, datum(value, tables)?));
+            }
+            tables.pop();
+            Datum::HashTable(entries)
+        }
+        Object::Void
+        | Object::Nil
+        | Object::Bool(_)
+        | Object::Integer(_)
+        | Object::Float(_)
+        | Object::Char(_)
+        | Object::String(_)
+        | Object::Symbol(_) => Datum::try_from(Value::from(obj.clone()))?,
+        other => return Err(refused(&object::with_article(other.type_name()))),
+    })
+}
+
+/// What an error says, without the values it refers to, which stay on the
+/// interpreter's thread.
+struct SentError {
+    diagnostic: Box<Diagnostic>,
+    exit_code: Option<i32>,
+    limit: Option<Limit>,
+    interrupted: bool,
+}
+
+impl SentError {
+    fn new(err: EvalError) -> Self {
+        Self {
+            diagnostic: Box::new(err.diagnostic()),
+            exit_code: err.exit_code(),
+            limit: err.limit_exceeded(),
+            interrupted: err.is_interrupted(),
+        }
+    }
+
+    fn rebuilt(self) -> EvalError {
+        EvalError::rebuilt(
+            *self.diagnostic,
+            self.exit_code,
+            self.limit,
+            self.interrupted,
+        )
+    }
+}
+
+type Job = Box<dyn FnOnce(&mut Interpreter) + Send>;
+
+/// An interpreter running on a thread of its own, which handles the work
+/// sent to it in order. Clones are handles to the same interpreter, which
+/// stops once they are all dropped.
+#[derive(Clone)]
+pub struct SharedInterpreter {
+    jobs: mpsc::Sender<Job>,
+    interrupt: InterruptHandle,
+}
+
+fn stopped() -> EvalError {
+    EvalError::new("the interpreter thread has stopped")
+}
+
+impl SharedInterpreter {
+    /// Starts an interpreter made by `init` on its thread, where builtins
+    /// can be registered with `register_fn`.
+    pub fn spawn(init: impl FnOnce() -> Interpreter + Send + 'static) -> Self {
+        let (jobs, received) = mpsc::channel::<Job>();
+        let (sender, handle) = mpsc::channel();
+        // If the thread cannot be started, the handle is left unconnected
+        // too.
+        let _ = limits::thread_builder().spawn(move || {
+            let mut interpreter = init();
+            if sender.send(interpreter.interrupt_handle()).is_err() {
+                return;
+            }
+            for job in received {
+                job(&mut interpreter);
+            }
+        });
+
+        Self {
+            jobs,
+            // A panic in `init` leaves the handle unconnected, and every
+            // job fails.
+            interrupt: handle.recv().unwrap_or_default(),
+        }
+    }
+
+    /// Runs `f` with the interpreter on its thread, waiting for the result.
+    pub fn with<R, F>(&self, f: F) -> Result<R, EvalError>
+    where
+        R: Send + 'static,
+        F: FnOnce(&mut Interpreter) -> R + Send + 'static,
+    {
+        let (sender, result) = mpsc::channel();
+        self.jobs
+            .send(Box::new(move |interpreter: &mut Interpreter| {
+                let _ = sender.send(f(interpreter));
+            }))
+            .map_err(|_| stopped())?;
+
+        result.recv().map_err(|_| stopped())
+    }
+
+    /// Evaluates every form in `program`, returning the value of the last.
+    pub fn eval_str(&self, program: impl Into<String>) -> Result<Datum, EvalError> {
+        let program = program.into();
+        self.with(move |interpreter| {
+            interpreter
+                .eval_str(&program)
+                .and_then(Datum::try_from)
+                .map_err(SentError::new)
+        })?
+        .map_err(SentError::rebuilt)
+    }
+
+    /// Calls the Lisp procedure bound to the global variable `name`.
+    pub fn call(&self, name: impl Into<String>, args: Vec<Datum>) -> Result<Datum, EvalError> {
+        let name = name.into();
+        self.with(move |interpreter| {
+            let args = args.into_iter().map(Value::from).collect::<Vec<_>>();
+            interpreter
+                .call(&name, &args)
+                .and_then(Datum::try_from)
+                .map_err(SentError::new)
+        })?
+        .map_err(SentError::rebuilt)
+    }
+
+    /// Interrupts the evaluation running, as `Interpreter::interrupt_handle`
+    /// does; the work sent after it still runs.
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.interrupt.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn test_shared_interpreter() {
+        assert_send_sync::<SharedInterpreter>();
+        assert_send_sync::<Datum>();
+
+        let shared = SharedInterpreter::spawn(|| {
+            let mut interpreter = Interpreter::new();
+            interpreter.register_fn("host", |_| Ok(Value::from("here")));
+            interpreter
+        });
+        shared
+            .eval_str("(define (greet name) (list (host) name))")
+            .unwrap();
+
+        let workers = (0..4)
+            .map(|n| {
+                let shared = shared.clone();
+                thread::spawn(move || shared.call("greet", vec![Datum::Int(n)]).unwrap())
+            })
+            .collect::<Vec<_>>();
+        for (n, worker) in workers.into_iter().enumerate() {
+            assert_eq!(
+                worker.join().unwrap(),
+                Datum::List(vec![Datum::String("here".into()), Datum::Int(n as i64)])
+            );
+        }
+
+        let err = shared.eval_str("car").unwrap_err();
+        assert_eq!(err.message(), "a procedure cannot leave its interpreter");
+        let err = shared.eval_str("(car 1)").unwrap_err();
+        assert!(err.diagnostic().span.is_some());
+    }
+
+    #[test]
+    fn test_data_leaving_the_interpreter() {
+        let shared = SharedInterpreter::spawn(Interpreter::new);
+        let datum = shared
+            .eval_str(
+                "(define t (make-hash-table)) (hash-table-set! t 'a '(1))
+                 (list (expt 10 30) 1/3 '(1 2 . 3) t)",
+            )
+            .unwrap();
+        let Datum::List(items) = &datum else {
+            panic!("expected a list, got {:?}", datum);
+        };
+        assert_eq!(
+            items[..3],
+            [
+                Datum::BigInt(BigInt::parse("1000000000000000000000000000000").unwrap()),
+                Datum::Rational(Rational::new(BigInt::from_i64(1), BigInt::from_i64(3)).unwrap()),
+                Datum::DottedList(vec![Datum::Int(1), Datum::Int(2)], Box::new(Datum::Int(3))),
+            ]
+        );
+        assert_eq!(
+            items[3],
+            Datum::HashTable(vec![(
+                Datum::Symbol("a".into()),
+                Datum::List(vec![Datum::Int(1)])
+            )])
+        );
+
+        shared.eval_str("(define (same x) x)").unwrap();
+        assert_eq!(shared.call("same", vec![datum.clone()]).unwrap(), datum);
+
+        for (source, message) in [
+            (
+                "(define xs (list 1 2)) (set-cdr! (cdr xs) xs) xs",
+                "a list containing itself cannot leave its interpreter",
+            ),
+            (
+                "(define t (make-hash-table)) (hash-table-set! t 'self (list t)) t",
+                "a hash table containing itself cannot leave its interpreter",
+            ),
+            (
+                "(defstruct apple color) (make-apple 'red)",
+                "an apple cannot leave its interpreter",
+            ),
+        ] {
+            assert_eq!(shared.eval_str(source).unwrap_err().message(), message);
+        }
+    }
+}
@@ -0,0 +1,133 @@
+//! Unix signal handlers written in Lisp, enabled with the `signals` feature.
+//!
+//! Signals are only recorded when they arrive; the handlers run later, at
+//! the start of the next evaluation step, where running arbitrary Lisp code
+//! is safe.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+
+use signal_hook::consts::signal::{SIGHUP, SIGINT, SIGQUIT, SIGTERM, SIGUSR1, SIGUSR2};
+
+use crate::eval::{apply, EvalError};
+use crate::object::{BuiltinFn, Object};
+use crate::symbol::Symbol;
+
+pub const BUILTINS: &[(&str, BuiltinFn)] = &[("on-signal", on_signal)];
+
+const SIGNALS: &[(&str, i32)] = &[
+    ("sighup", SIGHUP),
+    ("sigint", SIGINT),
+    ("sigquit", SIGQUIT),
+    ("sigterm", SIGTERM),
+    ("sigusr1", SIGUSR1),
+    ("sigusr2", SIGUSR2),
+];
+
+struct Handler {
+    name: Symbol,
+    pending: Arc<AtomicBool>,
+    procedure: Object,
+}
+
+thread_local! {
+    static HANDLERS: RefCell<HashMap<i32, Handler>> = RefCell::new(HashMap::new());
+}
+
+/// Set whenever any handled signal arrives, so that `poll` stays cheap when
+/// nothing happened.
+fn any_pending() -> &'static Arc<AtomicBool> {
+    static ANY: OnceLock<Arc<AtomicBool>> = OnceLock::new();
+    ANY.get_or_init(|| Arc::new(AtomicBool::new(false)))
+}
+
+/// `(on-signal 'sigterm handler)` calls `handler` with the signal name each
+/// time the signal is received, replacing any previous handler.
+fn on_signal(args: &[Object]) -> Result<Object, EvalError> {
+    let (name, procedure) = match args {
+        [Object::Symbol(name), procedure] => (*name, procedure.clone()),
+        _ => {
+            return Err(EvalError::new(
+                "on-signal expects a signal name and a handler",
+            ))
+        }
+    };
+    let signal = SIGNALS
+        .iter()
+        .find(|(signal, _)| name == *signal)
+        .map(|&(_, signal)| signal)
+        .ok_or_else(|| EvalError::new(format!("unsupported signal: {}", name)))?;
+
+    HANDLERS.with(|handlers| {
+        let mut handlers = handlers.borrow_mut();
+        if let Some(handler) = handlers.get_mut(&signal) {
+            handler.procedure = procedure;
+            return Ok(());
+        }
+
+        let pending = Arc::new(AtomicBool::new(false));
+        signal_hook::flag::register(signal, pending.clone())
+            .and_then(|_| signal_hook::flag::register(signal, any_pending().clone()))
+            .map_err(|e| EvalError::new(format!("cannot handle {}: {}", name, e)))?;
+
+        handlers.insert(
+            signal,
+            Handler {
+                name,
+                pending,
+                procedure,
+            },
+        );
+        Ok(())
+    })?;
+
+    Ok(Object::Void)
+}
+
+/// Runs the handlers of the signals received since the last call.
+pub fn poll() -> Result<(), EvalError> {
+    if !any_pending().swap(false, Ordering::Relaxed) {
+        return Ok(());
+    }
+
+    let received: Vec<(Symbol, Object)> = HANDLERS.with(|handlers| {
+        handlers
+            .borrow()
+            .values()
+            .filter(|handler| handler.pending.swap(false, Ordering::Relaxed))
+            .map(|handler| (handler.name, handler.procedure.clone()))
+            .collect()
+    });
+
+    for (name, procedure) in received {
+        apply(&procedure, vec![Object::Symbol(name)])?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use signal_hook::consts::signal::SIGUSR1;
+    use signal_hook::low_level::raise;
+
+    use crate::builtins::global_env;
+    use crate::eval::eval_str;
+
+    #[test]
+    fn test_handler_runs_at_next_step() {
+        let env = global_env();
+        eval_str(
+            "(define received '())
+             (on-signal 'sigusr1 (lambda (sig) (set! received (cons sig received))))",
+            &env,
+        )
+        .unwrap();
+
+        raise(SIGUSR1).unwrap();
+        assert_eq!(eval_str("received", &env).unwrap().to_string(), "(sigusr1)");
+        assert!(eval_str("(on-signal 'sigkill (lambda (s) s))", &env).is_err());
+    }
+}
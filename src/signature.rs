@@ -0,0 +1,560 @@
+//! Signatures of procedures: their parameters, the type each expects, and
+//! so how many arguments they take.
+//!
+//! Every builtin has one, written in `SIGNATURES` as its name followed by
+//! its parameters, each `name:type`, in brackets when optional, and with
+//! `...` after the one that receives the rest of the arguments:
+//!
+//! ```text
+//! substring string:string start:integer [end:integer]
+//! ```
+//!
+//! A parameter without a type takes any value. Those of lambdas, compiled
+//! closures and procedures registered by a host come from their parameter
+//! lists or arities, and take any value; a lambda is named after the
+//! procedure `(define (f x) ...)` defines. `(procedure-signature f)` returns
+//! the signature of `f` as a hash table, and a builtin called with a wrong
+//! number of arguments fails with a hint showing how it is called.
+
+use crate::eval::EvalError;
+use crate::interpreter::Arity;
+use crate::lsp::object;
+use crate::object::Object;
+
+const SIGNATURES: &[&str] = &[
+    "+ numbers:number...",
+    "- a:number b:number",
+    "* numbers:number...",
+    "/ a:number b:number",
+    "expt base:number exponent:number",
+    "abs x:number",
+    "min x:number xs:number...",
+    "max x:number xs:number...",
+    "floor x:number",
+    "ceiling x:number",
+    "round x:number",
+    "truncate x:number",
+    "quotient n:integer d:integer",
+    "remainder n:integer d:integer",
+    "modulo n:integer d:integer",
+    "sqrt x:number",
+    "exp x:number",
+    "log x:number [base:number]",
+    "sin x:number",
+    "cos x:number",
+    "tan x:number",
+    "asin x:number",
+    "acos x:number",
+    "atan y:number [x:number]",
+    "random limit:number",
+    "set-random-seed! seed:integer",
+    "exact->inexact x:number",
+    "inexact->exact x:number",
+    "= a:number b:number",
+    "< a:number b:number",
+    "> a:number b:number",
+    "<= a:number b:number",
+    ">= a:number b:number",
+    "cons car cdr",
+    "car pair:pair",
+    "cdr pair:pair",
+    "set-car! pair:pair obj",
+    "set-cdr! pair:pair obj",
+    "list-set! list:list k:integer obj",
+    "append! lists...",
+    "reverse! list:list",
+    "list items...",
+    "eq? a b",
+    "eqv? a b",
+    "equal? a b",
+    "number? obj",
+    "string? obj",
+    "symbol? obj",
+    "pair? obj",
+    "null? obj",
+    "list? obj",
+    "procedure? obj",
+    "diff old new",
+    "hash obj",
+    "copy obj",
+    "apply procedure:procedure args...",
+    "values values...",
+    "call-with-values producer:procedure consumer:procedure",
+    "force promise",
+    "make-promise value",
+    "promise? obj",
+    "map procedure:procedure list:list lists:list...",
+    "par-map procedure:procedure list:list",
+    "for-each procedure:procedure list:list lists:list...",
+    "filter predicate:procedure list:list",
+    "reduce procedure:procedure default list:list",
+    "fold-left procedure:procedure initial list:list lists:list...",
+    "fold-right procedure:procedure initial list:list lists:list...",
+    "call/cc receiver:procedure",
+    "call-with-current-continuation receiver:procedure",
+    "call-with-escape-continuation receiver:procedure",
+    "dynamic-wind before:procedure thunk:procedure after:procedure",
+    "memoize procedure:procedure [capacity:integer]",
+    "benchmark thunk:procedure options...",
+    "break",
+    "gc",
+    "gc-stats",
+    "char->integer char:char",
+    "integer->char n:integer",
+    "char-upcase char:char",
+    "char-downcase char:char",
+    "string-ref string:string index:integer",
+    "string-length string:string",
+    "substring string:string start:integer [end:integer]",
+    "string-append strings:string...",
+    "string-copy string:string",
+    "string->list string:string",
+    "list->string chars:list",
+    "tainted? obj",
+    "taint string:string",
+    "untaint string:string",
+    "make-comparator type-test equality ordering hash",
+    "make-default-comparator",
+    "comparator? obj",
+    "comparator-ordered? comparator:comparator",
+    "comparator-hashable? comparator:comparator",
+    "comparator-hash comparator:comparator obj",
+    "=? comparator:comparator a b rest...",
+    "<? comparator:comparator a b rest...",
+    ">? comparator:comparator a b rest...",
+    "<=? comparator:comparator a b rest...",
+    ">=? comparator:comparator a b rest...",
+    "sort list:list [order]",
+    "deque-sort! deque:deque [order]",
+    "make-heap [order]",
+    "heap-push! heap:heap item",
+    "heap-pop! heap:heap",
+    "heap-peek heap:heap",
+    "heap-size heap:heap",
+    "make-deque",
+    "deque-push-front! deque:deque item",
+    "deque-push-back! deque:deque item",
+    "deque-pop-front! deque:deque",
+    "deque-pop-back! deque:deque",
+    "deque-size deque:deque",
+    "make-hash-table",
+    "hash-table? obj",
+    "hash-table-set! table:hash-table key value",
+    "hash-table-ref table:hash-table key [default]",
+    "hash-table-delete! table:hash-table key",
+    "hash-table-count table:hash-table",
+    "hash-table-keys table:hash-table",
+    "hash-table->alist table:hash-table",
+    "alist->hash-table alist:list",
+    "assq key alist:list",
+    "assv key alist:list",
+    "assoc key alist:list [equal:procedure]",
+    "gensym [prefix]",
+    "kv-open path:string",
+    "kv-get store:kv-store key [default]",
+    "kv-set! store:kv-store key value",
+    "kv-delete! store:kv-store key",
+    "kv-keys store:kv-store",
+    "kv-compact! store:kv-store",
+    "tcp-connect host:string port:integer",
+    "tcp-listen host:string port:integer",
+    "tcp-accept listener:socket",
+    "socket-read-line socket:socket",
+    "socket-write socket:socket string:string",
+    "socket-port socket:socket",
+    "socket-close socket:socket",
+    "render-template template:string context",
+    "json-parse json:string options...",
+    "json-stringify value",
+    "make-sorted-map [order]",
+    "sorted-map-set! map:sorted-map key value",
+    "sorted-map-ref map:sorted-map key [default]",
+    "sorted-map-delete! map:sorted-map key",
+    "sorted-map-size map:sorted-map",
+    "sorted-map->list map:sorted-map",
+    "sorted-map-keys map:sorted-map",
+    "sorted-map-floor-key map:sorted-map key",
+    "sorted-map-ceiling-key map:sorted-map key",
+    "sorted-map-submap map:sorted-map from to",
+    "make-graph edges:list",
+    "topological-sort graph",
+    "strongly-connected-components graph",
+    "shortest-path graph from to",
+    "datetime-now [time-zone:string]",
+    "string->datetime string:string",
+    "datetime->string datetime:string [format:string]",
+    "datetime->timezone datetime:string time-zone:string",
+    "datetime-add datetime:string amount:integer unit:symbol",
+    "datetime-difference a:string b:string",
+    "current-time",
+    "time-millis",
+    "sleep ms:number",
+    "define-messages locale:symbol entries...",
+    "set-locale! locale:symbol [fallback:symbol]",
+    "current-locale",
+    "msg key:symbol values...",
+    "uuid4",
+    "uuid7",
+    "ulid",
+    "spawn thunk:procedure",
+    "join task:task",
+    "spawn-fiber thunk:procedure",
+    "yield-fiber",
+    "join-fiber fiber:fiber",
+    "run-fibers",
+    "fiber-done? fiber:fiber",
+    "make-channel",
+    "channel-send! channel:channel value",
+    "channel-receive channel:channel",
+    "make-rate-limiter per-second:number options...",
+    "acquire limiter:rate-limiter",
+    "try-acquire limiter:rate-limiter",
+    "schedule schedule:string thunk:procedure",
+    "run-scheduler",
+    "stop-scheduler",
+    "pp value [width:integer]",
+    "set-ordered-printing! ordered:boolean",
+    "set-float-format! format:symbol [decimals:integer]",
+    "float-format",
+    "reload-module name",
+    "read options...",
+    "read-string string:string options...",
+    "eval datum [environment:environment]",
+    "interaction-environment",
+    "command-line",
+    "getenv name:string",
+    "setenv name:string value",
+    "system command:string",
+    "process-run program:string [arguments:list]",
+    "exit [code]",
+    "procedure-signature procedure:procedure",
+    "help x",
+    "terminal-size",
+    "clear-screen!",
+    "cursor-move! column:integer row:integer",
+    "cursor-hide!",
+    "cursor-show!",
+    "set-color! foreground:symbol [background:symbol]",
+    "reset-color!",
+    "raw-mode! enabled:boolean",
+    "read-key",
+    "on-signal signal:symbol handler:procedure",
+    "watch-path path:string handler:procedure",
+    "stop-watching",
+    "regex-match? pattern:string text:string",
+    "regex-find-all pattern:string text:string",
+    "regex-replace pattern:string text:string replacement",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Parameter {
+    pub name: String,
+    /// The type of the values it takes, as `type_name` calls them, or
+    /// `number`, `list`, `procedure` or `any`.
+    pub kind: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    /// The name of the procedure, unless it is an anonymous lambda.
+    pub name: Option<String>,
+    pub required: Vec<Parameter>,
+    pub optional: Vec<Parameter>,
+    /// Receives the arguments past the others, if any are accepted.
+    pub rest: Option<Parameter>,
+}
+
+impl Parameter {
+    fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            kind: String::from("any"),
+        }
+    }
+
+    fn to_object(&self) -> Object {
+        object([
+            ("name", Object::string(self.name.as_str())),
+            ("type", Object::string(self.kind.as_str())),
+        ])
+    }
+}
+
+impl Signature {
+    /// Reads a signature written as in `SIGNATURES`.
+    fn parse(spec: &str) -> Self {
+        let mut words = spec.split_whitespace();
+        let mut signature = Signature {
+            name: words.next().map(String::from),
+            required: Vec::new(),
+            optional: Vec::new(),
+            rest: None,
+        };
+        for word in words {
+            let optional = word.starts_with('[');
+            let word = word.trim_matches(|c| c == '[' || c == ']');
+            let (word, rest) = match word.strip_suffix("...") {
+                Some(word) => (word, true),
+                None => (word, false),
+            };
+            let parameter = match word.split_once(':') {
+                Some((name, kind)) => Parameter {
+                    name: name.to_string(),
+                    kind: kind.to_string(),
+                },
+                None => Parameter::new(word),
+            };
+            match (rest, optional) {
+                (true, _) => signature.rest = Some(parameter),
+                (false, true) => signature.optional.push(parameter),
+                (false, false) => signature.required.push(parameter),
+            }
+        }
+
+        signature
+    }
+
+    /// A signature taking `arity` arguments of any type.
+    fn from_arity(name: Option<String>, arity: Arity) -> Self {
+        let (required, optional, rest) = match arity {
+            Arity::Exact(n) => (n, 0, false),
+            Arity::AtLeast(n) => (n, 0, true),
+            Arity::Range(min, max) => (min, max.saturating_sub(min), false),
+        };
+        let numbered = |range: std::ops::Range<usize>| {
+            range
+                .map(|i| Parameter::new(format!("arg{}", i + 1)))
+                .collect::<Vec<_>>()
+        };
+
+        Self {
+            name,
+            required: numbered(0..required),
+            optional: numbered(required..required + optional),
+            rest: rest.then(|| Parameter::new("args")),
+        }
+    }
+
+    pub fn min_arity(&self) -> usize {
+        self.required.len()
+    }
+
+    /// The most arguments accepted, or `None` if there is no limit.
+    pub fn max_arity(&self) -> Option<usize> {
+        match self.rest {
+            Some(_) => None,
+            None => Some(self.required.len() + self.optional.len()),
+        }
+    }
+
+    pub fn accepts(&self, count: usize) -> bool {
+        count >= self.min_arity() && self.max_arity().is_none_or(|max| count <= max)
+    }
+
+    /// How the procedure is called, as `(substring string start [end])`.
+    pub fn usage(&self) -> String {
+        let mut words = vec![self
+            .name
+            .clone()
+            .unwrap_or_else(|| String::from("<lambda>"))];
+        words.extend(self.required.iter().map(|p| p.name.clone()));
+        words.extend(self.optional.iter().map(|p| format!("[{}]", p.name)));
+        words.extend(self.rest.iter().map(|p| format!("{}...", p.name)));
+
+        format!("({})", words.join(" "))
+    }
+
+    /// The signature as a hash table of its `name` (`#f` for a lambda), its
+    /// `required` and `optional` parameters, each a table of its `name` and
+    /// `type`, the `rest` one or `#f`, and its `min-arity` and `max-arity`,
+    /// which is `#f` when there is no limit.
+    pub fn to_object(&self) -> Object {
+        let parameters = |parameters: &[Parameter]| {
+            Object::list(
+                parameters
+                    .iter()
+                    .map(Parameter::to_object)
+                    .collect::<Vec<_>>(),
+            )
+        };
+
+        object([
+            (
+                "name",
+                match &self.name {
+                    Some(name) => Object::string(name.as_str()),
+                    None => Object::Bool(false),
+                },
+            ),
+            ("required", parameters(&self.required)),
+            ("optional", parameters(&self.optional)),
+            (
+                "rest",
+                match &self.rest {
+                    Some(rest) => rest.to_object(),
+                    None => Object::Bool(false),
+                },
+            ),
+            ("min-arity", Object::Integer(self.min_arity() as i64)),
+            (
+                "max-arity",
+                match self.max_arity() {
+                    Some(max) => Object::Integer(max as i64),
+                    None => Object::Bool(false),
+                },
+            ),
+        ])
+    }
+}
+
+/// The signature of the builtin called `name`.
+pub fn builtin(name: &str) -> Option<Signature> {
+    SIGNATURES
+        .iter()
+        .find(|spec| spec.split_whitespace().next() == Some(name))
+        .map(|spec| Signature::parse(spec))
+}
+
+/// The signature of `procedure`, if it is one whose parameters are known.
+pub fn of(procedure: &Object) -> Option<Signature> {
+    match procedure {
+        Object::Builtin(builtin) => self::builtin(builtin.name),
+        Object::Lambda(lambda) => Some(Signature {
+            name: lambda.name.map(|name| name.to_string()),
+            required: lambda
+                .params
+                .iter()
+                .map(|name| Parameter::new(name.as_str()))
+                .collect(),
+            optional: Vec::new(),
+            rest: lambda.rest.map(|name| Parameter::new(name.as_str())),
+        }),
+        Object::Closure(closure) => Some(Signature::from_arity(
+            None,
+            match closure.chunk.rest {
+                true => Arity::AtLeast(closure.chunk.params),
+                false => Arity::Exact(closure.chunk.params),
+            },
+        )),
+        Object::Native(native) => Some(Signature::from_arity(
+            Some(native.name().to_string()),
+            native.arity().unwrap_or(Arity::AtLeast(0)),
+        )),
+        Object::Memo(memo) => of(memo.func()),
+        Object::Continuation(_) => Some(Signature {
+            name: None,
+            required: Vec::new(),
+            optional: vec![Parameter::new("value")],
+            rest: None,
+        }),
+        _ => None,
+    }
+}
+
+/// Adds how `name` is called to an error it failed with when called with
+/// the wrong number of arguments.
+pub(crate) fn explain_arity(name: &str, count: usize, error: EvalError) -> EvalError {
+    match builtin(name) {
+        Some(signature) if error.is_failure() && !signature.accepts(count) => {
+            error.with_hint(format!("it is called as {}", signature.usage()))
+        }
+        _ => error,
+    }
+}
+
+/// `(procedure-signature f)`.
+pub fn procedure_signature(args: &[Object]) -> Result<Object, EvalError> {
+    let [procedure] = args else {
+        return Err(EvalError::new("procedure-signature expects one procedure"));
+    };
+
+    match of(procedure) {
+        Some(signature) => Ok(signature.to_object()),
+        None if procedure.is_procedure() => Ok(Object::Bool(false)),
+        None => Err(EvalError::new(format!(
+            "procedure-signature expects a procedure, got {}",
+            procedure
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builtins::{builtins, global_env};
+    use crate::eval::eval_str;
+
+    #[test]
+    fn test_every_builtin_has_a_signature() {
+        for (name, _) in builtins() {
+            assert!(builtin(name).is_some(), "{} has no signature", name);
+        }
+
+        let mut names = SIGNATURES
+            .iter()
+            .map(|spec| Signature::parse(spec).name.unwrap())
+            .collect::<Vec<_>>();
+        names.sort();
+        let count = names.len();
+        names.dedup();
+        assert_eq!(names.len(), count);
+    }
+
+    #[test]
+    fn test_signatures() {
+        let substring = builtin("substring").unwrap();
+        assert_eq!(substring.usage(), "(substring string start [end])");
+        assert_eq!((substring.min_arity(), substring.max_arity()), (2, Some(3)));
+        assert!(substring.accepts(3) && !substring.accepts(1));
+        assert_eq!(
+            builtin("map").unwrap().usage(),
+            "(map procedure list lists...)"
+        );
+
+        let env = global_env();
+        let eval = |program: &str| match eval_str(program, &env) {
+            Ok(value) => value.to_string(),
+            Err(e) => e.message().to_string(),
+        };
+        assert_eq!(
+            eval("(define s (procedure-signature substring))
+                  (list (hash-table-ref s \"name\") (hash-table-ref s \"min-arity\")
+                        (hash-table-ref s \"max-arity\")
+                        (map (lambda (p) (hash-table-ref p \"type\")) (hash-table-ref s \"optional\")))"),
+            "(\"substring\" 2 3 (\"integer\"))"
+        );
+        assert_eq!(
+            eval(
+                "(define s (procedure-signature (lambda (a b . rest) a)))
+                  (list (hash-table-ref s \"name\") (hash-table-ref s \"max-arity\")
+                        (hash-table-ref (hash-table-ref s \"rest\") \"name\"))"
+            ),
+            "(#f #f \"rest\")"
+        );
+        assert_eq!(
+            eval(
+                "(define (area w h) (* w h)) (hash-table-ref (procedure-signature area) \"name\")"
+            ),
+            "\"area\""
+        );
+        assert_eq!(
+            eval("(procedure-signature 1)"),
+            "procedure-signature expects a procedure, got 1"
+        );
+    }
+
+    #[test]
+    fn test_arity_hints() {
+        let env = global_env();
+        let hint = |program| eval_str(program, &env).unwrap_err().diagnostic().hint;
+
+        assert_eq!(
+            hint("(substring \"abc\")"),
+            Some(String::from(
+                "it is called as (substring string start [end])"
+            ))
+        );
+        assert_eq!(hint("(substring 1 2)"), None);
+        assert_eq!(hint("(car 1)"), None);
+    }
+}
@@ -0,0 +1,566 @@
+//! Sorted maps: AVL trees keyed by a comparator, for lookups by order
+//! rather than by hash, such as the nearest key below a score or every
+//! entry in an interval.
+//!
+//! The comparator may call back into Lisp code, so every comparison can
+//! fail. Updates compare keys on the way down and only restructure the tree
+//! on the way back up, so an error leaves the map as it was.
+
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::rc::Rc;
+
+use crate::comparator::{self, Comparator};
+use crate::eval::EvalError;
+use crate::gc::{self, Edge, Trace};
+use crate::object::Object;
+
+type Link = Option<Box<Node>>;
+
+struct Node {
+    key: Object,
+    value: Object,
+    height: u32,
+    left: Link,
+    right: Link,
+}
+
+fn height(link: &Link) -> u32 {
+    link.as_ref().map_or(0, |node| node.height)
+}
+
+fn rotate_left(link: &mut Link) {
+    let mut node = link.take().unwrap();
+    let mut right = node.right.take().unwrap();
+    node.right = right.left.take();
+    node.height = 1 + height(&node.left).max(height(&node.right));
+    right.left = Some(node);
+    right.height = 1 + height(&right.left).max(height(&right.right));
+    *link = Some(right);
+}
+
+fn rotate_right(link: &mut Link) {
+    let mut node = link.take().unwrap();
+    let mut left = node.left.take().unwrap();
+    node.left = left.right.take();
+    node.height = 1 + height(&node.left).max(height(&node.right));
+    left.right = Some(node);
+    left.height = 1 + height(&left.left).max(height(&left.right));
+    *link = Some(left);
+}
+
+/// Restores the height and balance of the node at `link` after one of its
+/// subtrees grew or shrank by one level.
+fn rebalance(link: &mut Link) {
+    let Some(node) = link else {
+        return;
+    };
+    let (left, right) = (height(&node.left), height(&node.right));
+
+    if left > right + 1 {
+        let child = node.left.as_ref().unwrap();
+        if height(&child.right) > height(&child.left) {
+            rotate_left(&mut node.left);
+        }
+        rotate_right(link);
+    } else if right > left + 1 {
+        let child = node.right.as_ref().unwrap();
+        if height(&child.left) > height(&child.right) {
+            rotate_right(&mut node.right);
+        }
+        rotate_left(link);
+    } else {
+        node.height = 1 + left.max(right);
+    }
+}
+
+fn insert(
+    link: &mut Link,
+    key: Object,
+    value: Object,
+    comparator: &Comparator,
+) -> Result<Option<Object>, EvalError> {
+    let Some(node) = link else {
+        *link = Some(Box::new(Node {
+            key,
+            value,
+            height: 1,
+            left: None,
+            right: None,
+        }));
+        return Ok(None);
+    };
+
+    let replaced = match comparator.compare(&key, &node.key)? {
+        Ordering::Less => insert(&mut node.left, key, value, comparator)?,
+        Ordering::Greater => insert(&mut node.right, key, value, comparator)?,
+        Ordering::Equal => return Ok(Some(std::mem::replace(&mut node.value, value))),
+    };
+    rebalance(link);
+
+    Ok(replaced)
+}
+
+/// Detaches the leftmost node of a non-empty tree.
+fn remove_min(link: &mut Link) -> Box<Node> {
+    let node = link.as_mut().unwrap();
+    if node.left.is_some() {
+        let min = remove_min(&mut node.left);
+        rebalance(link);
+        return min;
+    }
+
+    let mut min = link.take().unwrap();
+    *link = min.right.take();
+    min
+}
+
+fn remove(link: &mut Link, key: &Object, comparator: &Comparator) -> Result<bool, EvalError> {
+    let Some(node) = link else {
+        return Ok(false);
+    };
+
+    let removed = match comparator.compare(key, &node.key)? {
+        Ordering::Less => remove(&mut node.left, key, comparator)?,
+        Ordering::Greater => remove(&mut node.right, key, comparator)?,
+        Ordering::Equal => {
+            let mut node = link.take().unwrap();
+            *link = match (node.left.take(), node.right.take()) {
+                (left, None) => left,
+                (None, right) => right,
+                (left, mut right) => {
+                    let mut successor = remove_min(&mut right);
+                    successor.left = left;
+                    successor.right = right;
+                    Some(successor)
+                }
+            };
+            true
+        }
+    };
+    rebalance(link);
+
+    Ok(removed)
+}
+
+fn get<'a>(
+    mut link: &'a Link,
+    key: &Object,
+    comparator: &Comparator,
+) -> Result<Option<&'a Object>, EvalError> {
+    while let Some(node) = link {
+        link = match comparator.compare(key, &node.key)? {
+            Ordering::Less => &node.left,
+            Ordering::Greater => &node.right,
+            Ordering::Equal => return Ok(Some(&node.value)),
+        };
+    }
+
+    Ok(None)
+}
+
+/// The greatest key at most `key`, or with `ceiling` the least key at least
+/// `key`.
+fn nearest<'a>(
+    mut link: &'a Link,
+    key: &Object,
+    comparator: &Comparator,
+    ceiling: bool,
+) -> Result<Option<&'a Object>, EvalError> {
+    let mut best = None;
+    while let Some(node) = link {
+        link = match (comparator.compare(key, &node.key)?, ceiling) {
+            (Ordering::Equal, _) => return Ok(Some(&node.key)),
+            (Ordering::Less, false) => &node.left,
+            (Ordering::Greater, true) => &node.right,
+            (Ordering::Less, true) => {
+                best = Some(&node.key);
+                &node.left
+            }
+            (Ordering::Greater, false) => {
+                best = Some(&node.key);
+                &node.right
+            }
+        };
+    }
+
+    Ok(best)
+}
+
+/// Calls `f` on the entries with keys from `from` (inclusive) up to `to`
+/// (exclusive) in order, skipping the subtrees outside the range.
+fn range(
+    link: &Link,
+    from: Option<&Object>,
+    to: Option<&Object>,
+    comparator: &Comparator,
+    f: &mut dyn FnMut(&Object, &Object),
+) -> Result<(), EvalError> {
+    let Some(node) = link else {
+        return Ok(());
+    };
+
+    let above_from = match from {
+        Some(from) => !comparator.less(&node.key, from)?,
+        None => true,
+    };
+    let below_to = match to {
+        Some(to) => comparator.less(&node.key, to)?,
+        None => true,
+    };
+
+    if above_from {
+        range(&node.left, from, to, comparator, f)?;
+    }
+    if above_from && below_to {
+        f(&node.key, &node.value);
+    }
+    if below_to {
+        range(&node.right, from, to, comparator, f)?;
+    }
+
+    Ok(())
+}
+
+pub struct SortedMap {
+    /// Always an `Object::Comparator`, kept as an object for the collector.
+    comparator: Object,
+    root: Link,
+    len: usize,
+}
+
+impl SortedMap {
+    fn comparator(&self) -> Rc<Comparator> {
+        match &self.comparator {
+            Object::Comparator(comparator) => comparator.clone(),
+            _ => unreachable!("a sorted map's comparator is always a comparator"),
+        }
+    }
+
+    fn entries(&self) -> Vec<(Object, Object)> {
+        fn walk(link: &Link, entries: &mut Vec<(Object, Object)>) {
+            if let Some(node) = link {
+                walk(&node.left, entries);
+                entries.push((node.key.clone(), node.value.clone()));
+                walk(&node.right, entries);
+            }
+        }
+
+        let mut entries = Vec::with_capacity(self.len);
+        walk(&self.root, &mut entries);
+        entries
+    }
+}
+
+impl Trace for SortedMap {
+    fn trace(&self, edge: &mut dyn FnMut(Edge)) {
+        fn walk(link: &Link, edge: &mut dyn FnMut(Edge)) {
+            if let Some(node) = link {
+                walk(&node.left, edge);
+                edge(Edge::Object(&node.key));
+                edge(Edge::Object(&node.value));
+                walk(&node.right, edge);
+            }
+        }
+
+        edge(Edge::Object(&self.comparator));
+        walk(&self.root, edge);
+    }
+
+    fn clear(&mut self) {
+        self.comparator = Object::Void;
+        self.root = None;
+        self.len = 0;
+    }
+}
+
+fn new_map(comparator: Rc<Comparator>) -> Rc<RefCell<SortedMap>> {
+    let map = Rc::new(RefCell::new(SortedMap {
+        comparator: Object::Comparator(comparator),
+        root: None,
+        len: 0,
+    }));
+    gc::track(&map);
+
+    map
+}
+
+/// Runs `f` on the map's tree. Nothing stays borrowed while `f` runs, so a
+/// comparator touching the same map cannot trigger a double borrow.
+fn with_tree<T, F>(map: &RefCell<SortedMap>, f: F) -> Result<T, EvalError>
+where
+    F: FnOnce(&Comparator, &mut Link, &mut usize) -> Result<T, EvalError>,
+{
+    let comparator = map.borrow().comparator();
+    let mut root = map.borrow_mut().root.take();
+    let mut len = map.borrow().len;
+    let result = f(&comparator, &mut root, &mut len);
+
+    let mut map = map.borrow_mut();
+    map.root = root;
+    map.len = len;
+
+    result
+}
+
+fn map_arg<'a>(
+    name: &str,
+    args: &'a [Object],
+    len: usize,
+) -> Result<&'a Rc<RefCell<SortedMap>>, EvalError> {
+    match args.first() {
+        Some(Object::SortedMap(map)) if args.len() == len => Ok(map),
+        _ if args.len() != len => Err(EvalError::new(format!(
+            "{} expects {} arguments, got {}",
+            name,
+            len,
+            args.len()
+        ))),
+        _ => Err(EvalError::new(format!("{} expects a sorted map", name))),
+    }
+}
+
+/// `(make-sorted-map)` orders keys with the default comparator;
+/// `(make-sorted-map order)` with a comparator or a `less?` procedure.
+pub fn make_sorted_map(args: &[Object]) -> Result<Object, EvalError> {
+    let comparator = match args {
+        [] => Rc::new(Comparator::default_comparator()),
+        [order] => comparator::order_arg("make-sorted-map", order)?,
+        _ => {
+            return Err(EvalError::new(
+                "make-sorted-map expects an optional comparator",
+            ))
+        }
+    };
+
+    Ok(Object::SortedMap(new_map(comparator)))
+}
+
+pub fn sorted_map_set(args: &[Object]) -> Result<Object, EvalError> {
+    let map = map_arg("sorted-map-set!", args, 3)?;
+    let (key, value) = (args[1].clone(), args[2].clone());
+
+    with_tree(map, |comparator, root, len| {
+        if insert(root, key, value, comparator)?.is_none() {
+            *len += 1;
+        }
+        Ok(Object::Void)
+    })
+}
+
+/// `(sorted-map-ref map key)` fails when `key` is missing;
+/// `(sorted-map-ref map key default)` returns `default` instead.
+pub fn sorted_map_ref(args: &[Object]) -> Result<Object, EvalError> {
+    let len = args.len().clamp(2, 3);
+    let map = map_arg("sorted-map-ref", args, len)?;
+
+    let value = with_tree(map, |comparator, root, _| {
+        Ok(get(root, &args[1], comparator)?.cloned())
+    })?;
+    match (value, args.get(2)) {
+        (Some(value), _) => Ok(value),
+        (None, Some(default)) => Ok(default.clone()),
+        (None, None) => Err(EvalError::new(format!(
+            "sorted-map-ref: no entry for {}",
+            args[1]
+        ))),
+    }
+}
+
+/// `(sorted-map-delete! map key)` returns whether there was an entry.
+pub fn sorted_map_delete(args: &[Object]) -> Result<Object, EvalError> {
+    let map = map_arg("sorted-map-delete!", args, 2)?;
+
+    with_tree(map, |comparator, root, len| {
+        let removed = remove(root, &args[1], comparator)?;
+        if removed {
+            *len -= 1;
+        }
+        Ok(Object::Bool(removed))
+    })
+}
+
+pub fn sorted_map_size(args: &[Object]) -> Result<Object, EvalError> {
+    let map = map_arg("sorted-map-size", args, 1)?;
+
+    Ok(Object::Integer(map.borrow().len as i64))
+}
+
+/// `(sorted-map->list map)` is the entries as an association list, in key
+/// order.
+pub fn sorted_map_to_list(args: &[Object]) -> Result<Object, EvalError> {
+    let map = map_arg("sorted-map->list", args, 1)?;
+    let entries = map.borrow().entries();
+
+    Ok(Object::list(
+        entries
+            .into_iter()
+            .map(|(key, value)| Object::cons(key, value)),
+    ))
+}
+
+pub fn sorted_map_keys(args: &[Object]) -> Result<Object, EvalError> {
+    let map = map_arg("sorted-map-keys", args, 1)?;
+    let entries = map.borrow().entries();
+
+    Ok(Object::list(entries.into_iter().map(|(key, _)| key)))
+}
+
+fn nearest_key(name: &str, args: &[Object], ceiling: bool) -> Result<Object, EvalError> {
+    let map = map_arg(name, args, 2)?;
+
+    with_tree(map, |comparator, root, _| {
+        let key = nearest(root, &args[1], comparator, ceiling)?;
+        Ok(key.cloned().unwrap_or(Object::Bool(false)))
+    })
+}
+
+/// `(sorted-map-floor-key map key)` is the greatest key at most `key`, or
+/// `#f` if there is none.
+pub fn sorted_map_floor_key(args: &[Object]) -> Result<Object, EvalError> {
+    nearest_key("sorted-map-floor-key", args, false)
+}
+
+/// `(sorted-map-ceiling-key map key)` is the least key at least `key`, or
+/// `#f` if there is none.
+pub fn sorted_map_ceiling_key(args: &[Object]) -> Result<Object, EvalError> {
+    nearest_key("sorted-map-ceiling-key", args, true)
+}
+
+/// `(sorted-map-submap map from to)` is a new map with the entries whose
+/// keys are at least `from` and less than `to`. Either bound may be `#f`
+/// for no bound.
+pub fn sorted_map_submap(args: &[Object]) -> Result<Object, EvalError> {
+    let map = map_arg("sorted-map-submap", args, 3)?;
+    let bound = |obj: &Object| match obj {
+        Object::Bool(false) => None,
+        obj => Some(obj.clone()),
+    };
+    let (from, to) = (bound(&args[1]), bound(&args[2]));
+
+    let mut entries = Vec::new();
+    let comparator = with_tree(map, |comparator, root, _| {
+        range(
+            root,
+            from.as_ref(),
+            to.as_ref(),
+            comparator,
+            &mut |key, value| entries.push((key.clone(), value.clone())),
+        )?;
+        Ok(map.borrow().comparator())
+    })?;
+
+    // The entries are already in order, so building the new tree needs no
+    // comparisons.
+    let submap = new_map(comparator);
+    {
+        let mut submap = submap.borrow_mut();
+        submap.len = entries.len();
+        submap.root = build(&mut entries.into_iter(), submap.len);
+    }
+
+    Ok(Object::SortedMap(submap))
+}
+
+/// A balanced tree of the next `len` entries, which are in key order.
+fn build(entries: &mut impl Iterator<Item = (Object, Object)>, len: usize) -> Link {
+    if len == 0 {
+        return None;
+    }
+
+    let left = build(entries, len / 2);
+    let (key, value) = entries.next()?;
+    let right = build(entries, len - len / 2 - 1);
+
+    Some(Box::new(Node {
+        key,
+        value,
+        height: 1 + height(&left).max(height(&right)),
+        left,
+        right,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builtins::global_env;
+    use crate::eval::eval_str;
+
+    fn check_balanced(link: &Link) -> u32 {
+        let Some(node) = link else {
+            return 0;
+        };
+        let (left, right) = (check_balanced(&node.left), check_balanced(&node.right));
+        assert!(left.abs_diff(right) <= 1);
+        assert_eq!(node.height, 1 + left.max(right));
+        node.height
+    }
+
+    #[test]
+    fn test_stays_balanced() {
+        let comparator = Comparator::default_comparator();
+        let mut root = None;
+        for n in 0..1000 {
+            let key = Object::Integer((n * 7919) % 1000);
+            insert(&mut root, key, Object::Nil, &comparator).unwrap();
+            check_balanced(&root);
+        }
+        for n in (0..1000).step_by(3) {
+            assert!(remove(&mut root, &Object::Integer(n), &comparator).unwrap());
+            check_balanced(&root);
+        }
+        assert!(!remove(&mut root, &Object::Integer(0), &comparator).unwrap());
+
+        let map = SortedMap {
+            comparator: Object::Comparator(Rc::new(comparator)),
+            root,
+            len: 0,
+        };
+        let keys = map.entries().into_iter().map(|(key, _)| key);
+        let expected = (0..1000).filter(|n| n % 3 != 0).map(Object::Integer);
+        assert!(keys.eq(expected));
+    }
+
+    #[test]
+    fn test_sorted_map_procedures() {
+        let env = global_env();
+        let result = eval_str(
+            "(define scores (make-sorted-map))
+             (sorted-map-set! scores 30 'cy)
+             (sorted-map-set! scores 10 'ana)
+             (sorted-map-set! scores 20 'bo)
+             (sorted-map-set! scores 40 'di)
+             (sorted-map-set! scores 20 'ed)
+             (sorted-map-delete! scores 40)
+             (list (sorted-map-size scores)
+                   (sorted-map->list scores)
+                   (sorted-map-ref scores 20)
+                   (sorted-map-ref scores 25 'none)
+                   (sorted-map-floor-key scores 25)
+                   (sorted-map-ceiling-key scores 25)
+                   (sorted-map-floor-key scores 5)
+                   (sorted-map-keys (sorted-map-submap scores 15 30))
+                   (sorted-map-keys (sorted-map-submap scores #f 30)))",
+            &env,
+        )
+        .unwrap();
+
+        assert_eq!(
+            result.to_string(),
+            "(3 ((10 . ana) (20 . ed) (30 . cy)) ed none 20 30 #f (20) (10 20))"
+        );
+        assert!(eval_str("(sorted-map-ref scores 25)", &env).is_err());
+
+        let result = eval_str(
+            "(define names (make-sorted-map (lambda (a b) (> (string-length a) (string-length b)))))
+             (sorted-map-set! names \"ab\" 2)
+             (sorted-map-set! names \"abcd\" 4)
+             (sorted-map-set! names \"a\" 1)
+             (sorted-map-keys names)",
+            &env,
+        )
+        .unwrap();
+        assert_eq!(result.to_string(), "(\"abcd\" \"ab\" \"a\")");
+    }
+}
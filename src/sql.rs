@@ -0,0 +1,335 @@
+//! `sql`, a builder for parameterized SQL queries written as quasiquoted
+//! templates:
+//!
+//! ```text
+//! (sql `(select (id name) from users where id = ,id))
+//! ; => ("select id, name from users where id = ?" 42)
+//! ```
+//!
+//! The result is the text of the query followed by its parameters, the
+//! form a database driver takes them in. The value of each unquoted form is
+//! a parameter, leaving a `?` in the text, so values never become part of
+//! the SQL itself; an unquoted list is a parenthesized list of parameters,
+//! for `in`, and one unquoted with `,@` leaves out the parentheses.
+//!
+//! Symbols are written as they are, and must be names, such as `users.id`,
+//! or operators. Numbers, strings and booleans written in the template are
+//! SQL literals. A nested list is a parenthesized expression with its items
+//! separated by spaces when it has an operator or a keyword among them, as
+//! in `(a = 1 or b = 2)`, and a list with its items separated by commas
+//! otherwise, as in `(a b)`; after `select`, `distinct`, `by`, `returning`
+//! and `set` the parentheses are left out, along with those of expressions
+//! in the list, for `set ((a = 1) (b = 2))`. Two lists in a row, like the
+//! rows after `values`, are separated by a comma. A name and one list, like
+//! `(count (*))`, is a call.
+
+use std::cell::RefCell;
+use std::iter;
+use std::rc::Rc;
+
+use crate::bytecode::is_data;
+use crate::env::Env;
+use crate::eval::{eval, EvalError};
+use crate::object::{self, Object};
+use crate::quasiquote::form_argument;
+
+const OPERATORS: &[&str] = &[
+    "=", "==", "<>", "!=", "<", ">", "<=", ">=", "+", "-", "*", "/", "%", "||",
+];
+
+/// The words that make a list an expression rather than a list of items.
+const KEYWORDS: &[&str] = &[
+    "all",
+    "and",
+    "as",
+    "asc",
+    "between",
+    "by",
+    "case",
+    "delete",
+    "desc",
+    "distinct",
+    "else",
+    "end",
+    "exists",
+    "from",
+    "glob",
+    "group",
+    "having",
+    "in",
+    "insert",
+    "into",
+    "is",
+    "join",
+    "like",
+    "limit",
+    "not",
+    "null",
+    "offset",
+    "on",
+    "or",
+    "order",
+    "returning",
+    "select",
+    "set",
+    "then",
+    "union",
+    "update",
+    "values",
+    "when",
+    "where",
+];
+
+/// The words after which a list is written without parentheses.
+const LIST_KEYWORDS: &[&str] = &["by", "distinct", "returning", "select", "set"];
+
+fn error(message: impl std::fmt::Display) -> EvalError {
+    EvalError::new(format!("sql: {}", message))
+}
+
+fn is_keyword(word: &str) -> bool {
+    OPERATORS.contains(&word) || KEYWORDS.contains(&word.to_ascii_lowercase().as_str())
+}
+
+fn is_name(word: &str) -> bool {
+    let mut chars = word.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '$' | '*'))
+}
+
+fn is_expression(item: &Object) -> bool {
+    match item.to_vec() {
+        Some(items) if matches!(item, Object::Pair(_)) => items
+            .iter()
+            .any(|item| matches!(item, Object::Symbol(word) if is_keyword(word.as_str()))),
+        _ => false,
+    }
+}
+
+struct Query<'a> {
+    text: String,
+    params: Vec<Object>,
+    env: &'a Rc<RefCell<Env>>,
+}
+
+impl Query<'_> {
+    fn param(&mut self, value: Object) -> Result<(), EvalError> {
+        if !is_data(&value) {
+            return Err(error(format!(
+                "{} cannot be a parameter",
+                object::with_article(value.type_name())
+            )));
+        }
+        self.text.push('?');
+        self.params.push(value);
+        Ok(())
+    }
+
+    fn params(&mut self, values: Vec<Object>, bare: bool) -> Result<(), EvalError> {
+        if !bare {
+            self.text.push('(');
+        }
+        for (i, value) in values.into_iter().enumerate() {
+            if i > 0 {
+                self.text.push_str(", ");
+            }
+            self.param(value)?;
+        }
+        if !bare {
+            self.text.push(')');
+        }
+        Ok(())
+    }
+
+    fn sequence(&mut self, items: &[Object]) -> Result<(), EvalError> {
+        // The item before, and whether it was a parenthesized list.
+        let mut previous: Option<(&Object, bool)> = None;
+        for item in items {
+            let at = self.text.len();
+            let bare = match previous {
+                Some((word, _)) => {
+                    self.text.push(' ');
+                    matches!(word, Object::Symbol(word)
+                        if LIST_KEYWORDS.contains(&word.to_ascii_lowercase().as_str()))
+                }
+                None => false,
+            };
+            let list = self.item(item, bare)?;
+            if list && matches!(previous, Some((_, true))) {
+                self.text.insert(at, ',');
+            }
+            previous = Some((item, list));
+        }
+        Ok(())
+    }
+
+    /// Writes `item`, returning whether it is a parenthesized list.
+    fn item(&mut self, item: &Object, bare: bool) -> Result<bool, EvalError> {
+        if let Some(form) = form_argument(item, "unquote") {
+            let value = eval(&form, self.env)?;
+            return match value {
+                Object::Pair(_) | Object::Nil => {
+                    let values = value
+                        .to_vec()
+                        .ok_or_else(|| error(format!("cannot pass improper list {}", value)))?;
+                    self.params(values, bare)?;
+                    Ok(!bare)
+                }
+                value => self.param(value).map(|()| false),
+            };
+        }
+        if let Some(form) = form_argument(item, "unquote-splicing") {
+            let value = eval(&form, self.env)?;
+            let values = value
+                .to_vec()
+                .ok_or_else(|| error(format!("unquote-splicing expects a list, got {}", value)))?;
+            return self.params(values, true).map(|()| false);
+        }
+
+        match item {
+            Object::Symbol(word) if is_name(word.as_str()) || is_keyword(word.as_str()) => {
+                self.text.push_str(word.as_str())
+            }
+            Object::Symbol(word) => return Err(error(format!("{} is not a name", word))),
+            Object::Integer(_) | Object::BigInt(_) | Object::Float(_) => {
+                self.text.push_str(&item.to_string())
+            }
+            Object::String(s) => {
+                self.text.push('\'');
+                self.text.push_str(&s.replace('\'', "''"));
+                self.text.push('\'');
+            }
+            Object::Bool(b) => self.text.push_str(if *b { "TRUE" } else { "FALSE" }),
+            Object::Nil => {
+                self.text.push_str("()");
+                return Ok(true);
+            }
+            Object::Pair(_) => {
+                let items = item
+                    .to_vec()
+                    .ok_or_else(|| error(format!("cannot write improper list {}", item)))?;
+                return self.list(&items, bare);
+            }
+            other => return Err(error(format!("cannot write a {}", other.type_name()))),
+        }
+        Ok(false)
+    }
+
+    fn list(&mut self, items: &[Object], bare: bool) -> Result<bool, EvalError> {
+        if let [Object::Symbol(name), args @ Object::Pair(_)] = items {
+            if !is_keyword(name.as_str())
+                && form_argument(args, "unquote").is_none()
+                && form_argument(args, "unquote-splicing").is_none()
+            {
+                self.item(&items[0], false)?;
+                self.text.push('(');
+                self.item(args, true)?;
+                self.text.push(')');
+                return Ok(false);
+            }
+        }
+
+        if !bare {
+            self.text.push('(');
+        }
+        let expression = items
+            .iter()
+            .any(|item| matches!(item, Object::Symbol(word) if is_keyword(word.as_str())));
+        if expression {
+            self.sequence(items)?;
+        } else {
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    self.text.push_str(", ");
+                }
+                self.item(item, bare && is_expression(item))?;
+            }
+        }
+        if !bare {
+            self.text.push(')');
+        }
+        Ok(!bare && !expression)
+    }
+}
+
+/// `(sql `(statement ...))` is the list of the statement's text and its
+/// parameters.
+pub fn sql(list: &[Object], env: &Rc<RefCell<Env>>) -> Result<Object, EvalError> {
+    let template = match list {
+        [_, form] => form_argument(form, "quasiquote").or_else(|| form_argument(form, "quote")),
+        _ => None,
+    };
+    let items = template
+        .filter(|template| matches!(template, Object::Pair(_)))
+        .and_then(|template| template.to_vec())
+        .ok_or_else(|| EvalError::new("sql expects a quasiquoted statement"))?;
+
+    let mut query = Query {
+        text: String::new(),
+        params: Vec::new(),
+        env,
+    };
+    query.sequence(&items)?;
+
+    Ok(Object::list(
+        iter::once(Object::string(query.text)).chain(query.params),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builtins::global_env;
+    use crate::eval::eval_str;
+
+    fn sql(program: &str) -> String {
+        let program = format!(
+            "(define id 42) (define ids '(1 2 3)) (define name \"o'brien\") {}",
+            program
+        );
+        match eval_str(&program, &global_env()) {
+            Ok(value) => value.to_string(),
+            Err(e) => e.message().to_string(),
+        }
+    }
+
+    #[test]
+    fn test_queries() {
+        assert_eq!(
+            sql("(sql `(select * from users where id = ,id))"),
+            "(\"select * from users where id = ?\" 42)"
+        );
+        assert_eq!(
+            sql("(sql `(select ((count (*)) (max (id))) from users
+                        where (id in ,ids or name like \"a%\") and admin = #t
+                        group by (id name) limit 10))"),
+            "(\"select count(*), max(id) from users \
+             where (id in (?, ?, ?) or name like 'a%') and admin = TRUE \
+             group by id, name limit 10\" 1 2 3)"
+        );
+        assert_eq!(
+            sql("(sql `(insert into users (id name) values (,id ,name) (7 \"it's\")))"),
+            "(\"insert into users (id, name) values (?, ?), (7, 'it''s')\" 42 \"o'brien\")"
+        );
+        assert_eq!(
+            sql("(sql `(update users set ((name = ,name) (id = (id + 1)))
+                        where id in (,@ids)))"),
+            "(\"update users set name = ?, id = (id + 1) where id in (?, ?, ?)\" \"o'brien\" 1 2 3)"
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            sql("(sql '(select * from user-table))"),
+            "sql: user-table is not a name"
+        );
+        assert_eq!(
+            sql("(sql `(select * from t where f = ,car))"),
+            "sql: a procedure cannot be a parameter"
+        );
+        assert_eq!(
+            sql("(sql \"select\")"),
+            "sql expects a quasiquoted statement"
+        );
+    }
+}
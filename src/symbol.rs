@@ -0,0 +1,266 @@
+use alloc::borrow::ToOwned;
+use alloc::boxed::Box;
+use core::fmt;
+use core::fmt::Formatter;
+use core::hash::{BuildHasherDefault, Hash, Hasher};
+use core::ops::Deref;
+use core::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+
+/// An interned symbol name.
+///
+/// Every distinct name is allocated once, for the lifetime of the process,
+/// and all symbols with that name point at the same allocation. Comparing
+/// or hashing symbols therefore only looks at the pointer, which keeps
+/// environment lookups and `eq`-style comparisons cheap.
+///
+/// An uninterned symbol shares the interned name it was made with, as a
+/// prefix, and tells itself apart by a number of its own, so that making
+/// one allocates nothing.
+#[derive(Clone, Copy)]
+pub struct Symbol {
+    name: &'static str,
+    /// 0 for interned symbols.
+    id: usize,
+}
+
+static UNINTERNED: AtomicUsize = AtomicUsize::new(0);
+
+/// The most bytes of names `try_intern` lets the table hold. Interned names
+/// are never freed, so the symbols read from input, such as that of a
+/// `serve` client, would otherwise grow the process without bound; once
+/// the table is this big, reading a name it does not hold fails.
+pub const MAX_INTERNED_BYTES: usize = 64 * 1024 * 1024;
+
+/// The bytes of names in the table, only changed while it is locked.
+static INTERNED_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+#[cfg(feature = "std")]
+fn with_table<R>(f: impl FnOnce(&mut std::collections::HashSet<&'static str>) -> R) -> R {
+    use std::collections::HashSet;
+    use std::sync::{Mutex, OnceLock};
+
+    static TABLE: OnceLock<Mutex<HashSet<&'static str>>> = OnceLock::new();
+    let mut table = TABLE
+        .get_or_init(|| Mutex::new(HashSet::new()))
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+
+    f(&mut table)
+}
+
+/// Without `std` there is no `Mutex`, so the table is guarded by a spin
+/// lock. It is only held to look up or insert one name.
+#[cfg(not(feature = "std"))]
+fn with_table<R>(f: impl FnOnce(&mut alloc::collections::BTreeSet<&'static str>) -> R) -> R {
+    use alloc::collections::BTreeSet;
+    use core::cell::UnsafeCell;
+    use core::sync::atomic::{AtomicBool, Ordering};
+
+    struct Table {
+        locked: AtomicBool,
+        names: UnsafeCell<BTreeSet<&'static str>>,
+    }
+
+    // Safety: `names` is only accessed while `locked` is held.
+    unsafe impl Sync for Table {}
+
+    static TABLE: Table = Table {
+        locked: AtomicBool::new(false),
+        names: UnsafeCell::new(BTreeSet::new()),
+    };
+
+    while TABLE
+        .locked
+        .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+        .is_err()
+    {
+        core::hint::spin_loop();
+    }
+    // Safety: the lock is held until after `f` returns.
+    let result = f(unsafe { &mut *TABLE.names.get() });
+    TABLE.locked.store(false, Ordering::Release);
+
+    result
+}
+
+impl Symbol {
+    pub fn intern(name: &str) -> Self {
+        match Symbol::intern_within(name, usize::MAX) {
+            Some(symbol) => symbol,
+            None => unreachable!("the table has no limit"),
+        }
+    }
+
+    /// The symbol named `name`, unless adding it would make the table
+    /// bigger than `MAX_INTERNED_BYTES`: for names read from input.
+    pub fn try_intern(name: &str) -> Option<Self> {
+        Symbol::intern_within(name, MAX_INTERNED_BYTES)
+    }
+
+    fn intern_within(name: &str, max_bytes: usize) -> Option<Self> {
+        with_table(|table| match table.get(name) {
+            Some(&interned) => Some(Symbol::named(interned)),
+            None => {
+                let bytes = INTERNED_BYTES.load(AtomicOrdering::Relaxed) + name.len();
+                if bytes > max_bytes {
+                    return None;
+                }
+                INTERNED_BYTES.store(bytes, AtomicOrdering::Relaxed);
+                let interned: &'static str = Box::leak(name.to_owned().into_boxed_str());
+                table.insert(interned);
+                Some(Symbol::named(interned))
+            }
+        })
+    }
+
+    fn named(name: &'static str) -> Self {
+        Symbol { name, id: 0 }
+    }
+
+    /// A symbol different from every other, including those read as its
+    /// name, which is `prefix` followed by its number.
+    pub fn uninterned(prefix: &str) -> Self {
+        let id = UNINTERNED.fetch_add(1, AtomicOrdering::Relaxed) + 1;
+        Symbol {
+            name: Symbol::intern(prefix).name,
+            id,
+        }
+    }
+
+    pub fn is_interned(&self) -> bool {
+        self.id == 0
+    }
+
+    /// The name special forms are looked up by: that of an interned symbol,
+    /// and none for an uninterned one.
+    pub fn form_name(&self) -> Option<&'static str> {
+        self.is_interned().then_some(self.name)
+    }
+
+    /// The name of an interned symbol, or the prefix of an uninterned one,
+    /// whose full name only `Display` writes.
+    pub fn as_str(&self) -> &'static str {
+        self.name
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(name: &str) -> Self {
+        Symbol::intern(name)
+    }
+}
+
+impl Deref for Symbol {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.name
+    }
+}
+
+impl PartialEq for Symbol {
+    fn eq(&self, other: &Self) -> bool {
+        core::ptr::eq(self.name, other.name) && self.id == other.id
+    }
+}
+
+impl Eq for Symbol {}
+
+/// Only an interned symbol is equal to its name, so that no uninterned one
+/// is taken for a special form or a keyword.
+impl PartialEq<str> for Symbol {
+    fn eq(&self, other: &str) -> bool {
+        self.is_interned() && self.name == other
+    }
+}
+
+impl PartialEq<&str> for Symbol {
+    fn eq(&self, other: &&str) -> bool {
+        *self == **other
+    }
+}
+
+impl Hash for Symbol {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.name.as_ptr().hash(state);
+        if !self.is_interned() {
+            self.id.hash(state);
+        }
+    }
+}
+
+/// A hasher for maps keyed by symbols. Symbols hash as a single pointer, so
+/// mixing its bits is enough and much cheaper than the default SipHash.
+#[derive(Default)]
+pub struct SymbolHasher(u64);
+
+impl Hasher for SymbolHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.write_u64(u64::from(byte));
+        }
+    }
+
+    fn write_u64(&mut self, n: u64) {
+        self.0 = (self.0.rotate_left(5) ^ n).wrapping_mul(0x517c_c1b7_2722_0a95);
+    }
+
+    fn write_usize(&mut self, n: usize) {
+        self.write_u64(n as u64);
+    }
+}
+
+pub type BuildSymbolHasher = BuildHasherDefault<SymbolHasher>;
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.id {
+            0 => write!(f, "{}", self.name),
+            id => write!(f, "{}{}", self.name, id),
+        }
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interning_shares_names() {
+        let a = Symbol::intern("lambda");
+        let b = Symbol::intern(&String::from("lambda"));
+
+        assert_eq!(a, b);
+        assert!(std::ptr::eq(a.as_str(), b.as_str()));
+        assert_ne!(a, Symbol::intern("define"));
+        assert_eq!(a, "lambda");
+
+        let uninterned = Symbol::uninterned("lambda");
+        assert_ne!(uninterned, a);
+        assert_ne!(uninterned, Symbol::uninterned("lambda"));
+        assert_ne!(uninterned, "lambda");
+        assert!(std::ptr::eq(uninterned.as_str(), a.as_str()));
+        assert_eq!(uninterned.to_string(), format!("lambda{}", uninterned.id));
+    }
+
+    #[test]
+    fn test_interning_within_a_limit() {
+        let full = INTERNED_BYTES.load(AtomicOrdering::Relaxed);
+        assert!(Symbol::intern_within("a name no other test interns", full).is_none());
+        assert_eq!(
+            Symbol::intern_within("lambda", 0),
+            Some(Symbol::intern("lambda"))
+        );
+        assert_eq!(Symbol::try_intern("define"), Some(Symbol::intern("define")));
+    }
+}
@@ -0,0 +1,295 @@
+//! Taint tracking, an opt-in audit mode for hosts running code they do not
+//! trust with input they do not trust either.
+//!
+//! While tracking is enabled, strings from untrusted sources are marked as
+//! tainted: the command line, environment variables, the output of `pipe`
+//! and `process-run`, the lines read from sockets, and anything a host
+//! passes to `Interpreter::define_untrusted`. The mark survives
+//! `substring`, `string-copy`, `string-append`, `json-parse` and
+//! `json-stringify`, `msg` and `render-template` with a tainted value, and
+//! a tainted string reaching a sensitive sink (an argument to `pipe`,
+//! `system`, `process-run` or `setenv`, a host given to `tcp-connect`, or a
+//! path given to `load`, `require` or `kv-open`) is an error. `(untaint s)`
+//! is the explicit way to declare a string checked.
+//!
+//! Characters carry no mark, so once a tainted string has been taken apart
+//! with `string->list` or `string-ref`, every string `list->string` builds
+//! is tainted, whichever characters it is given. Code read from untrusted
+//! input, by `read` or by `read-string` from a tainted string, is refused
+//! by `eval`, as are the lists in it.
+
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::rc::{Rc, Weak};
+
+use crate::collections::{hash_table_object, HashTable};
+use crate::eval::EvalError;
+use crate::object::{Object, Pair};
+use crate::text::Str;
+
+thread_local! {
+    static ENABLED: Cell<bool> = const { Cell::new(false) };
+    /// Whether a tainted string has been taken apart into characters.
+    static TAKEN_APART: Cell<bool> = const { Cell::new(false) };
+    /// The pairs of the data read from untrusted input, by address.
+    static UNTRUSTED: RefCell<HashMap<usize, Weak<Pair>>> = RefCell::new(HashMap::new());
+}
+
+pub fn enable(enabled: bool) {
+    ENABLED.with(|cell| cell.set(enabled));
+    TAKEN_APART.with(|cell| cell.set(false));
+    UNTRUSTED.with(|untrusted| untrusted.borrow_mut().clear());
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.with(Cell::get)
+}
+
+/// Marks the strings in `obj`, including those in its lists and hash
+/// tables, as tainted. Does nothing unless tracking is enabled.
+pub fn mark(obj: Object) -> Object {
+    if !is_enabled() {
+        return obj;
+    }
+
+    taint_all(obj)
+}
+
+/// Marks the strings in `obj` whether or not tracking is enabled, for
+/// values derived from tainted ones.
+pub(crate) fn taint_all(obj: Object) -> Object {
+    match obj {
+        Object::String(s) => Object::String(s.with_taint(true)),
+        Object::Pair(_) => match obj.to_vec() {
+            Some(items) => Object::list(items.into_iter().map(taint_all).collect::<Vec<_>>()),
+            None => obj,
+        },
+        Object::HashTable(table) => {
+            let mut marked = HashTable::default();
+            for (key, value) in table.borrow().iter() {
+                marked.insert(taint_all(key.clone()), taint_all(value.clone()));
+            }
+            hash_table_object(marked)
+        }
+        other => other,
+    }
+}
+
+/// Whether `obj` is a tainted string or has one in its lists or hash
+/// tables.
+pub(crate) fn contains(obj: &Object) -> bool {
+    let mut pending = vec![obj.clone()];
+    let mut seen = HashSet::new();
+    while let Some(obj) = pending.pop() {
+        match obj {
+            Object::String(s) if s.is_tainted() => return true,
+            Object::Pair(pair) if seen.insert(Rc::as_ptr(&pair) as usize) => {
+                pending.push(pair.car());
+                pending.push(pair.cdr());
+            }
+            Object::HashTable(table) if seen.insert(Rc::as_ptr(&table) as *const u8 as usize) => {
+                for (key, value) in table.borrow().iter() {
+                    pending.push(key.clone());
+                    pending.push(value.clone());
+                }
+            }
+            _ => {}
+        }
+    }
+
+    false
+}
+
+/// `s`, marked as tainted if `tainted` is.
+pub(crate) fn string(s: impl Into<Str>, tainted: bool) -> Object {
+    Object::String(s.into().with_taint(tainted))
+}
+
+/// Notes that `s` is being taken apart into characters.
+pub(crate) fn take_apart(s: &Str) {
+    if s.is_tainted() && is_enabled() {
+        TAKEN_APART.with(|cell| cell.set(true));
+    }
+}
+
+/// Whether strings built from characters are tainted.
+pub(crate) fn characters_tainted() -> bool {
+    TAKEN_APART.with(Cell::get)
+}
+
+/// Marks the lists of `datum`, read from untrusted input, as code `eval`
+/// refuses. Does nothing unless tracking is enabled.
+pub(crate) fn mark_read(datum: Object) -> Object {
+    if !is_enabled() {
+        return datum;
+    }
+
+    UNTRUSTED.with(|untrusted| {
+        let mut untrusted = untrusted.borrow_mut();
+        if untrusted.len() >= 4096 {
+            untrusted.retain(|_, pair| pair.strong_count() > 0);
+        }
+        let mut pending = vec![datum.clone()];
+        while let Some(Object::Pair(pair)) = pending.pop() {
+            let address = Rc::as_ptr(&pair) as usize;
+            if untrusted.insert(address, Rc::downgrade(&pair)).is_none() {
+                pending.push(pair.car());
+                pending.push(pair.cdr());
+            }
+        }
+    });
+
+    datum
+}
+
+/// Fails if tracking is enabled and `form` is, or has a list that was, read
+/// from untrusted input.
+pub(crate) fn check_eval(form: &Object) -> Result<(), EvalError> {
+    if !is_enabled() {
+        return Ok(());
+    }
+
+    UNTRUSTED.with(|untrusted| {
+        let untrusted = untrusted.borrow();
+        let mut pending = vec![form.clone()];
+        let mut seen = HashSet::new();
+        while let Some(obj) = pending.pop() {
+            let Object::Pair(pair) = obj else { continue };
+            let address = Rc::as_ptr(&pair) as usize;
+            // A weak reference keeps the address from being reused.
+            if untrusted
+                .get(&address)
+                .is_some_and(|read| read.strong_count() > 0)
+            {
+                return Err(EvalError::new(format!(
+                    "eval refuses {}, read from untrusted input; untaint the text after checking it",
+                    form
+                )));
+            }
+            if seen.insert(address) {
+                pending.push(pair.car());
+                pending.push(pair.cdr());
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Fails if tracking is enabled and `obj` is a tainted string about to be
+/// used by `sink`.
+pub(crate) fn check(sink: &str, obj: &Object) -> Result<(), EvalError> {
+    match obj {
+        Object::String(s) if s.is_tainted() && is_enabled() => Err(EvalError::new(format!(
+            "{} refuses the tainted string {:?}; use untaint after checking it",
+            sink,
+            s.as_str()
+        ))),
+        _ => Ok(()),
+    }
+}
+
+pub fn is_tainted(args: &[Object]) -> Result<Object, EvalError> {
+    match args {
+        [obj] => Ok(Object::Bool(
+            matches!(obj, Object::String(s) if s.is_tainted()),
+        )),
+        _ => Err(EvalError::new("tainted? expects one argument")),
+    }
+}
+
+/// `(taint s)` marks a string as untrusted, whether or not tracking is
+/// enabled, so that programs can test their own sanitizing.
+pub fn taint(args: &[Object]) -> Result<Object, EvalError> {
+    match args {
+        [Object::String(s)] => Ok(Object::String(s.clone().with_taint(true))),
+        _ => Err(EvalError::new("taint expects a string")),
+    }
+}
+
+pub fn untaint(args: &[Object]) -> Result<Object, EvalError> {
+    match args {
+        [Object::String(s)] => Ok(Object::String(s.clone().with_taint(false))),
+        _ => Err(EvalError::new("untaint expects a string")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builtins::global_env;
+    use crate::eval::eval_str;
+
+    #[test]
+    fn test_taint_propagates_to_sinks() {
+        enable(true);
+        let env = global_env();
+        env.borrow_mut()
+            .define("input", mark(Object::list([Object::string("x.lisp")])));
+
+        let result = eval_str(
+            "(define path (string-append \"dir/\" (substring (car input) 0 1)))
+             (list (tainted? (car input)) (tainted? path) (tainted? (untaint path)) (tainted? \"x\"))",
+            &env,
+        );
+        assert_eq!(result.unwrap().to_string(), "(#t #t #f #f)");
+
+        let err = eval_str("(load path)", &env).unwrap_err();
+        assert!(err.to_string().contains("refuses the tainted string"));
+        let err = eval_str("(load (untaint path))", &env).unwrap_err();
+        assert!(!err.to_string().contains("tainted"));
+
+        // Strings keep their mark, but sinks stop checking.
+        enable(false);
+        assert_eq!(
+            eval_str("(tainted? (car input))", &env).unwrap(),
+            Object::Bool(true)
+        );
+        let err = eval_str("(load path)", &env).unwrap_err();
+        assert!(!err.to_string().contains("tainted"));
+    }
+
+    #[test]
+    fn test_taint_survives_rebuilding() {
+        enable(true);
+        let env = global_env();
+        env.borrow_mut()
+            .define("input", mark(Object::list([Object::string("(+ 1 2)")])));
+
+        let err = eval_str("(eval (read-string (car input)))", &env).unwrap_err();
+        assert!(err.to_string().contains("eval refuses (+ 1 2)"));
+        let err = eval_str(
+            "(eval (list 'car (list 'quote (read-string (car input)))))",
+            &env,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("untrusted input"));
+        assert_eq!(
+            eval_str("(eval (read-string (untaint (car input))))", &env).unwrap(),
+            Object::Integer(3)
+        );
+
+        let result = eval_str(
+            "(list (tainted? (json-stringify (list 1 (car input))))
+                   (tainted? (json-parse (json-stringify (car input))))
+                   (tainted? (json-stringify (list 1 \"x\")))
+                   (tainted? (list->string (string->list (car input)))))",
+            &env,
+        );
+        assert_eq!(result.unwrap().to_string(), "(#t #t #f #t)");
+
+        eval_str("(define-messages 'en 'run \"run ~a\")", &env).unwrap();
+        eval_str("(set-locale! 'en)", &env).unwrap();
+        assert_eq!(
+            eval_str("(tainted? (msg 'run (car input)))", &env).unwrap(),
+            Object::Bool(true)
+        );
+
+        // Enabling tracking again forgets what was taken apart.
+        enable(true);
+        assert_eq!(
+            eval_str("(tainted? (list->string (list #\\a)))", &env).unwrap(),
+            Object::Bool(false)
+        );
+        enable(false);
+    }
+}
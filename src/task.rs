@@ -0,0 +1,204 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::env::Env;
+use crate::eval::{apply, eval, EvalError};
+use crate::gc::{self, Edge, Trace};
+use crate::object::Object;
+
+/// A unit of work started with `spawn`. Tasks are scheduled cooperatively:
+/// a pending task runs when it is joined, or when the scope that owns it
+/// exits.
+pub struct Task {
+    thunk: Object,
+    state: RefCell<TaskState>,
+}
+
+#[derive(Clone)]
+enum TaskState {
+    Pending,
+    Running,
+    Done(Object),
+    Failed(String),
+    Cancelled,
+}
+
+thread_local! {
+    static SCOPES: RefCell<Vec<Vec<Rc<Task>>>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Evaluates `body` inside a new task scope. Every task spawned in the scope
+/// is joined before the scope returns; if the body or any task fails, the
+/// tasks that have not run yet are cancelled and the error is propagated.
+pub fn with_scope(body: &[Object], env: &Rc<RefCell<Env>>) -> Result<Object, EvalError> {
+    SCOPES.with(|scopes| scopes.borrow_mut().push(Vec::new()));
+
+    let result = body
+        .iter()
+        .try_fold(Object::Void, |_, form| eval(form, env))
+        .and_then(|result| join_scope().map(|_| result));
+
+    if result.is_err() {
+        cancel_scope();
+    }
+    SCOPES.with(|scopes| scopes.borrow_mut().pop());
+
+    result
+}
+
+pub fn spawn(args: &[Object]) -> Result<Object, EvalError> {
+    let thunk = match args {
+        [thunk @ (Object::Lambda(_)
+        | Object::Builtin(_)
+        | Object::Native(_)
+        | Object::Memo(_)
+        | Object::Closure(_))] => thunk.clone(),
+        _ => return Err(EvalError::new("spawn expects a procedure of no arguments")),
+    };
+
+    let task = Rc::new(Task {
+        thunk,
+        state: RefCell::new(TaskState::Pending),
+    });
+
+    gc::track(&task);
+
+    SCOPES.with(|scopes| match scopes.borrow_mut().last_mut() {
+        Some(scope) => {
+            scope.push(task.clone());
+            Ok(Object::Task(task))
+        }
+        None => Err(EvalError::new("spawn used outside of with-task-scope")),
+    })
+}
+
+pub fn join(args: &[Object]) -> Result<Object, EvalError> {
+    match args {
+        [Object::Task(task)] => run(task),
+        _ => Err(EvalError::new("join expects a task")),
+    }
+}
+
+impl Task {
+    pub(crate) fn clear_state(&self) {
+        if let Ok(mut state) = self.state.try_borrow_mut() {
+            *state = TaskState::Cancelled;
+        }
+    }
+}
+
+impl Trace for Task {
+    fn trace(&self, edge: &mut dyn FnMut(Edge)) {
+        edge(Edge::Object(&self.thunk));
+        if let Ok(state) = self.state.try_borrow() {
+            if let TaskState::Done(value) = &*state {
+                edge(Edge::Object(value));
+            }
+        }
+    }
+}
+
+fn run(task: &Task) -> Result<Object, EvalError> {
+    let state = task.state.borrow().clone();
+
+    match state {
+        TaskState::Pending => {
+            *task.state.borrow_mut() = TaskState::Running;
+            let result = apply(&task.thunk, Vec::new());
+            *task.state.borrow_mut() = match &result {
+                Ok(value) => TaskState::Done(value.clone()),
+                Err(e) => TaskState::Failed(e.to_string()),
+            };
+            result
+        }
+        TaskState::Running => Err(EvalError::new("task joined itself")),
+        TaskState::Done(value) => Ok(value),
+        TaskState::Failed(err) => Err(EvalError::new(format!("task failed: {}", err))),
+        TaskState::Cancelled => Err(EvalError::new("task was cancelled")),
+    }
+}
+
+/// Runs the pending tasks of the innermost scope in spawn order, including
+/// tasks spawned by those tasks while the scope drains.
+fn join_scope() -> Result<(), EvalError> {
+    let mut next = 0;
+
+    loop {
+        let task = SCOPES.with(|scopes| scopes.borrow().last().and_then(|s| s.get(next).cloned()));
+        match task {
+            Some(task) => run(&task)?,
+            None => return Ok(()),
+        };
+        next += 1;
+    }
+}
+
+fn cancel_scope() {
+    SCOPES.with(|scopes| {
+        for task in scopes.borrow().last().into_iter().flatten() {
+            let mut state = task.state.borrow_mut();
+            if matches!(*state, TaskState::Pending) {
+                *state = TaskState::Cancelled;
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builtins::global_env;
+    use crate::eval::eval_str;
+    use crate::object::Object;
+
+    #[test]
+    fn test_scope_joins_tasks_on_exit() {
+        let env = global_env();
+        let result = eval_str(
+            "(define log '())
+             (with-task-scope
+               (spawn (lambda () (set! log (cons 'a log))))
+               (spawn (lambda () (set! log (cons 'b log))))
+               'body-done)
+             log",
+            &env,
+        )
+        .unwrap();
+
+        assert_eq!(result.to_string(), "(b a)");
+    }
+
+    #[test]
+    fn test_join_returns_task_result() {
+        let env = global_env();
+        let result = eval_str(
+            "(with-task-scope
+               (define t (spawn (lambda () (* 6 7))))
+               (join t))",
+            &env,
+        )
+        .unwrap();
+
+        assert_eq!(result, Object::Integer(42));
+    }
+
+    #[test]
+    fn test_error_cancels_pending_tasks() {
+        let env = global_env();
+        let result = eval_str(
+            "(define ran #f)
+             (define t #f)
+             (with-task-scope
+               (set! t (spawn (lambda () (set! ran #t))))
+               (car '()))",
+            &env,
+        );
+
+        assert!(result.is_err());
+        assert_eq!(eval_str("ran", &env).unwrap(), Object::Bool(false));
+        assert!(eval_str("(join t)", &env)
+            .unwrap_err()
+            .to_string()
+            .contains("cancelled"));
+        assert!(eval_str("(spawn (lambda () 1))", &env).is_err());
+    }
+}
@@ -0,0 +1,303 @@
+//! `render-template`, a small Mustache-like template engine for scripts
+//! that write reports or generate code.
+//!
+//! `{{name}}` is replaced by the value of `name`, with strings, characters
+//! and symbols inserted as their text and other values as they print, and
+//! nothing for a missing one. Nothing is escaped. Values are looked up in a
+//! context, an association list or a hash table with symbol or string
+//! keys, and `{{user.name}}` looks up `name` in the value of `user`.
+//!
+//! `{{#name}}...{{/name}}` is a section, left out when `name` is missing,
+//! `#f` or the empty list. A list other than an association list repeats
+//! it once per item, and any other value renders it once; inside, names
+//! are looked up in that item or value first, and `{{.}}` is the value
+//! itself. `{{^name}}...{{/name}}` is rendered only when the section would
+//! be left out. `{{! ...}}` is a comment. A section tag or a comment alone
+//! on its line removes the whole line from the output.
+
+use std::mem;
+
+use crate::eval::EvalError;
+use crate::object::Object;
+use crate::text::Str;
+
+enum Node {
+    Text(String),
+    Value(String),
+    Section {
+        name: String,
+        inverted: bool,
+        body: Vec<Node>,
+    },
+}
+
+fn error(message: impl std::fmt::Display) -> EvalError {
+    EvalError::new(format!("render-template: {}", message))
+}
+
+fn flush(text: &mut String, nodes: &mut Vec<Node>) {
+    if !text.is_empty() {
+        nodes.push(Node::Text(mem::take(text)));
+    }
+}
+
+fn parse(template: &str) -> Result<Vec<Node>, EvalError> {
+    let mut nodes = Vec::new();
+    // The open sections, with the nodes of the section around each.
+    let mut sections: Vec<(String, bool, Vec<Node>)> = Vec::new();
+    let mut text = String::new();
+    let mut pos = 0;
+
+    while let Some(open) = template[pos..].find("{{") {
+        let start = pos + open;
+        let close = template[start + 2..]
+            .find("}}")
+            .map(|close| start + 2 + close)
+            .ok_or_else(|| error("unclosed tag"))?;
+        let tag = template[start + 2..close].trim();
+        let mut end = close + 2;
+        text.push_str(&template[pos..start]);
+
+        let (kind, name) = match tag.chars().next() {
+            Some(kind @ ('#' | '^' | '/' | '!')) => (Some(kind), tag[1..].trim()),
+            _ => (None, tag),
+        };
+        if kind.is_some() {
+            // Standalone tags take their line with them, unless another tag
+            // is on it.
+            let line_start = template[..start].rfind('\n').map_or(0, |i| i + 1);
+            let line_end = template[end..]
+                .find('\n')
+                .map_or(template.len(), |i| end + i + 1);
+            let before = &template[line_start..start];
+            if line_start >= pos
+                && before.trim().is_empty()
+                && template[end..line_end].trim().is_empty()
+            {
+                text.truncate(text.len() - before.len());
+                end = line_end;
+            }
+        }
+
+        match kind {
+            Some('!') => {}
+            Some(kind @ ('#' | '^')) => {
+                flush(&mut text, &mut nodes);
+                sections.push((name.to_string(), kind == '^', mem::take(&mut nodes)));
+            }
+            Some(_) => {
+                flush(&mut text, &mut nodes);
+                let (open, inverted, outer) = sections
+                    .pop()
+                    .ok_or_else(|| error(format!("{{{{/{}}}}} closes no section", name)))?;
+                if open != name {
+                    return Err(error(format!("{{{{/{}}}}} closes section {}", name, open)));
+                }
+                let body = mem::replace(&mut nodes, outer);
+                nodes.push(Node::Section {
+                    name: open,
+                    inverted,
+                    body,
+                });
+            }
+            None => {
+                flush(&mut text, &mut nodes);
+                nodes.push(Node::Value(name.to_string()));
+            }
+        }
+        pos = end;
+    }
+
+    text.push_str(&template[pos..]);
+    flush(&mut text, &mut nodes);
+    match sections.pop() {
+        Some((name, _, _)) => Err(error(format!("unclosed section {}", name))),
+        None => Ok(nodes),
+    }
+}
+
+fn is_key(obj: &Object, key: &str) -> bool {
+    match obj {
+        Object::Symbol(name) => name.as_str() == key,
+        Object::String(s) => s.as_str() == key,
+        _ => false,
+    }
+}
+
+/// Whether `list` is an association list, and so a context rather than
+/// items to repeat a section for.
+fn is_alist(list: &Object) -> bool {
+    match list.to_vec() {
+        Some(entries) => entries.iter().all(|entry| match entry {
+            Object::Pair(pair) => matches!(pair.car(), Object::Symbol(_) | Object::String(_)),
+            _ => false,
+        }),
+        None => false,
+    }
+}
+
+fn field(context: &Object, key: &str) -> Option<Object> {
+    match context {
+        Object::HashTable(table) => {
+            let table = table.borrow();
+            table
+                .get(&Object::symbol(key))
+                .or_else(|| table.get(&Object::string(key)))
+                .cloned()
+        }
+        Object::Pair(_) => context.to_vec()?.into_iter().find_map(|entry| match entry {
+            Object::Pair(pair) if is_key(&pair.car(), key) => Some(pair.cdr()),
+            _ => None,
+        }),
+        _ => None,
+    }
+}
+
+fn lookup(contexts: &[Object], name: &str) -> Option<Object> {
+    if name == "." {
+        return contexts.last().cloned();
+    }
+
+    let mut keys = name.split('.');
+    let first = keys.next()?;
+    let value = contexts
+        .iter()
+        .rev()
+        .find_map(|context| field(context, first))?;
+    keys.try_fold(value, |value, key| field(&value, key))
+}
+
+struct Output {
+    text: String,
+    tainted: bool,
+}
+
+impl Output {
+    fn insert(&mut self, value: &Object) {
+        match value {
+            Object::String(s) => {
+                self.text.push_str(s);
+                self.tainted |= s.is_tainted();
+            }
+            Object::Char(c) => self.text.push(*c),
+            Object::Symbol(name) => self.text.push_str(name.as_str()),
+            Object::Void => {}
+            other => self.text.push_str(&other.to_string()),
+        }
+    }
+}
+
+fn render(nodes: &[Node], contexts: &mut Vec<Object>, out: &mut Output) {
+    for node in nodes {
+        match node {
+            Node::Text(text) => out.text.push_str(text),
+            Node::Value(name) => {
+                if let Some(value) = lookup(contexts, name) {
+                    out.insert(&value);
+                }
+            }
+            Node::Section {
+                name,
+                inverted,
+                body,
+            } => {
+                let value = lookup(contexts, name)
+                    .filter(|value| !matches!(value, Object::Bool(false) | Object::Nil));
+                let items = match (value, inverted) {
+                    (None, true) => vec![contexts.last().cloned().unwrap_or(Object::Nil)],
+                    (None, false) | (Some(_), true) => Vec::new(),
+                    (Some(value), false) if is_alist(&value) => vec![value],
+                    (Some(value), false) => value.to_vec().unwrap_or_else(|| vec![value]),
+                };
+                for item in items {
+                    contexts.push(item);
+                    render(body, contexts, out);
+                    contexts.pop();
+                }
+            }
+        }
+    }
+}
+
+/// `(render-template template context)` renders the template string with
+/// the values of `context`.
+pub fn render_template(args: &[Object]) -> Result<Object, EvalError> {
+    let (template, context) = match args {
+        [Object::String(template), context] => (template, context),
+        _ => {
+            return Err(EvalError::new(
+                "render-template expects a template string and a context",
+            ))
+        }
+    };
+
+    let nodes = parse(template)?;
+    let mut out = Output {
+        text: String::new(),
+        tainted: template.is_tainted(),
+    };
+    render(&nodes, &mut vec![context.clone()], &mut out);
+
+    Ok(Object::String(Str::from(out.text).with_taint(out.tainted)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builtins::global_env;
+    use crate::eval::eval_str;
+
+    fn render(template: &str, context: &str) -> String {
+        let env = global_env();
+        env.borrow_mut()
+            .define("template", crate::object::Object::string(template));
+        let program = format!("(render-template template {})", context);
+        match eval_str(&program, &env) {
+            Ok(crate::object::Object::String(s)) => s.to_string(),
+            Ok(other) => panic!("expected a string, got {}", other),
+            Err(e) => e.message().to_string(),
+        }
+    }
+
+    #[test]
+    fn test_values_and_sections() {
+        assert_eq!(
+            render(
+                "Hello {{name}}, {{user.age}} {{missing}}{{! not shown }}!",
+                "'((name . \"ana\") (user . ((age . 31))))"
+            ),
+            "Hello ana, 31 !"
+        );
+
+        let template = "struct {{name}} {\n\
+                        {{#fields}}\n    {{name}}: {{type}},\n{{/fields}}\n\
+                        {{^fields}}\n    // empty\n{{/fields}}\n\
+                        }\n\
+                        {{#debug}}#[derive(Debug)]{{/debug}}{{#tags}}[{{.}}]{{/tags}}";
+        assert_eq!(
+            render(
+                template,
+                "(list (cons 'name \"Point\") (cons 'debug #f) (cons 'tags '(a b))
+                       (cons 'fields (list '((name . x) (type . i64))
+                                           '((name . y) (type . i64)))))"
+            ),
+            "struct Point {\n    x: i64,\n    y: i64,\n}\n[a][b]"
+        );
+        assert_eq!(
+            render(template, "'((name . \"Unit\") (fields))"),
+            "struct Unit {\n    // empty\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            render("{{#a}} {{/b}}", "'()"),
+            "render-template: {{/b}} closes section a"
+        );
+        assert_eq!(
+            render("{{#a}}", "'()"),
+            "render-template: unclosed section a"
+        );
+        assert_eq!(render("{{a", "'()"), "render-template: unclosed tag");
+    }
+}
@@ -0,0 +1,189 @@
+//! Terminal control for interactive programs, enabled with the `terminal`
+//! feature.
+
+use std::io::{stdout, Write};
+
+use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use crossterm::style::{Color, ResetColor, SetBackgroundColor, SetForegroundColor};
+use crossterm::{cursor, terminal, QueueableCommand};
+
+use crate::eval::EvalError;
+use crate::object::{BuiltinFn, Object};
+
+pub const BUILTINS: &[(&str, BuiltinFn)] = &[
+    ("terminal-size", terminal_size),
+    ("clear-screen!", clear_screen),
+    ("cursor-move!", cursor_move),
+    ("cursor-hide!", cursor_hide),
+    ("cursor-show!", cursor_show),
+    ("set-color!", set_color),
+    ("reset-color!", reset_color),
+    ("raw-mode!", raw_mode),
+    ("read-key", read_key),
+];
+
+fn io_error(e: std::io::Error) -> EvalError {
+    EvalError::new(format!("terminal error: {}", e))
+}
+
+fn no_args(name: &str, args: &[Object]) -> Result<(), EvalError> {
+    if !args.is_empty() {
+        return Err(EvalError::new(format!("{} expects no arguments", name)));
+    }
+
+    Ok(())
+}
+
+/// Queues `command` on stdout and flushes it.
+fn emit(command: impl crossterm::Command) -> Result<Object, EvalError> {
+    let mut out = stdout();
+    out.queue(command).map_err(io_error)?;
+    out.flush().map_err(io_error)?;
+
+    Ok(Object::Void)
+}
+
+/// `(terminal-size)` returns `(columns rows)`.
+fn terminal_size(args: &[Object]) -> Result<Object, EvalError> {
+    no_args("terminal-size", args)?;
+    let (columns, rows) = terminal::size().map_err(io_error)?;
+
+    Ok(Object::list(vec![
+        Object::Integer(columns as i64),
+        Object::Integer(rows as i64),
+    ]))
+}
+
+fn clear_screen(args: &[Object]) -> Result<Object, EvalError> {
+    no_args("clear-screen!", args)?;
+    emit(terminal::Clear(terminal::ClearType::All))?;
+    emit(cursor::MoveTo(0, 0))
+}
+
+/// `(cursor-move! column row)`, both counted from zero.
+fn cursor_move(args: &[Object]) -> Result<Object, EvalError> {
+    match args {
+        [Object::Integer(column), Object::Integer(row)] => {
+            match (u16::try_from(*column), u16::try_from(*row)) {
+                (Ok(column), Ok(row)) => emit(cursor::MoveTo(column, row)),
+                _ => Err(EvalError::new("cursor-move!: position out of range")),
+            }
+        }
+        _ => Err(EvalError::new("cursor-move! expects a column and a row")),
+    }
+}
+
+fn cursor_hide(args: &[Object]) -> Result<Object, EvalError> {
+    no_args("cursor-hide!", args)?;
+    emit(cursor::Hide)
+}
+
+fn cursor_show(args: &[Object]) -> Result<Object, EvalError> {
+    no_args("cursor-show!", args)?;
+    emit(cursor::Show)
+}
+
+fn color(obj: &Object) -> Result<Color, EvalError> {
+    let name = match obj {
+        Object::Symbol(name) => name.as_str(),
+        other => return Err(EvalError::new(format!("invalid color: {}", other))),
+    };
+
+    Ok(match name {
+        "black" => Color::Black,
+        "red" => Color::DarkRed,
+        "green" => Color::DarkGreen,
+        "yellow" => Color::DarkYellow,
+        "blue" => Color::DarkBlue,
+        "magenta" => Color::DarkMagenta,
+        "cyan" => Color::DarkCyan,
+        "white" => Color::Grey,
+        "bright-black" => Color::DarkGrey,
+        "bright-red" => Color::Red,
+        "bright-green" => Color::Green,
+        "bright-yellow" => Color::Yellow,
+        "bright-blue" => Color::Blue,
+        "bright-magenta" => Color::Magenta,
+        "bright-cyan" => Color::Cyan,
+        "bright-white" => Color::White,
+        _ => return Err(EvalError::new(format!("unknown color: {}", name))),
+    })
+}
+
+/// `(set-color! 'red)` sets the foreground; `(set-color! 'red 'black)` also
+/// sets the background.
+fn set_color(args: &[Object]) -> Result<Object, EvalError> {
+    match args {
+        [fg] => emit(SetForegroundColor(color(fg)?)),
+        [fg, bg] => {
+            emit(SetForegroundColor(color(fg)?))?;
+            emit(SetBackgroundColor(color(bg)?))
+        }
+        _ => Err(EvalError::new(
+            "set-color! expects a foreground and an optional background",
+        )),
+    }
+}
+
+fn reset_color(args: &[Object]) -> Result<Object, EvalError> {
+    no_args("reset-color!", args)?;
+    emit(ResetColor)
+}
+
+/// `(raw-mode! #t)` stops the terminal from echoing input and buffering it
+/// by line; `(raw-mode! #f)` restores it.
+fn raw_mode(args: &[Object]) -> Result<Object, EvalError> {
+    match args {
+        [Object::Bool(true)] => terminal::enable_raw_mode().map_err(io_error)?,
+        [Object::Bool(false)] => terminal::disable_raw_mode().map_err(io_error)?,
+        _ => return Err(EvalError::new("raw-mode! expects #t or #f")),
+    }
+
+    Ok(Object::Void)
+}
+
+/// Blocks until a key is pressed. Printable keys are returned as chars and
+/// the others as symbols such as `up`, `enter` or `escape`.
+fn read_key(args: &[Object]) -> Result<Object, EvalError> {
+    no_args("read-key", args)?;
+
+    loop {
+        let key = match event::read().map_err(io_error)? {
+            Event::Key(key) if key.kind != KeyEventKind::Release => key,
+            _ => continue,
+        };
+
+        let name = match key.code {
+            KeyCode::Char(c) => return Ok(Object::Char(c)),
+            KeyCode::Enter => "enter",
+            KeyCode::Esc => "escape",
+            KeyCode::Backspace => "backspace",
+            KeyCode::Tab => "tab",
+            KeyCode::Delete => "delete",
+            KeyCode::Up => "up",
+            KeyCode::Down => "down",
+            KeyCode::Left => "left",
+            KeyCode::Right => "right",
+            KeyCode::Home => "home",
+            KeyCode::End => "end",
+            KeyCode::PageUp => "page-up",
+            KeyCode::PageDown => "page-down",
+            _ => continue,
+        };
+
+        return Ok(Object::symbol(name));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_argument_checks() {
+        assert!(color(&Object::symbol("bright-cyan")).is_ok());
+        assert!(color(&Object::symbol("mauve")).is_err());
+        assert!(cursor_move(&[Object::Integer(-1), Object::Integer(0)]).is_err());
+        assert!(raw_mode(&[Object::Integer(1)]).is_err());
+    }
+}
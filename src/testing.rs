@@ -0,0 +1,318 @@
+//! Unit tests for Lisp code, written in Lisp.
+//!
+//! `(assert expr)` fails unless `expr` is true, and `(assert-equal expected
+//! actual)` unless the two are `equal?`; their errors quote the expression
+//! that failed. `(deftest name body...)` defines a test in the global
+//! environment, and `(run-tests)` runs every test defined in its global
+//! environment so far, each in an environment of its own,
+//! printing a line per test and, for those that failed, the error and where
+//! it happened:
+//!
+//! ```text
+//! test adds ... ok
+//! test joins ... FAILED
+//!
+//! failures:
+//!     joins, at tests.lisp:4:1
+//!         tests.lisp:5:3: assertion failed: (string-append "a" "b") is "ab", expected "a b"
+//!
+//! 2 tests: 1 passed, 1 failed
+//! ```
+//!
+//! `run-tests` returns whether every test passed. Defining a test again
+//! replaces it, so that a file of tests can be loaded more than once.
+//!
+//! `lisp-rs test DIR` runs the tests of every `*-test.lisp` file in a
+//! directory and those in it, which `discover` finds.
+
+use std::cell::RefCell;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::env::{self, Env};
+use crate::eval::{eval, source_of, EvalError, Source};
+use crate::gc::{Edge, Trace};
+use crate::object::Object;
+
+pub(crate) struct Test {
+    name: String,
+    body: Vec<Object>,
+    env: Rc<RefCell<Env>>,
+    /// Where the test was defined, if it was in source text.
+    location: Option<String>,
+    /// The source it is in, where the errors it fails with are found.
+    source: Option<Rc<Source>>,
+}
+
+impl Trace for Test {
+    fn trace(&self, edge: &mut dyn FnMut(Edge)) {
+        edge(Edge::Env(&self.env));
+        for form in &self.body {
+            edge(Edge::Object(form));
+        }
+    }
+}
+
+/// `(assert expr)`, or `(assert expr message)`.
+pub fn assert(list: &[Object], env: &Rc<RefCell<Env>>) -> Result<Object, EvalError> {
+    let (expr, message) = match list {
+        [_, expr] => (expr, None),
+        [_, expr, message] => (expr, Some(message)),
+        _ => return Err(EvalError::new("assert expects an expression")),
+    };
+    if eval(expr, env)?.is_truthy() {
+        return Ok(Object::Void);
+    }
+
+    Err(EvalError::new(
+        match message.map(|m| eval(m, env)).transpose()? {
+            Some(Object::String(message)) => format!("assertion failed: {}: {}", expr, message),
+            Some(message) => format!("assertion failed: {}: {}", expr, message),
+            None => format!("assertion failed: {}", expr),
+        },
+    ))
+}
+
+/// `(assert-equal expected actual)`.
+pub fn assert_equal(list: &[Object], env: &Rc<RefCell<Env>>) -> Result<Object, EvalError> {
+    let [_, expected, actual] = list else {
+        return Err(EvalError::new(
+            "assert-equal expects an expected value and an expression",
+        ));
+    };
+    let expected = eval(expected, env)?;
+    let value = eval(actual, env)?;
+
+    match value == expected {
+        true => Ok(Object::Void),
+        false => Err(EvalError::new(format!(
+            "assertion failed: {} is {}, expected {}",
+            actual, value, expected
+        ))),
+    }
+}
+
+/// `(deftest name body...)`, where `name` is a symbol or a string.
+pub fn deftest(
+    form: &Object,
+    list: &[Object],
+    env: &Rc<RefCell<Env>>,
+) -> Result<Object, EvalError> {
+    let name = match list.get(1) {
+        Some(Object::Symbol(name)) if list.len() > 2 => name.to_string(),
+        Some(Object::String(name)) if list.len() > 2 => name.to_string(),
+        _ => return Err(EvalError::new("deftest expects a name and a body")),
+    };
+    let source = source_of(form);
+    let test = Rc::new(Test {
+        name,
+        body: list[2..].to_vec(),
+        env: env.clone(),
+        location: source.as_ref().and_then(|source| source.locate(form)),
+        source,
+    });
+
+    let global = env::global(env);
+    let mut global = global.borrow_mut();
+    let tests = global.tests_mut();
+    match tests.iter_mut().find(|defined| defined.name == test.name) {
+        Some(defined) => *defined = test,
+        None => tests.push(test),
+    }
+
+    Ok(Object::Void)
+}
+
+/// How many tests passed and failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Summary {
+    pub passed: usize,
+    pub failed: usize,
+}
+
+/// Runs the tests defined so far in the global environment of `env`,
+/// reporting on them to `out`. Leaving the evaluation, as `exit` does, or
+/// being stopped by its host stops the run.
+pub fn run(env: &Rc<RefCell<Env>>, out: &mut dyn Write) -> Result<Summary, EvalError> {
+    let tests = env::global(env).borrow().tests().to_vec();
+    let write_error = |e: io::Error| EvalError::new(format!("run-tests: {}", e));
+
+    let mut failures = Vec::new();
+    for test in &tests {
+        write!(out, "test {} ... ", test.name).map_err(write_error)?;
+        let env = Rc::new(RefCell::new(Env::extend(test.env.clone())));
+        match test
+            .body
+            .iter()
+            .try_for_each(|form| eval(form, &env).map(drop))
+        {
+            Ok(()) => writeln!(out, "ok").map_err(write_error)?,
+            Err(e) if e.is_failure() => {
+                writeln!(out, "FAILED").map_err(write_error)?;
+                failures.push((test, e));
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    if !failures.is_empty() {
+        writeln!(out, "\nfailures:").map_err(write_error)?;
+        for (test, e) in &failures {
+            match &test.location {
+                Some(location) => writeln!(out, "    {}, at {}", test.name, location),
+                None => writeln!(out, "    {}", test.name),
+            }
+            .map_err(write_error)?;
+            match e.location(test.source.as_deref()) {
+                Some(location) => writeln!(out, "        {}: {}", location, e.message()),
+                None => writeln!(out, "        {}", e.message()),
+            }
+            .map_err(write_error)?;
+        }
+    }
+
+    let summary = Summary {
+        passed: tests.len() - failures.len(),
+        failed: failures.len(),
+    };
+    writeln!(
+        out,
+        "\n{} tests: {} passed, {} failed",
+        tests.len(),
+        summary.passed,
+        summary.failed
+    )
+    .map_err(write_error)?;
+
+    Ok(summary)
+}
+
+/// The files named `*-test.lisp` in `dir` and the directories in it, in
+/// order, leaving out hidden directories.
+pub fn discover(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if entry.file_type()?.is_dir() {
+                if !name.starts_with('.') {
+                    pending.push(path);
+                }
+            } else if name.ends_with("-test.lisp") {
+                files.push(path);
+            }
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+/// `(run-tests)` runs the tests defined so far, printing how they went, and
+/// returns whether they all passed.
+pub fn run_tests(list: &[Object], env: &Rc<RefCell<Env>>) -> Result<Object, EvalError> {
+    if list.len() != 1 {
+        return Err(EvalError::new("run-tests expects no arguments"));
+    }
+    let summary = run(env, &mut io::stdout())?;
+
+    Ok(Object::Bool(summary.failed == 0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builtins::global_env;
+    use crate::eval::{eval_named, eval_str};
+
+    #[test]
+    fn test_assertions() {
+        let env = global_env();
+        let error = |program| eval_str(program, &env).unwrap_err().message().to_string();
+
+        assert!(eval_str("(assert (= 1 1)) (assert-equal '(1 2) (list 1 2))", &env).is_ok());
+        assert_eq!(error("(assert (> 1 2))"), "assertion failed: (> 1 2)");
+        assert_eq!(
+            error("(assert #f \"never\")"),
+            "assertion failed: #f: never"
+        );
+        assert_eq!(
+            error("(assert-equal 3 (+ 1 1))"),
+            "assertion failed: (+ 1 1) is 2, expected 3"
+        );
+    }
+
+    #[test]
+    fn test_run_tests() {
+        let env = global_env();
+        let source = "(define (double x) (* 2 x))
+(deftest doubles
+  (assert-equal 4 (double 2)))
+(deftest \"fails\"
+  (define y 1)
+  (assert (= (double y) 3)))
+(deftest doubles (assert-equal 6 (double 3)))";
+        eval_named(source, "t.lisp", &env).unwrap();
+
+        let mut out = Vec::new();
+        let summary = run(&env, &mut out).unwrap();
+        assert_eq!(
+            summary,
+            Summary {
+                passed: 1,
+                failed: 1
+            }
+        );
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "test doubles ... ok
+test fails ... FAILED
+
+failures:
+    fails, at t.lisp:4:1
+        t.lisp:6:3: assertion failed: (= (double y) 3)
+
+2 tests: 1 passed, 1 failed
+"
+        );
+        assert!(eval_str("y", &env).is_err());
+
+        // Other environments have tests of their own.
+        let other = global_env();
+        assert_eq!(run(&other, &mut Vec::new()).unwrap().passed, 0);
+        assert_eq!(
+            eval_str("(deftest other (exit 2)) (run-tests)", &other)
+                .unwrap_err()
+                .exit_code(),
+            Some(2)
+        );
+    }
+
+    #[test]
+    fn test_discover() {
+        let dir = std::env::temp_dir().join(format!("lisp-rs-discover-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        for path in [
+            "b-test.lisp",
+            "a/c-test.lisp",
+            "a/helper.lisp",
+            ".git/d-test.lisp",
+        ] {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+
+        assert_eq!(
+            discover(&dir).unwrap(),
+            [dir.join("a/c-test.lisp"), dir.join("b-test.lisp")]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
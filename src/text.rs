@@ -0,0 +1,196 @@
+//! The representation of Lisp strings.
+//!
+//! Strings are immutable, so a `Str` is a range of a shared buffer: cloning
+//! one, passing it around or taking a `substring` of it never copies the
+//! text. `string-copy` makes a compact copy, for when a small slice would
+//! otherwise keep a large buffer alive. Strings from untrusted sources are
+//! marked as tainted (see `taint`), and the mark is kept by these
+//! operations.
+
+use std::fmt;
+use std::fmt::Formatter;
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
+use std::rc::Rc;
+
+#[derive(Clone)]
+pub struct Str {
+    text: Rc<str>,
+    start: usize,
+    end: usize,
+    /// Whether the buffer is all ASCII, so that character indices are byte
+    /// indices.
+    ascii: bool,
+    tainted: bool,
+}
+
+impl Str {
+    pub fn as_str(&self) -> &str {
+        &self.text[self.start..self.end]
+    }
+
+    /// The length in characters.
+    pub fn char_len(&self) -> usize {
+        match self.ascii {
+            true => self.end - self.start,
+            false => self.as_str().chars().count(),
+        }
+    }
+
+    /// The characters from `start` up to `end`, sharing this string's
+    /// buffer, or `None` if the range is out of bounds.
+    pub fn substring(&self, start: usize, end: usize) -> Option<Str> {
+        if start > end {
+            return None;
+        }
+
+        let (start, end) = match self.ascii {
+            true => (start, end),
+            false => {
+                let s = self.as_str();
+                let offset = |index| match s.char_indices().nth(index) {
+                    Some((offset, _)) => Some(offset),
+                    None if index == s.chars().count() => Some(s.len()),
+                    None => None,
+                };
+                (offset(start)?, offset(end)?)
+            }
+        };
+        if end > self.end - self.start {
+            return None;
+        }
+
+        Some(Str {
+            text: self.text.clone(),
+            start: self.start + start,
+            end: self.start + end,
+            ascii: self.ascii,
+            tainted: self.tainted,
+        })
+    }
+
+    /// A copy with a buffer of its own, holding just this string.
+    pub fn compact(&self) -> Str {
+        Str::from(self.as_str()).with_taint(self.tainted)
+    }
+
+    /// Whether both are the same string, not only equal ones.
+    pub fn ptr_eq(&self, other: &Str) -> bool {
+        Rc::ptr_eq(&self.text, &other.text) && self.start == other.start && self.end == other.end
+    }
+
+    pub fn is_tainted(&self) -> bool {
+        self.tainted
+    }
+
+    pub fn with_taint(self, tainted: bool) -> Str {
+        Str { tainted, ..self }
+    }
+}
+
+impl Deref for Str {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl From<&str> for Str {
+    fn from(s: &str) -> Self {
+        Str {
+            text: Rc::from(s),
+            start: 0,
+            end: s.len(),
+            ascii: s.is_ascii(),
+            tainted: false,
+        }
+    }
+}
+
+impl From<String> for Str {
+    fn from(s: String) -> Self {
+        let ascii = s.is_ascii();
+        let end = s.len();
+
+        Str {
+            text: Rc::from(s),
+            start: 0,
+            end,
+            ascii,
+            tainted: false,
+        }
+    }
+}
+
+impl PartialEq for Str {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl Eq for Str {}
+
+impl PartialEq<str> for Str {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for Str {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl Hash for Str {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_str().hash(state);
+    }
+}
+
+impl fmt::Display for Str {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for Str {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_substrings_share_the_buffer() {
+        let s = Str::from("hello, world");
+        let world = s.substring(7, 12).unwrap();
+        assert_eq!(world, "world");
+        assert!(Rc::ptr_eq(&s.text, &world.text));
+        assert_eq!(world.substring(1, 3).unwrap(), "or");
+
+        assert!(s.substring(3, 2).is_none());
+        assert!(s.substring(0, 13).is_none());
+        assert!(world.substring(0, 6).is_none());
+        assert!(!Rc::ptr_eq(&world.compact().text, &s.text));
+
+        let tainted = s.with_taint(true);
+        assert!(tainted.substring(0, 5).unwrap().is_tainted());
+        assert!(tainted.compact().is_tainted());
+        assert_eq!(tainted, Str::from("hello, world"));
+    }
+
+    #[test]
+    fn test_non_ascii() {
+        let s = Str::from(String::from("héllo wörld"));
+        assert_eq!(s.char_len(), 11);
+        assert_eq!(s.substring(1, 5).unwrap(), "éllo");
+        assert_eq!(s.substring(6, 11).unwrap(), "wörld");
+        assert_eq!(s.substring(11, 11).unwrap(), "");
+        assert!(s.substring(6, 12).is_none());
+    }
+}
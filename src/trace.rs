@@ -0,0 +1,302 @@
+//! `trace` and `untrace`, which print the calls to the procedures they name
+//! and what each call returns, indented by how deeply the traced calls nest:
+//!
+//! ```text
+//! (fact 2)
+//!   (fact 1)
+//!   => 1
+//! => 2
+//! ```
+//!
+//! Both are special forms, as they take the names of the procedures and not
+//! their values. Tracing is done with a `Hook`, installed while at least
+//! one procedure is traced.
+//!
+//! `ChromeTrace` is a hook recording every call instead, for
+//! `--trace-out`.
+
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use crate::bench;
+use crate::comparator::is_procedure;
+use crate::env::Env;
+use crate::eval::EvalError;
+use crate::hooks;
+use crate::hooks::{Hook, HookId, ProcedureNames};
+use crate::json;
+use crate::object::Object;
+use crate::printer;
+use crate::symbol::Symbol;
+
+thread_local! {
+    /// The traced procedures, with the names they were traced by.
+    static TRACED: RefCell<Vec<(Object, Symbol)>> = const { RefCell::new(Vec::new()) };
+    static HOOK: Cell<Option<HookId>> = const { Cell::new(None) };
+    /// The number of traced calls that have not returned.
+    static DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+struct Tracer;
+
+fn traced_name(func: &Object) -> Option<Symbol> {
+    TRACED.with(|traced| {
+        traced
+            .borrow()
+            .iter()
+            .find(|(traced, _)| traced == func)
+            .map(|(_, name)| *name)
+    })
+}
+
+fn print_indented(line: &str) {
+    let depth = DEPTH.with(Cell::get);
+    printer::print_line(&format!("{}{}", "  ".repeat(depth), line));
+}
+
+impl Hook for Tracer {
+    fn on_call(&self, func: &Object, args: &[Object]) {
+        if let Some(name) = traced_name(func) {
+            let call =
+                Object::list(std::iter::once(Object::Symbol(name)).chain(args.iter().cloned()));
+            print_indented(&call.to_string());
+            DEPTH.with(|depth| depth.set(depth.get() + 1));
+        }
+    }
+
+    fn on_return(&self, func: &Object, result: &Object) {
+        if traced_name(func).is_some() {
+            DEPTH.with(|depth| depth.set(depth.get().saturating_sub(1)));
+            print_indented(&format!("=> {}", result));
+        }
+    }
+
+    /// The traced calls the error unwinds never return, so the indentation
+    /// starts over.
+    fn on_error(&self, err: &EvalError) {
+        if DEPTH.with(Cell::get) > 0 {
+            print_indented(&format!("error: {}", err.message()));
+            DEPTH.with(|depth| depth.set(0));
+        }
+    }
+}
+
+fn names(list: &[Object], form: &str) -> Result<Vec<Symbol>, EvalError> {
+    list.iter()
+        .map(|name| match name {
+            Object::Symbol(name) => Ok(*name),
+            other => Err(EvalError::new(format!(
+                "{} expects procedure names, got {}",
+                form, other
+            ))),
+        })
+        .collect()
+}
+
+fn traced_names() -> Object {
+    TRACED.with(|traced| {
+        Object::list(
+            traced
+                .borrow()
+                .iter()
+                .map(|(_, name)| Object::Symbol(*name))
+                .collect::<Vec<_>>(),
+        )
+    })
+}
+
+/// `(trace name ...)` traces the procedures bound to the names, and
+/// returns the names of every traced procedure.
+pub fn trace(list: &[Object], env: &Rc<RefCell<Env>>) -> Result<Object, EvalError> {
+    let mut procedures = Vec::new();
+    for name in names(&list[1..], "trace")? {
+        match env.borrow().get(name) {
+            Some(func) if is_procedure(&func) => procedures.push((func, name)),
+            Some(other) => {
+                return Err(EvalError::new(format!(
+                    "trace expects procedure names, but {} is {}",
+                    name, other
+                )))
+            }
+            None => return Err(EvalError::new(format!("Unbound symbol: {}", name))),
+        }
+    }
+
+    TRACED.with(|traced| {
+        let mut traced = traced.borrow_mut();
+        for (func, name) in procedures {
+            traced.retain(|(traced, _)| *traced != func);
+            traced.push((func, name));
+        }
+    });
+    if HOOK.with(Cell::get).is_none() && !list[1..].is_empty() {
+        HOOK.with(|hook| hook.set(Some(hooks::add(Rc::new(Tracer)))));
+    }
+
+    Ok(traced_names())
+}
+
+/// `(untrace name ...)` stops tracing the procedures traced by the names,
+/// or every procedure when given none. Returns the names still traced.
+pub fn untrace(list: &[Object]) -> Result<Object, EvalError> {
+    let untraced = names(&list[1..], "untrace")?;
+    let empty = TRACED.with(|traced| {
+        let mut traced = traced.borrow_mut();
+        if untraced.is_empty() {
+            traced.clear();
+        } else {
+            traced.retain(|(_, name)| !untraced.contains(name));
+        }
+        traced.is_empty()
+    });
+
+    if empty {
+        if let Some(hook) = HOOK.with(Cell::take) {
+            hooks::remove(hook);
+        }
+        DEPTH.with(|depth| depth.set(0));
+    }
+
+    Ok(traced_names())
+}
+
+/// Records every procedure call as a begin and an end event, in the Chrome
+/// trace event format read by `chrome://tracing` and Perfetto, which show
+/// them as a timeline. Calls an error unwinds end when the trace does.
+#[derive(Default)]
+pub struct ChromeTrace {
+    names: ProcedureNames,
+    /// The calls, as their name if they begin or `None` if they end, with
+    /// their times in microseconds.
+    events: RefCell<Vec<(Option<String>, f64)>>,
+    open: Cell<usize>,
+}
+
+impl ChromeTrace {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The recorded events, as a JSON trace file.
+    pub fn to_json(&self) -> String {
+        let events = self.events.borrow();
+        let end = events.last().map_or(0.0, |(_, time)| *time);
+        let closing = (None, end);
+        let closing = std::iter::repeat_n(&closing, self.open.get());
+
+        let events = events
+            .iter()
+            .chain(closing)
+            .map(|(name, time)| match name {
+                Some(name) => format!(
+                    r#"{{"name":{},"ph":"B","ts":{:.3},"pid":1,"tid":1}}"#,
+                    json::stringify(&Object::string(name.as_str())).unwrap_or_default(),
+                    time
+                ),
+                None => format!(r#"{{"ph":"E","ts":{:.3},"pid":1,"tid":1}}"#, time),
+            })
+            .collect::<Vec<_>>();
+
+        format!("{{\"traceEvents\":[\n{}\n]}}\n", events.join(",\n"))
+    }
+}
+
+impl Hook for ChromeTrace {
+    fn on_call(&self, func: &Object, _args: &[Object]) {
+        let name = self.names.name(func);
+        self.events
+            .borrow_mut()
+            .push((Some(name), bench::now() * 1000.0));
+        self.open.set(self.open.get() + 1);
+    }
+
+    fn on_return(&self, _func: &Object, _result: &Object) {
+        if self.open.get() > 0 {
+            self.events.borrow_mut().push((None, bench::now() * 1000.0));
+            self.open.set(self.open.get() - 1);
+        }
+    }
+
+    fn on_define(&self, name: &str, value: &Object) {
+        self.names.define(name, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builtins::global_env;
+    use crate::eval::eval_str;
+    use crate::printer;
+
+    #[test]
+    fn test_trace() {
+        let env = global_env();
+        printer::capture_output(true);
+        let result = eval_str(
+            "(define (fact n) (if (< n 2) 1 (* n (fact (- n 1)))))
+             (define (twice f x) (f (f x)))
+             (trace fact twice)
+             (twice fact 2)
+             (untrace twice)
+             (fact 1)
+             (untrace)
+             (fact 3)",
+            &env,
+        );
+        let output = printer::take_captured();
+        printer::capture_output(false);
+
+        assert_eq!(result.unwrap().to_string(), "6");
+        assert_eq!(
+            output,
+            "(twice #<procedure> 2)\n  (fact 2)\n    (fact 1)\n    => 1\n  => 2\n  \
+             (fact 2)\n    (fact 1)\n    => 1\n  => 2\n=> 2\n(fact 1)\n=> 1\n"
+        );
+    }
+
+    #[test]
+    fn test_trace_errors() {
+        let env = global_env();
+        let err = eval_str("(define x 1) (trace x)", &env).unwrap_err();
+        assert_eq!(err.message(), "trace expects procedure names, but x is 1");
+
+        printer::capture_output(true);
+        let err = eval_str("(define (f n) (car n)) (trace f) (f 1)", &env).unwrap_err();
+        eval_str("(untrace f)", &env).unwrap();
+        let output = printer::take_captured();
+        printer::capture_output(false);
+
+        assert!(output.starts_with("(f 1)\n  error: "), "{}", output);
+        assert!(output.ends_with(&format!("{}\n", err.message())));
+    }
+
+    #[test]
+    fn test_chrome_trace() {
+        let env = global_env();
+        let trace = Rc::new(ChromeTrace::new());
+        let hook = hooks::add(trace.clone());
+        let result = eval_str("(define (f x) (+ x 1)) (f (f 1)) (car 1)", &env);
+        hooks::remove(hook);
+        assert!(result.is_err());
+
+        let events = json::parse(&trace.to_json()).unwrap();
+        let events = crate::lsp::field(&events, &["traceEvents"])
+            .and_then(|events| events.to_vec())
+            .unwrap();
+        let phases = events
+            .iter()
+            .map(|event| {
+                let phase = crate::lsp::string_field(event, &["ph"]).unwrap();
+                match crate::lsp::string_field(event, &["name"]) {
+                    Some(name) => format!("{} {}", phase, name),
+                    None => phase,
+                }
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            phases,
+            ["B f", "B +", "E", "E", "B f", "B +", "E", "E", "B car", "E"]
+        );
+    }
+}
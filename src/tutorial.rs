@@ -0,0 +1,361 @@
+//! `lisp-rs learn`: a tutorial taken at a prompt, a step at a time.
+//!
+//! The lessons are data, in `LESSONS`. Each step asks for something to be
+//! typed, and checks what was typed by evaluating an expression after it,
+//! or the entry itself, and comparing the value with the one the step
+//! expects, as `assert-equal` does:
+//!
+//! ```text
+//! Lesson 2: Definitions (step 2 of 2)
+//! (define (name parameters...) body) defines a procedure. Define square,
+//! which multiplies a number by itself.
+//! learn> (define (square x) (+ x x))
+//! not yet: (list (square 3) (square -4)) is (6 -8), expected (9 16)
+//! learn> :hint
+//! ```
+//!
+//! A step is done once its check passes; an entry that fails only shows
+//! the error. `:hint` shows how the step can be done, `:skip` moves on
+//! without doing it, `:lessons` lists the lessons and `:quit` stops. Each
+//! lesson evaluates in an environment of its own, and how far the tutorial
+//! got is saved after every step, so that it carries on from there the
+//! next time.
+
+use std::cell::RefCell;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::builtins::global_env;
+use crate::env::Env;
+use crate::eval::{eval, eval_str, EvalError};
+use crate::object::Object;
+use crate::repl::{FormReader, LineEditor};
+
+const PROMPT: &str = "learn> ";
+
+pub struct Step {
+    /// What to do, as shown before the prompt.
+    pub text: &'static str,
+    /// The expression evaluated after each entry, or `""` for the value of
+    /// the entry itself.
+    pub check: &'static str,
+    /// The source of the value the check must have.
+    pub expected: &'static str,
+    pub hint: &'static str,
+}
+
+pub struct Lesson {
+    pub title: &'static str,
+    pub steps: &'static [Step],
+}
+
+pub const LESSONS: &[Lesson] = &[
+    Lesson {
+        title: "Expressions",
+        steps: &[
+            Step {
+                text: "A call is written in parentheses, the procedure first: (+ 1 2) adds 1 and 2.\nAdd 40 and 2.",
+                check: "",
+                expected: "42",
+                hint: "(+ 40 2)",
+            },
+            Step {
+                text: "Calls nest, and the arguments are evaluated before the call.\nMultiply 6 by the sum of 3 and 4.",
+                check: "",
+                expected: "42",
+                hint: "(* 6 (+ 3 4))",
+            },
+            Step {
+                text: "Strings are written in double quotes, and string-append joins them.\nJoin \"hello, \" and \"world\".",
+                check: "",
+                expected: "\"hello, world\"",
+                hint: "(string-append \"hello, \" \"world\")",
+            },
+        ],
+    },
+    Lesson {
+        title: "Definitions",
+        steps: &[
+            Step {
+                text: "(define name value) gives a value a name.\nDefine answer as 42.",
+                check: "answer",
+                expected: "42",
+                hint: "(define answer 42)",
+            },
+            Step {
+                text: "(define (name parameters...) body) defines a procedure.\nDefine square, which multiplies a number by itself.",
+                check: "(list (square 3) (square -4))",
+                expected: "'(9 16)",
+                hint: "(define (square x) (* x x))",
+            },
+        ],
+    },
+    Lesson {
+        title: "Choices and recursion",
+        steps: &[
+            Step {
+                text: "(if test then else) is then when test is true, and else otherwise.\nDefine (distance a b), how far apart two numbers are.",
+                check: "(list (distance 3 5) (distance 5 3))",
+                expected: "'(2 2)",
+                hint: "(define (distance a b) (if (> a b) (- a b) (- b a)))",
+            },
+            Step {
+                text: "A procedure can call itself, on a smaller problem each time.\nDefine (factorial n), the product of the numbers from 1 to n.",
+                check: "(list (factorial 0) (factorial 10))",
+                expected: "'(1 3628800)",
+                hint: "(define (factorial n) (if (= n 0) 1 (* n (factorial (- n 1)))))",
+            },
+        ],
+    },
+    Lesson {
+        title: "Lists",
+        steps: &[
+            Step {
+                text: "'(a b c) is a list, car its first element and cdr the list of the others.\nTake the second element of '(a b c).",
+                check: "",
+                expected: "'b",
+                hint: "(car (cdr '(a b c)))",
+            },
+            Step {
+                text: "(lambda (x) body) is a procedure without a name, and map applies one to\nevery element of a list. Square each of '(1 2 3).",
+                check: "",
+                expected: "'(1 4 9)",
+                hint: "(map (lambda (x) (* x x)) '(1 2 3))",
+            },
+            Step {
+                text: "filter keeps the elements a procedure is true of.\nKeep the numbers of '(1 5 2 8 3) greater than 2.",
+                check: "",
+                expected: "'(5 8 3)",
+                hint: "(filter (lambda (n) (> n 2)) '(1 5 2 8 3))",
+            },
+        ],
+    },
+];
+
+/// How far the tutorial got: the step to do next.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Progress {
+    pub lesson: usize,
+    pub step: usize,
+}
+
+impl Progress {
+    /// The progress saved at `path`, or the start if there is none.
+    pub fn load(path: &Path) -> Progress {
+        let text = fs::read_to_string(path).unwrap_or_default();
+        let mut numbers = text.split_whitespace().map(str::parse);
+        match (numbers.next(), numbers.next()) {
+            (Some(Ok(lesson)), Some(Ok(step))) => Progress { lesson, step },
+            _ => Progress::default(),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, format!("{} {}\n", self.lesson, self.step))
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.lesson >= LESSONS.len()
+    }
+
+    fn advance(&mut self) {
+        self.step += 1;
+        if self.step >= LESSONS[self.lesson].steps.len() {
+            self.lesson += 1;
+            self.step = 0;
+        }
+    }
+}
+
+/// Evaluates the forms of an entry in `env`, failing unless they did what
+/// `step` asks.
+fn check(step: &Step, forms: &[Object], env: &Rc<RefCell<Env>>) -> Result<(), EvalError> {
+    let mut value = Object::Void;
+    for form in forms {
+        value = eval(form, env)?;
+    }
+    if !step.check.is_empty() {
+        value = eval_str(step.check, env)
+            .map_err(|e| EvalError::new(format!("not yet: {}", e.message())))?;
+    }
+
+    let expected = eval_str(step.expected, &global_env())?;
+    match value == expected {
+        true => Ok(()),
+        false if step.check.is_empty() => Err(EvalError::new(format!(
+            "not yet: that is {}, expected {}",
+            value, expected
+        ))),
+        false => Err(EvalError::new(format!(
+            "not yet: {} is {}, expected {}",
+            step.check, value, expected
+        ))),
+    }
+}
+
+/// What the learner did with a step.
+enum Outcome {
+    Done,
+    Skipped,
+    Quit,
+    Exited(i32),
+}
+
+/// Reads entries until one does what `step` asks, or the learner gives up
+/// on it.
+fn take_step(
+    step: &Step,
+    editor: &mut dyn LineEditor,
+    out: &mut dyn Write,
+    env: &Rc<RefCell<Env>>,
+) -> io::Result<Outcome> {
+    let mut reader = FormReader::new();
+    loop {
+        let prompt = if reader.is_empty() { PROMPT } else { "...    " };
+        let Some(line) = editor.read_line(prompt)? else {
+            return Ok(Outcome::Quit);
+        };
+
+        if reader.is_empty() && line.trim_start().starts_with(':') {
+            match line.trim() {
+                ":hint" => writeln!(out, "{}", step.hint)?,
+                ":skip" => return Ok(Outcome::Skipped),
+                ":lessons" => {
+                    for (i, lesson) in LESSONS.iter().enumerate() {
+                        writeln!(out, "{}. {}", i + 1, lesson.title)?;
+                    }
+                }
+                ":quit" => return Ok(Outcome::Quit),
+                other => writeln!(
+                    out,
+                    "unknown command {}; the commands are :hint, :skip, :lessons and :quit",
+                    other
+                )?,
+            }
+            continue;
+        }
+
+        let forms = match reader.push_line(&line) {
+            Ok(Some(forms)) => forms,
+            Ok(None) => continue,
+            Err(e) => {
+                writeln!(out, "{}", e)?;
+                continue;
+            }
+        };
+        editor.add_history(line.trim());
+        match check(step, &forms, env) {
+            Ok(()) => return Ok(Outcome::Done),
+            Err(e) => match e.exit_code() {
+                Some(code) => return Ok(Outcome::Exited(code)),
+                None => writeln!(out, "{}", e)?,
+            },
+        }
+    }
+}
+
+/// Takes the tutorial from `progress`, reading entries from `editor` and
+/// writing to `out`, and saving the progress to `path` after every step.
+/// Returns the exit code if an entry exits.
+pub fn run(
+    editor: &mut dyn LineEditor,
+    out: &mut dyn Write,
+    progress: &mut Progress,
+    path: Option<&Path>,
+) -> io::Result<Option<i32>> {
+    let mut env: Option<(usize, Rc<RefCell<Env>>)> = None;
+    while !progress.is_finished() {
+        let lesson = &LESSONS[progress.lesson];
+        let step = &lesson.steps[progress.step];
+        if env.as_ref().is_none_or(|(i, _)| *i != progress.lesson) {
+            env = Some((
+                progress.lesson,
+                Rc::new(RefCell::new(Env::extend(global_env()))),
+            ));
+        }
+        writeln!(
+            out,
+            "\nLesson {}: {} (step {} of {})\n{}",
+            progress.lesson + 1,
+            lesson.title,
+            progress.step + 1,
+            lesson.steps.len(),
+            step.text
+        )?;
+
+        match take_step(step, editor, out, &env.as_ref().unwrap().1)? {
+            Outcome::Done => writeln!(out, "right!")?,
+            Outcome::Skipped => {}
+            Outcome::Quit => return Ok(None),
+            Outcome::Exited(code) => return Ok(Some(code)),
+        }
+        progress.advance();
+        if let Some(path) = path {
+            progress.save(path)?;
+        }
+    }
+
+    writeln!(out, "\nThat was the last lesson.")?;
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repl::BasicEditor;
+
+    #[test]
+    fn test_hints_pass() {
+        for lesson in LESSONS {
+            let env = Rc::new(RefCell::new(Env::extend(global_env())));
+            for step in lesson.steps {
+                let forms = crate::parser::parse(step.hint).unwrap();
+                if let Err(e) = check(step, &forms, &env) {
+                    panic!("{}: {}", step.hint, e.message());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_run() {
+        let path = std::env::temp_dir().join(format!("lisp-rs-learn-{}", std::process::id()));
+        let input = "(+ 40 1)\n:hint\n(+ 40\n 2)\n:skip\n:nope\n(car '())\n:quit\n";
+        let mut editor = BasicEditor::new(input.as_bytes(), Vec::new());
+        let mut out = Vec::new();
+        let mut progress = Progress::default();
+
+        assert_eq!(
+            run(&mut editor, &mut out, &mut progress, Some(&path)).unwrap(),
+            None
+        );
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("not yet: that is 41, expected 42\n(+ 40 2)\nright!\n"));
+        assert!(out.contains("Lesson 1: Expressions (step 3 of 3)"));
+        assert!(out.contains("unknown command :nope"));
+        assert!(out.contains("car expects a pair, got ()"));
+        assert_eq!(progress, Progress { lesson: 0, step: 2 });
+        assert_eq!(Progress::load(&path), progress);
+
+        // A finished tutorial stops at once.
+        let mut progress = Progress {
+            lesson: LESSONS.len(),
+            step: 0,
+        };
+        let mut out = Vec::new();
+        let mut editor = BasicEditor::new("(exit 3)\n".as_bytes(), Vec::new());
+        run(&mut editor, &mut out, &mut progress, None).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "\nThat was the last lesson.\n"
+        );
+
+        let mut editor = BasicEditor::new("(exit 3)\n".as_bytes(), Vec::new());
+        let result = run(&mut editor, &mut Vec::new(), &mut Progress::default(), None);
+        assert_eq!(result.unwrap(), Some(3));
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,169 @@
+//! Multiple values.
+//!
+//! `(values a b)` returns both `a` and `b` at once, as one object that
+//! `call-with-values` and `let-values` take apart again:
+//!
+//! ```text
+//! (define (divmod n d) (values (quotient n d) (remainder n d)))
+//! (call-with-values (lambda () (divmod 7 2)) list)   ; => (3 1)
+//! (let-values (((q r) (divmod 7 2))) (+ q r))        ; => 4
+//! ```
+//!
+//! A single value is the value itself, so that `(values x)` is `x` and a
+//! consumer given an ordinary value receives it as its only argument.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::env::Env;
+use crate::eval::{apply, eval, EvalError};
+use crate::object::Object;
+
+/// The values `obj` stands for, one unless it is made by `values`.
+fn spread(obj: Object) -> Vec<Object> {
+    match obj {
+        Object::Values(values) => values.to_vec(),
+        other => vec![other],
+    }
+}
+
+/// `(values obj...)`.
+pub fn values(args: &[Object]) -> Result<Object, EvalError> {
+    Ok(match args {
+        [value] => value.clone(),
+        _ => Object::Values(Rc::new(args.to_vec())),
+    })
+}
+
+/// `(call-with-values producer consumer)` calls `consumer` with the values
+/// `producer` returns when called with no arguments.
+pub fn call_with_values(args: &[Object]) -> Result<Object, EvalError> {
+    let [producer, consumer] = args else {
+        return Err(EvalError::new(
+            "call-with-values expects a producer and a consumer",
+        ));
+    };
+    let values = spread(apply(producer, Vec::new())?);
+
+    apply(consumer, values)
+}
+
+/// Binds the names of `formals`, a parameter list like that of a lambda,
+/// to `values` in `env`.
+fn bind(formals: &Object, values: Vec<Object>, env: &mut Env) -> Result<(), EvalError> {
+    let count = values.len();
+    let mut values = values.into_iter();
+    let mut current = formals.clone();
+    loop {
+        match current {
+            Object::Symbol(rest) => {
+                env.define(rest, Object::list(values.collect::<Vec<_>>()));
+                return Ok(());
+            }
+            Object::Pair(pair) => {
+                let Object::Symbol(name) = pair.car() else {
+                    return Err(EvalError::new(format!("invalid parameter: {}", pair.car())));
+                };
+                let Some(value) = values.next() else {
+                    break;
+                };
+                env.define(name, value);
+                current = pair.cdr();
+            }
+            Object::Nil if values.len() == 0 => return Ok(()),
+            Object::Nil => break,
+            other => return Err(EvalError::new(format!("invalid parameter: {}", other))),
+        }
+    }
+
+    Err(EvalError::new(format!(
+        "let-values: {} cannot bind {} values",
+        formals, count
+    )))
+}
+
+/// `(let-values ((formals expr)...) body...)` binds the values of each
+/// `expr`, evaluated in the enclosing environment, to its `formals`.
+pub fn let_values(list: &[Object], env: &Rc<RefCell<Env>>) -> Result<Object, EvalError> {
+    let (Some(bindings), true) = (list.get(1).and_then(Object::to_vec), list.len() > 2) else {
+        return Err(EvalError::new("let-values expects bindings and a body"));
+    };
+
+    let mut scope = Env::extend(env.clone());
+    for binding in bindings {
+        let items = binding.to_vec();
+        let [formals, expr] = items.as_deref().unwrap_or_default() else {
+            return Err(EvalError::new(format!(
+                "let-values: invalid binding: {}",
+                binding
+            )));
+        };
+        bind(formals, spread(eval(expr, env)?), &mut scope)?;
+    }
+
+    let scope = Rc::new(RefCell::new(scope));
+    let mut value = Object::Void;
+    for form in &list[2..] {
+        value = eval(form, &scope)?;
+    }
+
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builtins::global_env;
+    use crate::eval::eval_str;
+
+    #[test]
+    fn test_values() {
+        let env = global_env();
+        let eval = |program: &str| match eval_str(program, &env) {
+            Ok(value) => value.to_string(),
+            Err(e) => e.message().to_string(),
+        };
+
+        eval("(define (divmod n d) (values (quotient n d) (remainder n d)))");
+        assert_eq!(
+            eval("(call-with-values (lambda () (divmod 7 2)) list)"),
+            "(3 1)"
+        );
+        assert_eq!(eval("(call-with-values (lambda () 5) list)"), "(5)");
+        assert_eq!(eval("(call-with-values values list)"), "()");
+        assert_eq!(eval("(values 1 \"two\")"), "1 \"two\"");
+        assert_eq!(eval("(equal? (values 1 2) (values 1 2))"), "#t");
+        assert_eq!(eval("(values 'x)"), "x");
+    }
+
+    #[test]
+    fn test_let_values() {
+        let env = global_env();
+        let eval = |program: &str| match eval_str(program, &env) {
+            Ok(value) => value.to_string(),
+            Err(e) => e.message().to_string(),
+        };
+
+        assert_eq!(
+            eval(
+                "(define q 'outer)
+                 (let-values (((q r) (values 3 1))
+                              ((first . rest) (values q 'b 'c))
+                              (all (values)))
+                   (list q r first rest all))"
+            ),
+            "(3 1 outer (b c) ())"
+        );
+        assert_eq!(
+            eval("(let-values (((a b) (values 1 2 3))) a)"),
+            "let-values: (a b) cannot bind 3 values"
+        );
+        assert_eq!(
+            eval("(let-values (((a b c) (values 1 2))) a)"),
+            "let-values: (a b c) cannot bind 2 values"
+        );
+        assert_eq!(
+            eval("(let-values ((a)) a)"),
+            "let-values: invalid binding: (a)"
+        );
+    }
+}
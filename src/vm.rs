@@ -0,0 +1,518 @@
+//! A stack-based virtual machine for the bytecode made by `compiler`.
+//!
+//! Forms the compiler does not handle are evaluated by the tree-walking
+//! evaluator in `eval`, which stays the reference implementation. Compiled
+//! procedures are `Object::Closure`s; they can be called from interpreted
+//! code and builtins like any other procedure, and call them in turn.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::compiler::{compile, Capture, Chunk, Op, TopLevel};
+use crate::env::{self, Env};
+use crate::eval::{self, EvalError};
+use crate::gc;
+use crate::macros;
+use crate::object::Object;
+use crate::parser::parse;
+
+/// A variable shared by closures and the code that made them.
+pub(crate) type Cell = Rc<RefCell<Object>>;
+
+/// A compiled procedure and the variables it captured.
+pub struct Closure {
+    pub chunk: Rc<Chunk>,
+    /// The values listed by `chunk.free`.
+    pub(crate) free: Vec<Object>,
+    /// The cells listed by `chunk.free_cells`.
+    pub(crate) cells: Vec<Cell>,
+    /// The environment its global variables are looked up in.
+    pub globals: Rc<RefCell<Env>>,
+}
+
+/// Evaluates `form` on the VM, or with the tree-walking evaluator if it
+/// cannot be compiled or uses a macro.
+pub fn eval(form: &Object, env: &Rc<RefCell<Env>>) -> Result<Object, EvalError> {
+    if macros::uses_macro(form, env) {
+        return eval::eval(form, env);
+    }
+    match compile(form) {
+        Some(chunk) => run_chunk(Rc::new(chunk), env),
+        None => eval::eval(form, env),
+    }
+}
+
+/// Runs a program compiled ahead of time, such as one loaded by
+/// `bytecode::decode`, returning the value of its last form.
+pub fn run_program(program: &[TopLevel], env: &Rc<RefCell<Env>>) -> Result<Object, EvalError> {
+    let mut result = Object::Void;
+
+    for form in program {
+        result = match form {
+            TopLevel::Compiled(chunk) => run_chunk(chunk.clone(), env)?,
+            TopLevel::Interpreted(form) => eval::eval(form, env)?,
+        };
+    }
+
+    Ok(result)
+}
+
+fn run_chunk(chunk: Rc<Chunk>, env: &Rc<RefCell<Env>>) -> Result<Object, EvalError> {
+    let stack = vec![Object::Void; chunk.slots];
+    let frame = Frame {
+        cells: new_cells(&chunk),
+        chunk,
+        ip: 0,
+        closure: None,
+        globals: env.clone(),
+        base: 0,
+        return_to: 0,
+    };
+
+    run(frame, stack)
+}
+
+/// Like `eval::eval_str`, but evaluating each top-level form with `eval`.
+pub fn eval_str(program: &str, env: &Rc<RefCell<Env>>) -> Result<Object, EvalError> {
+    let forms = parse(program).map_err(|e| EvalError::new(e.to_string()))?;
+    let mut result = Object::Void;
+
+    for form in forms {
+        result = eval(&form, env)?;
+    }
+
+    Ok(result)
+}
+
+/// Calls a compiled procedure with already evaluated arguments.
+pub fn call(closure: &Rc<Closure>, args: Vec<Object>) -> Result<Object, EvalError> {
+    let mut stack = Vec::with_capacity(args.len() + 1);
+    stack.push(Object::Closure(closure.clone()));
+    stack.extend(args);
+
+    let frame = enter(&mut stack, 0, closure)?;
+    crate::limits::nested(|| run(frame, stack))
+}
+
+/// Sets up a call to the closure at `stack[func_at]` with the values above
+/// it as arguments, which become its first slots.
+fn enter(
+    stack: &mut Vec<Object>,
+    func_at: usize,
+    closure: &Rc<Closure>,
+) -> Result<Frame, EvalError> {
+    let chunk = &closure.chunk;
+    let argc = stack.len() - func_at - 1;
+    let arity_ok = match chunk.rest {
+        true => argc >= chunk.params,
+        false => argc == chunk.params,
+    };
+    if !arity_ok {
+        return Err(EvalError::new(format!(
+            "expected {}{} arguments, got {}",
+            if chunk.rest { "at least " } else { "" },
+            chunk.params,
+            argc
+        )));
+    }
+
+    if chunk.rest {
+        let rest = Object::list(stack.split_off(func_at + 1 + chunk.params));
+        stack.push(rest);
+    }
+
+    stack.resize(func_at + 1 + chunk.slots, Object::Void);
+
+    Ok(Frame {
+        chunk: chunk.clone(),
+        ip: 0,
+        closure: Some(closure.clone()),
+        cells: new_cells(chunk),
+        globals: closure.globals.clone(),
+        base: func_at + 1,
+        return_to: func_at,
+    })
+}
+
+/// The cells of a frame of `chunk`. A closure stored in a variable it
+/// captures makes a cycle through its cell, so cells are registered with
+/// the collector.
+fn new_cells(chunk: &Chunk) -> Vec<Cell> {
+    (0..chunk.cells)
+        .map(|_| {
+            let cell = Rc::new(RefCell::new(Object::Void));
+            gc::track(&cell);
+            cell
+        })
+        .collect()
+}
+
+/// A chunk being run.
+struct Frame {
+    chunk: Rc<Chunk>,
+    ip: usize,
+    /// The closure running, or `None` for a top-level form.
+    closure: Option<Rc<Closure>>,
+    cells: Vec<Cell>,
+    globals: Rc<RefCell<Env>>,
+    /// Where the chunk's stack slots start.
+    base: usize,
+    /// The height the value stack goes back to when the chunk returns.
+    return_to: usize,
+}
+
+fn run(mut frame: Frame, mut stack: Vec<Object>) -> Result<Object, EvalError> {
+    let mut calls: Vec<Frame> = Vec::new();
+
+    loop {
+        let mut op = frame.chunk.code[frame.ip];
+        frame.ip += 1;
+
+        if let Op::Arith {
+            op: arith,
+            symbol,
+            tail,
+        } = op
+        {
+            let func = global(&frame, symbol)?;
+            let args = &stack[stack.len() - 2..];
+            let result = match (&func, &args[0], &args[1]) {
+                (Object::Builtin(builtin), Object::Integer(a), Object::Integer(b))
+                    if builtin.name == arith.name() =>
+                {
+                    arith.apply(*a, *b)
+                }
+                _ => None,
+            };
+            match result {
+                Some(result) => {
+                    stack.truncate(stack.len() - 2);
+                    stack.push(result);
+                    continue;
+                }
+                // The guard failed: call whatever the global holds.
+                None => {
+                    stack.insert(stack.len() - 2, func);
+                    op = match tail {
+                        true => Op::TailCall(2),
+                        false => Op::Call(2),
+                    };
+                }
+            }
+        }
+
+        // The value returned from the current chunk, if this op returns.
+        let returned = match op {
+            Op::Const(index) => {
+                stack.push(frame.chunk.constants[index].clone());
+                continue;
+            }
+            Op::Cell(index) => {
+                let value = frame.cells[index].borrow().clone();
+                stack.push(value);
+                continue;
+            }
+            Op::SetCell(index) => {
+                *frame.cells[index].borrow_mut() = pop(&mut stack);
+                continue;
+            }
+            Op::Free(index) => {
+                stack.push(captured(&frame).free[index].clone());
+                continue;
+            }
+            Op::FreeCell(index) => {
+                let value = captured(&frame).cells[index].borrow().clone();
+                stack.push(value);
+                continue;
+            }
+            Op::SetFreeCell(index) => {
+                *captured(&frame).cells[index].borrow_mut() = pop(&mut stack);
+                continue;
+            }
+            Op::Slot(slot) => {
+                stack.push(stack[frame.base + slot].clone());
+                continue;
+            }
+            Op::SetSlot(slot) => {
+                let value = pop(&mut stack);
+                stack[frame.base + slot] = value;
+                continue;
+            }
+            Op::Global(index) => {
+                stack.push(global(&frame, index)?);
+                continue;
+            }
+            Op::SetGlobal(index) => {
+                let name = frame.chunk.symbols[index];
+                if !frame.globals.borrow_mut().set(name, pop(&mut stack)) {
+                    return Err(EvalError::new(format!("set! of unbound symbol: {}", name)));
+                }
+                continue;
+            }
+            Op::DefineGlobal(index) => {
+                let name = frame.chunk.symbols[index];
+                frame.globals.borrow_mut().define(name, pop(&mut stack));
+                continue;
+            }
+            Op::Pop => {
+                stack.pop();
+                continue;
+            }
+            Op::Jump(target) => {
+                frame.ip = target;
+                continue;
+            }
+            Op::JumpIfFalse(target) => {
+                if !pop(&mut stack).is_truthy() {
+                    frame.ip = target;
+                }
+                continue;
+            }
+            Op::Closure(index) => {
+                let chunk = frame.chunk.chunks[index].clone();
+                let free = chunk
+                    .free
+                    .iter()
+                    .map(|capture| match *capture {
+                        Capture::Local(slot) => stack[frame.base + slot].clone(),
+                        Capture::Free(i) => captured(&frame).free[i].clone(),
+                    })
+                    .collect();
+                let cells = chunk
+                    .free_cells
+                    .iter()
+                    .map(|capture| match *capture {
+                        Capture::Local(cell) => frame.cells[cell].clone(),
+                        Capture::Free(i) => captured(&frame).cells[i].clone(),
+                    })
+                    .collect();
+                stack.push(Object::Closure(Rc::new(Closure {
+                    chunk,
+                    free,
+                    cells,
+                    globals: frame.globals.clone(),
+                })));
+                continue;
+            }
+            Op::Inline {
+                symbol,
+                chunk,
+                argc,
+                fallback,
+            } => {
+                let func = global(&frame, symbol)?;
+                let inlined = match &func {
+                    Object::Closure(closure) => {
+                        Rc::ptr_eq(&closure.chunk, &frame.chunk.chunks[chunk])
+                            && Rc::ptr_eq(&closure.globals, &frame.globals)
+                    }
+                    _ => false,
+                };
+                if !inlined {
+                    stack.insert(stack.len() - argc, func);
+                    frame.ip = fallback;
+                }
+                continue;
+            }
+            Op::Arith { .. } => unreachable!("arithmetic is run or turned into a call above"),
+            Op::Return => pop(&mut stack),
+            Op::Call(argc) | Op::TailCall(argc) => {
+                #[cfg(all(feature = "signals", unix))]
+                crate::signal::poll()?;
+                crate::limits::step()?;
+                crate::interrupt::poll()?;
+
+                let tail = matches!(op, Op::TailCall(_));
+                let func_at = stack.len() - argc - 1;
+
+                let result = match &stack[func_at] {
+                    Object::Closure(closure) => {
+                        let closure = closure.clone();
+                        let mut callee = enter(&mut stack, func_at, &closure)?;
+                        if tail {
+                            // Drop the caller's slots from under the callee's.
+                            let dropped = func_at - frame.return_to;
+                            stack.drain(frame.return_to..func_at);
+                            callee.base -= dropped;
+                            callee.return_to -= dropped;
+                            frame = callee;
+                        } else {
+                            calls.push(std::mem::replace(&mut frame, callee));
+                        }
+                        continue;
+                    }
+                    // Builtins read their arguments straight off the stack.
+                    Object::Builtin(builtin) => {
+                        let builtin = builtin.clone();
+                        let result = builtin.call(&stack[func_at + 1..])?;
+                        stack.truncate(func_at);
+                        result
+                    }
+                    func => {
+                        let func = func.clone();
+                        let args = stack.split_off(func_at + 1);
+                        stack.pop();
+                        eval::apply(&func, args)?
+                    }
+                };
+
+                if !tail {
+                    stack.push(result);
+                    continue;
+                }
+                result
+            }
+        };
+
+        stack.truncate(frame.return_to);
+        match calls.pop() {
+            Some(caller) => {
+                frame = caller;
+                stack.push(returned);
+            }
+            None => return Ok(returned),
+        }
+    }
+}
+
+/// Looks up `chunk.symbols[index]`, skipping the environment while nothing
+/// has been defined or assigned since the last lookup.
+fn global(frame: &Frame, index: usize) -> Result<Object, EvalError> {
+    let generation = env::generation();
+    if let Some((cached, value)) = &frame.chunk.cache.borrow()[index] {
+        if *cached == generation {
+            return Ok(value.clone());
+        }
+    }
+
+    let name = frame.chunk.symbols[index];
+    let value = frame
+        .globals
+        .borrow()
+        .get(name)
+        .ok_or_else(|| EvalError::new(format!("unbound symbol: {}", name)))?;
+    frame.chunk.cache.borrow_mut()[index] = Some((generation, value.clone()));
+
+    Ok(value)
+}
+
+/// The closure running in `frame`, which code capturing variables is
+/// always run by.
+fn captured(frame: &Frame) -> &Closure {
+    frame
+        .closure
+        .as_deref()
+        .expect("only procedures capture variables")
+}
+
+fn pop(stack: &mut Vec<Object>) -> Object {
+    stack
+        .pop()
+        .expect("compiled code never pops an empty stack")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builtins::global_env;
+    use crate::compiler::compile_program;
+
+    fn both(program: &str) -> (Object, Object) {
+        (
+            eval::eval_str(program, &global_env()).unwrap(),
+            eval_str(program, &global_env()).unwrap(),
+        )
+    }
+
+    /// The value of `program` compiled as a whole.
+    fn compiled(program: &str) -> Object {
+        let program = compile_program(parse(program).unwrap());
+        run_program(&program, &global_env()).unwrap()
+    }
+
+    #[test]
+    fn test_matches_the_tree_walker() {
+        for program in [
+            "(define (fib n) (if (< n 2) n (+ (fib (- n 1)) (fib (- n 2))))) (fib 15)",
+            "(define (count n acc) (if (= n 0) acc (count (- n 1) (+ acc 1)))) (count 100000 0)",
+            "(define (make-counter)
+               (let ((n 0)) (lambda () (set! n (+ n 1)) n)))
+             (define c (make-counter)) (c) (c) (c)",
+            "(define (f . xs) xs) (list (f) (f 1 2) ((lambda (a . b) b) 1 2 3))",
+            "(define (outer x)
+               (define (even? n) (if (= n 0) #t (odd? (- n 1))))
+               (define (odd? n) (if (= n 0) #f (even? (- n 1))))
+               (even? x))
+             (list (outer 10) (outer 7))",
+            "(map (lambda (x) (* x x)) '(1 2 3))",
+            "(+ 1 (call/cc (lambda (k) (+ 10 (k 2)))))",
+            "(let ((x 1) (y 2)) (define z 3) (list x y z (if #f #f) 'done))",
+            "(define (add a b) (+ a b))
+             (list (add 1 2) (add 1.5 2) (add 9223372036854775807 1) (< 1 2.5) (= 2 2) (- 3 5))",
+            "(define (f) (* 2 3)) (define g (f)) (define (* a b) (+ a b)) (list g (f))",
+            "(define (counters)
+               (let ((n 0)) (list (lambda () (set! n (+ n 1)) n) (lambda () n))))
+             (define cs (counters)) ((car cs)) ((car cs)) ((cadr cs))",
+            "(define (f x) (define (get) x) (set! x (+ x 1)) (get)) (f 1)",
+            "((((lambda (a) (lambda (b) (lambda (c) (list a b c)))) 1) 2) 3)",
+            "(define (g x) (let ((y (* x 2))) (lambda (z) (set! y (+ y z)) (list x y)))) ((g 1) 5)",
+            "(define (scale xs k)
+               (let ((by-k (lambda (x) (* x k))) (small? (lambda (x) (< x 3))))
+                 (map (lambda (x) (by-k x)) (filter (lambda (x) (small? x)) xs))))
+             (scale '(1 2 3 4) 10)",
+            "(define (f x) (let ((g (lambda (y) (+ x y)))) (let ((x 100)) (g 1)))) (f 1)",
+            "(define (g) 'outer) (let ((g (lambda () 'inner)) (f (lambda () (g)))) (f))",
+            "(define y 'global)
+             (let ((g (lambda () y))) (let ((y 'local) (f (lambda () (g)))) (f)))",
+            "(define (h) (let ((f (lambda () z))) (define z 'inner) (f))) (define z 'outer) (h)",
+            "(let ((f (lambda (x) x))) (list (map f '(1 2)) (f 3)))",
+            "(let ((f (lambda (x) (set! x (+ x 1)) (lambda () x)))) (list ((f 1)) ((f 5))))",
+            "(let ((+ (lambda (a b) (- a b)))) (+ 5 3))",
+            "((lambda (x y) (list y x)) 1 2)",
+            "(define (sq x) (* x x)) (define (f y) (+ (sq y) 1)) (define a (f 3))
+             (define (sq x) 0) (list a (f 3))",
+            "(define (my-car p) (car p)) (define (second p) (my-car (cdr p))) (second '(1 2 3))",
+            "(declare (inline walk))
+             (define (walk n acc) (if (= n 0) acc (walk (- n 1) (cons n acc))))
+             (length (walk 100 '()))",
+            "(define (sq x) (* x x)) (define (g *) (sq 3)) (g +)",
+            "(define (k) 1) (set! k (lambda () 2)) (k)",
+        ] {
+            let (expected, actual) = both(program);
+            assert_eq!(expected, actual, "{}", program);
+            assert_eq!(expected, compiled(program), "{}", program);
+        }
+    }
+
+    #[test]
+    fn test_closures_capture_only_what_they_use() {
+        let env = global_env();
+        let items = Object::list(vec![Object::Integer(1); 1000]);
+        let Object::Pair(first) = &items else {
+            unreachable!("the list is not empty")
+        };
+        let first = Rc::downgrade(first);
+        env.borrow_mut().define("items", items);
+
+        let program = "(define (make-counter items)
+                         (let ((n (length items)) (count 0))
+                           (lambda () (set! count (+ count n)) count)))
+                       (define counter (make-counter items))
+                       (set! items #f)
+                       (counter)
+                       (counter)";
+        assert_eq!(eval_str(program, &env).unwrap(), Object::Integer(2000));
+        assert!(first.upgrade().is_none());
+    }
+
+    #[test]
+    fn test_errors() {
+        let env = global_env();
+        assert!(eval_str("(undefined-thing)", &env).is_err());
+        assert!(eval_str("((lambda (x) x))", &env)
+            .unwrap_err()
+            .to_string()
+            .contains("expected 1 arguments, got 0"));
+        assert!(eval_str("(set! nope 1)", &env).is_err());
+    }
+}
@@ -0,0 +1,49 @@
+//! The entry point for running the interpreter in a browser.
+//!
+//! Built for `wasm32-unknown-unknown`, `eval_to_string` is exported to
+//! JavaScript through `wasm-bindgen`, so a playground page can hand it the
+//! contents of an editor and show what comes back. Every call evaluates in
+//! the same global environment, so definitions persist between calls, as
+//! they do at the REPL. Loading files and running programs fail in the
+//! browser with ordinary evaluation errors.
+
+use std::cell::RefCell;
+
+#[cfg(target_arch = "wasm32")]
+use wasm_bindgen::prelude::wasm_bindgen;
+
+use crate::interpreter::Interpreter;
+use crate::object::Object;
+
+thread_local! {
+    static INTERPRETER: RefCell<Interpreter> = RefCell::new(Interpreter::new());
+}
+
+/// Evaluates `source` and returns the printed value of its last form, as
+/// the REPL would print it, or the error message.
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub fn eval_to_string(source: &str) -> String {
+    INTERPRETER.with(|interp| match interp.borrow_mut().eval_str(source) {
+        Ok(value) => Object::from(value).to_string(),
+        Err(e) => e.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_eval_to_string() {
+        assert_eq!(eval_to_string("(define (sq x) (* x x))"), "");
+        assert_eq!(eval_to_string("(list (sq 3) \"a\")"), "(9 \"a\")");
+        assert_eq!(
+            eval_to_string("(car '())"),
+            "Evaluation error: car expects a pair, got ()"
+        );
+        assert_eq!(
+            eval_to_string("(+ 1"),
+            "Evaluation error: Parse error: missing ')'"
+        );
+    }
+}
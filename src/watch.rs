@@ -0,0 +1,339 @@
+//! Watching files for changes, enabled with the `watch` feature.
+//!
+//! `(watch-path path handler)` calls `handler` with the path of each file
+//! created, changed or removed under `path`, a file or a directory watched
+//! with its subdirectories, until the handler calls `(stop-watching)`. The
+//! changes are those of a moment: a file written several times in a row,
+//! as editors do when saving, is reported once. A handler that fails is
+//! reported on standard error, and watching goes on. `lisp-rs watch`, which
+//! runs a script again whenever a file next to it changes, uses the same
+//! `Watcher`.
+//!
+//! On Linux, changes are reported by inotify. Elsewhere, or if inotify is
+//! not available, the files are checked for changes several times a
+//! second.
+
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+#[cfg(target_os = "linux")]
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify, WatchDescriptor};
+
+use crate::eval::{apply, EvalError};
+use crate::object::{BuiltinFn, Object};
+use crate::retry;
+
+pub const BUILTINS: &[(&str, BuiltinFn)] =
+    &[("watch-path", watch_path), ("stop-watching", stop_watching)];
+
+/// How often to look for changes, in milliseconds.
+const POLL_INTERVAL: f64 = 50.0;
+
+/// How long changes must stop coming for before they are reported.
+const SETTLE_TIME: f64 = 100.0;
+
+enum Backend {
+    #[cfg(target_os = "linux")]
+    Inotify {
+        inotify: Inotify,
+        dirs: HashMap<WatchDescriptor, PathBuf>,
+    },
+    /// When each file was last modified, and its size.
+    Poll(HashMap<PathBuf, (Option<SystemTime>, u64)>),
+}
+
+/// Watches a file, or a directory and everything in it.
+pub struct Watcher {
+    root: PathBuf,
+    backend: Backend,
+}
+
+/// Every file under `path`, with when it was modified and its size.
+fn snapshot(path: &Path, files: &mut HashMap<PathBuf, (Option<SystemTime>, u64)>) {
+    let Ok(metadata) = fs::metadata(path) else {
+        return;
+    };
+    if metadata.is_dir() {
+        for entry in fs::read_dir(path).into_iter().flatten().flatten() {
+            snapshot(&entry.path(), files);
+        }
+    } else {
+        files.insert(
+            path.to_path_buf(),
+            (metadata.modified().ok(), metadata.len()),
+        );
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn add_watch(
+    inotify: &Inotify,
+    dir: &Path,
+    dirs: &mut HashMap<WatchDescriptor, PathBuf>,
+) -> io::Result<()> {
+    let flags = AddWatchFlags::IN_MODIFY
+        | AddWatchFlags::IN_CLOSE_WRITE
+        | AddWatchFlags::IN_CREATE
+        | AddWatchFlags::IN_DELETE
+        | AddWatchFlags::IN_MOVE;
+    let wd = inotify.add_watch(dir, flags)?;
+    dirs.insert(wd, dir.to_path_buf());
+    Ok(())
+}
+
+/// Watches `dir` and every directory in it.
+#[cfg(target_os = "linux")]
+fn add_watches(
+    inotify: &Inotify,
+    dir: &Path,
+    dirs: &mut HashMap<WatchDescriptor, PathBuf>,
+) -> io::Result<()> {
+    add_watch(inotify, dir, dirs)?;
+    for entry in fs::read_dir(dir)?.flatten() {
+        if entry.file_type().is_ok_and(|kind| kind.is_dir()) {
+            add_watches(inotify, &entry.path(), dirs)?;
+        }
+    }
+    Ok(())
+}
+
+impl Watcher {
+    pub fn new(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut root = path.as_ref().to_path_buf();
+        if root.parent() == Some(Path::new("")) {
+            root = Path::new(".").join(root);
+        }
+        fs::metadata(&root)?;
+
+        #[cfg(target_os = "linux")]
+        if let Ok(inotify) = Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC) {
+            let mut dirs = HashMap::new();
+            // A file is watched through its directory, which still sees it
+            // when an editor saves it by replacing it with a new file.
+            let watched = match (root.is_dir(), root.parent()) {
+                (false, Some(dir)) => add_watch(&inotify, dir, &mut dirs),
+                _ => add_watches(&inotify, &root, &mut dirs),
+            };
+            if watched.is_ok() {
+                let backend = Backend::Inotify { inotify, dirs };
+                return Ok(Self { root, backend });
+            }
+        }
+
+        let mut files = HashMap::new();
+        snapshot(&root, &mut files);
+        Ok(Self {
+            root,
+            backend: Backend::Poll(files),
+        })
+    }
+
+    /// The paths changed since the last call, without waiting.
+    pub fn poll(&mut self) -> io::Result<Vec<PathBuf>> {
+        let mut changed = Vec::new();
+        match &mut self.backend {
+            #[cfg(target_os = "linux")]
+            Backend::Inotify { inotify, dirs } => loop {
+                let events = match inotify.read_events() {
+                    Ok(events) => events,
+                    Err(nix::errno::Errno::EAGAIN) => break,
+                    Err(e) => return Err(e.into()),
+                };
+                for event in events {
+                    if event.mask.contains(AddWatchFlags::IN_Q_OVERFLOW) {
+                        changed.push(self.root.clone());
+                        continue;
+                    }
+                    if event.mask.contains(AddWatchFlags::IN_IGNORED) {
+                        dirs.remove(&event.wd);
+                        continue;
+                    }
+                    let (Some(dir), Some(name)) = (dirs.get(&event.wd), event.name) else {
+                        continue;
+                    };
+                    let path = dir.join(name);
+                    if event.mask.contains(AddWatchFlags::IN_ISDIR)
+                        && event
+                            .mask
+                            .intersects(AddWatchFlags::IN_CREATE | AddWatchFlags::IN_MOVED_TO)
+                    {
+                        add_watches(inotify, &path, dirs)?;
+                    }
+                    if path.starts_with(&self.root) {
+                        changed.push(path);
+                    }
+                }
+            },
+            Backend::Poll(files) => {
+                let mut now = HashMap::new();
+                snapshot(&self.root, &mut now);
+                changed.extend(
+                    now.iter()
+                        .filter(|(path, state)| files.get(*path) != Some(state))
+                        .map(|(path, _)| path.clone()),
+                );
+                changed.extend(
+                    files
+                        .keys()
+                        .filter(|path| !now.contains_key(*path))
+                        .cloned(),
+                );
+                *files = now;
+            }
+        }
+
+        changed.sort();
+        changed.dedup();
+        Ok(changed)
+    }
+
+    /// Waits for changes, and returns the paths changed once they stop
+    /// coming. Fails early if the evaluation is interrupted.
+    pub fn wait(&mut self) -> Result<Vec<PathBuf>, EvalError> {
+        let mut changed = Vec::new();
+        loop {
+            let new = self.poll().map_err(|e| {
+                EvalError::new(format!("cannot watch {}: {}", self.root.display(), e))
+            })?;
+            if new.is_empty() && !changed.is_empty() {
+                changed.sort();
+                return Ok(changed);
+            }
+            for path in new {
+                if !changed.contains(&path) {
+                    changed.push(path);
+                }
+            }
+            retry::wait(match changed.is_empty() {
+                true => POLL_INTERVAL,
+                false => SETTLE_TIME,
+            })?;
+        }
+    }
+}
+
+thread_local! {
+    static STOPPING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// `(watch-path path handler)` calls `(handler changed-path)` for every
+/// change, until the handler calls `(stop-watching)`.
+fn watch_path(args: &[Object]) -> Result<Object, EvalError> {
+    let (path, handler) = match args {
+        [Object::String(path), handler] => (path.to_string(), handler),
+        _ => return Err(EvalError::new("watch-path expects a path and a procedure")),
+    };
+    let mut watcher =
+        Watcher::new(&path).map_err(|e| EvalError::new(format!("cannot watch {}: {}", path, e)))?;
+
+    STOPPING.with(|stopping| stopping.set(false));
+    while !STOPPING.with(Cell::get) {
+        for changed in watcher.wait()? {
+            let changed = Object::string(changed.to_string_lossy().into_owned());
+            match apply(handler, vec![changed]) {
+                Err(e) if e.is_failure() => {
+                    eprintln!("watch-path: handler failed: {}", e.message())
+                }
+                result => {
+                    result?;
+                }
+            }
+            if STOPPING.with(Cell::get) {
+                break;
+            }
+        }
+    }
+
+    Ok(Object::Void)
+}
+
+fn stop_watching(args: &[Object]) -> Result<Object, EvalError> {
+    if !args.is_empty() {
+        return Err(EvalError::new("stop-watching expects no arguments"));
+    }
+    STOPPING.with(|stopping| stopping.set(true));
+    Ok(Object::Void)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::builtins::global_env;
+    use crate::eval::eval_str;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("lisp-rs-watch-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_watcher() {
+        let dir = temp_dir("watcher");
+        check_watcher(&dir, Watcher::new(&dir).unwrap());
+
+        let dir = temp_dir("polling");
+        let mut files = HashMap::new();
+        snapshot(&dir, &mut files);
+        let backend = Backend::Poll(files);
+        check_watcher(
+            &dir,
+            Watcher {
+                root: dir.clone(),
+                backend,
+            },
+        );
+    }
+
+    fn check_watcher(dir: &Path, mut watcher: Watcher) {
+        assert!(watcher.poll().unwrap().is_empty());
+
+        fs::write(dir.join("a.txt"), "1").unwrap();
+        fs::write(dir.join("a.txt"), "12").unwrap();
+        fs::write(dir.join("sub/b.txt"), "1").unwrap();
+        assert_eq!(
+            watcher.wait().unwrap(),
+            [dir.join("a.txt"), dir.join("sub/b.txt")]
+        );
+
+        fs::remove_file(dir.join("a.txt")).unwrap();
+        assert_eq!(watcher.wait().unwrap(), [dir.join("a.txt")]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_watch_path() {
+        let dir = temp_dir("builtin");
+        let file = dir.join("input.txt");
+        fs::write(&file, "").unwrap();
+        let writer = {
+            let file = file.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(200));
+                fs::write(file, "changed").unwrap();
+            })
+        };
+
+        let env = global_env();
+        let program = format!(
+            "(define seen '())
+             (watch-path {:?} (lambda (path) (set! seen (cons path seen)) (stop-watching)))
+             seen",
+            file.to_string_lossy()
+        );
+        let seen = eval_str(&program, &env).unwrap();
+        writer.join().unwrap();
+
+        assert_eq!(seen.to_string(), format!("({:?})", file.to_string_lossy()));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}